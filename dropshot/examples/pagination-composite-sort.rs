@@ -0,0 +1,186 @@
+// Copyright 2024 Oxide Computer Company
+//! Example of an API endpoint that paginates using a composite sort key.
+//!
+//! When you run this program, it will start an HTTP server on an available local
+//! port.  See the log for example URLs to use.
+//!
+//! This example uses a resource called an "Event", which has an "id" and a
+//! creation time.  Many events share the same creation time, so the creation
+//! time alone can't be used as the marker in a page token: a page might end in
+//! the middle of a group of events created at the same time, and there'd be no
+//! way to tell which of those the client had already seen.  Instead, we sort by
+//! the `(created_at, id)` tuple, which is unique, using Dropshot's
+//! `CompositeSort` to compare keys and `CompositePageSelector` as the page
+//! selector.
+//!
+//! Clients can list the newest events first (the default) or the oldest first:
+//!
+//! ```ignore
+//! $ curl -s 'http://127.0.0.1:50800/events?limit=3&order=oldest-first' | json
+//! ```
+//!
+//! When listing the newest events first, events created at the same time are
+//! still listed in ascending order of id, which shows that the columns of a
+//! composite key can be sorted in different directions.
+
+use chrono::offset::TimeZone;
+use chrono::DateTime;
+use chrono::Utc;
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::CompositePageSelector;
+use dropshot::CompositeSort;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServerStarter;
+use dropshot::PaginationOrder::Ascending;
+use dropshot::PaginationOrder::Descending;
+use dropshot::PaginationParams;
+use dropshot::Query;
+use dropshot::RequestContext;
+use dropshot::ResultsPage;
+use dropshot::WhichPage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use tracing::info;
+
+/// Item returned by our paginated endpoint
+#[derive(Clone, JsonSchema, Serialize)]
+struct Event {
+    id: u32,
+    created_at: DateTime<Utc>,
+    // lots more fields
+}
+
+/// The key that events are sorted by: creation time, with ties broken by id
+type EventKey = (DateTime<Utc>, u32);
+
+impl Event {
+    fn key(&self) -> EventKey {
+        (self.created_at, self.id)
+    }
+}
+
+/// Specifies how the client wants to page through events
+#[derive(Deserialize, JsonSchema)]
+struct EventScanParams {
+    #[serde(default)]
+    order: EventOrder,
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+enum EventOrder {
+    /// most recently created first
+    #[default]
+    NewestFirst,
+    /// least recently created first
+    OldestFirst,
+}
+
+impl EventOrder {
+    fn sort(&self) -> CompositeSort {
+        match self {
+            EventOrder::NewestFirst => {
+                CompositeSort::new(vec![Descending, Ascending])
+            }
+            EventOrder::OldestFirst => {
+                CompositeSort::new(vec![Ascending, Ascending])
+            }
+        }
+    }
+}
+
+/// API endpoint for listing events
+///
+/// This implementation keeps the events in a Vec and sorts them for each
+/// request.  A database-backed implementation would instead translate the sort
+/// and the marker into an ORDER BY and WHERE clause.
+#[endpoint {
+    method = GET,
+    path = "/events"
+}]
+async fn example_list_events(
+    rqctx: RequestContext<Vec<Event>>,
+    query: Query<
+        PaginationParams<EventScanParams, CompositePageSelector<EventKey>>,
+    >,
+) -> Result<HttpResponseOk<ResultsPage<Event>>, HttpError> {
+    let pag_params = query.into_inner();
+    let limit = rqctx.page_limit(&pag_params)?.get() as usize;
+    let (sort, marker) = match &pag_params.page {
+        WhichPage::First(EventScanParams { order }) => (order.sort(), None),
+        WhichPage::Next(selector) => {
+            // The sort comes from the client, so make sure that it fits our key
+            // before using it.
+            selector.sort.check::<EventKey>()?;
+            (selector.sort.clone(), Some(&selector.last_seen))
+        }
+    };
+
+    let mut events = rqctx.context().clone();
+    events.sort_by(|a, b| sort.compare(&a.key(), &b.key()));
+    let events = events
+        .into_iter()
+        .filter(|event| match marker {
+            Some(marker) => sort.is_after(&event.key(), marker),
+            None => true,
+        })
+        .take(limit)
+        .collect();
+
+    Ok(HttpResponseOk(ResultsPage::new(
+        events,
+        &sort,
+        |event: &Event, sort| sort.page_selector(event.key()),
+    )?))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .compact()
+        .init();
+
+    let port = std::env::args()
+        .nth(1)
+        .map(|p| p.parse::<u16>())
+        .transpose()
+        .map_err(|e| format!("failed to parse \"port\" argument: {}", e))?
+        .unwrap_or(0);
+
+    // Create 999 events up front, with ten created at each point in time.
+    // The ids are assigned so that they don't follow the creation times.
+    let start = Utc.with_ymd_and_hms(2020, 7, 13, 17, 35, 0).unwrap();
+    let events = (1..1000)
+        .map(|n| Event {
+            id: (n * 7) % 1000,
+            created_at: start + chrono::Duration::seconds(i64::from(n / 10)),
+        })
+        .collect::<Vec<_>>();
+
+    // Run the Dropshot server.
+    let config_dropshot = ConfigDropshot {
+        bind_address: SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+        ..Default::default()
+    };
+    let mut api = ApiDescription::new();
+    api.register(example_list_events).unwrap();
+    let server = HttpServerStarter::new(&config_dropshot, api, None, events)
+        .map_err(|error| format!("failed to create server: {}", error))?
+        .start();
+
+    info!(address = server.local_addr().to_string(), "started http server");
+    info!(
+        "example: http://{}/events?limit=3&order=oldest-first",
+        server.local_addr()
+    );
+
+    server.await
+}
@@ -8,6 +8,7 @@
 use dropshot::{
     endpoint, ApiDescription, EndpointTagPolicy, HttpError, HttpResponseOk,
     HttpServerStarter, RequestContext, TagConfig, TagDetails, TagExternalDocs,
+    TagGroup,
 };
 use tracing::info;
 
@@ -91,6 +92,11 @@ async fn main() -> Result<(), String> {
         ]
         .into_iter()
         .collect(),
+        tag_order: vec!["simpsons".to_string(), "futurama".to_string()],
+        tag_groups: vec![TagGroup {
+            name: "Cartoons".to_string(),
+            tags: vec!["simpsons".to_string(), "futurama".to_string()],
+        }],
     });
     api.register(get_homerism).unwrap();
     api.register(get_barneyism).unwrap();
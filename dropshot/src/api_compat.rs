@@ -0,0 +1,569 @@
+// Copyright 2024 Oxide Computer Company
+//! Finding the changes between two versions of an OpenAPI document that would
+//! break existing clients
+//!
+//! See [`compare_openapi`].  The comparison works on the JSON form of the
+//! documents (as produced by [`crate::OpenApiDefinition::json`]), so that a
+//! document generated by the current code can be checked against one that was
+//! checked in or published earlier.
+//!
+//! This understands the subset of OpenAPI that Dropshot generates.  Schemas are
+//! compared structurally: references to "#/components/schemas" are followed,
+//! and a reference is considered equivalent to the schema it refers to.
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// HTTP methods that an OpenAPI path item may have operations for
+const METHODS: [&str; 8] =
+    ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// A change between two versions of an OpenAPI document that would break
+/// clients written against the older one
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BreakingChange {
+    /// the affected operation, as its method and path (e.g.,
+    /// "GET /projects/{project}")
+    pub operation: String,
+    /// where in the operation the change is (e.g., "query parameter
+    /// \"limit\"", or "response 200 (application/json)"), or an empty string
+    /// if it's the operation as a whole
+    pub location: String,
+    /// what changed
+    pub description: String,
+}
+
+impl fmt::Display for BreakingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.location.is_empty() {
+            write!(f, "{}: {}", self.operation, self.description)
+        } else {
+            write!(
+                f,
+                "{}: {}: {}",
+                self.operation, self.location, self.description
+            )
+        }
+    }
+}
+
+/// Returns the changes from the OpenAPI document `old` to `new` that would
+/// break clients written against `old`
+///
+/// These are:
+///
+/// * operations that were removed
+/// * parameters that were added as required, or that became required
+/// * request bodies that became required, or that no longer accept a content
+///   type that they used to
+/// * successful responses that were removed, or that no longer have a content
+///   type that they used to
+/// * schemas that accept less than they used to, for anything the client sends
+///   (e.g., a different type, fewer enum values, a new required property, or
+///   a tighter bound)
+/// * schemas that may produce more than they used to, for anything the client
+///   receives (e.g., a different type, more enum values, or a required
+///   property that was removed or became optional or nullable)
+///
+/// Changes to descriptions, tags, and other documentation are ignored, as are
+/// changes that only add to the API.
+///
+/// ```
+/// let old = serde_json::json!({
+///     "paths": { "/projects": { "get": {}, "post": {} } }
+/// });
+/// let new = serde_json::json!({
+///     "paths": { "/projects": { "get": {} } }
+/// });
+/// let changes = dropshot::compare_openapi(&old, &new);
+/// assert_eq!(changes.len(), 1);
+/// assert_eq!(
+///     changes[0].to_string(),
+///     "POST /projects: operation was removed"
+/// );
+/// ```
+pub fn compare_openapi(old: &Value, new: &Value) -> Vec<BreakingChange> {
+    let mut comparison = Comparison { old, new, changes: Vec::new() };
+    comparison.compare_paths();
+    comparison.changes
+}
+
+/// Whether a schema describes data the client sends or data it receives
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Direction {
+    Input,
+    Output,
+}
+
+struct Comparison<'a> {
+    old: &'a Value,
+    new: &'a Value,
+    changes: Vec<BreakingChange>,
+}
+
+/// Describes where in a document a schema comparison is
+struct Site<'a> {
+    operation: &'a str,
+    location: &'a str,
+    direction: Direction,
+}
+
+impl<'a> Comparison<'a> {
+    fn report(&mut self, operation: &str, location: &str, description: String) {
+        self.changes.push(BreakingChange {
+            operation: operation.to_string(),
+            location: location.to_string(),
+            description,
+        });
+    }
+
+    fn compare_paths(&mut self) {
+        let (old, new): (&'a Value, &'a Value) = (self.old, self.new);
+        let Some(old_paths) = old["paths"].as_object() else {
+            return;
+        };
+        for (path, old_item) in old_paths {
+            let new_item = &new["paths"][path];
+            for method in METHODS {
+                let Some(old_op) = old_item.get(method) else {
+                    continue;
+                };
+                let operation = format!("{} {}", method.to_uppercase(), path);
+                match new_item.get(method) {
+                    None => self.report(
+                        &operation,
+                        "",
+                        String::from("operation was removed"),
+                    ),
+                    Some(new_op) => self.compare_operation(
+                        &operation,
+                        (old_item, old_op),
+                        (new_item, new_op),
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Compares an operation, given along with the path item it's in (whose
+    /// parameters apply to it, too)
+    fn compare_operation(
+        &mut self,
+        operation: &str,
+        (old_item, old_op): (&'a Value, &'a Value),
+        (new_item, new_op): (&'a Value, &'a Value),
+    ) {
+        self.compare_parameters(
+            operation,
+            (old_item, old_op),
+            (new_item, new_op),
+        );
+        self.compare_request_body(operation, old_op, new_op);
+        self.compare_responses(operation, old_op, new_op);
+    }
+
+    fn compare_parameters(
+        &mut self,
+        operation: &str,
+        (old_item, old_op): (&'a Value, &'a Value),
+        (new_item, new_op): (&'a Value, &'a Value),
+    ) {
+        // An operation's parameters include those of its path item, unless
+        // the operation overrides them.
+        let parameters = |item: &'a Value, op: &'a Value| -> Vec<&'a Value> {
+            let list = |value: &'a Value| -> &'a [Value] {
+                value["parameters"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+            };
+            let overridden = |p: &Value| {
+                list(op)
+                    .iter()
+                    .any(|q| q["in"] == p["in"] && q["name"] == p["name"])
+            };
+            list(item)
+                .iter()
+                .filter(|p| !overridden(p))
+                .chain(list(op))
+                .collect()
+        };
+        let old_params = parameters(old_item, old_op);
+        let find_old = |location: &Value, name: &Value| {
+            old_params
+                .iter()
+                .copied()
+                .find(|p| p["in"] == *location && p["name"] == *name)
+        };
+
+        for new_param in parameters(new_item, new_op) {
+            let location = format!(
+                "{} parameter {}",
+                new_param["in"].as_str().unwrap_or("unknown"),
+                new_param["name"]
+            );
+            let new_required = is_true(&new_param["required"]);
+            match find_old(&new_param["in"], &new_param["name"]) {
+                None if new_required => self.report(
+                    operation,
+                    &location,
+                    String::from("required parameter was added"),
+                ),
+                None => (),
+                Some(old_param) => {
+                    if new_required && !is_true(&old_param["required"]) {
+                        self.report(
+                            operation,
+                            &location,
+                            String::from("parameter became required"),
+                        );
+                    }
+                    let site = Site {
+                        operation,
+                        location: &location,
+                        direction: Direction::Input,
+                    };
+                    self.compare_schemas(
+                        &site,
+                        &old_param["schema"],
+                        &new_param["schema"],
+                        &mut BTreeSet::new(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn compare_request_body(
+        &mut self,
+        operation: &str,
+        old_op: &'a Value,
+        new_op: &'a Value,
+    ) {
+        let old_body = &old_op["requestBody"];
+        let new_body = &new_op["requestBody"];
+        if new_body.is_null() {
+            return;
+        }
+        if is_true(&new_body["required"]) && !is_true(&old_body["required"]) {
+            self.report(
+                operation,
+                "request body",
+                String::from("request body became required"),
+            );
+        }
+        if old_body.is_null() {
+            return;
+        }
+        self.compare_content(
+            operation,
+            "request body",
+            &old_body["content"],
+            &new_body["content"],
+            Direction::Input,
+        );
+    }
+
+    fn compare_responses(
+        &mut self,
+        operation: &str,
+        old_op: &'a Value,
+        new_op: &'a Value,
+    ) {
+        let Some(old_responses) = old_op["responses"].as_object() else {
+            return;
+        };
+        for (status, old_response) in old_responses {
+            // Clients can't rely on getting any particular error, so only
+            // successful responses are compared.
+            if !status.starts_with('2') {
+                continue;
+            }
+            let location = format!("response {}", status);
+            let new_response = &new_op["responses"][status];
+            if new_response.is_null() {
+                self.report(
+                    operation,
+                    &location,
+                    String::from("response was removed"),
+                );
+                continue;
+            }
+            self.compare_content(
+                operation,
+                &location,
+                &old_response["content"],
+                &new_response["content"],
+                Direction::Output,
+            );
+        }
+    }
+
+    /// Compares the schemas for each content type of a request body or
+    /// response
+    fn compare_content(
+        &mut self,
+        operation: &str,
+        location: &str,
+        old_content: &'a Value,
+        new_content: &'a Value,
+        direction: Direction,
+    ) {
+        let Some(old_content) = old_content.as_object() else {
+            return;
+        };
+        for (content_type, old_media) in old_content {
+            let location = format!("{} ({})", location, content_type);
+            let Some(new_media) = new_content.get(content_type) else {
+                self.report(
+                    operation,
+                    &location,
+                    String::from("content type was removed"),
+                );
+                continue;
+            };
+            let site = Site { operation, location: &location, direction };
+            self.compare_schemas(
+                &site,
+                &old_media["schema"],
+                &new_media["schema"],
+                &mut BTreeSet::new(),
+            );
+        }
+    }
+
+    /// Returns the schema that `schema` refers to, if it's a reference, or
+    /// that it wraps, if it's an "allOf" with only one schema
+    fn resolve(document: &'a Value, mut schema: &'a Value) -> &'a Value {
+        // Bound the number of steps in case references form a cycle.
+        for _ in 0..32 {
+            if let Some(reference) = schema["$ref"].as_str() {
+                let Some(pointer) = reference.strip_prefix('#') else {
+                    return schema;
+                };
+                match document.pointer(pointer) {
+                    Some(target) => schema = target,
+                    None => return schema,
+                }
+            } else if let Some([only]) =
+                schema["allOf"].as_array().map(Vec::as_slice)
+            {
+                schema = only;
+            } else {
+                return schema;
+            }
+        }
+        schema
+    }
+
+    fn compare_schemas(
+        &mut self,
+        site: &Site<'_>,
+        old_schema: &'a Value,
+        new_schema: &'a Value,
+        visited: &mut BTreeSet<(String, String)>,
+    ) {
+        // Recursive types would otherwise be compared forever.
+        if let (Some(old_ref), Some(new_ref)) =
+            (old_schema["$ref"].as_str(), new_schema["$ref"].as_str())
+        {
+            if !visited.insert((old_ref.to_string(), new_ref.to_string())) {
+                return;
+            }
+        }
+
+        let old = Self::resolve(self.old, old_schema);
+        let new = Self::resolve(self.new, new_schema);
+        // An empty or missing schema allows anything.
+        let is_any = |schema: &Value| {
+            schema.is_null()
+                || schema.as_object().map_or(false, |s| s.is_empty())
+        };
+        match site.direction {
+            Direction::Input if is_any(new) => return,
+            Direction::Output if is_any(old) => return,
+            _ => (),
+        }
+
+        let old_type = &old["type"];
+        let new_type = &new["type"];
+        if !old_type.is_null() && !new_type.is_null() && old_type != new_type {
+            self.report(
+                site.operation,
+                site.location,
+                format!("type changed from {} to {}", old_type, new_type),
+            );
+            return;
+        }
+        if !old["format"].is_null() && old["format"] != new["format"] {
+            self.report(
+                site.operation,
+                site.location,
+                format!(
+                    "format changed from {} to {}",
+                    old["format"], new["format"]
+                ),
+            );
+        }
+
+        let old_nullable = is_true(&old["nullable"]);
+        let new_nullable = is_true(&new["nullable"]);
+        match site.direction {
+            Direction::Input if old_nullable && !new_nullable => self.report(
+                site.operation,
+                site.location,
+                String::from("value is no longer nullable"),
+            ),
+            Direction::Output if new_nullable && !old_nullable => self.report(
+                site.operation,
+                site.location,
+                String::from("value became nullable"),
+            ),
+            _ => (),
+        }
+
+        self.compare_enums(site, old, new);
+        self.compare_bounds(site, old, new);
+        self.compare_properties(site, old, new, visited);
+
+        if !old["items"].is_null() && !new["items"].is_null() {
+            self.compare_schemas(site, &old["items"], &new["items"], visited);
+        }
+    }
+
+    fn compare_enums(&mut self, site: &Site<'_>, old: &Value, new: &Value) {
+        let (narrower, wider, what) = match site.direction {
+            Direction::Input => (new, old, "is no longer accepted"),
+            Direction::Output => (old, new, "was added"),
+        };
+        let Some(allowed) = narrower["enum"].as_array() else {
+            return;
+        };
+        let values: Vec<&Value> = match wider["enum"].as_array() {
+            Some(values) => values.iter().collect(),
+            None if site.direction == Direction::Input => {
+                self.report(
+                    site.operation,
+                    site.location,
+                    String::from("value was restricted to an enum"),
+                );
+                return;
+            }
+            None => return,
+        };
+        for value in values {
+            if !allowed.contains(value) {
+                self.report(
+                    site.operation,
+                    site.location,
+                    format!("enum value {} {}", value, what),
+                );
+            }
+        }
+    }
+
+    fn compare_bounds(&mut self, site: &Site<'_>, old: &Value, new: &Value) {
+        // For each bound: its name, and whether a greater value is tighter.
+        const BOUNDS: [(&str, bool); 6] = [
+            ("minimum", true),
+            ("maximum", false),
+            ("minLength", true),
+            ("maxLength", false),
+            ("minItems", true),
+            ("maxItems", false),
+        ];
+        for (name, greater_is_tighter) in BOUNDS {
+            let old_bound = old[name].as_f64();
+            let new_bound = new[name].as_f64();
+            let tighter = match (old_bound, new_bound) {
+                (None, None) => continue,
+                (None, Some(_)) => true,
+                (Some(_), None) => false,
+                (Some(o), Some(n)) if o == n => continue,
+                (Some(o), Some(n)) => (n > o) == greater_is_tighter,
+            };
+            let breaking = match site.direction {
+                Direction::Input => tighter,
+                Direction::Output => !tighter,
+            };
+            if breaking {
+                self.report(
+                    site.operation,
+                    site.location,
+                    format!(
+                        "{} changed from {} to {}",
+                        name, old[name], new[name]
+                    ),
+                );
+            }
+        }
+    }
+
+    fn compare_properties(
+        &mut self,
+        site: &Site<'_>,
+        old: &'a Value,
+        new: &'a Value,
+        visited: &mut BTreeSet<(String, String)>,
+    ) {
+        let required = |schema: &Value| -> BTreeSet<String> {
+            schema["required"]
+                .as_array()
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|n| n.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let old_required = required(old);
+        let new_required = required(new);
+        match site.direction {
+            Direction::Input => {
+                for name in new_required.difference(&old_required) {
+                    self.report(
+                        site.operation,
+                        site.location,
+                        format!("property \"{}\" became required", name),
+                    );
+                }
+            }
+            Direction::Output => {
+                for name in old_required.difference(&new_required) {
+                    self.report(
+                        site.operation,
+                        site.location,
+                        format!(
+                            "property \"{}\" is no longer always present",
+                            name
+                        ),
+                    );
+                }
+            }
+        }
+
+        let (Some(old_props), Some(new_props)) =
+            (old["properties"].as_object(), new["properties"].as_object())
+        else {
+            return;
+        };
+        for (name, old_prop) in old_props {
+            if let Some(new_prop) = new_props.get(name) {
+                let location =
+                    format!("{}: property \"{}\"", site.location, name);
+                let site = Site {
+                    operation: site.operation,
+                    location: &location,
+                    direction: site.direction,
+                };
+                self.compare_schemas(&site, old_prop, new_prop, visited);
+            }
+        }
+    }
+}
+
+fn is_true(value: &Value) -> bool {
+    value.as_bool() == Some(true)
+}
@@ -1,33 +1,62 @@
 // Copyright 2023 Oxide Computer Company
 //! Describes the endpoints and handler functions in your API
 
+use crate::error::ErrorMapper;
+use crate::error::ErrorResponseFormat;
+use crate::error::HttpError;
+use crate::extractor::PathError;
+use crate::extractor::PathErrorHandler;
 use crate::extractor::RequestExtractor;
+use crate::handler::validate_success_status;
 use crate::handler::HttpHandlerFunc;
 use crate::handler::HttpResponse;
 use crate::handler::HttpRouteHandler;
+use crate::handler::RequestContext;
+use crate::handler::RequestInfo;
 use crate::handler::RouteHandler;
+use crate::handler::StatusOverrideHandler;
+use crate::handler::StubHandler;
+use crate::pagination::PageLimits;
+use crate::parameter_style::ParameterSerialization;
+use crate::parameter_style::ParameterStyle;
 use crate::router::route_path_to_segments;
 use crate::router::HttpRouter;
 use crate::router::PathSegment;
 use crate::schema_util::j2oas_schema;
+use crate::schema_util::make_subschema_for;
+use crate::schema_validation::ConfigSchemaValidation;
+use crate::schema_validation::SchemaValidator;
+use crate::security::SecurityRequirement;
+use crate::security::SecurityScheme;
 use crate::server::ServerContext;
 use crate::type_util::type_is_scalar;
 use crate::type_util::type_is_string_enum;
+use crate::versioning::ApiEndpointVersions;
+use crate::versioning::PrereleaseMatching;
+use crate::versioning::VersionObserver;
+use crate::versioning::VersionPolicy;
 use crate::HttpErrorResponseBody;
+use crate::ProblemDetails;
 use crate::CONTENT_TYPE_JSON;
 use crate::CONTENT_TYPE_MULTIPART_FORM_DATA;
 use crate::CONTENT_TYPE_OCTET_STREAM;
+use crate::CONTENT_TYPE_PROBLEM_JSON;
 use crate::CONTENT_TYPE_URL_ENCODED;
 
 use http::Method;
 use http::StatusCode;
+use hyper::ext::ReasonPhrase;
+use schemars::JsonSchema;
 use serde::de::Error;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::num::NonZeroU32;
 use std::sync::Arc;
+use std::sync::OnceLock;
 
 /// ApiEndpoint represents a single API endpoint associated with an
 /// ApiDescription. It has a handler, HTTP method (e.g. GET, POST), and a path--
@@ -46,8 +75,49 @@ pub struct ApiEndpoint<Context: ServerContext> {
     pub description: Option<String>,
     pub tags: Vec<String>,
     pub extension_mode: ExtensionMode,
-    pub visible: bool,
+    /// Which definitions of the API document this endpoint
+    pub visibility: ApiEndpointVisibility,
+    /// Versions of the API in which this endpoint appears
+    pub versions: ApiEndpointVersions,
     pub deprecated: bool,
+    /// Versions of the API in which this endpoint is deprecated (see
+    /// [`ApiEndpoint::deprecate_versions`])
+    pub deprecated_versions: Vec<ApiEndpointVersions>,
+    pub request_body_max_bytes: Option<usize>,
+    /// Limits on the page size for this endpoint, if it's paginated (see
+    /// [`ApiEndpoint::page_max_limit`])
+    pub page_limits: PageLimits,
+    /// Alternative security requirements for calling this endpoint, any one
+    /// of which is enough
+    pub security: Vec<SecurityRequirement>,
+    /// Vendor extensions for this endpoint's operation, by name
+    pub extensions: BTreeMap<String, serde_json::Value>,
+    /// Requests this endpoint's operation may make to the client, by name
+    pub callbacks: Vec<ApiEndpointCallback>,
+    /// Documentation for this endpoint's operation outside of the OpenAPI
+    /// definition
+    pub external_docs: Option<TagExternalDocs>,
+    /// Fingerprint that this endpoint's interface must have, if it's frozen
+    /// (see [`ApiEndpoint::frozen`])
+    pub frozen: Option<String>,
+    /// Whether the handler's successful responses may have a body (see
+    /// [`HttpResponse::HAS_BODY`])
+    pub(crate) response_has_body: bool,
+    /// Whether the handler reads the request body as it arrives (see
+    /// [`ExclusiveExtractor::STREAMS_BODY`](crate::ExclusiveExtractor::STREAMS_BODY))
+    pub(crate) request_body_streamed: bool,
+}
+
+/// Which definitions of an API document an endpoint (see
+/// [`OpenApiDefinition::audience`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiEndpointVisibility {
+    /// documented for every audience
+    Public,
+    /// documented only for these audiences (e.g., "internal" or "partner")
+    Audiences(Vec<String>),
+    /// not documented at all
+    Unpublished,
 }
 
 impl<'a, Context: ServerContext> ApiEndpoint<Context> {
@@ -80,8 +150,19 @@ impl<'a, Context: ServerContext> ApiEndpoint<Context> {
             description: None,
             tags: vec![],
             extension_mode: func_parameters.extension_mode,
-            visible: true,
+            visibility: ApiEndpointVisibility::Public,
+            versions: ApiEndpointVersions::All,
             deprecated: false,
+            deprecated_versions: vec![],
+            request_body_max_bytes: None,
+            page_limits: PageLimits::default(),
+            security: vec![],
+            extensions: BTreeMap::new(),
+            callbacks: vec![],
+            external_docs: None,
+            frozen: None,
+            response_has_body: ResponseType::HAS_BODY,
+            request_body_streamed: FuncParams::STREAMS_BODY,
         }
     }
 
@@ -100,8 +181,52 @@ impl<'a, Context: ServerContext> ApiEndpoint<Context> {
         self
     }
 
+    /// Document this endpoint for every audience (if `visible` is true) or
+    /// not at all (if it's false)
     pub fn visible(mut self, visible: bool) -> Self {
-        self.visible = visible;
+        self.visibility = if visible {
+            ApiEndpointVisibility::Public
+        } else {
+            ApiEndpointVisibility::Unpublished
+        };
+        self
+    }
+
+    /// Document this endpoint only for `audience` (in addition to any other
+    /// audiences given), rather than for every audience
+    ///
+    /// See [`OpenApiDefinition::audience`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint is unpublished.
+    pub fn audience<T: ToString>(mut self, audience: T) -> Self {
+        match &mut self.visibility {
+            ApiEndpointVisibility::Public => {
+                self.visibility =
+                    ApiEndpointVisibility::Audiences(
+                        vec![audience.to_string()],
+                    );
+            }
+            ApiEndpointVisibility::Audiences(audiences) => {
+                audiences.push(audience.to_string());
+            }
+            ApiEndpointVisibility::Unpublished => panic!(
+                "endpoint \"{}\" is unpublished, so it can't be documented \
+                 for audience \"{}\"",
+                self.operation_id,
+                audience.to_string()
+            ),
+        }
+        self
+    }
+
+    /// Limits this endpoint to the given versions of the API, rather than
+    /// every version
+    ///
+    /// See [`crate::VersionPolicy`].
+    pub fn versions(mut self, versions: ApiEndpointVersions) -> Self {
+        self.versions = versions;
         self
     }
 
@@ -109,6 +234,508 @@ impl<'a, Context: ServerContext> ApiEndpoint<Context> {
         self.deprecated = deprecated;
         self
     }
+
+    /// Deprecates this endpoint in the given versions of the API, in addition
+    /// to any deprecated already
+    ///
+    /// Responses to requests for those versions have a "Deprecation" header
+    /// and a "Warning" header, to help drive clients off of them, and the
+    /// server logs each such request.  The endpoint is also marked deprecated
+    /// in the OpenAPI definitions of those versions.
+    pub fn deprecate_versions(mut self, versions: ApiEndpointVersions) -> Self {
+        self.deprecated_versions.push(versions);
+        self
+    }
+
+    /// Returns whether this endpoint is deprecated in `version`
+    pub(crate) fn is_deprecated_in(&self, version: &semver::Version) -> bool {
+        self.deprecated_versions
+            .iter()
+            .any(|versions| versions.matches(version))
+    }
+
+    /// Freezes this endpoint's interface, so that registering it fails unless
+    /// its [`ApiEndpoint::fingerprint`] is `fingerprint`
+    ///
+    /// This is meant for endpoints in old versions of an API (see
+    /// [`ApiEndpoint::versions`]), which clients rely on not changing: a
+    /// change to the endpoint's method, path, parameters, request body, or
+    /// response (including the types they refer to) is caught when the API
+    /// is described, rather than by its clients.
+    pub fn frozen(mut self, fingerprint: &str) -> Self {
+        self.frozen = Some(fingerprint.to_string());
+        self
+    }
+
+    /// Returns a fingerprint of this endpoint's interface: its method, path,
+    /// parameters, request body, and response, including the schemas of the
+    /// types they refer to
+    ///
+    /// See [`ApiEndpoint::frozen`].
+    pub fn fingerprint(&self) -> String {
+        let mut generator = schemars::gen::SchemaGenerator::new(
+            schemars::gen::SchemaSettings::openapi3(),
+        );
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|param| {
+                let (location, name) = match &param.metadata {
+                    ApiEndpointParameterMetadata::Path(name) => {
+                        ("path", name.as_str())
+                    }
+                    ApiEndpointParameterMetadata::Query(name) => {
+                        ("query", name.as_str())
+                    }
+                    ApiEndpointParameterMetadata::Header(name) => {
+                        ("header", name.as_str())
+                    }
+                    ApiEndpointParameterMetadata::Body(_) => ("body", ""),
+                };
+                serde_json::json!({
+                    "in": location,
+                    "name": name,
+                    "required": param.required,
+                    "schema": schema_json(&param.schema, &mut generator),
+                })
+            })
+            .collect::<Vec<_>>();
+        let headers = self
+            .response
+            .headers
+            .iter()
+            .map(|header| {
+                serde_json::json!({
+                    "name": header.name,
+                    "required": header.required,
+                    "schema": schema_json(&header.schema, &mut generator),
+                })
+            })
+            .collect::<Vec<_>>();
+        let response_schema = self
+            .response
+            .schema
+            .as_ref()
+            .map(|schema| schema_json(schema, &mut generator));
+        let interface = serde_json::json!({
+            "method": self.method.as_str(),
+            "path": self.path,
+            "body_content_type": self.body_content_type.mime_type(),
+            "parameters": parameters,
+            "response": {
+                "status": self.response.success.map(|status| status.as_u16()),
+                "schema": response_schema,
+                "headers": headers,
+                "content_types": self.response.content_types,
+            },
+            "definitions": generator.definitions(),
+        });
+        crate::cache::hex_digest(interface.to_string().as_bytes())
+    }
+
+    /// Allows calling this endpoint using the security scheme named `scheme`
+    /// (which must be declared with [`ApiDescription::security_scheme`]),
+    /// with the given OAuth2 `scopes`, if it's an OAuth2 scheme.  Each call
+    /// adds an alternative to those already allowed.
+    pub fn security<S: ToString>(mut self, scheme: S, scopes: &[&str]) -> Self {
+        let scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+        self.security.push(BTreeMap::from([(scheme.to_string(), scopes)]));
+        self
+    }
+
+    /// Overrides the server-wide
+    /// [`request_body_max_bytes`](crate::ConfigDropshot::request_body_max_bytes)
+    /// for requests to this endpoint.
+    ///
+    /// For [`SpooledBody`](crate::SpooledBody), this is the limit on the
+    /// total size of the body, while the server-wide limit still determines
+    /// how much of it is buffered in memory.
+    pub fn request_body_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.request_body_max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Overrides the server-wide default page size (100 items) for this
+    /// endpoint, which must be paginated.
+    ///
+    /// This is documented as the default of the `limit` query parameter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint has no `limit` query parameter.
+    pub fn page_default_limit(mut self, limit: NonZeroU32) -> Self {
+        self.page_limits.default = Some(limit);
+        self.document_page_limits();
+        self
+    }
+
+    /// Overrides the server-wide maximum page size (10,000 items) for this
+    /// endpoint, which must be paginated.
+    ///
+    /// Unlike the server-wide maximum, which larger requested page sizes are
+    /// quietly reduced to, this is documented as the maximum of the `limit`
+    /// query parameter, and requests for larger pages fail with a 400 ("Bad
+    /// Request") response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint has no `limit` query parameter.
+    pub fn page_max_limit(mut self, limit: NonZeroU32) -> Self {
+        self.page_limits.max = Some(limit);
+        self.document_page_limits();
+        self
+    }
+
+    /// Adds the endpoint's page limits to the schema of its `limit` parameter
+    fn document_page_limits(&mut self) {
+        let PageLimits { default, max } = self.page_limits;
+        let parameter = self.named_parameter_mut("limit");
+        if let ApiSchemaGenerator::Static { schema, .. } = &mut parameter.schema
+        {
+            if let schemars::schema::Schema::Object(object) = schema.as_mut() {
+                object.number().maximum = max.map(|max| f64::from(max.get()));
+                object.metadata().default =
+                    default.map(|default| serde_json::json!(default.get()));
+            }
+        }
+    }
+
+    /// Replaces the success status code of the handler's response type.
+    ///
+    /// Responses whose status is the one declared by the response type (e.g.,
+    /// 200 for `HttpResponseOk`) are sent with `status` instead, and the
+    /// OpenAPI description reflects that.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `status` is not acceptable for the handler's response type
+    /// (see [`crate::validate_success_status`]).
+    pub fn success_status(mut self, status: StatusCode) -> Self {
+        validate_success_status(status.as_u16(), self.response_has_body);
+        if let Some(declared) = self.response.success {
+            if declared != status {
+                self.handler = StatusOverrideHandler::new(
+                    self.handler,
+                    declared,
+                    status,
+                    None,
+                );
+                self.response.description =
+                    status.canonical_reason().map(str::to_lowercase);
+            }
+        }
+        self.response.success = Some(status);
+        self
+    }
+
+    /// Replaces the reason phrase of successful responses (e.g., "Queued"
+    /// instead of "Accepted" for 202), which also becomes the description of
+    /// the response in the OpenAPI document.
+    ///
+    /// Only HTTP/1 responses carry a reason phrase.  This has no effect on
+    /// handlers whose response type doesn't declare its success status (like
+    /// `Response<Body>`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reason` contains characters not allowed in a reason phrase
+    /// (control characters other than tab).
+    pub fn success_reason(mut self, reason: &str) -> Self {
+        let phrase =
+            ReasonPhrase::try_from(reason.as_bytes()).unwrap_or_else(|_| {
+                panic!("invalid reason phrase for endpoint: {:?}", reason)
+            });
+        if let Some(status) = self.response.success {
+            self.handler = StatusOverrideHandler::new(
+                self.handler,
+                status,
+                status,
+                Some(phrase),
+            );
+            self.response.description = Some(reason.to_string());
+        }
+        self
+    }
+
+    /// Adds an OpenAPI link named `name` from this endpoint's successful
+    /// response to the operation `operation_id`, which is passed the given
+    /// `parameters` (pairs of parameter name and value, such as the runtime
+    /// expression `$response.body#/id`).  This is typically used to point
+    /// from a 202 ("Accepted") response to the operation that reports the
+    /// status of the work (see [`crate::http_response_accepted`]).
+    pub fn response_link<S1, S2>(
+        mut self,
+        name: S1,
+        operation_id: S2,
+        parameters: &[(&str, &str)],
+    ) -> Self
+    where
+        S1: ToString,
+        S2: ToString,
+    {
+        self.response.links.push(ApiEndpointLink {
+            name: name.to_string(),
+            operation_id: operation_id.to_string(),
+            parameters: parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        });
+        self
+    }
+
+    /// Refers to documentation of this endpoint's operation found at `url`
+    /// (described by `description`, if given) in the OpenAPI definition.
+    pub fn external_docs<S: ToString>(
+        mut self,
+        url: S,
+        description: Option<&str>,
+    ) -> Self {
+        self.external_docs = Some(TagExternalDocs {
+            description: description.map(str::to_string),
+            url: url.to_string(),
+        });
+        self
+    }
+
+    /// Adds an example named `name` of this endpoint's request body to the
+    /// OpenAPI definition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint has no request body, or if `value` can't be
+    /// serialized as JSON.
+    pub fn request_example<S, T>(mut self, name: S, value: T) -> Self
+    where
+        S: ToString,
+        T: Serialize,
+    {
+        let example = ApiEndpointExample::new(name, value);
+        let body = self
+            .parameters
+            .iter_mut()
+            .find(|param| {
+                matches!(param.metadata, ApiEndpointParameterMetadata::Body(_))
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "endpoint \"{}\" has no request body for example \"{}\"",
+                    self.operation_id, example.name
+                )
+            });
+        body.examples.push(example);
+        self
+    }
+
+    /// Adds an example named `name` of this endpoint's successful response
+    /// body to the OpenAPI definition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` can't be serialized as JSON.
+    pub fn response_example<S, T>(mut self, name: S, value: T) -> Self
+    where
+        S: ToString,
+        T: Serialize,
+    {
+        self.response.examples.push(ApiEndpointExample::new(name, value));
+        self
+    }
+
+    /// Documents `content_type` (e.g., "text/plain" or "image/png") as a
+    /// media type of this endpoint's successful response in the OpenAPI
+    /// definition.  Each call adds a media type, and once any are given, only
+    /// those are documented.  JSON media types (including those with a
+    /// `+json` suffix) are described by the response type's schema, if it has
+    /// one, and other media types as binary strings (or, for `text/*`, plain
+    /// strings).
+    ///
+    /// This is mostly useful for handlers that return `Response<Body>` or a
+    /// [`crate::FreeformBody`], whose responses are otherwise documented as
+    /// having any media type.  It only affects the OpenAPI definition: the
+    /// handler is still responsible for the `Content-Type` header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `content_type` is not a valid media type.
+    pub fn response_content_type(mut self, content_type: &str) -> Self {
+        assert!(
+            content_type.parse::<mime_guess::mime::Mime>().is_ok(),
+            "endpoint \"{}\" has invalid response content type \"{}\"",
+            self.operation_id,
+            content_type
+        );
+        self.response.content_types.push(content_type.to_string());
+        self
+    }
+
+    /// Adds an example named `name` of the path, query, or header parameter
+    /// `parameter` to the OpenAPI definition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint has no such parameter, or if `value` can't be
+    /// serialized as JSON.
+    pub fn parameter_example<S, T>(
+        mut self,
+        parameter: &str,
+        name: S,
+        value: T,
+    ) -> Self
+    where
+        S: ToString,
+        T: Serialize,
+    {
+        let example = ApiEndpointExample::new(name, value);
+        self.named_parameter_mut(parameter).examples.push(example);
+        self
+    }
+
+    /// Adds the vendor extension `name` (which must begin with `x-`) to this
+    /// endpoint's operation in the OpenAPI definition, with the given value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` doesn't begin with `x-`, or if `value` can't be
+    /// serialized as JSON.
+    pub fn extension<T: Serialize>(mut self, name: &str, value: T) -> Self {
+        self.extensions.insert(name.to_string(), extension_value(name, value));
+        self
+    }
+
+    /// Adds the vendor extension `name` (which must begin with `x-`) to the
+    /// path, query, or header parameter `parameter` in the OpenAPI
+    /// definition, with the given value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the endpoint has no such parameter, if `name` doesn't begin
+    /// with `x-`, or if `value` can't be serialized as JSON.
+    pub fn parameter_extension<T: Serialize>(
+        mut self,
+        parameter: &str,
+        name: &str,
+        value: T,
+    ) -> Self {
+        let value = extension_value(name, value);
+        self.named_parameter_mut(parameter)
+            .extensions
+            .insert(name.to_string(), value);
+        self
+    }
+
+    /// Documents a request named `name` that the server may make as a result
+    /// of this endpoint's operation (an OpenAPI callback), to the URL given by
+    /// the runtime expression `url` (e.g., `{$request.body#/callback_url}`).
+    pub fn callback<S1, S2>(
+        mut self,
+        name: S1,
+        url: S2,
+        hook: ApiWebhook,
+    ) -> Self
+    where
+        S1: ToString,
+        S2: ToString,
+    {
+        self.callbacks.push(ApiEndpointCallback {
+            name: name.to_string(),
+            url: url.to_string(),
+            hook,
+        });
+        self
+    }
+
+    /// Returns this endpoint with a handler that responds to every request
+    /// with the first of its response examples (see [`ApiDescription::into_stub`])
+    fn into_stub<S: ServerContext>(self) -> ApiEndpoint<S> {
+        let handler = StubHandler::new(
+            &self.operation_id,
+            self.response.success.unwrap_or(StatusCode::OK),
+            self.response.examples.first().map(|example| &example.value),
+        );
+        ApiEndpoint {
+            operation_id: self.operation_id,
+            handler,
+            method: self.method,
+            path: self.path,
+            parameters: self.parameters,
+            body_content_type: self.body_content_type,
+            response: self.response,
+            summary: self.summary,
+            description: self.description,
+            tags: self.tags,
+            extension_mode: self.extension_mode,
+            visibility: self.visibility,
+            versions: self.versions,
+            deprecated: self.deprecated,
+            deprecated_versions: self.deprecated_versions,
+            request_body_max_bytes: self.request_body_max_bytes,
+            page_limits: self.page_limits,
+            security: self.security,
+            extensions: self.extensions,
+            callbacks: self.callbacks,
+            external_docs: self.external_docs,
+            frozen: self.frozen,
+            response_has_body: self.response_has_body,
+            request_body_streamed: self.request_body_streamed,
+        }
+    }
+
+    fn named_parameter_mut(
+        &mut self,
+        parameter: &str,
+    ) -> &mut ApiEndpointParameter {
+        let operation_id = &self.operation_id;
+        self.parameters
+            .iter_mut()
+            .find(|param| match &param.metadata {
+                ApiEndpointParameterMetadata::Path(name)
+                | ApiEndpointParameterMetadata::Query(name)
+                | ApiEndpointParameterMetadata::Header(name) => {
+                    name == parameter
+                }
+                ApiEndpointParameterMetadata::Body(_) => false,
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "endpoint \"{}\" has no parameter \"{}\"",
+                    operation_id, parameter
+                )
+            })
+    }
+}
+
+/// Returns `value`, serialized as JSON, for the vendor extension `name`
+///
+/// # Panics
+///
+/// Panics if `name` doesn't begin with `x-`, or if `value` can't be serialized
+/// as JSON.
+fn extension_value<T: Serialize>(name: &str, value: T) -> serde_json::Value {
+    assert!(
+        name.starts_with("x-"),
+        "extension name \"{}\" must begin with \"x-\"",
+        name
+    );
+    serde_json::to_value(value).unwrap_or_else(|error| {
+        panic!("extension \"{}\" can't be serialized: {}", name, error)
+    })
+}
+
+/// Returns `schema` as JSON, generating it (and the schemas it refers to) with
+/// `generator` if need be
+fn schema_json(
+    schema: &ApiSchemaGenerator,
+    generator: &mut schemars::gen::SchemaGenerator,
+) -> serde_json::Value {
+    match schema {
+        ApiSchemaGenerator::Gen { schema, .. } => {
+            serde_json::json!(schema(generator))
+        }
+        ApiSchemaGenerator::Static { schema, dependencies } => {
+            serde_json::json!({ "schema": schema, "dependencies": dependencies })
+        }
+    }
 }
 
 /// ApiEndpointParameter represents the discrete path and query parameters for a
@@ -120,7 +747,12 @@ pub struct ApiEndpointParameter {
     pub description: Option<String>,
     pub required: bool,
     pub schema: ApiSchemaGenerator,
-    pub examples: Vec<String>,
+    pub examples: Vec<ApiEndpointExample>,
+    /// Vendor extensions for this parameter, by name
+    pub extensions: BTreeMap<String, serde_json::Value>,
+    /// How this parameter is serialized, if its type declares it (see
+    /// [`crate::Exploded`] and the like)
+    pub serialization: Option<ParameterSerialization>,
 }
 
 impl ApiEndpointParameter {
@@ -130,7 +762,7 @@ impl ApiEndpointParameter {
         description: Option<String>,
         required: bool,
         schema: ApiSchemaGenerator,
-        examples: Vec<String>,
+        examples: Vec<ApiEndpointExample>,
     ) -> Self {
         Self {
             metadata: match loc {
@@ -140,11 +772,16 @@ impl ApiEndpointParameter {
                 ApiEndpointParameterLocation::Query => {
                     ApiEndpointParameterMetadata::Query(name)
                 }
+                ApiEndpointParameterLocation::Header => {
+                    ApiEndpointParameterMetadata::Header(name)
+                }
             },
             description,
             required,
             schema,
             examples,
+            extensions: BTreeMap::new(),
+            serialization: None,
         }
     }
 
@@ -152,7 +789,7 @@ impl ApiEndpointParameter {
         content_type: ApiEndpointBodyContentType,
         required: bool,
         schema: ApiSchemaGenerator,
-        examples: Vec<String>,
+        examples: Vec<ApiEndpointExample>,
     ) -> Self {
         Self {
             metadata: ApiEndpointParameterMetadata::Body(content_type),
@@ -160,6 +797,8 @@ impl ApiEndpointParameter {
             schema,
             examples,
             description: None,
+            extensions: BTreeMap::new(),
+            serialization: None,
         }
     }
 }
@@ -168,12 +807,14 @@ impl ApiEndpointParameter {
 pub enum ApiEndpointParameterLocation {
     Path,
     Query,
+    Header,
 }
 
 #[derive(Debug, Clone)]
 pub enum ApiEndpointParameterMetadata {
     Path(String),
     Query(String),
+    Header(String),
     Body(ApiEndpointBodyContentType),
 }
 
@@ -224,6 +865,136 @@ pub struct ApiEndpointHeader {
     pub required: bool,
 }
 
+/// A named example of a request body, response body, or parameter, as it
+/// appears in the OpenAPI definition
+#[derive(Clone, Debug)]
+pub struct ApiEndpointExample {
+    /// name of the example, unique among those for the same value
+    pub name: String,
+    /// the example value
+    pub value: serde_json::Value,
+}
+
+impl ApiEndpointExample {
+    /// Returns an example named `name` whose value is `value` serialized as
+    /// JSON
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` can't be serialized as JSON.
+    pub fn new<S: ToString, T: Serialize>(name: S, value: T) -> Self {
+        let name = name.to_string();
+        let value = serde_json::to_value(value).unwrap_or_else(|error| {
+            panic!("example \"{}\" can't be serialized: {}", name, error)
+        });
+        ApiEndpointExample { name, value }
+    }
+}
+
+/// An OpenAPI link from an endpoint's response to another operation (e.g.,
+/// one that reports the status of a long-running operation)
+#[derive(Debug)]
+pub struct ApiEndpointLink {
+    /// name of the link, unique among the response's links
+    pub name: String,
+    /// operation id of the target operation
+    pub operation_id: String,
+    /// target operation parameters, by name, with the value of each (e.g., a
+    /// runtime expression like `$response.body#/id`)
+    pub parameters: Vec<(String, String)>,
+}
+
+/// Extension of the OpenAPI definition that lists groups of tags (see
+/// [`TagConfig::tag_groups`])
+pub(crate) const TAG_GROUPS_EXTENSION: &str = "x-tagGroups";
+
+/// Extension of the OpenAPI definition that describes the requests added with
+/// [`ApiDescription::webhook`]
+pub(crate) const WEBHOOKS_EXTENSION: &str = "x-webhooks";
+
+/// An OpenAPI callback: a request that the server may make to the client as
+/// a result of an endpoint's operation (see [`ApiEndpoint::callback`])
+#[derive(Debug)]
+pub struct ApiEndpointCallback {
+    /// name of the callback, unique among the operation's callbacks
+    pub name: String,
+    /// runtime expression for the URL to which the request is made
+    pub url: String,
+    /// the request that's made
+    pub hook: ApiWebhook,
+}
+
+/// A request that a service makes to its clients, such as the delivery of an
+/// event to a webhook, as it appears in the OpenAPI definition
+///
+/// These are documented either as callbacks of the operations that cause them
+/// (see [`ApiEndpoint::callback`]) or, for events that aren't caused by any
+/// one operation, alongside the API's endpoints (see
+/// [`ApiDescription::webhook`]).  In both cases, the request carries a JSON
+/// body described by a type that implements [`JsonSchema`]:
+///
+/// ```
+/// use dropshot::ApiDescription;
+/// use dropshot::ApiWebhook;
+/// use schemars::JsonSchema;
+/// use serde::Serialize;
+///
+/// /// Sent when a project is created
+/// #[derive(JsonSchema, Serialize)]
+/// struct ProjectCreated {
+///     id: String,
+///     name: String,
+/// }
+///
+/// let api = ApiDescription::<()>::new().webhook(
+///     ApiWebhook::new::<ProjectCreated>("project_created")
+///         .summary("a project was created"),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct ApiWebhook {
+    /// name of the request, used as its operation id
+    pub name: String,
+    /// HTTP method of the request
+    pub method: Method,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    /// schema of the request body
+    pub payload: ApiSchemaGenerator,
+}
+
+impl ApiWebhook {
+    /// Returns a description of a `POST` request named `name` whose body is a
+    /// `T`, serialized as JSON
+    pub fn new<T: JsonSchema>(name: &str) -> Self {
+        ApiWebhook {
+            name: name.to_string(),
+            method: Method::POST,
+            summary: None,
+            description: None,
+            payload: ApiSchemaGenerator::Gen {
+                name: T::schema_name,
+                schema: make_subschema_for::<T>,
+            },
+        }
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub fn summary<T: ToString>(mut self, summary: T) -> Self {
+        self.summary.replace(summary.to_string());
+        self
+    }
+
+    pub fn description<T: ToString>(mut self, description: T) -> Self {
+        self.description.replace(description.to_string());
+        self
+    }
+}
+
 /// Metadata for an API endpoint response: type information and status code.
 #[derive(Debug, Default)]
 pub struct ApiEndpointResponse {
@@ -231,6 +1002,24 @@ pub struct ApiEndpointResponse {
     pub headers: Vec<ApiEndpointHeader>,
     pub success: Option<StatusCode>,
     pub description: Option<String>,
+    pub links: Vec<ApiEndpointLink>,
+    pub examples: Vec<ApiEndpointExample>,
+    /// Media types of the response body documented in place of the response
+    /// type's own (see [`ApiEndpoint::response_content_type`])
+    pub content_types: Vec<String>,
+    /// Other successful responses the endpoint may produce instead of the
+    /// `success` one
+    pub alternates: Vec<ApiEndpointAlternateResponse>,
+}
+
+/// A successful response, other than the usual one, that an endpoint may
+/// produce (e.g., a 202 for an operation that the client asked to run
+/// asynchronously).  These have no body.
+#[derive(Debug)]
+pub struct ApiEndpointAlternateResponse {
+    pub status: StatusCode,
+    pub description: String,
+    pub headers: Vec<ApiEndpointHeader>,
 }
 
 /// Wrapper for both dynamically generated and pre-generated schemas.
@@ -264,6 +1053,113 @@ pub struct ApiDescription<Context: ServerContext> {
     /// In practice, all the information we need is encoded in the router.
     router: HttpRouter<Context>,
     tag_config: TagConfig,
+    /// Optional function used to report path parameter errors
+    pub(crate) path_error_handler: Option<PathErrorHandler>,
+    /// Format of error response bodies
+    pub(crate) error_response_format: ErrorResponseFormat,
+    /// Optional function used to produce all error responses
+    pub(crate) error_mapper: Option<ErrorMapper>,
+    /// The OpenAPI document served by the endpoint registered with
+    /// [`ApiDescription::register_openapi_endpoint`], if any
+    served_openapi: Option<ServedOpenApi>,
+    /// Security schemes that endpoints may use, by name
+    security_schemes: BTreeMap<String, SecurityScheme>,
+    /// Vendor extensions for named schemas, by schema name
+    schema_extensions: BTreeMap<String, BTreeMap<String, serde_json::Value>>,
+    /// Requests the service makes to its clients, outside of any operation
+    webhooks: Vec<ApiWebhook>,
+    /// Adjusts the settings used to generate schemas for the OpenAPI
+    /// definition, if provided
+    schema_settings: Option<SchemaSettingsHook>,
+    /// Functions run over each schema in the OpenAPI definition's
+    /// `components` section, in order
+    schema_visitors: Vec<SchemaVisitor>,
+    /// Whether path parameters common to all of a path's operations are
+    /// described once for the path in the OpenAPI definition
+    share_path_parameters: bool,
+    /// Determines the version of the API that each request is for
+    pub(crate) version_policy: VersionPolicy,
+    /// Optional function told of the version that each request is for
+    pub(crate) version_observer: Option<VersionObserver>,
+    /// How requests for prerelease versions are matched with the versions of
+    /// endpoints
+    pub(crate) prerelease_matching: PrereleaseMatching,
+    /// Versions of the API that are published (see
+    /// [`ApiDescription::api_versions`])
+    api_versions: BTreeSet<semver::Version>,
+}
+
+/// Function used to adjust the settings with which the schemas in an OpenAPI
+/// definition are generated.  See [`ApiDescription::schema_settings`].
+pub type SchemaSettingsHook =
+    Arc<dyn Fn(&mut schemars::gen::SchemaSettings) + Send + Sync>;
+
+/// Function run over each named schema in an OpenAPI definition, given its
+/// name and its JSON form, either of which it may change.  See
+/// [`ApiDescription::schema_visitor`].
+pub type SchemaVisitor =
+    Arc<dyn Fn(&mut String, &mut serde_json::Value) + Send + Sync>;
+
+/// The OpenAPI documents served by an API's own endpoint
+///
+/// The documents describe every endpoint in the API, including those
+/// registered after the one that serves them, so they're only generated once
+/// the API is complete (when a server is created from it).
+struct ServedOpenApi {
+    path: String,
+    info: openapiv3::Info,
+    documents: Arc<OnceLock<ServedDocuments>>,
+}
+
+/// The OpenAPI documents served by an API's own endpoint: one for the version
+/// given to [`ApiDescription::register_openapi_endpoint`], and one for each
+/// version declared with [`ApiDescription::api_versions`]
+struct ServedDocuments {
+    default: ServedDocument,
+    by_version: BTreeMap<semver::Version, ServedDocument>,
+}
+
+impl ServedDocuments {
+    /// Returns the document for the version of the API that a request is
+    /// handled as, or the default document if that version wasn't declared
+    fn for_version(
+        &self,
+        version: Option<&semver::Version>,
+    ) -> &ServedDocument {
+        version
+            .and_then(|version| self.by_version.get(version))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// One OpenAPI document, in each of the formats it's served in
+struct ServedDocument {
+    json: Vec<u8>,
+    #[cfg(feature = "yaml")]
+    yaml: Vec<u8>,
+}
+
+impl ServedDocument {
+    fn new<Context: ServerContext>(
+        definition: &OpenApiDefinition<'_, Context>,
+    ) -> ServedDocument {
+        // Serializing an OpenAPI definition can't fail: its maps all have
+        // string keys.
+        let json = definition.json().unwrap();
+        ServedDocument {
+            json: serde_json::to_vec_pretty(&json).unwrap(),
+            #[cfg(feature = "yaml")]
+            yaml: serde_yaml::to_string(&json).unwrap().into_bytes(),
+        }
+    }
+}
+
+/// Format of an OpenAPI document served by an API's own endpoint
+#[derive(Clone, Copy)]
+enum ServedFormat {
+    Json,
+    #[cfg(feature = "yaml")]
+    Yaml,
 }
 
 impl<Context: ServerContext> ApiDescription<Context> {
@@ -271,6 +1167,20 @@ impl<Context: ServerContext> ApiDescription<Context> {
         ApiDescription {
             router: HttpRouter::new(),
             tag_config: TagConfig::default(),
+            path_error_handler: None,
+            error_response_format: ErrorResponseFormat::default(),
+            error_mapper: None,
+            served_openapi: None,
+            security_schemes: BTreeMap::new(),
+            schema_extensions: BTreeMap::new(),
+            webhooks: Vec::new(),
+            schema_settings: None,
+            schema_visitors: Vec::new(),
+            share_path_parameters: true,
+            version_policy: VersionPolicy::Unversioned,
+            version_observer: None,
+            prerelease_matching: PrereleaseMatching::default(),
+            api_versions: BTreeSet::new(),
         }
     }
 
@@ -279,6 +1189,279 @@ impl<Context: ServerContext> ApiDescription<Context> {
         self
     }
 
+    /// Declare a security scheme named `name` that endpoints may use (see
+    /// [`ApiEndpoint::security`]).  Schemes must be declared before the
+    /// endpoints that use them are registered.
+    ///
+    /// ```
+    /// use dropshot::ApiDescription;
+    /// use dropshot::SecurityScheme;
+    ///
+    /// let api = ApiDescription::<()>::new().security_scheme(
+    ///     "bearer",
+    ///     SecurityScheme::bearer().description("session token"),
+    /// );
+    /// ```
+    pub fn security_scheme<S: ToString>(
+        mut self,
+        name: S,
+        scheme: SecurityScheme,
+    ) -> Self {
+        self.security_schemes.insert(name.to_string(), scheme);
+        self
+    }
+
+    /// Add the vendor extension `name` (which must begin with `x-`) to the
+    /// schema named `schema` (e.g., a type's name) in the `components`
+    /// section of the OpenAPI definition, with the given value.  Nothing is
+    /// added if the definition has no such schema.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` doesn't begin with `x-`, or if `value` can't be
+    /// serialized as JSON.
+    pub fn schema_extension<T: Serialize>(
+        mut self,
+        schema: &str,
+        name: &str,
+        value: T,
+    ) -> Self {
+        let value = extension_value(name, value);
+        self.schema_extensions
+            .entry(schema.to_string())
+            .or_default()
+            .insert(name.to_string(), value);
+        self
+    }
+
+    /// Document a request that the service makes to its clients (e.g., to
+    /// deliver an event to a webhook) that isn't caused by any one of its
+    /// endpoints.  OpenAPI 3.0 has no place for these, so they appear in the
+    /// `x-webhooks` extension of the OpenAPI definition, in the form of the
+    /// `webhooks` object of OpenAPI 3.1 (which some documentation tools
+    /// understand).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a webhook with the same name has already been added.
+    pub fn webhook(mut self, hook: ApiWebhook) -> Self {
+        assert!(
+            self.webhooks.iter().all(|other| other.name != hook.name),
+            "webhook \"{}\" has already been added",
+            hook.name
+        );
+        self.webhooks.push(hook);
+        self
+    }
+
+    /// Specify a function that adjusts the settings with which the schemas of
+    /// request and response bodies are generated for the OpenAPI definition.
+    /// The function is given [`SchemaSettings::openapi3`] to modify, which is
+    /// what's used otherwise.  For example, setting `inline_subschemas` causes
+    /// types to be described where they're used, instead of by references to
+    /// the `components` section.
+    ///
+    /// References to named schemas are made using the settings'
+    /// `definitions_path`, but the schemas themselves are always in the
+    /// `components` section, so changing it is only useful in combination
+    /// with tools that move them accordingly.
+    ///
+    /// [`SchemaSettings::openapi3`]: schemars::gen::SchemaSettings::openapi3
+    pub fn schema_settings<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut schemars::gen::SchemaSettings) + Send + Sync + 'static,
+    {
+        self.schema_settings = Some(Arc::new(hook));
+        self
+    }
+
+    /// Specify a function run over each schema in the `components` section of
+    /// the OpenAPI definition, once the definition has been generated.  The
+    /// function is given the schema's name and its JSON form, and can change
+    /// either one: e.g., to apply naming conventions, to remove properties
+    /// that are for internal use, or to add annotations.  References to
+    /// schemas that are renamed are updated throughout the definition.
+    /// Functions are run in the order they're specified.
+    ///
+    /// ```
+    /// use dropshot::ApiDescription;
+    ///
+    /// let api = ApiDescription::<()>::new().schema_visitor(|name, schema| {
+    ///     *name = format!("Acme{}", name);
+    ///     if let Some(properties) = schema["properties"].as_object_mut() {
+    ///         properties.remove("internal_id");
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the OpenAPI definition panics if two schemas are given the
+    /// same name, or if a schema is changed into something that isn't a valid
+    /// OpenAPI schema.
+    pub fn schema_visitor<F>(mut self, visitor: F) -> Self
+    where
+        F: Fn(&mut String, &mut serde_json::Value) + Send + Sync + 'static,
+    {
+        self.schema_visitors.push(Arc::new(visitor));
+        self
+    }
+
+    /// Specify whether path parameters that all of a path's operations have
+    /// in common (e.g., `{project_id}` in `/projects/{project_id}`) are
+    /// described once, in the path item of the OpenAPI definition, rather
+    /// than in each of its operations.  They're shared by default.
+    ///
+    /// Parameters are only shared by paths with more than one operation, and
+    /// only if every operation describes them in exactly the same way.
+    pub fn share_path_parameters(mut self, share: bool) -> Self {
+        self.share_path_parameters = share;
+        self
+    }
+
+    /// Specify a function used to construct the error returned to clients
+    /// when the [`crate::Path`] extractor fails to deserialize a request's
+    /// path parameters.  The function is given a [`PathError`] identifying
+    /// the offending variable, its value, and the expected type.  By default,
+    /// these errors are reported as 400 ("Bad Request") errors with a
+    /// descriptive message.
+    pub fn path_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(PathError) -> HttpError + Send + Sync + 'static,
+    {
+        self.path_error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Specify the format of the bodies of error responses.  With
+    /// [`ErrorResponseFormat::ProblemDetails`], servers for this API render
+    /// errors as RFC 9457 `application/problem+json` objects (using the request
+    /// path as the problem's `instance`), and the OpenAPI document's error
+    /// response refers to the [`ProblemDetails`] schema.  By default, errors
+    /// are rendered as [`HttpErrorResponseBody`] objects.
+    pub fn error_response_format(
+        mut self,
+        format: ErrorResponseFormat,
+    ) -> Self {
+        self.error_response_format = format;
+        self
+    }
+
+    /// Specify a function used to produce the response for every error that a
+    /// server for this API returns, whether it comes from a handler, an
+    /// extractor (e.g., a 400 for a malformed body), or Dropshot itself (e.g.,
+    /// a 404 or 405 from routing).  A handler that panics is reported to the
+    /// function as a 500 error, too, rather than the panic propagating.  This
+    /// lets consumers use their own error envelope everywhere without wrapping
+    /// each handler.  The function is given the error and a description of
+    /// the request; Dropshot still adds the request id header to whatever it
+    /// returns.  This takes precedence
+    /// over [`ApiDescription::error_response_format`] and
+    /// [`crate::ConfigDropshot::error_content_negotiation`] for the wire
+    /// format, though the OpenAPI document still describes errors according
+    /// to the former.
+    pub fn map_error<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(HttpError, &RequestInfo) -> hyper::Response<hyper::Body>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.error_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Specify how servers for this API determine the version of the API that
+    /// each request is for, and so which of the endpoints for its method and
+    /// path handles it (see [`ApiEndpoint::versions`]).  By default, the API
+    /// isn't versioned.
+    pub fn version_policy(mut self, policy: VersionPolicy) -> Self {
+        self.version_policy = policy;
+        self
+    }
+
+    /// Specify a function to be called for each request to a versioned API
+    /// with the operation id of the endpoint that handles it, the version of
+    /// the API that the client asked for, and the versions that the endpoint is
+    /// in (see [`ApiEndpoint::versions`])
+    ///
+    /// This lets services record which versions clients still use (e.g., as a
+    /// metric), so they know when an old range of versions can be dropped.
+    /// The function is called before the handler runs, so it should be quick.
+    pub fn version_observer<F>(mut self, observer: F) -> Self
+    where
+        F: Fn(&str, &semver::Version, &ApiEndpointVersions)
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.version_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Specify how requests for prerelease versions of the API (e.g.,
+    /// "2.0.0-beta.1") are matched with the versions of endpoints, both when
+    /// routing requests and when generating OpenAPI definitions.  By default,
+    /// they're matched as semver orders them (see [`PrereleaseMatching`]).
+    pub fn prerelease_matching(mut self, matching: PrereleaseMatching) -> Self {
+        self.prerelease_matching = matching;
+        self
+    }
+
+    /// Returns the version that endpoints' versions are matched with for
+    /// the API version `version`, if it's a semver
+    fn matching_version(&self, version: &str) -> Option<semver::Version> {
+        semver::Version::parse(version)
+            .ok()
+            .map(|version| self.prerelease_matching.matching_version(&version))
+    }
+
+    /// Declare the versions of this API that are published, in addition to
+    /// any declared already.  These are the versions whose OpenAPI
+    /// definitions [`ApiDescription::openapi_all_versions`] generates.
+    pub fn api_versions<I>(mut self, versions: I) -> Self
+    where
+        I: IntoIterator<Item = semver::Version>,
+    {
+        self.api_versions.extend(versions);
+        self
+    }
+
+    /// Checks that every version of the API has an endpoint for each
+    /// operation (method and path) between the first and last versions that
+    /// have one, returning an error describing the gaps if not
+    ///
+    /// Endpoints for the same operation can't overlap (registering them
+    /// panics), so this checks that each version in that span has exactly one.
+    /// An operation may still be added in a later version, or removed in one,
+    /// but not left out of versions in between, which usually means that an
+    /// endpoint's range was mistyped.  API owners may call this at startup.
+    /// See [`ApiEndpointVersions::gaps`].
+    pub fn check_version_coverage(&self) -> Result<(), String> {
+        let mut operations = BTreeMap::<_, Vec<&ApiEndpointVersions>>::new();
+        for (path, method, endpoint) in &self.router {
+            operations
+                .entry((path, method))
+                .or_default()
+                .push(&endpoint.versions);
+        }
+        let gaps = operations
+            .into_iter()
+            .flat_map(|((path, method), versions)| {
+                ApiEndpointVersions::gaps(versions).into_iter().map(
+                    move |gap| {
+                        format!("{} {}: no endpoint in {}", method, path, gap)
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        if gaps.is_empty() {
+            Ok(())
+        } else {
+            Err(gaps.join("; "))
+        }
+    }
+
     /// Register a new API endpoint.
     pub fn register<T>(&mut self, endpoint: T) -> Result<(), String>
     where
@@ -292,8 +1475,11 @@ impl<Context: ServerContext> ApiDescription<Context> {
             e: ApiEndpoint<C>,
         ) -> Result<(), String> {
             s.validate_tags(&e)?;
+            s.validate_security(&e)?;
             s.validate_path_parameters(&e)?;
             s.validate_named_parameters(&e)?;
+            s.validate_frozen(&e)?;
+            s.validate_page_limits(&e)?;
 
             s.router.insert(e);
 
@@ -305,10 +1491,270 @@ impl<Context: ServerContext> ApiDescription<Context> {
         Ok(())
     }
 
+    /// Register an endpoint that responds to `GET` requests for `path` (e.g.,
+    /// "/openapi.json") with the OpenAPI definition for this API, so that
+    /// consumers don't each need to write their own.
+    ///
+    /// The `title` and `version` are used as for [`ApiDescription::openapi`].
+    /// The definition describes every endpoint in the API, even those
+    /// registered after this one, but not this endpoint itself.  If the
+    /// version that a request is handled as (see [`crate::VersionPolicy`]) is
+    /// one declared with [`ApiDescription::api_versions`], the definition of
+    /// that version is served instead (see
+    /// [`ApiDescription::openapi_all_versions`]).  The definitions are
+    /// generated once, when a server is created for the API.
+    ///
+    /// With the `yaml` feature, the definition is also served as YAML from
+    /// the same path with a ".yaml" extension in place of any ".json" one
+    /// (e.g., "/openapi.yaml").
+    ///
+    /// ```
+    /// use dropshot::ApiDescription;
+    ///
+    /// let mut api = ApiDescription::<()>::new();
+    /// api.register_openapi_endpoint("/openapi.json", "Example API", "1.0.0")
+    ///     .unwrap();
+    /// ```
+    pub fn register_openapi_endpoint<S1, S2>(
+        &mut self,
+        path: &str,
+        title: S1,
+        version: S2,
+    ) -> Result<(), String>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        if self.served_openapi.is_some() {
+            return Err(String::from(
+                "an OpenAPI endpoint has already been registered",
+            ));
+        }
+
+        let documents = Arc::new(OnceLock::new());
+        self.register(openapi_endpoint(
+            path,
+            ServedFormat::Json,
+            Arc::clone(&documents),
+        ))?;
+        #[cfg(feature = "yaml")]
+        self.register(openapi_endpoint(
+            &openapi_yaml_path(path),
+            ServedFormat::Yaml,
+            Arc::clone(&documents),
+        ))?;
+
+        self.served_openapi = Some(ServedOpenApi {
+            path: path.to_string(),
+            info: openapiv3::Info {
+                title: title.as_ref().to_string(),
+                version: version.as_ref().to_string(),
+                ..Default::default()
+            },
+            documents,
+        });
+        Ok(())
+    }
+
+    /// Add the endpoints of `other` (e.g., an API fragment from another crate
+    /// or team) to this API, along with its tag definitions, security
+    /// schemes, webhooks, schema extensions and visitors, and OpenAPI
+    /// endpoint.
+    ///
+    /// Nothing is changed if the two APIs conflict: if they have endpoints
+    /// with the same operation id, or for the same method and path, or with
+    /// paths that name the same variable differently; or if they define the
+    /// same tag, security scheme, or webhook differently; or if they both
+    /// have an OpenAPI endpoint.  The returned error describes every
+    /// conflict.  It's also an error if `other`'s endpoints don't conform to
+    /// this API's tag policy.
+    ///
+    /// Settings that apply to the API as a whole are this API's: its tag
+    /// policy, its error handling and format, its schema settings, whether
+    /// it shares path parameters, and its version policy.  `other`'s are
+    /// ignored, except that its published versions are added to this API's.
+    /// Tags that `other` orders or groups are ordered or grouped after this
+    /// API's own.
+    pub fn merge(
+        &mut self,
+        other: ApiDescription<Context>,
+    ) -> Result<(), String> {
+        let mut conflicts = BTreeSet::new();
+
+        let ours = (&self.router)
+            .into_iter()
+            .map(|(_, method, e)| {
+                (
+                    method,
+                    e.path.clone(),
+                    e.operation_id.clone(),
+                    e.versions.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for (_, method, e) in &other.router {
+            for (our_method, our_path, our_operation_id, our_versions) in &ours
+            {
+                if *our_operation_id == e.operation_id {
+                    conflicts.insert(format!(
+                        "operation id \"{}\" is used by both APIs",
+                        e.operation_id
+                    ));
+                }
+                match route_overlap(our_path, &e.path) {
+                    RouteOverlap::None => (),
+                    RouteOverlap::Same
+                        if *our_method == method
+                            && our_versions.overlaps_with(&e.versions) =>
+                    {
+                        conflicts.insert(format!(
+                            "both APIs have an endpoint for {} {}",
+                            method, e.path
+                        ));
+                    }
+                    RouteOverlap::Same => (),
+                    RouteOverlap::Variables(ours, theirs) => {
+                        conflicts.insert(format!(
+                            "path \"{}\" names variable \"{}\" where path \
+                             \"{}\" names it \"{}\"",
+                            e.path, theirs, our_path, ours
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (name, theirs) in &other.tag_config.tag_definitions {
+            if let Some(ours) = self.tag_config.tag_definitions.get(name) {
+                let docs = |details: &TagDetails| {
+                    details
+                        .external_docs
+                        .as_ref()
+                        .map(|e| (e.description.clone(), e.url.clone()))
+                };
+                if ours.description != theirs.description
+                    || docs(ours) != docs(theirs)
+                {
+                    conflicts.insert(format!(
+                        "tag \"{}\" is defined differently by each API",
+                        name
+                    ));
+                }
+            }
+        }
+        for (name, theirs) in &other.security_schemes {
+            if let Some(ours) = self.security_schemes.get(name) {
+                // Serializing a security scheme can't fail.
+                let ours = serde_json::to_value(ours.to_openapi()).unwrap();
+                let theirs = serde_json::to_value(theirs.to_openapi()).unwrap();
+                if ours != theirs {
+                    conflicts.insert(format!(
+                        "security scheme \"{}\" is defined differently by \
+                         each API",
+                        name
+                    ));
+                }
+            }
+        }
+        for hook in &other.webhooks {
+            if self.webhooks.iter().any(|ours| ours.name == hook.name) {
+                conflicts.insert(format!(
+                    "webhook \"{}\" is defined by both APIs",
+                    hook.name
+                ));
+            }
+        }
+        if self.served_openapi.is_some() && other.served_openapi.is_some() {
+            conflicts
+                .insert(String::from("both APIs have an OpenAPI endpoint"));
+        }
+
+        if !conflicts.is_empty() {
+            return Err(conflicts.into_iter().collect::<Vec<_>>().join("; "));
+        }
+
+        let ApiDescription {
+            router,
+            tag_config: their_tags,
+            security_schemes,
+            schema_extensions,
+            webhooks,
+            schema_visitors,
+            served_openapi,
+            api_versions,
+            ..
+        } = other;
+
+        // Check the other API's endpoints against this one's tag policy, given
+        // the tags that either one defines.
+        let added_tags = their_tags
+            .tag_definitions
+            .into_iter()
+            .filter(|(name, _)| {
+                !self.tag_config.tag_definitions.contains_key(name)
+            })
+            .collect::<Vec<_>>();
+        let added_names =
+            added_tags.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        self.tag_config.tag_definitions.extend(added_tags);
+        if let Err(error) = (&router).into_iter().try_for_each(|(_, _, e)| {
+            self.validate_tags(e).map_err(|error| {
+                format!("endpoint \"{}\": {}", e.operation_id, error)
+            })
+        }) {
+            for name in &added_names {
+                self.tag_config.tag_definitions.remove(name);
+            }
+            return Err(error);
+        }
+
+        for tag in their_tags.tag_order {
+            if !self.tag_config.tag_order.contains(&tag) {
+                self.tag_config.tag_order.push(tag);
+            }
+        }
+        for group in their_tags.tag_groups {
+            match self
+                .tag_config
+                .tag_groups
+                .iter_mut()
+                .find(|ours| ours.name == group.name)
+            {
+                Some(ours) => {
+                    for tag in group.tags {
+                        if !ours.tags.contains(&tag) {
+                            ours.tags.push(tag);
+                        }
+                    }
+                }
+                None => self.tag_config.tag_groups.push(group),
+            }
+        }
+
+        self.security_schemes.extend(security_schemes);
+        for (schema, extensions) in schema_extensions {
+            self.schema_extensions
+                .entry(schema)
+                .or_default()
+                .extend(extensions);
+        }
+        self.webhooks.extend(webhooks);
+        self.schema_visitors.extend(schema_visitors);
+        self.api_versions.extend(api_versions);
+        if served_openapi.is_some() {
+            self.served_openapi = served_openapi;
+        }
+        for e in router.into_endpoints() {
+            self.router.insert(e);
+        }
+
+        Ok(())
+    }
+
     /// Validate that the tags conform to the tags policy.
     fn validate_tags(&self, e: &ApiEndpoint<Context>) -> Result<(), String> {
         // Don't care about endpoints that don't appear in the OpenAPI
-        if !e.visible {
+        if e.visibility == ApiEndpointVisibility::Unpublished {
             return Ok(());
         }
 
@@ -333,6 +1779,66 @@ impl<Context: ServerContext> ApiDescription<Context> {
         Ok(())
     }
 
+    /// Validate that the endpoint's security requirements refer only to
+    /// declared security schemes, and to scopes that they define.
+    fn validate_security(
+        &self,
+        e: &ApiEndpoint<Context>,
+    ) -> Result<(), String> {
+        for (name, scopes) in e.security.iter().flatten() {
+            let scheme = self.security_schemes.get(name).ok_or_else(|| {
+                format!(
+                    "endpoint \"{}\" uses undeclared security scheme \"{}\"",
+                    e.operation_id, name
+                )
+            })?;
+            if let Some(scope) =
+                scopes.iter().find(|scope| !scheme.has_scope(scope))
+            {
+                return Err(format!(
+                    "endpoint \"{}\" requires scope \"{}\", which security \
+                     scheme \"{}\" does not define",
+                    e.operation_id, scope, name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that a frozen endpoint's interface hasn't changed.
+    fn validate_page_limits(
+        &self,
+        e: &ApiEndpoint<Context>,
+    ) -> Result<(), String> {
+        if let PageLimits { default: Some(default), max: Some(max) } =
+            e.page_limits
+        {
+            if default > max {
+                return Err(format!(
+                    "endpoint \"{}\" has a default page limit ({}) greater \
+                     than its maximum ({})",
+                    e.operation_id, default, max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_frozen(&self, e: &ApiEndpoint<Context>) -> Result<(), String> {
+        let Some(expected) = &e.frozen else {
+            return Ok(());
+        };
+        let fingerprint = e.fingerprint();
+        if *expected != fingerprint {
+            return Err(format!(
+                "endpoint \"{}\" ({}) is frozen, but its interface has \
+                 changed (its fingerprint is now \"{}\")",
+                e.operation_id, e.versions, fingerprint
+            ));
+        }
+        Ok(())
+    }
+
     /// Validate that the parameters specified in the path match the parameters
     /// specified by the path parameter arguments to the handler function.
     fn validate_path_parameters(
@@ -436,6 +1942,10 @@ impl<Context: ServerContext> ApiDescription<Context> {
             match &param.metadata {
                 ApiEndpointParameterMetadata::Path(ref name) => {
                     match path_segments.get(name) {
+                        // Parameters with a style (see
+                        // `crate::parameter_style`) are arrays or objects.
+                        Some(SegmentOrWildcard::Segment)
+                            if param.serialization.is_some() => {}
                         Some(SegmentOrWildcard::Segment) => {
                             type_is_scalar(
                                 &e.operation_id,
@@ -465,12 +1975,14 @@ impl<Context: ServerContext> ApiDescription<Context> {
                             name
                         ));
                     }
-                    type_is_scalar(
-                        &e.operation_id,
-                        name,
-                        schema,
-                        dependencies,
-                    )?;
+                    if param.serialization.is_none() {
+                        type_is_scalar(
+                            &e.operation_id,
+                            name,
+                            schema,
+                            dependencies,
+                        )?;
+                    }
                 }
                 _ => (),
             }
@@ -498,17 +2010,89 @@ impl<Context: ServerContext> ApiDescription<Context> {
         OpenApiDefinition::new(self, title.as_ref(), version.as_ref())
     }
 
+    /// Build the OpenAPI definition of each version of this API declared
+    /// with [`ApiDescription::api_versions`], by version
+    ///
+    /// Each definition describes the endpoints in its version, as the
+    /// definition returned by [`ApiDescription::openapi`] for that version
+    /// does, and can be customized in the same ways.
+    ///
+    /// ```
+    /// use dropshot::semver::Version;
+    /// use dropshot::ApiDescription;
+    ///
+    /// let api = ApiDescription::<()>::new()
+    ///     .api_versions([Version::new(1, 0, 0), Version::new(2, 0, 0)]);
+    /// for (version, mut definition) in api.openapi_all_versions("Example API")
+    /// {
+    ///     definition.description(format!("Example API, version {}", version));
+    ///     let json = definition.json().unwrap();
+    ///     assert_eq!(json["info"]["version"], version.to_string());
+    /// }
+    /// ```
+    pub fn openapi_all_versions<S>(
+        &self,
+        title: S,
+    ) -> BTreeMap<semver::Version, OpenApiDefinition<'_, Context>>
+    where
+        S: AsRef<str>,
+    {
+        self.api_versions
+            .iter()
+            .map(|version| {
+                let definition = OpenApiDefinition::new(
+                    self,
+                    title.as_ref(),
+                    &version.to_string(),
+                );
+                (version.clone(), definition)
+            })
+            .collect()
+    }
+
+    /// Write the OpenAPI definition of each version of this API (see
+    /// [`ApiDescription::openapi_all_versions`]) to the directory `dir`, as
+    /// "<version>.json", returning the paths of the files written
+    ///
+    /// The directory is created if it doesn't exist.  Other files in it are
+    /// left alone.
+    pub fn write_openapi_all_versions<S>(
+        &self,
+        title: S,
+        dir: &std::path::Path,
+    ) -> std::io::Result<Vec<std::path::PathBuf>>
+    where
+        S: AsRef<str>,
+    {
+        std::fs::create_dir_all(dir)?;
+        self.openapi_all_versions(title)
+            .into_iter()
+            .map(|(version, definition)| {
+                let path = dir.join(format!("{}.json", version));
+                let mut file = std::fs::File::create(&path)?;
+                definition.write(&mut file)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
     /// Internal routine for constructing the OpenAPI definition describing this
     /// API in its JSON form.
-    fn gen_openapi(&self, info: openapiv3::Info) -> openapiv3::OpenAPI {
+    fn gen_openapi(
+        &self,
+        info: openapiv3::Info,
+        documented: &dyn Fn(&ApiEndpoint<Context>) -> bool,
+    ) -> openapiv3::OpenAPI {
+        let version = self.matching_version(&info.version);
         let mut openapi = openapiv3::OpenAPI::default();
 
         openapi.openapi = "3.0.3".to_string();
         openapi.info = info;
 
-        // Gather up the ad hoc tags from endpoints
+        // Gather up the ad hoc tags from documented endpoints
         let endpoint_tags = (&self.router)
             .into_iter()
+            .filter(|(_, _, endpoint)| documented(endpoint))
             .flat_map(|(_, _, endpoint)| {
                 endpoint.tags.iter().filter(|tag| {
                     !self.tag_config.tag_definitions.contains_key(*tag)
@@ -539,16 +2123,31 @@ impl<Context: ServerContext> ApiDescription<Context> {
             .chain(endpoint_tags)
             .collect();
 
-        // Sort the tags for stability
-        openapi.tags.sort_by(|a, b| a.name.cmp(&b.name));
+        // Sort the tags for stability, with those given an explicit order
+        // first
+        let tag_order = &self.tag_config.tag_order;
+        openapi.tags.sort_by_cached_key(|tag| {
+            let position = tag_order.iter().position(|name| *name == tag.name);
+            (position.unwrap_or(tag_order.len()), tag.name.clone())
+        });
 
-        let settings = schemars::gen::SchemaSettings::openapi3();
+        if !self.tag_config.tag_groups.is_empty() {
+            openapi.extensions.insert(
+                String::from(TAG_GROUPS_EXTENSION),
+                serde_json::to_value(&self.tag_config.tag_groups).unwrap(),
+            );
+        }
+
+        let mut settings = schemars::gen::SchemaSettings::openapi3();
+        if let Some(hook) = &self.schema_settings {
+            hook(&mut settings);
+        }
         let mut generator = schemars::gen::SchemaGenerator::new(settings);
         let mut definitions =
             indexmap::IndexMap::<String, schemars::schema::Schema>::new();
 
         for (path, method, endpoint) in &self.router {
-            if !endpoint.visible {
+            if !documented(endpoint) {
                 continue;
             }
             let path = openapi.paths.paths.entry(path).or_insert(
@@ -560,23 +2159,40 @@ impl<Context: ServerContext> ApiDescription<Context> {
                 _ => panic!("reference not expected"),
             };
 
-            let method_ref = match &method[..] {
-                "GET" => &mut pathitem.get,
-                "PUT" => &mut pathitem.put,
-                "POST" => &mut pathitem.post,
-                "DELETE" => &mut pathitem.delete,
-                "OPTIONS" => &mut pathitem.options,
-                "HEAD" => &mut pathitem.head,
-                "PATCH" => &mut pathitem.patch,
-                "TRACE" => &mut pathitem.trace,
-                other => panic!("unexpected method `{}`", other),
-            };
+            let method_ref = path_item_operation_mut(pathitem, &method);
             let mut operation = openapiv3::Operation::default();
             operation.operation_id = Some(endpoint.operation_id.clone());
             operation.summary = endpoint.summary.clone();
             operation.description = endpoint.description.clone();
             operation.tags = endpoint.tags.clone();
-            operation.deprecated = endpoint.deprecated;
+            operation.deprecated = endpoint.deprecated
+                || version
+                    .as_ref()
+                    .is_some_and(|version| endpoint.is_deprecated_in(version));
+            operation.external_docs =
+                endpoint.external_docs.as_ref().map(|e| {
+                    openapiv3::ExternalDocumentation {
+                        description: e.description.clone(),
+                        url: e.url.clone(),
+                        ..Default::default()
+                    }
+                });
+            if !endpoint.security.is_empty() {
+                operation.security = Some(
+                    endpoint
+                        .security
+                        .iter()
+                        .map(|requirement| {
+                            requirement
+                                .iter()
+                                .map(|(name, scopes)| {
+                                    (name.clone(), scopes.clone())
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                );
+            }
 
             operation.parameters = endpoint
                 .parameters
@@ -590,6 +2206,9 @@ impl<Context: ServerContext> ApiDescription<Context> {
                         ApiEndpointParameterMetadata::Query(name) => {
                             (name, ApiEndpointParameterLocation::Query)
                         }
+                        ApiEndpointParameterMetadata::Header(name) => {
+                            (name, ApiEndpointParameterLocation::Header)
+                        }
                     };
 
                     let schema = match &param.schema {
@@ -611,17 +2230,35 @@ impl<Context: ServerContext> ApiDescription<Context> {
                             schema,
                         ),
                         example: None,
-                        examples: indexmap::IndexMap::new(),
-                        extensions: indexmap::IndexMap::new(),
-                        explode: None,
+                        examples: oas_examples(&param.examples),
+                        extensions: param
+                            .extensions
+                            .iter()
+                            .map(|(name, value)| (name.clone(), value.clone()))
+                            .collect(),
+                        explode: param
+                            .serialization
+                            .map(|serialization| serialization.explode),
                     };
                     match location {
                         ApiEndpointParameterLocation::Query => {
+                            let style = match param
+                                .serialization
+                                .map(|serialization| serialization.style)
+                            {
+                                Some(ParameterStyle::PipeDelimited) => {
+                                    openapiv3::QueryStyle::PipeDelimited
+                                }
+                                Some(ParameterStyle::DeepObject) => {
+                                    openapiv3::QueryStyle::DeepObject
+                                }
+                                _ => openapiv3::QueryStyle::Form,
+                            };
                             Some(openapiv3::ReferenceOr::Item(
                                 openapiv3::Parameter::Query {
                                     parameter_data: parameter_data,
                                     allow_reserved: false,
-                                    style: openapiv3::QueryStyle::Form,
+                                    style,
                                     allow_empty_value: None,
                                 },
                             ))
@@ -634,6 +2271,14 @@ impl<Context: ServerContext> ApiDescription<Context> {
                                 },
                             ))
                         }
+                        ApiEndpointParameterLocation::Header => {
+                            Some(openapiv3::ReferenceOr::Item(
+                                openapiv3::Parameter::Header {
+                                    parameter_data: parameter_data,
+                                    style: openapiv3::HeaderStyle::Simple,
+                                },
+                            ))
+                        }
                     }
                 })
                 .collect::<Vec<_>>();
@@ -665,6 +2310,7 @@ impl<Context: ServerContext> ApiDescription<Context> {
                         mime_type.to_string(),
                         openapiv3::MediaType {
                             schema: Some(schema),
+                            examples: oas_examples(&param.examples),
                             ..Default::default()
                         },
                     );
@@ -685,15 +2331,58 @@ impl<Context: ServerContext> ApiDescription<Context> {
                         first_page_schema.clone(),
                     );
                 }
+                ExtensionMode::OffsetPaginated => {
+                    operation.extensions.insert(
+                        crate::pagination::OFFSET_PAGINATION_EXTENSION
+                            .to_string(),
+                        serde_json::json!({}),
+                    );
+                }
                 ExtensionMode::Websocket => {
                     operation.extensions.insert(
                         crate::websocket::WEBSOCKET_EXTENSION.to_string(),
                         serde_json::json!({}),
                     );
                 }
+                ExtensionMode::EventStream => {
+                    operation.extensions.insert(
+                        crate::event_stream::EVENT_STREAM_EXTENSION.to_string(),
+                        serde_json::json!({}),
+                    );
+                }
+            }
+            operation.extensions.extend(
+                endpoint
+                    .extensions
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+
+            if !endpoint.callbacks.is_empty() {
+                let callbacks = endpoint
+                    .callbacks
+                    .iter()
+                    .map(|callback| {
+                        let item = webhook_path_item(
+                            &callback.hook,
+                            &mut generator,
+                            &mut definitions,
+                        );
+                        let mut expressions = serde_json::Map::new();
+                        expressions.insert(
+                            callback.url.clone(),
+                            serde_json::to_value(item).unwrap(),
+                        );
+                        (callback.name.clone(), expressions.into())
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                // The callbacks are built as JSON since that's simpler than
+                // nesting path items in references and maps.
+                operation.callbacks = serde_json::from_value(callbacks.into())
+                    .expect("callbacks should be valid OpenAPI");
             }
 
-            let response = if let Some(schema) = &endpoint.response.schema {
+            let mut response = if let Some(schema) = &endpoint.response.schema {
                 let (name, js) = match schema {
                     ApiSchemaGenerator::Gen { name, schema } => {
                         (Some(name()), schema(&mut generator))
@@ -714,44 +2403,10 @@ impl<Context: ServerContext> ApiDescription<Context> {
                     );
                 }
 
-                let headers = endpoint
-                    .response
-                    .headers
-                    .iter()
-                    .map(|header| {
-                        let schema = match &header.schema {
-                            ApiSchemaGenerator::Static {
-                                schema,
-                                dependencies,
-                            } => {
-                                definitions.extend(dependencies.clone());
-                                j2oas_schema(None, schema)
-                            }
-                            _ => {
-                                unimplemented!(
-                                    "this may happen for complex types"
-                                )
-                            }
-                        };
-
-                        (
-                            header.name.clone(),
-                            openapiv3::ReferenceOr::Item(openapiv3::Header {
-                                description: header.description.clone(),
-                                style: openapiv3::HeaderStyle::Simple,
-                                required: header.required,
-                                deprecated: None,
-                                format:
-                                    openapiv3::ParameterSchemaOrContent::Schema(
-                                        schema,
-                                    ),
-                                example: None,
-                                examples: indexmap::IndexMap::new(),
-                                extensions: indexmap::IndexMap::new(),
-                            }),
-                        )
-                    })
-                    .collect();
+                let headers = oas_response_headers(
+                    &endpoint.response.headers,
+                    &mut definitions,
+                );
 
                 let response = openapiv3::Response {
                     description: if let Some(description) =
@@ -799,6 +2454,46 @@ impl<Context: ServerContext> ApiDescription<Context> {
                 }
             };
 
+            if !endpoint.response.content_types.is_empty() {
+                let json_schema = response
+                    .content
+                    .get(CONTENT_TYPE_JSON)
+                    .and_then(|media_type| media_type.schema.clone());
+                response.content = response_content(
+                    &endpoint.response.content_types,
+                    json_schema,
+                );
+            }
+
+            for media_type in response.content.values_mut() {
+                media_type.examples = oas_examples(&endpoint.response.examples);
+            }
+
+            response.links = endpoint
+                .response
+                .links
+                .iter()
+                .map(|link| {
+                    let item = openapiv3::Link {
+                        description: None,
+                        operation: openapiv3::LinkOperation::OperationId(
+                            link.operation_id.clone(),
+                        ),
+                        request_body: None,
+                        parameters: link
+                            .parameters
+                            .iter()
+                            .map(|(name, value)| {
+                                (name.clone(), value.clone().into())
+                            })
+                            .collect(),
+                        server: None,
+                        extensions: indexmap::IndexMap::new(),
+                    };
+                    (link.name.clone(), openapiv3::ReferenceOr::Item(item))
+                })
+                .collect();
+
             if let Some(code) = &endpoint.response.success {
                 operation.responses.responses.insert(
                     openapiv3::StatusCode::Code(code.as_u16()),
@@ -817,6 +2512,21 @@ impl<Context: ServerContext> ApiDescription<Context> {
                     .responses
                     .responses
                     .insert(openapiv3::StatusCode::Range(5), err_ref);
+
+                for alternate in &endpoint.response.alternates {
+                    let response = openapiv3::Response {
+                        description: alternate.description.clone(),
+                        headers: oas_response_headers(
+                            &alternate.headers,
+                            &mut definitions,
+                        ),
+                        ..Default::default()
+                    };
+                    operation.responses.responses.insert(
+                        openapiv3::StatusCode::Code(alternate.status.as_u16()),
+                        openapiv3::ReferenceOr::Item(response),
+                    );
+                }
             } else {
                 operation.responses.default =
                     Some(openapiv3::ReferenceOr::Item(response))
@@ -826,20 +2536,64 @@ impl<Context: ServerContext> ApiDescription<Context> {
             method_ref.replace(operation);
         }
 
+        if self.share_path_parameters {
+            for item in openapi.paths.paths.values_mut() {
+                if let openapiv3::ReferenceOr::Item(item) = item {
+                    share_path_parameters(item);
+                }
+            }
+        }
+
+        if !self.webhooks.is_empty() {
+            let webhooks = self
+                .webhooks
+                .iter()
+                .map(|hook| {
+                    let item = webhook_path_item(
+                        hook,
+                        &mut generator,
+                        &mut definitions,
+                    );
+                    (hook.name.clone(), serde_json::to_value(item).unwrap())
+                })
+                .collect::<serde_json::Map<_, _>>();
+            openapi
+                .extensions
+                .insert(String::from(WEBHOOKS_EXTENSION), webhooks.into());
+        }
+
         let components = &mut openapi
             .components
             .get_or_insert_with(openapiv3::Components::default);
 
+        components.security_schemes = self
+            .security_schemes
+            .iter()
+            .map(|(name, scheme)| {
+                (
+                    name.clone(),
+                    openapiv3::ReferenceOr::Item(scheme.to_openapi()),
+                )
+            })
+            .collect();
+
         // All endpoints share an error response
         let responses = &mut components.responses;
         let mut content = indexmap::IndexMap::new();
+        let (content_type, schema) = match self.error_response_format {
+            ErrorResponseFormat::Dropshot => (
+                CONTENT_TYPE_JSON,
+                generator.subschema_for::<HttpErrorResponseBody>(),
+            ),
+            ErrorResponseFormat::ProblemDetails => (
+                CONTENT_TYPE_PROBLEM_JSON,
+                generator.subschema_for::<ProblemDetails>(),
+            ),
+        };
         content.insert(
-            CONTENT_TYPE_JSON.to_string(),
+            content_type.to_string(),
             openapiv3::MediaType {
-                schema: Some(j2oas_schema(
-                    None,
-                    &generator.subschema_for::<HttpErrorResponseBody>(),
-                )),
+                schema: Some(j2oas_schema(None, &schema)),
                 ..Default::default()
             },
         );
@@ -867,17 +2621,545 @@ impl<Context: ServerContext> ApiDescription<Context> {
             }
         });
 
-        openapi
+        for (key, extensions) in &self.schema_extensions {
+            if let Some(openapiv3::ReferenceOr::Item(schema)) =
+                schemas.get_mut(key)
+            {
+                schema.schema_data.extensions.extend(
+                    extensions
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone())),
+                );
+            }
+        }
+
+        if self.schema_visitors.is_empty() {
+            openapi
+        } else {
+            visit_schemas(openapi, &self.schema_visitors)
+        }
+    }
+
+    /// Returns a stub of this API: one with the same endpoints and OpenAPI
+    /// definition, but whose handlers respond to every request with the first
+    /// example of the endpoint's successful response (see
+    /// [`ApiEndpoint::response_example`] and the `response_examples` endpoint
+    /// attribute), for any server context type.  Requests themselves are not
+    /// examined, so they need not be valid.  Endpoints without an example
+    /// respond with no body if their success status is 204 ("No Content"), and
+    /// with a 501 ("Not Implemented") error otherwise.  The endpoint
+    /// registered with [`ApiDescription::register_openapi_endpoint`], if any,
+    /// still serves the OpenAPI definition.
+    ///
+    /// This allows clients (e.g., a web console) to be developed against an
+    /// API before its implementation exists:
+    ///
+    /// ```no_run
+    /// # fn make_api() -> dropshot::ApiDescription<std::sync::Arc<String>> {
+    /// #     dropshot::ApiDescription::new()
+    /// # }
+    /// use dropshot::ConfigDropshot;
+    /// use dropshot::HttpServerStarter;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), String> {
+    /// let api = make_api().into_stub();
+    /// let server =
+    ///     HttpServerStarter::new(&ConfigDropshot::default(), api, None, ())
+    ///         .map_err(|error| format!("failed to start server: {}", error))?
+    ///         .start();
+    /// server.await
+    /// # }
+    /// ```
+    pub fn into_stub<S: ServerContext>(self) -> ApiDescription<S> {
+        let mut router = HttpRouter::new();
+        for endpoint in self.router.into_endpoints() {
+            let endpoint = match &self.served_openapi {
+                Some(served)
+                    if endpoint.method == Method::GET
+                        && endpoint.path == served.path =>
+                {
+                    openapi_endpoint(
+                        &served.path,
+                        ServedFormat::Json,
+                        Arc::clone(&served.documents),
+                    )
+                }
+                #[cfg(feature = "yaml")]
+                Some(served)
+                    if endpoint.method == Method::GET
+                        && endpoint.path == openapi_yaml_path(&served.path) =>
+                {
+                    openapi_endpoint(
+                        &endpoint.path,
+                        ServedFormat::Yaml,
+                        Arc::clone(&served.documents),
+                    )
+                }
+                _ => endpoint.into_stub(),
+            };
+            router.insert(endpoint);
+        }
+
+        ApiDescription {
+            router,
+            tag_config: self.tag_config,
+            path_error_handler: self.path_error_handler,
+            error_response_format: self.error_response_format,
+            error_mapper: self.error_mapper,
+            served_openapi: self.served_openapi,
+            security_schemes: self.security_schemes,
+            schema_extensions: self.schema_extensions,
+            webhooks: self.webhooks,
+            schema_settings: self.schema_settings,
+            schema_visitors: self.schema_visitors,
+            share_path_parameters: self.share_path_parameters,
+            version_policy: self.version_policy,
+            version_observer: self.version_observer,
+            prerelease_matching: self.prerelease_matching,
+            api_versions: self.api_versions,
+        }
+    }
+
+    /// Returns a validator for the bodies of this API's requests and
+    /// responses, or `None` if `mode` disables validation
+    ///
+    /// Every endpoint is validated, whether or not it's documented.
+    pub(crate) fn schema_validator(
+        &self,
+        mode: ConfigSchemaValidation,
+    ) -> Option<SchemaValidator> {
+        if mode == ConfigSchemaValidation::Disabled {
+            return None;
+        }
+        let openapi = self.gen_openapi(openapiv3::Info::default(), &|_| true);
+        Some(SchemaValidator::new(
+            mode,
+            &serde_json::to_value(openapi).unwrap(),
+        ))
     }
 
     // TODO-cleanup is there a way to make this available only within this
     // crate?  Once we do that, we don't need to consume the ApiDescription to
     // do this.
     pub fn into_router(self) -> HttpRouter<Context> {
+        if let Some(served) = &self.served_openapi {
+            let document = |version: &str| {
+                ServedDocument::new(&OpenApiDefinition::new(
+                    &self,
+                    &served.info.title,
+                    version,
+                ))
+            };
+            let documents = ServedDocuments {
+                default: document(&served.info.version),
+                by_version: self
+                    .api_versions
+                    .iter()
+                    .map(|version| {
+                        (version.clone(), document(&version.to_string()))
+                    })
+                    .collect(),
+            };
+            // This API can only be consumed once, so this is the only time the
+            // documents are set.
+            let _ = served.documents.set(documents);
+        }
         self.router
     }
 }
 
+/// Returns `openapi` with `visitors` run over each of its named schemas,
+/// updating references to those that are renamed
+///
+/// This works on the JSON form of the definition, which is what the visitors
+/// are given, and which makes it easy to find all of the references.
+fn visit_schemas(
+    openapi: openapiv3::OpenAPI,
+    visitors: &[SchemaVisitor],
+) -> openapiv3::OpenAPI {
+    const PREFIX: &str = "#/components/schemas/";
+
+    let mut document = serde_json::to_value(openapi).unwrap();
+    let schemas = match document["components"]["schemas"].take() {
+        serde_json::Value::Object(schemas) => schemas,
+        _ => serde_json::Map::new(),
+    };
+
+    let mut visited = serde_json::Map::new();
+    let mut renamed = HashMap::new();
+    for (name, mut schema) in schemas {
+        let mut new_name = name.clone();
+        for visitor in visitors {
+            visitor(&mut new_name, &mut schema);
+        }
+        if visited.contains_key(&new_name) {
+            panic!(
+                "schema visitors gave two schemas the name \"{}\"",
+                new_name
+            );
+        }
+        if new_name != name {
+            renamed.insert(
+                format!("{}{}", PREFIX, name),
+                format!("{}{}", PREFIX, new_name),
+            );
+        }
+        visited.insert(new_name, schema);
+    }
+    document["components"]["schemas"] = visited.into();
+
+    fn rename_references(
+        value: &mut serde_json::Value,
+        renamed: &HashMap<String, String>,
+    ) {
+        match value {
+            serde_json::Value::Object(members) => {
+                for (key, member) in members.iter_mut() {
+                    match member {
+                        serde_json::Value::String(reference)
+                            if key == "$ref" =>
+                        {
+                            if let Some(new) = renamed.get(reference.as_str()) {
+                                *reference = new.clone();
+                            }
+                        }
+                        _ => rename_references(member, renamed),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    rename_references(item, renamed);
+                }
+            }
+            _ => (),
+        }
+    }
+    if !renamed.is_empty() {
+        rename_references(&mut document, &renamed);
+    }
+
+    serde_json::from_value(document).unwrap_or_else(|error| {
+        panic!("schema visitors produced an invalid schema: {}", error)
+    })
+}
+
+/// Returns the member of `item` for the operation with HTTP method `method`
+fn path_item_operation_mut<'a>(
+    item: &'a mut openapiv3::PathItem,
+    method: &str,
+) -> &'a mut Option<openapiv3::Operation> {
+    match method {
+        "GET" => &mut item.get,
+        "PUT" => &mut item.put,
+        "POST" => &mut item.post,
+        "DELETE" => &mut item.delete,
+        "OPTIONS" => &mut item.options,
+        "HEAD" => &mut item.head,
+        "PATCH" => &mut item.patch,
+        "TRACE" => &mut item.trace,
+        other => panic!("unexpected method `{}`", other),
+    }
+}
+
+/// Returns the content of a response documented as having the given media
+/// types, of which the JSON ones (and server-sent events, whose events carry
+/// JSON) have the response type's schema `json_schema`, if it has one
+fn response_content(
+    content_types: &[String],
+    json_schema: Option<openapiv3::ReferenceOr<openapiv3::Schema>>,
+) -> indexmap::IndexMap<String, openapiv3::MediaType> {
+    content_types
+        .iter()
+        .map(|content_type| {
+            let mime = content_type
+                .parse::<mime_guess::mime::Mime>()
+                .expect("response content type should have been validated");
+            let is_json = mime.subtype() == mime_guess::mime::JSON
+                || mime.suffix() == Some(mime_guess::mime::JSON)
+                || mime.essence_str()
+                    == crate::event_stream::CONTENT_TYPE_EVENT_STREAM;
+            let schema_kind = if is_json {
+                if let Some(schema) = &json_schema {
+                    let media_type = openapiv3::MediaType {
+                        schema: Some(schema.clone()),
+                        ..Default::default()
+                    };
+                    return (content_type.clone(), media_type);
+                }
+                openapiv3::SchemaKind::Any(openapiv3::AnySchema::default())
+            } else {
+                let format = if mime.type_() == mime_guess::mime::TEXT {
+                    openapiv3::VariantOrUnknownOrEmpty::Empty
+                } else {
+                    openapiv3::VariantOrUnknownOrEmpty::Item(
+                        openapiv3::StringFormat::Binary,
+                    )
+                };
+                openapiv3::SchemaKind::Type(openapiv3::Type::String(
+                    openapiv3::StringType { format, ..Default::default() },
+                ))
+            };
+            let media_type = openapiv3::MediaType {
+                schema: Some(openapiv3::ReferenceOr::Item(openapiv3::Schema {
+                    schema_data: openapiv3::SchemaData::default(),
+                    schema_kind,
+                })),
+                ..Default::default()
+            };
+            (content_type.clone(), media_type)
+        })
+        .collect()
+}
+
+/// Moves the path parameters that all of the operations in `item` describe in
+/// the same way to the path item itself, if it has more than one operation
+fn share_path_parameters(item: &mut openapiv3::PathItem) {
+    let mut operations = [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.options,
+        &mut item.head,
+        &mut item.patch,
+        &mut item.trace,
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    let Some((first, rest)) = operations.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        return;
+    }
+
+    let shared = first
+        .parameters
+        .iter()
+        .filter(|parameter| {
+            matches!(
+                parameter,
+                openapiv3::ReferenceOr::Item(openapiv3::Parameter::Path { .. })
+            ) && rest.iter().all(|op| op.parameters.contains(parameter))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    for operation in &mut operations {
+        operation.parameters.retain(|parameter| !shared.contains(parameter));
+    }
+    item.parameters.extend(shared);
+}
+
+/// Returns the OpenAPI path item describing the request `hook`, adding the
+/// schemas it refers to to `generator` or `definitions`
+fn webhook_path_item(
+    hook: &ApiWebhook,
+    generator: &mut schemars::gen::SchemaGenerator,
+    definitions: &mut indexmap::IndexMap<String, schemars::schema::Schema>,
+) -> openapiv3::PathItem {
+    let (name, js) = match &hook.payload {
+        ApiSchemaGenerator::Gen { name, schema } => {
+            (Some(name()), schema(generator))
+        }
+        ApiSchemaGenerator::Static { schema, dependencies } => {
+            definitions.extend(dependencies.clone());
+            (None, schema.as_ref().clone())
+        }
+    };
+
+    let mut content = indexmap::IndexMap::new();
+    content.insert(
+        CONTENT_TYPE_JSON.to_string(),
+        openapiv3::MediaType {
+            schema: Some(j2oas_schema(name.as_ref(), &js)),
+            ..Default::default()
+        },
+    );
+
+    let mut operation = openapiv3::Operation::default();
+    operation.operation_id = Some(hook.name.clone());
+    operation.summary = hook.summary.clone();
+    operation.description = hook.description.clone();
+    operation.request_body =
+        Some(openapiv3::ReferenceOr::Item(openapiv3::RequestBody {
+            content,
+            required: true,
+            ..Default::default()
+        }));
+    // Any successful response means the client received the request.
+    operation.responses.responses.insert(
+        openapiv3::StatusCode::Range(2),
+        openapiv3::ReferenceOr::Item(openapiv3::Response {
+            description: String::from("request received"),
+            ..Default::default()
+        }),
+    );
+
+    let mut item = openapiv3::PathItem::default();
+    path_item_operation_mut(&mut item, hook.method.as_str()).replace(operation);
+    item
+}
+
+/// How the paths of two endpoints relate, as far as routing is concerned
+enum RouteOverlap {
+    /// the paths lead to different routes
+    None,
+    /// the paths are for the same route (though perhaps different methods)
+    Same,
+    /// the paths give different names to the same variable (the first path's
+    /// name, then the second's), which the router doesn't allow
+    Variables(String, String),
+}
+
+/// Determines how the endpoint paths `a` and `b` relate
+fn route_overlap(a: &str, b: &str) -> RouteOverlap {
+    let a_segments = route_path_to_segments(a);
+    let b_segments = route_path_to_segments(b);
+    for (a_segment, b_segment) in a_segments.iter().zip(&b_segments) {
+        match (PathSegment::from(a_segment), PathSegment::from(b_segment)) {
+            (PathSegment::Literal(a_lit), PathSegment::Literal(b_lit)) => {
+                if a_lit != b_lit {
+                    return RouteOverlap::None;
+                }
+            }
+            (
+                PathSegment::VarnameSegment(a_var),
+                PathSegment::VarnameSegment(b_var),
+            )
+            | (
+                PathSegment::VarnameWildcard(a_var),
+                PathSegment::VarnameWildcard(b_var),
+            ) => {
+                if a_var != b_var {
+                    return RouteOverlap::Variables(a_var, b_var);
+                }
+            }
+            _ => return RouteOverlap::None,
+        }
+    }
+    if a_segments.len() == b_segments.len() {
+        RouteOverlap::Same
+    } else {
+        RouteOverlap::None
+    }
+}
+
+/// Returns the path at which [`ApiDescription::register_openapi_endpoint`]
+/// serves the YAML form of the OpenAPI definition served as JSON at `path`
+#[cfg(feature = "yaml")]
+fn openapi_yaml_path(path: &str) -> String {
+    format!("{}.yaml", path.strip_suffix(".json").unwrap_or(path))
+}
+
+/// Returns an endpoint registered at `path` by
+/// [`ApiDescription::register_openapi_endpoint`], which serves one of
+/// `documents` in `format`
+fn openapi_endpoint<Context: ServerContext>(
+    path: &str,
+    format: ServedFormat,
+    documents: Arc<OnceLock<ServedDocuments>>,
+) -> ApiEndpoint<Context> {
+    let operation_id = match format {
+        ServedFormat::Json => "openapi",
+        #[cfg(feature = "yaml")]
+        ServedFormat::Yaml => "openapi_yaml",
+    };
+    ApiEndpoint::new(
+        String::from(operation_id),
+        move |rqctx: RequestContext<Context>| {
+            let documents = Arc::clone(&documents);
+            async move {
+                openapi_response(&documents, rqctx.api_version.as_ref(), format)
+            }
+        },
+        Method::GET,
+        CONTENT_TYPE_JSON,
+        path,
+    )
+    .summary("Fetch the OpenAPI definition for this API")
+    .visible(false)
+}
+
+/// Returns the response for an endpoint registered with
+/// [`ApiDescription::register_openapi_endpoint`], for a request handled as
+/// `version` of the API
+fn openapi_response(
+    documents: &OnceLock<ServedDocuments>,
+    version: Option<&semver::Version>,
+    format: ServedFormat,
+) -> Result<hyper::Response<hyper::Body>, HttpError> {
+    let document = documents
+        .get()
+        .ok_or_else(|| {
+            HttpError::for_internal_error(String::from(
+                "OpenAPI definition has not been generated",
+            ))
+        })?
+        .for_version(version);
+    let (content_type, body) = match format {
+        ServedFormat::Json => (CONTENT_TYPE_JSON, &document.json),
+        #[cfg(feature = "yaml")]
+        ServedFormat::Yaml => ("application/yaml", &document.yaml),
+    };
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .body(body.clone().into())?)
+}
+
+/// Converts examples to their form in an OpenAPI definition
+fn oas_response_headers(
+    headers: &[ApiEndpointHeader],
+    definitions: &mut indexmap::IndexMap<String, schemars::schema::Schema>,
+) -> indexmap::IndexMap<String, openapiv3::ReferenceOr<openapiv3::Header>> {
+    headers
+        .iter()
+        .map(|header| {
+            let schema = match &header.schema {
+                ApiSchemaGenerator::Static { schema, dependencies } => {
+                    definitions.extend(dependencies.clone());
+                    j2oas_schema(None, schema)
+                }
+                _ => {
+                    unimplemented!("this may happen for complex types")
+                }
+            };
+
+            (
+                header.name.clone(),
+                openapiv3::ReferenceOr::Item(openapiv3::Header {
+                    description: header.description.clone(),
+                    style: openapiv3::HeaderStyle::Simple,
+                    required: header.required,
+                    deprecated: None,
+                    format: openapiv3::ParameterSchemaOrContent::Schema(schema),
+                    example: None,
+                    examples: indexmap::IndexMap::new(),
+                    extensions: indexmap::IndexMap::new(),
+                }),
+            )
+        })
+        .collect()
+}
+
+fn oas_examples(
+    examples: &[ApiEndpointExample],
+) -> indexmap::IndexMap<String, openapiv3::ReferenceOr<openapiv3::Example>> {
+    examples
+        .iter()
+        .map(|example| {
+            let item = openapiv3::Example {
+                value: Some(example.value.clone()),
+                ..Default::default()
+            };
+            (example.name.clone(), openapiv3::ReferenceOr::Item(item))
+        })
+        .collect()
+}
+
 /// Returns true iff the schema represents the void schema that matches no data.
 fn is_empty(schema: &schemars::schema::Schema) -> bool {
     if let schemars::schema::Schema::Bool(false) = schema {
@@ -942,8 +3224,16 @@ fn is_empty(schema: &schemars::schema::Schema) -> bool {
 pub struct OpenApiDefinition<'a, Context: ServerContext> {
     api: &'a ApiDescription<Context>,
     info: openapiv3::Info,
+    servers: Vec<openapiv3::Server>,
+    extensions: indexmap::IndexMap<String, serde_json::Value>,
+    audience: Option<String>,
+    filter: Option<EndpointFilter<'a, Context>>,
 }
 
+/// Function choosing the endpoints documented by an [`OpenApiDefinition`]
+type EndpointFilter<'a, Context> =
+    Box<dyn Fn(&ApiEndpoint<Context>) -> bool + 'a>;
+
 impl<'a, Context: ServerContext> OpenApiDefinition<'a, Context> {
     fn new(
         api: &'a ApiDescription<Context>,
@@ -955,7 +3245,14 @@ impl<'a, Context: ServerContext> OpenApiDefinition<'a, Context> {
             version: version.to_string(),
             ..Default::default()
         };
-        OpenApiDefinition { api, info }
+        OpenApiDefinition {
+            api,
+            info,
+            servers: Vec::new(),
+            extensions: indexmap::IndexMap::new(),
+            audience: None,
+            filter: None,
+        }
     }
 
     /// Provide a short description of the API.  CommonMark syntax may be
@@ -1047,9 +3344,151 @@ impl<'a, Context: ServerContext> OpenApiDefinition<'a, Context> {
         self
     }
 
+    /// Add an extension (whose name must begin with `x-`) to the `Info`
+    /// object in the OpenAPI definition, with the given value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` doesn't begin with `x-`.
+    pub fn info_extension<S: AsRef<str>>(
+        &mut self,
+        name: S,
+        value: serde_json::Value,
+    ) -> &mut Self {
+        let name = name.as_ref();
+        let value = extension_value(name, value);
+        self.info.extensions.insert(name.to_string(), value);
+        self
+    }
+
+    /// Add an extension (whose name must begin with `x-`) at the top level of
+    /// the OpenAPI definition, with the given value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` doesn't begin with `x-`.
+    pub fn extension<S: AsRef<str>>(
+        &mut self,
+        name: S,
+        value: serde_json::Value,
+    ) -> &mut Self {
+        let name = name.as_ref();
+        let value = extension_value(name, value);
+        self.extensions.insert(name.to_string(), value);
+        self
+    }
+
+    /// Add a server that hosts the API.  Servers appear in the order in which
+    /// they're added.
+    ///
+    /// This routine will add a `Server` object to the `servers` property of
+    /// the OpenAPI definition.
+    ///
+    /// ```
+    /// use dropshot::ApiDescription;
+    /// use dropshot::ServerDetails;
+    /// use dropshot::ServerVariable;
+    ///
+    /// let api = ApiDescription::<()>::new();
+    /// let region = ServerVariable {
+    ///     default: String::from("us-east"),
+    ///     values: vec![String::from("us-east"), String::from("us-west")],
+    ///     description: None,
+    /// };
+    /// let json = api
+    ///     .openapi("Example API", "1.0.0")
+    ///     .server(ServerDetails {
+    ///         url: String::from("https://{region}.example.com"),
+    ///         description: Some(String::from("regional endpoint")),
+    ///         variables: [(String::from("region"), region)].into(),
+    ///     })
+    ///     .json()
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     json["servers"][0]["variables"]["region"]["default"],
+    ///     "us-east"
+    /// );
+    /// ```
+    pub fn server(&mut self, server: ServerDetails) -> &mut Self {
+        let variables = server
+            .variables
+            .into_iter()
+            .map(|(name, variable)| {
+                let variable = openapiv3::ServerVariable {
+                    enumeration: variable.values,
+                    default: variable.default,
+                    description: variable.description,
+                    extensions: indexmap::IndexMap::new(),
+                };
+                (name, variable)
+            })
+            .collect::<indexmap::IndexMap<_, _>>();
+        self.servers.push(openapiv3::Server {
+            url: server.url,
+            description: server.description,
+            variables: (!variables.is_empty()).then_some(variables),
+            extensions: indexmap::IndexMap::new(),
+        });
+        self
+    }
+
+    /// Document the API as seen by `audience` (e.g., "internal" or
+    /// "partner"): the endpoints documented for every audience, and those
+    /// documented for `audience` in particular (see
+    /// [`ApiEndpoint::audience`]).
+    ///
+    /// By default, only the endpoints documented for every audience are
+    /// included.  Unpublished endpoints are never included.
+    pub fn audience<S: AsRef<str>>(&mut self, audience: S) -> &mut Self {
+        self.audience = Some(audience.as_ref().to_string());
+        self
+    }
+
+    /// Document only the endpoints for which `filter` returns true (of those
+    /// that would otherwise be included), e.g., those with a particular tag
+    ///
+    /// ```
+    /// use dropshot::ApiDescription;
+    ///
+    /// let api = ApiDescription::<()>::new();
+    /// let json = api
+    ///     .openapi("Example API", "1.0.0")
+    ///     .filter(|endpoint| endpoint.tags.iter().any(|tag| tag == "public"))
+    ///     .json()
+    ///     .unwrap();
+    /// ```
+    pub fn filter<F>(&mut self, filter: F) -> &mut Self
+    where
+        F: Fn(&ApiEndpoint<Context>) -> bool + 'a,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    fn gen_openapi(&self) -> openapiv3::OpenAPI {
+        let version = self.api.matching_version(&self.info.version);
+        let documented = |endpoint: &ApiEndpoint<Context>| {
+            let visible = match &endpoint.visibility {
+                ApiEndpointVisibility::Public => true,
+                ApiEndpointVisibility::Audiences(audiences) => self
+                    .audience
+                    .as_ref()
+                    .is_some_and(|audience| audiences.contains(audience)),
+                ApiEndpointVisibility::Unpublished => false,
+            };
+            visible
+                && endpoint.versions.documented_in(version.as_ref())
+                && self.filter.as_ref().map_or(true, |f| f(endpoint))
+        };
+        let mut openapi = self.api.gen_openapi(self.info.clone(), &documented);
+        openapi.servers = self.servers.clone();
+        openapi.extensions.extend(self.extensions.clone());
+        openapi
+    }
+
     /// Build a JSON object containing the OpenAPI definition for this API.
     pub fn json(&self) -> serde_json::Result<serde_json::Value> {
-        serde_json::to_value(&self.api.gen_openapi(self.info.clone()))
+        serde_json::to_value(&OpenApiDocument::new(&self.gen_openapi()))
     }
 
     /// Build a JSON object containing the OpenAPI definition for this API and
@@ -1060,11 +3499,142 @@ impl<'a, Context: ServerContext> OpenApiDefinition<'a, Context> {
     ) -> serde_json::Result<()> {
         serde_json::to_writer_pretty(
             &mut *out,
-            &self.api.gen_openapi(self.info.clone()),
+            &OpenApiDocument::new(&self.gen_openapi()),
         )?;
         writeln!(out).map_err(serde_json::Error::custom)?;
         Ok(())
     }
+
+    /// Build a YAML document containing the OpenAPI definition for this API
+    ///
+    /// Object keys are sorted, as in [`OpenApiDefinition::json`], so that the
+    /// output is stable from one build to the next and diffs between versions
+    /// of the definition are easy to review.
+    #[cfg(feature = "yaml")]
+    pub fn yaml(&self) -> serde_yaml::Result<String> {
+        let json = self.json().map_err(serde_yaml::Error::custom)?;
+        serde_yaml::to_string(&json)
+    }
+
+    /// Build a YAML document containing the OpenAPI definition for this API
+    /// (see [`OpenApiDefinition::yaml`]) and write it to the provided stream.
+    #[cfg(feature = "yaml")]
+    pub fn write_yaml(
+        &self,
+        out: &mut dyn std::io::Write,
+    ) -> serde_yaml::Result<()> {
+        let json = self.json().map_err(serde_yaml::Error::custom)?;
+        serde_yaml::to_writer(out, &json)
+    }
+
+    /// Build a standalone JSON Schema for each of the schemas in the OpenAPI
+    /// definition's components, by file name ("<schema name>.json"), for
+    /// sharing the API's types without the rest of the definition
+    ///
+    /// References between the schemas are relative references to each
+    /// other's files, so they should be kept together (e.g., in one
+    /// directory).
+    pub fn json_schemas(
+        &self,
+    ) -> serde_json::Result<BTreeMap<String, serde_json::Value>> {
+        Ok(crate::schema_export::json_schemas(&self.json()?))
+    }
+
+    /// Write TypeScript declarations (suitable for a ".d.ts" file) for the
+    /// schemas in the OpenAPI definition's components to the provided stream
+    ///
+    /// Each schema becomes an interface (for objects with fixed properties)
+    /// or a type alias of the same name.
+    pub fn write_typescript(
+        &self,
+        out: &mut dyn std::io::Write,
+    ) -> serde_json::Result<()> {
+        let types = crate::schema_export::typescript(&self.json()?);
+        out.write_all(types.as_bytes()).map_err(serde_json::Error::custom)
+    }
+}
+
+/// Serializes an OpenAPI document as `openapiv3` does, except that server
+/// variables without a description leave it out rather than serializing it as
+/// `null`, which isn't valid OpenAPI
+#[derive(Serialize)]
+struct OpenApiDocument<'a> {
+    openapi: &'a str,
+    info: &'a openapiv3::Info,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    servers: Vec<OpenApiServer<'a>>,
+    paths: &'a openapiv3::Paths,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    components: Option<&'a openapiv3::Components>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    security: Option<&'a Vec<openapiv3::SecurityRequirement>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [openapiv3::Tag],
+    #[serde(rename = "externalDocs", skip_serializing_if = "Option::is_none")]
+    external_docs: Option<&'a openapiv3::ExternalDocumentation>,
+    #[serde(flatten)]
+    extensions: &'a indexmap::IndexMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct OpenApiServer<'a> {
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variables: Option<indexmap::IndexMap<&'a str, OpenApiServerVariable<'a>>>,
+    #[serde(flatten)]
+    extensions: &'a indexmap::IndexMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct OpenApiServerVariable<'a> {
+    #[serde(rename = "enum", skip_serializing_if = "<[_]>::is_empty")]
+    enumeration: &'a [String],
+    default: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(flatten)]
+    extensions: &'a indexmap::IndexMap<String, serde_json::Value>,
+}
+
+impl<'a> OpenApiDocument<'a> {
+    fn new(openapi: &'a openapiv3::OpenAPI) -> OpenApiDocument<'a> {
+        let servers = openapi
+            .servers
+            .iter()
+            .map(|server| OpenApiServer {
+                url: &server.url,
+                description: server.description.as_deref(),
+                variables: server.variables.as_ref().map(|variables| {
+                    variables
+                        .iter()
+                        .map(|(name, variable)| {
+                            let variable = OpenApiServerVariable {
+                                enumeration: &variable.enumeration,
+                                default: &variable.default,
+                                description: variable.description.as_deref(),
+                                extensions: &variable.extensions,
+                            };
+                            (name.as_str(), variable)
+                        })
+                        .collect()
+                }),
+                extensions: &server.extensions,
+            })
+            .collect();
+        OpenApiDocument {
+            openapi: &openapi.openapi,
+            info: &openapi.info,
+            servers,
+            paths: &openapi.paths,
+            components: openapi.components.as_ref(),
+            security: openapi.security.as_ref(),
+            tags: &openapi.tags,
+            external_docs: openapi.external_docs.as_ref(),
+            extensions: &openapi.extensions,
+        }
+    }
 }
 
 /// Configuration used describe OpenAPI tags and to validate per-endpoint tags.
@@ -1076,6 +3646,15 @@ pub struct TagConfig {
     pub allow_other_tags: bool,
     pub endpoint_tag_policy: EndpointTagPolicy,
     pub tag_definitions: HashMap<String, TagDetails>,
+    /// Tags to list first in the OpenAPI definition, in this order.  Other
+    /// tags follow in alphabetical order.
+    #[serde(default)]
+    pub tag_order: Vec<String>,
+    /// Groups of tags, listed in the `x-tagGroups` extension of the OpenAPI
+    /// definition for documentation tools that understand it (e.g., Redoc).
+    /// Such tools typically omit tags that aren't in any group.
+    #[serde(default)]
+    pub tag_groups: Vec<TagGroup>,
 }
 
 impl Default for TagConfig {
@@ -1084,10 +3663,19 @@ impl Default for TagConfig {
             allow_other_tags: true,
             endpoint_tag_policy: EndpointTagPolicy::Any,
             tag_definitions: HashMap::new(),
+            tag_order: Vec::new(),
+            tag_groups: Vec::new(),
         }
     }
 }
 
+/// A named group of tags (see [`TagConfig::tag_groups`])
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagGroup {
+    pub name: String,
+    pub tags: Vec<String>,
+}
+
 /// Endpoint tagging policy
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum EndpointTagPolicy {
@@ -1113,13 +3701,39 @@ pub struct TagExternalDocs {
     pub url: String,
 }
 
+/// A server that hosts an API, as listed in its OpenAPI definition (see
+/// [`OpenApiDefinition::server`])
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ServerDetails {
+    /// URL of the server, which may contain variables in braces (e.g.,
+    /// "https://{region}.example.com")
+    pub url: String,
+    pub description: Option<String>,
+    /// Values of the variables in the URL, by name
+    #[serde(default)]
+    pub variables: BTreeMap<String, ServerVariable>,
+}
+
+/// A variable in the URL of a server
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ServerVariable {
+    /// value to use if the client doesn't provide one
+    pub default: String,
+    /// values the variable may take (any value, if empty)
+    #[serde(default)]
+    pub values: Vec<String>,
+    pub description: Option<String>,
+}
+
 /// Dropshot/Progenitor features used by endpoints which are not a part of the base OpenAPI spec.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub enum ExtensionMode {
     #[default]
     None,
     Paginated(serde_json::Value),
+    OffsetPaginated,
     Websocket,
+    EventStream,
 }
 
 #[cfg(test)]
@@ -1335,6 +3949,7 @@ mod test {
             ]
             .into_iter()
             .collect(),
+            ..Default::default()
         });
         api.register(
             ApiEndpoint::new(
@@ -0,0 +1,219 @@
+// Copyright 2024 Oxide Computer Company
+//! Keeping `Content-Length` consistent when response bodies are rewritten
+//!
+//! A [`Middleware`](crate::Middleware) that transforms response bodies (e.g.,
+//! to compress them) must also fix up the `Content-Length` header: a handler
+//! may have set it for the original body, and the transformed body is
+//! usually a different size, often one that isn't known until it's been
+//! produced.  A stale `Content-Length` causes the response to fail.
+//!
+//! [`map_response_body`] takes care of this.  The transformation sees the
+//! body as a [`LengthTrackedBody`], which knows the body's [`BodyLength`]
+//! (from `Content-Length` if present, or else from the body itself), and
+//! returns one whose length is either known exactly or unknown.  Afterward,
+//! `Content-Length` is set to the new length if it's known, or removed if not,
+//! in which case the body is sent with chunked transfer encoding (in HTTP/1.1)
+//! as it's produced.
+//!
+//! ```
+//! use dropshot::map_response_body;
+//! use dropshot::BodyLength;
+//! use dropshot::LengthTrackedBody;
+//! use futures::TryStreamExt;
+//! use hyper::{Body, Response};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let response = Response::builder()
+//!     .header("content-length", "5")
+//!     .body(Body::from("hello"))
+//!     .unwrap();
+//!
+//! // Upper-case the body as it's streamed: the result's length isn't known
+//! // in advance.
+//! let response = map_response_body(response, |body| {
+//!     assert_eq!(body.length(), BodyLength::Exact(5));
+//!     body.map(|body| {
+//!         Body::wrap_stream(body.map_ok(|chunk| {
+//!             bytes::Bytes::from(chunk.to_ascii_uppercase())
+//!         }))
+//!     })
+//! });
+//! assert!(response.headers().get("content-length").is_none());
+//!
+//! // Append to the body: here the new length is known.
+//! let response = map_response_body(response, |body| {
+//!     LengthTrackedBody::with_length(
+//!         Body::from("HELLO, WORLD"),
+//!         BodyLength::Exact(12),
+//!     )
+//! });
+//! assert_eq!(response.headers()["content-length"], "12");
+//! # }
+//! ```
+
+use http::header::{self, HeaderValue};
+use http::StatusCode;
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+
+/// Length of a body, as far as it's known before it's been sent
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BodyLength {
+    /// The body is exactly this many bytes long.
+    Exact(u64),
+    /// The body's length won't be known until it's been produced.
+    Unknown,
+}
+
+impl BodyLength {
+    /// Returns the length of `body` according to its size hint
+    pub fn of(body: &Body) -> BodyLength {
+        match body.size_hint().exact() {
+            Some(len) => BodyLength::Exact(len),
+            None => BodyLength::Unknown,
+        }
+    }
+}
+
+/// A response body along with its [`BodyLength`]
+///
+/// See the module-level documentation for how this is used.
+#[derive(Debug)]
+pub struct LengthTrackedBody {
+    body: Body,
+    length: BodyLength,
+}
+
+impl LengthTrackedBody {
+    /// Wraps `body`, whose length is determined from its size hint
+    pub fn new(body: Body) -> LengthTrackedBody {
+        let length = BodyLength::of(&body);
+        LengthTrackedBody { body, length }
+    }
+
+    /// Wraps `body`, whose length is known by the caller to be `length`
+    pub fn with_length(body: Body, length: BodyLength) -> LengthTrackedBody {
+        LengthTrackedBody { body, length }
+    }
+
+    /// Returns the length of the body
+    pub fn length(&self) -> BodyLength {
+        self.length
+    }
+
+    /// Transforms the body with `f`.  The length of the result is determined
+    /// from its size hint, which means it's usually unknown for streaming
+    /// transformations; use [`LengthTrackedBody::with_length`] for
+    /// transformations that produce a body of known length.
+    pub fn map<F>(self, f: F) -> LengthTrackedBody
+    where
+        F: FnOnce(Body) -> Body,
+    {
+        LengthTrackedBody::new(f(self.body))
+    }
+
+    /// Returns the body itself
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+}
+
+/// Replaces the body of `response` with the result of `f`, updating its
+/// `Content-Length` header to match
+///
+/// `f` is given the current body, whose length is taken from the response's
+/// `Content-Length` header if it has a valid one.  Responses that never have
+/// a body (204 "No Content" and 304 "Not Modified") are returned unchanged.
+pub fn map_response_body<F>(response: Response<Body>, f: F) -> Response<Body>
+where
+    F: FnOnce(LengthTrackedBody) -> LengthTrackedBody,
+{
+    let status = response.status();
+    if status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let declared = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let body = match declared {
+        Some(len) => {
+            LengthTrackedBody::with_length(body, BodyLength::Exact(len))
+        }
+        None => LengthTrackedBody::new(body),
+    };
+
+    let body = f(body);
+    match body.length {
+        BodyLength::Exact(len) => {
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+        }
+        BodyLength::Unknown => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+        }
+    }
+    Response::from_parts(parts, body.body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::map_response_body;
+    use super::BodyLength;
+    use super::LengthTrackedBody;
+    use http::StatusCode;
+    use hyper::{Body, Response};
+
+    #[test]
+    fn test_map_response_body() {
+        // The length comes from the body if there's no header...
+        let response = Response::new(Body::from("abc"));
+        let response = map_response_body(response, |body| {
+            assert_eq!(body.length(), BodyLength::Exact(3));
+            body
+        });
+        assert_eq!(response.headers()["content-length"], "3");
+
+        // ... or from the header if there is one.
+        let (sender, body) = Body::channel();
+        drop(sender);
+        let response = Response::builder()
+            .header("content-length", "10")
+            .body(body)
+            .unwrap();
+        let response = map_response_body(response, |body| {
+            assert_eq!(body.length(), BodyLength::Exact(10));
+            body.map(|_| Body::from("0123456789abcdef"))
+        });
+        assert_eq!(response.headers()["content-length"], "16");
+
+        let response =
+            map_response_body(response, |body| body.map(Body::wrap_stream));
+        assert!(response.headers().get("content-length").is_none());
+
+        let response = map_response_body(response, |body| {
+            assert_eq!(body.length(), BodyLength::Unknown);
+            LengthTrackedBody::with_length(
+                body.into_body(),
+                BodyLength::Unknown,
+            )
+        });
+        assert!(response.headers().get("content-length").is_none());
+
+        // Bodiless responses are left alone.
+        let response = Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("content-length", "100")
+            .body(Body::empty())
+            .unwrap();
+        let response = map_response_body(response, |_| {
+            panic!("304 response body transformed")
+        });
+        assert_eq!(response.headers()["content-length"], "100");
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for validating cached responses with entity tags
+//!
+//! Clients that poll an endpoint frequently often get back the same response
+//! over and over.  Wrapping such a response in [`HttpResponseCached`] gives it
+//! an `ETag` computed from its body.  Clients that send that tag back in
+//! `If-None-Match` get a bodiless 304 ("Not Modified") response if the body
+//! would have been the same, saving the bandwidth (though not the work of
+//! producing the response in the first place).
+
+use crate::api_description::{
+    ApiEndpointHeader, ApiEndpointResponse, ApiSchemaGenerator,
+};
+use crate::error::HttpError;
+use crate::file::etag_matches;
+use crate::handler::{HttpHandlerResult, HttpResponse, RequestInfo};
+use http::header::{self, HeaderValue};
+use http::{Method, StatusCode};
+use hyper::{Body, Response};
+use sha1::{Digest, Sha1};
+use std::fmt::Write;
+use std::marker::PhantomData;
+
+/// `Cache-Control` value used by [`HttpResponseCached`] unless overridden: the
+/// client may cache the response but must check that it's current before
+/// using it
+pub const CACHE_CONTROL_DEFAULT: &str = "no-cache";
+
+/// `HttpResponseCached<T>` wraps a response `T`, adding an `ETag` header and
+/// answering conditional requests whose `If-None-Match` matches it with 304
+/// ("Not Modified")
+///
+/// The entity tag is a hash of the response body, so the body is buffered in
+/// memory.  This is intended for modestly-sized responses (like JSON objects),
+/// not large or streaming ones.  The response also gets a `Cache-Control`
+/// header ([`CACHE_CONTROL_DEFAULT`] unless changed with
+/// [`HttpResponseCached::cache_control`]).
+///
+/// Only successful responses to `GET` and `HEAD` requests are replaced with a
+/// 304.  The OpenAPI document describes this like `T`, with the addition of
+/// the `ETag` and `Cache-Control` headers.
+///
+/// ```
+/// use dropshot::endpoint;
+/// use dropshot::HttpError;
+/// use dropshot::HttpResponseCached;
+/// use dropshot::HttpResponseOk;
+/// use dropshot::RequestContext;
+///
+/// #[endpoint {
+///     method = GET,
+///     path = "/status",
+/// }]
+/// async fn get_status(
+///     rqctx: RequestContext<()>,
+/// ) -> Result<HttpResponseCached<HttpResponseOk<String>>, HttpError> {
+///     let status = String::from("all systems go");
+///     HttpResponseCached::new(&rqctx.request, HttpResponseOk(status)).await
+/// }
+/// ```
+pub struct HttpResponseCached<T: HttpResponse> {
+    response: Response<Body>,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: HttpResponse> HttpResponseCached<T> {
+    /// Produces `response` and validates it against `request`'s
+    /// `If-None-Match` header
+    pub async fn new(
+        request: &RequestInfo,
+        response: T,
+    ) -> Result<HttpResponseCached<T>, HttpError> {
+        let response = response.to_result()?;
+        let (mut parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "reading response body: {}",
+                e
+            ))
+        })?;
+
+        if !parts.status.is_success() {
+            let response = Response::from_parts(parts, Body::from(body));
+            return Ok(HttpResponseCached { response, phantom: PhantomData });
+        }
+
+        parts.headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(CACHE_CONTROL_DEFAULT),
+        );
+
+        let etag = format!("\"{}\"", hex_digest(&body));
+        parts
+            .headers
+            .insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+
+        let not_modified = (request.method() == Method::GET
+            || request.method() == Method::HEAD)
+            && request
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map_or(false, |list| etag_matches(list, &etag, true));
+        let response = if not_modified {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(header::CONTENT_TYPE);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::empty())
+        } else {
+            Response::from_parts(parts, Body::from(body))
+        };
+        Ok(HttpResponseCached { response, phantom: PhantomData })
+    }
+
+    /// Replaces the `Cache-Control` header (e.g., with "max-age=60" to let
+    /// clients use the response for a minute without checking)
+    pub fn cache_control(
+        mut self,
+        value: &str,
+    ) -> Result<HttpResponseCached<T>, HttpError> {
+        let value = HeaderValue::from_str(value).map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "invalid Cache-Control value {:?}: {}",
+                value, e
+            ))
+        })?;
+        self.response.headers_mut().insert(header::CACHE_CONTROL, value);
+        Ok(self)
+    }
+
+    /// Returns the status code of this response
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+}
+
+pub(crate) fn hex_digest(body: &[u8]) -> String {
+    Sha1::digest(body).iter().fold(String::new(), |mut digest, b| {
+        write!(digest, "{:02x}", b).unwrap();
+        digest
+    })
+}
+
+impl<T: HttpResponse> HttpResponse for HttpResponseCached<T> {
+    fn to_result(self) -> HttpHandlerResult {
+        Ok(self.response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        let mut metadata = T::response_metadata();
+        let string_schema = || ApiSchemaGenerator::Static {
+            schema: Box::new(
+                schemars::schema::SchemaObject {
+                    instance_type: Some(
+                        schemars::schema::InstanceType::String.into(),
+                    ),
+                    ..Default::default()
+                }
+                .into(),
+            ),
+            dependencies: indexmap::IndexMap::default(),
+        };
+        metadata.headers.push(ApiEndpointHeader {
+            name: String::from("ETag"),
+            description: Some(String::from(
+                "entity tag for the response body, for use with \
+                 If-None-Match",
+            )),
+            schema: string_schema(),
+            required: true,
+        });
+        metadata.headers.push(ApiEndpointHeader {
+            name: String::from("Cache-Control"),
+            description: None,
+            schema: string_schema(),
+            required: true,
+        });
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HttpResponseCached;
+    use crate::handler::{HttpResponse, RequestInfo};
+    use crate::HttpResponseOk;
+    use http::{Method, StatusCode};
+
+    fn request(method: Method, if_none_match: Option<&str>) -> RequestInfo {
+        let mut builder = hyper::Request::builder().method(method).uri("/");
+        if let Some(value) = if_none_match {
+            builder = builder.header("if-none-match", value);
+        }
+        let request = builder.body(()).unwrap();
+        RequestInfo::new(&request, "127.0.0.1:12345".parse().unwrap())
+    }
+
+    async fn respond(
+        request: &RequestInfo,
+        body: &str,
+    ) -> hyper::Response<hyper::Body> {
+        HttpResponseCached::new(request, HttpResponseOk(body.to_string()))
+            .await
+            .unwrap()
+            .to_result()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cached_response() {
+        let response = respond(&request(Method::GET, None), "one").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        // Same body, matching tag: not modified.
+        let response = respond(&request(Method::GET, Some(&etag)), "one").await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        assert!(response.headers().get("content-type").is_none());
+
+        // Weak comparison applies, and any tag in the list can match.
+        let list = format!("\"abc\", W/{}", etag);
+        let response =
+            respond(&request(Method::HEAD, Some(&list)), "one").await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // Different body: the whole response.
+        let response = respond(&request(Method::GET, Some(&etag)), "two").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()["etag"], etag.as_str());
+
+        // Only GET and HEAD are short-circuited.
+        let response =
+            respond(&request(Method::POST, Some(&etag)), "one").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = HttpResponseCached::new(
+            &request(Method::GET, None),
+            HttpResponseOk(1),
+        )
+        .await
+        .unwrap()
+        .cache_control("max-age=60")
+        .unwrap()
+        .to_result()
+        .unwrap();
+        assert_eq!(response.headers()["cache-control"], "max-age=60");
+    }
+}
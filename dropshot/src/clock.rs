@@ -0,0 +1,38 @@
+// Copyright 2024 Oxide Computer Company
+//! Source of the current time for a server
+//!
+//! By default, a server uses the system clock.  Giving it some other
+//! [`Clock`] (see [`crate::HttpServerStarter::clock`]) lets tests control the
+//! wall-clock time that the server reports in the `Date` header of each
+//! response and that handlers see with [`crate::DropshotState::now`] (e.g., to
+//! expire tokens or to enforce rate limits), rather than sleeping.  The same
+//! clock determines when the records of [`crate::InMemoryIdempotencyStore`]
+//! and sealed page tokens (see [`crate::PageTokenKeys::with_ttl`]) expire, and
+//! paces the websocket message rate limit
+//! ([`crate::ConfigWebsocket::max_messages_per_second`]).  Timeouts that the
+//! server itself enforces (e.g., on idle connections) always use real time.
+
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+
+/// Source of the current wall-clock time
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// Returns the current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Returns the value of a `Date` header for `time`
+pub(crate) fn http_date(time: DateTime<Utc>) -> http::HeaderValue {
+    http::HeaderValue::from_str(&httpdate::fmt_http_date(time.into()))
+        .expect("HTTP date is a valid header value")
+}
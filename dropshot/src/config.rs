@@ -1,10 +1,17 @@
 // Copyright 2020 Oxide Computer Company
 //! Configuration for Dropshot
 
+use crate::cors::ConfigCors;
+use crate::forwarded::IpCidr;
+use crate::schema_validation::ConfigSchemaValidation;
+use crate::server_timing::ConfigServerTiming;
+use crate::tls_client_auth::ClientCertificate;
 use serde::Deserialize;
 use serde::Serialize;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Raw [`rustls::ServerConfig`] TLS configuration for use with
 /// [`ConfigTls::Dynamic`]
@@ -47,11 +54,469 @@ pub type RawTlsConfig = rustls::ServerConfig;
 pub struct ConfigDropshot {
     /// IP address and TCP port to which to bind for accepting connections
     pub bind_address: SocketAddr,
+    /// If set, the server accepts connections on this Unix domain socket
+    /// instead of binding to `bind_address`.  See [`ConfigUnixSocket`].
+    /// Defaults to `None`.
+    pub unix_socket: Option<ConfigUnixSocket>,
     /// maximum allowed size of a request body, defaults to 1024
     pub request_body_max_bytes: usize,
     /// Default behavior for HTTP handler functions with respect to clients
     /// disconnecting early.
     pub default_handler_task_mode: HandlerTaskMode,
+    /// Peers that are trusted to report the address of the client on whose
+    /// behalf they're forwarding a request (via the `Forwarded` or
+    /// `X-Forwarded-For` headers).  Requests from any other peer have these
+    /// headers ignored.  Defaults to empty, meaning that the client address is
+    /// always the address of the connected peer.  See
+    /// [`crate::RequestInfo::client_ip`].
+    pub trusted_proxies: Vec<IpCidr>,
+    /// If true, every TCP connection must come from one of the
+    /// `trusted_proxies` and begin with a PROXY protocol header (version 1 or
+    /// 2), as sent by load balancers that forward connections rather than
+    /// requests.  The client address in that header is used as the
+    /// connection's remote address.  Connections from other peers, or
+    /// without a valid header, are closed.  This isn't supported on Unix
+    /// domain sockets or with HTTP/3.  Defaults to false.
+    pub proxy_protocol: bool,
+    /// How long (in milliseconds) the server keeps serving requests after
+    /// it's been asked to shut down and before it stops accepting
+    /// connections.  During this "lame-duck" period,
+    /// [`crate::DropshotState::is_draining`] returns `true` (so that readiness
+    /// checks can fail and load balancers can shift traffic elsewhere) and
+    /// HTTP/1 responses include `Connection: close` so that clients don't
+    /// reuse their connections.  Defaults to 0 (no lame-duck period).
+    pub lame_duck_period_ms: u64,
+    /// How long (in milliseconds) [`crate::HttpServer::close`] waits for
+    /// requests that are already in progress to complete, once the server
+    /// has stopped accepting connections.  The connections of any that take
+    /// longer are closed.  See [`crate::HttpServer::close_with_timeout`].
+    /// Defaults to no limit.
+    pub shutdown_timeout_ms: Option<u64>,
+    /// How long (in milliseconds) a connection may be idle before the server
+    /// closes it.  A connection is idle when none of its requests are being
+    /// handled and nothing has been sent or received on it.  Defaults to no
+    /// limit.
+    pub keep_alive_timeout_ms: Option<u64>,
+    /// How long (in milliseconds) a client may take to send the headers of
+    /// an HTTP/1 request, from when the server first receives any part of
+    /// them, before the server closes the connection.  Such connections are
+    /// counted by [`crate::DropshotState::header_read_timeout_count`].
+    /// Defaults to no limit.
+    pub header_read_timeout_ms: Option<u64>,
+    /// Longest timeout (in milliseconds) that a client may ask for with the
+    /// `Request-Timeout` or `grpc-timeout` header.  If this is set, a request
+    /// with either header gets a [`crate::Deadline`] that's the smaller of its
+    /// timeout and this one, and handlers still running when it passes are
+    /// cancelled.  Defaults to `None`, meaning that these headers are ignored.
+    pub request_timeout_max_ms: Option<u64>,
+    /// Minimum rate (in bytes per second) at which a client must send the
+    /// body of a request, once the handler has started reading it.  Only time
+    /// spent waiting for the client counts, and the client is allowed one
+    /// second beyond what the body it's sent so far would take at this rate.
+    /// If it falls further behind, the server closes the connection (along
+    /// with any other requests on it), and counts it in
+    /// [`crate::DropshotState::slow_request_body_count`].  This protects
+    /// against clients that tie up connections by trickling data.  Defaults
+    /// to no limit.
+    pub min_request_body_rate: Option<u64>,
+    /// Maximum number of requests served on a single connection.  The
+    /// response to the last one includes `Connection: close`, after which
+    /// the server closes the connection.  Defaults to no limit.
+    ///
+    /// This and `max_connection_age_ms` only apply to HTTP/1 connections:
+    /// HTTP/2 connections are bounded by `keep_alive_timeout_ms` alone.
+    pub max_requests_per_connection: Option<u64>,
+    /// How long (in milliseconds) a connection may be used for new requests.
+    /// The response to the first request received after this includes
+    /// `Connection: close`, after which the server closes the connection.
+    /// Limiting this periodically moves clients' traffic to new connections,
+    /// which a load balancer may route elsewhere.  Defaults to no limit.
+    pub max_connection_age_ms: Option<u64>,
+    /// Maximum number of connections that may be open at once.  While this
+    /// many are open, the server closes each new connection as soon as it's
+    /// accepted, without reading any requests from it.  These are counted by
+    /// [`crate::DropshotState::refused_connection_count`].  Defaults to no
+    /// limit.
+    pub max_connections: Option<usize>,
+    /// Maximum length (in bytes) of the path and query string of a request.
+    /// Requests with longer ones fail with a 414 ("URI Too Long") response.
+    /// Defaults to no limit beyond hyper's own.
+    pub max_uri_length: Option<usize>,
+    /// Maximum total size (in bytes) of the names and values of a request's
+    /// header fields.  Requests with larger headers fail with a 431 ("Request
+    /// Header Fields Too Large") response.  Requests whose headers are much
+    /// larger than this are cut off before they've been read completely (in
+    /// which case HTTP/1 clients get a 431 response with no body).  Defaults
+    /// to no limit beyond hyper's own.
+    pub max_request_header_bytes: Option<usize>,
+    /// Maximum number of header fields in a request.  Requests with more fail
+    /// with a 431 ("Request Header Fields Too Large") response.  Regardless of
+    /// this, hyper rejects HTTP/1 requests with more than 100 header fields.
+    /// Defaults to no limit beyond that.
+    pub max_request_headers: Option<usize>,
+    /// Maximum number of requests that may be handled at once, across all
+    /// connections.  Requests beyond this fail with a 503 ("Service
+    /// Unavailable") response.  Defaults to no limit.
+    ///
+    /// Requests rejected because of this limit are counted by
+    /// [`crate::DropshotState::shed_request_count`].
+    pub max_concurrent_requests: Option<usize>,
+    /// If true, error responses are rendered in whichever format the client
+    /// asks for via the `Accept` header: JSON,
+    /// `application/problem+json`, or plain text.  See
+    /// [`crate::HttpError::into_negotiated_response`].  Clients with no
+    /// preference get the API's [`crate::ErrorResponseFormat`].  Defaults to
+    /// false, in which case error responses always use that format.
+    pub error_content_negotiation: bool,
+    /// Maximum number of websocket connections that may be open at once.
+    /// Websockets are long-lived, so they're limited separately from
+    /// ordinary requests: once this many are open, further upgrade requests
+    /// fail with a 503 until some of them close.  See
+    /// [`crate::DropshotState::websocket_connection_count`].  Defaults to no
+    /// limit.
+    pub max_websocket_connections: Option<usize>,
+    /// Keepalive, idle timeout, and shutdown grace period for websocket
+    /// connections.  By default, there's no keepalive (leaving it to each
+    /// handler to notice dead connections), and handlers get 5 seconds to
+    /// finish once the server begins shutting down.  See [`ConfigWebsocket`].
+    pub websocket: ConfigWebsocket,
+    /// Cross-Origin Resource Sharing (CORS) policy, selected by profile.  When
+    /// enabled, the server answers CORS preflight requests for any registered
+    /// route itself.  Defaults to disabled.  See [`ConfigCors`].
+    pub cors: ConfigCors,
+    /// When to report how long each phase of handling a request took via the
+    /// `Server-Timing` response header.  Defaults to never.  See
+    /// [`ConfigServerTiming`].
+    pub server_timing: ConfigServerTiming,
+    /// Whether to validate JSON request and response bodies against the
+    /// schemas in the API's OpenAPI definition.  This is meant for tests and
+    /// development builds.  Defaults to disabled.  See
+    /// [`ConfigSchemaValidation`].
+    pub schema_validation: ConfigSchemaValidation,
+    /// If set, the server also serves HTTP/3 as described here.  This is
+    /// experimental, requires the "http3" feature, and is only supported for
+    /// servers that use TLS.  See [`ConfigHttp3`].  Defaults to `None`.
+    pub http3: Option<ConfigHttp3>,
+    /// Options for the server's TCP sockets, for tuning high-throughput
+    /// deployments.  Defaults to the operating system's defaults (except for
+    /// the accept backlog).  See [`ConfigTcp`].
+    pub tcp: ConfigTcp,
+}
+
+/// Options for the TCP sockets on which the server listens, and for the
+/// connections that it accepts on them
+///
+/// `reuse_port` and `backlog` only apply to sockets that the server binds
+/// itself, not to listeners provided by the caller (as with
+/// [`crate::HttpServerStarter::new_with_listener`]).  The other options apply
+/// to every connection accepted on a TCP listener, with or without TLS.
+///
+/// ```
+/// use dropshot::ConfigDropshot;
+///
+/// let config: ConfigDropshot = toml::from_str(
+///     r##"
+///         bind_address = "0.0.0.0:8080"
+///         [tcp]
+///         nodelay = true
+///         backlog = 4096
+///         keepalive_time_secs = 60
+///     "##
+/// ).unwrap();
+/// assert!(config.tcp.nodelay);
+/// assert!(!config.tcp.reuse_port);
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ConfigTcp {
+    /// Whether to disable Nagle's algorithm (i.e., set `TCP_NODELAY`) on
+    /// accepted connections, so that small responses are sent without delay.
+    /// Defaults to false.
+    pub nodelay: bool,
+    /// Whether to set `SO_REUSEPORT` on the listening socket, which allows
+    /// several sockets (e.g., in several processes) to be bound to the same
+    /// address, with the operating system spreading connections among them.
+    /// Not supported on Windows or illumos.  Defaults to false.
+    pub reuse_port: bool,
+    /// Maximum number of connections that may be waiting to be accepted.
+    /// The operating system may impose a lower limit.  Defaults to 1024.
+    pub backlog: Option<u32>,
+    /// How long (in seconds) a connection may be idle before TCP keepalive
+    /// probes are sent.  If unset, keepalive probes are not enabled (and the
+    /// other keepalive options are ignored).
+    pub keepalive_time_secs: Option<u64>,
+    /// How long (in seconds) to wait between TCP keepalive probes that go
+    /// unanswered.  Defaults to the operating system's default.
+    pub keepalive_interval_secs: Option<u64>,
+    /// How many TCP keepalive probes may go unanswered before the connection
+    /// is closed.  Not supported on Windows.  Defaults to the operating
+    /// system's default.
+    pub keepalive_retries: Option<u32>,
+}
+
+impl ConfigTcp {
+    /// Accept backlog used when none is configured
+    pub(crate) const DEFAULT_BACKLOG: u32 = 1024;
+}
+
+/// Keepalive, timeouts, message limits, and send queues for
+/// upgraded websocket connections
+///
+/// With these, Dropshot pings clients and closes connections that have died
+/// or gone quiet (logging why), so that websocket handlers don't each need
+/// their own keepalive logic.  Pings are only sent between the frames that the
+/// handler writes, and only while the handler is reading or writing the
+/// connection; the idle timeout applies regardless.  Pongs (and pings) don't
+/// count as activity for the idle timeout.
+///
+/// When the server begins shutting down gracefully, websocket handlers are told
+/// (see [`crate::WebsocketConnection::shutdown_signal`]) so that they can send
+/// a Close frame and finish, and the server waits for them to do so (as it
+/// does for other requests).  Handlers that are still running at the end of
+/// the grace period are cancelled, closing their connections.
+///
+/// Clients that send a frame or message larger than allowed have their
+/// connections closed with status 1009 ("Message Too Big"), and those that
+/// send messages too quickly, with status 1008 ("Policy Violation").  The
+/// handler's reads and writes fail from then on, and it's cancelled if it
+/// doesn't finish right away.  Limits only apply to what the client sends.
+///
+/// Messages that handlers queue with [`crate::WebsocketSendQueue`] are held in
+/// a bounded queue, and what happens when a client falls behind enough to fill
+/// it is set by [`WebsocketOverflowPolicy`].
+///
+/// These settings apply to every websocket connection.  Individual channels can
+/// override them (see [`crate::WebsocketUpgrade::with_config`]).
+///
+/// ```
+/// use dropshot::ConfigDropshot;
+///
+/// let config: ConfigDropshot = toml::from_str(
+///     r##"
+///         bind_address = "0.0.0.0:8080"
+///         [websocket]
+///         ping_interval_ms = 30000
+///         pong_timeout_ms = 10000
+///     "##
+/// ).unwrap();
+/// assert_eq!(config.websocket.ping_interval_ms, Some(30000));
+/// assert_eq!(config.websocket.idle_timeout_ms, None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ConfigWebsocket {
+    /// How often (in milliseconds) to ping the client.  Defaults to never.
+    pub ping_interval_ms: Option<u64>,
+    /// How long (in milliseconds) the client may take to answer a ping with a
+    /// pong before the connection is closed.  Defaults to no limit.
+    pub pong_timeout_ms: Option<u64>,
+    /// How long (in milliseconds) a connection may go without any messages in
+    /// either direction before it's closed.  Defaults to no limit.
+    pub idle_timeout_ms: Option<u64>,
+    /// How long (in milliseconds) handlers may keep running once the server
+    /// has begun shutting down.  Defaults to 5 seconds.
+    pub shutdown_grace_period_ms: Option<u64>,
+    /// Largest frame (in bytes of payload) that clients may send.  Defaults
+    /// to no limit.
+    pub max_frame_bytes: Option<u64>,
+    /// Largest message (in bytes of payload, across all of its frames) that
+    /// clients may send.  Defaults to no limit.
+    pub max_message_bytes: Option<u64>,
+    /// How many messages clients may send per second, on average.  Clients
+    /// may send up to a second's worth at once.  Defaults to no limit.
+    pub max_messages_per_second: Option<u32>,
+    /// How many messages the connection's [`crate::WebsocketSendQueue`] may
+    /// hold.  Defaults to 256.
+    pub send_queue_len: Option<usize>,
+    /// How many bytes the connection's [`crate::WebsocketSendQueue`] may hold.
+    /// Defaults to no limit.
+    pub send_queue_max_bytes: Option<u64>,
+    /// What to do when the connection's [`crate::WebsocketSendQueue`] is full.
+    /// Defaults to [`WebsocketOverflowPolicy::Block`].
+    pub send_queue_policy: Option<WebsocketOverflowPolicy>,
+}
+
+impl ConfigWebsocket {
+    /// Returns these settings, with any that `overrides` sets replaced by its
+    /// values
+    pub(crate) fn merge(&self, overrides: &ConfigWebsocket) -> ConfigWebsocket {
+        ConfigWebsocket {
+            ping_interval_ms: overrides
+                .ping_interval_ms
+                .or(self.ping_interval_ms),
+            pong_timeout_ms: overrides.pong_timeout_ms.or(self.pong_timeout_ms),
+            idle_timeout_ms: overrides.idle_timeout_ms.or(self.idle_timeout_ms),
+            shutdown_grace_period_ms: overrides
+                .shutdown_grace_period_ms
+                .or(self.shutdown_grace_period_ms),
+            max_frame_bytes: overrides.max_frame_bytes.or(self.max_frame_bytes),
+            max_message_bytes: overrides
+                .max_message_bytes
+                .or(self.max_message_bytes),
+            max_messages_per_second: overrides
+                .max_messages_per_second
+                .or(self.max_messages_per_second),
+            send_queue_len: overrides.send_queue_len.or(self.send_queue_len),
+            send_queue_max_bytes: overrides
+                .send_queue_max_bytes
+                .or(self.send_queue_max_bytes),
+            send_queue_policy: overrides
+                .send_queue_policy
+                .or(self.send_queue_policy),
+        }
+    }
+
+    /// Returns how long handlers may keep running once the server has begun
+    /// shutting down
+    pub(crate) fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_millis(
+            self.shutdown_grace_period_ms
+                .unwrap_or(Self::DEFAULT_SHUTDOWN_GRACE_PERIOD_MS),
+        )
+    }
+
+    /// Shutdown grace period used when none is configured
+    const DEFAULT_SHUTDOWN_GRACE_PERIOD_MS: u64 = 5000;
+
+    /// Returns whether any of the keepalive settings are enabled
+    pub(crate) fn has_keepalive(&self) -> bool {
+        self.ping_interval_ms.is_some()
+            || self.pong_timeout_ms.is_some()
+            || self.idle_timeout_ms.is_some()
+    }
+
+    /// Returns whether any of the limits on what clients send are enabled
+    pub(crate) fn has_limits(&self) -> bool {
+        self.max_frame_bytes.is_some()
+            || self.max_message_bytes.is_some()
+            || self.max_messages_per_second.is_some()
+    }
+}
+
+/// What a websocket connection's [`crate::WebsocketSendQueue`] does with a
+/// message when it's full, because the client isn't receiving messages as
+/// fast as they're sent
+///
+/// ```
+/// use dropshot::ConfigDropshot;
+/// use dropshot::WebsocketOverflowPolicy;
+///
+/// let config: ConfigDropshot = toml::from_str(
+///     r##"
+///         bind_address = "0.0.0.0:8080"
+///         [websocket]
+///         send_queue_len = 64
+///         send_queue_policy = "drop-oldest"
+///     "##
+/// ).unwrap();
+/// assert_eq!(
+///     config.websocket.send_queue_policy,
+///     Some(WebsocketOverflowPolicy::DropOldest)
+/// );
+/// ```
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebsocketOverflowPolicy {
+    /// Make the sender wait until there's room.
+    #[default]
+    Block,
+    /// Drop the oldest queued messages to make room, for clients that only
+    /// care about the latest state.
+    DropOldest,
+    /// Close the connection (with status 1008, "Policy Violation").
+    Close,
+}
+
+/// Configuration for serving HTTP/3 (over QUIC) in addition to HTTP/1.1 and
+/// HTTP/2
+///
+/// **This is experimental**, and it requires the "http3" feature.  Requests
+/// received over HTTP/3 are routed to the same handlers, with the same
+/// context, as those received on the server's other listeners.  The server's
+/// TLS certificate and key are used for QUIC as well, so the TLS
+/// configuration must be [`ConfigTls::AsFile`] or [`ConfigTls::AsBytes`]
+/// (without client authentication).
+///
+/// Clients generally only try HTTP/3 once they've learned that it's
+/// available, so responses on the server's other listeners advertise it with
+/// an `Alt-Svc` header.
+///
+/// Websocket upgrades aren't supported over HTTP/3, and HTTP/3 connections
+/// don't count toward [`ConfigDropshot::max_connections`] or the other
+/// per-connection limits.
+///
+/// ```
+/// use dropshot::ConfigDropshot;
+///
+/// let config: ConfigDropshot = toml::from_str(
+///     r##"
+///         bind_address = "0.0.0.0:443"
+///         [http3]
+///         bind_address = "0.0.0.0:443"
+///     "##
+/// ).unwrap();
+/// assert_eq!(config.http3.unwrap().alt_svc_max_age_secs, 86400);
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigHttp3 {
+    /// IP address and UDP port to which to bind.  This is usually the same
+    /// as the server's (TCP) `bind_address`.
+    pub bind_address: SocketAddr,
+    /// How long (in seconds) clients may remember that HTTP/3 is available,
+    /// as advertised in the `Alt-Svc` header.  If 0, HTTP/3 isn't advertised.
+    /// Defaults to one day.
+    #[serde(default = "ConfigHttp3::default_alt_svc_max_age_secs")]
+    pub alt_svc_max_age_secs: u64,
+}
+
+impl ConfigHttp3 {
+    fn default_alt_svc_max_age_secs() -> u64 {
+        86400
+    }
+}
+
+/// Configuration for accepting connections on a Unix domain socket
+///
+/// This is useful for services that are only meant to be used by other
+/// processes on the same system, which can then be identified by their
+/// credentials (see [`crate::RequestInfo::peer_credentials`]) and restricted
+/// using filesystem permissions.  Unix domain sockets are only supported on
+/// Unix-like systems, and not in combination with TLS.
+///
+/// Clients connected via a Unix domain socket have no IP address, so
+/// [`crate::RequestInfo::remote_addr`] reports the unspecified address
+/// `0.0.0.0:0` for them.  The same goes for
+/// [`crate::HttpServer::local_addr`].
+///
+/// ```
+/// use dropshot::ConfigDropshot;
+///
+/// let config: ConfigDropshot = toml::from_str(
+///     r##"
+///         [unix_socket]
+///         path = "/var/run/myservice.sock"
+///         mode = 0o660
+///     "##
+/// ).unwrap();
+/// assert_eq!(config.unix_socket.unwrap().mode, Some(0o660));
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigUnixSocket {
+    /// Filesystem path at which to create the socket.  Nothing may exist at
+    /// this path when the server starts.  The server does not remove the
+    /// socket when it shuts down.
+    pub path: PathBuf,
+    /// If set, the socket's permission bits are set to this mode (e.g.,
+    /// `0o660` to allow connections only from the owning user and group)
+    /// before it appears at `path`, so it's never reachable with any other
+    /// mode.  (The socket is created in a temporary directory alongside
+    /// `path` and then linked into place, so the server needs write access to
+    /// the parent directory.)  Otherwise, they're determined by the process's
+    /// umask.
+    #[serde(default)]
+    pub mode: Option<u32>,
 }
 
 /// Enum specifying options for how a Dropshot server should run its handler
@@ -99,14 +564,117 @@ pub enum ConfigTls {
     /// The dropshot consumer will provide TLS configuration dynamically (that
     /// is not expressible in a static config file)
     Dynamic(RawTlsConfig),
+    /// The server will identify itself as described by `server` (which must
+    /// be `AsFile` or `AsBytes`) and authenticate clients using certificates
+    /// as described by `client_auth`.  See [`ConfigTls::with_client_auth`].
+    WithClientAuth { server: Box<ConfigTls>, client_auth: ConfigClientAuth },
+}
+
+impl ConfigTls {
+    /// Adds client certificate authentication ("mutual TLS") to this
+    /// configuration
+    ///
+    /// Handlers can find out which certificate the client presented using
+    /// [`crate::RequestInfo::client_certificate`].
+    pub fn with_client_auth(self, client_auth: ConfigClientAuth) -> ConfigTls {
+        ConfigTls::WithClientAuth { server: Box::new(self), client_auth }
+    }
+}
+
+/// PEM-encoded data (certificates or revocation lists) used in TLS
+/// configuration
+#[derive(Clone, Debug)]
+pub enum ConfigPem {
+    /// The data will be read from the specified file.
+    AsFile(PathBuf),
+    /// The data is provided directly.
+    AsBytes(Vec<u8>),
+}
+
+/// Function used to reject client certificates beyond the usual validation:
+/// see [`ConfigClientAuth::revocation_check`]
+pub type ClientCertificateCheck =
+    Arc<dyn Fn(&ClientCertificate) -> Result<(), String> + Send + Sync>;
+
+/// Configuration for authenticating TLS clients using certificates
+///
+/// See [`ConfigTls::with_client_auth`].
+#[derive(Clone)]
+pub struct ConfigClientAuth {
+    /// Certificates of the certificate authorities trusted to issue client
+    /// certificates
+    pub ca_certs: ConfigPem,
+    /// If true, clients must present a valid certificate to connect.  If
+    /// false, they may also connect without one (but a certificate that they
+    /// do present must still be valid).
+    pub required: bool,
+    /// Certificate revocation lists used to reject revoked client
+    /// certificates.  If any are provided, certificates whose revocation
+    /// status they don't cover are rejected.
+    pub crls: Vec<ConfigPem>,
+    /// If set, this function is called for each client certificate that's
+    /// otherwise valid, for checks that certificate revocation lists can't
+    /// express (e.g., consulting an up-to-date list of revoked serial
+    /// numbers).  Returning an error rejects the certificate as revoked and
+    /// logs the message.  This is called during the TLS handshake, so it
+    /// must not block.
+    pub revocation_check: Option<ClientCertificateCheck>,
+}
+
+impl std::fmt::Debug for ConfigClientAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigClientAuth")
+            .field("ca_certs", &self.ca_certs)
+            .field("required", &self.required)
+            .field("crls", &self.crls)
+            .field("revocation_check", &self.revocation_check.is_some())
+            .finish()
+    }
+}
+
+/// Configuration for an additional address on which a server accepts
+/// connections
+///
+/// See [`crate::HttpServerStarter::listen`].
+#[derive(Clone, Debug)]
+pub struct ConfigListener {
+    /// IP address and TCP port to which to bind
+    pub bind_address: SocketAddr,
+    /// If set, connections to this address use TLS with this configuration.
+    /// This is independent of whether the server's other listeners use TLS.
+    pub tls: Option<ConfigTls>,
 }
 
 impl Default for ConfigDropshot {
     fn default() -> Self {
         ConfigDropshot {
             bind_address: "127.0.0.1:0".parse().unwrap(),
+            unix_socket: None,
             request_body_max_bytes: 1024,
             default_handler_task_mode: HandlerTaskMode::Detached,
+            trusted_proxies: Vec::new(),
+            proxy_protocol: false,
+            lame_duck_period_ms: 0,
+            shutdown_timeout_ms: None,
+            keep_alive_timeout_ms: None,
+            header_read_timeout_ms: None,
+            request_timeout_max_ms: None,
+            min_request_body_rate: None,
+            max_requests_per_connection: None,
+            max_connection_age_ms: None,
+            max_connections: None,
+            max_concurrent_requests: None,
+            max_uri_length: None,
+            max_request_header_bytes: None,
+            max_request_headers: None,
+            error_content_negotiation: false,
+            max_websocket_connections: None,
+            websocket: ConfigWebsocket::default(),
+            cors: ConfigCors::Disabled,
+            server_timing: ConfigServerTiming::default(),
+            schema_validation: ConfigSchemaValidation::default(),
+            http3: None,
+            tcp: ConfigTcp::default(),
         }
     }
 }
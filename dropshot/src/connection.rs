@@ -0,0 +1,626 @@
+// Copyright 2024 Oxide Computer Company
+//! State and limits for each connection accepted by the server
+//!
+//! Every kind of listener (TCP, TLS, or a Unix domain socket) produces
+//! connections that are wrapped in a [`ServerConnection`] by a
+//! [`ConnectionAcceptor`].  The wrapper keeps track of the connection's
+//! activity so that it can be closed once it's been idle for too long (see
+//! [`crate::ConfigDropshot::keep_alive_timeout_ms`]), and it carries the
+//! [`ConnectionState`] that the connection's request handler uses to decide
+//! when the connection has served enough requests.  Connections on a TCP
+//! listener are first offered to the server's [`crate::ConnectionFilter`], if
+//! it has one, and connections beyond
+//! [`crate::ConfigDropshot::max_connections`] are closed as soon as they're
+//! accepted.
+//!
+//! This is also where clients that trickle data to tie up connections are
+//! cut off: the wrapper closes HTTP/1 connections whose request headers take
+//! too long to arrive (see [`crate::ConfigDropshot::header_read_timeout_ms`]),
+//! and request bodies are wrapped in a [`MinRateBody`] that closes the
+//! connection if the body arrives too slowly (see
+//! [`crate::ConfigDropshot::min_request_body_rate`]).
+
+use crate::connection_filter::{AdmittedConnection, ConnectionFilterSlot};
+use crate::server::{DropshotState, ServerConfig, ServerContext};
+use crate::tls_client_auth::ClientCertificate;
+use crate::unix_socket::PeerCredentials;
+use bytes::Bytes;
+use futures::task::AtomicWaker;
+use futures::Stream;
+use hyper::body::HttpBody;
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrStream;
+use hyper::Body;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// Information about the client that's determined when a connection is
+/// established, for each type of connection
+pub(crate) trait ConnectionInfo {
+    fn remote_addr(&self) -> SocketAddr;
+
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        None
+    }
+
+    fn client_certificate(&self) -> Option<ClientCertificate> {
+        None
+    }
+}
+
+impl ConnectionInfo for AddrStream {
+    fn remote_addr(&self) -> SocketAddr {
+        AddrStream::remote_addr(self)
+    }
+}
+
+#[cfg(unix)]
+impl ConnectionInfo for tokio::net::UnixStream {
+    fn remote_addr(&self) -> SocketAddr {
+        crate::unix_socket::UNIX_SOCKET_ADDR
+    }
+
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        match self.peer_cred() {
+            Ok(cred) => Some(PeerCredentials::from(cred)),
+            Err(error) => {
+                tracing::warn!(
+                    %error,
+                    "failed to get Unix domain socket peer credentials"
+                );
+                None
+            }
+        }
+    }
+}
+
+/// State of a connection that's shared between the connection itself and the
+/// service handling its requests
+#[derive(Debug)]
+pub(crate) struct ConnectionState {
+    /// when the connection was accepted
+    created: Instant,
+    /// number of requests received on this connection
+    nrequests: AtomicU64,
+    /// number of requests on this connection currently being handled
+    in_flight: AtomicUsize,
+    /// set when the connection is to be closed immediately
+    aborted: AtomicBool,
+    /// wakes up the connection to notice that it's been aborted
+    abort_waker: AtomicWaker,
+}
+
+impl ConnectionState {
+    fn new() -> Self {
+        ConnectionState {
+            created: Instant::now(),
+            nrequests: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+            abort_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Closes the connection without waiting for any requests on it to
+    /// complete
+    pub(crate) fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.abort_waker.wake();
+    }
+
+    /// Records the start of a request, which ends when the returned guard is
+    /// dropped
+    pub(crate) fn begin_request(self: &Arc<Self>) -> ActiveRequest {
+        let number = self.nrequests.fetch_add(1, Ordering::SeqCst) + 1;
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        ActiveRequest { connection: Arc::clone(self), number }
+    }
+}
+
+/// A request in progress on a connection
+pub(crate) struct ActiveRequest {
+    connection: Arc<ConnectionState>,
+    /// the number of this request (starting from 1) on its connection
+    number: u64,
+}
+
+impl ActiveRequest {
+    /// Returns whether the connection has reached the limits in `config` on
+    /// the number of requests or the age of a connection, meaning this should
+    /// be the last request on it
+    pub(crate) fn is_last(&self, config: &ServerConfig) -> bool {
+        config.max_requests_per_connection.is_some_and(|max| self.number >= max)
+            || config
+                .max_connection_age
+                .is_some_and(|age| self.connection.created.elapsed() >= age)
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.connection.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wraps each connection accepted by `A` in a [`ServerConnection`], keeping
+/// count of those that are open
+///
+/// Connections accepted while the server already has `max_connections` open
+/// are closed straight away, before any of their data is read.
+pub(crate) struct ConnectionAcceptor<A> {
+    inner: A,
+    keep_alive_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    header_read_timeouts: Arc<AtomicU64>,
+    connections: Arc<AtomicUsize>,
+    connections_refused: Arc<AtomicU64>,
+    max_connections: Option<usize>,
+    /// filter to apply to each connection, if `inner` doesn't already
+    filter: Option<Arc<ConnectionFilterSlot>>,
+}
+
+impl<A> ConnectionAcceptor<A> {
+    pub(crate) fn new<C: ServerContext>(
+        inner: A,
+        app_state: &DropshotState<C>,
+        filter: Option<Arc<ConnectionFilterSlot>>,
+    ) -> Self {
+        ConnectionAcceptor {
+            inner,
+            keep_alive_timeout: app_state.config.keep_alive_timeout,
+            header_read_timeout: app_state.config.header_read_timeout,
+            header_read_timeouts: Arc::clone(&app_state.header_read_timeouts),
+            connections: Arc::clone(&app_state.connections),
+            connections_refused: Arc::clone(&app_state.connections_refused),
+            max_connections: app_state.config.max_connections,
+            filter,
+        }
+    }
+
+    /// Counts a newly accepted connection from `remote_addr` as open, unless
+    /// that would exceed the server's limit
+    fn count(&self, remote_addr: SocketAddr) -> Option<CountedConnection> {
+        let nconnections = self.connections.fetch_add(1, Ordering::SeqCst);
+        let counted = CountedConnection(Arc::clone(&self.connections));
+        if self.max_connections.is_some_and(|max| nconnections >= max) {
+            drop(counted);
+            self.connections_refused.fetch_add(1, Ordering::SeqCst);
+            tracing::warn!(
+                %remote_addr,
+                max_connections = self.max_connections,
+                "too many connections; closing new connection"
+            );
+            return None;
+        }
+        Some(counted)
+    }
+
+    fn wrap<T>(
+        &self,
+        io: T,
+        counted: CountedConnection,
+        admitted: Option<AdmittedConnection>,
+    ) -> ServerConnection<T> {
+        let header_timer =
+            self.header_read_timeout.map(|timeout| HeaderTimer {
+                timeout,
+                running: None,
+                timeouts: Arc::clone(&self.header_read_timeouts),
+            });
+        ServerConnection::new(
+            io,
+            self.keep_alive_timeout,
+            header_timer,
+            counted,
+            admitted,
+        )
+    }
+}
+
+/// Decrements the server's count of open connections when dropped
+struct CountedConnection(Arc<AtomicUsize>);
+
+impl Drop for CountedConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<A> Accept for ConnectionAcceptor<A>
+where
+    A: Accept + Unpin,
+    A::Conn: ConnectionInfo,
+{
+    type Conn = ServerConnection<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            let io = match Pin::new(&mut this.inner).poll_accept(cx) {
+                Poll::Ready(Some(Ok(io))) => io,
+                Poll::Ready(Some(Err(error))) => {
+                    return Poll::Ready(Some(Err(error)))
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            let admitted = match &this.filter {
+                None => None,
+                Some(filter) => match filter.admit(io.remote_addr()) {
+                    Some(admitted) => Some(admitted),
+                    // Dropping the connection closes it.
+                    None => continue,
+                },
+            };
+            let Some(counted) = this.count(io.remote_addr()) else {
+                // As above, dropping the connection closes it.
+                continue;
+            };
+            return Poll::Ready(Some(Ok(this.wrap(io, counted, admitted))));
+        }
+    }
+}
+
+/// A connection accepted by the server, which is closed if it's idle for
+/// longer than the keep-alive timeout
+///
+/// A connection is idle when no requests on it are being handled and nothing
+/// has been read from or written to it.  Hyper treats the end-of-file that we
+/// report when it's timed out like the client closing the connection.
+///
+/// The connection is also closed if the client takes too long to send a
+/// request's headers, or if it's aborted (see [`ConnectionState::abort`]).
+/// In these cases we report an error, so that hyper gives up on any requests
+/// in progress.
+pub(crate) struct ServerConnection<T> {
+    io: T,
+    state: Arc<ConnectionState>,
+    idle_timer: Option<IdleTimer>,
+    header_timer: Option<HeaderTimer>,
+    /// whether anything has been read from the connection yet
+    received_any: bool,
+    _counted: CountedConnection,
+    _admitted: Option<AdmittedConnection>,
+}
+
+struct IdleTimer {
+    timeout: Duration,
+    last_activity: Instant,
+    sleep: Pin<Box<Sleep>>,
+}
+
+/// Limits how long a client may take to send the headers of an HTTP/1 request
+///
+/// The timer starts when data is received while no request is being handled,
+/// and it's stopped once a request has been received.
+struct HeaderTimer {
+    timeout: Duration,
+    /// if the timer is running, the number of requests that had been received
+    /// on the connection when it started, and when it expires
+    running: Option<(u64, Pin<Box<Sleep>>)>,
+    /// the server's count of connections that have timed out
+    timeouts: Arc<AtomicU64>,
+}
+
+/// Every HTTP/2 connection starts with this (which can't be the start of an
+/// HTTP/1 request)
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+impl<T> ServerConnection<T> {
+    fn new(
+        io: T,
+        keep_alive_timeout: Option<Duration>,
+        header_timer: Option<HeaderTimer>,
+        counted: CountedConnection,
+        admitted: Option<AdmittedConnection>,
+    ) -> Self {
+        let idle_timer = keep_alive_timeout.map(|timeout| {
+            let now = Instant::now();
+            IdleTimer {
+                timeout,
+                last_activity: now,
+                sleep: Box::pin(tokio::time::sleep_until(now + timeout)),
+            }
+        });
+        ServerConnection {
+            io,
+            state: Arc::new(ConnectionState::new()),
+            idle_timer,
+            header_timer,
+            received_any: false,
+            _counted: counted,
+            _admitted: admitted,
+        }
+    }
+
+    pub(crate) fn io(&self) -> &T {
+        &self.io
+    }
+
+    pub(crate) fn state(&self) -> &Arc<ConnectionState> {
+        &self.state
+    }
+
+    fn record_activity(&mut self) {
+        if let Some(idle_timer) = &mut self.idle_timer {
+            idle_timer.last_activity = Instant::now();
+        }
+    }
+
+    /// Starts the header timer if `data` (just received) may be the start of
+    /// a new request's headers
+    fn record_data(&mut self, data: &[u8]) {
+        if !self.received_any {
+            self.received_any = true;
+            // Like hyper's own, the timeout only applies to HTTP/1.
+            let n = std::cmp::min(data.len(), HTTP2_PREFACE.len());
+            if data[..n] == HTTP2_PREFACE[..n] {
+                self.header_timer = None;
+            }
+        }
+        let Some(header_timer) = &mut self.header_timer else {
+            return;
+        };
+        if self.state.in_flight.load(Ordering::SeqCst) != 0 {
+            return;
+        }
+        let nrequests = self.state.nrequests.load(Ordering::SeqCst);
+        if header_timer.running.as_ref().is_some_and(|(n, _)| *n == nrequests) {
+            return;
+        }
+        let sleep = Box::pin(tokio::time::sleep(header_timer.timeout));
+        header_timer.running = Some((nrequests, sleep));
+    }
+
+    /// Returns whether the client has taken too long to send a request's
+    /// headers, arranging to be woken up to check again otherwise
+    fn poll_header_timeout(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(header_timer) = &mut self.header_timer else {
+            return false;
+        };
+        let Some((nrequests, sleep)) = &mut header_timer.running else {
+            return false;
+        };
+        if sleep.as_mut().poll(cx).is_pending() {
+            return false;
+        }
+        let timed_out = self.state.in_flight.load(Ordering::SeqCst) == 0
+            && self.state.nrequests.load(Ordering::SeqCst) == *nrequests;
+        header_timer.running = None;
+        if timed_out {
+            header_timer.timeouts.fetch_add(1, Ordering::SeqCst);
+        }
+        timed_out
+    }
+
+    /// Returns whether the connection has been idle for longer than the
+    /// keep-alive timeout, arranging to be woken up to check again otherwise
+    fn poll_idle_timeout(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(idle_timer) = &mut self.idle_timer else {
+            return false;
+        };
+        loop {
+            if idle_timer.sleep.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+            let now = Instant::now();
+            let deadline = if self.state.in_flight.load(Ordering::SeqCst) == 0 {
+                idle_timer.last_activity + idle_timer.timeout
+            } else {
+                now + idle_timer.timeout
+            };
+            if deadline <= now {
+                return true;
+            }
+            idle_timer.sleep.as_mut().reset(deadline);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for ServerConnection<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        this.state.abort_waker.register(cx.waker());
+        if this.state.aborted.load(Ordering::SeqCst) {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "connection aborted by server",
+            )));
+        }
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if buf.filled().len() > filled {
+                    this.record_activity();
+                    this.record_data(&buf.filled()[filled..]);
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending if this.poll_header_timeout(cx) => {
+                tracing::debug!(
+                    "closing connection: timed out reading request headers"
+                );
+                Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "timed out reading request headers",
+                )))
+            }
+            Poll::Pending if this.poll_idle_timeout(cx) => {
+                tracing::debug!("closing idle connection");
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for ServerConnection<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.io).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.record_activity();
+            }
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.io).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.record_activity();
+            }
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// Allowance (beyond what the minimum rate allows for) for the time spent
+/// waiting for a request body
+const BODY_RATE_ALLOWANCE: Duration = Duration::from_secs(1);
+
+/// A request body that aborts its connection if the client sends it more
+/// slowly than a minimum rate
+///
+/// Only time spent waiting for the client counts, so a handler that takes a
+/// while to get around to reading the body (or that reads it slowly) doesn't
+/// cause the connection to be aborted.  The client may spend up to
+/// [`BODY_RATE_ALLOWANCE`] longer than it would take to send what it's sent
+/// so far at the minimum rate.
+pub(crate) struct MinRateBody {
+    body: Body,
+    /// minimum rate, in bytes per second (never zero)
+    min_rate: u64,
+    /// number of bytes of the body received so far
+    received: u64,
+    /// total time spent waiting for the client so far, not counting the
+    /// current wait
+    waited: Duration,
+    /// if we're waiting for the client, when that began and when it may last
+    /// until
+    waiting: Option<(Instant, Pin<Box<Sleep>>)>,
+    connection: Arc<ConnectionState>,
+    /// the server's count of connections aborted for sending bodies slowly
+    slow_bodies: Arc<AtomicU64>,
+}
+
+impl MinRateBody {
+    pub(crate) fn new(
+        body: Body,
+        min_rate: u64,
+        connection: Arc<ConnectionState>,
+        slow_bodies: Arc<AtomicU64>,
+    ) -> Self {
+        assert_ne!(min_rate, 0);
+        MinRateBody {
+            body,
+            min_rate,
+            received: 0,
+            waited: Duration::ZERO,
+            waiting: None,
+            connection,
+            slow_bodies,
+        }
+    }
+
+    /// Returns how much longer we may wait for the client before it's behind
+    fn remaining_allowance(&self) -> Duration {
+        let allowed = BODY_RATE_ALLOWANCE
+            + Duration::from_secs_f64(
+                self.received as f64 / self.min_rate as f64,
+            );
+        allowed.saturating_sub(self.waited)
+    }
+}
+
+impl Stream for MinRateBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_data(cx) {
+            Poll::Ready(chunk) => {
+                if let Some((began, _)) = this.waiting.take() {
+                    this.waited += began.elapsed();
+                }
+                if let Some(Ok(chunk)) = &chunk {
+                    this.received += chunk.len() as u64;
+                }
+                Poll::Ready(chunk.map(|chunk| chunk.map_err(Into::into)))
+            }
+            Poll::Pending => {
+                if this.waiting.is_none() {
+                    let now = Instant::now();
+                    let deadline = now + this.remaining_allowance();
+                    this.waiting = Some((
+                        now,
+                        Box::pin(tokio::time::sleep_until(deadline)),
+                    ));
+                }
+                let (_, sleep) = this.waiting.as_mut().unwrap();
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.waiting = None;
+                this.slow_bodies.fetch_add(1, Ordering::SeqCst);
+                tracing::warn!(
+                    min_rate = this.min_rate,
+                    received = this.received,
+                    "closing connection: request body sent too slowly"
+                );
+                this.connection.abort();
+                Poll::Ready(Some(Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "request body sent too slowly",
+                )))))
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2024 Oxide Computer Company
+//! Deciding whether to accept each connection based on the peer's address
+//!
+//! See [`crate::HttpServerStarter::connection_filter`].
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+/// Decides whether the server accepts each TCP connection, based only on the
+/// address of the peer
+///
+/// The filter is consulted as soon as a connection is accepted by the
+/// operating system, before any TLS negotiation or HTTP parsing, so rejecting
+/// a connection here costs much less than rejecting its requests in
+/// middleware.  Rejected connections are simply closed.
+///
+/// The peer address is the one the connection comes from, not one reported by
+/// a proxy in forwarding headers (which haven't been read yet).  Connections on
+/// a Unix domain socket are not filtered.
+///
+/// Any function or closure taking the peer address and returning whether to
+/// accept the connection is a `ConnectionFilter`.  Filters that keep track of
+/// the connections they've accepted (e.g., to limit the number of connections
+/// from each client) can implement [`ConnectionFilter::closed`] as well:
+///
+/// ```
+/// use dropshot::ConnectionFilter;
+/// use std::collections::HashMap;
+/// use std::net::{IpAddr, SocketAddr};
+/// use std::sync::Mutex;
+///
+/// struct PerClientLimit {
+///     max: usize,
+///     connections: Mutex<HashMap<IpAddr, usize>>,
+/// }
+///
+/// impl ConnectionFilter for PerClientLimit {
+///     fn accept(&self, peer: SocketAddr) -> bool {
+///         let mut connections = self.connections.lock().unwrap();
+///         let count = connections.entry(peer.ip()).or_insert(0);
+///         if *count >= self.max {
+///             return false;
+///         }
+///         *count += 1;
+///         true
+///     }
+///
+///     fn closed(&self, peer: SocketAddr) {
+///         let mut connections = self.connections.lock().unwrap();
+///         if let Some(count) = connections.get_mut(&peer.ip()) {
+///             *count -= 1;
+///             if *count == 0 {
+///                 connections.remove(&peer.ip());
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub trait ConnectionFilter: Send + Sync + 'static {
+    /// Returns whether to accept a connection from `peer`
+    ///
+    /// This is called on the task that accepts connections, so it must not
+    /// block.
+    fn accept(&self, peer: SocketAddr) -> bool;
+
+    /// Called when a connection from `peer` that this filter accepted is
+    /// closed, including when TLS negotiation fails
+    fn closed(&self, _peer: SocketAddr) {}
+}
+
+impl<F> ConnectionFilter for F
+where
+    F: Fn(SocketAddr) -> bool + Send + Sync + 'static,
+{
+    fn accept(&self, peer: SocketAddr) -> bool {
+        self(peer)
+    }
+}
+
+/// The server's connection filter, if any
+///
+/// The filter can be provided after the listeners have been created (but
+/// before any connections are accepted), so they share this slot.
+#[derive(Default)]
+pub(crate) struct ConnectionFilterSlot(
+    RwLock<Option<Arc<dyn ConnectionFilter>>>,
+);
+
+impl ConnectionFilterSlot {
+    pub(crate) fn set(&self, filter: Arc<dyn ConnectionFilter>) {
+        *self.0.write().unwrap() = Some(filter);
+    }
+
+    /// Asks the filter whether to accept a connection from `peer`, returning
+    /// `None` if it's to be rejected
+    pub(crate) fn admit(&self, peer: SocketAddr) -> Option<AdmittedConnection> {
+        let filter = self.0.read().unwrap().clone();
+        match filter {
+            None => Some(AdmittedConnection(None)),
+            Some(filter) if filter.accept(peer) => {
+                Some(AdmittedConnection(Some((filter, peer))))
+            }
+            Some(_) => {
+                debug!(remote_addr = %peer, "connection rejected by filter");
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionFilterSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let is_set = self.0.read().map_or(false, |filter| filter.is_some());
+        f.debug_tuple("ConnectionFilterSlot").field(&is_set).finish()
+    }
+}
+
+/// Held for as long as an admitted connection is open, to tell the filter when
+/// it's closed
+pub(crate) struct AdmittedConnection(
+    Option<(Arc<dyn ConnectionFilter>, SocketAddr)>,
+);
+
+impl fmt::Debug for AdmittedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let peer = self.0.as_ref().map(|(_, peer)| peer);
+        f.debug_tuple("AdmittedConnection").field(&peer).finish()
+    }
+}
+
+impl Drop for AdmittedConnection {
+    fn drop(&mut self) {
+        if let Some((filter, peer)) = &self.0 {
+            filter.closed(*peer);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2024 Oxide Computer Company
+//! Cross-Origin Resource Sharing (CORS) support
+//!
+//! When CORS is enabled (see [`crate::ConfigDropshot::cors`]), Dropshot
+//! answers CORS preflight requests itself, so API authors don't need to
+//! register `OPTIONS` endpoints, and it adds the appropriate
+//! `Access-Control-*` headers to every response to an allowed origin.
+
+use crate::router::HttpRouter;
+use crate::server::ServerContext;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use serde::Deserialize;
+use serde::Serialize;
+
+/// How long clients may cache the result of a preflight request
+const PREFLIGHT_MAX_AGE_SECS: &str = "600";
+
+/// CORS policy for a server, selected by profile
+///
+/// In TOML, this looks like:
+///
+/// ```toml
+/// [cors]
+/// profile = "same-site-console"
+/// allowed_origins = [ "https://console.example.com" ]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "profile", rename_all = "kebab-case")]
+pub enum ConfigCors {
+    /// No CORS headers are sent, so browsers only allow same-origin requests.
+    /// Preflight requests are handled like any other `OPTIONS` request.
+    #[default]
+    Disabled,
+    /// For an API used by a web console served from one of a few known
+    /// origins (e.g., on a sibling subdomain).  Requests from these origins
+    /// may include credentials (like cookies).  Requests from other origins
+    /// get no CORS headers.
+    SameSiteConsole {
+        /// origins (e.g., `"https://console.example.com"`) that may use the
+        /// API
+        allowed_origins: Vec<String>,
+    },
+    /// For an API meant to be used from any origin.  Requests may not include
+    /// credentials.
+    PublicApi,
+}
+
+impl ConfigCors {
+    /// Returns the value of `Access-Control-Allow-Origin` for a request with
+    /// the given `Origin`, or `None` if the request gets no CORS headers
+    fn allow_origin(
+        &self,
+        origin: Option<&HeaderValue>,
+    ) -> Option<HeaderValue> {
+        let origin = origin?;
+        match self {
+            ConfigCors::Disabled => None,
+            ConfigCors::SameSiteConsole { allowed_origins } => allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+                .then(|| origin.clone()),
+            ConfigCors::PublicApi => Some(HeaderValue::from_static("*")),
+        }
+    }
+}
+
+/// If `request` is a CORS preflight request for a route that exists and
+/// `config` allows the origin, returns the response to send.  Otherwise,
+/// returns `None`, and the request should be handled normally.
+pub(crate) fn preflight_response<C: ServerContext>(
+    config: &ConfigCors,
+    router: &HttpRouter<C>,
+    request: &Request<Body>,
+) -> Option<Response<Body>> {
+    if request.method() != Method::OPTIONS {
+        return None;
+    }
+    let headers = request.headers();
+    config.allow_origin(headers.get(header::ORIGIN))?;
+    let requested_method =
+        headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| Method::from_bytes(value.as_bytes()).ok())?;
+    // Preflight requests needn't identify a version of the API, so a route in
+    // any version will do.
+    router
+        .lookup_route(&requested_method, request.uri().path().into(), None)
+        .ok()?;
+
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, requested_method.as_str())
+        .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS)
+        .body(Body::empty())
+        .unwrap();
+    if let Some(requested_headers) =
+        headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+    {
+        response.headers_mut().insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            requested_headers.clone(),
+        );
+    }
+    Some(response)
+}
+
+/// Adds the CORS headers that `config` calls for to the headers of a response
+/// to a request with the given `Origin`.
+pub(crate) fn add_response_headers(
+    config: &ConfigCors,
+    origin: Option<&HeaderValue>,
+    headers: &mut HeaderMap,
+) {
+    if let ConfigCors::SameSiteConsole { .. } = config {
+        // The response depends on the origin, so caches must not reuse it for
+        // other origins.
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    let Some(allow_origin) = config.allow_origin(origin) else {
+        return;
+    };
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    if let ConfigCors::SameSiteConsole { .. } = config {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigCors;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_config_cors_toml() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            #[serde(default)]
+            cors: ConfigCors,
+        }
+
+        let parse = |s: &str| toml::from_str::<Config>(s).unwrap().cors;
+        assert_eq!(parse(""), ConfigCors::Disabled);
+        assert_eq!(
+            parse("[cors]\nprofile = \"disabled\"\n"),
+            ConfigCors::Disabled
+        );
+        assert_eq!(
+            parse("[cors]\nprofile = \"public-api\"\n"),
+            ConfigCors::PublicApi
+        );
+        assert_eq!(
+            parse(
+                "[cors]\nprofile = \"same-site-console\"\n\
+                 allowed_origins = [\"https://console.example.com\"]\n"
+            ),
+            ConfigCors::SameSiteConsole {
+                allowed_origins: vec![String::from(
+                    "https://console.example.com"
+                )],
+            }
+        );
+        assert!(
+            toml::from_str::<Config>("[cors]\nprofile = \"bogus\"\n").is_err()
+        );
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let console = HeaderValue::from_static("https://console.example.com");
+        let other = HeaderValue::from_static("https://evil.example.com");
+
+        assert_eq!(ConfigCors::Disabled.allow_origin(Some(&console)), None);
+        assert_eq!(
+            ConfigCors::PublicApi.allow_origin(Some(&other)),
+            Some(HeaderValue::from_static("*"))
+        );
+        assert_eq!(ConfigCors::PublicApi.allow_origin(None), None);
+
+        let config = ConfigCors::SameSiteConsole {
+            allowed_origins: vec![String::from("https://console.example.com")],
+        };
+        assert_eq!(config.allow_origin(Some(&console)), Some(console.clone()));
+        assert_eq!(config.allow_origin(Some(&other)), None);
+        assert_eq!(config.allow_origin(None), None);
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2024 Oxide Computer Company
+//! Client-specified request deadlines
+//!
+//! Clients may say how long they're willing to wait for a response using the
+//! `Request-Timeout` header (a number of seconds, possibly fractional) or the
+//! `grpc-timeout` header (an integer followed by a unit, as in `"250m"` for
+//! 250 milliseconds).  If the server allows it (see
+//! [`crate::ConfigDropshot::request_timeout_max_ms`]), Dropshot turns these
+//! into a [`Deadline`] for the request, no later than the server's maximum
+//! allows.  Handlers that are still running when the deadline passes are
+//! cancelled and the client gets a 504 response, since it has presumably
+//! given up by then anyway.
+
+use crate::api_description::{ApiEndpointBodyContentType, ExtensionMode};
+use crate::error::HttpError;
+use crate::server::ServerContext;
+use crate::{ExtractorMetadata, RequestContext, SharedExtractor};
+use async_trait::async_trait;
+use http::header::HeaderMap;
+use http::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Header carrying a timeout in (possibly fractional) seconds
+pub const HEADER_REQUEST_TIMEOUT: &str = "request-timeout";
+/// Header carrying a timeout in the format used by gRPC
+pub const HEADER_GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// Point in time by which the client expects a response, if any
+///
+/// This is available to handlers as [`RequestContext::deadline`] or as an
+/// extractor.  A request with no valid timeout headers, or to a server that
+/// doesn't honor them, has a deadline that never expires.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Returns a deadline that never expires
+    pub fn none() -> Deadline {
+        Deadline(None)
+    }
+
+    /// Returns a deadline `timeout` from now
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline(Some(Instant::now() + timeout))
+    }
+
+    /// Returns the instant at which this deadline expires, if it does
+    pub fn instant(&self) -> Option<Instant> {
+        self.0
+    }
+
+    /// Returns how much time is left before this deadline expires, or `None`
+    /// if it never does
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0.map(|instant| instant.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns whether this deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.0.map_or(false, |instant| instant <= Instant::now())
+    }
+
+    /// Runs `future` to completion or until this deadline expires, whichever
+    /// comes first, failing with a 504 in the latter case
+    pub async fn bound<F, T>(&self, future: F) -> Result<T, HttpError>
+    where
+        F: Future<Output = T>,
+    {
+        match self.0 {
+            None => Ok(future.await),
+            Some(instant) => tokio::time::timeout_at(instant, future)
+                .await
+                .map_err(|_| deadline_exceeded()),
+        }
+    }
+
+    /// Computes the deadline for a request that's just arrived with the given
+    /// headers, on a server that allows timeouts of up to `max` (or none at
+    /// all, if that's `None`).  If several timeouts are specified, the
+    /// earliest one wins.  Values that can't be parsed are ignored, since
+    /// the request can be served just as well without them.
+    pub(crate) fn from_headers(
+        headers: &HeaderMap,
+        max: Option<Duration>,
+    ) -> Deadline {
+        let Some(max) = max else {
+            return Deadline::none();
+        };
+        let mut timeout: Option<Duration> = None;
+        let mut consider =
+            |header: &str, parse: fn(&str) -> Option<Duration>| {
+                for value in headers.get_all(header) {
+                    match value.to_str().ok().and_then(|v| parse(v.trim())) {
+                        Some(parsed) => {
+                            timeout =
+                                Some(timeout.map_or(parsed, |t| t.min(parsed)));
+                        }
+                        None => debug!(
+                            header,
+                            value = ?value,
+                            "ignoring invalid timeout header"
+                        ),
+                    }
+                }
+            };
+        consider(HEADER_REQUEST_TIMEOUT, parse_request_timeout);
+        consider(HEADER_GRPC_TIMEOUT, parse_grpc_timeout);
+        timeout.map_or_else(Deadline::none, |timeout| {
+            Deadline::after(timeout.min(max))
+        })
+    }
+}
+
+fn deadline_exceeded() -> HttpError {
+    let message = String::from("request deadline exceeded");
+    HttpError {
+        status_code: StatusCode::GATEWAY_TIMEOUT,
+        error_code: None,
+        internal_message: message.clone(),
+        external_message: message,
+    }
+}
+
+/// Parses a `Request-Timeout` value: a non-negative number of seconds
+fn parse_request_timeout(value: &str) -> Option<Duration> {
+    if value.is_empty()
+        || !value.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+    {
+        return None;
+    }
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// Parses a `grpc-timeout` value: up to 8 digits followed by a unit
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty()
+        || digits.len() > 8
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(n * 60 * 60)),
+        "M" => Some(Duration::from_secs(n * 60)),
+        "S" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_millis(n)),
+        "u" => Some(Duration::from_micros(n)),
+        "n" => Some(Duration::from_nanos(n)),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl SharedExtractor for Deadline {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+    ) -> Result<Deadline, HttpError> {
+        Ok(rqctx.deadline)
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        ExtractorMetadata {
+            parameters: vec![],
+            extension_mode: ExtensionMode::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_grpc_timeout;
+    use super::parse_request_timeout;
+    use super::Deadline;
+    use http::header::HeaderMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_timeouts() {
+        assert_eq!(parse_request_timeout("5"), Some(Duration::from_secs(5)));
+        assert_eq!(
+            parse_request_timeout("0.25"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_request_timeout(""), None);
+        assert_eq!(parse_request_timeout("-1"), None);
+        assert_eq!(parse_request_timeout("1e3"), None);
+        assert_eq!(parse_request_timeout("inf"), None);
+
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_grpc_timeout("3M"), Some(Duration::from_secs(180)));
+        assert_eq!(parse_grpc_timeout("10S"), Some(Duration::from_secs(10)));
+        assert_eq!(
+            parse_grpc_timeout("250m"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_grpc_timeout("7u"), Some(Duration::from_micros(7)));
+        assert_eq!(parse_grpc_timeout("9n"), Some(Duration::from_nanos(9)));
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("10s"), None);
+        assert_eq!(parse_grpc_timeout("1.5S"), None);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_from_headers() {
+        let max = Some(Duration::from_secs(10));
+        let deadline = Deadline::from_headers(&HeaderMap::new(), max);
+        assert_eq!(deadline, Deadline::none());
+        assert_eq!(deadline.remaining(), None);
+        assert!(!deadline.is_expired());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("request-timeout", "60".parse().unwrap());
+        headers.insert("grpc-timeout", "1S".parse().unwrap());
+        let deadline = Deadline::from_headers(&headers, max);
+        let remaining = deadline.remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(1));
+        assert!(remaining > Duration::from_millis(500));
+
+        // Timeouts are ignored unless the server allows them.
+        assert_eq!(Deadline::from_headers(&headers, None), Deadline::none());
+
+        // Timeouts longer than the server allows are cut short.
+        let mut headers = HeaderMap::new();
+        headers.insert("request-timeout", "60".parse().unwrap());
+        let remaining =
+            Deadline::from_headers(&headers, max).remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(10));
+        assert!(remaining > Duration::from_secs(9));
+
+        // Invalid values are ignored.
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-timeout", "soon".parse().unwrap());
+        assert_eq!(Deadline::from_headers(&headers, max), Deadline::none());
+        headers.insert("request-timeout", "2".parse().unwrap());
+        let remaining =
+            Deadline::from_headers(&headers, max).remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_bound() {
+        assert_eq!(Deadline::none().bound(async { 3 }).await.unwrap(), 3);
+
+        let deadline = Deadline::after(Duration::ZERO);
+        assert!(deadline.is_expired());
+        let error = deadline
+            .bound(tokio::time::sleep(Duration::from_secs(60)))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2024 Oxide Computer Company
+//! Built-in endpoints that help diagnose problems between clients and a
+//! Dropshot server
+
+use crate::api_description::ApiEndpoint;
+use crate::error::HttpError;
+use crate::handler::HttpResponseOk;
+use crate::handler::RequestContext;
+use crate::server::ServerContext;
+use crate::CONTENT_TYPE_JSON;
+use http::Method;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Headers whose values are never echoed back by
+/// [`request_echo_endpoint`], since responses may wind up pasted into bug
+/// reports
+const REDACTED_HEADERS: [&str; 3] =
+    ["authorization", "cookie", "proxy-authorization"];
+
+/// Description of an HTTP request as received by the server, returned by the
+/// endpoint created with [`request_echo_endpoint`]
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct RequestEcho {
+    /// unique id assigned to this request by the server
+    pub request_id: String,
+    /// HTTP method of the request
+    pub method: String,
+    /// request URI, as it appeared in the request line
+    pub uri: String,
+    /// HTTP protocol version of the request
+    pub version: String,
+    /// request headers (values of sensitive headers are redacted)
+    pub headers: BTreeMap<String, Vec<String>>,
+    /// address of the peer that sent the request
+    pub remote_addr: String,
+    /// address of the client on whose behalf the request was made (see
+    /// [`crate::ConfigDropshot::trusted_proxies`])
+    pub client_ip: String,
+    /// address on which the server received the request
+    pub local_addr: String,
+    /// whether the request was received over TLS
+    pub tls: bool,
+}
+
+/// Returns an endpoint that responds to `GET` requests for `path` by echoing
+/// back a description of the request as the server received it: the request
+/// line, headers, and information about the peer.
+///
+/// This endpoint is not registered by default.  Consumers that want it can
+/// register it (e.g., on an admin server, or only when some debug flag is
+/// enabled) so that support teams can ask users to hit a single, consistent
+/// URL when diagnosing problems with proxies or headers.  The endpoint is
+/// excluded from the OpenAPI document, and the values of headers that carry
+/// credentials (like `Authorization` and `Cookie`) are redacted.
+///
+/// ```
+/// use dropshot::request_echo_endpoint;
+/// use dropshot::ApiDescription;
+///
+/// let mut api = ApiDescription::<()>::new();
+/// api.register(request_echo_endpoint("/debug/echo")).unwrap();
+/// ```
+pub fn request_echo_endpoint<C: ServerContext>(path: &str) -> ApiEndpoint<C> {
+    ApiEndpoint::new(
+        String::from("request_echo"),
+        request_echo::<C>,
+        Method::GET,
+        CONTENT_TYPE_JSON,
+        path,
+    )
+    .summary("Describe the request as received by the server")
+    .visible(false)
+}
+
+async fn request_echo<C: ServerContext>(
+    rqctx: RequestContext<C>,
+) -> Result<HttpResponseOk<RequestEcho>, HttpError> {
+    let request = &rqctx.request;
+    let mut headers = BTreeMap::<String, Vec<String>>::new();
+    for (name, value) in request.headers() {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            String::from("<redacted>")
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        headers.entry(name.to_string()).or_default().push(value);
+    }
+
+    Ok(HttpResponseOk(RequestEcho {
+        request_id: rqctx.request_id.clone(),
+        method: request.method().to_string(),
+        uri: request.uri().to_string(),
+        version: format!("{:?}", request.version()),
+        headers,
+        remote_addr: request.remote_addr().to_string(),
+        client_ip: request.client_ip().to_string(),
+        local_addr: rqctx.server.local_addr.to_string(),
+        tls: rqctx.server.using_tls(),
+    }))
+}
+
+/// Returns an endpoint that responds to `GET` requests for `path` with a
+/// description of each Dropshot server running in this process (see
+/// [`crate::running_servers`])
+///
+/// Like [`request_echo_endpoint`], this is not registered by default, and it's
+/// excluded from the OpenAPI document.  It's intended for admin servers.
+#[cfg(feature = "server-registry")]
+pub fn running_servers_endpoint<C: ServerContext>(
+    path: &str,
+) -> ApiEndpoint<C> {
+    ApiEndpoint::new(
+        String::from("running_servers"),
+        running_servers::<C>,
+        Method::GET,
+        CONTENT_TYPE_JSON,
+        path,
+    )
+    .summary("Describe the Dropshot servers running in this process")
+    .visible(false)
+}
+
+#[cfg(feature = "server-registry")]
+async fn running_servers<C: ServerContext>(
+    _rqctx: RequestContext<C>,
+) -> Result<HttpResponseOk<Vec<crate::RunningServer>>, HttpError> {
+    Ok(HttpResponseOk(crate::running_servers()))
+}
@@ -43,12 +43,18 @@
 //! way.  Consumers can provide a `From` implementation that converts these
 //! errors into HttpErrors.
 
+use crate::handler::RequestInfo;
+use crate::http_util::negotiate_media_type;
+use crate::http_util::CONTENT_TYPE_JSON;
+use crate::http_util::CONTENT_TYPE_PROBLEM_JSON;
+use crate::http_util::CONTENT_TYPE_TEXT_PLAIN;
 use hyper::Error as HyperError;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 /// `HttpError` represents an error generated as part of handling an API
 /// request.  When these bubble up to the top of the request handling stack
@@ -107,6 +113,27 @@ pub struct HttpErrorResponseBody {
     pub message: String,
 }
 
+/// Function used to produce the response for every [`HttpError`] a server
+/// returns, given the error and a description of the request.  See
+/// [`crate::ApiDescription::map_error`].
+pub type ErrorMapper = Arc<
+    dyn Fn(HttpError, &RequestInfo) -> hyper::Response<hyper::Body>
+        + Send
+        + Sync,
+>;
+
+/// Selects the format of the bodies of error responses generated from
+/// [`HttpError`]s.  See [`crate::ApiDescription::error_response_format`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ErrorResponseFormat {
+    /// `application/json` objects described by [`HttpErrorResponseBody`]
+    #[default]
+    Dropshot,
+    /// RFC 9457 "problem details" objects (`application/problem+json`)
+    /// described by [`ProblemDetails`]
+    ProblemDetails,
+}
+
 // We hand-roll our JSON schema to avoid `error_code` being "nullable".
 impl JsonSchema for HttpErrorResponseBody {
     fn schema_name() -> String {
@@ -302,6 +329,202 @@ impl HttpError {
             )
             .unwrap()
     }
+
+    /// Generates an HTTP response for the given `HttpError` like
+    /// [`HttpError::into_response`], but renders the body in whichever format
+    /// the client prefers according to its `Accept` header (`accept`):
+    ///
+    /// * `application/json` (the usual [`HttpErrorResponseBody`]),
+    /// * `application/problem+json` (an RFC 9457 problem details object, with
+    ///   `request_id` and `error_code` as extension members), or
+    /// * `text/plain` (a short, human-readable summary).
+    ///
+    /// JSON is used if the client expresses no preference or accepts none of
+    /// these.  Servers do this for all errors when
+    /// [`crate::ConfigDropshot::error_content_negotiation`] is enabled.
+    pub fn into_negotiated_response(
+        self,
+        request_id: &str,
+        accept: Option<&http::HeaderValue>,
+    ) -> hyper::Response<hyper::Body> {
+        self.into_negotiated_response_with(
+            request_id,
+            accept,
+            ErrorResponseFormat::Dropshot,
+            None,
+        )
+    }
+
+    /// Generates an `application/problem+json` response (RFC 9457) for the
+    /// given `HttpError`.  `instance`, if given, identifies this occurrence of
+    /// the problem (servers use the request path).  Servers do this for all
+    /// errors when the API's format is [`ErrorResponseFormat::ProblemDetails`].
+    pub fn into_problem_response(
+        self,
+        request_id: &str,
+        instance: Option<&str>,
+    ) -> hyper::Response<hyper::Body> {
+        let body = self.problem_details_body(request_id, instance);
+        hyper::Response::builder()
+            .status(self.status_code)
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE_PROBLEM_JSON)
+            .header(super::http_util::HEADER_REQUEST_ID, request_id)
+            .body(body.into())
+            .unwrap()
+    }
+
+    /// Like [`HttpError::into_negotiated_response`], but falls back to
+    /// `default_format` when the client has no preference
+    pub(crate) fn into_negotiated_response_with(
+        self,
+        request_id: &str,
+        accept: Option<&http::HeaderValue>,
+        default_format: ErrorResponseFormat,
+        instance: Option<&str>,
+    ) -> hyper::Response<hyper::Body> {
+        let offered = match default_format {
+            ErrorResponseFormat::Dropshot => [
+                CONTENT_TYPE_JSON,
+                CONTENT_TYPE_PROBLEM_JSON,
+                CONTENT_TYPE_TEXT_PLAIN,
+            ],
+            ErrorResponseFormat::ProblemDetails => [
+                CONTENT_TYPE_PROBLEM_JSON,
+                CONTENT_TYPE_JSON,
+                CONTENT_TYPE_TEXT_PLAIN,
+            ],
+        };
+        let content_type = negotiate_media_type(accept, &offered);
+        let body = match content_type {
+            Some(CONTENT_TYPE_PROBLEM_JSON) => {
+                self.problem_details_body(request_id, instance)
+            }
+            Some(CONTENT_TYPE_TEXT_PLAIN) => {
+                let mut text = format!(
+                    "{}: {}\nrequest id: {}\n",
+                    self.status_code, self.external_message, request_id
+                );
+                if let Some(error_code) = &self.error_code {
+                    text.push_str(&format!("error code: {}\n", error_code));
+                }
+                text
+            }
+            Some(CONTENT_TYPE_JSON) => return self.into_response(request_id),
+            _ => match default_format {
+                ErrorResponseFormat::Dropshot => {
+                    return self.into_response(request_id)
+                }
+                ErrorResponseFormat::ProblemDetails => {
+                    return self.into_problem_response(request_id, instance)
+                }
+            },
+        };
+
+        hyper::Response::builder()
+            .status(self.status_code)
+            .header(http::header::CONTENT_TYPE, content_type.unwrap())
+            .header(super::http_util::HEADER_REQUEST_ID, request_id)
+            .body(body.into())
+            .unwrap()
+    }
+
+    fn problem_details_body(
+        &self,
+        request_id: &str,
+        instance: Option<&str>,
+    ) -> String {
+        serde_json::to_string_pretty(&ProblemDetails {
+            problem_type: String::from("about:blank"),
+            title: self.status_code.canonical_reason().map(String::from),
+            status: self.status_code.as_u16(),
+            detail: self.external_message.clone(),
+            instance: instance.map(String::from),
+            request_id: request_id.to_string(),
+            error_code: self.error_code.clone(),
+        })
+        .unwrap()
+    }
+}
+
+/// Body of an `application/problem+json` error response (RFC 9457), as
+/// produced for APIs using [`ErrorResponseFormat::ProblemDetails`].  Like
+/// [`HttpErrorResponseBody`], this type can be used to deserialize such a
+/// response in order to access the error code, detail, etc.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProblemDetails {
+    /// URI reference identifying the problem type
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// short summary of the problem type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// HTTP status code
+    pub status: u16,
+    /// explanation specific to this occurrence of the problem
+    pub detail: String,
+    /// URI reference identifying this occurrence of the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub request_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+// As with `HttpErrorResponseBody`, the schema is hand-rolled so that optional
+// members aren't "nullable".
+impl JsonSchema for ProblemDetails {
+    fn schema_name() -> String {
+        "ProblemDetails".to_string()
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        let str_schema = String::json_schema(gen);
+        let int_schema = u16::json_schema(gen);
+
+        schemars::schema::SchemaObject {
+            metadata: Some(
+                schemars::schema::Metadata {
+                    description: Some(
+                        "Error information from a response, as RFC 9457 \
+                         problem details."
+                            .into(),
+                    ),
+                    ..Default::default()
+                }
+                .into(),
+            ),
+            instance_type: Some(schemars::schema::InstanceType::Object.into()),
+            object: Some(
+                schemars::schema::ObjectValidation {
+                    required: [
+                        "detail".into(),
+                        "request_id".into(),
+                        "status".into(),
+                        "type".into(),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    properties: [
+                        ("detail".into(), str_schema.clone()),
+                        ("error_code".into(), str_schema.clone()),
+                        ("instance".into(), str_schema.clone()),
+                        ("request_id".into(), str_schema.clone()),
+                        ("status".into(), int_schema),
+                        ("title".into(), str_schema.clone()),
+                        ("type".into(), str_schema),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    ..Default::default()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        }
+        .into()
+    }
 }
 
 impl fmt::Display for HttpError {
@@ -318,6 +541,9 @@ impl Error for HttpError {
 
 #[cfg(test)]
 mod test {
+    use super::ErrorResponseFormat;
+    use super::ProblemDetails;
+    use crate::HttpError;
     use crate::HttpErrorResponseBody;
 
     #[test]
@@ -341,4 +567,119 @@ mod test {
             r#"{"request_id":"123","error_code":"err","message":"oy!"}"#
         );
     }
+
+    async fn negotiated(
+        accept: Option<&'static str>,
+    ) -> (String, serde_json::Value, String) {
+        let error = HttpError::for_client_error(
+            Some(String::from("Teapot")),
+            http::StatusCode::IM_A_TEAPOT,
+            String::from("short and stout"),
+        );
+        let accept = accept.map(http::HeaderValue::from_static);
+        let response = error.into_negotiated_response("123", accept.as_ref());
+        assert_eq!(response.status(), http::StatusCode::IM_A_TEAPOT);
+        assert_eq!(response.headers()[crate::HEADER_REQUEST_ID], "123");
+        let content_type = response.headers()[http::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let json = serde_json::from_str(&body).unwrap_or_default();
+        (content_type, json, body)
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_error_response() {
+        for accept in [None, Some("*/*"), Some("application/json")] {
+            let (content_type, json, _) = negotiated(accept).await;
+            assert_eq!(content_type, "application/json");
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "request_id": "123",
+                    "error_code": "Teapot",
+                    "message": "short and stout",
+                })
+            );
+        }
+
+        let (content_type, json, _) =
+            negotiated(Some("application/problem+json")).await;
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "I'm a teapot",
+                "status": 418,
+                "detail": "short and stout",
+                "request_id": "123",
+                "error_code": "Teapot",
+            })
+        );
+
+        let (content_type, _, body) = negotiated(Some("text/plain")).await;
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(
+            body,
+            "418 I'm a teapot: short and stout\n\
+             request id: 123\n\
+             error code: Teapot\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_problem_details_response() {
+        let error = HttpError::for_not_found(None, String::from("gone"));
+        let response = error.into_problem_response("123", Some("/things/1"));
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Not Found",
+                "instance": "/things/1",
+                "request_id": "123",
+            })
+        );
+
+        let parsed: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(parsed.detail, "Not Found");
+        assert_eq!(parsed.request_id, "123");
+        assert_eq!(parsed.problem_type, "about:blank");
+        assert_eq!(parsed.status, 404);
+        assert_eq!(parsed.instance.as_deref(), Some("/things/1"));
+
+        // With negotiation, problem details become the default, but clients
+        // can still ask for the usual JSON.
+        for (accept, expected) in [
+            (None, "application/problem+json"),
+            (Some("*/*"), "application/problem+json"),
+            (Some("application/json"), "application/json"),
+            (Some("image/png"), "application/problem+json"),
+        ] {
+            let error = HttpError::for_not_found(None, String::from("gone"));
+            let accept = accept.map(http::HeaderValue::from_static);
+            let response = error.into_negotiated_response_with(
+                "123",
+                accept.as_ref(),
+                ErrorResponseFormat::ProblemDetails,
+                None,
+            );
+            assert_eq!(
+                response.headers()[http::header::CONTENT_TYPE],
+                expected
+            );
+        }
+    }
 }
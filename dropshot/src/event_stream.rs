@@ -0,0 +1,393 @@
+// Copyright 2024 Oxide Computer Company
+//! Streams of typed events, served over a websocket or as server-sent events
+//!
+//! Websockets suit clients that want a stream of events from the server, but
+//! not every client can use them: proxies between a client and the server may
+//! not pass upgrades through.  An endpoint that takes an
+//! [`EventStreamUpgrade`] serves the same stream of events over a websocket to
+//! clients that ask for one, and as server-sent events (SSE) to the rest.
+//! Either way, each event is sent as its JSON serialization: as a text message
+//! on the websocket, or as the data of an SSE event.
+//!
+//! The endpoint's OpenAPI definition documents the event type as the schema of
+//! its `text/event-stream` response, and marks the operation with the
+//! `x-dropshot-event-stream` extension, so that clients know that they can
+//! upgrade the request to a websocket instead.
+
+use crate::api_description::ApiEndpointBodyContentType;
+use crate::api_description::ApiEndpointResponse;
+use crate::api_description::ApiSchemaGenerator;
+use crate::api_description::ExtensionMode;
+use crate::handler::HttpHandlerResult;
+use crate::handler::HttpResponse;
+use crate::schema_util::make_subschema_for;
+use crate::server::{ServerContext, ServerPhase};
+use crate::streamed_body::StreamedBody;
+use crate::websocket::close_frame;
+use crate::websocket::WebsocketConnection;
+use crate::websocket::WebsocketUpgrade;
+use crate::websocket_queue::server_frame;
+use crate::websocket_queue::OPCODE_TEXT;
+use crate::ExclusiveExtractor;
+use crate::ExtractorMetadata;
+use crate::HttpError;
+use crate::RequestContext;
+use crate::WebsocketChannelResult;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use futures::StreamExt;
+use http::header;
+use http::Response;
+use http::StatusCode;
+use hyper::Body;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+
+/// MIME type for server-sent events
+pub const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+/// OpenAPI extension marking operations that serve an [`EventStream`]
+pub(crate) const EVENT_STREAM_EXTENSION: &str = "x-dropshot-event-stream";
+
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// How long to wait for the client to answer the server's Close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An `ExclusiveExtractor` for endpoints that stream events to the client,
+/// over a websocket if the client asks to upgrade the request, or as
+/// server-sent events otherwise
+///
+/// The endpoint returns what [`EventStreamUpgrade::stream`] does.
+///
+/// ```
+/// use dropshot::endpoint;
+/// use dropshot::EventStream;
+/// use dropshot::EventStreamUpgrade;
+/// use dropshot::HttpError;
+/// use dropshot::RequestContext;
+/// use schemars::JsonSchema;
+/// use serde::Serialize;
+///
+/// #[derive(JsonSchema, Serialize)]
+/// struct Tick {
+///     count: u64,
+/// }
+///
+/// #[endpoint {
+///     method = GET,
+///     path = "/ticks",
+/// }]
+/// async fn get_ticks(
+///     _rqctx: RequestContext<()>,
+///     events: EventStreamUpgrade,
+/// ) -> Result<EventStream<Tick>, HttpError> {
+///     let ticks = (0..10).map(|count| Tick { count });
+///     events.stream(futures::stream::iter(ticks))
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EventStreamUpgrade(Transport);
+
+#[derive(Debug)]
+enum Transport {
+    Websocket(Box<WebsocketUpgrade>),
+    Sse { shutdown: watch::Receiver<ServerPhase> },
+}
+
+/// An endpoint's response streaming events of type `T` to the client, as
+/// produced by [`EventStreamUpgrade::stream`]
+pub struct EventStream<T> {
+    response: Response<Body>,
+    event: PhantomData<fn() -> T>,
+}
+
+#[async_trait]
+impl ExclusiveExtractor for EventStreamUpgrade {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<Self, HttpError> {
+        let upgrade = request
+            .headers()
+            .get(header::UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value
+                    .split(|c| c == ',' || c == ' ')
+                    .any(|v| v.eq_ignore_ascii_case("websocket"))
+            });
+        if upgrade {
+            let websocket =
+                WebsocketUpgrade::from_request(rqctx, request).await?;
+            Ok(EventStreamUpgrade(Transport::Websocket(Box::new(websocket))))
+        } else {
+            Ok(EventStreamUpgrade(Transport::Sse {
+                shutdown: rqctx.server.phase.subscribe(),
+            }))
+        }
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        ExtractorMetadata {
+            parameters: vec![],
+            extension_mode: ExtensionMode::EventStream,
+        }
+    }
+}
+
+impl EventStreamUpgrade {
+    /// Returns whether the client asked for the events over a websocket
+    pub fn is_websocket(&self) -> bool {
+        matches!(self.0, Transport::Websocket(_))
+    }
+
+    /// Streams `events` to the client, finishing when it runs out
+    ///
+    /// Over a websocket, the connection is then closed, and the client's
+    /// messages (other than pings and Close frames) are ignored.  As server-sent
+    /// events, the events have no names or ids.  Either way, the stream is cut
+    /// off when the server begins shutting down.
+    pub fn stream<T, S>(self, events: S) -> Result<EventStream<T>, HttpError>
+    where
+        T: Serialize + Send + 'static,
+        S: Stream<Item = T> + Send + 'static,
+    {
+        let response = match self.0 {
+            Transport::Websocket(websocket) => websocket
+                .handle(move |conn| websocket_events(conn, events.boxed()))?,
+            Transport::Sse { shutdown } => {
+                let chunks = events.map(|event| {
+                    serde_json::to_string(&event)
+                        .map(|data| Bytes::from(format!("data: {}\n\n", data)))
+                });
+                StreamedBody::new(chunks)
+                    .with_shutdown(shutdown)
+                    .into_response(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(
+                                header::CONTENT_TYPE,
+                                CONTENT_TYPE_EVENT_STREAM,
+                            )
+                            .header(header::CACHE_CONTROL, "no-cache"),
+                    )?
+            }
+        };
+        Ok(EventStream { response, event: PhantomData })
+    }
+}
+
+impl<T: JsonSchema + 'static> HttpResponse for EventStream<T> {
+    fn to_result(self) -> HttpHandlerResult {
+        Ok(self.response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        ApiEndpointResponse {
+            schema: Some(ApiSchemaGenerator::Gen {
+                name: T::schema_name,
+                schema: make_subschema_for::<T>,
+            }),
+            success: Some(StatusCode::OK),
+            description: Some(String::from("stream of events")),
+            content_types: vec![CONTENT_TYPE_EVENT_STREAM.to_string()],
+            ..Default::default()
+        }
+    }
+}
+
+/// Sends `events` as text messages on a websocket, answering the client's
+/// pings and Close frame along the way
+async fn websocket_events<T: Serialize>(
+    conn: WebsocketConnection,
+    mut events: futures::stream::BoxStream<'static, T>,
+) -> WebsocketChannelResult {
+    let shutdown = conn.shutdown_signal();
+    let mut raw = conn.into_inner();
+    let mut incoming = IncomingFrames::default();
+    let mut buf = [0; 4096];
+    let close = loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => {
+                    let data = match serde_json::to_string(&event) {
+                        Ok(data) => data,
+                        Err(error) => {
+                            let reason = error.to_string();
+                            raw.write_all(&close_frame(
+                                CLOSE_INTERNAL_ERROR,
+                                &reason,
+                            ))
+                            .await?;
+                            return Err(error.into());
+                        }
+                    };
+                    raw.write_all(&server_frame(OPCODE_TEXT, data.as_bytes()))
+                        .await?;
+                }
+                None => break close_frame(CLOSE_NORMAL, ""),
+            },
+            n = raw.read(&mut buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                incoming.push(&buf[..n]);
+                while let Some((opcode, payload)) = incoming.next_control() {
+                    match opcode {
+                        OPCODE_PING => {
+                            raw.write_all(&server_frame(OPCODE_PONG, &payload))
+                                .await?;
+                        }
+                        // The client has begun closing the connection, so
+                        // echo its status code and finish.
+                        OPCODE_CLOSE => {
+                            let frame = server_frame(
+                                OPCODE_CLOSE,
+                                &payload[..payload.len().min(2)],
+                            );
+                            raw.write_all(&frame).await?;
+                            return Ok(());
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            () = shutdown.wait() => {
+                break close_frame(CLOSE_GOING_AWAY, "server shutting down");
+            }
+        }
+    };
+
+    // Begin closing the connection, and wait (for a while) for the client to
+    // finish closing it.
+    raw.write_all(&close).await?;
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+        loop {
+            match raw.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    incoming.push(&buf[..n]);
+                    while let Some((opcode, _)) = incoming.next_control() {
+                        if opcode == OPCODE_CLOSE {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Parses the frames that a client sends, keeping only the control frames
+#[derive(Default)]
+struct IncomingFrames {
+    buf: Vec<u8>,
+    /// how much of the current data frame's payload is yet to be skipped
+    skip: u64,
+}
+
+impl IncomingFrames {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the opcode and (unmasked) payload of the next control frame,
+    /// if a whole one has been received
+    fn next_control(&mut self) -> Option<(u8, Vec<u8>)> {
+        loop {
+            if self.skip > 0 {
+                let n = usize::try_from(self.skip)
+                    .unwrap_or(usize::MAX)
+                    .min(self.buf.len());
+                self.buf.drain(..n);
+                self.skip -= n as u64;
+                if self.skip > 0 {
+                    return None;
+                }
+            }
+
+            let header = self.buf.get(..2)?;
+            let opcode = header[0] & 0x0f;
+            let masked = header[1] & 0x80 != 0;
+            let (len_bytes, short_len) = match header[1] & 0x7f {
+                126 => (2, None),
+                127 => (8, None),
+                len => (0, Some(u64::from(len))),
+            };
+            let mask_start = 2 + len_bytes;
+            let payload_start = mask_start + if masked { 4 } else { 0 };
+            if self.buf.len() < payload_start {
+                return None;
+            }
+            let len = short_len.unwrap_or_else(|| {
+                self.buf[2..mask_start]
+                    .iter()
+                    .fold(0, |len, byte| (len << 8) | u64::from(*byte))
+            });
+
+            if opcode & 0x8 == 0 {
+                self.buf.drain(..payload_start);
+                self.skip = len;
+                continue;
+            }
+
+            // Control frames have at most 125 bytes of payload.
+            let payload_end = payload_start + len.min(125) as usize;
+            if self.buf.len() < payload_end {
+                return None;
+            }
+            let mut payload = self.buf[payload_start..payload_end].to_vec();
+            if masked {
+                let mask = &self.buf[mask_start..payload_start];
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[i % 4];
+                }
+            }
+            self.buf.drain(..payload_end);
+            return Some((opcode, payload));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IncomingFrames;
+
+    #[test]
+    fn test_incoming_frames() {
+        let mut incoming = IncomingFrames::default();
+
+        // A masked text frame with a 16-bit length, split partway through its
+        // payload, then a masked ping.
+        let mut bytes = vec![0x81, 0xfe, 0x01, 0x00, 1, 2, 3, 4];
+        bytes.extend(std::iter::repeat(0).take(0x100));
+        bytes.extend([0x89, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2]);
+        incoming.push(&bytes[..100]);
+        assert_eq!(incoming.next_control(), None);
+        incoming.push(&bytes[100..bytes.len() - 1]);
+        assert_eq!(incoming.next_control(), None);
+        incoming.push(&bytes[bytes.len() - 1..]);
+        assert_eq!(incoming.next_control(), Some((0x9, b"hi".to_vec())));
+        assert_eq!(incoming.next_control(), None);
+
+        // An unmasked Close frame with a status code.
+        incoming.push(&[0x88, 0x02, 0x03, 0xe8]);
+        assert_eq!(incoming.next_control(), Some((0x8, vec![0x03, 0xe8])));
+    }
+}
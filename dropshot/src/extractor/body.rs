@@ -10,6 +10,7 @@ use crate::http_util::http_dump_body;
 use crate::http_util::CONTENT_TYPE_JSON;
 use crate::schema_util::make_subschema_for;
 use crate::server::ServerContext;
+use crate::versioning::ReplacesOlderVersion;
 use crate::ExclusiveExtractor;
 use crate::ExtractorMetadata;
 use crate::RequestContext;
@@ -26,6 +27,12 @@ use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use std::convert::Infallible;
 use std::fmt::Debug;
+use std::io::SeekFrom;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncSeekExt;
+use tokio::io::AsyncWriteExt;
 
 // TypedBody: body extractor for formats that can be deserialized to a specific
 // type.  Only JSON is currently supported.
@@ -55,6 +62,8 @@ pub struct MultipartBody {
 
 #[async_trait]
 impl ExclusiveExtractor for MultipartBody {
+    const STREAMS_BODY: bool = true;
+
     async fn from_request<Context: ServerContext>(
         _rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
@@ -122,14 +131,13 @@ impl ExclusiveExtractor for MultipartBody {
 async fn http_request_load_body<Context: ServerContext, BodyType>(
     rqctx: &RequestContext<Context>,
     request: hyper::Request<hyper::Body>,
-) -> Result<TypedBody<BodyType>, HttpError>
+) -> Result<BodyType, HttpError>
 where
-    BodyType: JsonSchema + DeserializeOwned + Send + Sync,
+    BodyType: DeserializeOwned + Send + Sync,
 {
-    let server = &rqctx.server;
     let (parts, body) = request.into_parts();
-    let body = StreamingBody::new(body, server.config.request_body_max_bytes)
-        .into_bytes_mut()
+    let body = StreamingBody::new(body, rqctx.request_body_max_bytes)
+        .into_bytes()
         .await?;
 
     // RFC 7231 §3.1.1.1: media types are case insensitive and may
@@ -188,7 +196,7 @@ where
             ))
         }
     };
-    Ok(TypedBody { inner: content })
+    Ok(content)
 }
 
 // The `ExclusiveExtractor` implementation for TypedBody<BodyType> describes how
@@ -206,7 +214,7 @@ where
         rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
     ) -> Result<TypedBody<BodyType>, HttpError> {
-        http_request_load_body(rqctx, request).await
+        Ok(TypedBody { inner: http_request_load_body(rqctx, request).await? })
     }
 
     fn metadata(content_type: ApiEndpointBodyContentType) -> ExtractorMetadata {
@@ -226,10 +234,64 @@ where
     }
 }
 
+// VersionedBody: body extractor for types whose form changed in some version of
+// the API.
+
+/// `VersionedBody<Old, New>` is an extractor like [`TypedBody`] for request
+/// bodies whose type took a new form, `New`, in some version of the API
+/// (see [`ReplacesOlderVersion`]).  Requests for earlier versions send the body
+/// in its older form, `Old`, which is converted into `New`, so the handler
+/// only deals with the latest form.
+///
+/// The body is documented with the schema of `New`.
+#[derive(Debug)]
+pub struct VersionedBody<Old, New> {
+    inner: New,
+    _old: PhantomData<fn() -> Old>,
+}
+
+impl<Old, New> VersionedBody<Old, New> {
+    pub fn into_inner(self) -> New {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<Old, New> ExclusiveExtractor for VersionedBody<Old, New>
+where
+    Old: DeserializeOwned + Send + Sync + 'static,
+    New: ReplacesOlderVersion<Old>
+        + JsonSchema
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<VersionedBody<Old, New>, HttpError> {
+        let inner = if New::uses_older_form(rqctx.api_version.as_ref()) {
+            New::from(http_request_load_body::<_, Old>(rqctx, request).await?)
+        } else {
+            http_request_load_body::<_, New>(rqctx, request).await?
+        };
+        Ok(VersionedBody { inner, _old: PhantomData })
+    }
+
+    fn metadata(content_type: ApiEndpointBodyContentType) -> ExtractorMetadata {
+        TypedBody::<New>::metadata(content_type)
+    }
+}
+
 // UntypedBody: body extractor for a plain array of bytes of a body.
 
 /// `UntypedBody` is an extractor for reading in the contents of the HTTP request
 /// body and making the raw bytes directly available to the consumer.
+///
+/// The whole body is buffered in memory, so it's subject to the endpoint's
+/// [`request_body_max_bytes`](crate::ApiEndpoint::request_body_max_bytes)
+/// limit.  See [`SpooledBody`] for bodies that may be too large for that.
 #[derive(Debug)]
 pub struct UntypedBody {
     content: Bytes,
@@ -242,6 +304,12 @@ impl UntypedBody {
         &self.content
     }
 
+    /// Consumes `self`, returning the underlying body content without copying
+    /// it.
+    pub fn into_bytes(self) -> Bytes {
+        self.content
+    }
+
     /// Convenience wrapper to convert the body to a UTF-8 string slice,
     /// returning a 400-level error if the body is not valid UTF-8.
     pub fn as_str(&self) -> Result<&str, HttpError> {
@@ -260,13 +328,141 @@ impl ExclusiveExtractor for UntypedBody {
         rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
     ) -> Result<UntypedBody, HttpError> {
-        let server = &rqctx.server;
         let body = request.into_body();
-        let body_bytes =
-            StreamingBody::new(body, server.config.request_body_max_bytes)
-                .into_bytes_mut()
-                .await?;
-        Ok(UntypedBody { content: body_bytes.freeze() })
+        let content = StreamingBody::new(body, rqctx.request_body_max_bytes)
+            .into_bytes()
+            .await?;
+        Ok(UntypedBody { content })
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        untyped_metadata()
+    }
+}
+
+// SpooledBody: body extractor that buffers small bodies in memory and larger
+// ones in a temporary file.
+
+/// `SpooledBody` is an extractor for reading in the whole HTTP request body
+/// when it may be too large to comfortably buffer in memory.
+///
+/// Bodies up to the server-wide
+/// [`request_body_max_bytes`](crate::ConfigDropshot::request_body_max_bytes)
+/// are kept in memory.  Larger bodies are written to an anonymous temporary
+/// file, which is removed once the `SpooledBody` is dropped.  To accept such
+/// bodies at all, the endpoint must raise its own limit with
+/// [`ApiEndpoint::request_body_max_bytes`](crate::ApiEndpoint::request_body_max_bytes)
+/// (or the `request_body_max_bytes` endpoint attribute).
+#[derive(Debug)]
+pub struct SpooledBody {
+    content: SpooledContent,
+    len: u64,
+}
+
+#[derive(Debug)]
+enum SpooledContent {
+    Memory(Bytes),
+    File(tokio::fs::File),
+}
+
+impl SpooledBody {
+    /// Returns the size of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the body was too large to keep in memory and was
+    /// written to a temporary file instead.
+    pub fn is_spooled(&self) -> bool {
+        matches!(self.content, SpooledContent::File(_))
+    }
+
+    /// Consumes `self`, returning a reader positioned at the start of the
+    /// body.
+    pub fn into_reader(self) -> Pin<Box<dyn AsyncRead + Send + Sync>> {
+        match self.content {
+            SpooledContent::Memory(bytes) => {
+                Box::pin(std::io::Cursor::new(bytes))
+            }
+            SpooledContent::File(file) => Box::pin(file),
+        }
+    }
+
+    async fn spool(
+        body: StreamingBody,
+        memory_max_bytes: usize,
+    ) -> Result<SpooledBody, HttpError> {
+        let stream = body.into_stream();
+        tokio::pin!(stream);
+
+        let mut buffer = BytesMut::new();
+        let mut file: Option<tokio::fs::File> = None;
+        let mut len: u64 = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            len += chunk.len() as u64;
+            if let Some(file) = &mut file {
+                file.write_all(&chunk).await.map_err(spool_error)?;
+            } else if buffer.len() + chunk.len() <= memory_max_bytes {
+                buffer.put(chunk);
+            } else {
+                let mut new_file =
+                    tokio::task::spawn_blocking(tempfile::tempfile)
+                        .await
+                        .map_err(|e| {
+                            HttpError::for_internal_error(format!(
+                        "failed to create temporary file for request body: {}",
+                        e
+                    ))
+                        })?
+                        .map(tokio::fs::File::from_std)
+                        .map_err(spool_error)?;
+                new_file.write_all(&buffer).await.map_err(spool_error)?;
+                new_file.write_all(&chunk).await.map_err(spool_error)?;
+                buffer = BytesMut::new();
+                file = Some(new_file);
+            }
+        }
+
+        let content = match file {
+            None => SpooledContent::Memory(buffer.freeze()),
+            Some(mut file) => {
+                file.flush().await.map_err(spool_error)?;
+                file.seek(SeekFrom::Start(0)).await.map_err(spool_error)?;
+                SpooledContent::File(file)
+            }
+        };
+        Ok(SpooledBody { content, len })
+    }
+}
+
+fn spool_error(error: std::io::Error) -> HttpError {
+    HttpError::for_internal_error(format!(
+        "failed to write request body to temporary file: {}",
+        error
+    ))
+}
+
+#[async_trait]
+impl ExclusiveExtractor for SpooledBody {
+    const STREAMS_BODY: bool = true;
+
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<SpooledBody, HttpError> {
+        let body = request.into_body();
+        SpooledBody::spool(
+            StreamingBody::new(body, rqctx.request_body_max_bytes),
+            rqctx.server.config.request_body_max_bytes,
+        )
+        .await
     }
 
     fn metadata(
@@ -287,7 +483,7 @@ pub struct StreamingBody {
 }
 
 impl StreamingBody {
-    fn new(body: hyper::Body, cap: usize) -> Self {
+    pub(crate) fn new(body: hyper::Body, cap: usize) -> Self {
         Self { body, cap }
     }
 
@@ -309,7 +505,8 @@ impl StreamingBody {
     /// The stream produces an [`HttpError`] if any of the following cases occur:
     ///
     /// * A network error occurred.
-    /// * `request_body_max_bytes` was exceeded for this request.
+    /// * `request_body_max_bytes` (for the server, or as overridden by the
+    ///   endpoint) was exceeded for this request.
     ///
     /// # Examples
     ///
@@ -400,30 +597,46 @@ impl StreamingBody {
         }
     }
 
-    /// Converts `self` into a [`BytesMut`], buffering the entire response in
-    /// memory. Not public API because most users of this should use
+    /// Converts `self` into [`Bytes`], buffering the entire response in
+    /// memory.  A body that arrives in a single chunk is returned without
+    /// being copied.  Not public API because most users of this should use
     /// `UntypedBody` instead.
-    async fn into_bytes_mut(self) -> Result<BytesMut, HttpError> {
-        self.into_stream()
-            .try_fold(BytesMut::new(), |mut out, chunk| {
+    pub(crate) async fn into_bytes(self) -> Result<Bytes, HttpError> {
+        let stream = self.into_stream();
+        tokio::pin!(stream);
+
+        let first = match stream.try_next().await? {
+            Some(chunk) => chunk,
+            None => return Ok(Bytes::new()),
+        };
+        let Some(second) = stream.try_next().await? else {
+            return Ok(first);
+        };
+
+        let mut out = BytesMut::new();
+        out.put(first);
+        out.put(second);
+        stream
+            .try_fold(out, |mut out, chunk| {
                 out.put(chunk);
                 futures::future::ok(out)
             })
             .await
+            .map(BytesMut::freeze)
     }
 }
 
 #[async_trait]
 impl ExclusiveExtractor for StreamingBody {
+    const STREAMS_BODY: bool = true;
+
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
     ) -> Result<Self, HttpError> {
-        let server = &rqctx.server;
-
         Ok(Self {
             body: request.into_body(),
-            cap: server.config.request_body_max_bytes,
+            cap: rqctx.request_body_max_bytes,
         })
     }
 
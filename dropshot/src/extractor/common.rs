@@ -22,6 +22,11 @@ pub struct ExtractorMetadata {
 /// be at most one of these associated with any request.
 #[async_trait]
 pub trait ExclusiveExtractor: Send + Sync + Sized {
+    /// Whether this extractor hands the request body to the handler as it
+    /// arrives, rather than reading all of it first, so that middleware
+    /// shouldn't buffer it either (see [`crate::IdempotencyMiddleware`])
+    const STREAMS_BODY: bool = false;
+
     /// Construct an instance of this type from a `RequestContext`.
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
@@ -93,6 +98,10 @@ impl<S: SharedExtractor> ExclusiveExtractor for S {
 /// and it would generally not be implemented on tuple types.
 #[async_trait]
 pub trait RequestExtractor: Send + Sync + Sized {
+    /// Whether the exclusive extractor, if any, streams the request body (see
+    /// [`ExclusiveExtractor::STREAMS_BODY`])
+    const STREAMS_BODY: bool = false;
+
     /// Construct an instance of this type from a `RequestContext`.
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
@@ -127,6 +136,8 @@ impl RequestExtractor for () {
 // Impl for one-element tuple with an exclusive extractor
 #[async_trait]
 impl<X: ExclusiveExtractor + 'static> RequestExtractor for (X,) {
+    const STREAMS_BODY: bool = X::STREAMS_BODY;
+
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
@@ -160,6 +171,8 @@ macro_rules! impl_rqextractor_for_tuple {
         RequestExtractor
         for ($($S,)+ X)
     {
+        const STREAMS_BODY: bool = X::STREAMS_BODY;
+
         async fn from_request<Context: ServerContext>(
             rqctx: &RequestContext<Context>,
             request: hyper::Request<hyper::Body>
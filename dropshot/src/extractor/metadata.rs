@@ -1,7 +1,10 @@
 // Copyright 2023 Oxide Computer Company
 
 use crate::api_description::ApiSchemaGenerator;
+use crate::pagination::OFFSET_PAGINATION_PARAM_SENTINEL;
 use crate::pagination::PAGINATION_PARAM_SENTINEL;
+use crate::parameter_style::ParameterSerialization;
+use crate::parameter_style::PARAMETER_STYLE_SENTINEL;
 use crate::schema_util::schema2struct;
 use crate::schema_util::schema_extensions;
 use crate::schema_util::ReferenceVisitor;
@@ -31,15 +34,22 @@ where
         Some(extensions) => {
             let paginated =
                 extensions.get(&PAGINATION_PARAM_SENTINEL.to_string());
+            let offset_paginated =
+                extensions.get(&OFFSET_PAGINATION_PARAM_SENTINEL.to_string());
             let websocket =
                 extensions.get(&WEBSOCKET_PARAM_SENTINEL.to_string());
-            match (paginated, websocket) {
-                (None, None) => ExtensionMode::None,
-                (None, Some(_)) => ExtensionMode::Websocket,
-                (Some(first_page_schema), None) => {
+            match (paginated, offset_paginated, websocket) {
+                (None, None, None) => ExtensionMode::None,
+                (None, None, Some(_)) => ExtensionMode::Websocket,
+                (Some(first_page_schema), None, None) => {
                     ExtensionMode::Paginated(first_page_schema.clone())
                 }
-                (Some(_), Some(_)) => panic!(
+                (None, Some(_), None) => ExtensionMode::OffsetPaginated,
+                (Some(_), Some(_), _) => panic!(
+                    "Cannot use token and offset pagination in the same \
+                     endpoint!"
+                ),
+                (_, _, Some(_)) => panic!(
                     "Cannot use websocket and pagination in the same endpoint!"
                 ),
             }
@@ -61,7 +71,21 @@ where
         let mut visitor = ReferenceVisitor::new(&generator);
         schemars::visit::visit_schema(&mut visitor, &mut s);
 
-        ApiEndpointParameter::new_named(
+        // Types that declare a style for the parameter do so with a sentinel,
+        // which doesn't belong in the parameter's schema.
+        let serialization = match &mut s {
+            schemars::schema::Schema::Object(object) => object
+                .extensions
+                .remove(PARAMETER_STYLE_SENTINEL)
+                .map(|value| {
+                    serde_json::from_value::<ParameterSerialization>(value)
+                        .unwrap()
+                        .for_location(&struct_member.name, loc)
+                }),
+            schemars::schema::Schema::Bool(_) => None,
+        };
+
+        let mut parameter = ApiEndpointParameter::new_named(
             loc,
             struct_member.name,
             struct_member.description,
@@ -71,7 +95,9 @@ where
                 dependencies: visitor.dependencies(),
             },
             Vec::new(),
-        )
+        );
+        parameter.serialization = serialization;
+        parameter
     })
     .collect::<Vec<_>>();
 
@@ -12,14 +12,18 @@ pub use common::SharedExtractor;
 
 mod body;
 pub use body::MultipartBody;
+pub use body::SpooledBody;
 pub use body::StreamingBody;
 pub use body::TypedBody;
 pub use body::UntypedBody;
+pub use body::VersionedBody;
 
 mod metadata;
 
 mod path;
 pub use path::Path;
+pub use path::PathError;
+pub use path::PathErrorHandler;
 
 mod query;
 pub use query::Query;
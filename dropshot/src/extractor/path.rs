@@ -15,6 +15,8 @@ use async_trait::async_trait;
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use std::fmt::Debug;
+use std::fmt::Display;
+use std::sync::Arc;
 
 /// `Path<PathType>` is an extractor used to deserialize an instance of
 /// `PathType` from an HTTP request's path parameters.  `PathType` is any
@@ -32,6 +34,55 @@ impl<PathType: JsonSchema + Send + Sync> Path<PathType> {
     }
 }
 
+/// Describes a failure to deserialize a request's path parameters into the type
+/// expected by an endpoint, as when a client supplies `"abc"` for a path
+/// variable that's supposed to be a `u32`
+///
+/// By default, this is reported to the client as a 400 ("Bad Request") error
+/// whose message is derived from this value's `Display` impl.  Consumers can
+/// customize this with [`crate::ApiDescription::path_error_handler`].
+#[derive(Clone, Debug)]
+pub struct PathError {
+    /// name of the path variable that could not be deserialized, if known
+    pub name: Option<String>,
+    /// value supplied by the client for that variable, if known
+    pub value: Option<String>,
+    /// description of the type expected for that variable, if known
+    pub expected: Option<String>,
+    /// description of the problem
+    pub message: String,
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{}: ", name)?;
+        }
+        // Most errors already describe the offending value, but some (e.g.,
+        // those produced by a type's own `FromStr`) don't.
+        if let Some(value) = &self.value {
+            if !self.message.contains(value.as_str()) {
+                write!(f, "invalid value {:?}: ", value)?;
+            }
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl From<PathError> for HttpError {
+    fn from(error: PathError) -> Self {
+        HttpError::for_bad_request(
+            None,
+            format!("bad parameter in URL path: {}", error),
+        )
+    }
+}
+
+/// Function used to produce the error returned to clients when a request's
+/// path parameters cannot be deserialized.  See
+/// [`crate::ApiDescription::path_error_handler`].
+pub type PathErrorHandler = Arc<dyn Fn(PathError) -> HttpError + Send + Sync>;
+
 // The `SharedExtractor` implementation for Path<PathType> describes how to
 // construct an instance of `Path<QueryType>` from an HTTP request: namely, by
 // extracting parameters from the query string.
@@ -43,7 +94,11 @@ where
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
     ) -> Result<Path<PathType>, HttpError> {
-        let params: PathType = http_extract_path_params(&rqctx.path_variables)?;
+        let params: PathType = http_extract_path_params(&rqctx.path_variables)
+            .map_err(|error| match &*rqctx.server.path_error_handler {
+                Some(handler) => handler(error),
+                None => HttpError::from(error),
+            })?;
         Ok(Path { inner: params })
     }
 
@@ -5,7 +5,13 @@
 use super::metadata::get_metadata;
 use crate::api_description::ApiEndpointBodyContentType;
 use crate::api_description::ApiEndpointParameterLocation;
+use crate::api_description::ApiEndpointParameterMetadata;
+use crate::api_description::ExtensionMode;
 use crate::error::HttpError;
+use crate::pagination::open_sealed_page_token;
+use crate::pagination::PageTokenKeys;
+use crate::parameter_style::group_query_parameters;
+use crate::parameter_style::ParameterStyle;
 use crate::server::ServerContext;
 use crate::ExtractorMetadata;
 use crate::RequestContext;
@@ -14,7 +20,13 @@ use crate::SharedExtractor;
 use async_trait::async_trait;
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
 
 /// `Query<QueryType>` is an extractor used to deserialize an instance of
 /// `QueryType` from an HTTP request's query string.  `QueryType` is any
@@ -32,17 +44,110 @@ impl<QueryType: DeserializeOwned + JsonSchema + Send + Sync> Query<QueryType> {
     }
 }
 
+/// What Dropshot needs to know about `QueryType` to load it from a query
+/// string
+///
+/// This is found from `QueryType`'s schema, so it's remembered for each type
+/// rather than found again for each request.
+struct QueryLayout {
+    /// styles of the parameters whose values are spread across several
+    /// entries of the query string (exploded arrays and deep objects), by name
+    grouped: BTreeMap<String, ParameterStyle>,
+    /// whether `QueryType` is [`crate::PaginationParams`], whose page tokens
+    /// may be sealed
+    paginated: bool,
+}
+
+/// Returns the [`QueryLayout`] of `QueryType`
+fn query_layout<QueryType>() -> Arc<QueryLayout>
+where
+    QueryType: JsonSchema + 'static,
+{
+    static LAYOUTS: OnceLock<Mutex<HashMap<TypeId, Arc<QueryLayout>>>> =
+        OnceLock::new();
+
+    let mut layouts = LAYOUTS.get_or_init(Default::default).lock().unwrap();
+    let layout =
+        layouts.entry(TypeId::of::<QueryType>()).or_insert_with(|| {
+            let metadata =
+                get_metadata::<QueryType>(&ApiEndpointParameterLocation::Query);
+            let grouped = metadata
+                .parameters
+                .into_iter()
+                .filter_map(|parameter| {
+                    let ApiEndpointParameterMetadata::Query(name) =
+                        parameter.metadata
+                    else {
+                        return None;
+                    };
+                    let serialization = parameter.serialization?;
+                    serialization.explode.then_some((name, serialization.style))
+                })
+                .collect();
+            let paginated =
+                matches!(metadata.extension_mode, ExtensionMode::Paginated(_));
+            Arc::new(QueryLayout { grouped, paginated })
+        });
+    Arc::clone(layout)
+}
+
+/// Replaces the sealed page token in `query_string`, if there is one, with
+/// the same token opened with `keys`, so that it can be deserialized like any
+/// other
+fn open_page_token(
+    query_string: &str,
+    keys: &PageTokenKeys,
+) -> Result<String, HttpError> {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (name, value) in form_urlencoded::parse(query_string.as_bytes()) {
+        if name == "page_token" {
+            let opened =
+                open_sealed_page_token(keys, &value).map_err(|message| {
+                    HttpError::for_bad_request(
+                        None,
+                        format!("unable to parse query string: {}", message),
+                    )
+                })?;
+            serializer.append_pair(&name, &opened);
+        } else {
+            serializer.append_pair(&name, &value);
+        }
+    }
+    Ok(serializer.finish())
+}
+
 /// Given an HTTP request, pull out the query string and attempt to deserialize
 /// it as an instance of `QueryType`.
+///
+/// If `QueryType` is [`crate::PaginationParams`], its page token is opened
+/// with `page_token_keys`, if there are any.
 fn http_request_load_query<QueryType>(
     request: &RequestInfo,
+    page_token_keys: Option<&PageTokenKeys>,
 ) -> Result<Query<QueryType>, HttpError>
 where
-    QueryType: DeserializeOwned + JsonSchema + Send + Sync,
+    QueryType: DeserializeOwned + JsonSchema + Send + Sync + 'static,
 {
     let raw_query_string = request.uri().query().unwrap_or("");
+    let layout = query_layout::<QueryType>();
+    let opened_query_string;
+    let raw_query_string = match page_token_keys {
+        Some(keys) if layout.paginated => {
+            opened_query_string = open_page_token(raw_query_string, keys)?;
+            &opened_query_string
+        }
+        _ => raw_query_string,
+    };
+    let grouped_query_string;
+    let query_string = if layout.grouped.is_empty() {
+        raw_query_string
+    } else {
+        grouped_query_string =
+            group_query_parameters(raw_query_string, &layout.grouped);
+        &grouped_query_string
+    };
     // TODO-correctness: are query strings defined to be urlencoded in this way?
-    match serde_urlencoded::from_str(raw_query_string) {
+    match serde_urlencoded::from_str(query_string) {
         Ok(q) => Ok(Query { inner: q }),
         Err(e) => Err(HttpError::for_bad_request(
             None,
@@ -65,7 +170,10 @@ where
     async fn from_request<Context: ServerContext>(
         rqctx: &RequestContext<Context>,
     ) -> Result<Query<QueryType>, HttpError> {
-        http_request_load_query(&rqctx.request)
+        rqctx
+            .page_limits
+            .check_request(rqctx.request.uri().query().unwrap_or(""))?;
+        http_request_load_query(&rqctx.request, rqctx.page_token_keys())
     }
 
     fn metadata(
@@ -0,0 +1,464 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for downloading files
+//!
+//! [`HttpResponseFile`] sends a file from the local filesystem the way a
+//! static file server would: the file is streamed rather than read into
+//! memory, its type is guessed from its name, and the response carries
+//! validators (`ETag` and `Last-Modified`) so that clients can make
+//! conditional requests (e.g., to revalidate a cached copy) and resume
+//! interrupted downloads with range requests.
+
+use crate::api_description::ApiEndpointResponse;
+use crate::error::HttpError;
+use crate::handler::{HttpHandlerResult, HttpResponse, RequestInfo};
+use crate::range::{ByteRange, HttpResponsePartialContent, Range};
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, StatusCode};
+use hyper::{Body, Response};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Characters that must be percent-encoded in the `filename*` parameter of
+/// `Content-Disposition` (everything but RFC 8187's `attr-char`)
+const FILENAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// `HttpResponseFile` is a response that sends the contents of a file
+///
+/// The response includes:
+///
+/// * `Content-Type`, guessed from the file's name (falling back to
+///   "application/octet-stream")
+/// * `Content-Length`
+/// * `Content-Disposition`, which by default tells browsers to save the file
+///   (see [`HttpResponseFile::inline`])
+/// * `ETag` and `Last-Modified`, derived from the file's size and
+///   modification time
+/// * `Accept-Ranges`
+///
+/// Constructing one evaluates the request's conditional headers
+/// (`If-Match`, `If-Unmodified-Since`, `If-None-Match`, and
+/// `If-Modified-Since`) and `Range` header against the file, so the response
+/// may instead be a 304 ("Not Modified"), part of the file (see
+/// [`HttpResponsePartialContent`]), or an error with status 412
+/// ("Precondition Failed").
+///
+/// Because the status code varies, this is described in the OpenAPI document
+/// like a hand-rolled `Response<Body>`.
+#[derive(Debug)]
+pub struct HttpResponseFile {
+    response: Response<Body>,
+}
+
+impl HttpResponseFile {
+    /// Responds to `request` with the file at `path`
+    ///
+    /// If the file doesn't exist, this fails with a 404 ("Not Found") error.
+    pub async fn from_path<P: AsRef<Path>>(
+        request: &RequestInfo,
+        path: P,
+    ) -> Result<HttpResponseFile, HttpError> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await.map_err(|e| {
+            let message = format!("opening {:?}: {}", path, e);
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HttpError::for_not_found(None, message)
+            } else {
+                HttpError::for_internal_error(message)
+            }
+        })?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        HttpResponseFile::from_file(request, file, &filename).await
+    }
+
+    /// Responds to `request` with the contents of `file`, which is presented
+    /// to the client as `filename`
+    ///
+    /// `filename` is used to guess the content type and to suggest a name to
+    /// clients that save the file.  It may be empty.
+    pub async fn from_file(
+        request: &RequestInfo,
+        file: tokio::fs::File,
+        filename: &str,
+    ) -> Result<HttpResponseFile, HttpError> {
+        let metadata = file.metadata().await.map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "reading file metadata: {}",
+                e
+            ))
+        })?;
+        if !metadata.is_file() {
+            return Err(HttpError::for_not_found(
+                None,
+                format!("{:?} is not a regular file", filename),
+            ));
+        }
+
+        // HTTP dates only have a resolution of one second, so comparisons are
+        // done in whole seconds.
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| UNIX_EPOCH + Duration::from_secs(d.as_secs()));
+        let validators = Validators {
+            etag: format!(
+                "\"{:x}-{:x}\"",
+                metadata.len(),
+                modified.map_or(0, |m| {
+                    m.duration_since(UNIX_EPOCH).unwrap().as_secs()
+                })
+            ),
+            modified,
+        };
+
+        let headers = request.headers();
+        let mut response = match validators.evaluate(request.method(), headers)
+        {
+            Condition::Failed => {
+                return Err(HttpError::for_status(
+                    None,
+                    StatusCode::PRECONDITION_FAILED,
+                ));
+            }
+            Condition::NotModified => Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())?,
+            Condition::Passed { range } => {
+                let content_type = mime_guess::from_path(filename)
+                    .first_raw()
+                    .unwrap_or("application/octet-stream");
+                let mut response = HttpResponsePartialContent::from_file(
+                    &Range(range),
+                    content_type,
+                    file,
+                )
+                .await?
+                .to_result()?;
+                response.headers_mut().insert(
+                    header::CONTENT_DISPOSITION,
+                    content_disposition("attachment", filename),
+                );
+                response
+            }
+        };
+
+        let response_headers = response.headers_mut();
+        response_headers.insert(
+            header::ETAG,
+            HeaderValue::from_str(&validators.etag).unwrap(),
+        );
+        if let Some(modified) = validators.modified {
+            response_headers.insert(
+                header::LAST_MODIFIED,
+                HeaderValue::from_str(&httpdate::fmt_http_date(modified))
+                    .unwrap(),
+            );
+        }
+        Ok(HttpResponseFile { response })
+    }
+
+    /// Tells browsers to display the file rather than save it
+    ///
+    /// This changes the disposition type in `Content-Disposition` from
+    /// "attachment" to "inline".
+    pub fn inline(mut self) -> HttpResponseFile {
+        let headers = self.response.headers_mut();
+        if let Some(value) = headers.get(header::CONTENT_DISPOSITION) {
+            let value = value.to_str().unwrap_or("");
+            let params = value.strip_prefix("attachment").unwrap_or("");
+            let value = HeaderValue::from_str(&format!("inline{}", params));
+            headers.insert(header::CONTENT_DISPOSITION, value.unwrap());
+        }
+        self
+    }
+
+    /// Overrides the content type guessed from the file's name
+    pub fn content_type(
+        mut self,
+        content_type: &str,
+    ) -> Result<HttpResponseFile, HttpError> {
+        let headers = self.response.headers_mut();
+        if headers.contains_key(header::CONTENT_TYPE) {
+            let value = HeaderValue::from_str(content_type).map_err(|e| {
+                HttpError::for_internal_error(format!(
+                    "invalid content type {:?}: {}",
+                    content_type, e
+                ))
+            })?;
+            headers.insert(header::CONTENT_TYPE, value);
+        }
+        Ok(self)
+    }
+
+    /// Returns the status code of this response
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+}
+
+impl HttpResponse for HttpResponseFile {
+    fn to_result(self) -> HttpHandlerResult {
+        Ok(self.response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        ApiEndpointResponse::default()
+    }
+}
+
+/// Returns a `Content-Disposition` value suggesting `filename`, with both the
+/// plain `filename` parameter (with non-ASCII characters replaced) for old
+/// clients and the encoded `filename*` parameter
+fn content_disposition(kind: &str, filename: &str) -> HeaderValue {
+    if filename.is_empty() {
+        return HeaderValue::from_str(kind).unwrap();
+    }
+    let plain = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let encoded = utf8_percent_encode(filename, FILENAME_ENCODE_SET);
+    HeaderValue::from_str(&format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind, plain, encoded
+    ))
+    .unwrap()
+}
+
+/// Outcome of evaluating a request's preconditions
+#[derive(Debug, Eq, PartialEq)]
+enum Condition {
+    /// send (the requested part of) the file
+    Passed { range: Option<ByteRange> },
+    /// the client's copy is current
+    NotModified,
+    /// a precondition failed
+    Failed,
+}
+
+/// Validators for the current version of a file
+struct Validators {
+    etag: String,
+    modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Evaluates conditional request headers in the order given by RFC 9110
+    /// section 13.2.2
+    fn evaluate(&self, method: &Method, headers: &HeaderMap) -> Condition {
+        let get_or_head = method == Method::GET || method == Method::HEAD;
+
+        if let Some(if_match) = header_str(headers, header::IF_MATCH) {
+            if !self.etag_matches(if_match, false) {
+                return Condition::Failed;
+            }
+        } else if let Some(since) =
+            header_date(headers, header::IF_UNMODIFIED_SINCE)
+        {
+            if self.modified.map_or(true, |m| m > since) {
+                return Condition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH)
+        {
+            if self.etag_matches(if_none_match, true) {
+                return if get_or_head {
+                    Condition::NotModified
+                } else {
+                    Condition::Failed
+                };
+            }
+        } else if let Some(since) =
+            header_date(headers, header::IF_MODIFIED_SINCE)
+        {
+            if get_or_head && self.modified.map_or(false, |m| m <= since) {
+                return Condition::NotModified;
+            }
+        }
+
+        let range = header_str(headers, header::RANGE)
+            .filter(|_| method == Method::GET)
+            .and_then(ByteRange::parse)
+            .filter(|_| match header_str(headers, header::IF_RANGE) {
+                None => true,
+                Some(if_range) if if_range.contains('"') => {
+                    !if_range.starts_with("W/") && if_range == self.etag
+                }
+                Some(if_range) => httpdate::parse_http_date(if_range)
+                    .map_or(false, |date| Some(date) == self.modified),
+            });
+        Condition::Passed { range }
+    }
+
+    fn etag_matches(&self, list: &str, weak: bool) -> bool {
+        etag_matches(list, &self.etag, weak)
+    }
+}
+
+/// Returns whether `etag` matches any of the entity tags in `list`, an
+/// `If-Match` or `If-None-Match` value, using the weak comparison function if
+/// `weak` is set and the strong one otherwise
+pub(crate) fn etag_matches(list: &str, etag: &str, weak: bool) -> bool {
+    list.split(',').map(str::trim).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+        match tag.strip_prefix("W/") {
+            Some(tag) => weak && tag == etag,
+            None => tag == etag,
+        }
+    })
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_date(
+    headers: &HeaderMap,
+    name: header::HeaderName,
+) -> Option<SystemTime> {
+    header_str(headers, name).and_then(|s| httpdate::parse_http_date(s).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::content_disposition;
+    use super::Condition;
+    use super::Validators;
+    use crate::range::ByteRange;
+    use http::header::HeaderMap;
+    use http::Method;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn evaluate(method: Method, headers: &[(&'static str, &str)]) -> Condition {
+        let validators = Validators {
+            etag: String::from("\"2b-5e7a\""),
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+        };
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+        validators.evaluate(&method, &map)
+    }
+
+    #[test]
+    fn test_conditions() {
+        let full = Condition::Passed { range: None };
+        let range = Condition::Passed {
+            range: Some(ByteRange::FromTo { start: 0, end: 9 }),
+        };
+        let before = "Sat, 08 Sep 2001 00:00:00 GMT";
+        let at = "Sun, 09 Sep 2001 01:46:40 GMT";
+        let after = "Mon, 10 Sep 2001 00:00:00 GMT";
+
+        assert_eq!(evaluate(Method::GET, &[]), full);
+
+        // If-None-Match takes precedence over If-Modified-Since.
+        assert_eq!(
+            evaluate(Method::GET, &[("if-none-match", "\"x\", W/\"2b-5e7a\"")]),
+            Condition::NotModified
+        );
+        assert_eq!(
+            evaluate(Method::HEAD, &[("if-none-match", "*")]),
+            Condition::NotModified
+        );
+        assert_eq!(
+            evaluate(Method::PUT, &[("if-none-match", "*")]),
+            Condition::Failed
+        );
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[("if-none-match", "\"x\""), ("if-modified-since", after)]
+            ),
+            full
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[("if-modified-since", at)]),
+            Condition::NotModified
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[("if-modified-since", before)]),
+            full
+        );
+
+        // If-Match uses the strong comparison.
+        assert_eq!(evaluate(Method::GET, &[("if-match", "\"2b-5e7a\"")]), full);
+        assert_eq!(
+            evaluate(Method::GET, &[("if-match", "W/\"2b-5e7a\"")]),
+            Condition::Failed
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[("if-unmodified-since", before)]),
+            Condition::Failed
+        );
+        assert_eq!(evaluate(Method::GET, &[("if-unmodified-since", at)]), full);
+
+        // Ranges are only honored for GET, and only if If-Range (if any)
+        // matches.
+        assert_eq!(evaluate(Method::GET, &[("range", "bytes=0-9")]), range);
+        assert_eq!(evaluate(Method::HEAD, &[("range", "bytes=0-9")]), full);
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[("range", "bytes=0-9"), ("if-range", "\"2b-5e7a\"")]
+            ),
+            range
+        );
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[("range", "bytes=0-9"), ("if-range", "\"other\"")]
+            ),
+            full
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[("range", "bytes=0-9"), ("if-range", at)]),
+            range
+        );
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[("range", "bytes=0-9"), ("if-range", before)]
+            ),
+            full
+        );
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(content_disposition("attachment", ""), "attachment");
+        assert_eq!(
+            content_disposition("attachment", "report.pdf"),
+            "attachment; filename=\"report.pdf\"; \
+             filename*=UTF-8''report.pdf"
+        );
+        assert_eq!(
+            content_disposition("inline", "naïve \"plan\".txt"),
+            "inline; filename=\"na_ve _plan_.txt\"; \
+             filename*=UTF-8''na%C3%AFve%20%22plan%22.txt"
+        );
+    }
+}
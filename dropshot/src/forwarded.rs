@@ -0,0 +1,345 @@
+// Copyright 2024 Oxide Computer Company
+//! Resolution of the effective client address for requests that arrive via
+//! trusted reverse proxies
+
+use http::header::HeaderMap;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Header used by many proxies to record the chain of client addresses
+const HEADER_X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Block of IP addresses in CIDR notation (e.g., `"10.0.0.0/8"` or
+/// `"fd00::/8"`)
+///
+/// This is used with [`crate::ConfigDropshot::trusted_proxies`] to describe
+/// which peers are trusted to report the address of the client they are
+/// forwarding for.  A bare address (e.g., `"127.0.0.1"`) is accepted and
+/// describes a block containing only that address.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// Returns a block of addresses whose first `prefix_len` bits match those
+    /// of `addr`.  Fails if `prefix_len` is too large for the address family.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<IpCidr, String> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(format!(
+                "prefix length {} is too large for address {} (max {})",
+                prefix_len, addr, max_len
+            ));
+        }
+        Ok(IpCidr { addr, prefix_len })
+    }
+
+    /// Returns whether `ip` falls within this block
+    ///
+    /// IPv4-mapped IPv6 addresses are treated as the IPv4 addresses they
+    /// represent.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr.to_canonical(), ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = match self.prefix_len {
+                    0 => 0,
+                    n => u32::MAX << (32 - u32::from(n)),
+                };
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = match self.prefix_len {
+                    0 => 0,
+                    n => u128::MAX << (128 - u32::from(n)),
+                };
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr_str, prefix_len) = match s.split_once('/') {
+            Some((addr_str, len_str)) => {
+                let len = len_str
+                    .parse::<u8>()
+                    .map_err(|_| format!("invalid prefix length in {:?}", s))?;
+                (addr_str, Some(len))
+            }
+            None => (s, None),
+        };
+        let addr = addr_str
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid IP address in {:?}", s))?;
+        let prefix_len = prefix_len.unwrap_or(match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        });
+        IpCidr::new(addr, prefix_len)
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<IpCidr> for String {
+    fn from(cidr: IpCidr) -> String {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Computes the address of the client on whose behalf a request was made.
+///
+/// If the peer that sent us the request (`remote_ip`) is not in
+/// `trusted_proxies`, it is the client and any forwarding headers are ignored
+/// (since anybody can send them).  Otherwise, we walk the chain of addresses
+/// reported in the `Forwarded` header (or, if that's absent,
+/// `X-Forwarded-For`) from the nearest hop outward, skipping over trusted
+/// proxies.  The first untrusted address is the client.  If we run into an
+/// entry that we cannot interpret (e.g., an obfuscated identifier or
+/// `"unknown"`), we stop and report the last hop we could identify.
+pub(crate) fn resolve_client_ip(
+    remote_ip: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpCidr],
+) -> IpAddr {
+    let is_trusted =
+        |ip: &IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+
+    let mut client_ip = remote_ip;
+    if !is_trusted(&client_ip) {
+        return client_ip;
+    }
+
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        match hop {
+            Some(ip) => {
+                client_ip = ip;
+                if !is_trusted(&ip) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    client_ip
+}
+
+/// Returns the chain of forwarded-for addresses recorded in the request
+/// headers, in the order they were appended (i.e., the original client first).
+/// Entries that don't identify an IP address are represented by `None`.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded = headers.get_all(http::header::FORWARDED);
+    if forwarded.iter().next().is_some() {
+        return forwarded
+            .iter()
+            .flat_map(|value| match value.to_str() {
+                Ok(value) => value
+                    .split(',')
+                    .map(|element| {
+                        element
+                            .split(';')
+                            .filter_map(|pair| pair.split_once('='))
+                            .find(|(name, _)| {
+                                name.trim().eq_ignore_ascii_case("for")
+                            })
+                            .and_then(|(_, node)| parse_node(node))
+                    })
+                    .collect::<Vec<_>>(),
+                Err(_) => vec![None],
+            })
+            .collect();
+    }
+
+    headers
+        .get_all(HEADER_X_FORWARDED_FOR)
+        .iter()
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value.split(',').map(parse_node).collect::<Vec<_>>(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+/// Parses a single node from a forwarding header.  Nodes may be quoted and may
+/// include a port (e.g., `"[2001:db8::1]:4711"` or `192.0.2.1:80`).
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::resolve_client_ip;
+    use super::IpCidr;
+    use http::header::HeaderMap;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_cidr_parse() {
+        let cidr: IpCidr = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert!(cidr.contains(&ip("10.1.2.3")));
+        assert!(!cidr.contains(&ip("11.0.0.1")));
+        assert!(cidr.contains(&ip("::ffff:10.9.9.9")));
+        assert!(!cidr.contains(&ip("fd00::1")));
+
+        let cidr: IpCidr = "127.0.0.1".parse().unwrap();
+        assert_eq!(cidr.to_string(), "127.0.0.1/32");
+        assert!(cidr.contains(&ip("127.0.0.1")));
+        assert!(!cidr.contains(&ip("127.0.0.2")));
+
+        let cidr: IpCidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&ip("fd12:3456::1")));
+        assert!(!cidr.contains(&ip("fe80::1")));
+
+        let cidr: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(cidr.contains(&ip("192.0.2.1")));
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<IpCidr>().unwrap_err(),
+            "prefix length 33 is too large for address 10.0.0.0 (max 32)"
+        );
+        assert_eq!(
+            "10.0.0.0/x".parse::<IpCidr>().unwrap_err(),
+            "invalid prefix length in \"10.0.0.0/x\""
+        );
+        assert_eq!(
+            "garbage/8".parse::<IpCidr>().unwrap_err(),
+            "invalid IP address in \"garbage/8\""
+        );
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let h = headers(&[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(
+            resolve_client_ip(ip("198.51.100.7"), &h, &trusted),
+            ip("198.51.100.7")
+        );
+        assert_eq!(resolve_client_ip(ip("10.0.0.1"), &h, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_x_forwarded_for() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+
+        // The nearest untrusted hop wins, even if the client claims to be
+        // somebody else further out.
+        let h =
+            headers(&[("x-forwarded-for", "203.0.113.9, 192.0.2.1, 10.0.0.2")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("192.0.2.1")
+        );
+
+        // Multiple header lines are treated as one list.
+        let h = headers(&[
+            ("x-forwarded-for", "192.0.2.1"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("192.0.2.1")
+        );
+
+        // If every hop is trusted, the outermost one is the client.
+        let h = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.3")
+        );
+
+        // Garbage stops the walk at the last hop we could identify.
+        let h = headers(&[("x-forwarded-for", "192.0.2.1, bogus, 10.0.0.2")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.2")
+        );
+
+        // No header at all means the proxy itself is the client.
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let trusted =
+            vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()];
+
+        let h = headers(&[(
+            "forwarded",
+            "for=192.0.2.60;proto=http;by=203.0.113.43, \
+             For=\"[2001:db8:cafe::17]:4711\", for=\"[fd00::2]\"",
+        )]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("2001:db8:cafe::17")
+        );
+
+        // `Forwarded` takes precedence over `X-Forwarded-For`.
+        let h = headers(&[
+            ("forwarded", "for=192.0.2.60:8080"),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("192.0.2.60")
+        );
+
+        let h = headers(&[("forwarded", "for=unknown, for=10.0.0.9")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.9")
+        );
+    }
+}
@@ -20,12 +20,25 @@ use std::fmt::Display;
 pub(crate) fn from_map<'a, T, Z>(
     map: &'a BTreeMap<String, Z>,
 ) -> Result<T, String>
+where
+    T: Deserialize<'a>,
+    Z: MapValue + Debug + Clone + 'static,
+{
+    from_map_detailed(map).map_err(|e| e.message)
+}
+
+/// Like `from_map()`, but on failure, reports which entry of the map could not
+/// be deserialized (when that's known), along with its value and the type that
+/// was expected.
+pub(crate) fn from_map_detailed<'a, T, Z>(
+    map: &'a BTreeMap<String, Z>,
+) -> Result<T, MapError>
 where
     T: Deserialize<'a>,
     Z: MapValue + Debug + Clone + 'static,
 {
     let mut deserializer = MapDeserializer::from_map(map);
-    T::deserialize(&mut deserializer).map_err(|e| e.0)
+    T::deserialize(&mut deserializer)
 }
 
 pub(crate) trait MapValue {
@@ -39,9 +52,8 @@ impl MapValue for String {
     }
 
     fn as_seq(&self) -> Result<Box<dyn Iterator<Item = String>>, MapError> {
-        Err(MapError(
-            "a string may not be used in place of a sequence of values"
-                .to_string(),
+        Err(MapError::new(
+            "a string may not be used in place of a sequence of values",
         ))
     }
 }
@@ -70,20 +82,39 @@ where
     {
         match self {
             MapDeserializer::Value(ref raw_value) => deserialize(raw_value),
-            MapDeserializer::Map(_) => Err(MapError(
-                "must be applied to a flattened struct rather than a raw type"
-                    .to_string(),
+            MapDeserializer::Map(_) => Err(MapError::new(
+                "must be applied to a flattened struct rather than a raw type",
             )),
         }
     }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct MapError(pub String);
+pub(crate) struct MapError {
+    /// description of the problem
+    pub message: String,
+    /// key of the map entry that could not be deserialized, if known
+    pub field: Option<String>,
+    /// raw value of the map entry that could not be deserialized, if known
+    pub value: Option<String>,
+    /// description of what was expected in place of `value`, if known
+    pub expected: Option<String>,
+}
+
+impl MapError {
+    pub(crate) fn new<S: Into<String>>(message: S) -> MapError {
+        MapError {
+            message: message.into(),
+            field: None,
+            value: None,
+            expected: None,
+        }
+    }
+}
 
 impl Display for MapError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(self.0.as_str())
+        f.write_str(self.message.as_str())
     }
 }
 
@@ -92,7 +123,33 @@ impl serde::de::Error for MapError {
     where
         T: std::fmt::Display,
     {
-        MapError(format!("{}", msg))
+        MapError::new(format!("{}", msg))
+    }
+
+    fn invalid_type(
+        unexp: serde::de::Unexpected<'_>,
+        exp: &dyn serde::de::Expected,
+    ) -> Self {
+        MapError {
+            expected: Some(exp.to_string()),
+            ..MapError::new(format!(
+                "invalid type: {}, expected {}",
+                unexp, exp
+            ))
+        }
+    }
+
+    fn invalid_value(
+        unexp: serde::de::Unexpected<'_>,
+        exp: &dyn serde::de::Expected,
+    ) -> Self {
+        MapError {
+            expected: Some(exp.to_string()),
+            ..MapError::new(format!(
+                "invalid value: {}, expected {}",
+                unexp, exp
+            ))
+        }
     }
 }
 
@@ -126,11 +183,15 @@ macro_rules! de_value {
             {
                 self.value(|raw_value| match raw_value.as_value()?.parse::<$i>() {
                     Ok(value) => visitor.[<visit_ $i>](value),
-                    Err(_) => Err(MapError(format!(
-                        "unable to parse '{}' as {}",
-                        raw_value.as_value()?,
-                        type_name::<$i>()
-                    ))),
+                    Err(_) => Err(MapError {
+                        value: Some(raw_value.as_value()?.to_string()),
+                        expected: Some(type_name::<$i>().to_string()),
+                        ..MapError::new(format!(
+                            "unable to parse '{}' as {}",
+                            raw_value.as_value()?,
+                            type_name::<$i>()
+                        ))
+                    }),
                 })
             }
         }
@@ -199,12 +260,12 @@ where
             MapDeserializer::Map(map) => {
                 let xx = map.clone();
                 let x = Box::new(xx.into_iter());
-                let m = MapMapAccess::<Z> { iter: x, value: None };
+                let m = MapMapAccess::<Z> { iter: x, key: None, value: None };
                 visitor.visit_map(m)
             }
-            MapDeserializer::Value(_) => Err(MapError(
-                "destination struct must be fully flattened".to_string(),
-            )),
+            MapDeserializer::Value(_) => {
+                Err(MapError::new("destination struct must be fully flattened"))
+            }
         }
     }
     fn deserialize_identifier<V>(
@@ -357,6 +418,8 @@ where
 struct MapMapAccess<Z> {
     /// Iterator through the Map
     iter: Box<dyn Iterator<Item = (String, Z)>>,
+    /// Key of the pending value, used to provide context for errors
+    key: Option<String>,
     /// Pending value in a key-value pair
     value: Option<Z>,
 }
@@ -376,7 +439,8 @@ where
     {
         match self.iter.next() {
             Some((key, value)) => {
-                // Save the value for later.
+                // Save the key and value for later.
+                self.key.replace(key.clone());
                 self.value.replace(value);
                 // Create a Deserializer for that single value.
                 let mut deserializer = MapDeserializer::Value(key);
@@ -391,8 +455,24 @@ where
     {
         match self.value.take() {
             Some(value) => {
-                let mut deserializer = MapDeserializer::Value(value);
-                seed.deserialize(&mut deserializer)
+                let mut deserializer = MapDeserializer::Value(value.clone());
+                seed.deserialize(&mut deserializer).map_err(|mut error| {
+                    // Attach the name and raw value of the entry we were
+                    // working on, unless some inner entry already claimed the
+                    // error.
+                    if error.field.is_none() {
+                        error.field = self.key.take();
+                        if error.value.is_none() {
+                            error.value = match value.as_value() {
+                                Ok(v) => Some(v.to_string()),
+                                Err(_) => value.as_seq().ok().map(|seq| {
+                                    seq.collect::<Vec<_>>().join("/")
+                                }),
+                            };
+                        }
+                    }
+                    error
+                })
             }
             // This means we were called without a corresponding call to
             // next_key_seed() which should not be possible.
@@ -431,6 +511,7 @@ where
 #[cfg(test)]
 mod test {
     use super::from_map;
+    use super::from_map_detailed;
     use serde::Deserialize;
     use std::collections::BTreeMap;
 
@@ -571,4 +652,44 @@ mod test {
             Ok(_) => panic!("unexpected success"),
         }
     }
+
+    #[test]
+    fn test_error_detail() {
+        #![allow(dead_code)]
+
+        #[derive(Deserialize, Debug)]
+        struct A {
+            astring: String,
+            au32: u32,
+        }
+        let mut map = BTreeMap::new();
+        map.insert("astring".to_string(), "A string".to_string());
+        map.insert("au32".to_string(), "abc".to_string());
+        let error = from_map_detailed::<A, String>(&map).unwrap_err();
+        assert_eq!(error.message, "unable to parse 'abc' as u32");
+        assert_eq!(error.field.as_deref(), Some("au32"));
+        assert_eq!(error.value.as_deref(), Some("abc"));
+        assert_eq!(error.expected.as_deref(), Some("u32"));
+
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "snake_case")]
+        enum Color {
+            Red,
+            Green,
+        }
+        #[derive(Deserialize, Debug)]
+        struct B {
+            color: Color,
+        }
+        let mut map = BTreeMap::new();
+        map.insert("color".to_string(), "blue".to_string());
+        let error = from_map_detailed::<B, String>(&map).unwrap_err();
+        assert_eq!(
+            error.message,
+            "unknown variant `blue`, expected `red` or `green`"
+        );
+        assert_eq!(error.field.as_deref(), Some("color"));
+        assert_eq!(error.value.as_deref(), Some("blue"));
+        assert_eq!(error.expected, None);
+    }
 }
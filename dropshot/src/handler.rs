@@ -31,16 +31,24 @@
 //! facilities don't seem that valuable right now since they largely don't affect
 //! OpenAPI document generation.
 
+use super::deadline::Deadline;
 use super::error::HttpError;
 use super::extractor::RequestExtractor;
 use super::http_util::CONTENT_TYPE_JSON;
 use super::http_util::CONTENT_TYPE_OCTET_STREAM;
+use super::prefer::Preferences;
 use super::server::DropshotState;
 use super::server::ServerContext;
+use super::server_timing::ServerTiming;
+use super::tls_client_auth::ClientCertificate;
+use super::unix_socket::PeerCredentials;
 use crate::api_description::{
     ApiEndpointBodyContentType, ApiEndpointHeader, ApiEndpointResponse,
     ApiSchemaGenerator,
 };
+use crate::pagination::OffsetPaginationParams;
+use crate::pagination::PageLimits;
+use crate::pagination::PageTokenKeys;
 use crate::pagination::PaginationParams;
 use crate::router::VariableSet;
 use crate::schema_util::make_subschema_for;
@@ -50,6 +58,7 @@ use crate::to_map::to_map;
 
 use async_trait::async_trait;
 use http::{HeaderMap, StatusCode};
+use hyper::ext::ReasonPhrase;
 use hyper::{Body, Response};
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
@@ -62,6 +71,7 @@ use std::{
     marker::PhantomData,
     num::NonZeroU32,
     sync::Arc,
+    time::Instant,
 };
 
 /// Type alias for the result returned by HTTP handler functions.
@@ -76,21 +86,39 @@ pub struct RequestContext<Context: ServerContext> {
     pub path_variables: VariableSet,
     /// expected request body mime type
     pub body_content_type: ApiEndpointBodyContentType,
+    /// maximum allowed size of the request body for this endpoint
+    pub request_body_max_bytes: usize,
+    /// limits on the page size for this endpoint, if it overrides the server's
+    pub page_limits: PageLimits,
+    /// time by which the client expects a response (see [`Deadline`])
+    pub deadline: Deadline,
+    /// preferences from the request's `Prefer` headers (see [`Preferences`])
+    pub preferences: Preferences,
     /// unique id assigned to this request
     pub request_id: String,
+    /// version of the API that the request is handled as, if the API is
+    /// versioned (see [`crate::VersionPolicy`] and
+    /// [`crate::PrereleaseMatching`])
+    pub api_version: Option<semver::Version>,
     /// basic request information (method, URI, etc.)
     pub request: RequestInfo,
+    /// whether to time the phases of handling this request for the
+    /// `Server-Timing` header (see [`crate::ConfigServerTiming`])
+    pub(crate) server_timing: bool,
 }
 
 // This is deliberately as close to compatible with `hyper::Request` as
 // reasonable with the addition of the remote address.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RequestInfo {
     method: http::Method,
     uri: http::Uri,
     version: http::Version,
     headers: http::HeaderMap<http::HeaderValue>,
     remote_addr: std::net::SocketAddr,
+    client_ip: std::net::IpAddr,
+    peer_credentials: Option<PeerCredentials>,
+    client_certificate: Option<ClientCertificate>,
 }
 
 impl RequestInfo {
@@ -104,8 +132,21 @@ impl RequestInfo {
             version: request.version(),
             headers: request.headers().clone(),
             remote_addr,
+            client_ip: remote_addr.ip(),
+            peer_credentials: request
+                .extensions()
+                .get::<PeerCredentials>()
+                .copied(),
+            client_certificate: request
+                .extensions()
+                .get::<ClientCertificate>()
+                .cloned(),
         }
     }
+
+    pub(crate) fn with_client_ip(self, client_ip: std::net::IpAddr) -> Self {
+        RequestInfo { client_ip, ..self }
+    }
 }
 
 impl RequestInfo {
@@ -129,6 +170,32 @@ impl RequestInfo {
         self.remote_addr
     }
 
+    /// Returns the IP address of the client on whose behalf this request was
+    /// made
+    ///
+    /// This is the same as the address of [`RequestInfo::remote_addr()`]
+    /// unless the peer is one of the server's
+    /// [`crate::ConfigDropshot::trusted_proxies`], in which case it's the
+    /// address reported by the `Forwarded` or `X-Forwarded-For` header.  (With
+    /// [`crate::ConfigDropshot::proxy_protocol`], the remote address is
+    /// already the one reported by the proxy.)
+    pub fn client_ip(&self) -> std::net::IpAddr {
+        self.client_ip
+    }
+
+    /// Returns the credentials of the client process, if the request arrived
+    /// over a Unix domain socket (see [`crate::ConfigUnixSocket`])
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
+    /// Returns the certificate presented by the client, if the request
+    /// arrived over a TLS connection that authenticates clients (see
+    /// [`crate::ConfigTls::with_client_auth`])
+    pub fn client_certificate(&self) -> Option<&ClientCertificate> {
+        self.client_certificate.as_ref()
+    }
+
     /// Returns a reference to the `RequestInfo` itself
     ///
     /// This is provided for source compatibility.  In previous versions of
@@ -182,16 +249,46 @@ impl<Context: ServerContext> RequestContext<Context> {
         ScanParams: DeserializeOwned,
         PageSelector: DeserializeOwned + Serialize,
     {
-        let server_config = &self.server.config;
+        Ok(self.clamp_page_limit(pag_params.limit))
+    }
 
-        Ok(pag_params
-            .limit
+    /// Returns the appropriate count of items to return for a request
+    /// paginated by offset
+    ///
+    /// This behaves just like [`RequestContext::page_limit()`], but for
+    /// endpoints that accept [`OffsetPaginationParams`].
+    pub fn offset_page_limit(
+        &self,
+        pag_params: &OffsetPaginationParams,
+    ) -> Result<NonZeroU32, HttpError> {
+        Ok(self.clamp_page_limit(pag_params.limit))
+    }
+
+    /// Returns the keys that the server seals page tokens with, if it does
+    ///
+    /// Pass these to [`ResultsPage::seal()`][crate::ResultsPage::seal()] to
+    /// seal the tokens of the pages that the handler returns.  See
+    /// [`PageTokenKeys`].
+    pub fn page_token_keys(&self) -> Option<&PageTokenKeys> {
+        self.server.page_token_keys()
+    }
+
+    fn clamp_page_limit(&self, limit: Option<NonZeroU32>) -> NonZeroU32 {
+        let server_config = &self.server.config;
+        // The endpoint's own limits take precedence over the server's.
+        let max = self.page_limits.max.unwrap_or(server_config.page_max_nitems);
+        let default = self
+            .page_limits
+            .default
+            .unwrap_or(server_config.page_default_nitems);
+
+        limit
             // Compare the client-provided limit to the configured max for the
-            // server and take the smaller one.
-            .map(|limit| min(limit, server_config.page_max_nitems))
+            // endpoint and take the smaller one.
+            .map(|limit| min(limit, max))
             // If no limit was provided by the client, use the configured
             // default.
-            .unwrap_or(server_config.page_default_nitems))
+            .unwrap_or(min(default, max))
     }
 }
 
@@ -340,9 +437,20 @@ macro_rules! impl_HttpHandlerFunc_for_func_with_params {
             _param_tuple: ($($T,)*)
         ) -> HttpHandlerResult
         {
+            if !rqctx.server_timing {
+                let response: ResponseType =
+                    (self)(rqctx, $(_param_tuple.$i,)*).await?;
+                return response.to_result();
+            }
+            let start = Instant::now();
             let response: ResponseType =
                 (self)(rqctx, $(_param_tuple.$i,)*).await?;
-            response.to_result()
+            let handler_done = Instant::now();
+            let mut response = response.to_result()?;
+            let timing = ServerTiming::of(&mut response);
+            timing.handler = Some(handler_done - start);
+            timing.serialize = Some(handler_done.elapsed());
+            Ok(response)
         }
     }
 }}
@@ -449,10 +557,16 @@ where
         // is resolved statically.makes them actual function arguments for the
         // actual handler function.  From this point down, all of this is
         // resolved statically.
+        let start = rqctx.server_timing.then(Instant::now);
         let funcparams =
             RequestExtractor::from_request(&rqctx, request).await?;
+        let extract_time = start.map(|start| start.elapsed());
         let future = self.handler.handle_request(rqctx, funcparams);
-        future.await
+        let mut response = future.await?;
+        if let Some(extract_time) = extract_time {
+            ServerTiming::of(&mut response).extract = Some(extract_time);
+        }
+        Ok(response)
     }
 }
 
@@ -488,6 +602,125 @@ where
     }
 }
 
+/// `StatusOverrideHandler` wraps another `RouteHandler`, changing the status
+/// (and possibly the reason phrase) of its successful responses from the one
+/// declared by its response type to another one.  See
+/// [`crate::ApiEndpoint::success_status`] and
+/// [`crate::ApiEndpoint::success_reason`].
+pub(crate) struct StatusOverrideHandler<Context: ServerContext> {
+    inner: Arc<dyn RouteHandler<Context>>,
+    declared: StatusCode,
+    status: StatusCode,
+    reason: Option<ReasonPhrase>,
+}
+
+impl<Context: ServerContext> Debug for StatusOverrideHandler<Context> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:?} (status {})", self.inner, self.status)
+    }
+}
+
+impl<Context: ServerContext> StatusOverrideHandler<Context> {
+    pub(crate) fn new(
+        inner: Arc<dyn RouteHandler<Context>>,
+        declared: StatusCode,
+        status: StatusCode,
+        reason: Option<ReasonPhrase>,
+    ) -> Arc<dyn RouteHandler<Context>> {
+        Arc::new(StatusOverrideHandler { inner, declared, status, reason })
+    }
+}
+
+#[async_trait]
+impl<Context: ServerContext> RouteHandler<Context>
+    for StatusOverrideHandler<Context>
+{
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    async fn handle_request(
+        &self,
+        rqctx: RequestContext<Context>,
+        request: hyper::Request<hyper::Body>,
+    ) -> HttpHandlerResult {
+        let mut response = self.inner.handle_request(rqctx, request).await?;
+        if response.status() == self.declared {
+            *response.status_mut() = self.status;
+            if let Some(reason) = &self.reason {
+                response.extensions_mut().insert(reason.clone());
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// `StubHandler` stands in for the handler of an endpoint in a stub of an API,
+/// responding to every request with an example of the endpoint's successful
+/// response.  See [`crate::ApiDescription::into_stub`].
+#[derive(Debug)]
+pub(crate) struct StubHandler {
+    operation_id: String,
+    status: StatusCode,
+    body: Option<Vec<u8>>,
+}
+
+impl StubHandler {
+    /// Returns a handler for the endpoint `operation_id` that responds with
+    /// `status` and `example` (serialized as JSON), if any
+    ///
+    /// Without an example, the response has no body if `status` is 204 ("No
+    /// Content"), and is a 501 ("Not Implemented") error otherwise.
+    pub(crate) fn new<Context: ServerContext>(
+        operation_id: &str,
+        status: StatusCode,
+        example: Option<&serde_json::Value>,
+    ) -> Arc<dyn RouteHandler<Context>> {
+        // Serializing a `serde_json::Value` can't fail.
+        let body = example.map(|value| serde_json::to_vec(value).unwrap());
+        Arc::new(StubHandler {
+            operation_id: operation_id.to_string(),
+            status,
+            body,
+        })
+    }
+}
+
+#[async_trait]
+impl<Context: ServerContext> RouteHandler<Context> for StubHandler {
+    fn label(&self) -> &str {
+        &self.operation_id
+    }
+
+    async fn handle_request(
+        &self,
+        _rqctx: RequestContext<Context>,
+        _request: hyper::Request<hyper::Body>,
+    ) -> HttpHandlerResult {
+        let builder = Response::builder().status(self.status);
+        match &self.body {
+            Some(body) => Ok(builder
+                .header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
+                .body(body.clone().into())?),
+            None if self.status == StatusCode::NO_CONTENT => {
+                Ok(builder.body(Body::empty())?)
+            }
+            None => {
+                let message = format!(
+                    "operation \"{}\" has no example response",
+                    self.operation_id
+                );
+                Err(HttpError {
+                    status_code: StatusCode::NOT_IMPLEMENTED,
+                    error_code: Some(String::from("NotImplemented")),
+                    external_message: message.clone(),
+                    internal_message: message,
+                })
+            }
+        }
+    }
+}
+
 // Response Type Conversion
 //
 // See the discussion on macro `impl_HttpHandlerFunc_for_func_with_params` for a
@@ -503,6 +736,11 @@ pub trait HttpResponse {
     /// Extract status code and structure metadata for the non-error response.
     /// Type information for errors is handled generically across all endpoints.
     fn response_metadata() -> ApiEndpointResponse;
+
+    /// Whether successful responses of this type may have a body.  Only types
+    /// whose responses never do may be sent with a status that forbids a body,
+    /// like 204 ("No Content").
+    const HAS_BODY: bool = true;
 }
 
 /// `Response<Body>` is used for free-form responses. The implementation of
@@ -552,6 +790,9 @@ pub trait HttpResponseContent {
     // with multiple, explicitly enumerated mime types.
     // TODO the ApiSchemaGenerator type is particularly inelegant.
     fn content_metadata() -> Option<ApiSchemaGenerator>;
+
+    /// Whether responses with this content have a body at all
+    const HAS_BODY: bool = true;
 }
 
 impl HttpResponseContent for FreeformBody {
@@ -570,6 +811,8 @@ impl HttpResponseContent for FreeformBody {
 }
 
 impl HttpResponseContent for Empty {
+    const HAS_BODY: bool = false;
+
     fn to_response(
         self,
         builder: http::response::Builder,
@@ -643,6 +886,8 @@ where
             ..Default::default()
         }
     }
+
+    const HAS_BODY: bool = T::Body::HAS_BODY;
 }
 
 /// `HttpResponseCreated<T: Serialize>` wraps an object of any serializable type.
@@ -691,6 +936,55 @@ impl<T: HttpResponseContent + Send + Sync + 'static>
     }
 }
 
+/// Describes headers associated with a 202 ("Accepted") response for an
+/// operation whose status can be polled.
+#[derive(JsonSchema, Serialize)]
+#[doc(hidden)]
+pub struct AcceptedHeaders {
+    /// where to check the status of the operation
+    // See `RedirectHeaders` for why this is a `String`.
+    location: String,
+    /// how many seconds the client should wait before checking the status
+    #[serde(rename = "retry-after")]
+    retry_after: Option<u64>,
+}
+
+/// See `http_response_accepted()`
+pub type HttpResponseAcceptedLocation<T> =
+    HttpResponseHeaders<HttpResponseAccepted<T>, AcceptedHeaders>;
+
+/// `http_response_accepted` returns an HTTP 202 ("Accepted") response for an
+/// operation that will complete in the background, with a body generated by
+/// serializing `body`.
+///
+/// `location` becomes the value of the `Location` header, which should
+/// identify a resource describing the status of the operation.  If
+/// `retry_after` is given, it becomes the value of the `Retry-After` header
+/// (in whole seconds, rounded up), suggesting how long the client should wait
+/// before checking.  Use [`crate::ApiEndpoint::response_link`] to link the
+/// response to the status operation in the OpenAPI document.
+pub fn http_response_accepted<
+    T: HttpResponseContent + Send + Sync + 'static,
+>(
+    body: T,
+    location: String,
+    retry_after: Option<std::time::Duration>,
+) -> Result<HttpResponseAcceptedLocation<T>, HttpError> {
+    let _ = http::HeaderValue::from_str(&location).map_err(|e| {
+        HttpError::for_internal_error(format!(
+            "error encoding Location header {:?}: {:#}",
+            location, e
+        ))
+    })?;
+    let retry_after = retry_after.map(|duration| {
+        duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+    });
+    Ok(HttpResponseHeaders::new(
+        HttpResponseAccepted(body),
+        AcceptedHeaders { location, retry_after },
+    ))
+}
+
 /// `HttpResponseOk<T: Serialize>` wraps an object of any serializable type.  It
 /// denotes an HTTP 200 "OK" response whose body is generated by serializing the
 /// object.
@@ -712,6 +1006,82 @@ impl<T: HttpResponseContent + Send + Sync + 'static> From<HttpResponseOk<T>>
     }
 }
 
+/// `HttpResponseWithStatus<T, STATUS>` wraps an object of any serializable
+/// type (or a [FreeformBody]).  It denotes an HTTP response with status code
+/// `STATUS` whose body is generated by serializing the object.  This is for
+/// less common status codes that have no dedicated type, like 207
+/// ("Multi-Status") or 226 ("IM Used"); the OpenAPI document describes the
+/// response with that status code, just like for the dedicated types.
+///
+/// `STATUS` must be a success (2xx) or redirection (3xx) status, and it must
+/// not be 204 ("No Content"), 205 ("Reset Content"), or 304 ("Not Modified")
+/// unless `T` is [`Empty`]; other values fail to compile (see
+/// [`validate_success_status`]).
+///
+/// ```
+/// use dropshot::HttpResponseWithStatus;
+///
+/// type MultiStatus = HttpResponseWithStatus<Vec<String>, 207>;
+/// let response: MultiStatus = HttpResponseWithStatus(vec![]);
+/// assert_eq!(MultiStatus::status_code(), http::StatusCode::MULTI_STATUS);
+/// ```
+pub struct HttpResponseWithStatus<
+    T: HttpResponseContent + Send + Sync + 'static,
+    const STATUS: u16,
+>(pub T);
+impl<T: HttpResponseContent + Send + Sync + 'static, const STATUS: u16>
+    HttpResponseWithStatus<T, STATUS>
+{
+    const VALID: () = validate_success_status(STATUS, T::HAS_BODY);
+
+    /// Returns `STATUS` as a status code
+    pub fn status_code() -> StatusCode {
+        let () = Self::VALID;
+        StatusCode::from_u16(STATUS).unwrap()
+    }
+}
+impl<T: HttpResponseContent + Send + Sync + 'static, const STATUS: u16>
+    HttpResponse for HttpResponseWithStatus<T, STATUS>
+{
+    fn to_result(self) -> HttpHandlerResult {
+        self.0.to_response(Response::builder().status(Self::status_code()))
+    }
+    fn response_metadata() -> ApiEndpointResponse {
+        let status = Self::status_code();
+        ApiEndpointResponse {
+            schema: T::content_metadata(),
+            success: Some(status),
+            description: status.canonical_reason().map(str::to_lowercase),
+            ..Default::default()
+        }
+    }
+
+    const HAS_BODY: bool = T::HAS_BODY;
+}
+
+/// Checks that `status` can be the status of a successful response, given
+/// whether the response has a body
+///
+/// The status must be a success (2xx) or redirection (3xx) status, and
+/// responses with status 204 ("No Content"), 205 ("Reset Content"), or 304
+/// ("Not Modified") must not have a body.  This is evaluated at compile time
+/// for [`HttpResponseWithStatus`], and `#[endpoint]` applies the same rules to
+/// its `status`.
+///
+/// # Panics
+///
+/// Panics if `status` is not acceptable.
+pub const fn validate_success_status(status: u16, has_body: bool) {
+    assert!(
+        200 <= status && status < 400,
+        "success status must be a 2xx or 3xx status code"
+    );
+    assert!(
+        !has_body || !matches!(status, 204 | 205 | 304),
+        "responses with status 204, 205, or 304 must not have a body"
+    );
+}
+
 /// `HttpResponseDeleted` represents an HTTP 204 "No Content" response, intended
 /// for use when an API operation has successfully deleted an object.
 pub struct HttpResponseDeleted();
@@ -895,6 +1265,47 @@ impl From<HttpResponseTemporaryRedirectStatus> for HttpHandlerResult {
     }
 }
 
+/// See `http_response_permanent_redirect()`
+pub type HttpResponsePermanentRedirect =
+    HttpResponseHeaders<HttpResponsePermanentRedirectStatus, RedirectHeaders>;
+
+/// `http_response_permanent_redirect` represents an HTTP 308 "Permanent
+/// Redirect" response with no response body.
+///
+/// The sole argument will become the value of the `Location` header.  This is
+/// where you want to redirect the client to.
+///
+/// Use this when a resource has moved for good: clients may remember the new
+/// location and use it for future requests.  Like 307 ("Temporary Redirect"),
+/// this tells the client to use the same request method and body when it makes
+/// the follow-up request.
+pub fn http_response_permanent_redirect(
+    location: String,
+) -> Result<HttpResponsePermanentRedirect, HttpError> {
+    let _ = http::HeaderValue::from_str(&location)
+        .map_err(|e| http_redirect_error(e, &location))?;
+    Ok(HttpResponseHeaders::new(
+        HttpResponsePermanentRedirectStatus,
+        RedirectHeaders { location },
+    ))
+}
+
+/// This internal type impls HttpCodedResponse.  Consumers should use
+/// `HttpResponsePermanentRedirect` instead, which includes metadata about the
+/// `Location` header.
+#[doc(hidden)]
+pub struct HttpResponsePermanentRedirectStatus;
+impl HttpCodedResponse for HttpResponsePermanentRedirectStatus {
+    type Body = Empty;
+    const STATUS_CODE: StatusCode = StatusCode::PERMANENT_REDIRECT;
+    const DESCRIPTION: &'static str = "redirect (permanent redirect)";
+}
+impl From<HttpResponsePermanentRedirectStatus> for HttpHandlerResult {
+    fn from(_: HttpResponsePermanentRedirectStatus) -> HttpHandlerResult {
+        HttpResponsePermanentRedirectStatus::for_object(Empty)
+    }
+}
+
 #[derive(Serialize, JsonSchema)]
 pub struct NoHeaders {}
 
@@ -906,6 +1317,40 @@ pub struct NoHeaders {}
 /// named headers, consumers may add additional headers via the `headers_mut`
 /// interface. Unnamed headers override named headers in the case of naming
 /// conflicts.
+///
+/// Each field of the named headers struct becomes a header, named after the
+/// field (use `#[serde(rename = "...")]` for names that aren't valid Rust
+/// identifiers).  Fields may be strings, integers, floating-point numbers,
+/// booleans, unit enum variants, or newtypes around any of these, and their
+/// types are reflected in the OpenAPI document.  Fields of type `Option` are
+/// documented as optional and the header is omitted when they're `None`.
+///
+/// ```
+/// use dropshot::HttpResponseHeaders;
+/// use dropshot::HttpResponseOk;
+/// use schemars::JsonSchema;
+/// use serde::Serialize;
+///
+/// #[derive(JsonSchema, Serialize)]
+/// struct RateLimitHeaders {
+///     /// requests remaining in the current window
+///     #[serde(rename = "x-ratelimit-remaining")]
+///     remaining: u64,
+///     /// current version of the resource
+///     #[serde(rename = "ETag")]
+///     etag: Option<String>,
+/// }
+///
+/// type MyResponse = HttpResponseHeaders<
+///     HttpResponseOk<String>,
+///     RateLimitHeaders,
+/// >;
+///
+/// let response: MyResponse = HttpResponseHeaders::new(
+///     HttpResponseOk(String::from("hello")),
+///     RateLimitHeaders { remaining: 99, etag: None },
+/// );
+/// ```
 pub struct HttpResponseHeaders<
     T: HttpCodedResponse,
     H: JsonSchema + Serialize + Send + Sync + 'static = NoHeaders,
@@ -0,0 +1,364 @@
+// Copyright 2024 Oxide Computer Company
+//! Serving HTTP/3 over QUIC (experimental)
+//!
+//! See [`crate::ConfigHttp3`].  Requests are converted to the same
+//! `hyper::Request<Body>` that the HTTP/1 and HTTP/2 listeners produce and
+//! handed to the same request handling code, and the resulting responses are
+//! streamed back over the request's QUIC stream.
+
+use crate::config::{ConfigHttp3, ConfigTls};
+use crate::server::{
+    http_request_handle_wrap, io_error, DropshotState, ServerContext,
+    ServerPhase,
+};
+use bytes::{Buf, Bytes};
+use h3::error::ErrorLevel;
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tracing::{debug, trace, warn};
+
+type RequestStream =
+    h3::server::RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// An HTTP/3 listener that has been bound, but that isn't yet accepting
+/// connections
+pub(crate) struct Http3Listener {
+    endpoint: quinn::Endpoint,
+    local_addr: SocketAddr,
+    alt_svc_max_age_secs: u64,
+}
+
+impl Http3Listener {
+    /// Binds a QUIC endpoint as described by `config`, identifying itself
+    /// with the certificate and key in `tls`
+    pub(crate) fn bind(
+        config: &ConfigHttp3,
+        tls: &ConfigTls,
+    ) -> std::io::Result<Http3Listener> {
+        let crypto = quic_tls_config(tls)?;
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let socket = std::net::UdpSocket::bind(config.bind_address)?;
+        let endpoint = quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            Some(server_config),
+            socket,
+            Arc::new(quinn::TokioRuntime),
+        )?;
+        let local_addr = endpoint.local_addr()?;
+        Ok(Http3Listener {
+            endpoint,
+            local_addr,
+            alt_svc_max_age_secs: config.alt_svc_max_age_secs,
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the value of the `Alt-Svc` header that advertises this
+    /// listener, if it's to be advertised
+    pub(crate) fn alt_svc(&self) -> Option<http::HeaderValue> {
+        if self.alt_svc_max_age_secs == 0 {
+            return None;
+        }
+        let value = format!(
+            "h3=\":{}\"; ma={}",
+            self.local_addr.port(),
+            self.alt_svc_max_age_secs
+        );
+        Some(http::HeaderValue::try_from(value).unwrap())
+    }
+
+    /// Starts accepting connections for the server described by `app_state`
+    /// (see [`Http3ServerStarter`])
+    pub(crate) fn with_state<C: ServerContext>(
+        self,
+        app_state: Arc<DropshotState<C>>,
+    ) -> Http3ServerStarter<C> {
+        Http3ServerStarter { listener: self, app_state }
+    }
+}
+
+/// Serves HTTP/3 for a server once started
+pub(crate) struct Http3ServerStarter<C: ServerContext> {
+    listener: Http3Listener,
+    app_state: Arc<DropshotState<C>>,
+}
+
+impl<C: ServerContext> Http3ServerStarter<C> {
+    /// Begins accepting connections, until `close_signal` completes
+    ///
+    /// Once it does, clients are asked (with a `GOAWAY` frame) not to send any
+    /// more requests, and the returned task completes once those in progress
+    /// have been handled, or once graceful shutdown times out.
+    pub(crate) fn start<F>(
+        self,
+        close_signal: F,
+    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Http3ServerStarter { listener, app_state } = self;
+        let endpoint = listener.endpoint;
+        tokio::spawn(async move {
+            let mut connections = JoinSet::new();
+            tokio::pin!(close_signal);
+            loop {
+                tokio::select! {
+                    () = &mut close_signal => break,
+                    connecting = endpoint.accept() => {
+                        let Some(connecting) = connecting else { break };
+                        connections.spawn(serve_connection(
+                            Arc::clone(&app_state),
+                            connecting,
+                        ));
+                    }
+                    Some(_) = connections.join_next(), if
+                        !connections.is_empty() => (),
+                }
+            }
+
+            // Connections notice that the server is shutting down themselves,
+            // so all that's left is to wait for them.  If graceful shutdown
+            // times out, closing the endpoint closes them all immediately.
+            let mut aborting = app_state.aborting.subscribe();
+            tokio::select! {
+                () = async {
+                    while connections.join_next().await.is_some() {}
+                } => (),
+                Ok(_) = aborting.wait_for(|a| *a) => {
+                    endpoint.close(0u32.into(), b"server shutting down");
+                }
+            }
+            endpoint.wait_idle().await;
+            Ok(())
+        })
+    }
+}
+
+/// Handles the requests received on one HTTP/3 connection until the client
+/// closes it or the server shuts down
+async fn serve_connection<C: ServerContext>(
+    app_state: Arc<DropshotState<C>>,
+    connecting: quinn::Connecting,
+) {
+    let remote_addr = connecting.remote_address();
+    let Some(_admitted) = app_state.connection_filter.admit(remote_addr) else {
+        return;
+    };
+    let connection = match connecting.await {
+        Ok(connection) => connection,
+        Err(error) => {
+            debug!(remote_addr = %remote_addr, %error, "QUIC handshake failed");
+            return;
+        }
+    };
+    trace!(remote_addr = %remote_addr, "accepted HTTP/3 connection");
+
+    let mut h3_connection: h3::server::Connection<h3_quinn::Connection, Bytes> =
+        match h3::server::Connection::new(h3_quinn::Connection::new(connection))
+            .await
+        {
+            Ok(h3_connection) => h3_connection,
+            Err(error) => {
+                debug!(
+                    remote_addr = %remote_addr,
+                    %error,
+                    "failed to establish HTTP/3 connection"
+                );
+                return;
+            }
+        };
+
+    let mut phase = app_state.phase.subscribe();
+    let mut goaway_sent = false;
+    let mut requests = JoinSet::new();
+    loop {
+        tokio::select! {
+            // The guard returned by `wait_for` isn't `Send`, so it has to be
+            // dropped before anything else is awaited.
+            _ = async {
+                let _ = phase
+                    .wait_for(|p| *p == ServerPhase::ShuttingDown)
+                    .await;
+            }, if !goaway_sent =>
+            {
+                goaway_sent = true;
+                if let Err(error) = h3_connection.shutdown(0).await {
+                    debug!(%error, "failed to shut down HTTP/3 connection");
+                    break;
+                }
+            }
+            accepted = h3_connection.accept() => match accepted {
+                Ok(Some((request, stream))) => {
+                    requests.spawn(serve_request(
+                        Arc::clone(&app_state),
+                        remote_addr,
+                        request,
+                        stream,
+                    ));
+                }
+                Ok(None) => break,
+                Err(error) => match error.get_error_level() {
+                    ErrorLevel::ConnectionError => {
+                        debug!(%error, "HTTP/3 connection failed");
+                        break;
+                    }
+                    ErrorLevel::StreamError => {
+                        debug!(%error, "failed to receive HTTP/3 request");
+                    }
+                },
+            },
+        }
+    }
+
+    while requests.join_next().await.is_some() {}
+}
+
+/// Handles one HTTP/3 request, sending the response on `stream`
+async fn serve_request<C: ServerContext>(
+    app_state: Arc<DropshotState<C>>,
+    remote_addr: SocketAddr,
+    request: Request<()>,
+    stream: RequestStream,
+) {
+    let (mut send_stream, mut recv_stream) = stream.split();
+
+    // The request body is passed along as it arrives, so that it's subject to
+    // the usual limits on its size.
+    let (mut body_sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            match recv_stream.recv_data().await {
+                Ok(Some(mut data)) => {
+                    let data = data.copy_to_bytes(data.remaining());
+                    if body_sender.send_data(data).await.is_err() {
+                        // The handler is no longer reading the body.
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    debug!(%error, "failed to receive HTTP/3 request body");
+                    body_sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+    let (parts, ()) = request.into_parts();
+    let request = Request::from_parts(parts, body);
+
+    let response =
+        match http_request_handle_wrap(app_state, remote_addr, request).await {
+            Ok(response) => response,
+            Err(error) => {
+                warn!(%error, "failed to handle HTTP/3 request");
+                return;
+            }
+        };
+
+    let (mut parts, mut body) = response.into_parts();
+    // HTTP/3 doesn't allow connection-specific header fields.
+    parts.headers.remove(http::header::CONNECTION);
+    parts.headers.remove(http::header::TRANSFER_ENCODING);
+    if let Err(error) =
+        send_stream.send_response(Response::from_parts(parts, ())).await
+    {
+        debug!(%error, "failed to send HTTP/3 response");
+        return;
+    }
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                warn!(%error, "failed to produce HTTP/3 response body");
+                return;
+            }
+        };
+        if let Err(error) = send_stream.send_data(chunk).await {
+            debug!(%error, "failed to send HTTP/3 response body");
+            return;
+        }
+    }
+    match body.trailers().await {
+        Ok(Some(trailers)) => {
+            if let Err(error) = send_stream.send_trailers(trailers).await {
+                debug!(%error, "failed to send HTTP/3 response trailers");
+                return;
+            }
+        }
+        Ok(None) => (),
+        Err(error) => {
+            warn!(%error, "failed to produce HTTP/3 response trailers");
+            return;
+        }
+    }
+    if let Err(error) = send_stream.finish().await {
+        debug!(%error, "failed to finish HTTP/3 response");
+    }
+}
+
+/// Builds the TLS configuration for QUIC from the server's TLS configuration
+///
+/// QUIC support is built on a different version of rustls than the rest of
+/// the server, so the certificate and key are loaded separately.
+fn quic_tls_config(
+    tls: &ConfigTls,
+) -> std::io::Result<rustls_021::ServerConfig> {
+    let (certs, key) = match tls {
+        ConfigTls::AsFile { cert_file, key_file } => {
+            let read = |path: &std::path::Path| {
+                std::fs::read(path).map_err(|e| {
+                    io_error(format!(
+                        "failed to open {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })
+            };
+            (read(cert_file)?, read(key_file)?)
+        }
+        ConfigTls::AsBytes { certs, key } => (certs.clone(), key.clone()),
+        ConfigTls::Dynamic(_) | ConfigTls::WithClientAuth { .. } => {
+            return Err(io_error(
+                "HTTP/3 requires a TLS configuration from a certificate and \
+                 key, without client authentication"
+                    .into(),
+            ));
+        }
+    };
+
+    let certs = rustls_pemfile::certs(&mut certs.as_slice())
+        .map(|cert| cert.map(|cert| rustls_021::Certificate(cert.to_vec())))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            io_error(format!("failed to load certificate: {err}"))
+        })?;
+    let keys = rustls_pemfile::pkcs8_private_keys(&mut key.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            io_error(format!("failed to load private key: {err}"))
+        })?;
+    let mut keys_iter = keys.into_iter();
+    let (Some(private_key), None) = (keys_iter.next(), keys_iter.next()) else {
+        return Err(io_error("expected a single private key".into()));
+    };
+    let private_key =
+        rustls_021::PrivateKey(private_key.secret_pkcs8_der().to_vec());
+
+    let mut config = rustls_021::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .map_err(|err| {
+            io_error(format!("invalid certificate or key: {err}"))
+        })?;
+    config.alpn_protocols = vec![b"h3".to_vec()];
+    Ok(config)
+}
@@ -5,8 +5,8 @@ use bytes::Bytes;
 use hyper::body::HttpBody;
 use serde::de::DeserializeOwned;
 
-use super::error::HttpError;
-use crate::from_map::from_map;
+use crate::extractor::PathError;
+use crate::from_map::from_map_detailed;
 use crate::router::VariableSet;
 
 /// header name for conveying request ids ("x-request-id")
@@ -21,6 +21,10 @@ pub const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
 pub const CONTENT_TYPE_URL_ENCODED: &str = "application/x-www-form-urlencoded";
 /// MIME type for multipart/form-data
 pub const CONTENT_TYPE_MULTIPART_FORM_DATA: &str = "multipart/form-data";
+/// MIME type for RFC 9457 problem details
+pub const CONTENT_TYPE_PROBLEM_JSON: &str = "application/problem+json";
+/// MIME type for plain text
+pub const CONTENT_TYPE_TEXT_PLAIN: &str = "text/plain; charset=utf-8";
 
 /// Reads the rest of the body from the request, dropping all the bytes.  This is
 /// useful after encountering error conditions.
@@ -72,8 +76,8 @@ where
 /// TODO-testing: Add automated tests.
 pub fn http_extract_path_params<T: DeserializeOwned>(
     path_params: &VariableSet,
-) -> Result<T, HttpError> {
-    from_map(path_params).map_err(|message| {
+) -> Result<T, PathError> {
+    from_map_detailed(path_params).map_err(|error| {
         // TODO-correctness We'd like to assert that the error here is a bad
         // type, not a missing field.  If it's a missing field, then we somehow
         // allowed somebody to register a handler function for a path where the
@@ -83,13 +87,120 @@ pub fn http_extract_path_params<T: DeserializeOwned>(
         // even with our own deserializer, we'd also have to build our
         // own serde::de::Error impl in order to distinguish this particular
         // case.  For now, we resort to parsing the error message.
-        // TODO-correctness The error message produced in the type-error case
-        // (that end users will see) does not indicate which path parameter was
-        // invalid.  That's pretty bad for end users.
-        assert!(!message.starts_with("missing field: "));
-        HttpError::for_bad_request(
-            None,
-            format!("bad parameter in URL path: {}", message),
-        )
+        assert!(!error.message.starts_with("missing field: "));
+        PathError {
+            name: error.field,
+            value: error.value,
+            expected: error.expected,
+            message: error.message,
+        }
     })
 }
+
+/// Given the value of a request's `Accept` header, choose which of the
+/// `offered` media types (listed in order of the server's preference) to use
+/// for the response.
+///
+/// Each offered type is assigned the quality value of the most specific media
+/// range in the header that matches it (e.g., `text/plain` beats `text/*`,
+/// which beats `*/*`).  The offered type with the highest quality wins, with
+/// ties going to whichever the server listed first.  If there's no `Accept`
+/// header (or it can't be parsed), the first offered type is chosen.  If the
+/// client finds none of the offered types acceptable, this returns `None`.
+pub(crate) fn negotiate_media_type<'a>(
+    accept: Option<&http::HeaderValue>,
+    offered: &[&'a str],
+) -> Option<&'a str> {
+    let Some(accept) = accept.and_then(|a| a.to_str().ok()) else {
+        return offered.first().copied();
+    };
+
+    let ranges = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let media_range = parts.next()?.trim().to_ascii_lowercase();
+            let (range_type, range_subtype) = media_range.split_once('/')?;
+            let quality = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, value)| value.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((range_type.to_string(), range_subtype.to_string(), quality))
+        })
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return offered.first().copied();
+    }
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for candidate in offered {
+        // Ignore parameters like "charset" on the offered type.
+        let essence = candidate.split(';').next().unwrap_or("").trim();
+        let Some((ctype, csubtype)) = essence.split_once('/') else {
+            continue;
+        };
+        let quality = ranges
+            .iter()
+            .filter_map(|(rtype, rsubtype, quality)| {
+                let specificity = match (rtype.as_str(), rsubtype.as_str()) {
+                    ("*", "*") => 0,
+                    (t, "*") if t.eq_ignore_ascii_case(ctype) => 1,
+                    (t, s)
+                        if t.eq_ignore_ascii_case(ctype)
+                            && s.eq_ignore_ascii_case(csubtype) =>
+                    {
+                        2
+                    }
+                    _ => return None,
+                };
+                Some((specificity, *quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0);
+        if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+            best = Some((candidate, quality));
+        }
+    }
+
+    best.map(|(candidate, _)| candidate)
+}
+
+#[cfg(test)]
+mod test {
+    use super::negotiate_media_type;
+
+    const OFFERED: [&str; 3] =
+        ["application/json", "application/problem+json", "text/plain"];
+
+    fn negotiate(accept: Option<&'static str>) -> Option<&'static str> {
+        let accept = accept.map(http::HeaderValue::from_static);
+        negotiate_media_type(accept.as_ref(), &OFFERED)
+    }
+
+    #[test]
+    fn test_negotiate_media_type() {
+        assert_eq!(negotiate(None), Some("application/json"));
+        assert_eq!(negotiate(Some("*/*")), Some("application/json"));
+        assert_eq!(negotiate(Some("text/plain")), Some("text/plain"));
+        assert_eq!(negotiate(Some("text/*")), Some("text/plain"));
+        assert_eq!(
+            negotiate(Some("application/problem+json, */*;q=0.1")),
+            Some("application/problem+json")
+        );
+        assert_eq!(
+            negotiate(Some("text/plain;q=0.5, application/json;q=0.9")),
+            Some("application/json")
+        );
+        // The most specific matching range determines an offered type's
+        // quality.
+        assert_eq!(
+            negotiate(Some("*/*;q=0.8, application/json;q=0.1")),
+            Some("application/problem+json")
+        );
+        assert_eq!(negotiate(Some("image/png")), None);
+        assert_eq!(negotiate(Some("text/plain;q=0")), None);
+        assert_eq!(negotiate(Some("garbage")), Some("application/json"));
+    }
+}
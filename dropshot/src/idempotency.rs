@@ -0,0 +1,540 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for idempotent retries of non-idempotent requests via the
+//! `Idempotency-Key` header
+//!
+//! Clients that can't tell whether a `POST` succeeded (e.g., because the
+//! connection dropped before the response arrived) may retry it with the same
+//! `Idempotency-Key`.  [`IdempotencyMiddleware`] records the response to the
+//! first request with each key in an [`IdempotencyStore`] and replays it for
+//! any retries, so that the operation is carried out at most once.  Keys can
+//! be kept separate for each client (see
+//! [`IdempotencyMiddleware::with_principal`]).  Handlers
+//! that want to see the key themselves (e.g., to pass it along to a
+//! downstream service) can use the [`IdempotencyKey`] extractor.
+
+use crate::api_description::{ApiEndpointBodyContentType, ExtensionMode};
+use crate::error::HttpError;
+use crate::http_util::HEADER_REQUEST_ID;
+use crate::server::{DropshotState, Middleware, ServerContext};
+use crate::{
+    ExtractorMetadata, RequestContext, SharedExtractor, StreamingBody,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use debug_ignore::DebugIgnore;
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use hyper::{Body, Request, Response};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Header used by clients to identify retries of the same operation
+pub const HEADER_IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Header added to responses that were replayed from an [`IdempotencyStore`]
+/// rather than produced by running the handler
+pub const HEADER_IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Longest `Idempotency-Key` we accept
+const IDEMPOTENCY_KEY_MAX_LEN: usize = 255;
+
+/// `IdempotencyKey` is an extractor for the value of the request's
+/// `Idempotency-Key` header.  Requests without a valid key fail with a 400.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+
+    /// Parses the key from a header value, which must be a non-empty string
+    /// of at most 255 visible ASCII characters.
+    fn from_header(value: &HeaderValue) -> Result<IdempotencyKey, HttpError> {
+        let key = value
+            .to_str()
+            .ok()
+            .map(|key| key.trim_matches('"'))
+            .filter(|key| {
+                !key.is_empty()
+                    && key.len() <= IDEMPOTENCY_KEY_MAX_LEN
+                    && key.bytes().all(|b| b.is_ascii_graphic())
+            })
+            .ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    format!(
+                        "invalid {} header: expected 1 to {} visible ASCII \
+                         characters",
+                        HEADER_IDEMPOTENCY_KEY, IDEMPOTENCY_KEY_MAX_LEN
+                    ),
+                )
+            })?;
+        Ok(IdempotencyKey(key.to_string()))
+    }
+}
+
+#[async_trait]
+impl SharedExtractor for IdempotencyKey {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+    ) -> Result<IdempotencyKey, HttpError> {
+        let value =
+            rqctx.request.headers().get(HEADER_IDEMPOTENCY_KEY).ok_or_else(
+                || {
+                    HttpError::for_bad_request(
+                        None,
+                        format!("missing {} header", HEADER_IDEMPOTENCY_KEY),
+                    )
+                },
+            )?;
+        IdempotencyKey::from_header(value)
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        ExtractorMetadata {
+            parameters: vec![],
+            extension_mode: ExtensionMode::None,
+        }
+    }
+}
+
+/// A response recorded by [`IdempotencyMiddleware`] for replay
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// State of an idempotency key, as reported by
+/// [`IdempotencyStore::reserve`]
+#[derive(Clone, Debug)]
+pub enum IdempotencyRecord {
+    /// The key was not in use and has now been reserved for the caller
+    Reserved,
+    /// Another request with this key is still being processed
+    InProgress { fingerprint: String },
+    /// A request with this key has already completed
+    Completed { fingerprint: String, response: StoredResponse },
+}
+
+/// Storage for the responses used by [`IdempotencyMiddleware`]
+///
+/// Implementations that are shared by several server instances (e.g., backed
+/// by a database) must implement `reserve` atomically, so that only one of
+/// several concurrent requests with the same key is told that it has reserved
+/// the key.  See [`InMemoryIdempotencyStore`] for an implementation suitable
+/// for a single server.
+///
+/// Stores that expire keys should measure time with the `now` given to
+/// `reserve` (the time according to the server's clock, see
+/// [`crate::HttpServerStarter::clock`]) rather than the system clock, so that
+/// tests can control it.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync + Debug + 'static {
+    /// Reserves `key` for a request identified by `fingerprint`, unless it's
+    /// already in use, in which case this returns its current state.  The
+    /// request arrived at `now`.
+    async fn reserve(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        now: DateTime<Utc>,
+    ) -> IdempotencyRecord;
+
+    /// Records the response to the request that reserved `key`.
+    async fn complete(&self, key: &str, response: StoredResponse);
+
+    /// Releases a reservation for `key` without recording a response, so
+    /// that the request can be retried.
+    async fn release(&self, key: &str);
+}
+
+/// [`IdempotencyStore`] that keeps responses in memory for a fixed period
+#[derive(Debug)]
+pub struct InMemoryIdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, InMemoryEntry>>,
+}
+
+#[derive(Debug)]
+struct InMemoryEntry {
+    fingerprint: String,
+    response: Option<StoredResponse>,
+    expires: DateTime<Utc>,
+}
+
+impl InMemoryIdempotencyStore {
+    /// Returns a store that forgets each key `ttl` after it was first used
+    ///
+    /// Keys whose requests never complete (e.g., because the handler was
+    /// cancelled) also become available again after `ttl`.  Time is measured
+    /// by the server's clock.
+    pub fn new(ttl: Duration) -> InMemoryIdempotencyStore {
+        InMemoryIdempotencyStore { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn reserve(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        now: DateTime<Utc>,
+    ) -> IdempotencyRecord {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires > now);
+        match entries.get(key) {
+            Some(InMemoryEntry { fingerprint, response: None, .. }) => {
+                IdempotencyRecord::InProgress {
+                    fingerprint: fingerprint.clone(),
+                }
+            }
+            Some(InMemoryEntry {
+                fingerprint,
+                response: Some(response),
+                ..
+            }) => IdempotencyRecord::Completed {
+                fingerprint: fingerprint.clone(),
+                response: response.clone(),
+            },
+            None => {
+                entries.insert(
+                    key.to_string(),
+                    InMemoryEntry {
+                        fingerprint: fingerprint.to_string(),
+                        response: None,
+                        expires: chrono::Duration::from_std(self.ttl)
+                            .ok()
+                            .and_then(|ttl| now.checked_add_signed(ttl))
+                            .unwrap_or(DateTime::<Utc>::MAX_UTC),
+                    },
+                );
+                IdempotencyRecord::Reserved
+            }
+        }
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    async fn release(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// [`Middleware`] that replays responses to `POST` and `PATCH` requests that
+/// carry an `Idempotency-Key` already seen by the server
+///
+/// The first request with a given key runs as usual, and its response is
+/// recorded in the [`IdempotencyStore`].  Later requests with the same key get
+/// the recorded response (with an `Idempotent-Replayed: true` header) without
+/// running the handler.  A request that arrives while the first one is still
+/// running gets a 409, and one that reuses a key for a different method, URI,
+/// or body gets a 422.
+///
+/// Request bodies are buffered (up to the endpoint's
+/// [`crate::ApiEndpoint::request_body_max_bytes`], or else the server's
+/// [`crate::ConfigDropshot::request_body_max_bytes`]) so that their SHA-256
+/// digest can be recorded along with the method and URI.  Bodies of requests
+/// to endpoints that stream them (e.g., with [`crate::StreamingBody`] or
+/// [`crate::SpooledBody`]; see [`crate::ExclusiveExtractor::STREAMS_BODY`])
+/// are passed through unbuffered instead, so retries of those are only told
+/// apart from other requests by their method and URI.  Responses are buffered
+/// in full so that they can be recorded.  Error responses with 5xx status codes
+/// are not recorded, so that the client can retry.  Requests without an
+/// `Idempotency-Key` header, and requests using other methods, are passed
+/// through untouched.
+#[derive(Debug)]
+pub struct IdempotencyMiddleware<S: IdempotencyStore> {
+    store: S,
+    principal: Option<DebugIgnore<Box<PrincipalFn>>>,
+}
+
+/// Identifies the client on whose behalf a request was made (see
+/// [`IdempotencyMiddleware::with_principal`])
+type PrincipalFn = dyn Fn(&Request<Body>) -> Option<String> + Send + Sync;
+
+impl<S: IdempotencyStore> IdempotencyMiddleware<S> {
+    pub fn new(store: S) -> IdempotencyMiddleware<S> {
+        IdempotencyMiddleware { store, principal: None }
+    }
+
+    /// Keeps each client's idempotency keys separate, so that one client
+    /// can't replay (or block) another's operations by guessing its keys
+    ///
+    /// `principal` identifies the client that made a request, typically from
+    /// its credentials.  Keys are only matched against earlier requests with
+    /// the same principal.  Requests for which it returns `None` share a
+    /// single namespace, as do all requests if no principal is configured.
+    pub fn with_principal<F>(mut self, principal: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.principal = Some(DebugIgnore(Box::new(principal)));
+        self
+    }
+}
+
+/// Returns the key under which a request's record is stored
+///
+/// Idempotency keys never contain spaces, so the key and principal can always
+/// be told apart.
+fn store_key(key: &IdempotencyKey, principal: Option<&str>) -> String {
+    match principal {
+        Some(principal) => format!("{} {}", key.as_str(), principal),
+        None => key.as_str().to_string(),
+    }
+}
+
+/// Returns a digest identifying a request with the given method, URI, and
+/// body (unless it's streamed to the handler), so that retries can be told
+/// apart from other requests reusing the same key
+fn fingerprint(
+    method: &Method,
+    uri: &http::Uri,
+    body: Option<&[u8]>,
+) -> String {
+    use std::fmt::Write;
+    let mut fingerprint = format!("{} {}", method, uri);
+    if let Some(body) = body {
+        let digest = ring::digest::digest(&ring::digest::SHA256, body);
+        fingerprint.push_str(" sha256:");
+        for b in digest.as_ref() {
+            write!(fingerprint, "{:02x}", b).unwrap();
+        }
+    }
+    fingerprint
+}
+
+#[async_trait]
+impl<C: ServerContext, S: IdempotencyStore> Middleware<C>
+    for IdempotencyMiddleware<S>
+{
+    async fn handle(
+        &self,
+        server: Arc<DropshotState<C>>,
+        request: Request<Body>,
+        request_id: String,
+        remote_addr: SocketAddr,
+        next: fn(
+            Arc<DropshotState<C>>,
+            Request<Body>,
+            String,
+            SocketAddr,
+        ) -> Pin<
+            Box<dyn Future<Output = Result<Response<Body>, HttpError>> + Send>,
+        >,
+    ) -> Result<Response<Body>, HttpError> {
+        let header = request.headers().get(HEADER_IDEMPOTENCY_KEY);
+        let (Some(header), &Method::POST | &Method::PATCH) =
+            (header, request.method())
+        else {
+            return next(server, request, request_id, remote_addr).await;
+        };
+
+        let key = IdempotencyKey::from_header(header)?;
+        let principal = self.principal.as_ref().and_then(|f| f(&request));
+        let key = store_key(&key, principal.as_deref());
+        let route = server.route_of(&request);
+        let (fingerprint, request) = if route
+            .as_ref()
+            .is_some_and(|route| route.request_body_streamed)
+        {
+            (fingerprint(request.method(), request.uri(), None), request)
+        } else {
+            let request_body_max_bytes = route
+                .and_then(|route| route.request_body_max_bytes)
+                .unwrap_or(server.config.request_body_max_bytes);
+            let (parts, body) = request.into_parts();
+            let body = StreamingBody::new(body, request_body_max_bytes)
+                .into_bytes()
+                .await?;
+            let fingerprint =
+                fingerprint(&parts.method, &parts.uri, Some(&body));
+            (fingerprint, Request::from_parts(parts, Body::from(body)))
+        };
+        let check_fingerprint = |stored: &str| {
+            if stored == fingerprint {
+                Ok(())
+            } else {
+                Err(HttpError::for_client_error(
+                    None,
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    String::from(
+                        "idempotency key was already used for a different \
+                         request",
+                    ),
+                ))
+            }
+        };
+
+        match self.store.reserve(&key, &fingerprint, server.now()).await {
+            IdempotencyRecord::Reserved => (),
+            IdempotencyRecord::InProgress { fingerprint } => {
+                check_fingerprint(&fingerprint)?;
+                return Err(HttpError::for_client_error(
+                    None,
+                    StatusCode::CONFLICT,
+                    String::from(
+                        "a request with this idempotency key is still in \
+                         progress",
+                    ),
+                ));
+            }
+            IdempotencyRecord::Completed { fingerprint, response } => {
+                check_fingerprint(&fingerprint)?;
+                let mut replayed = Response::new(Body::from(response.body));
+                *replayed.status_mut() = response.status;
+                *replayed.headers_mut() = response.headers;
+                replayed.headers_mut().insert(
+                    HEADER_IDEMPOTENT_REPLAYED,
+                    HeaderValue::from_static("true"),
+                );
+                if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+                    replayed
+                        .headers_mut()
+                        .insert(HEADER_REQUEST_ID, request_id);
+                }
+                return Ok(replayed);
+            }
+        }
+
+        let response = match next(server, request, request_id, remote_addr)
+            .await
+        {
+            Ok(response) if !response.status().is_server_error() => response,
+            result => {
+                self.store.release(&key).await;
+                return result;
+            }
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(error) => {
+                self.store.release(&key).await;
+                return Err(HttpError::for_internal_error(format!(
+                    "reading response body: {}",
+                    error
+                )));
+            }
+        };
+        // Each replay will get its own request id.
+        let mut headers = parts.headers.clone();
+        headers.remove(HEADER_REQUEST_ID);
+        self.store
+            .complete(
+                &key,
+                StoredResponse {
+                    status: parts.status,
+                    headers,
+                    body: body.clone(),
+                },
+            )
+            .await;
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IdempotencyKey;
+    use super::IdempotencyRecord;
+    use super::IdempotencyStore;
+    use super::InMemoryIdempotencyStore;
+    use super::StoredResponse;
+    use chrono::Utc;
+    use http::{HeaderMap, HeaderValue, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn test_idempotency_key_parse() {
+        let parse = |s: &'static str| {
+            IdempotencyKey::from_header(&HeaderValue::from_static(s))
+        };
+        assert_eq!(parse("abc-123").unwrap().as_str(), "abc-123");
+        assert_eq!(parse("\"abc-123\"").unwrap().as_str(), "abc-123");
+        assert!(parse("").is_err());
+        assert!(parse("has space").is_err());
+        let long = "x".repeat(256);
+        assert!(IdempotencyKey::from_header(
+            &HeaderValue::from_str(&long).unwrap()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store() {
+        let store = InMemoryIdempotencyStore::new(Duration::from_secs(60));
+        let now = Utc::now();
+        assert!(matches!(
+            store.reserve("k1", "POST /a", now).await,
+            IdempotencyRecord::Reserved
+        ));
+        match store.reserve("k1", "POST /a", now).await {
+            IdempotencyRecord::InProgress { fingerprint } => {
+                assert_eq!(fingerprint, "POST /a")
+            }
+            other => panic!("unexpected record: {:?}", other),
+        }
+
+        store
+            .complete(
+                "k1",
+                StoredResponse {
+                    status: StatusCode::CREATED,
+                    headers: HeaderMap::new(),
+                    body: "done".into(),
+                },
+            )
+            .await;
+        match store.reserve("k1", "POST /b", now).await {
+            IdempotencyRecord::Completed { fingerprint, response } => {
+                assert_eq!(fingerprint, "POST /a");
+                assert_eq!(response.status, StatusCode::CREATED);
+                assert_eq!(response.body, "done");
+            }
+            other => panic!("unexpected record: {:?}", other),
+        }
+
+        // Released keys may be reused.
+        assert!(matches!(
+            store.reserve("k2", "POST /a", now).await,
+            IdempotencyRecord::Reserved
+        ));
+        store.release("k2").await;
+        assert!(matches!(
+            store.reserve("k2", "POST /a", now).await,
+            IdempotencyRecord::Reserved
+        ));
+
+        // Keys expire once the TTL has passed.
+        let later = now + chrono::Duration::seconds(59);
+        assert!(matches!(
+            store.reserve("k1", "POST /a", later).await,
+            IdempotencyRecord::Completed { .. }
+        ));
+        let later = now + chrono::Duration::seconds(60);
+        assert!(matches!(
+            store.reserve("k1", "POST /a", later).await,
+            IdempotencyRecord::Reserved
+        ));
+    }
+}
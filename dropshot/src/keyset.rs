@@ -0,0 +1,519 @@
+// Copyright 2024 Oxide Computer Company
+//! Translating a paginated scan into a keyset query for SQL databases
+//!
+//! Most paginated endpoints backed by a SQL database do the same thing with
+//! the page selector: they sort by the columns of the marker and ask for the
+//! rows after it.  For a key sorted by `(created_at DESC, id ASC)`, that's:
+//!
+//! ```text
+//! WHERE ("created_at" < $1) OR ("created_at" = $1 AND "id" > $2)
+//! ORDER BY "created_at" DESC, "id" ASC
+//! ```
+//!
+//! [`Keyset`] generates these fragments from a [`CompositeSort`] and the
+//! marker, along with the values to bind to their parameters.  It doesn't
+//! depend on any particular database library: the marker's columns are
+//! converted with [`KeysetValues`] into whatever value type the consumer's
+//! library binds, and a [`KeysetDialect`] says how to write bind parameters and
+//! quote column names.
+//!
+//! A consumer's value type (say, an enum with a variant for each type in its
+//! keys) only needs `From` impls for those types, and its data layer binds
+//! [`KeysetQuery::values`] in order, as it would any other parameters.  With
+//! the feature flag `"sqlx"`, `KeysetQuery::bind` and `KeysetQuery::bind_as`
+//! do that for queries made with sqlx.
+//!
+//! Column names are quoted as identifiers, so they can't change the structure
+//! of the query, but they should still come from the server rather than the
+//! client.
+
+use crate::error::HttpError;
+use crate::pagination::CompositeSort;
+use crate::pagination::PageDirection;
+use crate::pagination::PaginationOrder;
+use crate::pagination::SortKey;
+
+/// How a SQL dialect writes bind parameters and identifiers
+pub trait KeysetDialect {
+    /// Returns the placeholder for bind parameter `n` (counting from 1)
+    fn placeholder(&self, n: usize) -> String;
+
+    /// Returns whether a placeholder may appear more than once in a
+    /// statement, referring to the same value each time
+    fn reuses_placeholders(&self) -> bool;
+
+    /// Returns `name` quoted as an identifier
+    ///
+    /// The default uses standard SQL double quotes, with any double quotes in
+    /// `name` doubled.
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '"')
+    }
+}
+
+/// Quotes `name` with `quote`, doubling any `quote` characters in it
+fn quote_with(name: &str, quote: char) -> String {
+    let mut quoted = String::with_capacity(name.len() + 2);
+    quoted.push(quote);
+    for c in name.chars() {
+        if c == quote {
+            quoted.push(quote);
+        }
+        quoted.push(c);
+    }
+    quoted.push(quote);
+    quoted
+}
+
+/// Numbered bind parameters (`$1`, `$2`, ...), as used by PostgreSQL
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NumberedParams;
+
+impl KeysetDialect for NumberedParams {
+    fn placeholder(&self, n: usize) -> String {
+        format!("${}", n)
+    }
+
+    fn reuses_placeholders(&self) -> bool {
+        true
+    }
+}
+
+/// Positional bind parameters (`?`), as used by MySQL and SQLite
+///
+/// Identifiers are quoted with backticks, which both accept.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PositionalParams;
+
+impl KeysetDialect for PositionalParams {
+    fn placeholder(&self, _n: usize) -> String {
+        String::from("?")
+    }
+
+    fn reuses_placeholders(&self) -> bool {
+        false
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '`')
+    }
+}
+
+/// A sort key whose columns can be bound as parameters of type `V`
+///
+/// This is implemented for the same tuples as [`SortKey`], when each column
+/// converts into `V`.  `V` is usually the dynamically-typed value of the
+/// consumer's database library.
+pub trait KeysetValues<V>: SortKey {
+    /// Returns the value of each column, in order
+    fn keyset_values(&self) -> Vec<V>;
+}
+
+macro_rules! impl_keyset_values {
+    ($($T:ident $i:tt),+) => {
+        impl<V, $($T),+> KeysetValues<V> for ($($T,)+)
+        where
+            ($($T,)+): SortKey,
+            $($T: Clone + Into<V>),+
+        {
+            fn keyset_values(&self) -> Vec<V> {
+                vec![$(self.$i.clone().into()),+]
+            }
+        }
+    };
+}
+
+impl_keyset_values!(A 0);
+impl_keyset_values!(A 0, B 1);
+impl_keyset_values!(A 0, B 1, C 2);
+impl_keyset_values!(A 0, B 1, C 2, D 3);
+
+/// Describes how to query a page of a scan sorted by a composite key
+///
+/// ```
+/// use dropshot::CompositeSort;
+/// use dropshot::Keyset;
+/// use dropshot::NumberedParams;
+/// use dropshot::PaginationOrder::{Ascending, Descending};
+///
+/// let sort = CompositeSort::new(vec![Descending, Ascending]);
+/// let marker = (20200713_i64, 17_i64);
+/// let query = Keyset::new(&["created_at", "id"], &sort)
+///     .query::<_, i64, _>(&NumberedParams, Some(&marker))
+///     .unwrap();
+/// assert_eq!(
+///     query.where_clause.unwrap(),
+///     r#"(("created_at" < $1) OR ("created_at" = $1 AND "id" > $2))"#
+/// );
+/// assert_eq!(query.order_by, r#""created_at" DESC, "id" ASC"#);
+/// assert_eq!(query.values, vec![20200713, 17]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Keyset<'a> {
+    columns: &'a [&'a str],
+    sort: &'a CompositeSort,
+    direction: PageDirection,
+    first_param: usize,
+}
+
+/// The SQL fragments and bind values for one page of a scan
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeysetQuery<V> {
+    /// condition selecting the rows after the marker (`None` for the first
+    /// page of a scan)
+    pub where_clause: Option<String>,
+    /// columns to sort by, without the `ORDER BY` keyword
+    pub order_by: String,
+    /// values to bind to the parameters in `where_clause`, in order
+    pub values: Vec<V>,
+}
+
+#[cfg(feature = "sqlx")]
+impl<V> KeysetQuery<V> {
+    /// Binds the values of this page to `query` (made with [`sqlx::query`]),
+    /// after any values already bound to it
+    ///
+    /// `V` must be a type that sqlx can bind, like `i64` for a key made of
+    /// integers.
+    pub fn bind<'q, DB>(
+        self,
+        query: sqlx::query::Query<
+            'q,
+            DB,
+            <DB as sqlx::database::HasArguments<'q>>::Arguments,
+        >,
+    ) -> sqlx::query::Query<
+        'q,
+        DB,
+        <DB as sqlx::database::HasArguments<'q>>::Arguments,
+    >
+    where
+        DB: sqlx::Database,
+        V: 'q + Send + sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    {
+        self.values.into_iter().fold(query, |query, value| query.bind(value))
+    }
+
+    /// Like [`KeysetQuery::bind`], but for a query made with
+    /// [`sqlx::query_as`]
+    pub fn bind_as<'q, DB, O>(
+        self,
+        query: sqlx::query::QueryAs<
+            'q,
+            DB,
+            O,
+            <DB as sqlx::database::HasArguments<'q>>::Arguments,
+        >,
+    ) -> sqlx::query::QueryAs<
+        'q,
+        DB,
+        O,
+        <DB as sqlx::database::HasArguments<'q>>::Arguments,
+    >
+    where
+        DB: sqlx::Database,
+        V: 'q + Send + sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    {
+        self.values.into_iter().fold(query, |query, value| query.bind(value))
+    }
+}
+
+impl<'a> Keyset<'a> {
+    /// Describes a scan of a table sorted by `columns` in the orders given by
+    /// `sort`
+    ///
+    /// A column may be qualified with its table (as in `items.id`): each
+    /// dot-separated part is quoted separately.
+    ///
+    /// # Panics
+    ///
+    /// If a column name (or part of one) is empty or contains a NUL
+    /// character, neither of which can be quoted.
+    pub fn new(columns: &'a [&'a str], sort: &'a CompositeSort) -> Keyset<'a> {
+        for column in columns {
+            assert!(
+                column.split('.').all(|part| !part.is_empty())
+                    && !column.contains('\0'),
+                "invalid keyset column name: {:?}",
+                column,
+            );
+        }
+        Keyset {
+            columns,
+            sort,
+            direction: PageDirection::Forward,
+            first_param: 1,
+        }
+    }
+
+    /// Sets the direction of the requested page (see
+    /// [`PaginationParams::direction()`](crate::PaginationParams::direction))
+    ///
+    /// For [`PageDirection::Backward`], the query selects the rows before the
+    /// marker, in reverse order, as
+    /// [`ResultsPage::new_bidirectional()`](crate::ResultsPage::new_bidirectional)
+    /// expects.
+    pub fn direction(mut self, direction: PageDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Sets the number of the first bind parameter, for statements that bind
+    /// other parameters before the keyset's (the default is 1)
+    pub fn first_param(mut self, n: usize) -> Self {
+        self.first_param = n;
+        self
+    }
+
+    /// Returns the columns to sort by, without the `ORDER BY` keyword
+    pub fn order_by<D: KeysetDialect>(&self, dialect: &D) -> String {
+        self.quoted_columns(dialect)
+            .iter()
+            .zip(self.orders())
+            .map(|(column, order)| match order {
+                PaginationOrder::Ascending => format!("{} ASC", column),
+                PaginationOrder::Descending => format!("{} DESC", column),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the query for the page after `marker` (or the first page of the
+    /// scan, if there's no marker)
+    ///
+    /// Fails with a 400 ("Bad Request") if the sort, which comes from the
+    /// client's page token, doesn't fit `Key`.
+    ///
+    /// # Panics
+    ///
+    /// If the number of columns doesn't match `Key`.
+    pub fn query<Key, V, D>(
+        &self,
+        dialect: &D,
+        marker: Option<&Key>,
+    ) -> Result<KeysetQuery<V>, HttpError>
+    where
+        Key: KeysetValues<V>,
+        V: Clone,
+        D: KeysetDialect,
+    {
+        assert_eq!(
+            self.columns.len(),
+            Key::NCOLUMNS,
+            "keyset has {} columns, but its key has {}",
+            self.columns.len(),
+            Key::NCOLUMNS,
+        );
+        self.sort.check::<Key>()?;
+
+        let order_by = self.order_by(dialect);
+        let Some(marker) = marker else {
+            return Ok(KeysetQuery {
+                where_clause: None,
+                order_by,
+                values: Vec::new(),
+            });
+        };
+
+        let marker_values = marker.keyset_values();
+        let mut values = Vec::new();
+        let mut param = |column: usize| {
+            if dialect.reuses_placeholders() {
+                dialect.placeholder(self.first_param + column)
+            } else {
+                values.push(marker_values[column].clone());
+                dialect.placeholder(self.first_param + values.len() - 1)
+            }
+        };
+
+        // A row comes after the marker if it's after it in the first column,
+        // or equal in the first column and after it in the second, and so on.
+        let columns = self.quoted_columns(dialect);
+        let orders = self.orders();
+        let mut terms = Vec::with_capacity(columns.len());
+        for (i, order) in orders.iter().enumerate() {
+            let mut conditions = Vec::with_capacity(i + 1);
+            for (j, column) in columns[..i].iter().enumerate() {
+                conditions.push(format!("{} = {}", column, param(j)));
+            }
+            let op = match order {
+                PaginationOrder::Ascending => ">",
+                PaginationOrder::Descending => "<",
+            };
+            conditions.push(format!("{} {} {}", columns[i], op, param(i)));
+            terms.push(format!("({})", conditions.join(" AND ")));
+        }
+
+        if dialect.reuses_placeholders() {
+            values = marker_values;
+        }
+
+        Ok(KeysetQuery {
+            where_clause: Some(format!("({})", terms.join(" OR "))),
+            order_by,
+            values,
+        })
+    }
+
+    /// Returns each column name, quoted for `dialect`
+    fn quoted_columns<D: KeysetDialect>(&self, dialect: &D) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| {
+                column
+                    .split('.')
+                    .map(|part| dialect.quote_identifier(part))
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect()
+    }
+
+    /// Returns the order of each column in which to look for rows
+    fn orders(&self) -> Vec<PaginationOrder> {
+        self.sort
+            .orders()
+            .iter()
+            .map(|order| self.direction.scan_order(*order))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keyset;
+    use super::KeysetQuery;
+    use super::NumberedParams;
+    use super::PositionalParams;
+    use crate::pagination::CompositeSort;
+    use crate::pagination::PageDirection;
+    use crate::pagination::PaginationOrder::Ascending;
+    use crate::pagination::PaginationOrder::Descending;
+    use http::StatusCode;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Value {
+        Int(i64),
+        Text(String),
+    }
+
+    impl From<i64> for Value {
+        fn from(n: i64) -> Value {
+            Value::Int(n)
+        }
+    }
+
+    impl From<String> for Value {
+        fn from(s: String) -> Value {
+            Value::Text(s)
+        }
+    }
+
+    #[test]
+    fn test_keyset_query() {
+        let sort = CompositeSort::new(vec![Ascending, Descending, Ascending]);
+        let columns = ["name", "size", "id"];
+        let keyset = Keyset::new(&columns, &sort);
+        let marker = (String::from("a"), 10_i64, 3_i64);
+
+        // First page: no condition.
+        let query =
+            keyset.query::<(String, i64, i64), Value, _>(&NumberedParams, None);
+        assert_eq!(
+            query.unwrap(),
+            KeysetQuery {
+                where_clause: None,
+                order_by: String::from(r#""name" ASC, "size" DESC, "id" ASC"#),
+                values: Vec::new(),
+            }
+        );
+
+        // Numbered parameters are each bound once.
+        let query = keyset
+            .clone()
+            .first_param(3)
+            .query::<_, Value, _>(&NumberedParams, Some(&marker))
+            .unwrap();
+        assert_eq!(
+            query.where_clause.unwrap(),
+            "((\"name\" > $3) OR (\"name\" = $3 AND \"size\" < $4) OR \
+             (\"name\" = $3 AND \"size\" = $4 AND \"id\" > $5))"
+        );
+        assert_eq!(
+            query.values,
+            vec![Value::Text(String::from("a")), Value::Int(10), Value::Int(3)]
+        );
+
+        // Positional parameters are bound wherever they appear.
+        let query = keyset
+            .query::<_, Value, _>(&PositionalParams, Some(&marker))
+            .unwrap();
+        assert_eq!(
+            query.where_clause.unwrap(),
+            "((`name` > ?) OR (`name` = ? AND `size` < ?) OR \
+             (`name` = ? AND `size` = ? AND `id` > ?))"
+        );
+        assert_eq!(
+            query.values,
+            vec![
+                Value::Text(String::from("a")),
+                Value::Text(String::from("a")),
+                Value::Int(10),
+                Value::Text(String::from("a")),
+                Value::Int(10),
+                Value::Int(3),
+            ]
+        );
+
+        // Going backward reverses every column.
+        let query = keyset
+            .direction(PageDirection::Backward)
+            .query::<_, Value, _>(&NumberedParams, Some(&marker))
+            .unwrap();
+        assert_eq!(
+            query.where_clause.unwrap(),
+            "((\"name\" < $1) OR (\"name\" = $1 AND \"size\" > $2) OR \
+             (\"name\" = $1 AND \"size\" = $2 AND \"id\" < $3))"
+        );
+        assert_eq!(query.order_by, r#""name" DESC, "size" ASC, "id" DESC"#);
+
+        // A sort that doesn't fit the key is the client's fault.
+        let bad_sort = CompositeSort::new(vec![Ascending]);
+        let error = Keyset::new(&columns, &bad_sort)
+            .query::<_, Value, _>(&NumberedParams, Some(&marker))
+            .unwrap_err();
+        assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_keyset_quoting() {
+        // Names can't break out of their quotes, and qualified names are
+        // quoted part by part.
+        let sort = CompositeSort::new(vec![Ascending, Ascending]);
+        let columns = ["items.id", "x\" = 1 OR \"`"];
+        let keyset = Keyset::new(&columns, &sort);
+        assert_eq!(
+            keyset.order_by(&NumberedParams),
+            r#""items"."id" ASC, "x"" = 1 OR ""`" ASC"#
+        );
+        assert_eq!(
+            keyset.order_by(&PositionalParams),
+            r#"`items`.`id` ASC, `x" = 1 OR "``` ASC"#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid keyset column name: \"items.\"")]
+    fn test_keyset_bad_column() {
+        let sort = CompositeSort::new(vec![Ascending]);
+        let _ = Keyset::new(&["items."], &sort);
+    }
+
+    #[test]
+    #[should_panic(expected = "keyset has 2 columns, but its key has 1")]
+    fn test_keyset_wrong_columns() {
+        let sort = CompositeSort::new(vec![Ascending]);
+        let _ = Keyset::new(&["a", "b"], &sort)
+            .query::<(i64,), Value, _>(&NumberedParams, None);
+    }
+}
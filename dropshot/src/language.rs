@@ -0,0 +1,264 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for choosing a language based on `Accept-Language`
+//!
+//! Internationalized APIs declare the languages they support by implementing
+//! [`AvailableLanguages`] and accept a [`Language`] extractor parameterized by
+//! that type.  The extractor picks the supported language that best matches
+//! the client's `Accept-Language` header, considering the client's languages
+//! in order of preference (quality).  Clients that don't say what they
+//! prefer, or that prefer only unsupported languages, get the application's
+//! default language.
+
+use crate::api_description::{
+    ApiEndpointBodyContentType, ApiEndpointParameter,
+    ApiEndpointParameterLocation, ApiSchemaGenerator, ExtensionMode,
+};
+use crate::error::HttpError;
+use crate::server::ServerContext;
+use crate::{ExtractorMetadata, RequestContext, SharedExtractor};
+use async_trait::async_trait;
+use http::header;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// The set of languages that an application supports
+///
+/// ```
+/// struct MyLanguages;
+/// impl dropshot::AvailableLanguages for MyLanguages {
+///     const LANGUAGES: &'static [&'static str] = &["en-US", "fr", "de"];
+/// }
+/// ```
+pub trait AvailableLanguages: Send + Sync + 'static {
+    /// Language tags (as in BCP 47) for the supported languages.  The first
+    /// one is the default.  This must not be empty: using [`Language`] with
+    /// an empty list fails to compile.
+    const LANGUAGES: &'static [&'static str];
+}
+
+/// `Language<L>` is an extractor for the language in which to respond, chosen
+/// from those in `L` according to the request's `Accept-Language` header
+///
+/// This never fails: if nothing acceptable to the client is available, the
+/// default language (the first one in `L`) is used, and it's up to the
+/// application whether to respond in that language or fail the request.
+pub struct Language<L: AvailableLanguages> {
+    tag: &'static str,
+    matched: bool,
+    phantom: PhantomData<L>,
+}
+
+impl<L: AvailableLanguages> Language<L> {
+    /// The default language (the first one in `L`).  Evaluating this for an
+    /// empty list of languages is a compile-time error.
+    const DEFAULT: &'static str = match L::LANGUAGES {
+        [first, ..] => first,
+        [] => panic!("AvailableLanguages::LANGUAGES must not be empty"),
+    };
+
+    /// Chooses a language from `L` for a request with the given value of
+    /// `Accept-Language` (if any)
+    pub fn choose(accept_language: Option<&str>) -> Language<L> {
+        let chosen = accept_language.and_then(|value| {
+            parse_accept_language(value)
+                .into_iter()
+                .find_map(|(range, _)| best_match(range, L::LANGUAGES))
+        });
+        Language {
+            tag: chosen.unwrap_or(Self::DEFAULT),
+            matched: chosen.is_some(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the tag of the chosen language, as it appears in
+    /// [`AvailableLanguages::LANGUAGES`]
+    pub fn as_str(&self) -> &'static str {
+        self.tag
+    }
+
+    /// Returns whether the chosen language is one the client asked for (as
+    /// opposed to the default)
+    pub fn is_acceptable(&self) -> bool {
+        self.matched
+    }
+}
+
+impl<L: AvailableLanguages> Clone for Language<L> {
+    fn clone(&self) -> Self {
+        Language { tag: self.tag, matched: self.matched, phantom: PhantomData }
+    }
+}
+
+impl<L: AvailableLanguages> Debug for Language<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Language")
+            .field("tag", &self.tag)
+            .field("matched", &self.matched)
+            .finish()
+    }
+}
+
+/// Parses an `Accept-Language` value into language ranges, most preferred
+/// first.  Ranges with quality 0 (meaning "not acceptable") and malformed
+/// entries are dropped.  Ranges with the same quality keep their order.
+fn parse_accept_language(value: &str) -> Vec<(&str, f32)> {
+    let mut ranges = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            if range.is_empty()
+                || !range.bytes().all(|b| {
+                    b.is_ascii_alphanumeric() || b == b'-' || b == b'*'
+                })
+            {
+                return None;
+            }
+            let mut quality = 1.0;
+            for param in parts {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse::<f32>().ok()?;
+                    if !(0.0..=1.0).contains(&quality) {
+                        return None;
+                    }
+                }
+            }
+            (quality > 0.0).then_some((range, quality))
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_by(|(_, q1), (_, q2)| q2.total_cmp(q1));
+    ranges
+}
+
+/// Returns the available language that best matches a single language range
+/// from `Accept-Language`, if any matches at all
+///
+/// An exact (case-insensitive) match is best.  Failing that, a range matches
+/// more specific tags (so "en" matches "en-US"), and then less specific tags
+/// (so "en-GB" matches "en").  "*" matches the default language.
+fn best_match(range: &str, available: &[&'static str]) -> Option<&'static str> {
+    if range == "*" {
+        return available.first().copied();
+    }
+    if let Some(tag) =
+        available.iter().find(|tag| tag.eq_ignore_ascii_case(range))
+    {
+        return Some(tag);
+    }
+    if let Some(tag) = available.iter().find(|tag| {
+        tag.len() > range.len()
+            && tag.as_bytes()[range.len()] == b'-'
+            && tag[..range.len()].eq_ignore_ascii_case(range)
+    }) {
+        return Some(tag);
+    }
+    let mut prefix = range;
+    while let Some((shorter, _)) = prefix.rsplit_once('-') {
+        prefix = shorter;
+        if let Some(tag) =
+            available.iter().find(|tag| tag.eq_ignore_ascii_case(prefix))
+        {
+            return Some(tag);
+        }
+    }
+    None
+}
+
+#[async_trait]
+impl<L: AvailableLanguages> SharedExtractor for Language<L> {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+    ) -> Result<Language<L>, HttpError> {
+        let value = rqctx
+            .request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok());
+        Ok(Language::choose(value))
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        let schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        let parameter = ApiEndpointParameter::new_named(
+            &ApiEndpointParameterLocation::Header,
+            String::from("Accept-Language"),
+            Some(format!(
+                "preferred languages for the response (available: {})",
+                L::LANGUAGES.join(", ")
+            )),
+            false,
+            ApiSchemaGenerator::Static {
+                schema: Box::new(schema.into()),
+                dependencies: indexmap::IndexMap::default(),
+            },
+            Vec::new(),
+        );
+        ExtractorMetadata {
+            parameters: vec![parameter],
+            extension_mode: ExtensionMode::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_accept_language;
+    use super::AvailableLanguages;
+    use super::Language;
+
+    struct TestLanguages;
+    impl AvailableLanguages for TestLanguages {
+        const LANGUAGES: &'static [&'static str] = &["en-US", "fr", "de-CH"];
+    }
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(
+            parse_accept_language(
+                "fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"
+            ),
+            vec![
+                ("fr-CH", 1.0),
+                ("fr", 0.9),
+                ("en", 0.8),
+                ("de", 0.7),
+                ("*", 0.5)
+            ]
+        );
+        assert_eq!(
+            parse_accept_language("de;q=0.2, en, es;q=0, fr;q=0.5, it"),
+            vec![("en", 1.0), ("it", 1.0), ("fr", 0.5), ("de", 0.2)]
+        );
+        assert_eq!(
+            parse_accept_language("en;q=2, fr;q=x, ;q=1, ../x, de"),
+            vec![("de", 1.0)]
+        );
+        assert_eq!(parse_accept_language(""), vec![]);
+    }
+
+    #[test]
+    fn test_choose_language() {
+        let choose = |value| {
+            let language = Language::<TestLanguages>::choose(value);
+            (language.as_str(), language.is_acceptable())
+        };
+        assert_eq!(choose(None), ("en-US", false));
+        assert_eq!(choose(Some("")), ("en-US", false));
+        assert_eq!(choose(Some("ja, ko")), ("en-US", false));
+        assert_eq!(choose(Some("FR")), ("fr", true));
+        assert_eq!(choose(Some("en")), ("en-US", true));
+        assert_eq!(choose(Some("fr-CA")), ("fr", true));
+        assert_eq!(choose(Some("de-DE")), ("en-US", false));
+        assert_eq!(choose(Some("de")), ("de-CH", true));
+        assert_eq!(choose(Some("ja, *;q=0.1")), ("en-US", true));
+        assert_eq!(choose(Some("en;q=0.5, fr;q=0.8")), ("fr", true));
+        assert_eq!(choose(Some("fr;q=0, de")), ("de-CH", true));
+    }
+}
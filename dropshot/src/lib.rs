@@ -62,6 +62,7 @@
 //!                 bind_address: "127.0.0.1:0".parse().unwrap(),
 //!                 request_body_max_bytes: 1024,
 //!                 default_handler_task_mode: HandlerTaskMode::Detached,
+//!                 ..Default::default()
 //!             },
 //!             api,
 //!             None,
@@ -76,10 +77,26 @@
 //!
 //! This server returns a 404 for all resources because no API functions were
 //! registered.  See `examples/basic.rs` for a simple, documented example that
-//! provides a few resources using shared state.
+//! provides a few resources using shared state.  An API can also be put
+//! together from parts described separately (e.g., in different crates) using
+//! [`ApiDescription::merge`].
 //!
 //! For a given `ApiDescription`, you can also print out an OpenAPI spec
-//! describing the API.  See [`ApiDescription::openapi`].
+//! describing the API.  See [`ApiDescription::openapi`].  To catch changes
+//! that would break existing clients (e.g., in CI), [`compare_openapi`] compares
+//! a generated spec with an earlier one, and
+//! [`test_util::assert_openapi_compatible`] does the same in a test.
+//! The API's types can also be exported on their own, as JSON Schemas or
+//! TypeScript declarations (see [`OpenApiDefinition::json_schemas`] and
+//! [`OpenApiDefinition::write_typescript`]).
+//! [`ApiDescription::into_stub`] makes a stub of the API, which responds with
+//! example responses, for developing clients before the API is implemented.
+//! In tests and development builds, the server can also check request and
+//! response bodies against the API's schemas as it handles them (see
+//! [`ConfigSchemaValidation`]).
+//! An API can serve several versions at once, with different endpoints (and
+//! a different OpenAPI spec) in each; see [`VersionPolicy`] and
+//! [`ApiDescription::openapi_all_versions`].
 //!
 //!
 //! ## API Handler Functions
@@ -183,6 +200,22 @@
 //!
 //!     // Optional fields
 //!     tags = [ "all", "your", "OpenAPI", "tags" ],
+//!     audiences = [ "internal" ],
+//!     status = 202,
+//!     reason = "Queued",
+//!     request_examples = [ "example_request" ],
+//!     response_examples = [ "example_response" ],
+//!     response_content_types = [ "text/csv" ],
+//!     security = [ { scheme = "oauth2", scopes = [ "read" ] } ],
+//!     extensions = [ { name = "x-go-name", value = "GetThing" } ],
+//!     external_docs = { url = "https://example.com/docs" },
+//!     links = [ {
+//!         name = "GetThing",
+//!         operation_id = "get_thing",
+//!         parameters = { id = "$response.body#/id" },
+//!     } ],
+//!     versions = { from = "1.0.0", until = "2.0.0" },
+//!     deprecated_versions = { until = "1.5.0" },
 //! }]
 //! ```
 //!
@@ -193,6 +226,66 @@
 //! The tags field is used to categorize API endpoints and only impacts the
 //! OpenAPI spec output.
 //!
+//! The audiences field restricts the endpoint to the OpenAPI spec output for
+//! those audiences (e.g., "internal" or "partner"), which is generated with
+//! [`OpenApiDefinition::audience`].  Other endpoints are documented for every
+//! audience, except those with `unpublished = true`, which are never
+//! documented.
+//!
+//! The status field replaces the success status code of the handler's response
+//! type, both in responses and in the OpenAPI spec output.  For example, a
+//! handler that returns `HttpResponseOk<T>` with `status = 202` responds with
+//! "202 Accepted" instead of "200 OK".  This must be a 2xx or 3xx status code,
+//! and it can't be 204, 205, or 304 unless the response type has no body (see
+//! [`validate_success_status`]); other values fail to compile.  The reason
+//! field similarly replaces the reason phrase of successful responses, which
+//! only HTTP/1 responses carry, and becomes the description of the response in
+//! the OpenAPI spec output.
+//!
+//! The request_examples and response_examples fields name functions (taking no
+//! arguments) that return examples of the request and response bodies.  Each
+//! example's value is serialized as JSON and included in the OpenAPI spec
+//! output, named after its function.  Examples of path, query, and header
+//! parameters can be added with [`ApiEndpoint::parameter_example`].
+//!
+//! The response_content_types field lists the media types of the successful
+//! response's body for the OpenAPI spec output, in place of the one implied by
+//! the handler's return type.  This is mainly for handlers that return
+//! `Response<Body>` or a [`FreeformBody`], whose responses are otherwise
+//! documented as having any media type.  See
+//! [`ApiEndpoint::response_content_type`].
+//!
+//! The security field lists the security schemes, any one of which may be used
+//! to call the endpoint, along with the OAuth2 scopes it requires (if any).
+//! The schemes must be declared with [`ApiDescription::security_scheme`]
+//! before the endpoint is registered.  These appear in the OpenAPI spec output,
+//! and authentication middleware can look them up with
+//! [`DropshotState::security_requirements`].
+//!
+//! The extensions field adds vendor extensions (whose names begin with `x-`)
+//! with string values to the operation in the OpenAPI spec output, for tools
+//! that generate code from it.  Extensions with other values, and extensions
+//! for parameters, schemas, and the document as a whole, can be added with
+//! [`ApiEndpoint::extension`], [`ApiEndpoint::parameter_extension`],
+//! [`ApiDescription::schema_extension`], and [`OpenApiDefinition::extension`].
+//!
+//! The external_docs field refers to documentation of the operation found
+//! elsewhere, and the links field adds OpenAPI links from the operation's
+//! successful response to other operations (e.g., from creating a resource to
+//! getting it by its id), with values for the target operation's parameters
+//! that are usually runtime expressions like `$response.body#/id`.  Both only
+//! impact the OpenAPI spec output.  Links can also be added with
+//! [`ApiEndpoint::response_link`].
+//!
+//! The versions field limits the endpoint to a range of versions of the API,
+//! from the first version given (inclusive) until the second (exclusive).
+//! Either may be omitted.  See [`ApiEndpointVersions`] and [`VersionPolicy`].
+//!
+//! The deprecated_versions field, given in the same form, deprecates the
+//! endpoint in a range of versions.  Responses to requests for those versions
+//! carry "Deprecation" and "Warning" headers.  See
+//! [`ApiEndpoint::deprecate_versions`].
+//!
 //!
 //! ### Function parameters
 //!
@@ -206,6 +299,7 @@
 //!      [body_param: TypedBody<J>,]
 //!      [body_param: UntypedBody,]
 //!      [body_param: StreamingBody,]
+//!      [body_param: SpooledBody,]
 //!      [raw_request: RawRequest,]
 //! ) -> Result<HttpResponse*, HttpError>
 //! ```
@@ -222,19 +316,27 @@
 //!   `schemars::JsonSchema`.
 //! * [`Path`]`<P>` extracts parameters from HTTP path, deserializing them into
 //!   an instance of type `P`. `P` must implement `serde::Deserialize` and
-//!   `schemars::JsonSchema`.
+//!   `schemars::JsonSchema`.  Fields of `Q` and `P` can use types like
+//!   [`Exploded`] and [`DeepObject`] to take arrays and objects in the styles
+//!   that OpenAPI describes.
 //! * [`TypedBody`]`<J>` extracts content from the request body by parsing the
 //!   body as JSON (or form/url-encoded) and deserializing it into an instance
 //!   of type `J`. `J` must implement `serde::Deserialize` and `schemars::JsonSchema`.
+//! * [`VersionedBody`]`<O, J>` is like `TypedBody<J>`, but also accepts
+//!   bodies in an older form `O` from requests for earlier versions of the API,
+//!   converting them into `J` (see [`ReplacesOlderVersion`]).
 //! * [`UntypedBody`] extracts the raw bytes of the request body.
 //! * [`StreamingBody`] provides the raw bytes of the request body as a
 //!   [`Stream`](futures::Stream) of [`Bytes`](bytes::Bytes) chunks.
+//! * [`SpooledBody`] reads the whole request body, keeping it in memory if
+//!   it's small and writing it to a temporary file otherwise.
 //! * [`RawRequest`] provides access to the underlying [`hyper::Request`].  The
 //!   hope is that this would generally not be needed.  It can be useful to
 //!   implement functionality not provided by Dropshot.
 //!
-//! `Query` and `Path` impl `SharedExtractor`.  `TypedBody`, `UntypedBody`,
-//! `StreamingBody`, and `RawRequest` impl `ExclusiveExtractor`.  Your function
+//! `Query` and `Path` impl `SharedExtractor`.  `TypedBody`, `VersionedBody`,
+//! `UntypedBody`, `StreamingBody`, `SpooledBody`, and `RawRequest` impl
+//! `ExclusiveExtractor`.  Your function
 //! may accept 0-3 extractors, but only one can be `ExclusiveExtractor`, and it
 //! must be the last one.  Otherwise, the order of extractor arguments does not
 //! matter.
@@ -449,8 +551,46 @@
 //! structure that contains a [`ResultsPage`] (possibly using
 //! `#[serde(flatten)]`), if that's the behavior you want.
 //!
+//! A page can also tell clients the total number of items in the collection,
+//! whether there are items before it, and cursors for its first and last items.
+//! To include these, implement [`ScanNavigation`] and construct the page with
+//! [`ResultsPage::new_with_navigation()`].
+//!
+//! An endpoint can also let clients page backward from any page they've seen.
+//! Pages built with [`ResultsPage::new_bidirectional()`] carry a `prev_page`
+//! token alongside `next_page`, and the handler uses
+//! [`PaginationParams::direction()`] to tell which way the client is going.
+//! (This is different from scanning in reverse order: the scan's order stays
+//! the same, and only the page requested moves backward.)  Each token records
+//! its own direction, so clients can switch direction as often as they like.
+//!
 //! There are several complete, documented examples in the "examples" directory.
 //!
+//! By default, clients get pages of 100 items unless they ask for a different
+//! `limit`, which is quietly reduced to 10,000 if it's larger.  An endpoint can
+//! declare its own default and maximum with the `page_default_limit` and
+//! `page_max_limit` endpoint attributes (or [`ApiEndpoint::page_default_limit`]
+//! and [`ApiEndpoint::page_max_limit`]).  These are documented in the OpenAPI
+//! schema for `limit`, and requests for more than the endpoint's maximum fail
+//! with a 400 ("Bad Request") response.  Either way, handlers should use
+//! [`RequestContext::page_limit()`] to find how many items to return.
+//!
+//! When the field a collection is sorted by isn't unique, the marker has to
+//! include another field that breaks ties, like an id.  [`CompositeSort`] and
+//! [`CompositePageSelector`] help with such composite keys, including ones whose
+//! columns are sorted in different directions.  See the
+//! "pagination-composite-sort" example.  For collections stored in a SQL
+//! database, [`Keyset`] turns such a sort and the marker into the `WHERE` and
+//! `ORDER BY` clauses that fetch a page.  It works with any database library,
+//! and the feature flag `"sqlx"` adds methods that bind its values to queries
+//! made with sqlx.
+//!
+//! Page tokens are only encoded, not encrypted, so clients can decode them and
+//! construct their own.  To keep the page selector private and tamper-proof,
+//! give the server [`PageTokenKeys`] to seal tokens with (see
+//! [`HttpServerStarter::page_token_keys()`]), and have handlers seal the pages
+//! they return with the request's keys (see [`ResultsPage::seal()`]).
+//!
 //!
 //! ### Advanced usage notes
 //!
@@ -504,6 +644,19 @@
 //! is specified. That is not the case for required parameters defined by
 //! `MyExtraQueryParams`--those must be supplied on each invocation.
 //!
+//! ### Pagination by offset
+//!
+//! Some collections are backed by stores where a stable, unique marker isn't
+//! available.  For these, a handler can instead accept
+//! [`Query`]`<`[`OffsetPaginationParams`]`>` and return an
+//! [`OffsetResultsPage`].  Clients provide `offset` (the number of items to
+//! skip) and optionally `limit`, and each page tells them the `next_offset` to
+//! request, if any.  Use [`RequestContext::offset_page_limit()`] to get the
+//! page size, just like `page_limit()` for token-based pagination.
+//!
+//! Offset-based scans may skip or repeat items if the collection changes during
+//! the scan, so token-based pagination should be preferred where possible.
+//!
 //! ### OpenAPI extension
 //!
 //! In generated OpenAPI documents, Dropshot adds the `x-dropshot-pagination`
@@ -519,6 +672,10 @@
 //! parameters that are mandatory if `page_token` is not specified (when
 //! fetching the first page of data).
 //!
+//! Operations paginated by offset instead get the
+//! `x-dropshot-offset-pagination` extension, whose value is currently an empty
+//! object.
+//!
 //! ## DTrace probes
 //!
 //! Dropshot optionally exposes two DTrace probes, `request_start` and
@@ -559,6 +716,42 @@
 //! {"ok":{"id":"a53696af-543d-452f-81b6-5a045dd9921d","local_addr":"127.0.0.1:61028","remote_addr":"127.0.0.1:57376","method":"PUT","path":"/counter","query":null}}
 //! {"ok":{"id":"a53696af-543d-452f-81b6-5a045dd9921d","local_addr":"127.0.0.1:61028","remote_addr":"127.0.0.1:57376","status_code":204,"message":""}}
 //! ```
+//!
+//! ## Server registry
+//!
+//! With the feature flag `"server-registry"`, Dropshot keeps track of every
+//! `HttpServer` in the process.  `dropshot::running_servers()` describes each
+//! one: where it's listening, how many endpoints it has, and how many requests
+//! it's handling.  Consumers can expose the same information on an admin server
+//! by registering the endpoint created with
+//! `dropshot::running_servers_endpoint()`.
+//!
+//! ## HTTP/3
+//!
+//! With the feature flag `"http3"`, a server using TLS can also serve HTTP/3
+//! over QUIC, as configured by `ConfigDropshot::http3`.  This is experimental.
+//! See `ConfigHttp3`.
+//!
+//! ## YAML OpenAPI output
+//!
+//! With the feature flag `"yaml"`, OpenAPI definitions can be written as YAML
+//! as well as JSON.  See `OpenApiDefinition::yaml` and
+//! `OpenApiDefinition::write_yaml`.  The endpoint registered with
+//! `ApiDescription::register_openapi_endpoint` also serves the definition as
+//! YAML (e.g., at "/openapi.yaml" for "/openapi.json").
+//!
+//! ## Testing TLS servers
+//!
+//! With the feature flag `"tls-test"`, `test_util::TestContext::new_with_tls`
+//! starts a server that serves HTTPS, with a client that trusts its
+//! certificate, and `test_util::ClientTestContext::with_tls` makes a client
+//! for such a server.  Enable it in your `[dev-dependencies]`.
+//!
+//! ## sqlx
+//!
+//! With the feature flag `"sqlx"`, the values of a page of a keyset query (see
+//! `Keyset`) can be bound to a query made with sqlx, using `KeysetQuery::bind`
+//! or `KeysetQuery::bind_as`.
 
 // Clippy's style advice is definitely valuable, but not worth the trouble for
 // automated enforcement.
@@ -575,63 +768,188 @@
 // that might use it.
 mod dtrace;
 
+mod api_compat;
 mod api_description;
+mod body_length;
+mod cache;
+mod clock;
 mod config;
+mod connection;
+mod connection_filter;
+mod cors;
+mod deadline;
+mod debug;
 mod error;
+mod event_stream;
 mod extractor;
+mod file;
+mod forwarded;
 mod from_map;
 mod handler;
+#[cfg(feature = "http3")]
+mod http3;
 mod http_util;
+mod idempotency;
+mod keyset;
+mod language;
 mod pagination;
+mod parameter_style;
+mod prefer;
+mod proxy_protocol;
+mod range;
+#[cfg(feature = "server-registry")]
+mod registry;
+mod route_stats;
 mod router;
+mod schema_export;
 mod schema_util;
+mod schema_validation;
+mod security;
 mod server;
+mod server_timing;
+mod service;
+#[cfg(unix)]
+mod socket_activation;
+mod streamed_body;
+mod tls_client_auth;
 mod to_map;
 mod type_util;
+mod unix_socket;
+mod upload;
+mod versioning;
 mod websocket;
+mod websocket_queue;
+mod websocket_registry;
 
 pub mod test_util;
 
+pub use api_compat::{compare_openapi, BreakingChange};
 pub use api_description::{
-    ApiDescription, ApiEndpoint, ApiEndpointBodyContentType,
-    ApiEndpointParameter, ApiEndpointParameterLocation, ApiEndpointResponse,
-    EndpointTagPolicy, ExtensionMode, OpenApiDefinition, TagConfig, TagDetails,
-    TagExternalDocs,
+    ApiDescription, ApiEndpoint, ApiEndpointAlternateResponse,
+    ApiEndpointBodyContentType, ApiEndpointCallback, ApiEndpointExample,
+    ApiEndpointLink, ApiEndpointParameter, ApiEndpointParameterLocation,
+    ApiEndpointResponse, ApiEndpointVisibility, ApiWebhook, EndpointTagPolicy,
+    ExtensionMode, OpenApiDefinition, SchemaSettingsHook, SchemaVisitor,
+    ServerDetails, ServerVariable, TagConfig, TagDetails, TagExternalDocs,
+    TagGroup,
+};
+pub use body_length::{map_response_body, BodyLength, LengthTrackedBody};
+pub use cache::{HttpResponseCached, CACHE_CONTROL_DEFAULT};
+pub use clock::{Clock, SystemClock};
+pub use config::{
+    ClientCertificateCheck, ConfigClientAuth, ConfigDropshot, ConfigHttp3,
+    ConfigListener, ConfigPem, ConfigTcp, ConfigTls, ConfigUnixSocket,
+    ConfigWebsocket, HandlerTaskMode, RawTlsConfig, WebsocketOverflowPolicy,
 };
-pub use config::{ConfigDropshot, ConfigTls, HandlerTaskMode, RawTlsConfig};
+pub use connection_filter::ConnectionFilter;
+pub use cors::ConfigCors;
+pub use deadline::{Deadline, HEADER_GRPC_TIMEOUT, HEADER_REQUEST_TIMEOUT};
+#[cfg(feature = "server-registry")]
+pub use debug::running_servers_endpoint;
+pub use debug::{request_echo_endpoint, RequestEcho};
 pub use dtrace::ProbeRegistration;
-pub use error::{HttpError, HttpErrorResponseBody};
+pub use error::{
+    ErrorMapper, ErrorResponseFormat, HttpError, HttpErrorResponseBody,
+    ProblemDetails,
+};
+pub use event_stream::{
+    EventStream, EventStreamUpgrade, CONTENT_TYPE_EVENT_STREAM,
+};
 pub use extractor::{
-    ExclusiveExtractor, ExtractorMetadata, MultipartBody, Path, Query,
-    RawRequest, SharedExtractor, StreamingBody, TypedBody, UntypedBody,
+    ExclusiveExtractor, ExtractorMetadata, MultipartBody, Path, PathError,
+    PathErrorHandler, Query, RawRequest, SharedExtractor, SpooledBody,
+    StreamingBody, TypedBody, UntypedBody, VersionedBody,
 };
+pub use file::HttpResponseFile;
+pub use forwarded::IpCidr;
+pub use handler::validate_success_status;
 pub use handler::{
-    http_response_found, http_response_see_other,
+    http_response_accepted, http_response_found,
+    http_response_permanent_redirect, http_response_see_other,
     http_response_temporary_redirect, FreeformBody, HttpCodedResponse,
-    HttpResponse, HttpResponseAccepted, HttpResponseCreated,
-    HttpResponseDeleted, HttpResponseFound, HttpResponseHeaders,
-    HttpResponseOk, HttpResponseSeeOther, HttpResponseTemporaryRedirect,
-    HttpResponseUpdatedNoContent, NoHeaders, RequestContext, RequestInfo,
+    HttpResponse, HttpResponseAccepted, HttpResponseAcceptedLocation,
+    HttpResponseCreated, HttpResponseDeleted, HttpResponseFound,
+    HttpResponseHeaders, HttpResponseOk, HttpResponsePermanentRedirect,
+    HttpResponseSeeOther, HttpResponseTemporaryRedirect,
+    HttpResponseUpdatedNoContent, HttpResponseWithStatus, NoHeaders,
+    RequestContext, RequestInfo,
 };
 pub use http_util::{
     CONTENT_TYPE_JSON, CONTENT_TYPE_MULTIPART_FORM_DATA, CONTENT_TYPE_NDJSON,
-    CONTENT_TYPE_OCTET_STREAM, CONTENT_TYPE_URL_ENCODED, HEADER_REQUEST_ID,
+    CONTENT_TYPE_OCTET_STREAM, CONTENT_TYPE_PROBLEM_JSON,
+    CONTENT_TYPE_TEXT_PLAIN, CONTENT_TYPE_URL_ENCODED, HEADER_REQUEST_ID,
+};
+pub use idempotency::{
+    IdempotencyKey, IdempotencyMiddleware, IdempotencyRecord, IdempotencyStore,
+    InMemoryIdempotencyStore, StoredResponse, HEADER_IDEMPOTENCY_KEY,
+    HEADER_IDEMPOTENT_REPLAYED,
+};
+pub use keyset::{
+    Keyset, KeysetDialect, KeysetQuery, KeysetValues, NumberedParams,
+    PositionalParams,
 };
+pub use language::{AvailableLanguages, Language};
 pub use pagination::{
-    EmptyScanParams, PaginationOrder, PaginationParams, ResultsPage, WhichPage,
+    CompositePageSelector, CompositeSort, EmptyScanParams,
+    OffsetPaginationParams, OffsetResultsPage, PageDirection, PageLimits,
+    PageTokenKeys, PaginationOrder, PaginationParams, ResultsPage,
+    ScanNavigation, SortKey, WhichPage,
+};
+pub use parameter_style::{
+    CommaDelimited, DeepObject, Exploded, ParameterSerialization,
+    ParameterStyle, PipeDelimited,
+};
+pub use prefer::{
+    HttpResponsePreferred, PreferReturn, Preferences, HEADER_PREFER,
+    HEADER_PREFERENCE_APPLIED,
+};
+pub use range::{ByteRange, HttpResponsePartialContent, Range};
+#[cfg(feature = "server-registry")]
+pub use registry::{running_servers, RunningServer};
+pub use route_stats::RouteStats;
+pub use schema_validation::ConfigSchemaValidation;
+pub use security::{
+    ApiKeyLocation, OAuth2Flow, SecurityRequirement, SecurityScheme,
 };
 pub use server::{
     DropshotState, HttpServer, HttpServerStarter, Middleware, ServerContext,
     ShutdownWaitFuture,
 };
+pub use server_timing::{
+    ConfigServerTiming, HEADER_SERVER_TIMING, HEADER_SERVER_TIMING_REQUESTED,
+};
+pub use service::ApiService;
+#[cfg(unix)]
+pub use socket_activation::{
+    inherited_listeners, pass_listeners, systemd_listeners,
+};
+pub use streamed_body::StreamedBody;
+pub use tls_client_auth::ClientCertificate;
+pub use unix_socket::PeerCredentials;
+pub use upload::{
+    receive_chunk, ContentRange, HttpResponseUploadProgress,
+    TempFileUploadStore, UploadProgress, UploadStore,
+};
+pub use versioning::{
+    ApiEndpointVersions, ClientSpecifiesVersionInAcceptHeader,
+    ClientSpecifiesVersionInHeader, ClientSpecifiesVersionInPath,
+    DynamicVersionPolicy, PrereleaseMatching, ReplacesOlderVersion,
+    VersionObserver, VersionPolicy, Versioned,
+};
 pub use websocket::{
     WebsocketChannelResult, WebsocketConnection, WebsocketConnectionRaw,
-    WebsocketEndpointResult, WebsocketUpgrade,
+    WebsocketContext, WebsocketEndpointResult, WebsocketShutdown,
+    WebsocketUpgrade,
+};
+pub use websocket_queue::{WebsocketSendError, WebsocketSendQueue};
+pub use websocket_registry::{
+    WebsocketMessage, WebsocketRegistration, WebsocketRegistry,
 };
 
 // Users of the `endpoint` macro need the following macros:
 pub use handler::RequestContextArgument;
 pub use http::Method;
+pub use semver;
 
 extern crate dropshot_endpoint;
 pub use dropshot_endpoint::channel;
@@ -97,18 +97,26 @@
 //! [1]: https://cloud.google.com/apis/design/design_patterns#list_pagination
 //! [2]: https://www.citusdata.com/blog/2016/03/30/five-ways-to-paginate/
 
+use crate::clock::Clock;
 use crate::error::HttpError;
 use crate::from_map::from_map;
 use base64::engine::general_purpose::URL_SAFE;
 use base64::Engine;
+use ring::aead;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::Serialize;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::Duration;
 
 /// A page of results from a paginated API
 ///
@@ -120,6 +128,21 @@ pub struct ResultsPage<ItemType> {
     pub next_page: Option<String>,
     /// list of items on this page of results
     pub items: Vec<ItemType>,
+    /// token used to fetch the previous page of results (if any)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_page: Option<String>,
+    /// total number of items in the collection (if known)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<u64>,
+    /// whether there are items before this page (if known)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_previous: Option<bool>,
+    /// token identifying the first item on this page (if provided)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_cursor: Option<String>,
+    /// token identifying the last item on this page (if provided)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_cursor: Option<String>,
 }
 
 impl<ItemType> JsonSchema for ResultsPage<ItemType>
@@ -144,6 +167,16 @@ pub struct ResultsPageSchema<ItemType> {
     pub next_page: Option<String>,
     /// list of items on this page of results
     pub items: Vec<ItemType>,
+    /// token used to fetch the previous page of results (if any)
+    pub prev_page: Option<String>,
+    /// total number of items in the collection (if known)
+    pub total_count: Option<u64>,
+    /// whether there are items before this page (if known)
+    pub has_previous: Option<bool>,
+    /// token identifying the first item on this page (if provided)
+    pub first_cursor: Option<String>,
+    /// token identifying the last item on this page (if provided)
+    pub last_cursor: Option<String>,
 }
 
 impl<ItemType> ResultsPage<ItemType> {
@@ -168,10 +201,163 @@ impl<ItemType> ResultsPage<ItemType> {
             })
             .transpose()?;
 
-        Ok(ResultsPage { next_page, items })
+        Ok(ResultsPage {
+            next_page,
+            items,
+            prev_page: None,
+            total_count: None,
+            has_previous: None,
+            first_cursor: None,
+            last_cursor: None,
+        })
+    }
+
+    /// Like [`ResultsPage::new()`], but for endpoints that let clients page
+    /// backward as well as forward
+    ///
+    /// `pag_params` are the parameters of the request.  If it's for a page
+    /// before the one the client saw (see [`PaginationParams::direction()`]),
+    /// `items` must be in the order that they were found, which is the reverse
+    /// of the scan's order (see [`PageDirection::scan_order()`]); they're put
+    /// back in the scan's order here.
+    ///
+    /// Besides the `next_page` token, the page gets a `prev_page` token
+    /// (unless it's the first page of a scan, or empty), which fetches the
+    /// items before this page's first item.  Each token records its direction,
+    /// so a client may switch direction at any point in a scan.
+    pub fn new_bidirectional<F, ScanParams, PageSelector, RequestScanParams>(
+        mut items: Vec<ItemType>,
+        scan_params: &ScanParams,
+        get_page_selector: F,
+        pag_params: &PaginationParams<RequestScanParams, PageSelector>,
+    ) -> Result<ResultsPage<ItemType>, HttpError>
+    where
+        F: Fn(&ItemType, &ScanParams) -> PageSelector,
+        PageSelector: DeserializeOwned + Serialize,
+        RequestScanParams: DeserializeOwned,
+    {
+        if pag_params.direction() == PageDirection::Backward {
+            items.reverse();
+        }
+        let prev_page = match (&pag_params.page, items.first()) {
+            (WhichPage::Next(_), Some(first_item)) => {
+                Some(serialize_page_token_in(
+                    get_page_selector(first_item, scan_params),
+                    PageDirection::Backward,
+                )?)
+            }
+            _ => None,
+        };
+
+        let mut page =
+            ResultsPage::new(items, scan_params, &get_page_selector)?;
+        page.prev_page = prev_page;
+        Ok(page)
+    }
+
+    /// Like [`ResultsPage::new()`], but also fills in the page's navigation
+    /// metadata
+    ///
+    /// `total_count` and `has_previous` come from `scan_params` (see
+    /// [`ScanNavigation`]).  `first_cursor` and `last_cursor` are page tokens
+    /// constructed by `get_page_selector` from the first and last items on the
+    /// page, which clients can use to page backwards (if the endpoint supports
+    /// a reversed scan) as well as forwards.
+    pub fn new_with_navigation<F, ScanParams, PageSelector>(
+        items: Vec<ItemType>,
+        scan_params: &ScanParams,
+        get_page_selector: F,
+    ) -> Result<ResultsPage<ItemType>, HttpError>
+    where
+        F: Fn(&ItemType, &ScanParams) -> PageSelector,
+        ScanParams: ScanNavigation<ItemType>,
+        PageSelector: Serialize,
+    {
+        let cursor = |item: Option<&ItemType>| {
+            item.map(|item| {
+                serialize_page_token(get_page_selector(item, scan_params))
+            })
+            .transpose()
+        };
+        let first_cursor = cursor(items.first())?;
+        let last_cursor = cursor(items.last())?;
+        let total_count = scan_params.total_count(&items);
+        let has_previous = scan_params.has_previous(&items);
+
+        let mut page =
+            ResultsPage::new(items, scan_params, &get_page_selector)?;
+        page.total_count = total_count;
+        page.has_previous = has_previous;
+        page.first_cursor = first_cursor;
+        page.last_cursor = last_cursor;
+        Ok(page)
+    }
+
+    /// Seals this page's tokens with `keys`, if there are any, so that
+    /// clients can neither read nor alter them
+    ///
+    /// Pass the keys of the server handling the request (from
+    /// [`RequestContext::page_token_keys()`][crate::RequestContext::page_token_keys()]).
+    /// Servers with keys reject tokens that weren't sealed, so a page that
+    /// isn't sealed can't be used to continue the scan.  See
+    /// [`PageTokenKeys`].
+    ///
+    /// ```
+    /// use dropshot::EmptyScanParams;
+    /// use dropshot::HttpError;
+    /// use dropshot::PageTokenKeys;
+    /// use dropshot::ResultsPage;
+    ///
+    /// # fn main() -> Result<(), HttpError> {
+    /// let keys = PageTokenKeys::new(&[1u8; 32]);
+    /// let page = ResultsPage::new(vec![1, 2, 3], &EmptyScanParams {}, |n, _| *n)?
+    ///     .seal(Some(&keys))?;
+    /// assert!(page.next_page.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn seal(
+        mut self,
+        keys: Option<&PageTokenKeys>,
+    ) -> Result<ResultsPage<ItemType>, HttpError> {
+        let Some(keys) = keys else {
+            return Ok(self);
+        };
+        let tokens = [
+            &mut self.next_page,
+            &mut self.prev_page,
+            &mut self.first_cursor,
+            &mut self.last_cursor,
+        ];
+        for token in tokens.into_iter().flatten() {
+            *token = seal_page_token(keys, token)?;
+        }
+        Ok(self)
+    }
+}
+
+/// Provides navigation metadata for a page of results
+///
+/// Implement this for your `ScanParams` type (or for whatever type you pass as
+/// `scan_params`, which may carry state like a count from your database) to use
+/// [`ResultsPage::new_with_navigation()`].  Both methods default to providing
+/// nothing, in which case the corresponding fields are left out of the
+/// response.
+pub trait ScanNavigation<ItemType> {
+    /// Returns the total number of items in the collection being scanned, if
+    /// known
+    fn total_count(&self, _items: &[ItemType]) -> Option<u64> {
+        None
+    }
+
+    /// Returns whether any items precede `items` in the scan, if known
+    fn has_previous(&self, _items: &[ItemType]) -> Option<bool> {
+        None
     }
 }
 
+impl<ItemType> ScanNavigation<ItemType> for EmptyScanParams {}
+
 /// Querystring parameters provided by clients when scanning a paginated
 /// collection
 ///
@@ -218,6 +404,14 @@ where
     #[serde(flatten, deserialize_with = "deserialize_whichpage")]
     pub page: WhichPage<ScanParams, PageSelector>,
 
+    /// Direction of the requested page relative to the page whose token the
+    /// client provided
+    ///
+    /// Consumers should use [`PaginationParams::direction()`] to access this
+    /// value.
+    #[serde(flatten, deserialize_with = "deserialize_direction")]
+    pub(crate) direction: PageDirection,
+
     /// Client-requested limit on page size (optional)
     ///
     /// Consumers should use
@@ -293,6 +487,43 @@ struct SchemaPaginationParams<ScanParams> {
     page_token: Option<String>,
 }
 
+impl<ScanParams, PageSelector> PaginationParams<ScanParams, PageSelector>
+where
+    ScanParams: DeserializeOwned,
+    PageSelector: DeserializeOwned + Serialize,
+{
+    /// Returns the direction of the requested page
+    ///
+    /// This is always [`PageDirection::Forward`] for the first page of a scan
+    /// and for pages requested with a `next_page` token.  It's
+    /// [`PageDirection::Backward`] for pages requested with a `prev_page` token
+    /// (see [`ResultsPage::new_bidirectional()`]), in which case the
+    /// `PageSelector` identifies the first item of the page that the client
+    /// saw, and the requested page consists of the items just before it.
+    pub fn direction(&self) -> PageDirection {
+        self.direction
+    }
+}
+
+// Deserialize the direction of the requested page for `PaginationParams` from
+// the page token, if there is one.  Problems with the token are reported when
+// deserializing `WhichPage`, so they're ignored here.
+fn deserialize_direction<'de, D>(
+    deserializer: D,
+) -> Result<PageDirection, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_params = BTreeMap::<String, String>::deserialize(deserializer)?;
+    Ok(raw_params
+        .get("page_token")
+        .and_then(|page_token| {
+            open_page_token::<serde::de::IgnoredAny>(page_token).ok()
+        })
+        .map(|token| token.dir)
+        .unwrap_or_default())
+}
+
 // Deserialize `WhichPage` for `PaginationParams`. In REST APIs, callers
 // typically provide either the parameters to resume a scan (in our case, just
 // "page_token") or the parameters to begin a new one (which can be
@@ -324,6 +555,48 @@ where
     }
 }
 
+/// Limits on the page size for one endpoint, overriding the server's defaults
+///
+/// See [`ApiEndpoint::page_default_limit`](crate::ApiEndpoint::page_default_limit)
+/// and [`ApiEndpoint::page_max_limit`](crate::ApiEndpoint::page_max_limit).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PageLimits {
+    /// page size used when the client doesn't request one
+    pub default: Option<NonZeroU32>,
+    /// largest page size that a client may request
+    pub max: Option<NonZeroU32>,
+}
+
+impl PageLimits {
+    /// Returns an error if the client requested a larger page than this
+    /// endpoint allows
+    ///
+    /// Servers otherwise clamp the requested page size to their maximum, but
+    /// an endpoint's maximum is part of its documented interface, so larger
+    /// requests are rejected.
+    pub(crate) fn check_request(
+        &self,
+        raw_query_string: &str,
+    ) -> Result<(), HttpError> {
+        let Some(max) = self.max else {
+            return Ok(());
+        };
+        // A limit that doesn't parse is left for deserialization to report.
+        let requested = form_urlencoded::parse(raw_query_string.as_bytes())
+            .find(|(name, _)| name == "limit")
+            .and_then(|(_, value)| value.parse::<u64>().ok());
+        match requested {
+            Some(limit) if limit > u64::from(max.get()) => {
+                Err(HttpError::for_bad_request(
+                    None,
+                    format!("limit must not be greater than {}", max),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Describes whether the client is beginning a new scan or resuming an existing
 /// one
 ///
@@ -360,6 +633,348 @@ pub enum PaginationOrder {
     Descending,
 }
 
+impl PaginationOrder {
+    /// Returns the opposite order
+    pub fn reverse(self) -> PaginationOrder {
+        match self {
+            PaginationOrder::Ascending => PaginationOrder::Descending,
+            PaginationOrder::Descending => PaginationOrder::Ascending,
+        }
+    }
+}
+
+/// The direction of a requested page relative to the page whose token the
+/// client provided (see [`PaginationParams::direction()`])
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PageDirection {
+    /// the page after the one the client saw (or the first page of a scan)
+    #[default]
+    Forward,
+    /// the page before the one the client saw
+    Backward,
+}
+
+impl PageDirection {
+    fn is_forward(&self) -> bool {
+        *self == PageDirection::Forward
+    }
+
+    /// Returns the order in which to look for the items on the requested page,
+    /// given the `order` of the scan
+    ///
+    /// To find the page before the one the client saw, a handler looks for
+    /// items before the selector's marker in reverse order, and then hands them
+    /// to [`ResultsPage::new_bidirectional()`] in that order.
+    pub fn scan_order(self, order: PaginationOrder) -> PaginationOrder {
+        match self {
+            PageDirection::Forward => order,
+            PageDirection::Backward => order.reverse(),
+        }
+    }
+}
+
+// Composite sort keys
+//
+// A marker has to be unique within the collection, so scans sorted by a field
+// that isn't (like a creation time) have to break ties with another one that
+// is (like an id).  These helpers deal with keys made of several such columns,
+// each of which may be sorted in either direction.
+
+/// A key made of several columns, used to sort the items in a scan
+///
+/// This is implemented for tuples of up to four columns, each of which must be
+/// `Ord`.  Keys are compared column by column, with each column sorted in the
+/// order given for it (see [`CompositeSort`]).
+pub trait SortKey: Clone + DeserializeOwned + Serialize {
+    /// number of columns in the key
+    const NCOLUMNS: usize;
+
+    /// Compares two keys, sorting each column in the corresponding order in
+    /// `orders`
+    ///
+    /// Columns without an order are sorted in ascending order.
+    fn cmp_columns(&self, other: &Self, orders: &[PaginationOrder])
+        -> Ordering;
+}
+
+fn cmp_column<T: Ord>(
+    a: &T,
+    b: &T,
+    orders: &[PaginationOrder],
+    column: usize,
+) -> Ordering {
+    match orders.get(column) {
+        Some(PaginationOrder::Descending) => b.cmp(a),
+        Some(PaginationOrder::Ascending) | None => a.cmp(b),
+    }
+}
+
+macro_rules! impl_sort_key {
+    ($n:expr; $($T:ident $i:tt),+) => {
+        impl<$($T),+> SortKey for ($($T,)+)
+        where
+            $($T: Clone + DeserializeOwned + Ord + Serialize),+
+        {
+            const NCOLUMNS: usize = $n;
+
+            fn cmp_columns(
+                &self,
+                other: &Self,
+                orders: &[PaginationOrder],
+            ) -> Ordering {
+                Ordering::Equal
+                    $(.then_with(|| {
+                        cmp_column(&self.$i, &other.$i, orders, $i)
+                    }))+
+            }
+        }
+    };
+}
+
+impl_sort_key!(1; A 0);
+impl_sort_key!(2; A 0, B 1);
+impl_sort_key!(3; A 0, B 1, C 2);
+impl_sort_key!(4; A 0, B 1, C 2, D 3);
+
+/// The order of each column in a scan sorted by a composite key
+///
+/// For example, to list the most recently created items first, with ties
+/// broken by id, you might sort by `(created_at, id)` with
+/// `CompositeSort::new(vec![Descending, Ascending])`.  This provides a
+/// comparator for the keys, a way to tell which items come after the marker
+/// from a page token, and page selectors that record both the order and the
+/// marker:
+///
+/// ```
+/// use dropshot::CompositeSort;
+/// use dropshot::PaginationOrder::{Ascending, Descending};
+///
+/// let sort = CompositeSort::new(vec![Descending, Ascending]);
+/// let mut keys = vec![(1, 'b'), (2, 'a'), (1, 'a')];
+/// keys.sort_by(sort.comparator());
+/// assert_eq!(keys, vec![(2, 'a'), (1, 'a'), (1, 'b')]);
+/// assert!(sort.is_after(&(1, 'b'), &(1, 'a')));
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct CompositeSort {
+    orders: Vec<PaginationOrder>,
+}
+
+impl CompositeSort {
+    /// Returns a sort with the given order for each column of the key
+    pub fn new(orders: Vec<PaginationOrder>) -> CompositeSort {
+        CompositeSort { orders }
+    }
+
+    /// Returns the order of each column of the key
+    pub fn orders(&self) -> &[PaginationOrder] {
+        &self.orders
+    }
+
+    /// Returns an error unless this sort has an order for each column of
+    /// `Key`
+    ///
+    /// Sorts that come from page tokens or the querystring should be checked
+    /// before they're used.
+    pub fn check<Key: SortKey>(&self) -> Result<(), HttpError> {
+        if self.orders.len() == Key::NCOLUMNS {
+            Ok(())
+        } else {
+            Err(HttpError::for_bad_request(
+                None,
+                format!(
+                    "sort has {} columns, but expected {}",
+                    self.orders.len(),
+                    Key::NCOLUMNS
+                ),
+            ))
+        }
+    }
+
+    /// Compares two keys in scan order
+    pub fn compare<Key: SortKey>(&self, a: &Key, b: &Key) -> Ordering {
+        a.cmp_columns(b, &self.orders)
+    }
+
+    /// Returns a function that compares keys in scan order, as for
+    /// `slice::sort_by`
+    pub fn comparator<Key: SortKey>(
+        &self,
+    ) -> impl Fn(&Key, &Key) -> Ordering + '_ {
+        move |a, b| self.compare(a, b)
+    }
+
+    /// Returns whether `key` comes after `marker` in scan order (and so
+    /// belongs on a page that follows the one `marker` was the last item of)
+    pub fn is_after<Key: SortKey>(&self, key: &Key, marker: &Key) -> bool {
+        self.compare(key, marker) == Ordering::Greater
+    }
+
+    /// Returns the page selector for a page whose last item has key
+    /// `last_seen`
+    pub fn page_selector<Key: SortKey>(
+        &self,
+        last_seen: Key,
+    ) -> CompositePageSelector<Key> {
+        CompositePageSelector { sort: self.clone(), last_seen }
+    }
+}
+
+/// Page selector for a scan sorted by a composite key
+///
+/// This records the order of each column as well as the key of the last item
+/// seen, so that a scan can resume in the same order that it started in.  See
+/// [`CompositeSort`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CompositePageSelector<Key> {
+    /// order of each column of the key
+    pub sort: CompositeSort,
+    /// key of the last item on the previous page
+    pub last_seen: Key,
+}
+
+/// A page of results from an API paginated by offset
+///
+/// This is the counterpart to [`ResultsPage`] for endpoints that accept
+/// [`OffsetPaginationParams`].  Like `ResultsPage`, it's intended for use both
+/// on the server side and on the client side.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OffsetResultsPage<ItemType> {
+    /// offset at which to fetch the next page of results (if any)
+    pub next_offset: Option<u64>,
+    /// list of items on this page of results
+    pub items: Vec<ItemType>,
+}
+
+impl<ItemType> JsonSchema for OffsetResultsPage<ItemType>
+where
+    ItemType: JsonSchema,
+{
+    fn schema_name() -> String {
+        format!("{}OffsetResultsPage", ItemType::schema_name())
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        OffsetResultsPageSchema::<ItemType>::json_schema(gen)
+    }
+}
+
+/// A single page of results
+#[derive(JsonSchema)]
+pub struct OffsetResultsPageSchema<ItemType> {
+    /// offset at which to fetch the next page of results (if any)
+    pub next_offset: Option<u64>,
+    /// list of items on this page of results
+    pub items: Vec<ItemType>,
+}
+
+impl<ItemType> OffsetResultsPage<ItemType> {
+    /// Construct a new results page from the list of `items` found at the
+    /// offset requested by `pag_params`.  `limit` is the page size the handler
+    /// used (usually from
+    /// [`RequestContext::offset_page_limit()`][crate::handler::RequestContext::offset_page_limit()]).
+    ///
+    /// A full page is taken to mean that there may be more items, so the
+    /// client is told to continue at the offset just past this page.  A short
+    /// page ends the scan.
+    pub fn new(
+        items: Vec<ItemType>,
+        pag_params: &OffsetPaginationParams,
+        limit: NonZeroU32,
+    ) -> Result<OffsetResultsPage<ItemType>, HttpError> {
+        let nitems = u64::try_from(items.len()).unwrap();
+        let next_offset = if nitems >= u64::from(limit.get()) {
+            Some(pag_params.offset().checked_add(nitems).ok_or_else(|| {
+                HttpError::for_internal_error(String::from(
+                    "next page offset overflowed",
+                ))
+            })?)
+        } else {
+            None
+        };
+
+        Ok(OffsetResultsPage { next_offset, items })
+    }
+}
+
+/// Querystring parameters provided by clients when scanning a collection
+/// paginated by offset
+///
+/// This is an alternative to [`PaginationParams`] for collections where a
+/// stable cursor isn't available, such as those backed by stores that can only
+/// skip a number of rows.  Clients pass `offset` (the number of items to skip,
+/// defaulting to 0) and optionally `limit`.  Handlers return an
+/// [`OffsetResultsPage`].
+///
+/// Offset-based scans don't have the consistency properties of token-based
+/// ones: if items are inserted or removed during a scan, a client may see some
+/// items twice or not at all.  Prefer [`PaginationParams`] where possible.
+#[derive(Debug, Deserialize)]
+pub struct OffsetPaginationParams {
+    /// Number of items skipped before the first item on this page
+    ///
+    /// Consumers should use [`OffsetPaginationParams::offset()`] to access
+    /// this value.
+    pub(crate) offset: Option<u64>,
+
+    /// Client-requested limit on page size (optional)
+    ///
+    /// Consumers should use
+    /// [`RequestContext`][crate::handler::RequestContext::offset_page_limit()]
+    /// to access this value.
+    pub(crate) limit: Option<NonZeroU32>,
+}
+
+pub(crate) const OFFSET_PAGINATION_PARAM_SENTINEL: &str =
+    "x-dropshot-offset-pagination-param";
+pub(crate) const OFFSET_PAGINATION_EXTENSION: &str =
+    "x-dropshot-offset-pagination";
+
+impl OffsetPaginationParams {
+    /// Returns the number of items the client asked to skip
+    pub fn offset(&self) -> u64 {
+        self.offset.unwrap_or(0)
+    }
+}
+
+impl JsonSchema for OffsetPaginationParams {
+    fn schema_name() -> String {
+        "OffsetPaginationParams".to_string()
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        // As with `PaginationParams`, the sentinel tags the associated
+        // operation so that it's marked as paginated (by offset) in the
+        // OpenAPI document.
+        let mut schema =
+            SchemaOffsetPaginationParams::json_schema(gen).into_object();
+        schema.extensions.insert(
+            OFFSET_PAGINATION_PARAM_SENTINEL.to_string(),
+            serde_json::json!({}),
+        );
+        schemars::schema::Schema::Object(schema)
+    }
+}
+
+// This is the API consumer-visible interface for endpoints paginated by
+// offset.  We use this solely to generate the schema.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct SchemaOffsetPaginationParams {
+    /// Number of items to skip before the first item returned (default 0)
+    offset: Option<u64>,
+    /// Maximum number of items returned by a single call
+    limit: Option<NonZeroU32>,
+}
+
 // Token and querystring serialization and deserialization
 //
 // Page tokens essentially take the consumer's PageSelector struct, add a
@@ -408,15 +1023,195 @@ enum PaginationVersion {
 struct SerializedToken<PageSelector> {
     v: PaginationVersion,
     page_start: PageSelector,
+    /// direction of the page that the token fetches (left out when it's
+    /// forward, so that such tokens are the same as those from before
+    /// backward paging was supported)
+    #[serde(default, skip_serializing_if = "PageDirection::is_forward")]
+    dir: PageDirection,
+}
+
+/// Keys used to seal page tokens so that clients can neither read nor alter
+/// them
+///
+/// By default, page tokens are just encoded, so a client can decode one to see
+/// the consumer's `PageSelector` and can construct tokens of its own.  When a
+/// server is given page token keys (see
+/// [`HttpServerStarter::page_token_keys()`][crate::HttpServerStarter::page_token_keys()]),
+/// tokens are instead encrypted and authenticated with AES-256-GCM: handlers
+/// seal the pages they return with
+/// [`RequestContext::page_token_keys()`][crate::RequestContext::page_token_keys()]
+/// (see [`ResultsPage::seal()`]), and the server's [`PaginationParams`] only
+/// accept tokens that were sealed with one of its keys.  Others are rejected
+/// as corrupted.
+///
+/// New tokens are always sealed with the current key.  To rotate keys without
+/// breaking scans that are in progress, make the old current key a previous
+/// key for a while (at least as long as clients may take to finish a scan):
+///
+/// ```
+/// use dropshot::PageTokenKeys;
+///
+/// # let (new_key, old_key) = ([1u8; 32], [2u8; 32]);
+/// let keys = PageTokenKeys::new(&new_key).with_previous(&old_key);
+/// ```
+///
+/// Servers behind a load balancer should share the same keys.
+///
+/// Sealed tokens can also be made to expire (see
+/// [`PageTokenKeys::with_ttl()`]), so that a scan can't be continued long
+/// after it was started.
+pub struct PageTokenKeys {
+    /// the current key, followed by any previous keys
+    keys: Vec<aead::LessSafeKey>,
+    /// how long sealed tokens last, if they expire
+    ttl: Option<Duration>,
+    /// the clock of the server using these keys, which determines when
+    /// tokens expire (the system clock if not set)
+    clock: OnceLock<Arc<dyn Clock>>,
+}
+
+impl PageTokenKeys {
+    /// Returns keys that seal tokens with the 256-bit key `current`
+    pub fn new(current: &[u8; 32]) -> PageTokenKeys {
+        PageTokenKeys {
+            keys: vec![Self::make_key(current)],
+            ttl: None,
+            clock: OnceLock::new(),
+        }
+    }
+
+    /// Adds a previous key, which is used to open tokens but not to seal them
+    pub fn with_previous(mut self, previous: &[u8; 32]) -> PageTokenKeys {
+        self.keys.push(Self::make_key(previous));
+        self
+    }
+
+    /// Makes tokens expire `ttl` after they're sealed
+    ///
+    /// Expired tokens are rejected with a 400 error.  Time is measured by the
+    /// server's clock (see
+    /// [`HttpServerStarter::clock()`][crate::HttpServerStarter::clock()]).
+    pub fn with_ttl(mut self, ttl: Duration) -> PageTokenKeys {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Measures the expiry of tokens with `clock` (that of the server using
+    /// these keys) rather than the system clock
+    pub(crate) fn use_clock(&self, clock: Arc<dyn Clock>) {
+        let _ = self.clock.set(clock);
+    }
+
+    /// Returns the current time in milliseconds since the Unix epoch,
+    /// according to the server's clock
+    fn now_millis(&self) -> i64 {
+        match self.clock.get() {
+            Some(clock) => clock.now().timestamp_millis(),
+            None => chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    fn make_key(key: &[u8; 32]) -> aead::LessSafeKey {
+        aead::LessSafeKey::new(
+            aead::UnboundKey::new(&aead::AES_256_GCM, key).unwrap(),
+        )
+    }
+
+    /// Seals `token`, along with the time at which it expires
+    fn seal(&self, token: &[u8]) -> Result<Vec<u8>, String> {
+        let expires = match self.ttl {
+            Some(ttl) => {
+                let ttl = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+                self.now_millis().saturating_add(ttl)
+            }
+            None => i64::MAX,
+        };
+        let mut bytes = expires.to_be_bytes().to_vec();
+        bytes.extend_from_slice(token);
+
+        let mut nonce_bytes = [0u8; aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce_bytes)
+            .map_err(|_| String::from("failed to generate nonce"))?;
+        self.keys[0]
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce_bytes),
+                aead::Aad::from(PAGE_TOKEN_AAD),
+                &mut bytes,
+            )
+            .map_err(|_| String::from("failed to seal token"))?;
+        let mut sealed = nonce_bytes.to_vec();
+        sealed.extend_from_slice(&bytes);
+        Ok(sealed)
+    }
+
+    /// Opens a token sealed with one of these keys, returning an error
+    /// ("corrupted" or "expired") if it can't be used
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, &'static str> {
+        const EXPIRES_LEN: usize = std::mem::size_of::<i64>();
+        let plaintext = self.open_plaintext(sealed).ok_or("corrupted token")?;
+        if plaintext.len() < EXPIRES_LEN {
+            return Err("corrupted token");
+        }
+        let (expires, token) = plaintext.split_at(EXPIRES_LEN);
+        let expires = i64::from_be_bytes(expires.try_into().unwrap());
+        if self.now_millis() >= expires {
+            return Err("expired token");
+        }
+        Ok(token.to_vec())
+    }
+
+    fn open_plaintext(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < aead::NONCE_LEN {
+            return None;
+        }
+        let (nonce_bytes, ciphertext) = sealed.split_at(aead::NONCE_LEN);
+        self.keys.iter().find_map(|key| {
+            let nonce =
+                aead::Nonce::try_assume_unique_for_key(nonce_bytes).ok()?;
+            let mut bytes = ciphertext.to_vec();
+            let plaintext = key
+                .open_in_place(
+                    nonce,
+                    aead::Aad::from(PAGE_TOKEN_AAD),
+                    &mut bytes,
+                )
+                .ok()?;
+            Some(plaintext.to_vec())
+        })
+    }
 }
 
+impl Debug for PageTokenKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't include the keys themselves.
+        f.debug_struct("PageTokenKeys")
+            .field("nkeys", &self.keys.len())
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Additional data authenticated along with each sealed token, so that other
+/// things sealed with the same keys can't be passed off as page tokens
+const PAGE_TOKEN_AAD: &[u8] = b"dropshot page token";
+
 /// Construct a serialized page token from a consumer's page selector
 fn serialize_page_token<PageSelector: Serialize>(
     page_start: PageSelector,
+) -> Result<String, HttpError> {
+    serialize_page_token_in(page_start, PageDirection::Forward)
+}
+
+/// Construct a serialized page token for a page in the given direction from a
+/// consumer's page selector
+fn serialize_page_token_in<PageSelector: Serialize>(
+    page_start: PageSelector,
+    dir: PageDirection,
 ) -> Result<String, HttpError> {
     let token_bytes = {
         let serialized_token =
-            SerializedToken { v: PaginationVersion::V1, page_start };
+            SerializedToken { v: PaginationVersion::V1, page_start, dir };
 
         let json_bytes =
             serde_json::to_vec(&serialized_token).map_err(|e| {
@@ -435,15 +1230,65 @@ fn serialize_page_token<PageSelector: Serialize>(
     // mean that if it ever works, then it will always work?  But would that
     // interface be a pain to use, given that variable-length strings are
     // very common in the token?
-    if token_bytes.len() > MAX_TOKEN_LENGTH {
+    check_token_length(token_bytes)
+}
+
+/// Returns an error if `token` is too long to hand out (see
+/// [`MAX_TOKEN_LENGTH`])
+fn check_token_length(token: String) -> Result<String, HttpError> {
+    if token.len() > MAX_TOKEN_LENGTH {
         return Err(HttpError::for_internal_error(format!(
             "serialized token is too large ({} bytes, max is {})",
-            token_bytes.len(),
+            token.len(),
             MAX_TOKEN_LENGTH
         )));
     }
 
-    Ok(token_bytes)
+    Ok(token)
+}
+
+/// Seals the serialized page token `token` with `keys`
+fn seal_page_token(
+    keys: &PageTokenKeys,
+    token: &str,
+) -> Result<String, HttpError> {
+    let json_bytes = URL_SAFE.decode(token.as_bytes()).map_err(|e| {
+        HttpError::for_internal_error(format!(
+            "failed to serialize token: {}",
+            e
+        ))
+    })?;
+    let sealed = keys.seal(&json_bytes).map_err(|e| {
+        HttpError::for_internal_error(format!(
+            "failed to serialize token: {}",
+            e
+        ))
+    })?;
+    check_token_length(URL_SAFE.encode(sealed))
+}
+
+/// Opens the sealed page token `token` with `keys`, returning it as an
+/// ordinary (unsealed) page token
+///
+/// A token that can't be opened is reported just like any other corrupted
+/// token, so that clients learn nothing about why.  Expired tokens are
+/// reported as such.
+pub(crate) fn open_sealed_page_token(
+    keys: &PageTokenKeys,
+    token: &str,
+) -> Result<String, String> {
+    if token.len() > MAX_TOKEN_LENGTH {
+        return Err(String::from(
+            "failed to parse pagination token: too large",
+        ));
+    }
+    let sealed = URL_SAFE
+        .decode(token.as_bytes())
+        .map_err(|e| format!("failed to parse pagination token: {}", e))?;
+    let json_bytes = keys
+        .open(&sealed)
+        .map_err(|e| format!("failed to parse pagination token: {}", e))?;
+    Ok(URL_SAFE.encode(json_bytes))
 }
 
 /// Deserialize a token from the given string into the consumer's page selector
@@ -451,12 +1296,20 @@ fn serialize_page_token<PageSelector: Serialize>(
 fn deserialize_page_token<PageSelector: DeserializeOwned>(
     token_str: &str,
 ) -> Result<PageSelector, String> {
+    open_page_token(token_str).map(|token| token.page_start)
+}
+
+/// Deserialize a token from the given string, including the metadata that
+/// Dropshot adds to the consumer's page selector
+fn open_page_token<PageSelector: DeserializeOwned>(
+    token_str: &str,
+) -> Result<SerializedToken<PageSelector>, String> {
     if token_str.len() > MAX_TOKEN_LENGTH {
         return Err(String::from(
             "failed to parse pagination token: too large",
         ));
     }
-    let json_bytes = URL_SAFE
+    let token_bytes = URL_SAFE
         .decode(token_str.as_bytes())
         .map_err(|e| format!("failed to parse pagination token: {}", e))?;
 
@@ -470,7 +1323,7 @@ fn deserialize_page_token<PageSelector: DeserializeOwned>(
     // output of the error anyway.  It's not clear how else we could
     // propagate this information out.
     let deserialized: SerializedToken<PageSelector> =
-        serde_json::from_slice(&json_bytes).map_err(|_| {
+        serde_json::from_slice(&token_bytes).map_err(|_| {
             String::from("failed to parse pagination token: corrupted token")
         })?;
 
@@ -481,17 +1334,30 @@ fn deserialize_page_token<PageSelector: DeserializeOwned>(
         ));
     }
 
-    Ok(deserialized.page_start)
+    Ok(deserialized)
 }
 
 #[cfg(test)]
 mod test {
     use super::deserialize_page_token;
+    use super::open_sealed_page_token;
+    use super::seal_page_token;
     use super::serialize_page_token;
+    use super::CompositePageSelector;
+    use super::CompositeSort;
+    use super::EmptyScanParams;
+    use super::OffsetPaginationParams;
+    use super::OffsetResultsPage;
+    use super::PageDirection;
+    use super::PageTokenKeys;
+    use super::PaginationOrder::Ascending;
+    use super::PaginationOrder::Descending;
     use super::PaginationParams;
     use super::ResultsPage;
     use super::WhichPage;
+    use super::OFFSET_PAGINATION_PARAM_SENTINEL;
     use super::PAGINATION_PARAM_SENTINEL;
+    use crate::test_util::TestClock;
     use base64::engine::general_purpose::URL_SAFE;
     use base64::Engine;
     use schemars::JsonSchema;
@@ -499,6 +1365,8 @@ mod test {
     use serde::Deserialize;
     use serde::Serialize;
     use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
     use std::{fmt::Debug, num::NonZeroU32};
 
     #[test]
@@ -795,6 +1663,94 @@ mod test {
         assert!(results.next_page.is_none());
     }
 
+    #[test]
+    fn test_results_page_bidirectional() {
+        #[derive(Debug, Deserialize, Serialize)]
+        struct NumPageSelector {
+            n: u32,
+        }
+        type Params = PaginationParams<EmptyScanParams, NumPageSelector>;
+        let get_page =
+            |item: &u32, _: &EmptyScanParams| NumPageSelector { n: *item };
+        let parse = |token: &str| -> Params {
+            serde_urlencoded::from_str(&format!("page_token={}", token))
+                .unwrap()
+        };
+
+        assert_eq!(Ascending.reverse(), Descending);
+        assert_eq!(Descending.reverse(), Ascending);
+        assert_eq!(PageDirection::Forward.scan_order(Descending), Descending);
+        assert_eq!(PageDirection::Backward.scan_order(Descending), Ascending);
+
+        // The first page of a scan has no "prev_page" token.
+        let first: Params = serde_urlencoded::from_str("").unwrap();
+        assert_eq!(first.direction(), PageDirection::Forward);
+        let results = ResultsPage::new_bidirectional(
+            vec![1, 2, 3],
+            &EmptyScanParams {},
+            get_page,
+            &first,
+        )
+        .unwrap();
+        assert_eq!(results.items, vec![1, 2, 3]);
+        assert!(results.prev_page.is_none());
+
+        // Tokens for the next page look just like the ones from
+        // `ResultsPage::new()`, and they fetch forward.
+        let next_token = results.next_page.unwrap();
+        let plain =
+            ResultsPage::new(vec![3], &EmptyScanParams {}, get_page).unwrap();
+        assert_eq!(plain.next_page.unwrap(), next_token);
+        let next = parse(&next_token);
+        assert_eq!(next.direction(), PageDirection::Forward);
+        let results = ResultsPage::new_bidirectional(
+            vec![4, 5, 6],
+            &EmptyScanParams {},
+            get_page,
+            &next,
+        )
+        .unwrap();
+        assert_eq!(results.items, vec![4, 5, 6]);
+
+        // The "prev_page" token fetches backward from the first item.
+        let prev = parse(&results.prev_page.unwrap());
+        assert_eq!(prev.direction(), PageDirection::Backward);
+        let WhichPage::Next(NumPageSelector { n }) = prev.page else {
+            panic!("expected next page");
+        };
+        assert_eq!(n, 4);
+
+        // Items found going backward are put back in the scan's order, and the
+        // tokens are built from the resulting first and last items.
+        let results = ResultsPage::new_bidirectional(
+            vec![3, 2, 1],
+            &EmptyScanParams {},
+            get_page,
+            &prev,
+        )
+        .unwrap();
+        assert_eq!(results.items, vec![1, 2, 3]);
+        let next: NumPageSelector =
+            deserialize_page_token(&results.next_page.unwrap()).unwrap();
+        assert_eq!(next.n, 3);
+        let prev = parse(&results.prev_page.unwrap());
+        let WhichPage::Next(NumPageSelector { n }) = prev.page else {
+            panic!("expected next page");
+        };
+        assert_eq!(n, 1);
+
+        // An empty page has neither token.
+        let results = ResultsPage::new_bidirectional(
+            Vec::<u32>::new(),
+            &EmptyScanParams {},
+            get_page,
+            &parse(&next_token),
+        )
+        .unwrap();
+        assert!(results.next_page.is_none());
+        assert!(results.prev_page.is_none());
+    }
+
     #[derive(Deserialize, Serialize, JsonSchema)]
     struct Name {
         name: String,
@@ -820,4 +1776,183 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn test_sealed_page_tokens() {
+        #[derive(Debug, Deserialize, Serialize)]
+        struct MyToken {
+            x: u16,
+        }
+
+        let keys = PageTokenKeys::new(&[7u8; 32]);
+        let plain = serialize_page_token(&MyToken { x: 1025 }).unwrap();
+        let sealed = seal_page_token(&keys, &plain).unwrap();
+
+        // Sealed tokens round-trip with the same key, and each sealing uses a
+        // fresh nonce.
+        let opened = open_sealed_page_token(&keys, &sealed).unwrap();
+        let after: MyToken = deserialize_page_token(&opened).unwrap();
+        assert_eq!(after.x, 1025);
+        let again = seal_page_token(&keys, &plain).unwrap();
+        assert_ne!(sealed, again);
+
+        // They can't be read without keys, nor opened with a different key.
+        let error = deserialize_page_token::<MyToken>(&sealed).unwrap_err();
+        assert!(error.contains("corrupted token"));
+        let other_keys = PageTokenKeys::new(&[8u8; 32]);
+        let error = open_sealed_page_token(&other_keys, &sealed).unwrap_err();
+        assert!(error.contains("corrupted token"));
+
+        // Unsealed and truncated tokens are corrupted, too.
+        let error = open_sealed_page_token(&keys, &plain).unwrap_err();
+        assert!(error.contains("corrupted token"));
+        let error =
+            open_sealed_page_token(&keys, &URL_SAFE.encode("abc")).unwrap_err();
+        assert!(error.contains("corrupted token"));
+
+        // A previous key still opens tokens.
+        let rotated = PageTokenKeys::new(&[8u8; 32]).with_previous(&[7u8; 32]);
+        let opened = open_sealed_page_token(&rotated, &sealed).unwrap();
+        let after: MyToken = deserialize_page_token(&opened).unwrap();
+        assert_eq!(after.x, 1025);
+
+        // Sealing costs some of the token's length.
+        #[derive(Debug, Deserialize, Serialize)]
+        struct TokenWithStr {
+            s: String,
+        }
+        let input =
+            TokenWithStr { s: String::from_utf8(vec![b'e'; 352]).unwrap() };
+        let plain = serialize_page_token(&input).unwrap();
+        let error = seal_page_token(&keys, &plain).unwrap_err();
+        assert!(error
+            .internal_message
+            .contains("serialized token is too large"));
+
+        // Sealing a page seals each of its tokens, and sealing without keys
+        // leaves them alone.
+        let page = ResultsPage::new_with_navigation(
+            vec![1u16, 2, 3],
+            &EmptyScanParams {},
+            |n: &u16, _| MyToken { x: *n },
+        )
+        .unwrap();
+        let unsealed = page.next_page.clone();
+        let page = page.seal(None).unwrap();
+        assert_eq!(page.next_page, unsealed);
+        let page = page.seal(Some(&keys)).unwrap();
+        for token in [&page.next_page, &page.first_cursor, &page.last_cursor] {
+            let opened =
+                open_sealed_page_token(&keys, token.as_ref().unwrap()).unwrap();
+            deserialize_page_token::<MyToken>(&opened).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sealed_page_token_expiry() {
+        let clock = TestClock::new();
+        clock.freeze();
+        let keys =
+            PageTokenKeys::new(&[7u8; 32]).with_ttl(Duration::from_secs(60));
+        keys.use_clock(Arc::new(clock.clone()));
+        let plain = serialize_page_token(&"abc").unwrap();
+        let sealed = seal_page_token(&keys, &plain).unwrap();
+
+        // Tokens open until the TTL has passed on the keys' clock...
+        clock.advance(Duration::from_millis(59_999));
+        assert_eq!(open_sealed_page_token(&keys, &sealed).unwrap(), plain);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(
+            open_sealed_page_token(&keys, &sealed).unwrap_err(),
+            "failed to parse pagination token: expired token"
+        );
+
+        // ... and without a TTL, they never expire.
+        let keys = PageTokenKeys::new(&[7u8; 32]);
+        keys.use_clock(Arc::new(clock.clone()));
+        let sealed = seal_page_token(&keys, &plain).unwrap();
+        clock.advance(Duration::from_secs(100 * 365 * 86400));
+        assert_eq!(open_sealed_page_token(&keys, &sealed).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_composite_sort() {
+        let sort = CompositeSort::new(vec![Descending, Ascending]);
+        let mut keys = vec![(1, 'a'), (2, 'b'), (1, 'c'), (2, 'a')];
+        keys.sort_by(sort.comparator());
+        assert_eq!(keys, vec![(2, 'a'), (2, 'b'), (1, 'a'), (1, 'c')]);
+        assert!(sort.is_after(&(1, 'a'), &(2, 'b')));
+        assert!(sort.is_after(&(2, 'b'), &(2, 'a')));
+        assert!(!sort.is_after(&(2, 'a'), &(2, 'a')));
+
+        // Page selectors round-trip through page tokens.
+        let selector = sort.page_selector((2u32, String::from("b")));
+        let token = serialize_page_token(&selector).unwrap();
+        let after: CompositePageSelector<(u32, String)> =
+            deserialize_page_token(&token).unwrap();
+        assert_eq!(after, selector);
+        after.sort.check::<(u32, String)>().unwrap();
+
+        // A sort with the wrong number of columns for the key is rejected.
+        let error = CompositeSort::new(vec![Ascending])
+            .check::<(u32, String)>()
+            .unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.external_message,
+            "sort has 1 columns, but expected 2"
+        );
+
+        // Up to four columns are supported.
+        let sort = CompositeSort::new(vec![
+            Ascending, Ascending, Descending, Ascending,
+        ]);
+        assert!(sort.is_after(&(1, 1, 1, 2), &(1, 1, 1, 1)));
+        assert!(sort.is_after(&(1, 1, 1, 1), &(1, 1, 2, 9)));
+    }
+
+    #[test]
+    fn test_offset_pagination() {
+        let settings = schemars::gen::SchemaSettings::openapi3();
+        let mut generator = schemars::gen::SchemaGenerator::new(settings);
+        let schema =
+            OffsetPaginationParams::json_schema(&mut generator).into_object();
+        assert_eq!(
+            *schema
+                .extensions
+                .get(&(OFFSET_PAGINATION_PARAM_SENTINEL.to_string()))
+                .unwrap(),
+            json!({})
+        );
+        let properties = schema.object.unwrap().properties;
+        assert_eq!(
+            properties.keys().collect::<Vec<_>>(),
+            vec!["limit", "offset"]
+        );
+
+        let limit = NonZeroU32::new(3).unwrap();
+        let params: OffsetPaginationParams =
+            serde_urlencoded::from_str("").unwrap();
+        assert_eq!(params.offset(), 0);
+
+        // A full page points the client at the next one.
+        let page =
+            OffsetResultsPage::new(vec![1, 2, 3], &params, limit).unwrap();
+        assert_eq!(page.next_offset, Some(3));
+
+        // A short page ends the scan.
+        let params: OffsetPaginationParams =
+            serde_urlencoded::from_str("offset=3").unwrap();
+        assert_eq!(params.offset(), 3);
+        let page = OffsetResultsPage::new(vec![4, 5], &params, limit).unwrap();
+        assert_eq!(page.next_offset, None);
+
+        // An offset that can't be advanced is a server error rather than a
+        // wrapped value.
+        let params =
+            OffsetPaginationParams { offset: Some(u64::MAX - 1), limit: None };
+        let error =
+            OffsetResultsPage::new(vec![1, 2, 3], &params, limit).unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
@@ -0,0 +1,313 @@
+// Copyright 2024 Oxide Computer Company
+//! Serialization styles for query, path, and header parameters
+//!
+//! By default, each field of a [`Query`](crate::Query) or
+//! [`Path`](crate::Path) type is a single parameter with a single value (e.g.,
+//! `?limit=10`).  Wrapping a field's type in one of the types here declares
+//! that it's serialized in another style, as OpenAPI describes it.  The style
+//! is enforced when requests are parsed, and it appears in the OpenAPI
+//! definition for the sake of generated clients:
+//!
+//! | Field type | Style | Explode | Example |
+//! | ---------- | ----- | ------- | ------- |
+//! | [`Exploded`]`<T>` | form | true | `?id=1&id=2` |
+//! | [`CommaDelimited`]`<T>` | form (simple, in paths and headers) | false | `?id=1,2` |
+//! | [`PipeDelimited`]`<T>` | pipeDelimited | false | `?id=1\|2` |
+//! | [`DeepObject`]`<T>` | deepObject | true | `?filter[kind]=small&filter[size]=2` |
+//!
+//! Only `CommaDelimited` may be used in paths and headers.
+//!
+//! ```
+//! use dropshot::CommaDelimited;
+//! use dropshot::DeepObject;
+//! use dropshot::Exploded;
+//! use schemars::JsonSchema;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize, JsonSchema)]
+//! struct Filter {
+//!     kind: Option<String>,
+//!     size: Option<u32>,
+//! }
+//!
+//! #[derive(Deserialize, JsonSchema)]
+//! struct ListQuery {
+//!     /// e.g., `?id=1&id=2`
+//!     id: Option<Exploded<u32>>,
+//!     /// e.g., `?fields=name,size`
+//!     fields: Option<CommaDelimited<String>>,
+//!     /// e.g., `?filter[kind]=small`
+//!     filter: Option<DeepObject<Filter>>,
+//! }
+//! ```
+
+use crate::api_description::ApiEndpointParameterLocation;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::de::Error;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Schema extension with which the types here declare the style of a
+/// parameter, as a [`ParameterSerialization`].  It's removed from the
+/// parameter's schema when the parameter's metadata is generated.
+pub(crate) const PARAMETER_STYLE_SENTINEL: &str = "x-dropshot-parameter-style";
+
+/// How a parameter's value is serialized (see the OpenAPI specification)
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ParameterStyle {
+    Form,
+    Simple,
+    PipeDelimited,
+    DeepObject,
+}
+
+/// The style of a parameter, along with whether it's exploded (i.e., whether
+/// each element of an array, or each property of an object, is a separate
+/// parameter)
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ParameterSerialization {
+    pub style: ParameterStyle,
+    pub explode: bool,
+}
+
+impl ParameterSerialization {
+    /// Returns how the parameter named `name` is serialized in `location`,
+    /// given how its type declares it to be serialized in a query string
+    ///
+    /// # Panics
+    ///
+    /// Panics if the style can't be used in `location`.
+    pub(crate) fn for_location(
+        self,
+        name: &str,
+        location: &ApiEndpointParameterLocation,
+    ) -> Self {
+        let simple = ParameterSerialization {
+            style: ParameterStyle::Simple,
+            explode: false,
+        };
+        match location {
+            ApiEndpointParameterLocation::Query => self,
+            ApiEndpointParameterLocation::Path
+            | ApiEndpointParameterLocation::Header
+                if self.style == ParameterStyle::Form && !self.explode =>
+            {
+                simple
+            }
+            ApiEndpointParameterLocation::Path
+            | ApiEndpointParameterLocation::Header => panic!(
+                "parameter \"{}\": only CommaDelimited values may be used \
+                 outside of query strings",
+                name
+            ),
+        }
+    }
+}
+
+/// An array parameter whose elements are separate parameters of the same
+/// name, as in `?id=1&id=2`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Exploded<T>(pub Vec<T>);
+
+/// An array parameter whose elements are separated by commas, as in
+/// `?id=1,2`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CommaDelimited<T>(pub Vec<T>);
+
+/// An array parameter whose elements are separated by pipes, as in `?id=1|2`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PipeDelimited<T>(pub Vec<T>);
+
+/// An object parameter whose properties are separate parameters, as in
+/// `?filter[kind]=small&filter[size]=2`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeepObject<T>(pub T);
+
+/// Returns `schema` with the sentinel for `style` and `explode`
+fn styled_schema(
+    schema: Schema,
+    style: ParameterStyle,
+    explode: bool,
+) -> Schema {
+    let mut schema = schema.into_object();
+    schema.extensions.insert(
+        PARAMETER_STYLE_SENTINEL.to_string(),
+        serde_json::to_value(ParameterSerialization { style, explode })
+            .unwrap(),
+    );
+    Schema::Object(schema)
+}
+
+macro_rules! array_style {
+    ($name:ident, $style:expr, $explode:expr) => {
+        impl<T: JsonSchema> JsonSchema for $name<T> {
+            fn is_referenceable() -> bool {
+                false
+            }
+
+            fn schema_name() -> String {
+                format!("{}_of_{}", stringify!($name), T::schema_name())
+            }
+
+            fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+                styled_schema(Vec::<T>::json_schema(gen), $style, $explode)
+            }
+        }
+
+        impl<T> $name<T> {
+            pub fn into_inner(self) -> Vec<T> {
+                self.0
+            }
+        }
+    };
+}
+
+array_style!(Exploded, ParameterStyle::Form, true);
+array_style!(CommaDelimited, ParameterStyle::Form, false);
+array_style!(PipeDelimited, ParameterStyle::PipeDelimited, false);
+
+impl<T: JsonSchema> JsonSchema for DeepObject<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        format!("DeepObject_of_{}", T::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        styled_schema(T::json_schema(gen), ParameterStyle::DeepObject, true)
+    }
+}
+
+impl<T> DeepObject<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Deserializes the elements of an array parameter from their raw values
+fn parse_elements<'a, T, I>(values: I) -> Result<Vec<T>, String>
+where
+    T: DeserializeOwned,
+    I: Iterator<Item = &'a str>,
+{
+    // Encoding the elements as a query string lets them be parsed just as
+    // single values are.
+    let encoded = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(values.map(|value| ("v", value)))
+        .finish();
+    parse_grouped_elements(&encoded)
+}
+
+/// Deserializes the elements of an array parameter from their values as
+/// grouped by [`group_query_parameters`]
+fn parse_grouped_elements<T: DeserializeOwned>(
+    encoded: &str,
+) -> Result<Vec<T>, String> {
+    serde_urlencoded::from_str::<Vec<(String, T)>>(encoded)
+        .map(|pairs| pairs.into_iter().map(|(_, value)| value).collect())
+        .map_err(|error| error.to_string())
+}
+
+/// Splits a delimited array parameter into its elements
+fn parse_delimited<T: DeserializeOwned>(
+    value: &str,
+    delimiter: char,
+) -> Result<Vec<T>, String> {
+    if value.is_empty() {
+        Ok(Vec::new())
+    } else {
+        parse_elements(value.split(delimiter))
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Exploded<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        parse_grouped_elements(&value).map(Exploded).map_err(D::Error::custom)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for CommaDelimited<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        parse_delimited(&value, ',')
+            .map(CommaDelimited)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for PipeDelimited<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        parse_delimited(&value, '|')
+            .map(PipeDelimited)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for DeepObject<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        serde_urlencoded::from_str(&value)
+            .map(DeepObject)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Rewrites `query` so that each parameter in `grouped` (an exploded array or
+/// a deep object, by name) is a single entry, whose value is the query string
+/// of the parameter's elements or properties, which its type then parses
+///
+/// Other entries are kept as they are.  In particular, properties of a deep
+/// object that aren't followed by a name in brackets (e.g., `?filter=x`) are
+/// left for its type to reject.
+pub(crate) fn group_query_parameters(
+    query: &str,
+    grouped: &BTreeMap<String, ParameterStyle>,
+) -> String {
+    let mut groups = BTreeMap::new();
+    let mut rest = Vec::new();
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if let Some(style) = grouped.get(&*key) {
+            if *style == ParameterStyle::Form {
+                groups
+                    .entry(key.to_string())
+                    .or_insert_with(Vec::new)
+                    .push((String::from("v"), value.to_string()));
+                continue;
+            }
+        }
+        if let Some((name, property)) = key
+            .strip_suffix(']')
+            .and_then(|key| key.split_once('['))
+            .filter(|(name, _)| {
+                grouped.get(*name) == Some(&ParameterStyle::DeepObject)
+            })
+        {
+            groups
+                .entry(name.to_string())
+                .or_insert_with(Vec::new)
+                .push((property.to_string(), value.to_string()));
+            continue;
+        }
+        rest.push((key.to_string(), value.to_string()));
+    }
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.extend_pairs(rest);
+    for (name, pairs) in groups {
+        let value = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish();
+        serializer.append_pair(&name, &value);
+    }
+    serializer.finish()
+}
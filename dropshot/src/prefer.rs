@@ -0,0 +1,308 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for the `Prefer` request header (RFC 7240)
+//!
+//! Clients use `Prefer` to ask for optional server behaviors.  Dropshot parses
+//! it for every request into [`RequestContext::preferences`].  Two preferences
+//! are common enough that Dropshot helps handlers honor them:
+//!
+//! * `return=minimal` asks the server not to send back the representation of
+//!   the resource (e.g., after creating or updating it).  Wrapping a response
+//!   in [`HttpResponsePreferred::new`] sends an empty body in that case.
+//! * `respond-async` asks the server to start a long-running operation and
+//!   respond right away.  Handlers that can do that respond with
+//!   [`HttpResponsePreferred::accepted_async`]: a 202 ("Accepted") whose
+//!   `Location` refers to a resource that reports the operation's status.
+//!
+//! Either way, the response says which preferences were honored with the
+//! `Preference-Applied` header.
+//!
+//! [`RequestContext::preferences`]: crate::RequestContext::preferences
+
+use crate::api_description::{
+    ApiEndpointAlternateResponse, ApiEndpointHeader, ApiEndpointResponse,
+    ApiSchemaGenerator,
+};
+use crate::error::HttpError;
+use crate::handler::{HttpHandlerResult, HttpResponse};
+use http::header::{self, HeaderMap, HeaderValue};
+use http::StatusCode;
+use hyper::Body;
+use std::time::Duration;
+
+/// Request header carrying preferences
+pub const HEADER_PREFER: &str = "prefer";
+/// Response header listing the preferences that were honored
+pub const HEADER_PREFERENCE_APPLIED: &str = "preference-applied";
+
+/// The `return` preference: how much of a resource to send back
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PreferReturn {
+    /// `return=minimal`: the client doesn't need the resource
+    Minimal,
+    /// `return=representation`: the client wants the resource
+    Representation,
+}
+
+/// Preferences expressed by a request's `Prefer` headers
+///
+/// Preferences are only hints, so unrecognized or malformed ones are ignored
+/// rather than failing the request.  If a preference appears more than once,
+/// only the first one counts.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Preferences {
+    /// `return=minimal` or `return=representation`
+    pub return_kind: Option<PreferReturn>,
+    /// whether the client asked for `respond-async`
+    pub respond_async: bool,
+    /// `wait`: how long the client is willing to wait for a synchronous
+    /// response
+    pub wait: Option<Duration>,
+    /// any other preferences, as (lowercased) names and optional values
+    pub other: Vec<(String, Option<String>)>,
+}
+
+impl Preferences {
+    /// Parses all of the `Prefer` headers in `headers`
+    pub fn from_headers(headers: &HeaderMap) -> Preferences {
+        let mut preferences = Preferences::default();
+        let mut seen = Vec::new();
+        let items = headers
+            .get_all(HEADER_PREFER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for item in items {
+            // Parameters (after `;`) don't mean anything for the preferences we
+            // know about, so we drop them.
+            let pref = item.split(';').next().unwrap_or("").trim();
+            let (name, value) = match pref.split_once('=') {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value);
+                    (name.trim().to_ascii_lowercase(), Some(value))
+                }
+                None => (pref.to_ascii_lowercase(), None),
+            };
+            if name.is_empty() || seen.contains(&name) {
+                continue;
+            }
+            match (name.as_str(), value) {
+                ("return", Some(v)) if v.eq_ignore_ascii_case("minimal") => {
+                    preferences.return_kind = Some(PreferReturn::Minimal);
+                }
+                ("return", Some(v))
+                    if v.eq_ignore_ascii_case("representation") =>
+                {
+                    preferences.return_kind =
+                        Some(PreferReturn::Representation);
+                }
+                ("return", _) => (),
+                ("respond-async", _) => preferences.respond_async = true,
+                ("wait", Some(v)) => {
+                    preferences.wait =
+                        v.parse::<u64>().ok().map(Duration::from_secs);
+                }
+                (_, value) => preferences
+                    .other
+                    .push((name.clone(), value.map(str::to_string))),
+            }
+            seen.push(name);
+        }
+        preferences
+    }
+}
+
+/// `HttpResponsePreferred<T>` wraps a response `T` so that it honors the
+/// client's `return` and `respond-async` preferences
+///
+/// This is described in the OpenAPI document like `T`, plus a 202
+/// ("Accepted") response with a `Location` header for
+/// [`HttpResponsePreferred::accepted_async`].
+pub struct HttpResponsePreferred<T: HttpResponse> {
+    kind: PreferredKind<T>,
+}
+
+enum PreferredKind<T> {
+    Full { response: T, applied: Option<&'static str> },
+    Minimal { response: T },
+    Accepted { location: HeaderValue },
+}
+
+impl<T: HttpResponse> HttpResponsePreferred<T> {
+    /// Returns `response`, or just its status and headers if the client
+    /// prefers `return=minimal`
+    pub fn new(
+        preferences: &Preferences,
+        response: T,
+    ) -> HttpResponsePreferred<T> {
+        let kind = match preferences.return_kind {
+            Some(PreferReturn::Minimal) => PreferredKind::Minimal { response },
+            Some(PreferReturn::Representation) => PreferredKind::Full {
+                response,
+                applied: Some("return=representation"),
+            },
+            None => PreferredKind::Full { response, applied: None },
+        };
+        HttpResponsePreferred { kind }
+    }
+
+    /// Returns a 202 ("Accepted") response for an operation that was started
+    /// asynchronously because the client prefers `respond-async`
+    ///
+    /// `status_monitor` becomes the `Location` header.  It should identify a
+    /// resource that the client can poll to find out how the operation is
+    /// going.
+    pub fn accepted_async(
+        status_monitor: &str,
+    ) -> Result<HttpResponsePreferred<T>, HttpError> {
+        let location = HeaderValue::from_str(status_monitor).map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "error encoding status monitor URL {:?}: {:#}",
+                status_monitor, e
+            ))
+        })?;
+        Ok(HttpResponsePreferred { kind: PreferredKind::Accepted { location } })
+    }
+}
+
+impl<T: HttpResponse> HttpResponse for HttpResponsePreferred<T> {
+    fn to_result(self) -> HttpHandlerResult {
+        let (mut response, applied) = match self.kind {
+            PreferredKind::Full { response, applied } => {
+                (response.to_result()?, applied)
+            }
+            PreferredKind::Minimal { response } => {
+                let response = response.to_result()?;
+                let (mut parts, _) = response.into_parts();
+                parts.headers.remove(header::CONTENT_TYPE);
+                parts.headers.remove(header::CONTENT_LENGTH);
+                (
+                    hyper::Response::from_parts(parts, Body::empty()),
+                    Some("return=minimal"),
+                )
+            }
+            PreferredKind::Accepted { location } => {
+                let response = hyper::Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .header(header::LOCATION, location)
+                    .body(Body::empty())?;
+                (response, Some("respond-async"))
+            }
+        };
+        if let Some(applied) = applied {
+            response.headers_mut().insert(
+                HEADER_PREFERENCE_APPLIED,
+                HeaderValue::from_static(applied),
+            );
+        }
+        Ok(response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        let mut metadata = T::response_metadata();
+        let location_schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        metadata.alternates.push(ApiEndpointAlternateResponse {
+            status: StatusCode::ACCEPTED,
+            description: String::from(
+                "the operation was started asynchronously",
+            ),
+            headers: vec![ApiEndpointHeader {
+                name: String::from("Location"),
+                description: Some(String::from(
+                    "status monitor for the operation",
+                )),
+                schema: ApiSchemaGenerator::Static {
+                    schema: Box::new(location_schema.into()),
+                    dependencies: indexmap::IndexMap::default(),
+                },
+                required: true,
+            }],
+        });
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HttpResponsePreferred;
+    use super::PreferReturn;
+    use super::Preferences;
+    use crate::handler::HttpResponse;
+    use crate::HttpResponseCreated;
+    use http::header::HeaderMap;
+    use http::StatusCode;
+    use std::time::Duration;
+
+    fn parse(values: &[&str]) -> Preferences {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("prefer", value.parse().unwrap());
+        }
+        Preferences::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_parse_preferences() {
+        assert_eq!(parse(&[]), Preferences::default());
+
+        let preferences = parse(&["respond-async, wait=100"]);
+        assert!(preferences.respond_async);
+        assert_eq!(preferences.wait, Some(Duration::from_secs(100)));
+        assert_eq!(preferences.return_kind, None);
+
+        let preferences =
+            parse(&["Return=Minimal; foo=\"bar\"", "return=representation"]);
+        assert_eq!(preferences.return_kind, Some(PreferReturn::Minimal));
+        assert!(preferences.other.is_empty());
+
+        let preferences =
+            parse(&["handling=lenient, odd=\"x\", , return=bogus"]);
+        assert_eq!(preferences.return_kind, None);
+        assert_eq!(
+            preferences.other,
+            vec![
+                (String::from("handling"), Some(String::from("lenient"))),
+                (String::from("odd"), Some(String::from("x"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preferred_response() {
+        let minimal = parse(&["return=minimal"]);
+        let response = HttpResponsePreferred::new(
+            &minimal,
+            HttpResponseCreated(String::from("widget")),
+        )
+        .to_result()
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["preference-applied"], "return=minimal");
+        assert!(response.headers().get("content-type").is_none());
+
+        let response = HttpResponsePreferred::new(
+            &Preferences::default(),
+            HttpResponseCreated(String::from("widget")),
+        )
+        .to_result()
+        .unwrap();
+        assert!(response.headers().get("preference-applied").is_none());
+        assert!(response.headers().get("content-type").is_some());
+
+        let response = HttpResponsePreferred::<HttpResponseCreated<String>>::accepted_async(
+            "/operations/1",
+        )
+        .unwrap()
+        .to_result()
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers()["location"], "/operations/1");
+        assert_eq!(response.headers()["preference-applied"], "respond-async");
+    }
+}
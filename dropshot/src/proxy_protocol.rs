@@ -0,0 +1,577 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for the PROXY protocol (versions 1 and 2)
+//!
+//! A load balancer that forwards TCP connections (rather than HTTP requests)
+//! can't add a `Forwarded` header, so it instead sends a short header of its
+//! own at the start of each connection describing the client's address (see
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>).  When
+//! [`crate::ConfigDropshot::proxy_protocol`] is set, every connection must
+//! come from one of the [`crate::ConfigDropshot::trusted_proxies`] and must
+//! begin with this header.  The address it reports is then used as the
+//! connection's remote address.
+
+use crate::connection::ConnectionInfo;
+use crate::forwarded::IpCidr;
+use crate::server::ServerConfig;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::FutureExt;
+use futures::Stream;
+use hyper::server::accept::Accept;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+/// Start of every version 1 header
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of a version 1 header, including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+/// Start of every version 2 header
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Length of the fixed part of a version 2 header
+const V2_FIXED_LEN: usize = 16;
+
+/// How long a proxy has to send the header if the server has no
+/// [`crate::ConfigDropshot::header_read_timeout_ms`]
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of trying to parse a header from the data received so far
+#[derive(Debug, Eq, PartialEq)]
+enum Parsed {
+    /// more data is needed
+    Incomplete,
+    /// the header was `len` bytes long and reported the client's address as
+    /// `source`, if it reported one
+    Complete { source: Option<SocketAddr>, len: usize },
+}
+
+/// Parses a PROXY protocol header (of either version) from the start of `buf`
+fn parse_header(buf: &[u8]) -> Result<Parsed, String> {
+    if is_prefix(buf, V1_PREFIX) {
+        if buf.len() < V1_PREFIX.len() {
+            return Ok(Parsed::Incomplete);
+        }
+        parse_v1(buf)
+    } else if is_prefix(buf, V2_SIGNATURE) {
+        if buf.len() < V2_FIXED_LEN {
+            return Ok(Parsed::Incomplete);
+        }
+        parse_v2(buf)
+    } else {
+        Err(String::from("missing PROXY protocol header"))
+    }
+}
+
+/// Returns whether `buf` and `prefix` agree for as long as both have data
+fn is_prefix(buf: &[u8], prefix: &[u8]) -> bool {
+    let n = std::cmp::min(buf.len(), prefix.len());
+    buf[..n] == prefix[..n]
+}
+
+/// Parses a version 1 (text) header, which looks like
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(buf: &[u8]) -> Result<Parsed, String> {
+    let searched = &buf[..std::cmp::min(buf.len(), V1_MAX_LEN)];
+    let Some(end) = searched.windows(2).position(|w| w == b"\r\n") else {
+        return if buf.len() < V1_MAX_LEN {
+            Ok(Parsed::Incomplete)
+        } else {
+            Err(String::from("PROXY protocol header is too long"))
+        };
+    };
+    let line = std::str::from_utf8(&buf[V1_PREFIX.len()..end])
+        .map_err(|_| String::from("PROXY protocol header is not ASCII"))?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    let source = match fields[0] {
+        "UNKNOWN" => None,
+        "TCP4" | "TCP6" => {
+            let [_, src_addr, _, src_port, _] = fields[..] else {
+                return Err(format!(
+                    "malformed PROXY protocol header: {:?}",
+                    line
+                ));
+            };
+            let ip = src_addr.parse::<IpAddr>().map_err(|_| {
+                format!("invalid source address in PROXY header: {:?}", line)
+            })?;
+            if ip.is_ipv4() != (fields[0] == "TCP4") {
+                return Err(format!(
+                    "source address does not match protocol in PROXY \
+                     header: {:?}",
+                    line
+                ));
+            }
+            let port = src_port.parse::<u16>().map_err(|_| {
+                format!("invalid source port in PROXY header: {:?}", line)
+            })?;
+            Some(SocketAddr::new(ip, port))
+        }
+        protocol => {
+            return Err(format!(
+                "unsupported protocol in PROXY header: {:?}",
+                protocol
+            ));
+        }
+    };
+    Ok(Parsed::Complete { source, len: end + 2 })
+}
+
+/// Parses a version 2 (binary) header
+fn parse_v2(buf: &[u8]) -> Result<Parsed, String> {
+    let version_command = buf[12];
+    if version_command >> 4 != 2 {
+        return Err(format!(
+            "unsupported PROXY protocol version {}",
+            version_command >> 4
+        ));
+    }
+    let addr_len = usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    let len = V2_FIXED_LEN + addr_len;
+    if buf.len() < len {
+        return Ok(Parsed::Incomplete);
+    }
+    let addrs = &buf[V2_FIXED_LEN..len];
+    let source = match (version_command & 0xf, buf[13]) {
+        // LOCAL: the connection was made by the proxy itself (e.g., for a
+        // health check), so its own address is the right one.
+        (0x0, _) => None,
+        // PROXY over TCP/IPv4
+        (0x1, 0x11) => {
+            if addrs.len() < 12 {
+                return Err(String::from("truncated PROXY header addresses"));
+            }
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[0..4]).unwrap());
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        // PROXY over TCP/IPv6
+        (0x1, 0x21) => {
+            if addrs.len() < 36 {
+                return Err(String::from("truncated PROXY header addresses"));
+            }
+            let ip =
+                Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[0..16]).unwrap());
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Some(SocketAddr::new(IpAddr::V6(ip), port))
+        }
+        // Any other address family (e.g., unspecified or a Unix socket)
+        // doesn't tell us anything we can use.
+        (0x1, _) => None,
+        (command, _) => {
+            return Err(format!(
+                "unsupported PROXY protocol command {}",
+                command
+            ));
+        }
+    };
+    Ok(Parsed::Complete { source, len })
+}
+
+/// Settings for reading PROXY protocol headers, if they're expected
+#[derive(Clone, Debug)]
+pub(crate) struct ProxyProtocol {
+    trusted_proxies: Arc<[IpCidr]>,
+    timeout: Duration,
+}
+
+impl ProxyProtocol {
+    /// Returns the settings for a server with the given configuration, or
+    /// `None` if it doesn't use the PROXY protocol
+    pub(crate) fn new(config: &ServerConfig) -> Option<ProxyProtocol> {
+        config.proxy_protocol.then(|| ProxyProtocol {
+            trusted_proxies: config.trusted_proxies.clone().into(),
+            timeout: config
+                .header_read_timeout
+                .unwrap_or(DEFAULT_HEADER_TIMEOUT),
+        })
+    }
+
+    /// Reads the header from `io`, a connection from `peer`, returning a
+    /// stream that produces whatever follows it
+    pub(crate) async fn accept<S: AsyncRead + Unpin>(
+        &self,
+        io: S,
+        peer: SocketAddr,
+    ) -> io::Result<ProxiedStream<S>> {
+        let peer_ip = peer.ip();
+        if !self.trusted_proxies.iter().any(|cidr| cidr.contains(&peer_ip)) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "connection is not from a trusted proxy",
+            ));
+        }
+        match tokio::time::timeout(self.timeout, read_header(io)).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out reading PROXY protocol header",
+            )),
+        }
+    }
+}
+
+/// Reads a PROXY protocol header from the start of `io`
+async fn read_header<S: AsyncRead + Unpin>(
+    mut io: S,
+) -> io::Result<ProxiedStream<S>> {
+    let mut buf = Vec::with_capacity(V1_MAX_LEN);
+    loop {
+        match parse_header(&buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
+            Parsed::Complete { source, len } => {
+                // Anything read past the header is the start of the
+                // connection's real data.
+                buf.drain(..len);
+                return Ok(ProxiedStream {
+                    prefix: buf,
+                    offset: 0,
+                    io,
+                    source,
+                });
+            }
+            Parsed::Incomplete => (),
+        }
+        if io.read_buf(&mut buf).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before PROXY protocol header",
+            ));
+        }
+    }
+}
+
+/// A connection whose PROXY protocol header (if any) has been read
+#[derive(Debug)]
+pub(crate) struct ProxiedStream<S> {
+    /// data read along with the header, to be produced before reading more
+    prefix: Vec<u8>,
+    /// how much of `prefix` has been produced already
+    offset: usize,
+    io: S,
+    /// client address reported by the header
+    source: Option<SocketAddr>,
+}
+
+impl<S> ProxiedStream<S> {
+    /// Wraps a connection that doesn't have a PROXY protocol header
+    pub(crate) fn direct(io: S) -> ProxiedStream<S> {
+        ProxiedStream { prefix: Vec::new(), offset: 0, io, source: None }
+    }
+
+    /// Returns the client address reported by the header, if there was one
+    pub(crate) fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+}
+
+impl<S: ConnectionInfo> ConnectionInfo for ProxiedStream<S> {
+    fn remote_addr(&self) -> SocketAddr {
+        self.source.unwrap_or_else(|| self.io.remote_addr())
+    }
+
+    fn peer_credentials(&self) -> Option<crate::unix_socket::PeerCredentials> {
+        self.io.peer_credentials()
+    }
+
+    fn client_certificate(
+        &self,
+    ) -> Option<crate::tls_client_auth::ClientCertificate> {
+        self.io.client_certificate()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ProxiedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.offset < this.prefix.len() {
+            let remaining = &this.prefix[this.offset..];
+            let n = std::cmp::min(remaining.len(), buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            this.offset += n;
+            if this.offset == this.prefix.len() {
+                this.prefix = Vec::new();
+                this.offset = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.io).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ProxiedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// Wraps an acceptor of plain TCP connections, reading the PROXY protocol
+/// header of each one (if the server expects them)
+///
+/// Headers are read concurrently, so a slow proxy doesn't hold up others.
+/// Connections whose header can't be read are logged and closed.
+pub(crate) struct ProxyAcceptor<A: Accept> {
+    inner: A,
+    proxy_protocol: Option<ProxyProtocol>,
+    /// whether `inner` has stopped producing connections
+    inner_done: bool,
+    pending:
+        FuturesUnordered<BoxFuture<'static, Option<ProxiedStream<A::Conn>>>>,
+}
+
+impl<A: Accept> ProxyAcceptor<A> {
+    pub(crate) fn new(
+        inner: A,
+        proxy_protocol: Option<ProxyProtocol>,
+    ) -> ProxyAcceptor<A> {
+        ProxyAcceptor {
+            inner,
+            proxy_protocol,
+            inner_done: false,
+            pending: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl<A> Accept for ProxyAcceptor<A>
+where
+    A: Accept + Unpin,
+    A::Conn: ConnectionInfo + AsyncRead + Unpin + Send + 'static,
+{
+    type Conn = ProxiedStream<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        while !this.inner_done {
+            let io = match Pin::new(&mut this.inner).poll_accept(cx) {
+                Poll::Ready(Some(Ok(io))) => io,
+                Poll::Ready(Some(Err(error))) => {
+                    return Poll::Ready(Some(Err(error)))
+                }
+                Poll::Ready(None) => {
+                    this.inner_done = true;
+                    break;
+                }
+                Poll::Pending => break,
+            };
+            let Some(proxy_protocol) = &this.proxy_protocol else {
+                return Poll::Ready(Some(Ok(ProxiedStream::direct(io))));
+            };
+            let proxy_protocol = proxy_protocol.clone();
+            this.pending.push(
+                async move {
+                    let peer = io.remote_addr();
+                    proxy_protocol
+                        .accept(io, peer)
+                        .await
+                        .map_err(|error| {
+                            warn!(
+                                remote_addr = %peer,
+                                %error,
+                                "closing connection: bad PROXY protocol header"
+                            );
+                        })
+                        .ok()
+                }
+                .boxed(),
+            );
+        }
+
+        loop {
+            match Pin::new(&mut this.pending).poll_next(cx) {
+                Poll::Ready(Some(Some(conn))) => {
+                    return Poll::Ready(Some(Ok(conn)))
+                }
+                Poll::Ready(Some(None)) => continue,
+                Poll::Ready(None) if this.inner_done => {
+                    return Poll::Ready(None)
+                }
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_header;
+    use super::Parsed;
+    use super::ProxyProtocol;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    fn addr(s: &str) -> Option<SocketAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+        assert_eq!(
+            parse_header(header),
+            Ok(Parsed::Complete { source: addr("192.0.2.1:56324"), len: 45 })
+        );
+        assert_eq!(
+            parse_header(b"PROXY TCP6 2001:db8::1 2001:db8::2 80 443\r\n"),
+            Ok(Parsed::Complete { source: addr("[2001:db8::1]:80"), len: 43 })
+        );
+        assert_eq!(
+            parse_header(b"PROXY UNKNOWN\r\n"),
+            Ok(Parsed::Complete { source: None, len: 15 })
+        );
+
+        // Every prefix of a header is incomplete.
+        for n in 0..45 {
+            assert_eq!(parse_header(&header[..n]), Ok(Parsed::Incomplete));
+        }
+
+        for bad in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 80 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 70000 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            &[b'P'; 200][..],
+        ] {
+            assert!(parse_header(bad).is_err(), "{:?}", bad);
+        }
+        let mut long = b"PROXY UNKNOWN".to_vec();
+        long.resize(200, b' ');
+        assert!(parse_header(&long).is_err());
+    }
+
+    fn v2_header(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = super::V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        header.extend_from_slice(addrs);
+        header
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut addrs = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+        // Trailing TLVs are skipped.
+        addrs.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]);
+        let header = v2_header(0x1, 0x11, &addrs);
+        assert_eq!(
+            parse_header(&header),
+            Ok(Parsed::Complete { source: addr("192.0.2.1:56324"), len: 32 })
+        );
+        for n in 0..header.len() {
+            assert_eq!(parse_header(&header[..n]), Ok(Parsed::Incomplete));
+        }
+
+        let mut addrs = vec![0u8; 36];
+        addrs[0] = 0x20;
+        addrs[1] = 0x01;
+        addrs[15] = 0x01;
+        addrs[32..34].copy_from_slice(&80u16.to_be_bytes());
+        assert_eq!(
+            parse_header(&v2_header(0x1, 0x21, &addrs)),
+            Ok(Parsed::Complete { source: addr("[2001::1]:80"), len: 52 })
+        );
+
+        // LOCAL connections and unknown families have no source address.
+        assert_eq!(
+            parse_header(&v2_header(0x0, 0x11, &[0; 12])),
+            Ok(Parsed::Complete { source: None, len: 28 })
+        );
+        assert_eq!(
+            parse_header(&v2_header(0x1, 0x00, &[])),
+            Ok(Parsed::Complete { source: None, len: 16 })
+        );
+
+        assert!(parse_header(&v2_header(0x1, 0x11, &[0; 4])).is_err());
+        assert!(parse_header(&v2_header(0x2, 0x11, &[0; 12])).is_err());
+        let mut bad_version = v2_header(0x1, 0x11, &[0; 12]);
+        bad_version[12] = 0x11;
+        assert!(parse_header(&bad_version).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let proxy_protocol = ProxyProtocol {
+            trusted_proxies: vec!["127.0.0.0/8".parse().unwrap()].into(),
+            timeout: Duration::from_secs(5),
+        };
+        let proxy = addr("127.0.0.1:1234").unwrap();
+
+        // The data after the header is readable whether or not it arrived
+        // along with the header.
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 5000 80\r\nhello")
+            .await
+            .unwrap();
+        let mut stream = proxy_protocol.accept(server, proxy).await.unwrap();
+        assert_eq!(stream.source(), addr("192.0.2.1:5000"));
+        client.write_all(b" world").await.unwrap();
+        drop(client);
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "hello world");
+
+        // Connections from untrusted peers are refused.
+        let (_client, server) = tokio::io::duplex(1024);
+        let error = proxy_protocol
+            .accept(server, addr("192.0.2.9:1234").unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+
+        // So are connections without a header.
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let error = proxy_protocol.accept(server, proxy).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
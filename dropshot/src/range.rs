@@ -0,0 +1,374 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for HTTP range requests
+//!
+//! Clients can ask for part of a resource (e.g., to resume a download or to
+//! seek within a media file) using the `Range` header.  The [`Range`]
+//! extractor parses this header, and [`HttpResponsePartialContent`] produces
+//! the appropriate response: a 206 ("Partial Content") with `Content-Range`
+//! when the requested range can be satisfied, a 416 ("Range Not Satisfiable")
+//! when it can't, and a 200 with the whole representation when no range was
+//! requested.
+//!
+//! Only single byte ranges are supported.  As RFC 9110 allows, requests for
+//! multiple ranges (which would call for a `multipart/byteranges` response)
+//! and malformed `Range` headers are ignored, and the whole representation is
+//! sent.
+
+use crate::api_description::{
+    ApiEndpointBodyContentType, ApiEndpointResponse, ExtensionMode,
+};
+use crate::error::HttpError;
+use crate::handler::{HttpHandlerResult, HttpResponse};
+use crate::server::ServerContext;
+use crate::{ExtractorMetadata, RequestContext, SharedExtractor};
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::header::{self, HeaderValue};
+use http::StatusCode;
+use hyper::{Body, Response};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Size of the chunks in which [`HttpResponsePartialContent::from_file`]
+/// reads files
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A single range of bytes from a `Range` request header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ByteRange {
+    /// `bytes=start-end`: bytes `start` through `end`, inclusive
+    FromTo { start: u64, end: u64 },
+    /// `bytes=start-`: bytes from `start` through the end
+    From { start: u64 },
+    /// `bytes=-len`: the last `len` bytes
+    Suffix { len: u64 },
+}
+
+impl ByteRange {
+    /// Parses the value of a `Range` header, returning `None` if it's malformed
+    /// or asks for more than one range
+    pub fn parse(value: &str) -> Option<ByteRange> {
+        let (unit, spec) = value.trim().split_once('=')?;
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            return None;
+        }
+        let (first, last) = spec.trim().split_once('-')?;
+        let parse_pos = |s: &str| {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                None
+            } else {
+                s.parse::<u64>().ok()
+            }
+        };
+        match (first.is_empty(), last.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some(ByteRange::Suffix { len: parse_pos(last)? }),
+            (false, true) => Some(ByteRange::From { start: parse_pos(first)? }),
+            (false, false) => {
+                let start = parse_pos(first)?;
+                let end = parse_pos(last)?;
+                (start <= end).then_some(ByteRange::FromTo { start, end })
+            }
+        }
+    }
+
+    /// Returns the (half-open) range of offsets selected by this range within
+    /// a representation that's `total_len` bytes long, or `None` if none of
+    /// those bytes exist
+    pub fn resolve(&self, total_len: u64) -> Option<std::ops::Range<u64>> {
+        match *self {
+            ByteRange::FromTo { start, end } => (start < total_len)
+                .then(|| start..end.saturating_add(1).min(total_len)),
+            ByteRange::From { start } => {
+                (start < total_len).then_some(start..total_len)
+            }
+            ByteRange::Suffix { len } => (len > 0 && total_len > 0)
+                .then(|| total_len - len.min(total_len)..total_len),
+        }
+    }
+}
+
+/// `Range` is an extractor for the `Range` request header
+///
+/// This is `None` if the request didn't include a usable `Range` header, in
+/// which case the whole representation should be sent.  See the module-level
+/// documentation for details.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Range(pub Option<ByteRange>);
+
+impl Range {
+    /// Returns the requested range, if any
+    pub fn into_inner(self) -> Option<ByteRange> {
+        self.0
+    }
+}
+
+#[async_trait]
+impl SharedExtractor for Range {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+    ) -> Result<Range, HttpError> {
+        let range = rqctx
+            .request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ByteRange::parse);
+        Ok(Range(range))
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        ExtractorMetadata {
+            parameters: vec![],
+            extension_mode: ExtensionMode::None,
+        }
+    }
+}
+
+/// `HttpResponsePartialContent` is a response to a request that may have
+/// asked for only part of a resource with the `Range` header
+///
+/// Depending on the requested range, this is a 206 ("Partial Content")
+/// response carrying just the requested bytes, a 416 ("Range Not
+/// Satisfiable") response if none of the requested bytes exist, or a 200
+/// ("OK") response carrying the whole representation if no range was
+/// requested.  All of these include `Accept-Ranges: bytes`, advertising that
+/// range requests are supported.
+///
+/// Because the status code varies, this is described in the OpenAPI document
+/// like a hand-rolled `Response<Body>`.
+#[derive(Debug)]
+pub struct HttpResponsePartialContent {
+    response: Response<Body>,
+}
+
+impl HttpResponsePartialContent {
+    /// Responds to `range` with the corresponding part of `content`
+    pub fn from_bytes(
+        range: &Range,
+        content_type: &str,
+        content: Bytes,
+    ) -> Result<HttpResponsePartialContent, HttpError> {
+        HttpResponsePartialContent::from_body_fn(
+            range,
+            content_type,
+            content.len() as u64,
+            |selected| {
+                Body::from(
+                    content
+                        .slice(selected.start as usize..selected.end as usize),
+                )
+            },
+        )
+    }
+
+    /// Responds to `range` with the corresponding part of `file`, which is
+    /// streamed to the client rather than read into memory
+    pub async fn from_file(
+        range: &Range,
+        content_type: &str,
+        mut file: tokio::fs::File,
+    ) -> Result<HttpResponsePartialContent, HttpError> {
+        let total_len = file
+            .metadata()
+            .await
+            .map_err(|e| {
+                HttpError::for_internal_error(format!(
+                    "reading file metadata: {}",
+                    e
+                ))
+            })?
+            .len();
+        let start =
+            range.0.and_then(|r| r.resolve(total_len)).map_or(0, |r| r.start);
+        file.seek(std::io::SeekFrom::Start(start)).await.map_err(|e| {
+            HttpError::for_internal_error(format!("seeking file: {}", e))
+        })?;
+        HttpResponsePartialContent::from_body_fn(
+            range,
+            content_type,
+            total_len,
+            |selected| {
+                let reader = file.take(selected.end - selected.start);
+                Body::wrap_stream(read_chunks(reader))
+            },
+        )
+    }
+
+    /// Responds to `range` for a representation that's `total_len` bytes long
+    /// and whose contents are produced by `make_body`
+    ///
+    /// `make_body` is given the (half-open) range of offsets to send (which
+    /// covers the whole representation if no range was requested) and must
+    /// return a body with exactly those bytes, e.g., as a stream.  It's not
+    /// called if the requested range can't be satisfied.
+    pub fn from_body_fn<F>(
+        range: &Range,
+        content_type: &str,
+        total_len: u64,
+        make_body: F,
+    ) -> Result<HttpResponsePartialContent, HttpError>
+    where
+        F: FnOnce(std::ops::Range<u64>) -> Body,
+    {
+        let builder = Response::builder()
+            .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        let response = match range.0.map(|r| r.resolve(total_len)) {
+            None => builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_LENGTH, total_len)
+                .body(make_body(0..total_len))?,
+            Some(Some(selected)) => builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_LENGTH, selected.end - selected.start)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!(
+                        "bytes {}-{}/{}",
+                        selected.start,
+                        selected.end - 1,
+                        total_len
+                    ),
+                )
+                .body(make_body(selected))?,
+            Some(None) => builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", total_len))
+                .body(Body::empty())?,
+        };
+        Ok(HttpResponsePartialContent { response })
+    }
+
+    /// Returns the status code of this response
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+}
+
+/// Returns a stream of the contents of `reader`
+fn read_chunks<R>(
+    mut reader: R,
+) -> impl futures::Stream<Item = std::io::Result<Bytes>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    async_stream::try_stream! {
+        loop {
+            let mut chunk = vec![0; FILE_CHUNK_SIZE];
+            let n = reader.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            chunk.truncate(n);
+            yield Bytes::from(chunk);
+        }
+    }
+}
+
+impl HttpResponse for HttpResponsePartialContent {
+    fn to_result(self) -> HttpHandlerResult {
+        Ok(self.response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        ApiEndpointResponse::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ByteRange;
+    use super::HttpResponsePartialContent;
+    use super::Range;
+    use bytes::Bytes;
+    use http::StatusCode;
+
+    #[test]
+    fn test_byte_range_parse() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-499"),
+            Some(ByteRange::FromTo { start: 0, end: 499 })
+        );
+        assert_eq!(
+            ByteRange::parse("Bytes = 500-"),
+            Some(ByteRange::From { start: 500 })
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-500"),
+            Some(ByteRange::Suffix { len: 500 })
+        );
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+        assert_eq!(ByteRange::parse("bytes=5-4"), None);
+        assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+        assert_eq!(ByteRange::parse("bytes=+1-2"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("0-1"), None);
+    }
+
+    #[test]
+    fn test_byte_range_resolve() {
+        let from_to = ByteRange::FromTo { start: 2, end: 5 };
+        assert_eq!(from_to.resolve(10), Some(2..6));
+        assert_eq!(from_to.resolve(4), Some(2..4));
+        assert_eq!(from_to.resolve(2), None);
+
+        let from = ByteRange::From { start: 7 };
+        assert_eq!(from.resolve(10), Some(7..10));
+        assert_eq!(from.resolve(7), None);
+
+        let suffix = ByteRange::Suffix { len: 3 };
+        assert_eq!(suffix.resolve(10), Some(7..10));
+        assert_eq!(suffix.resolve(2), Some(0..2));
+        assert_eq!(suffix.resolve(0), None);
+        assert_eq!(ByteRange::Suffix { len: 0 }.resolve(10), None);
+
+        let huge = ByteRange::FromTo { start: 0, end: u64::MAX };
+        assert_eq!(huge.resolve(10), Some(0..10));
+    }
+
+    #[test]
+    fn test_partial_content_from_bytes() {
+        let content = Bytes::from_static(b"0123456789");
+        let respond = |range| {
+            let response = HttpResponsePartialContent::from_bytes(
+                &Range(range),
+                "text/plain",
+                content.clone(),
+            )
+            .unwrap()
+            .response;
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .map(|v: &http::HeaderValue| v.to_str().unwrap().to_owned())
+            };
+            (
+                response.status(),
+                header(http::header::CONTENT_RANGE),
+                header(http::header::CONTENT_LENGTH),
+            )
+        };
+
+        assert_eq!(respond(None), (StatusCode::OK, None, Some("10".into())));
+        assert_eq!(
+            respond(Some(ByteRange::FromTo { start: 1, end: 3 })),
+            (
+                StatusCode::PARTIAL_CONTENT,
+                Some("bytes 1-3/10".into()),
+                Some("3".into())
+            )
+        );
+        assert_eq!(
+            respond(Some(ByteRange::From { start: 10 })),
+            (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                Some("bytes */10".into()),
+                None
+            )
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2024 Oxide Computer Company
+//! Process-wide registry of running Dropshot servers
+//!
+//! Programs often run several Dropshot servers (e.g., for an API, an admin
+//! interface, and metrics), and it's not always clear from the outside which
+//! one is bound where.  With the "server-registry" feature enabled, each
+//! [`HttpServer`] is recorded here from the time it starts until it's dropped
+//! (including after [`HttpServer::close`] completes), and [`running_servers`]
+//! describes all of them.  Programs can also register the endpoint created by
+//! [`running_servers_endpoint`] on an admin server.
+//!
+//! [`HttpServer`]: crate::HttpServer
+//! [`HttpServer::close`]: crate::HttpServer::close
+//! [`running_servers_endpoint`]: crate::running_servers_endpoint
+
+use crate::server::{DropshotState, ServerContext};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Description of a running Dropshot server, as returned by
+/// [`running_servers`]
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct RunningServer {
+    /// identifies this server among those in this process
+    pub id: u64,
+    /// address on which the server is listening
+    pub local_addr: SocketAddr,
+    /// whether the server accepts connections over TLS
+    pub tls: bool,
+    /// when the server was started
+    pub started: DateTime<Utc>,
+    /// number of registered endpoints (counting each method separately)
+    pub nroutes: usize,
+    /// number of requests currently being handled
+    pub requests_in_flight: usize,
+    /// number of requests received since the server started
+    pub requests_total: u64,
+    /// number of websocket connections currently being handled
+    pub websocket_connections: usize,
+    /// number of websocket connections registered with the server's
+    /// websocket registry, if it has one
+    pub websocket_registered: Option<usize>,
+    /// number of connections currently open
+    pub connections: usize,
+    /// number of connections closed as soon as they were accepted because
+    /// the server already had its maximum number open
+    pub connections_refused: u64,
+    /// number of requests rejected because the server was overloaded
+    pub requests_shed: u64,
+    /// number of connections closed because the client took too long to send
+    /// a request's headers
+    pub header_read_timeouts: u64,
+    /// number of connections closed because the client sent a request's body
+    /// too slowly
+    pub slow_request_bodies: u64,
+    /// whether the server has been asked to shut down
+    pub draining: bool,
+    /// whether the server is rejecting new requests because it's quiescing
+    pub quiescing: bool,
+}
+
+/// Type-erased view of a server's state, so that servers with different
+/// context types can be stored together
+trait RegisteredState: Send + Sync {
+    fn describe(&self, id: u64, started: DateTime<Utc>) -> RunningServer;
+}
+
+impl<C: ServerContext> RegisteredState for DropshotState<C> {
+    fn describe(&self, id: u64, started: DateTime<Utc>) -> RunningServer {
+        RunningServer {
+            id,
+            local_addr: self.local_addr,
+            tls: self.using_tls(),
+            started,
+            nroutes: self.router.into_iter().count(),
+            requests_in_flight: self.request_in_flight_count(),
+            requests_total: self.request_count(),
+            websocket_connections: self.websocket_connection_count(),
+            websocket_registered: self
+                .websocket_registry()
+                .map(|registry| registry.connection_count()),
+            connections: self.connection_count(),
+            connections_refused: self.refused_connection_count(),
+            requests_shed: self.shed_request_count(),
+            header_read_timeouts: self.header_read_timeout_count(),
+            slow_request_bodies: self.slow_request_body_count(),
+            draining: self.is_draining(),
+            quiescing: self.is_quiescing(),
+        }
+    }
+}
+
+struct RegistryEntry {
+    id: u64,
+    started: DateTime<Utc>,
+    state: Weak<dyn RegisteredState>,
+}
+
+static REGISTRY: Mutex<Vec<RegistryEntry>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Keeps a server in the registry until dropped
+#[derive(Debug)]
+pub(crate) struct Registration {
+    id: u64,
+}
+
+impl Registration {
+    pub(crate) fn new<C: ServerContext>(
+        state: &Arc<DropshotState<C>>,
+    ) -> Registration {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let state: Weak<DropshotState<C>> = Arc::downgrade(state);
+        REGISTRY.lock().unwrap().push(RegistryEntry {
+            id,
+            started: Utc::now(),
+            state,
+        });
+        Registration { id }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().retain(|entry| entry.id != self.id);
+    }
+}
+
+/// Describes the Dropshot servers running in this process, in the order they
+/// were started
+pub fn running_servers() -> Vec<RunningServer> {
+    let registry = REGISTRY.lock().unwrap();
+    registry
+        .iter()
+        .filter_map(|entry| {
+            // The state can go away while a server that's been closed is
+            // still finishing up, before it's removed from the registry.
+            let state = entry.state.upgrade()?;
+            Some(state.describe(entry.id, entry.started))
+        })
+        .collect()
+}
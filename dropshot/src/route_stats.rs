@@ -0,0 +1,161 @@
+// Copyright 2024 Oxide Computer Company
+//! Counting the requests handled by each endpoint
+//!
+//! A server keeps a [`RouteCounters`] for each endpoint registered with its
+//! API (identified by its method, path template, and versions, since none of
+//! these, nor its operation id, need be unique by itself), from which
+//! [`crate::DropshotState::route_stats`] (and
+//! [`crate::HttpServer::route_stats`]) report a [`RouteStats`].  Requests that
+//! don't match any endpoint aren't counted here.
+
+use crate::router::HttpRouter;
+use crate::server::ServerContext;
+use crate::versioning::ApiEndpointVersions;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Counts of the requests handled by one endpoint since the server started
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct RouteStats {
+    /// HTTP method of the endpoint
+    pub method: String,
+    /// path template of the endpoint (e.g., "/projects/{project}")
+    pub path: String,
+    /// versions of the API in which the endpoint appears (e.g., "versions
+    /// 2.0.0..", or "all versions")
+    pub versions: String,
+    /// the endpoint's operation id
+    pub operation_id: String,
+    /// number of requests received
+    pub requests: u64,
+    /// number of requests currently being handled
+    pub in_flight: usize,
+    /// number of requests that completed with a 4xx status
+    pub client_errors: u64,
+    /// number of requests that completed with a 5xx status
+    pub server_errors: u64,
+}
+
+/// Counters for one endpoint
+#[derive(Debug)]
+struct RouteCounters {
+    operation_id: String,
+    requests: AtomicU64,
+    in_flight: AtomicUsize,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+/// Identifies an endpoint: its method, path template, and versions
+type RouteKey = (String, String, String);
+
+/// Counters for each of a server's endpoints
+#[derive(Debug, Default)]
+pub(crate) struct RouteStatsTable(BTreeMap<RouteKey, RouteCounters>);
+
+impl RouteStatsTable {
+    pub(crate) fn new<C: ServerContext>(
+        router: &HttpRouter<C>,
+    ) -> RouteStatsTable {
+        let routes = router
+            .into_iter()
+            .map(|(_, method, endpoint)| {
+                let counters = RouteCounters {
+                    operation_id: endpoint.operation_id.clone(),
+                    requests: AtomicU64::new(0),
+                    in_flight: AtomicUsize::new(0),
+                    client_errors: AtomicU64::new(0),
+                    server_errors: AtomicU64::new(0),
+                };
+                // The endpoint's own path is used, rather than the router's
+                // rendering of it, since that's what lookups report.
+                let key = (
+                    method,
+                    endpoint.path.clone(),
+                    endpoint.versions.to_string(),
+                );
+                (key, counters)
+            })
+            .collect();
+        RouteStatsTable(routes)
+    }
+
+    /// Records the start of a request to the endpoint for `method` and the
+    /// path template `path` in `versions`, which ends when
+    /// [`RouteRequest::end`] is called or the returned value is dropped
+    pub(crate) fn begin(
+        &self,
+        method: &http::Method,
+        path: &str,
+        versions: &ApiEndpointVersions,
+    ) -> RouteRequest<'_> {
+        let key = (
+            method.as_str().to_uppercase(),
+            path.to_string(),
+            versions.to_string(),
+        );
+        let counters = self.0.get(&key);
+        if let Some(counters) = counters {
+            counters.requests.fetch_add(1, Ordering::SeqCst);
+            counters.in_flight.fetch_add(1, Ordering::SeqCst);
+        }
+        RouteRequest { counters }
+    }
+
+    /// Describes the requests handled by each endpoint, in order of their
+    /// paths
+    pub(crate) fn stats(&self) -> Vec<RouteStats> {
+        let mut stats: Vec<_> = self
+            .0
+            .iter()
+            .map(|((method, path, versions), counters)| RouteStats {
+                method: method.clone(),
+                path: path.clone(),
+                versions: versions.clone(),
+                operation_id: counters.operation_id.clone(),
+                requests: counters.requests.load(Ordering::SeqCst),
+                in_flight: counters.in_flight.load(Ordering::SeqCst),
+                client_errors: counters.client_errors.load(Ordering::SeqCst),
+                server_errors: counters.server_errors.load(Ordering::SeqCst),
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (&a.path, &a.method, &a.versions).cmp(&(
+                &b.path,
+                &b.method,
+                &b.versions,
+            ))
+        });
+        stats
+    }
+}
+
+/// A request being handled by an endpoint
+pub(crate) struct RouteRequest<'a> {
+    counters: Option<&'a RouteCounters>,
+}
+
+impl RouteRequest<'_> {
+    /// Records that the request completed with status `status`
+    pub(crate) fn end(self, status: StatusCode) {
+        let Some(counters) = self.counters else {
+            return;
+        };
+        if status.is_client_error() {
+            counters.client_errors.fetch_add(1, Ordering::SeqCst);
+        } else if status.is_server_error() {
+            counters.server_errors.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for RouteRequest<'_> {
+    fn drop(&mut self) {
+        if let Some(counters) = self.counters {
+            counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
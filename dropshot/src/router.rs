@@ -6,12 +6,16 @@ use super::handler::RouteHandler;
 
 use crate::from_map::MapError;
 use crate::from_map::MapValue;
+use crate::pagination::PageLimits;
+use crate::security::SecurityRequirement;
 use crate::server::ServerContext;
 use crate::ApiEndpoint;
 use crate::ApiEndpointBodyContentType;
+use crate::ApiEndpointVersions;
 use http::Method;
 use http::StatusCode;
 use percent_encoding::percent_decode_str;
+use semver::Version;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::sync::Arc;
@@ -51,7 +55,9 @@ use std::sync::Arc;
 /// * A given path cannot use the same variable name twice.  For example, you
 ///   can't register path `"/projects/{id}/instances/{id}"`.
 ///
-/// * A given resource may have at most one handler for a given HTTP method.
+/// * A given resource may have at most one handler for a given HTTP method in
+///   any version of the API.  (Handlers for the same method may be registered
+///   for different versions; see [`crate::ApiEndpointVersions`].)
 ///
 /// * The expectation is that during server initialization,
 ///   `HttpRouter::insert()` will be invoked to register a number of route
@@ -75,8 +81,10 @@ pub struct HttpRouter<Context: ServerContext> {
 /// of outgoing edges a node will have when we create it.
 #[derive(Debug)]
 struct HttpRouterNode<Context: ServerContext> {
-    /// Handlers, etc. for each of the HTTP methods defined for this node.
-    methods: BTreeMap<String, ApiEndpoint<Context>>,
+    /// Handlers, etc. for each of the HTTP methods defined for this node, in
+    /// the order they were inserted.  (There's more than one for a method only
+    /// if they're for different versions of the API.)
+    methods: BTreeMap<String, Vec<ApiEndpoint<Context>>>,
     /// Edges linking to child nodes.
     literal_edges: Option<BTreeMap<String, Box<HttpRouterNode<Context>>>>,
     variable_edge: Option<(String, Box<HttpRouterNode<Context>>)>,
@@ -173,16 +181,16 @@ impl MapValue for VariableValue {
     fn as_value(&self) -> Result<&str, MapError> {
         match self {
             VariableValue::String(s) => Ok(s.as_str()),
-            VariableValue::Components(_) => Err(MapError(
-                "cannot deserialize sequence as a single value".to_string(),
+            VariableValue::Components(_) => Err(MapError::new(
+                "cannot deserialize sequence as a single value",
             )),
         }
     }
 
     fn as_seq(&self) -> Result<Box<dyn Iterator<Item = String>>, MapError> {
         match self {
-            VariableValue::String(_) => Err(MapError(
-                "cannot deserialize a single value as a sequence".to_string(),
+            VariableValue::String(_) => Err(MapError::new(
+                "cannot deserialize a single value as a sequence",
             )),
             VariableValue::Components(v) => Ok(Box::new(v.clone().into_iter())),
         }
@@ -197,8 +205,21 @@ impl MapValue for VariableValue {
 #[derive(Debug)]
 pub struct RouterLookupResult<Context: ServerContext> {
     pub handler: Arc<dyn RouteHandler<Context>>,
+    pub operation_id: String,
+    /// Path template of the endpoint
+    pub path: String,
+    pub security: Vec<SecurityRequirement>,
     pub variables: VariableSet,
     pub body_content_type: ApiEndpointBodyContentType,
+    pub request_body_max_bytes: Option<usize>,
+    /// Whether the handler reads the request body as it arrives
+    pub request_body_streamed: bool,
+    /// Limits on the page size for the endpoint
+    pub page_limits: PageLimits,
+    /// Whether the endpoint is deprecated in the requested version
+    pub deprecated: bool,
+    /// Versions of the API in which the endpoint appears
+    pub versions: ApiEndpointVersions,
 }
 
 impl<Context: ServerContext> HttpRouterNode<Context> {
@@ -218,6 +239,28 @@ impl<Context: ServerContext> HttpRouter<Context> {
         HttpRouter { root: Box::new(HttpRouterNode::new()) }
     }
 
+    /// Consumes the router, returning each of its endpoints (in no particular
+    /// order).
+    pub(crate) fn into_endpoints(self) -> Vec<ApiEndpoint<Context>> {
+        let mut endpoints = Vec::new();
+        let mut nodes = vec![self.root];
+        while let Some(node) = nodes.pop() {
+            let HttpRouterNode {
+                methods,
+                literal_edges,
+                variable_edge,
+                rest_edge,
+            } = *node;
+            endpoints.extend(methods.into_values().flatten());
+            nodes.extend(
+                literal_edges.into_iter().flat_map(|e| e.into_values()),
+            );
+            nodes.extend(variable_edge.map(|(_, node)| node));
+            nodes.extend(rest_edge.map(|(_, node)| node));
+        }
+        endpoints
+    }
+
     /// Configure a route for HTTP requests based on the HTTP `method` and
     /// URI `path`.  See the `HttpRouter` docs for information about how `path`
     /// is processed.  Requests matching `path` will be resolved to `handler`.
@@ -310,7 +353,11 @@ impl<Context: ServerContext> HttpRouter<Context> {
         }
 
         let methodname = method.as_str().to_uppercase();
-        if node.methods.get(&methodname).is_some() {
+        let endpoints = node.methods.entry(methodname).or_default();
+        if endpoints
+            .iter()
+            .any(|e| e.versions.overlaps_with(&endpoint.versions))
+        {
             panic!(
                 "URI path \"{}\": attempted to create duplicate route for \
                  method \"{}\"",
@@ -318,7 +365,7 @@ impl<Context: ServerContext> HttpRouter<Context> {
             );
         }
 
-        node.methods.insert(methodname, endpoint);
+        endpoints.push(endpoint);
     }
 
     /// Look up the route handler for an HTTP request having method `method` and
@@ -335,10 +382,14 @@ impl<Context: ServerContext> HttpRouter<Context> {
     /// path structures, for example: `/foo/bar` and `/{id}/bar/hello` the path "/foo/bar/hello"
     /// will result in a 404, since the lookup logic will follow /foo/bar tree without consideration
     /// for the latter.
+    ///
+    /// If `version` is given, only routes in that version of the API are
+    /// considered.  Otherwise, versions are ignored.
     pub fn lookup_route(
         &self,
         method: &Method,
         path: InputPath<'_>,
+        version: Option<&Version>,
     ) -> Result<RouterLookupResult<Context>, HttpError> {
         let all_segments = input_path_to_segments(&path).map_err(|_| {
             HttpError::for_bad_request(
@@ -407,8 +458,12 @@ impl<Context: ServerContext> HttpRouter<Context> {
         }
 
         // As a somewhat special case, if one requests a node with no handlers
-        // at all, report a 404.  We could probably treat this as a 405 as well.
-        if node.methods.is_empty() {
+        // at all (in the requested version), report a 404.  We could probably
+        // treat this as a 405 as well.
+        let in_version = |endpoint: &&ApiEndpoint<Context>| {
+            version.map_or(true, |version| endpoint.versions.matches(version))
+        };
+        if !node.methods.values().flatten().any(|e| in_version(&e)) {
             return Err(HttpError::for_not_found(
                 None,
                 String::from("route has no handlers"),
@@ -418,10 +473,20 @@ impl<Context: ServerContext> HttpRouter<Context> {
         let methodname = method.as_str().to_uppercase();
         node.methods
             .get(&methodname)
+            .and_then(|endpoints| endpoints.iter().find(in_version))
             .map(|handler| RouterLookupResult {
                 handler: Arc::clone(&handler.handler),
+                operation_id: handler.operation_id.clone(),
+                path: handler.path.clone(),
+                security: handler.security.clone(),
                 variables,
                 body_content_type: handler.body_content_type.clone(),
+                request_body_max_bytes: handler.request_body_max_bytes,
+                request_body_streamed: handler.request_body_streamed,
+                page_limits: handler.page_limits,
+                deprecated: version
+                    .is_some_and(|version| handler.is_deprecated_in(version)),
+                versions: handler.versions.clone(),
             })
             .ok_or_else(|| {
                 HttpError::for_status(None, StatusCode::METHOD_NOT_ALLOWED)
@@ -474,7 +539,7 @@ type PathIter<'a, Context> =
 impl<'a, Context: ServerContext> HttpRouterIter<'a, Context> {
     fn new(router: &'a HttpRouter<Context>) -> Self {
         HttpRouterIter {
-            method: Box::new(router.root.methods.iter()),
+            method: HttpRouterIter::iter_methods(&router.root),
             path: vec![(
                 PathSegment::Literal("".to_string()),
                 HttpRouterIter::iter_node(&router.root),
@@ -482,6 +547,17 @@ impl<'a, Context: ServerContext> HttpRouterIter<'a, Context> {
         }
     }
 
+    /// Produce an iterator over the endpoints of `node`, along with their
+    /// methods.
+    fn iter_methods(
+        node: &'a HttpRouterNode<Context>,
+    ) -> Box<dyn Iterator<Item = (&'a String, &'a ApiEndpoint<Context>)> + 'a>
+    {
+        Box::new(node.methods.iter().flat_map(|(method, endpoints)| {
+            endpoints.iter().map(move |endpoint| (method, endpoint))
+        }))
+    }
+
     /// Produce an iterator over `node`'s children. This is the null (empty)
     /// iterator if there are no children, a single (once) iterator for a
     /// path parameter variable, and a modified iterator in the case of
@@ -567,7 +643,8 @@ impl<'a, Context: ServerContext> Iterator for HttpRouterIter<'a, Context> {
                                     path_component,
                                     HttpRouterIter::iter_node(node),
                                 ));
-                                self.method = Box::new(node.methods.iter());
+                                self.method =
+                                    HttpRouterIter::iter_methods(node);
                             }
                         },
                     }
@@ -678,10 +755,12 @@ mod test {
     use super::HttpRouter;
     use super::PathSegment;
     use crate::api_description::ApiEndpointBodyContentType;
+    use crate::api_description::ApiEndpointVisibility;
     use crate::from_map::from_map;
     use crate::router::VariableValue;
     use crate::ApiEndpoint;
     use crate::ApiEndpointResponse;
+    use crate::ApiEndpointVersions;
     use http::Method;
     use http::StatusCode;
     use hyper::Body;
@@ -721,8 +800,19 @@ mod test {
             description: None,
             tags: vec![],
             extension_mode: Default::default(),
-            visible: true,
+            visibility: ApiEndpointVisibility::Public,
+            versions: ApiEndpointVersions::All,
             deprecated: false,
+            deprecated_versions: vec![],
+            request_body_max_bytes: None,
+            page_limits: Default::default(),
+            security: vec![],
+            extensions: Default::default(),
+            callbacks: vec![],
+            external_docs: None,
+            frozen: None,
+            response_has_body: true,
+            request_body_streamed: false,
         }
     }
 
@@ -787,6 +877,61 @@ mod test {
         router.insert(new_endpoint(new_handler(), Method::GET, "//"));
     }
 
+    #[test]
+    #[should_panic(expected = "URI path \"/boo\": attempted to create \
+                               duplicate route for method \"GET\"")]
+    fn test_duplicate_route_versions() {
+        let v = |major| semver::Version::new(major, 0, 0);
+        let mut router = HttpRouter::new();
+        let mut endpoint = new_endpoint(new_handler(), Method::GET, "/boo");
+        endpoint.versions = ApiEndpointVersions::from_until(v(1), v(3));
+        router.insert(endpoint);
+        let mut endpoint = new_endpoint(new_handler(), Method::GET, "/boo");
+        endpoint.versions = ApiEndpointVersions::From(v(2));
+        router.insert(endpoint);
+    }
+
+    #[test]
+    fn test_versions() {
+        let v = |major| semver::Version::new(major, 0, 0);
+        let mut router = HttpRouter::new();
+        let mut endpoint =
+            new_endpoint(new_handler_named("h1"), Method::GET, "/boo");
+        endpoint.versions = ApiEndpointVersions::Until(v(2));
+        router.insert(endpoint);
+        let mut endpoint =
+            new_endpoint(new_handler_named("h2"), Method::GET, "/boo");
+        endpoint.versions = ApiEndpointVersions::from_until(v(2), v(3));
+        router.insert(endpoint);
+        let mut endpoint =
+            new_endpoint(new_handler_named("h3"), Method::PUT, "/boo");
+        endpoint.versions = ApiEndpointVersions::From(v(2));
+        router.insert(endpoint);
+
+        let lookup = |method: Method, version: Option<u64>| {
+            router.lookup_route(&method, "/boo".into(), version.map(v).as_ref())
+        };
+        assert_eq!(lookup(Method::GET, Some(1)).unwrap().handler.label(), "h1");
+        assert_eq!(lookup(Method::GET, Some(2)).unwrap().handler.label(), "h2");
+        assert_eq!(lookup(Method::PUT, Some(3)).unwrap().handler.label(), "h3");
+        // Without a version, the first endpoint for the method is used.
+        assert_eq!(lookup(Method::GET, None).unwrap().handler.label(), "h1");
+
+        let error = lookup(Method::PUT, Some(1)).unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        let error = lookup(Method::GET, Some(3)).unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+
+        // Routes with no handlers in the requested version aren't found.
+        let mut endpoint = new_endpoint(new_handler(), Method::GET, "/new");
+        endpoint.versions = ApiEndpointVersions::From(v(2));
+        router.insert(endpoint);
+        let error = router
+            .lookup_route(&Method::GET, "/new".into(), Some(&v(1)))
+            .unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+    }
+
     #[test]
     #[should_panic(expected = "URI path \"/projects/{id}/insts/{id}\": \
                                variable name \"id\" is used more than once")]
@@ -847,7 +992,7 @@ mod test {
             "/projects/default",
         ));
         let result = router
-            .lookup_route(&Method::GET, "/projects/default".into())
+            .lookup_route(&Method::GET, "/projects/default".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "route_two");
     }
@@ -865,8 +1010,9 @@ mod test {
             Method::GET,
             "/projects/default",
         ));
-        let result =
-            router.lookup_route(&Method::GET, "/projects/lol".into()).unwrap();
+        let result = router
+            .lookup_route(&Method::GET, "/projects/lol".into(), None)
+            .unwrap();
         assert_eq!(result.handler.label(), "route_one");
     }
 
@@ -888,23 +1034,25 @@ mod test {
             Method::GET,
             "/{path:.*}",
         ));
-        let result =
-            router.lookup_route(&Method::GET, "/projects/lol".into()).unwrap();
+        let result = router
+            .lookup_route(&Method::GET, "/projects/lol".into(), None)
+            .unwrap();
         assert_eq!(result.handler.label(), "route_one");
         let result = router
-            .lookup_route(&Method::GET, "/projects/default".into())
+            .lookup_route(&Method::GET, "/projects/default".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "route_two");
         let result =
-            router.lookup_route(&Method::GET, "/lolwut".into()).unwrap();
+            router.lookup_route(&Method::GET, "/lolwut".into(), None).unwrap();
         assert_eq!(result.handler.label(), "route_three");
 
-        let result =
-            router.lookup_route(&Method::GET, "/lolwut/test".into()).unwrap();
+        let result = router
+            .lookup_route(&Method::GET, "/lolwut/test".into(), None)
+            .unwrap();
         assert_eq!(result.handler.label(), "route_three");
 
         let result =
-            router.lookup_route(&Method::GET, "/lolwut".into()).unwrap();
+            router.lookup_route(&Method::GET, "/lolwut".into(), None).unwrap();
         assert_eq!(result.handler.label(), "route_three");
     }
 
@@ -929,18 +1077,18 @@ mod test {
         ));
         // Access to the more specific route works
         let result = router
-            .lookup_route(&Method::GET, "/projects/default".into())
+            .lookup_route(&Method::GET, "/projects/default".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "route_one");
 
         // Access to /projects/ starts down the /projects path and therefore doesnt' match
         assert!(router
-            .lookup_route(&Method::GET, "/projects/default/lol".into())
+            .lookup_route(&Method::GET, "/projects/default/lol".into(), None)
             .is_err());
 
         // Access to the less specific path as long as it's not /projects works.
         let result = router
-            .lookup_route(&Method::GET, "/some_id/default/lol".into())
+            .lookup_route(&Method::GET, "/some_id/default/lol".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "route_two");
     }
@@ -1017,54 +1165,68 @@ mod test {
         let mut router = HttpRouter::new();
 
         // Check a few initial conditions.
-        let error = router.lookup_route(&Method::GET, "/".into()).unwrap_err();
-        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error =
-            router.lookup_route(&Method::GET, "////".into()).unwrap_err();
+            router.lookup_route(&Method::GET, "/".into(), None).unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error =
-            router.lookup_route(&Method::GET, "/foo/bar".into()).unwrap_err();
+            router.lookup_route(&Method::GET, "////".into(), None).unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+        let error = router
+            .lookup_route(&Method::GET, "/foo/bar".into(), None)
+            .unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error = router
-            .lookup_route(&Method::GET, "//foo///bar".into())
+            .lookup_route(&Method::GET, "//foo///bar".into(), None)
             .unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
 
         // Insert a route into the middle of the tree.  This will let us look at
         // parent nodes, sibling nodes, and child nodes.
         router.insert(new_endpoint(new_handler(), Method::GET, "/foo/bar"));
-        assert!(router.lookup_route(&Method::GET, "/foo/bar".into()).is_ok());
-        assert!(router.lookup_route(&Method::GET, "/foo/bar/".into()).is_ok());
-        assert!(router.lookup_route(&Method::GET, "//foo/bar".into()).is_ok());
-        assert!(router.lookup_route(&Method::GET, "//foo//bar".into()).is_ok());
         assert!(router
-            .lookup_route(&Method::GET, "//foo//bar//".into())
+            .lookup_route(&Method::GET, "/foo/bar".into(), None)
+            .is_ok());
+        assert!(router
+            .lookup_route(&Method::GET, "/foo/bar/".into(), None)
+            .is_ok());
+        assert!(router
+            .lookup_route(&Method::GET, "//foo/bar".into(), None)
+            .is_ok());
+        assert!(router
+            .lookup_route(&Method::GET, "//foo//bar".into(), None)
+            .is_ok());
+        assert!(router
+            .lookup_route(&Method::GET, "//foo//bar//".into(), None)
             .is_ok());
         assert!(router
-            .lookup_route(&Method::GET, "///foo///bar///".into())
+            .lookup_route(&Method::GET, "///foo///bar///".into(), None)
             .is_ok());
 
         // TODO-cleanup: consider having a "build" step that constructs a
         // read-only router and does validation like making sure that there's a
         // GET route on all nodes?
-        let error = router.lookup_route(&Method::GET, "/".into()).unwrap_err();
-        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error =
-            router.lookup_route(&Method::GET, "/foo".into()).unwrap_err();
+            router.lookup_route(&Method::GET, "/".into(), None).unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error =
-            router.lookup_route(&Method::GET, "//foo".into()).unwrap_err();
+            router.lookup_route(&Method::GET, "/foo".into(), None).unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
         let error = router
-            .lookup_route(&Method::GET, "/foo/bar/baz".into())
+            .lookup_route(&Method::GET, "//foo".into(), None)
+            .unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+        let error = router
+            .lookup_route(&Method::GET, "/foo/bar/baz".into(), None)
             .unwrap_err();
         assert_eq!(error.status_code, StatusCode::NOT_FOUND);
 
-        let error =
-            router.lookup_route(&Method::PUT, "/foo/bar".into()).unwrap_err();
+        let error = router
+            .lookup_route(&Method::PUT, "/foo/bar".into(), None)
+            .unwrap_err();
         assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
-        let error =
-            router.lookup_route(&Method::PUT, "/foo/bar/".into()).unwrap_err();
+        let error = router
+            .lookup_route(&Method::PUT, "/foo/bar/".into(), None)
+            .unwrap_err();
         assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
     }
 
@@ -1076,15 +1238,18 @@ mod test {
         // back, even if we use different names that normalize to "/".
         // Before we start, sanity-check that there's nothing at the root
         // already.  Other test cases examine the errors in more detail.
-        assert!(router.lookup_route(&Method::GET, "/".into()).is_err());
+        assert!(router.lookup_route(&Method::GET, "/".into(), None).is_err());
         router.insert(new_endpoint(new_handler_named("h1"), Method::GET, "/"));
-        let result = router.lookup_route(&Method::GET, "/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h1");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "//".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "//".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h1");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "///".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "///".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h1");
         assert!(result.variables.is_empty());
 
@@ -1092,49 +1257,67 @@ mod test {
         // we get both this handler and the previous one if we ask for the
         // corresponding method and that we get no handler for a different,
         // third method.
-        assert!(router.lookup_route(&Method::PUT, "/".into()).is_err());
+        assert!(router.lookup_route(&Method::PUT, "/".into(), None).is_err());
         router.insert(new_endpoint(new_handler_named("h2"), Method::PUT, "/"));
-        let result = router.lookup_route(&Method::PUT, "/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::PUT, "/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h2");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h1");
-        assert!(router.lookup_route(&Method::DELETE, "/".into()).is_err());
+        assert!(router
+            .lookup_route(&Method::DELETE, "/".into(), None)
+            .is_err());
         assert!(result.variables.is_empty());
 
         // Now insert a handler one level deeper.  Verify that all the previous
         // handlers behave as we expect, and that we have one handler at the new
         // path, whichever name we use for it.
-        assert!(router.lookup_route(&Method::GET, "/foo".into()).is_err());
+        assert!(router
+            .lookup_route(&Method::GET, "/foo".into(), None)
+            .is_err());
         router.insert(new_endpoint(
             new_handler_named("h3"),
             Method::GET,
             "/foo",
         ));
-        let result = router.lookup_route(&Method::PUT, "/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::PUT, "/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h2");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h1");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "/foo".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "/foo".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h3");
         assert!(result.variables.is_empty());
-        let result = router.lookup_route(&Method::GET, "/foo/".into()).unwrap();
+        let result =
+            router.lookup_route(&Method::GET, "/foo/".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h3");
         assert!(result.variables.is_empty());
         let result =
-            router.lookup_route(&Method::GET, "//foo//".into()).unwrap();
+            router.lookup_route(&Method::GET, "//foo//".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h3");
         assert!(result.variables.is_empty());
         let result =
-            router.lookup_route(&Method::GET, "/foo//".into()).unwrap();
+            router.lookup_route(&Method::GET, "/foo//".into(), None).unwrap();
         assert_eq!(result.handler.label(), "h3");
         assert!(result.variables.is_empty());
-        assert!(router.lookup_route(&Method::PUT, "/foo".into()).is_err());
-        assert!(router.lookup_route(&Method::PUT, "/foo/".into()).is_err());
-        assert!(router.lookup_route(&Method::PUT, "//foo//".into()).is_err());
-        assert!(router.lookup_route(&Method::PUT, "/foo//".into()).is_err());
+        assert!(router
+            .lookup_route(&Method::PUT, "/foo".into(), None)
+            .is_err());
+        assert!(router
+            .lookup_route(&Method::PUT, "/foo/".into(), None)
+            .is_err());
+        assert!(router
+            .lookup_route(&Method::PUT, "//foo//".into(), None)
+            .is_err());
+        assert!(router
+            .lookup_route(&Method::PUT, "/foo//".into(), None)
+            .is_err());
     }
 
     #[test]
@@ -1143,7 +1326,7 @@ mod test {
         // change the behavior, intentionally or otherwise.
         let mut router = HttpRouter::new();
         assert!(router
-            .lookup_route(&Method::GET, "/not{a}variable".into())
+            .lookup_route(&Method::GET, "/not{a}variable".into(), None)
             .is_err());
         router.insert(new_endpoint(
             new_handler_named("h4"),
@@ -1151,15 +1334,15 @@ mod test {
             "/not{a}variable",
         ));
         let result = router
-            .lookup_route(&Method::GET, "/not{a}variable".into())
+            .lookup_route(&Method::GET, "/not{a}variable".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h4");
         assert!(result.variables.is_empty());
         assert!(router
-            .lookup_route(&Method::GET, "/not{b}variable".into())
+            .lookup_route(&Method::GET, "/not{b}variable".into(), None)
             .is_err());
         assert!(router
-            .lookup_route(&Method::GET, "/notnotavariable".into())
+            .lookup_route(&Method::GET, "/notnotavariable".into(), None)
             .is_err());
     }
 
@@ -1172,12 +1355,14 @@ mod test {
             Method::GET,
             "/projects/{project_id}",
         ));
-        assert!(router.lookup_route(&Method::GET, "/projects".into()).is_err());
         assert!(router
-            .lookup_route(&Method::GET, "/projects/".into())
+            .lookup_route(&Method::GET, "/projects".into(), None)
+            .is_err());
+        assert!(router
+            .lookup_route(&Method::GET, "/projects/".into(), None)
             .is_err());
         let result = router
-            .lookup_route(&Method::GET, "/projects/p12345".into())
+            .lookup_route(&Method::GET, "/projects/p12345".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h5");
         assert_eq!(
@@ -1189,10 +1374,10 @@ mod test {
             VariableValue::String("p12345".to_string())
         );
         assert!(router
-            .lookup_route(&Method::GET, "/projects/p12345/child".into())
+            .lookup_route(&Method::GET, "/projects/p12345/child".into(), None)
             .is_err());
         let result = router
-            .lookup_route(&Method::GET, "/projects/p12345/".into())
+            .lookup_route(&Method::GET, "/projects/p12345/".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h5");
         assert_eq!(
@@ -1200,7 +1385,7 @@ mod test {
             VariableValue::String("p12345".to_string())
         );
         let result = router
-            .lookup_route(&Method::GET, "/projects///p12345//".into())
+            .lookup_route(&Method::GET, "/projects///p12345//".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h5");
         assert_eq!(
@@ -1209,7 +1394,7 @@ mod test {
         );
         // Trick question!
         let result = router
-            .lookup_route(&Method::GET, "/projects/{project_id}".into())
+            .lookup_route(&Method::GET, "/projects/{project_id}".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h5");
         assert_eq!(
@@ -1232,6 +1417,7 @@ mod test {
             .lookup_route(
                 &Method::GET,
                 "/projects/p1/instances/i2/fwrules/fw3/info".into(),
+                None,
             )
             .unwrap();
         assert_eq!(result.handler.label(), "h6");
@@ -1264,16 +1450,16 @@ mod test {
             "/projects/{project_id}/instances",
         ));
         assert!(router
-            .lookup_route(&Method::GET, "/projects/instances".into())
+            .lookup_route(&Method::GET, "/projects/instances".into(), None)
             .is_err());
         assert!(router
-            .lookup_route(&Method::GET, "/projects//instances".into())
+            .lookup_route(&Method::GET, "/projects//instances".into(), None)
             .is_err());
         assert!(router
-            .lookup_route(&Method::GET, "/projects///instances".into())
+            .lookup_route(&Method::GET, "/projects///instances".into(), None)
             .is_err());
         let result = router
-            .lookup_route(&Method::GET, "/projects/foo/instances".into())
+            .lookup_route(&Method::GET, "/projects/foo/instances".into(), None)
             .unwrap();
         assert_eq!(result.handler.label(), "h7");
     }
@@ -1288,7 +1474,11 @@ mod test {
         ));
 
         let result = router
-            .lookup_route(&Method::OPTIONS, "/console/missiles/launch".into())
+            .lookup_route(
+                &Method::OPTIONS,
+                "/console/missiles/launch".into(),
+                None,
+            )
             .unwrap();
 
         assert_eq!(
@@ -1321,7 +1511,11 @@ mod test {
         ));
 
         let result = router
-            .lookup_route(&Method::OPTIONS, "/console/missiles/launch".into())
+            .lookup_route(
+                &Method::OPTIONS,
+                "/console/missiles/launch".into(),
+                None,
+            )
             .unwrap();
 
         let path =
@@ -0,0 +1,332 @@
+// Copyright 2024 Oxide Computer Company
+//! Exporting the component schemas of an OpenAPI document on their own
+//!
+//! See [`crate::OpenApiDefinition::json_schemas`] and
+//! [`crate::OpenApiDefinition::write_typescript`].  Like the rest of the
+//! OpenAPI support, this works on the JSON form of the document, after schema
+//! visitors have been applied.  It understands the subset of OpenAPI schemas
+//! that schemars generates.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Prefix of references to component schemas within an OpenAPI document
+const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+/// JSON Schema dialect of the exported schemas
+const JSON_SCHEMA_DIALECT: &str = "http://json-schema.org/draft-07/schema#";
+
+/// Returns the component schemas of `openapi`, by name
+fn component_schemas(openapi: &Value) -> BTreeMap<String, Value> {
+    openapi["components"]["schemas"]
+        .as_object()
+        .map(|schemas| {
+            schemas
+                .iter()
+                .map(|(name, schema)| (name.clone(), schema.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns each component schema of `openapi` as a standalone JSON Schema,
+/// by file name ("<schema name>.json")
+///
+/// References to other component schemas become relative references to their
+/// files.  OpenAPI's `nullable` and `example` keywords are replaced with their
+/// JSON Schema equivalents.
+pub(crate) fn json_schemas(openapi: &Value) -> BTreeMap<String, Value> {
+    component_schemas(openapi)
+        .into_iter()
+        .map(|(name, mut schema)| {
+            to_json_schema(&mut schema);
+            if let Value::Object(object) = &mut schema {
+                object.insert(
+                    String::from("$schema"),
+                    Value::from(JSON_SCHEMA_DIALECT),
+                );
+                object.insert(
+                    String::from("$id"),
+                    Value::from(format!("{}.json", name)),
+                );
+                object
+                    .entry("title")
+                    .or_insert_with(|| Value::from(name.clone()));
+            }
+            (format!("{}.json", name), schema)
+        })
+        .collect()
+}
+
+/// Converts the OpenAPI schema `schema` (and those nested within it) to JSON
+/// Schema
+fn to_json_schema(schema: &mut Value) {
+    let Value::Object(object) = schema else {
+        return;
+    };
+
+    if let Some(Value::String(reference)) = object.get_mut("$ref") {
+        if let Some(name) = reference.strip_prefix(SCHEMA_REF_PREFIX) {
+            *reference = format!("{}.json", name);
+        }
+    }
+    if let Some(Value::Object(properties)) = object.get_mut("properties") {
+        properties.values_mut().for_each(to_json_schema);
+    }
+    for keyword in ["items", "additionalProperties", "not"] {
+        if let Some(subschema) = object.get_mut(keyword) {
+            to_json_schema(subschema);
+        }
+    }
+    for keyword in ["allOf", "anyOf", "oneOf"] {
+        if let Some(Value::Array(subschemas)) = object.get_mut(keyword) {
+            subschemas.iter_mut().for_each(to_json_schema);
+        }
+    }
+    if let Some(example) = object.remove("example") {
+        object.insert(String::from("examples"), Value::Array(vec![example]));
+    }
+
+    if object.remove("nullable") != Some(Value::Bool(true)) {
+        return;
+    }
+    match object.get_mut("type") {
+        Some(Value::String(ty)) => {
+            let ty = Value::from(ty.as_str());
+            object.insert(
+                String::from("type"),
+                Value::Array(vec![ty, Value::from("null")]),
+            );
+            if let Some(Value::Array(values)) = object.get_mut("enum") {
+                if !values.contains(&Value::Null) {
+                    values.push(Value::Null);
+                }
+            }
+        }
+        _ => {
+            // Other than a type, the schema (e.g., a reference, or a
+            // combination of schemas) must be wrapped to allow null.
+            let description = object.remove("description");
+            let inner = Value::Object(std::mem::take(object));
+            object.insert(
+                String::from("anyOf"),
+                Value::Array(vec![inner, serde_json::json!({"type": "null"})]),
+            );
+            if let Some(description) = description {
+                object.insert(String::from("description"), description);
+            }
+        }
+    }
+}
+
+/// Returns TypeScript declarations (for a ".d.ts" file) of the component
+/// schemas of `openapi`
+pub(crate) fn typescript(openapi: &Value) -> String {
+    let mut out = String::new();
+    let title = openapi["info"]["title"].as_str().unwrap_or_default();
+    let version = openapi["info"]["version"].as_str().unwrap_or_default();
+    writeln!(out, "// Types for {} (version {})", title, version).unwrap();
+    writeln!(
+        out,
+        "// This file is generated from the API's OpenAPI definition."
+    )
+    .unwrap();
+
+    for (name, schema) in component_schemas(openapi) {
+        writeln!(out).unwrap();
+        write_doc_comment(&mut out, "", &schema);
+        if is_interface(&schema) {
+            writeln!(
+                out,
+                "export interface {} {}",
+                name,
+                object_type(&schema, "")
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "export type {} = {};", name, ts_type(&schema, ""))
+                .unwrap();
+        }
+    }
+    out
+}
+
+/// Returns whether `schema` can be declared as an interface, rather than as a
+/// type alias
+fn is_interface(schema: &Value) -> bool {
+    schema["type"] == "object"
+        && schema.get("properties").is_some()
+        && !is_nullable(schema)
+        && ["allOf", "anyOf", "oneOf", "enum"]
+            .iter()
+            .all(|keyword| schema.get(keyword).is_none())
+}
+
+fn is_nullable(schema: &Value) -> bool {
+    schema["nullable"] == true
+}
+
+/// Writes the description of `schema` (if any) as a doc comment at `indent`
+fn write_doc_comment(out: &mut String, indent: &str, schema: &Value) {
+    let Some(description) = schema["description"].as_str() else {
+        return;
+    };
+    writeln!(out, "{}/**", indent).unwrap();
+    for line in description.lines() {
+        // A "*/" in the description would end the comment early.
+        let line = line.replace("*/", "*\\/");
+        if line.is_empty() {
+            writeln!(out, "{} *", indent).unwrap();
+        } else {
+            writeln!(out, "{} * {}", indent, line).unwrap();
+        }
+    }
+    writeln!(out, "{} */", indent).unwrap();
+}
+
+/// Returns the TypeScript type for `schema`, which may be written across
+/// several lines at `indent`
+fn ts_type(schema: &Value, indent: &str) -> String {
+    let ty = ts_type_not_null(schema, indent);
+    if is_nullable(schema) && ty != "null" && ty != "unknown" {
+        format!("{} | null", parenthesize(ty))
+    } else {
+        ty
+    }
+}
+
+fn ts_type_not_null(schema: &Value, indent: &str) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return reference
+            .strip_prefix(SCHEMA_REF_PREFIX)
+            .unwrap_or("unknown")
+            .to_string();
+    }
+    for (keyword, separator) in
+        [("allOf", " & "), ("oneOf", " | "), ("anyOf", " | ")]
+    {
+        if let Some(subschemas) = schema[keyword].as_array() {
+            let types = subschemas
+                .iter()
+                .map(|subschema| ts_type(subschema, indent))
+                .collect::<Vec<_>>();
+            return if types.len() == 1 {
+                types.into_iter().next().unwrap()
+            } else {
+                types
+                    .into_iter()
+                    .map(parenthesize)
+                    .collect::<Vec<_>>()
+                    .join(separator)
+            };
+        }
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        // JSON literals are also TypeScript literal types.
+        return values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+
+    match schema["type"].as_str() {
+        Some("string") => String::from("string"),
+        Some("integer") | Some("number") => String::from("number"),
+        Some("boolean") => String::from("boolean"),
+        Some("null") => String::from("null"),
+        Some("array") => match schema.get("items") {
+            Some(items) => {
+                format!("{}[]", parenthesize(ts_type(items, indent)))
+            }
+            None => String::from("unknown[]"),
+        },
+        Some("object") => object_type(schema, indent),
+        _ => String::from("unknown"),
+    }
+}
+
+/// Returns the TypeScript type for the object schema `schema`, with its
+/// properties on separate lines below `indent`
+fn object_type(schema: &Value, indent: &str) -> String {
+    let properties = schema["properties"].as_object();
+    let additional = match schema.get("additionalProperties") {
+        Some(Value::Bool(false)) => None,
+        Some(Value::Bool(true)) => Some(String::from("unknown")),
+        Some(additional) => Some(ts_type(additional, indent)),
+        None if properties.is_none() => Some(String::from("unknown")),
+        None => None,
+    };
+    let Some(properties) = properties.filter(|p| !p.is_empty()) else {
+        return match additional {
+            Some(value) => format!("{{ [key: string]: {} }}", value),
+            None => String::from("{}"),
+        };
+    };
+
+    let inner = format!("{}    ", indent);
+    let required = schema["required"].as_array();
+    let mut out = String::from("{\n");
+    for (name, property) in properties {
+        let optional = match required {
+            Some(required) if required.iter().any(|r| r == name.as_str()) => "",
+            _ => "?",
+        };
+        write_doc_comment(&mut out, &inner, property);
+        writeln!(
+            out,
+            "{}{}{}: {};",
+            inner,
+            property_name(name),
+            optional,
+            ts_type(property, &inner)
+        )
+        .unwrap();
+    }
+    if let Some(value) = additional {
+        writeln!(out, "{}[key: string]: {};", inner, value).unwrap();
+    }
+    write!(out, "{}}}", indent).unwrap();
+    out
+}
+
+/// Returns `name` as a TypeScript property name, quoted if it isn't an
+/// identifier
+fn property_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_string()
+    } else {
+        Value::from(name).to_string()
+    }
+}
+
+/// Returns `ty` in parentheses if it's a union or intersection, so that it can
+/// be combined with other types
+fn parenthesize(ty: String) -> String {
+    // Only operators outside of any brackets (e.g., not those in the type of
+    // an object's property) matter.
+    let mut depth = 0;
+    let mut combined = false;
+    for (i, c) in ty.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '|' | '&' if depth == 0 && ty[..i].ends_with(' ') => {
+                combined = true;
+                break;
+            }
+            _ => (),
+        }
+    }
+    if combined {
+        format!("({})", ty)
+    } else {
+        ty
+    }
+}
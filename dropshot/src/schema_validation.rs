@@ -0,0 +1,507 @@
+// Copyright 2024 Oxide Computer Company
+//! Validating request and response bodies against the API's schemas
+//!
+//! The schemas in an API's OpenAPI definition are generated from the same
+//! types that handlers use, so bodies that handlers accept and produce
+//! generally match them.  They can still drift apart, as with hand-written
+//! `JsonSchema` implementations, `#[serde(...)]` attributes that schemars
+//! doesn't understand, or schema visitors (see
+//! [`crate::ApiDescription::schema_visitor`]).  When enabled with
+//! [`crate::ConfigDropshot::schema_validation`], the server checks JSON
+//! bodies against the schemas as requests are handled, which makes such
+//! drift easy to find in tests and development builds.  See
+//! [`ConfigSchemaValidation`].
+//!
+//! Like the rest of the OpenAPI support, this works on the JSON form of the
+//! definition, after schema visitors have been applied, and it understands
+//! the subset of OpenAPI schemas that schemars generates.  Keywords that it
+//! doesn't understand (e.g., `pattern` and `format`) are ignored.
+
+use crate::error::HttpError;
+use crate::StreamingBody;
+use http::header::CONTENT_TYPE;
+use hyper::body::Bytes;
+use hyper::{Body, Request, Response};
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use tracing::{error, warn};
+
+/// Prefix of references to component schemas within an OpenAPI document
+const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+/// Maximum number of mismatches reported for a single body
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+/// Whether (and how) the server validates JSON bodies against the schemas in
+/// the API's OpenAPI definition
+///
+/// Request bodies are validated before the handler runs, and successful
+/// responses are validated before they're sent.  Validation requires
+/// buffering bodies and walking their schemas, so it's meant for tests and
+/// development builds, not production servers.
+///
+/// In TOML, this looks like:
+///
+/// ```toml
+/// schema_validation = "enforce"
+/// ```
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigSchemaValidation {
+    /// Bodies aren't validated.
+    #[default]
+    Disabled,
+    /// Bodies that don't match their schemas are logged, but they're
+    /// otherwise handled as usual.
+    Log,
+    /// Requests whose bodies don't match their schemas fail with a 400 ("Bad
+    /// Request") response, without running the handler.  Responses whose
+    /// bodies don't match their schemas are logged and replaced with a 500
+    /// ("Internal Server Error") response.
+    Enforce,
+}
+
+/// The schemas of an operation's JSON bodies
+#[derive(Debug, Default)]
+struct OperationSchemas {
+    request: Option<Value>,
+    /// by status code (e.g., "200", "2XX", or "default")
+    responses: BTreeMap<String, Value>,
+}
+
+/// Validates the JSON bodies of requests and responses against the schemas
+/// of their operations
+#[derive(Debug)]
+pub(crate) struct SchemaValidator {
+    mode: ConfigSchemaValidation,
+    schemas: Map<String, Value>,
+    operations: HashMap<String, OperationSchemas>,
+}
+
+impl SchemaValidator {
+    /// Returns a validator for the operations in the OpenAPI document
+    /// `openapi`, which reports mismatches as `mode` says
+    pub(crate) fn new(
+        mode: ConfigSchemaValidation,
+        openapi: &Value,
+    ) -> SchemaValidator {
+        let schemas = openapi["components"]["schemas"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        let json_schema = |content: &Value| {
+            content["content"][crate::CONTENT_TYPE_JSON].get("schema").cloned()
+        };
+
+        let mut operations = HashMap::new();
+        let path_items = openapi["paths"].as_object().into_iter().flatten();
+        for (_, path_item) in path_items {
+            let items = path_item.as_object().into_iter().flatten();
+            for (_, operation) in items {
+                let Some(operation_id) = operation["operationId"].as_str()
+                else {
+                    continue;
+                };
+                let responses = operation["responses"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(status, response)| {
+                        Some((status.clone(), json_schema(response)?))
+                    })
+                    .collect();
+                operations.insert(
+                    operation_id.to_string(),
+                    OperationSchemas {
+                        request: json_schema(&operation["requestBody"]),
+                        responses,
+                    },
+                );
+            }
+        }
+
+        SchemaValidator { mode, schemas, operations }
+    }
+
+    /// Checks the body of `request`, which is for the operation
+    /// `operation_id`, returning the request to be handled
+    ///
+    /// Bodies that aren't JSON, or aren't even valid JSON, are left for the
+    /// handler's extractors to reject.  Bodies that are too large are
+    /// rejected here just as the extractors would reject them.
+    pub(crate) async fn check_request(
+        &self,
+        operation_id: &str,
+        request: Request<Body>,
+        request_body_max_bytes: usize,
+    ) -> Result<Request<Body>, HttpError> {
+        let Some(schema) = self
+            .operations
+            .get(operation_id)
+            .and_then(|operation| operation.request.as_ref())
+        else {
+            return Ok(request);
+        };
+        if !is_json(request.headers(), true) {
+            return Ok(request);
+        }
+
+        let (parts, body) = request.into_parts();
+        let body = StreamingBody::new(body, request_body_max_bytes)
+            .into_bytes()
+            .await?;
+        if let Ok(value) = serde_json::from_slice::<Value>(&body) {
+            let mismatches = self.mismatches(schema, &value);
+            if !mismatches.is_empty() {
+                warn!(
+                    operation_id,
+                    mismatches = mismatches.join("; "),
+                    "request body does not match its schema"
+                );
+                if self.mode == ConfigSchemaValidation::Enforce {
+                    return Err(HttpError::for_bad_request(
+                        None,
+                        format!(
+                            "request body does not match its schema: {}",
+                            mismatches.join("; ")
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(Request::from_parts(parts, Body::from(body)))
+    }
+
+    /// Checks the body of `response`, which was produced by the operation
+    /// `operation_id`, returning the response to be sent
+    ///
+    /// Only successful JSON responses whose whole bodies are available up
+    /// front are checked; streaming bodies are sent as they are.
+    pub(crate) async fn check_response(
+        &self,
+        operation_id: &str,
+        response: Response<Body>,
+    ) -> Result<Response<Body>, HttpError> {
+        let status = response.status();
+        let Some(schema) = self.operations.get(operation_id).and_then(|op| {
+            let status_class = format!("{}XX", status.as_u16() / 100);
+            let keys = [status.as_str(), status_class.as_str(), "default"];
+            keys.iter().find_map(|key| op.responses.get(*key))
+        }) else {
+            return Ok(response);
+        };
+        if !status.is_success()
+            || !is_json(response.headers(), false)
+            || hyper::body::HttpBody::size_hint(response.body())
+                .exact()
+                .is_none()
+        {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let body: Bytes = hyper::body::to_bytes(body).await.map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "reading response body: {}",
+                e
+            ))
+        })?;
+        let mismatches = match serde_json::from_slice::<Value>(&body) {
+            Ok(value) => self.mismatches(schema, &value),
+            Err(e) => vec![format!("invalid JSON: {}", e)],
+        };
+        if !mismatches.is_empty() {
+            if self.mode == ConfigSchemaValidation::Enforce {
+                error!(
+                    operation_id,
+                    mismatches = mismatches.join("; "),
+                    "response body does not match its schema"
+                );
+                return Err(HttpError::for_internal_error(format!(
+                    "response body of operation \"{}\" does not match its \
+                     schema: {}",
+                    operation_id,
+                    mismatches.join("; ")
+                )));
+            }
+            warn!(
+                operation_id,
+                mismatches = mismatches.join("; "),
+                "response body does not match its schema"
+            );
+        }
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    /// Returns descriptions of the ways in which `value` doesn't match
+    /// `schema` (up to a limit), or an empty list if it matches
+    fn mismatches(&self, schema: &Value, value: &Value) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        self.check(schema, value, "$", &mut mismatches);
+        mismatches.truncate(MAX_REPORTED_MISMATCHES);
+        mismatches
+    }
+
+    /// Returns whether `value` matches `schema`
+    fn matches(&self, schema: &Value, value: &Value) -> bool {
+        let mut mismatches = Vec::new();
+        self.check(schema, value, "$", &mut mismatches);
+        mismatches.is_empty()
+    }
+
+    /// Appends to `mismatches` the ways in which `value`, found at `path`
+    /// within the body, doesn't match `schema`
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        path: &str,
+        mismatches: &mut Vec<String>,
+    ) {
+        let Value::Object(schema) = schema else {
+            // Boolean schemas: `true` matches anything, `false` nothing.
+            if *schema == Value::Bool(false) {
+                mismatches.push(format!("{}: no value is allowed", path));
+            }
+            return;
+        };
+        let at = |message: String| format!("{}: {}", path, message);
+
+        if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true))
+        {
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference
+                .strip_prefix(SCHEMA_REF_PREFIX)
+                .and_then(|name| self.schemas.get(name))
+            {
+                Some(referenced) => {
+                    self.check(referenced, value, path, mismatches)
+                }
+                None => mismatches
+                    .push(at(format!("unknown schema {:?}", reference))),
+            }
+            return;
+        }
+
+        if let Some(subschemas) = schema.get("allOf").and_then(Value::as_array)
+        {
+            for subschema in subschemas {
+                self.check(subschema, value, path, mismatches);
+            }
+            return;
+        }
+        if let Some(subschemas) = schema.get("anyOf").and_then(Value::as_array)
+        {
+            if !subschemas.iter().any(|s| self.matches(s, value)) {
+                mismatches.push(at(String::from(
+                    "does not match any allowed schema",
+                )));
+            }
+            return;
+        }
+        if let Some(subschemas) = schema.get("oneOf").and_then(Value::as_array)
+        {
+            let nmatched =
+                subschemas.iter().filter(|s| self.matches(s, value)).count();
+            if nmatched != 1 {
+                mismatches.push(at(format!(
+                    "matches {} of the allowed schemas instead of exactly one",
+                    nmatched
+                )));
+            }
+            return;
+        }
+        if let Some(not) = schema.get("not") {
+            if self.matches(not, value) {
+                mismatches
+                    .push(at(String::from("matches a disallowed schema")));
+            }
+        }
+
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            if !values.contains(value) {
+                mismatches.push(at(format!(
+                    "{} is not one of the allowed values",
+                    value
+                )));
+                return;
+            }
+        }
+
+        let Some(ty) = schema.get("type").and_then(Value::as_str) else {
+            return;
+        };
+        let type_matches = match ty {
+            "string" => value.is_string(),
+            "integer" => {
+                value.is_i64()
+                    || value.is_u64()
+                    || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+            }
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !type_matches {
+            mismatches.push(at(format!(
+                "expected {}, found {}",
+                ty,
+                json_type(value)
+            )));
+            return;
+        }
+
+        match value {
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                let bound =
+                    |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+                let exclusive = |keyword: &str| {
+                    schema.get(keyword) == Some(&Value::Bool(true))
+                };
+                if let Some(min) = bound("minimum") {
+                    if n < min || (exclusive("exclusiveMinimum") && n == min) {
+                        mismatches
+                            .push(at(format!("{} is less than {}", n, min)));
+                    }
+                }
+                if let Some(max) = bound("maximum") {
+                    if n > max || (exclusive("exclusiveMaximum") && n == max) {
+                        mismatches
+                            .push(at(format!("{} is greater than {}", n, max)));
+                    }
+                }
+            }
+            Value::String(s) => {
+                let length = s.chars().count() as u64;
+                if let Some(min) =
+                    schema.get("minLength").and_then(Value::as_u64)
+                {
+                    if length < min {
+                        mismatches.push(at(format!(
+                            "shorter than {} characters",
+                            min
+                        )));
+                    }
+                }
+                if let Some(max) =
+                    schema.get("maxLength").and_then(Value::as_u64)
+                {
+                    if length > max {
+                        mismatches.push(at(format!(
+                            "longer than {} characters",
+                            max
+                        )));
+                    }
+                }
+            }
+            Value::Array(items) => {
+                let length = items.len() as u64;
+                if let Some(min) =
+                    schema.get("minItems").and_then(Value::as_u64)
+                {
+                    if length < min {
+                        mismatches
+                            .push(at(format!("fewer than {} items", min)));
+                    }
+                }
+                if let Some(max) =
+                    schema.get("maxItems").and_then(Value::as_u64)
+                {
+                    if length > max {
+                        mismatches.push(at(format!("more than {} items", max)));
+                    }
+                }
+                if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+                    let duplicated = items
+                        .iter()
+                        .enumerate()
+                        .any(|(i, item)| items[..i].contains(item));
+                    if duplicated {
+                        mismatches
+                            .push(at(String::from("items are not unique")));
+                    }
+                }
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        let item_path = format!("{}[{}]", path, i);
+                        self.check(item_schema, item, &item_path, mismatches);
+                    }
+                }
+            }
+            Value::Object(object) => {
+                let properties =
+                    schema.get("properties").and_then(Value::as_object);
+                let required = schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str);
+                for name in required {
+                    if !object.contains_key(name) {
+                        mismatches
+                            .push(at(format!("missing property {:?}", name)));
+                    }
+                }
+                for (name, property) in object {
+                    let property_path = format!("{}.{}", path, name);
+                    let property_schema = properties
+                        .and_then(|properties| properties.get(name))
+                        .or_else(|| schema.get("additionalProperties"));
+                    // Properties are allowed unless they're explicitly
+                    // disallowed.
+                    match property_schema {
+                        Some(Value::Bool(false)) => mismatches.push(format!(
+                            "{}: unexpected property",
+                            property_path
+                        )),
+                        Some(property_schema) => self.check(
+                            property_schema,
+                            property,
+                            &property_path,
+                            mismatches,
+                        ),
+                        None => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Returns whether `headers` describe a JSON body (which is the default for
+/// requests that don't say, if `default` is true)
+fn is_json(headers: &http::HeaderMap, default: bool) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE) else {
+        return default;
+    };
+    content_type.to_str().is_ok_and(|content_type| {
+        let mime_type = content_type.split(';').next().unwrap_or_default();
+        mime_type.trim().eq_ignore_ascii_case(crate::CONTENT_TYPE_JSON)
+    })
+}
+
+/// Returns the JSON type of `value`, for describing mismatches
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2024 Oxide Computer Company
+//! Describing how clients authenticate to an API
+//!
+//! An API declares the security schemes it supports with
+//! [`crate::ApiDescription::security_scheme`], and each endpoint lists the
+//! schemes that may be used to call it (see [`crate::ApiEndpoint::security`]).
+//! Both appear in the OpenAPI document.  Dropshot doesn't authenticate
+//! requests itself, but authentication middleware can find out what an
+//! endpoint expects with [`crate::DropshotState::security_requirements`].
+
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Security schemes that together permit calling an endpoint, by name, with
+/// the OAuth2 scopes required of each (which must be empty for other kinds of
+/// schemes)
+///
+/// An endpoint lists alternative requirements, any one of which is enough.
+pub type SecurityRequirement = BTreeMap<String, Vec<String>>;
+
+/// A way for clients to authenticate, as described in the OpenAPI document
+#[derive(Clone, Debug)]
+pub enum SecurityScheme {
+    /// HTTP bearer authentication, with a token in the `Authorization` header
+    Bearer {
+        /// hint as to how the token is formatted (e.g., "JWT")
+        bearer_format: Option<String>,
+        description: Option<String>,
+    },
+    /// An API key provided in a header, query parameter, or cookie
+    ApiKey {
+        /// name of the header, query parameter, or cookie
+        name: String,
+        location: ApiKeyLocation,
+        description: Option<String>,
+    },
+    /// OAuth2, with the given flows
+    OAuth2 { flows: Vec<OAuth2Flow>, description: Option<String> },
+}
+
+/// Where a client provides an API key
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ApiKeyLocation {
+    Header,
+    Query,
+    Cookie,
+}
+
+/// An OAuth2 flow, with the scopes it may grant (by name, with a description
+/// of each)
+#[derive(Clone, Debug)]
+pub enum OAuth2Flow {
+    Implicit {
+        authorization_url: String,
+        scopes: BTreeMap<String, String>,
+    },
+    Password {
+        token_url: String,
+        scopes: BTreeMap<String, String>,
+    },
+    ClientCredentials {
+        token_url: String,
+        scopes: BTreeMap<String, String>,
+    },
+    AuthorizationCode {
+        authorization_url: String,
+        token_url: String,
+        scopes: BTreeMap<String, String>,
+    },
+}
+
+impl OAuth2Flow {
+    /// Returns the scopes this flow may grant
+    pub fn scopes(&self) -> &BTreeMap<String, String> {
+        match self {
+            OAuth2Flow::Implicit { scopes, .. }
+            | OAuth2Flow::Password { scopes, .. }
+            | OAuth2Flow::ClientCredentials { scopes, .. }
+            | OAuth2Flow::AuthorizationCode { scopes, .. } => scopes,
+        }
+    }
+}
+
+impl SecurityScheme {
+    /// Returns a scheme for bearer tokens in the `Authorization` header
+    pub fn bearer() -> SecurityScheme {
+        SecurityScheme::Bearer { bearer_format: None, description: None }
+    }
+
+    /// Returns a scheme for API keys in the header, query parameter, or
+    /// cookie `name`
+    pub fn api_key<S: ToString>(
+        name: S,
+        location: ApiKeyLocation,
+    ) -> SecurityScheme {
+        SecurityScheme::ApiKey {
+            name: name.to_string(),
+            location,
+            description: None,
+        }
+    }
+
+    /// Returns a scheme for OAuth2 with the given flows
+    pub fn oauth2(flows: Vec<OAuth2Flow>) -> SecurityScheme {
+        SecurityScheme::OAuth2 { flows, description: None }
+    }
+
+    /// Sets the description of this scheme in the OpenAPI document
+    pub fn description<S: ToString>(mut self, text: S) -> SecurityScheme {
+        let (SecurityScheme::Bearer { description, .. }
+        | SecurityScheme::ApiKey { description, .. }
+        | SecurityScheme::OAuth2 { description, .. }) = &mut self;
+        *description = Some(text.to_string());
+        self
+    }
+
+    /// Returns whether `scope` may be required of this scheme
+    pub(crate) fn has_scope(&self, scope: &str) -> bool {
+        match self {
+            SecurityScheme::OAuth2 { flows, .. } => {
+                flows.iter().any(|flow| flow.scopes().contains_key(scope))
+            }
+            SecurityScheme::Bearer { .. } | SecurityScheme::ApiKey { .. } => {
+                false
+            }
+        }
+    }
+
+    /// Returns the OpenAPI Security Scheme Object describing this scheme
+    pub(crate) fn to_openapi(&self) -> openapiv3::SecurityScheme {
+        let value = match self {
+            SecurityScheme::Bearer { bearer_format, description } => json!({
+                "type": "http",
+                "scheme": "bearer",
+                "bearerFormat": bearer_format,
+                "description": description,
+            }),
+            SecurityScheme::ApiKey { name, location, description } => {
+                let location = match location {
+                    ApiKeyLocation::Header => "header",
+                    ApiKeyLocation::Query => "query",
+                    ApiKeyLocation::Cookie => "cookie",
+                };
+                json!({
+                    "type": "apiKey",
+                    "name": name,
+                    "in": location,
+                    "description": description,
+                })
+            }
+            SecurityScheme::OAuth2 { flows, description } => {
+                let flows = flows
+                    .iter()
+                    .map(|flow| match flow {
+                        OAuth2Flow::Implicit { authorization_url, scopes } => (
+                            "implicit",
+                            json!({
+                                "authorizationUrl": authorization_url,
+                                "scopes": scopes,
+                            }),
+                        ),
+                        OAuth2Flow::Password { token_url, scopes } => (
+                            "password",
+                            json!({ "tokenUrl": token_url, "scopes": scopes }),
+                        ),
+                        OAuth2Flow::ClientCredentials { token_url, scopes } => (
+                            "clientCredentials",
+                            json!({ "tokenUrl": token_url, "scopes": scopes }),
+                        ),
+                        OAuth2Flow::AuthorizationCode {
+                            authorization_url,
+                            token_url,
+                            scopes,
+                        } => (
+                            "authorizationCode",
+                            json!({
+                                "authorizationUrl": authorization_url,
+                                "tokenUrl": token_url,
+                                "scopes": scopes,
+                            }),
+                        ),
+                    })
+                    .map(|(name, flow)| (name.to_string(), flow))
+                    .collect::<serde_json::Map<_, _>>();
+                json!({
+                    "type": "oauth2",
+                    "flows": flows,
+                    "description": description,
+                })
+            }
+        };
+        // This is built to the OpenAPI specification, so there's nothing that
+        // the openapiv3 crate can fail to parse.
+        serde_json::from_value(value).unwrap()
+    }
+}
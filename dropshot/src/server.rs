@@ -2,33 +2,68 @@
 //! Generic server-wide state and facilities
 
 use super::api_description::ApiDescription;
-use super::config::{ConfigDropshot, ConfigTls};
+use super::clock::{http_date, Clock, SystemClock};
+use super::config::{
+    ConfigDropshot, ConfigListener, ConfigTcp, ConfigTls, ConfigWebsocket,
+};
+use super::connection::{
+    ConnectionAcceptor, ConnectionInfo, ConnectionState, MinRateBody,
+    ServerConnection,
+};
+use super::connection_filter::{
+    AdmittedConnection, ConnectionFilter, ConnectionFilterSlot,
+};
+use super::cors::{self, ConfigCors};
+use super::deadline::Deadline;
 #[cfg(feature = "usdt-probes")]
 use super::dtrace::probes;
+use super::error::ErrorMapper;
+use super::error::ErrorResponseFormat;
 use super::error::HttpError;
+use super::extractor::PathErrorHandler;
+use super::forwarded::{resolve_client_ip, IpCidr};
 use super::handler::RequestContext;
+#[cfg(feature = "http3")]
+use super::http3::{Http3Listener, Http3ServerStarter};
 use super::http_util::HEADER_REQUEST_ID;
-use super::router::HttpRouter;
+use super::pagination::PageTokenKeys;
+use super::prefer::Preferences;
+use super::proxy_protocol::{ProxiedStream, ProxyAcceptor, ProxyProtocol};
+use super::route_stats::{RouteStats, RouteStatsTable};
+use super::router::{HttpRouter, RouterLookupResult};
+use super::schema_validation::{ConfigSchemaValidation, SchemaValidator};
+use super::security::SecurityRequirement;
+use super::server_timing::{
+    ConfigServerTiming, ServerTiming, HEADER_SERVER_TIMING,
+};
+use super::service::{ApiService, MountTable, Route};
+use super::streamed_body;
+use super::tls_client_auth::{client_cert_verifier, ClientCertificate};
+use super::unix_socket::PeerCredentials;
+#[cfg(unix)]
+use super::unix_socket::{UnixAcceptor, UNIX_SOCKET_ADDR};
+use super::versioning::ApiEndpointVersions;
+use super::versioning::PrereleaseMatching;
+use super::versioning::VersionObserver;
+use super::versioning::VersionPolicy;
+use super::websocket_registry::WebsocketRegistry;
 use super::ProbeRegistration;
 
 use async_stream::stream;
 use debug_ignore::DebugIgnore;
-use futures::future::{
-    BoxFuture, FusedFuture, FutureExt, Shared, TryFutureExt,
-};
+use futures::future::{BoxFuture, FusedFuture, FutureExt, Shared};
 use futures::{
     lock::Mutex,
     stream::{Stream, StreamExt},
 };
+use hyper::body::HttpBody;
 use hyper::{
-    server::{
-        conn::{AddrIncoming, AddrStream},
-        Server,
-    },
+    server::{conn::AddrIncoming, Server},
     service::Service,
     Body, Request, Response,
 };
 use rustls;
+use rustls::server::WebPkiClientVerifier;
 use scopeguard::{guard, ScopeGuard};
 use std::fmt::Debug;
 use std::{
@@ -39,8 +74,10 @@ use std::{
     num::NonZeroU32,
     panic,
     pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     sync::Arc,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tokio::{
     io::ReadBuf,
@@ -74,6 +111,9 @@ pub trait Middleware<C: ServerContext>: Send + Sync + Debug {
     ) -> Result<Response<Body>, HttpError>;
 }
 
+/// How often to report on the requests that graceful shutdown is waiting for
+const SHUTDOWN_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
 // TODO Replace this with something else?
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
@@ -84,6 +124,19 @@ pub trait ServerContext: Send + Sync + 'static {}
 
 impl<T: 'static> ServerContext for T where T: Send + Sync {}
 
+/// How far a server has gotten in shutting down
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ServerPhase {
+    /// serving requests normally
+    Running,
+    /// asked to shut down, but still serving requests (during any lame-duck
+    /// period)
+    Draining,
+    /// graceful shutdown has begun: no new connections are accepted and
+    /// long-lived responses should stop
+    ShuttingDown,
+}
+
 /// Stores shared state used by the Dropshot server.
 #[derive(Debug)]
 pub struct DropshotState<C: ServerContext> {
@@ -97,17 +150,373 @@ pub struct DropshotState<C: ServerContext> {
     pub local_addr: SocketAddr,
     /// An optional middleware function that wraps all handlers.
     pub middleware: Option<Arc<dyn Middleware<C>>>,
+    /// Optional function used to report path parameter errors
+    pub(crate) path_error_handler: DebugIgnore<Option<PathErrorHandler>>,
+    /// Optional function used to produce all error responses
+    pub(crate) error_mapper: DebugIgnore<Option<ErrorMapper>>,
+    /// Determines the version of the API that each request is for
+    pub(crate) version_policy: VersionPolicy,
+    /// Optional function told of the version that each request is for
+    pub(crate) version_observer: DebugIgnore<Option<VersionObserver>>,
+    /// How requests for prerelease versions are matched with the versions of
+    /// endpoints
+    pub(crate) prerelease_matching: PrereleaseMatching,
     /// Identifies how to accept TLS connections
     pub(crate) tls_acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
     /// Worker for the handler_waitgroup associated with this server, allowing
-    /// graceful shutdown to wait for all handlers to complete.
-    pub(crate) handler_waitgroup_worker: DebugIgnore<waitgroup::Worker>,
+    /// graceful shutdown to wait for all handlers to complete.  This is
+    /// removed once the server has stopped serving connections, since no more
+    /// handlers can start after that.
+    pub(crate) handler_waitgroup_worker:
+        DebugIgnore<std::sync::Mutex<Option<waitgroup::Worker>>>,
+    /// How far the server has gotten in shutting down, for request handling
+    /// that depends on it and long-lived responses that need to stop early
+    pub(crate) phase: Arc<tokio::sync::watch::Sender<ServerPhase>>,
+    /// Set to `true` if graceful shutdown times out, to close the connections
+    /// that remain
+    pub(crate) aborting: Arc<tokio::sync::watch::Sender<bool>>,
+    /// Number of websocket connections currently upgraded (or about to be)
+    pub(crate) websocket_connections: Arc<AtomicUsize>,
+    /// Number of requests considered for `Server-Timing` sampling
+    pub(crate) server_timing_nrequests: AtomicU64,
+    /// Number of requests currently being handled
+    pub(crate) requests_in_flight: Arc<AtomicUsize>,
+    /// Number of requests received since the server started
+    pub(crate) requests_total: AtomicU64,
+    /// Number of connections currently open
+    pub(crate) connections: Arc<AtomicUsize>,
+    /// Number of connections closed as soon as they were accepted because
+    /// `max_connections` were already open
+    pub(crate) connections_refused: Arc<AtomicU64>,
+    /// Number of requests rejected because the server was overloaded
+    pub(crate) requests_shed: AtomicU64,
+    /// Number of connections closed because the client took too long to send
+    /// a request's headers
+    pub(crate) header_read_timeouts: Arc<AtomicU64>,
+    /// Number of connections closed because the client sent a request's body
+    /// too slowly
+    pub(crate) slow_request_bodies: Arc<AtomicU64>,
+    /// Decides whether to accept each TCP connection
+    pub(crate) connection_filter: Arc<ConnectionFilterSlot>,
+    /// APIs with other contexts that handle requests under path prefixes
+    pub(crate) mounts: MountTable,
+    /// when the server was created
+    pub(crate) created: Instant,
+    /// Set while new requests are to be rejected (see
+    /// [`DropshotState::set_quiescing`])
+    pub(crate) quiescing: AtomicBool,
+    /// Versions of the API that the server currently accepts requests for
+    /// (see [`DropshotState::set_supported_versions`])
+    pub(crate) supported_versions: std::sync::RwLock<ApiEndpointVersions>,
+    /// Keys used to seal page tokens, if they're sealed (see
+    /// [`HttpServerStarter::page_token_keys`])
+    pub(crate) page_token_keys: std::sync::OnceLock<PageTokenKeys>,
+    /// Registry of websocket connections, if there is one (see
+    /// [`DropshotState::set_websocket_registry`])
+    pub(crate) websocket_registry:
+        std::sync::RwLock<Option<Arc<WebsocketRegistry>>>,
+    /// Source of the current time, if not the system clock (see
+    /// [`HttpServerStarter::clock`])
+    pub(crate) clock: std::sync::RwLock<Option<Arc<dyn Clock>>>,
+    /// Counts of requests handled by each endpoint
+    pub(crate) route_stats: RouteStatsTable,
+    /// Validates request and response bodies, if that's enabled (see
+    /// [`ConfigDropshot::schema_validation`])
+    pub(crate) schema_validator: DebugIgnore<Option<SchemaValidator>>,
 }
 
 impl<C: ServerContext> DropshotState<C> {
+    /// Creates the state for a server that hasn't handled any requests yet
+    pub(crate) fn new(
+        private: C,
+        config: ServerConfig,
+        api: ApiDescription<C>,
+        middleware: Option<Arc<dyn Middleware<C>>>,
+        local_addr: SocketAddr,
+        tls_acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
+        handler_waitgroup_worker: Option<waitgroup::Worker>,
+    ) -> Self {
+        let path_error_handler = api.path_error_handler.clone();
+        let error_mapper = api.error_mapper.clone();
+        let version_policy = api.version_policy.clone();
+        let version_observer = api.version_observer.clone();
+        let prerelease_matching = api.prerelease_matching;
+        let schema_validator = api.schema_validator(config.schema_validation);
+        let router = api.into_router();
+        let route_stats = RouteStatsTable::new(&router);
+        DropshotState {
+            private,
+            config,
+            path_error_handler: DebugIgnore(path_error_handler),
+            error_mapper: DebugIgnore(error_mapper),
+            version_policy,
+            version_observer: DebugIgnore(version_observer),
+            prerelease_matching,
+            router,
+            middleware,
+            local_addr,
+            tls_acceptor,
+            handler_waitgroup_worker: DebugIgnore(std::sync::Mutex::new(
+                handler_waitgroup_worker,
+            )),
+            phase: Arc::new(
+                tokio::sync::watch::channel(ServerPhase::Running).0,
+            ),
+            aborting: Arc::new(tokio::sync::watch::channel(false).0),
+            websocket_connections: Arc::new(AtomicUsize::new(0)),
+            server_timing_nrequests: AtomicU64::new(0),
+            requests_in_flight: Arc::new(AtomicUsize::new(0)),
+            requests_total: AtomicU64::new(0),
+            connections: Arc::new(AtomicUsize::new(0)),
+            connections_refused: Arc::new(AtomicU64::new(0)),
+            requests_shed: AtomicU64::new(0),
+            header_read_timeouts: Arc::new(AtomicU64::new(0)),
+            slow_request_bodies: Arc::new(AtomicU64::new(0)),
+            connection_filter: Arc::new(ConnectionFilterSlot::default()),
+            mounts: MountTable::default(),
+            created: Instant::now(),
+            quiescing: AtomicBool::new(false),
+            supported_versions: std::sync::RwLock::new(
+                ApiEndpointVersions::All,
+            ),
+            page_token_keys: std::sync::OnceLock::new(),
+            clock: std::sync::RwLock::new(None),
+            websocket_registry: std::sync::RwLock::new(None),
+            route_stats,
+            schema_validator: DebugIgnore(schema_validator),
+        }
+    }
+
     pub fn using_tls(&self) -> bool {
         self.tls_acceptor.is_some()
     }
+
+    /// Returns whether the server has been asked to shut down
+    ///
+    /// This becomes `true` as soon as shutdown begins, including during the
+    /// lame-duck period (see [`ConfigDropshot::lame_duck_period_ms`]) when
+    /// requests are still being served.  Readiness checks should generally
+    /// report failure when this is set.
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() >= ServerPhase::Draining
+    }
+
+    /// Returns the number of websocket connections currently being handled
+    ///
+    /// This counts each connection from the time its upgrade request is
+    /// accepted until its handler completes.  See
+    /// [`ConfigDropshot::max_websocket_connections`].
+    pub fn websocket_connection_count(&self) -> usize {
+        self.websocket_connections.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of requests currently being handled
+    pub fn request_in_flight_count(&self) -> usize {
+        self.requests_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of requests received since the server started
+    pub fn request_count(&self) -> u64 {
+        self.requests_total.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of connections currently open
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of connections that were closed as soon as they
+    /// were accepted because [`ConfigDropshot::max_connections`] were already
+    /// open
+    pub fn refused_connection_count(&self) -> u64 {
+        self.connections_refused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of requests rejected with a 503 response because
+    /// of [`ConfigDropshot::max_concurrent_requests`]
+    pub fn shed_request_count(&self) -> u64 {
+        self.requests_shed.load(Ordering::SeqCst)
+    }
+
+    /// Returns how long it's been since the server was created
+    pub fn uptime(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Returns the current time according to the server's clock (see
+    /// [`HttpServerStarter::clock`])
+    ///
+    /// Handlers that deal in wall-clock time (e.g., to expire tokens, or to
+    /// enforce rate limits) should use this instead of the system clock, so
+    /// that tests can control it.
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        match &*self.clock.read().unwrap() {
+            Some(clock) => clock.now(),
+            None => chrono::Utc::now(),
+        }
+    }
+
+    /// Returns the server's clock (see [`HttpServerStarter::clock`])
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        match &*self.clock.read().unwrap() {
+            Some(clock) => Arc::clone(clock),
+            None => Arc::new(SystemClock),
+        }
+    }
+
+    /// Returns the security requirements of the endpoint that handles
+    /// requests with `method` for `path` (see [`crate::ApiEndpoint::security`]),
+    /// or the error that the server would report for such a request if
+    /// there's no such endpoint
+    ///
+    /// This lets authentication middleware check requests against what the
+    /// OpenAPI document says about them.  For versioned APIs, this describes
+    /// the first endpoint registered for `method` and `path`, whatever its
+    /// versions.
+    pub fn security_requirements(
+        &self,
+        method: &http::Method,
+        path: &str,
+    ) -> Result<Vec<SecurityRequirement>, HttpError> {
+        let lookup_result =
+            self.router.lookup_route(method, path.into(), None)?;
+        Ok(lookup_result.security)
+    }
+
+    /// Finds the endpoint that handles `request`, returning it along with the
+    /// version of the API that the request is for, if the API is versioned
+    ///
+    /// This removes the version from `request` if the version policy puts it
+    /// in the path.
+    pub(crate) fn route_request(
+        &self,
+        request: &mut Request<Body>,
+    ) -> Result<(Option<semver::Version>, RouterLookupResult<C>), HttpError>
+    {
+        let version = self.version_policy.request_version(request)?;
+        let matching_version = version
+            .as_ref()
+            .map(|version| self.prerelease_matching.matching_version(version));
+        if let (Some(version), Some(matching_version)) =
+            (&version, &matching_version)
+        {
+            let supported = self.supported_versions();
+            if !supported.matches(matching_version) {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    format!(
+                        "server does not support this API version: {} \
+                         (supported: {})",
+                        version, supported
+                    ),
+                ));
+            }
+        }
+        let lookup_result = self.router.lookup_route(
+            request.method(),
+            request.uri().path().into(),
+            matching_version.as_ref(),
+        )?;
+        Ok((version, lookup_result))
+    }
+
+    /// Finds the endpoint that would handle `request` (see
+    /// [`DropshotState::route_request`]) without modifying it, for middleware
+    /// that needs to know, or `None` if the server would reject the request
+    /// before it got to an endpoint
+    pub(crate) fn route_of(
+        &self,
+        request: &Request<Body>,
+    ) -> Option<RouterLookupResult<C>> {
+        let mut probe = Request::new(Body::empty());
+        *probe.method_mut() = request.method().clone();
+        *probe.uri_mut() = request.uri().clone();
+        *probe.version_mut() = request.version();
+        *probe.headers_mut() = request.headers().clone();
+        self.route_request(&mut probe).ok().map(|(_, route)| route)
+    }
+
+    /// Describes the requests handled by each of the server's endpoints, in
+    /// order of their paths
+    ///
+    /// Requests handled by APIs mounted on the server (see
+    /// [`HttpServerStarter::mount`]) are counted by those APIs instead.
+    pub fn route_stats(&self) -> Vec<RouteStats> {
+        self.route_stats.stats()
+    }
+
+    /// Sets whether the server is "quiescing", in which case it rejects new
+    /// requests with a 503 ("Service Unavailable") response
+    ///
+    /// Unlike shutting down, this can be undone, and the server keeps its
+    /// listeners and connections open throughout.  Requests already being
+    /// handled are unaffected, and [`DropshotState::request_in_flight_count`]
+    /// shows when they're done.  This is useful for taking a server out of
+    /// rotation during a rollout, or for debugging.
+    pub fn set_quiescing(&self, quiescing: bool) {
+        self.quiescing.store(quiescing, Ordering::SeqCst);
+    }
+
+    /// Returns whether the server is rejecting new requests because of
+    /// [`DropshotState::set_quiescing`]
+    pub fn is_quiescing(&self) -> bool {
+        self.quiescing.load(Ordering::SeqCst)
+    }
+
+    /// Sets the versions of the API that the server accepts requests for,
+    /// rejecting requests for other versions with a 400 ("Bad Request")
+    /// response
+    ///
+    /// By default, the server accepts requests for every version that its
+    /// [`VersionPolicy`] does.  This narrows that window at runtime, so that
+    /// operators can, for instance, retract a broken new version without
+    /// redeploying.  It only affects versioned APIs.
+    pub fn set_supported_versions(&self, versions: ApiEndpointVersions) {
+        *self.supported_versions.write().unwrap() = versions;
+    }
+
+    /// Returns the versions of the API that the server accepts requests for
+    /// (see [`DropshotState::set_supported_versions`])
+    pub fn supported_versions(&self) -> ApiEndpointVersions {
+        self.supported_versions.read().unwrap().clone()
+    }
+
+    /// Returns the keys used to seal page tokens, if they're sealed
+    pub(crate) fn page_token_keys(&self) -> Option<&PageTokenKeys> {
+        self.page_token_keys.get()
+    }
+
+    /// Sets the registry that websocket handlers register their connections
+    /// with, or stops registering them if `registry` is `None`
+    ///
+    /// Connections that are already registered stay in the registry they were
+    /// registered with.  See [`WebsocketRegistry`].
+    pub fn set_websocket_registry(
+        &self,
+        registry: Option<Arc<WebsocketRegistry>>,
+    ) {
+        *self.websocket_registry.write().unwrap() = registry;
+    }
+
+    /// Returns the registry that websocket handlers register their
+    /// connections with, if there is one
+    pub fn websocket_registry(&self) -> Option<Arc<WebsocketRegistry>> {
+        self.websocket_registry.read().unwrap().clone()
+    }
+
+    /// Returns the number of connections closed because the client took
+    /// longer than [`ConfigDropshot::header_read_timeout_ms`] to send a
+    /// request's headers
+    pub fn header_read_timeout_count(&self) -> u64 {
+        self.header_read_timeouts.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of connections closed because the client sent a
+    /// request's body more slowly than
+    /// [`ConfigDropshot::min_request_body_rate`]
+    pub fn slow_request_body_count(&self) -> u64 {
+        self.slow_request_bodies.load(Ordering::SeqCst)
+    }
 }
 
 /// Stores static configuration associated with the server
@@ -123,12 +532,122 @@ pub struct ServerConfig {
     /// Default behavior for HTTP handler functions with respect to clients
     /// disconnecting early.
     pub default_handler_task_mode: HandlerTaskMode,
+    /// peers trusted to report the client address via forwarding headers
+    pub trusted_proxies: Vec<IpCidr>,
+    /// whether connections begin with a PROXY protocol header
+    pub proxy_protocol: bool,
+    /// how long to keep serving requests after shutdown has been requested
+    pub lame_duck_period: Duration,
+    /// how long to wait for requests to complete during graceful shutdown
+    pub shutdown_timeout: Option<Duration>,
+    /// how long a connection may be idle before it's closed
+    pub keep_alive_timeout: Option<Duration>,
+    /// how long a client may take to send HTTP/1 request headers
+    pub header_read_timeout: Option<Duration>,
+    /// longest timeout a client may request with a timeout header, if those
+    /// are honored at all
+    pub request_timeout_max: Option<Duration>,
+    /// minimum rate (in bytes per second) at which request bodies are sent
+    pub min_request_body_rate: Option<u64>,
+    /// maximum number of requests on an HTTP/1 connection
+    pub max_requests_per_connection: Option<u64>,
+    /// how long an HTTP/1 connection may be used for new requests
+    pub max_connection_age: Option<Duration>,
+    /// maximum number of open connections
+    pub max_connections: Option<usize>,
+    /// maximum number of requests handled at once
+    pub max_concurrent_requests: Option<usize>,
+    /// maximum length of a request's path and query string
+    pub max_uri_length: Option<usize>,
+    /// maximum total size of a request's header fields
+    pub max_request_header_bytes: Option<usize>,
+    /// maximum number of header fields in a request
+    pub max_request_headers: Option<usize>,
+    /// whether error bodies are rendered according to the `Accept` header
+    pub error_content_negotiation: bool,
+    /// default format of error bodies (from the API description)
+    pub error_response_format: ErrorResponseFormat,
+    /// maximum number of concurrent websocket connections, if any
+    pub max_websocket_connections: Option<usize>,
+    /// keepalive and idle timeout for websocket connections
+    pub websocket: ConfigWebsocket,
+    /// CORS policy
+    pub cors: ConfigCors,
+    /// when to send a `Server-Timing` header
+    pub server_timing: ConfigServerTiming,
+    /// `Alt-Svc` header advertising HTTP/3, if it's being served
+    pub alt_svc: Option<http::HeaderValue>,
+    /// options for TCP sockets
+    pub tcp: ConfigTcp,
+    /// whether request and response bodies are validated against their
+    /// schemas
+    pub schema_validation: ConfigSchemaValidation,
+}
+
+impl ServerConfig {
+    /// Returns the configuration for a server described by `config`, serving
+    /// an API whose errors are reported in `error_response_format`
+    pub(crate) fn new(
+        config: &ConfigDropshot,
+        error_response_format: ErrorResponseFormat,
+        alt_svc: Option<http::HeaderValue>,
+    ) -> ServerConfig {
+        ServerConfig {
+            // We start aggressively to ensure test coverage.
+            request_body_max_bytes: config.request_body_max_bytes,
+            page_max_nitems: NonZeroU32::new(10000).unwrap(),
+            page_default_nitems: NonZeroU32::new(100).unwrap(),
+            default_handler_task_mode: config.default_handler_task_mode,
+            trusted_proxies: config.trusted_proxies.clone(),
+            proxy_protocol: config.proxy_protocol,
+            lame_duck_period: Duration::from_millis(config.lame_duck_period_ms),
+            shutdown_timeout: config
+                .shutdown_timeout_ms
+                .map(Duration::from_millis),
+            keep_alive_timeout: config
+                .keep_alive_timeout_ms
+                .map(Duration::from_millis),
+            header_read_timeout: config
+                .header_read_timeout_ms
+                .map(Duration::from_millis),
+            request_timeout_max: config
+                .request_timeout_max_ms
+                .map(Duration::from_millis),
+            min_request_body_rate: config
+                .min_request_body_rate
+                .filter(|rate| *rate > 0),
+            max_requests_per_connection: config.max_requests_per_connection,
+            max_connection_age: config
+                .max_connection_age_ms
+                .map(Duration::from_millis),
+            max_connections: config.max_connections,
+            max_concurrent_requests: config.max_concurrent_requests,
+            max_uri_length: config.max_uri_length,
+            max_request_header_bytes: config.max_request_header_bytes,
+            max_request_headers: config.max_request_headers,
+            error_content_negotiation: config.error_content_negotiation,
+            error_response_format,
+            max_websocket_connections: config.max_websocket_connections,
+            websocket: config.websocket,
+            cors: config.cors.clone(),
+            server_timing: config.server_timing.clone(),
+            alt_svc,
+            tcp: config.tcp.clone(),
+            schema_validation: config.schema_validation,
+        }
+    }
 }
 
 pub struct HttpServerStarter<C: ServerContext> {
     app_state: Arc<DropshotState<C>>,
     local_addr: SocketAddr,
     wrapped: WrappedHttpServerStarter<C>,
+    /// listeners added with `listen()`, and their bound addresses
+    additional_listeners: Vec<(SocketAddr, WrappedHttpServerStarter<C>)>,
+    /// future provided with `shutdown_signal()`
+    shutdown_signal: Option<SharedBoxFuture<()>>,
+    /// copies of the TCP listeners, for `HttpServer::try_clone_listeners()`
+    tcp_listeners: Vec<std::net::TcpListener>,
     handler_waitgroup: WaitGroup,
 }
 
@@ -149,20 +668,134 @@ impl<C: ServerContext> HttpServerStarter<C> {
         private: C,
         tls: Option<ConfigTls>,
     ) -> Result<HttpServerStarter<C>, GenericError> {
-        let server_config = ServerConfig {
-            // We start aggressively to ensure test coverage.
-            request_body_max_bytes: config.request_body_max_bytes,
-            page_max_nitems: NonZeroU32::new(10000).unwrap(),
-            page_default_nitems: NonZeroU32::new(100).unwrap(),
-            default_handler_task_mode: config.default_handler_task_mode,
+        Self::new_with_bound_listener(
+            config, api, middleware, private, tls, None,
+        )
+    }
+
+    /// Like [`HttpServerStarter::new_with_tls`], but accepts connections on
+    /// an existing `listener` instead of binding to
+    /// `config.bind_address` (or `config.unix_socket`), which are ignored
+    ///
+    /// This allows the socket to be created by some other process (e.g., one
+    /// that hands it off to a new version of the server for a restart that
+    /// doesn't refuse any connections, or a service manager that starts the
+    /// server on demand) or before the process gives up the privileges needed
+    /// to bind it.  See also [`crate::systemd_listeners`].
+    pub fn new_with_listener(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        middleware: Option<Arc<dyn Middleware<C>>>,
+        private: C,
+        tls: Option<ConfigTls>,
+        listener: std::net::TcpListener,
+    ) -> Result<HttpServerStarter<C>, GenericError> {
+        Self::new_with_bound_listener(
+            config,
+            api,
+            middleware,
+            private,
+            tls,
+            Some(listener),
+        )
+    }
+
+    fn new_with_bound_listener(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        middleware: Option<Arc<dyn Middleware<C>>>,
+        private: C,
+        tls: Option<ConfigTls>,
+        listener: Option<std::net::TcpListener>,
+    ) -> Result<HttpServerStarter<C>, GenericError> {
+        // The HTTP/3 listener is bound first so that the other listeners can
+        // advertise its port.
+        #[cfg(feature = "http3")]
+        let http3 = match (&config.http3, &tls) {
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err("HTTP/3 is only supported with TLS".into());
+            }
+            (Some(http3), Some(tls)) => Some(Http3Listener::bind(http3, tls)?),
+        };
+        #[cfg(feature = "http3")]
+        let alt_svc = http3.as_ref().and_then(Http3Listener::alt_svc);
+        #[cfg(not(feature = "http3"))]
+        let alt_svc = match &config.http3 {
+            None => None,
+            Some(_) => {
+                return Err(
+                    "HTTP/3 support requires the \"http3\" feature".into()
+                );
+            }
         };
 
+        let server_config =
+            ServerConfig::new(config, api.error_response_format, alt_svc);
+
         let handler_waitgroup = WaitGroup::new();
+        let use_unix_socket =
+            listener.is_none() && config.unix_socket.is_some();
+        if config.proxy_protocol {
+            if use_unix_socket {
+                return Err("the PROXY protocol is not supported on Unix \
+                    domain sockets"
+                    .into());
+            }
+            if config.http3.is_some() {
+                return Err(
+                    "the PROXY protocol is not supported with HTTP/3".into()
+                );
+            }
+            if config.trusted_proxies.is_empty() {
+                return Err("the PROXY protocol requires at least one \
+                    trusted proxy"
+                    .into());
+            }
+        }
         let starter = match &tls {
+            Some(_) if use_unix_socket => {
+                return Err(
+                    "TLS is not supported on Unix domain sockets".into()
+                );
+            }
+            #[cfg(unix)]
+            None if use_unix_socket => {
+                let tcp_listeners = Vec::new();
+                let (starter, app_state, local_addr) =
+                    InnerUnixServerStarter::new(
+                        config,
+                        server_config,
+                        api,
+                        middleware,
+                        private,
+                        handler_waitgroup.worker(),
+                    )?;
+                HttpServerStarter {
+                    app_state,
+                    local_addr,
+                    wrapped: WrappedHttpServerStarter::Unix(starter),
+                    additional_listeners: Vec::new(),
+                    shutdown_signal: None,
+                    tcp_listeners,
+                    handler_waitgroup,
+                }
+            }
+            #[cfg(not(unix))]
+            None if use_unix_socket => {
+                return Err("Unix domain sockets are not supported on this \
+                    platform"
+                    .into());
+            }
             Some(tls) => {
+                let tcp = match listener {
+                    Some(listener) => listener,
+                    None => bind_tcp(&config.bind_address, &config.tcp)?,
+                };
+                let tcp_listeners = vec![tcp.try_clone()?];
                 let (starter, app_state, local_addr) =
                     InnerHttpsServerStarter::new(
-                        config,
+                        tcp,
                         server_config,
                         api,
                         middleware,
@@ -174,13 +807,21 @@ impl<C: ServerContext> HttpServerStarter<C> {
                     app_state,
                     local_addr,
                     wrapped: WrappedHttpServerStarter::Https(starter),
+                    additional_listeners: Vec::new(),
+                    shutdown_signal: None,
+                    tcp_listeners,
                     handler_waitgroup,
                 }
             }
             None => {
+                let tcp = match listener {
+                    Some(listener) => listener,
+                    None => bind_tcp(&config.bind_address, &config.tcp)?,
+                };
+                let tcp_listeners = vec![tcp.try_clone()?];
                 let (starter, app_state, local_addr) =
                     InnerHttpServerStarter::new(
-                        config,
+                        tcp,
                         server_config,
                         api,
                         middleware,
@@ -191,11 +832,30 @@ impl<C: ServerContext> HttpServerStarter<C> {
                     app_state,
                     local_addr,
                     wrapped: WrappedHttpServerStarter::Http(starter),
+                    additional_listeners: Vec::new(),
+                    shutdown_signal: None,
+                    tcp_listeners,
                     handler_waitgroup,
                 }
             }
         };
 
+        #[cfg(feature = "http3")]
+        let starter = {
+            let mut starter = starter;
+            if let Some(http3) = http3 {
+                let local_addr = http3.local_addr();
+                let app_state = Arc::clone(&starter.app_state);
+                starter.additional_listeners.push((
+                    local_addr,
+                    WrappedHttpServerStarter::Http3(
+                        http3.with_state(app_state),
+                    ),
+                ));
+            }
+            starter
+        };
+
         for (path, method, _) in &starter.app_state.router {
             trace!(method = &method, path = &path, "registered endpoint");
         }
@@ -203,26 +863,318 @@ impl<C: ServerContext> HttpServerStarter<C> {
         Ok(starter)
     }
 
+    /// Also accepts connections on the address described by `listener`
+    ///
+    /// Connections on all of a server's listeners are handled the same way,
+    /// by the same API and with the same context, but each listener may or
+    /// may not use TLS.  This can be used to serve plaintext HTTP on
+    /// localhost alongside HTTPS on an external address, or to listen on
+    /// IPv4 and IPv6 addresses separately.  The addresses of all of the
+    /// listeners are reported by [`HttpServer::local_addrs`].
+    ///
+    /// [`DropshotState::using_tls`] and [`HttpServer::refresh_tls`] only
+    /// apply to the server's original listener.
+    pub fn listen(
+        self,
+        listener: &ConfigListener,
+    ) -> Result<HttpServerStarter<C>, GenericError> {
+        let tcp = bind_tcp(&listener.bind_address, &self.app_state.config.tcp)?;
+        self.listen_on(tcp, listener.tls.as_ref())
+    }
+
+    /// Like [`HttpServerStarter::listen`], but accepts connections on an
+    /// existing `listener`
+    ///
+    /// See [`HttpServerStarter::new_with_listener`].
+    pub fn listen_on(
+        mut self,
+        listener: std::net::TcpListener,
+        tls: Option<&ConfigTls>,
+    ) -> Result<HttpServerStarter<C>, GenericError> {
+        let app_state = Arc::clone(&self.app_state);
+        self.tcp_listeners.push(listener.try_clone()?);
+        let (local_addr, wrapped) = match tls {
+            None => {
+                let (starter, local_addr) =
+                    InnerHttpServerStarter::with_state(listener, app_state)?;
+                (local_addr, WrappedHttpServerStarter::Http(starter))
+            }
+            Some(tls) => {
+                let (starter, local_addr) =
+                    InnerHttpsServerStarter::with_state(
+                        listener, tls, app_state,
+                    )?;
+                (local_addr, WrappedHttpServerStarter::Https(starter))
+            }
+        };
+        self.additional_listeners.push((local_addr, wrapped));
+        Ok(self)
+    }
+
+    /// Arranges for the server to shut down gracefully when `signal`
+    /// completes
+    ///
+    /// This is an alternative to calling [`HttpServer::close`] for
+    /// applications that shut down in response to some external event (e.g., a
+    /// `SIGTERM`), which would otherwise need to hand the [`HttpServer`] to
+    /// whatever task waits for that event.  Once `signal` completes, shutdown
+    /// proceeds just as it does for `close()`, including the lame-duck period
+    /// and shutdown timeout (if configured), and the [`HttpServer`] (and any
+    /// [`ShutdownWaitFuture`]s) complete when it's finished.
+    ///
+    /// ```no_run
+    /// use dropshot::ApiDescription;
+    /// use dropshot::ConfigDropshot;
+    /// use dropshot::HttpServerStarter;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let server = HttpServerStarter::new(
+    ///     &ConfigDropshot::default(),
+    ///     ApiDescription::new(),
+    ///     None,
+    ///     (),
+    /// )?
+    /// .shutdown_signal(async {
+    ///     let _ = tokio::signal::ctrl_c().await;
+    /// })
+    /// .start();
+    /// server.await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shutdown_signal<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_signal = Some(signal.boxed().shared());
+        self
+    }
+
+    /// Consults `filter` before accepting each TCP connection on any of the
+    /// server's listeners, replacing any filter provided previously
+    ///
+    /// Rejected connections are closed before any TLS negotiation or HTTP
+    /// processing, which makes this a cheap way to enforce allow or deny lists
+    /// of client addresses or limits on the number of connections from each
+    /// client.  See [`ConnectionFilter`].
+    ///
+    /// ```no_run
+    /// use dropshot::ApiDescription;
+    /// use dropshot::ConfigDropshot;
+    /// use dropshot::HttpServerStarter;
+    /// use std::net::SocketAddr;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let server = HttpServerStarter::new(
+    ///     &ConfigDropshot::default(),
+    ///     ApiDescription::new(),
+    ///     None,
+    ///     (),
+    /// )?
+    /// .connection_filter(|peer: SocketAddr| peer.ip().is_loopback())
+    /// .start();
+    /// # server.close().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connection_filter<F: ConnectionFilter>(self, filter: F) -> Self {
+        self.app_state.connection_filter.set(Arc::new(filter));
+        self
+    }
+
+    /// Seals the page tokens handed out by paginated endpoints with `keys`,
+    /// so that clients can't read or alter them
+    ///
+    /// See [`PageTokenKeys`] for details.  The keys can't be changed once the
+    /// server is running: to rotate them, restart the server with the old
+    /// current key as a previous key.
+    ///
+    /// Handlers seal the pages they return with
+    /// [`RequestContext::page_token_keys()`] (see
+    /// [`crate::ResultsPage::seal()`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once.
+    pub fn page_token_keys(self, keys: PageTokenKeys) -> Self {
+        if self.app_state.page_token_keys.set(keys).is_err() {
+            panic!("page token keys were already set");
+        }
+        self
+    }
+
+    /// Lets websocket handlers register their connections with `registry`,
+    /// so that the rest of the server can send them messages
+    ///
+    /// See [`WebsocketRegistry`] for details.  The registry can be changed
+    /// once the server is running with [`HttpServer::set_websocket_registry`].
+    pub fn websocket_registry(self, registry: Arc<WebsocketRegistry>) -> Self {
+        self.app_state.set_websocket_registry(Some(registry));
+        self
+    }
+
+    /// Takes the current time from `clock` instead of the system clock
+    ///
+    /// The server's clock determines the `Date` header of each response, what
+    /// handlers see from [`DropshotState::now`], when idempotency records
+    /// and sealed page tokens expire, and how fast websocket clients may send
+    /// messages.  See [`Clock`] for details.  (This is mostly useful for tests: see
+    /// [`crate::test_util::TestClock`].)
+    pub fn clock(self, clock: Arc<dyn Clock>) -> Self {
+        *self.app_state.clock.write().unwrap() = Some(clock);
+        self
+    }
+
+    /// Hands requests whose paths begin with `prefix` to `service`, which
+    /// handles them for its own API, with its own context
+    ///
+    /// This allows one server to host several APIs whose context types
+    /// differ, like a public API and an administrative one.  The prefix (which
+    /// must begin with "/" and not end with one) is removed from the path of
+    /// each request before it's given to `service`, so that the mounted API's
+    /// endpoints are registered relative to it: with `prefix` "/admin", a
+    /// request for "/admin/users" goes to the mounted API's "/users" endpoint.
+    /// Routes of the server's own API under the prefix are unreachable.
+    ///
+    /// Mounted requests are handled according to the configuration that
+    /// `service` was created with, and they're counted in its
+    /// [`DropshotState`] rather than the server's, but they're received on the
+    /// server's listeners (subject to its limits on connections and request
+    /// headers).  Each API still has its own OpenAPI document.
+    ///
+    /// ```no_run
+    /// use dropshot::ApiDescription;
+    /// use dropshot::ApiService;
+    /// use dropshot::ConfigDropshot;
+    /// use dropshot::HttpServerStarter;
+    /// use std::sync::Arc;
+    ///
+    /// struct App;
+    /// struct AdminState;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let public_api: ApiDescription<Arc<App>> = ApiDescription::new();
+    /// let admin_api: ApiDescription<Arc<AdminState>> = ApiDescription::new();
+    /// let server = HttpServerStarter::new(
+    ///     &ConfigDropshot::default(),
+    ///     public_api,
+    ///     None,
+    ///     Arc::new(App),
+    /// )?
+    /// .mount("/admin", ApiService::new(admin_api, Arc::new(AdminState)))?
+    /// .start();
+    /// # server.close().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn mount<D: ServerContext>(
+        self,
+        prefix: &str,
+        service: ApiService<D>,
+    ) -> Result<Self, GenericError> {
+        self.app_state.mounts.add(prefix, Arc::new(service))?;
+        Ok(self)
+    }
+
     pub fn start(self) -> HttpServer<C> {
+        if let Some(keys) = self.app_state.page_token_keys() {
+            keys.use_clock(self.app_state.clock());
+        }
         let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-        let join_handle = match self.wrapped {
-            WrappedHttpServerStarter::Http(http) => http.start(rx),
-            WrappedHttpServerStarter::Https(https) => https.start(rx),
+        let phase = Arc::clone(&self.app_state.phase);
+        let lame_duck_period = self.app_state.config.lame_duck_period;
+        let shutdown_signal = self.shutdown_signal.clone();
+        let tcp_listeners = Arc::new(std::sync::Mutex::new(self.tcp_listeners));
+        let handoff_listeners = Arc::clone(&tcp_listeners);
+        let close_signal = async move {
+            let close_requested = async {
+                rx.await.expect(
+                    "dropshot server shutting down without invoking close()",
+                );
+            };
+            match shutdown_signal {
+                None => close_requested.await,
+                Some(signal) => {
+                    tokio::select! {
+                        () = close_requested => (),
+                        () = signal => (),
+                    }
+                }
+            }
+            phase.send_replace(ServerPhase::Draining);
+            if !lame_duck_period.is_zero() {
+                info!(
+                    lame_duck_period = ?lame_duck_period,
+                    "received request to shut down; entering lame-duck period"
+                );
+                tokio::time::sleep(lame_duck_period).await;
+            }
+            info!("received request to begin graceful shutdown");
+            // Our copies of the listening sockets must be closed along with
+            // the originals so that new connections are refused (unless
+            // another process has its own copies).
+            handoff_listeners.lock().unwrap().clear();
+            phase.send_replace(ServerPhase::ShuttingDown);
+        }
+        .boxed()
+        .shared();
+
+        let mut local_addrs = vec![self.local_addr];
+        let mut join_handles = vec![self.wrapped.start(close_signal.clone())];
+        for (local_addr, wrapped) in self.additional_listeners {
+            local_addrs.push(local_addr);
+            join_handles.push(wrapped.start(close_signal.clone()));
+        }
+        let join_handle =
+            futures::future::try_join_all(join_handles.into_iter().map(|h| {
+                h.map(|r| {
+                    r.map_err(|e| format!("waiting for server: {e}"))?
+                        .map_err(|e| format!("server stopped: {e}"))
+                })
+            }));
+        for local_addr in &local_addrs {
+            trace!(local_addr = %local_addr, "started web service");
         }
-        .map(|r| {
-            r.map_err(|e| format!("waiting for server: {e}"))?
-                .map_err(|e| format!("server stopped: {e}"))
-        });
-        trace!(local_addr = %self.local_addr, "started web service");
 
         let handler_waitgroup = self.handler_waitgroup;
+        let app_state = Arc::clone(&self.app_state);
         let join_handle = async move {
             // After the server shuts down, we also want to wait for any
-            // detached handler futures to complete.
-            () = join_handle.await?;
+            // detached handler futures to complete.  Our own worker must be
+            // dropped for that wait to finish.
+            join_handle.await?;
+            mem::drop(
+                app_state.handler_waitgroup_worker.lock().unwrap().take(),
+            );
+            mem::drop(app_state);
             () = handler_waitgroup.wait().await;
             Ok(())
         };
+        let join_future = match (
+            self.shutdown_signal,
+            self.app_state.config.shutdown_timeout,
+        ) {
+            // If shutdown is triggered by the signal rather than by `close()`,
+            // the timeout needs to be enforced here.
+            (Some(signal), Some(timeout)) => {
+                let monitor = ShutdownMonitor::new(&self.app_state);
+                let join_handle = join_handle.boxed().shared();
+                async move {
+                    tokio::select! {
+                        () = signal => {
+                            monitor.wait(join_handle, Some(timeout)).await
+                        }
+                        result = join_handle.clone() => result,
+                    }
+                }
+                .boxed()
+                .shared()
+            }
+            _ => join_handle.boxed().shared(),
+        };
 
         #[cfg(feature = "usdt-probes")]
         let probe_registration = match usdt::register_probes() {
@@ -244,10 +1196,14 @@ impl<C: ServerContext> HttpServerStarter<C> {
 
         HttpServer {
             probe_registration,
+            #[cfg(feature = "server-registry")]
+            _registration: crate::registry::Registration::new(&self.app_state),
             app_state: self.app_state,
             local_addr: self.local_addr,
+            local_addrs,
+            tcp_listeners,
             closer: CloseHandle { close_channel: Some(tx) },
-            join_future: join_handle.boxed().shared(),
+            join_future,
         }
     }
 }
@@ -255,10 +1211,37 @@ impl<C: ServerContext> HttpServerStarter<C> {
 enum WrappedHttpServerStarter<C: ServerContext> {
     Http(InnerHttpServerStarter<C>),
     Https(InnerHttpsServerStarter<C>),
+    #[cfg(unix)]
+    Unix(InnerUnixServerStarter<C>),
+    #[cfg(feature = "http3")]
+    Http3(Http3ServerStarter<C>),
+}
+
+impl<C: ServerContext> WrappedHttpServerStarter<C> {
+    fn start<F>(
+        self,
+        close_signal: F,
+    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            WrappedHttpServerStarter::Http(http) => http.start(close_signal),
+            WrappedHttpServerStarter::Https(https) => https.start(close_signal),
+            #[cfg(unix)]
+            WrappedHttpServerStarter::Unix(unix) => unix.start(close_signal),
+            #[cfg(feature = "http3")]
+            WrappedHttpServerStarter::Http3(http3) => http3.start(close_signal),
+        }
+    }
 }
 
 struct InnerHttpServerStarter<C: ServerContext>(
-    Server<AddrIncoming, ServerConnectionHandler<C>>,
+    Server<
+        ConnectionAcceptor<ProxyAcceptor<AddrIncoming>>,
+        ServerConnectionHandler<C>,
+        ConnectionExecutor,
+    >,
 );
 
 type InnerHttpServerStarterNewReturn<C> =
@@ -266,67 +1249,174 @@ type InnerHttpServerStarterNewReturn<C> =
 
 impl<C: ServerContext> InnerHttpServerStarter<C> {
     /// Begins execution of the underlying Http server.
-    fn start(
+    fn start<F>(
         self,
-        close_signal: tokio::sync::oneshot::Receiver<()>,
-    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>> {
-        let graceful = self.0.with_graceful_shutdown(async move {
-            close_signal.await.expect(
-                "dropshot server shutting down without invoking close()",
-            );
-            info!("received request to begin graceful shutdown");
-        });
-
+        close_signal: F,
+    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let graceful = self.0.with_graceful_shutdown(close_signal);
         tokio::spawn(graceful)
     }
 
-    /// Set up an HTTP server bound on the specified address that runs
-    /// registered handlers.  You must invoke `start()` on the returned instance
-    /// of `HttpServerStarter` (and await the result) to actually start the
-    /// server.
+    /// Set up an HTTP server accepting connections on the specified listener
+    /// that runs registered handlers.  You must invoke `start()` on the
+    /// returned instance of `HttpServerStarter` (and await the result) to
+    /// actually start the server.
     fn new(
-        config: &ConfigDropshot,
+        tcp: std::net::TcpListener,
         server_config: ServerConfig,
         api: ApiDescription<C>,
         middleware: Option<Arc<dyn Middleware<C>>>,
         private: C,
         handler_waitgroup_worker: waitgroup::Worker,
-    ) -> Result<InnerHttpServerStarterNewReturn<C>, hyper::Error> {
-        let incoming = AddrIncoming::bind(&config.bind_address)?;
+    ) -> Result<InnerHttpServerStarterNewReturn<C>, GenericError> {
+        let mut incoming =
+            AddrIncoming::from_listener(into_tokio_listener(tcp)?)?;
+        configure_incoming(&mut incoming, &server_config.tcp);
         let local_addr = incoming.local_addr();
 
-        let app_state = Arc::new(DropshotState {
+        let app_state = Arc::new(DropshotState::new(
             private,
-            config: server_config,
-            router: api.into_router(),
+            server_config,
+            api,
             middleware,
             local_addr,
-            tls_acceptor: None,
-            handler_waitgroup_worker: DebugIgnore(handler_waitgroup_worker),
-        });
+            None,
+            Some(handler_waitgroup_worker),
+        ));
 
         let make_service = ServerConnectionHandler::new(app_state.clone());
-        let builder = hyper::Server::builder(incoming);
-        let server = builder.serve(make_service);
+        let filter = Arc::clone(&app_state.connection_filter);
+        let incoming =
+            ProxyAcceptor::new(incoming, ProxyProtocol::new(&app_state.config));
+        let server = server_builder(incoming, &app_state, Some(filter))
+            .serve(make_service);
         Ok((InnerHttpServerStarter(server), app_state, local_addr))
     }
+
+    /// Set up an HTTP server accepting connections on the specified listener
+    /// for an existing server's state.  This is used for listeners beyond the
+    /// first.
+    fn with_state(
+        tcp: std::net::TcpListener,
+        app_state: Arc<DropshotState<C>>,
+    ) -> Result<(InnerHttpServerStarter<C>, SocketAddr), GenericError> {
+        let mut incoming =
+            AddrIncoming::from_listener(into_tokio_listener(tcp)?)?;
+        configure_incoming(&mut incoming, &app_state.config.tcp);
+        let local_addr = incoming.local_addr();
+        let filter = Arc::clone(&app_state.connection_filter);
+        let incoming =
+            ProxyAcceptor::new(incoming, ProxyProtocol::new(&app_state.config));
+        let builder = server_builder(incoming, &app_state, Some(filter));
+        let server = builder.serve(ServerConnectionHandler::new(app_state));
+        Ok((InnerHttpServerStarter(server), local_addr))
+    }
+}
+
+#[cfg(unix)]
+struct InnerUnixServerStarter<C: ServerContext>(
+    Server<
+        ConnectionAcceptor<UnixAcceptor>,
+        ServerConnectionHandler<C>,
+        ConnectionExecutor,
+    >,
+);
+
+#[cfg(unix)]
+type InnerUnixServerStarterNewReturn<C> =
+    (InnerUnixServerStarter<C>, Arc<DropshotState<C>>, SocketAddr);
+
+#[cfg(unix)]
+impl<C: ServerContext> InnerUnixServerStarter<C> {
+    /// Begins execution of the underlying Http server.
+    fn start<F>(
+        self,
+        close_signal: F,
+    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let graceful = self.0.with_graceful_shutdown(close_signal);
+        tokio::spawn(graceful)
+    }
+
+    /// Set up an HTTP server listening on the Unix domain socket specified by
+    /// `config.unix_socket`.
+    fn new(
+        config: &ConfigDropshot,
+        server_config: ServerConfig,
+        api: ApiDescription<C>,
+        middleware: Option<Arc<dyn Middleware<C>>>,
+        private: C,
+        handler_waitgroup_worker: waitgroup::Worker,
+    ) -> Result<InnerUnixServerStarterNewReturn<C>, GenericError> {
+        let unix_config = config
+            .unix_socket
+            .as_ref()
+            .expect("Unix domain socket server without a socket path");
+        let acceptor = UnixAcceptor::bind(unix_config).map_err(|e| {
+            io_error(format!(
+                "failed to bind to {}: {}",
+                unix_config.path.display(),
+                e
+            ))
+        })?;
+        let local_addr = UNIX_SOCKET_ADDR;
+
+        let app_state = Arc::new(DropshotState::new(
+            private,
+            server_config,
+            api,
+            middleware,
+            local_addr,
+            None,
+            Some(handler_waitgroup_worker),
+        ));
+
+        let make_service = ServerConnectionHandler::new(Arc::clone(&app_state));
+        let server =
+            server_builder(acceptor, &app_state, None).serve(make_service);
+        Ok((InnerUnixServerStarter(server), app_state, local_addr))
+    }
 }
 
 /// Wrapper for TlsStream<TcpStream> that also carries the remote SocketAddr
+/// and the client's certificate (if it presented one)
 #[derive(Debug)]
 struct TlsConn {
-    stream: TlsStream<TcpStream>,
+    stream: TlsStream<ProxiedStream<TcpStream>>,
     remote_addr: SocketAddr,
+    client_certificate: Option<ClientCertificate>,
+    /// held until the connection closes, for the server's connection filter
+    _admitted: AdmittedConnection,
 }
 
 impl TlsConn {
-    fn new(stream: TlsStream<TcpStream>, remote_addr: SocketAddr) -> TlsConn {
-        TlsConn { stream, remote_addr }
+    fn new(
+        stream: TlsStream<ProxiedStream<TcpStream>>,
+        remote_addr: SocketAddr,
+        admitted: AdmittedConnection,
+    ) -> TlsConn {
+        let client_certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(ClientCertificate::new);
+        TlsConn { stream, remote_addr, client_certificate, _admitted: admitted }
     }
+}
 
+impl ConnectionInfo for TlsConn {
     fn remote_addr(&self) -> SocketAddr {
         self.remote_addr
     }
+
+    fn client_certificate(&self) -> Option<ClientCertificate> {
+        self.client_certificate.clone()
+    }
 }
 
 /// Forward AsyncRead to the underlying stream
@@ -376,7 +1466,10 @@ impl tokio::io::AsyncWrite for TlsConn {
 /// Internally, it creates a stream that produces fully negotiated TLS
 /// connections as they come in from a TCP listen socket.  This stream allows
 /// for multiple TLS connections to be negotiated concurrently with new
-/// connections being accepted.
+/// connections being accepted.  The configured TCP options are applied to each
+/// connection, and if the server expects a PROXY protocol header, that's read
+/// next.  Connections are then offered to the server's connection filter
+/// before any TLS negotiation takes place.
 struct HttpsAcceptor {
     stream: Box<dyn Stream<Item = std::io::Result<TlsConn>> + Send + Unpin>,
 }
@@ -385,11 +1478,17 @@ impl HttpsAcceptor {
     pub fn new(
         tls_acceptor: Arc<Mutex<TlsAcceptor>>,
         tcp_listener: TcpListener,
+        tcp_config: ConfigTcp,
+        connection_filter: Arc<ConnectionFilterSlot>,
+        proxy_protocol: Option<ProxyProtocol>,
     ) -> HttpsAcceptor {
         HttpsAcceptor {
             stream: Box::new(Box::pin(Self::new_stream(
                 tls_acceptor,
                 tcp_listener,
+                tcp_config,
+                connection_filter,
+                proxy_protocol,
             ))),
         }
     }
@@ -397,6 +1496,9 @@ impl HttpsAcceptor {
     fn new_stream(
         tls_acceptor: Arc<Mutex<TlsAcceptor>>,
         tcp_listener: TcpListener,
+        tcp_config: ConfigTcp,
+        connection_filter: Arc<ConnectionFilterSlot>,
+        proxy_protocol: Option<ProxyProtocol>,
     ) -> impl Stream<Item = std::io::Result<TlsConn>> {
         stream! {
             let mut tls_negotiations = futures::stream::FuturesUnordered::new();
@@ -406,7 +1508,9 @@ impl HttpsAcceptor {
                             !tls_negotiations.is_empty() => {
 
                         match negotiation {
-                            Ok(conn) => yield Ok(conn),
+                            Ok(Some(conn)) => yield Ok(conn),
+                            // The connection filter rejected it.
+                            Ok(None) => (),
                             Err(e) => {
                                 // If TLS negotiation fails, log the cause but
                                 // don't forward it along. Yielding an error
@@ -445,11 +1549,36 @@ impl HttpsAcceptor {
                             }
                         };
 
-                        let tls_negotiation = tls_acceptor
-                            .lock()
-                            .await
-                            .accept(socket)
-                            .map_ok(move |stream| TlsConn::new(stream, addr));
+                        if let Err(error) =
+                            configure_tcp_stream(&socket, &tcp_config)
+                        {
+                            warn!(
+                                remote_addr = %addr,
+                                %error,
+                                "failed to set TCP options"
+                            );
+                        }
+                        let tls_acceptor = Arc::clone(&tls_acceptor);
+                        let connection_filter = Arc::clone(&connection_filter);
+                        let proxy_protocol = proxy_protocol.clone();
+                        let tls_negotiation = async move {
+                            let stream = match &proxy_protocol {
+                                Some(proxy_protocol) => {
+                                    proxy_protocol.accept(socket, addr).await?
+                                }
+                                None => ProxiedStream::direct(socket),
+                            };
+                            let addr = stream.source().unwrap_or(addr);
+                            let Some(admitted) = connection_filter.admit(addr)
+                            else {
+                                return Ok(None);
+                            };
+                            let tls_acceptor = tls_acceptor.lock().await.clone();
+                            let stream = tls_acceptor.accept(stream).await?;
+                            Ok::<_, std::io::Error>(Some(TlsConn::new(
+                                stream, addr, admitted,
+                            )))
+                        };
                         tls_negotiations.push(tls_negotiation);
                     },
                     else => break,
@@ -473,7 +1602,11 @@ impl hyper::server::accept::Accept for HttpsAcceptor {
 }
 
 struct InnerHttpsServerStarter<C: ServerContext>(
-    Server<HttpsAcceptor, ServerConnectionHandler<C>>,
+    Server<
+        ConnectionAcceptor<HttpsAcceptor>,
+        ServerConnectionHandler<C>,
+        ConnectionExecutor,
+    >,
 );
 
 /// Create a TLS configuration from the Dropshot config structure.
@@ -481,6 +1614,22 @@ impl TryFrom<&ConfigTls> for rustls::ServerConfig {
     type Error = std::io::Error;
 
     fn try_from(config: &ConfigTls) -> std::io::Result<Self> {
+        let (config, client_verifier) = match config {
+            ConfigTls::WithClientAuth { server, client_auth } => {
+                if let ConfigTls::Dynamic(_)
+                | ConfigTls::WithClientAuth { .. } = **server
+                {
+                    return Err(io_error(
+                        "client authentication can only be added to a TLS \
+                         configuration from a certificate and key"
+                            .into(),
+                    ));
+                }
+                (server.as_ref(), client_cert_verifier(client_auth)?)
+            }
+            config => (config, WebPkiClientVerifier::no_client_auth()),
+        };
+
         let (mut cert_reader, mut key_reader): (
             Box<dyn std::io::BufRead>,
             Box<dyn std::io::BufRead>,
@@ -488,6 +1637,7 @@ impl TryFrom<&ConfigTls> for rustls::ServerConfig {
             ConfigTls::Dynamic(raw) => {
                 return Ok(raw.clone());
             }
+            ConfigTls::WithClientAuth { .. } => unreachable!(),
             ConfigTls::AsBytes { certs, key } => (
                 Box::new(std::io::BufReader::new(certs.as_slice())),
                 Box::new(std::io::BufReader::new(key.as_slice())),
@@ -538,7 +1688,7 @@ impl TryFrom<&ConfigTls> for rustls::ServerConfig {
         };
 
         let mut cfg = rustls::ServerConfig::builder()
-            .with_no_client_auth()
+            .with_client_cert_verifier(client_verifier)
             .with_single_cert(certs, private_key.into())
             .expect("bad certificate/key");
         cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
@@ -551,22 +1701,19 @@ type InnerHttpsServerStarterNewReturn<C> =
 
 impl<C: ServerContext> InnerHttpsServerStarter<C> {
     /// Begins execution of the underlying Http server.
-    fn start(
+    fn start<F>(
         self,
-        close_signal: tokio::sync::oneshot::Receiver<()>,
-    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>> {
-        let graceful = self.0.with_graceful_shutdown(async move {
-            close_signal.await.expect(
-                "dropshot server shutting down without invoking close()",
-            );
-            info!("received request to begin graceful shutdown");
-        });
-
+        close_signal: F,
+    ) -> tokio::task::JoinHandle<Result<(), hyper::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let graceful = self.0.with_graceful_shutdown(close_signal);
         tokio::spawn(graceful)
     }
 
     fn new(
-        config: &ConfigDropshot,
+        tcp: std::net::TcpListener,
         server_config: ServerConfig,
         api: ApiDescription<C>,
         middleware: Option<Arc<dyn Middleware<C>>>,
@@ -578,53 +1725,225 @@ impl<C: ServerContext> InnerHttpsServerStarter<C> {
             rustls::ServerConfig::try_from(tls)?,
         ))));
 
-        let tcp = {
-            let listener = std::net::TcpListener::bind(&config.bind_address)?;
-            listener.set_nonblocking(true)?;
-            // We use `from_std` instead of just calling `bind` here directly
-            // to avoid invoking an async function, to match the interface
-            // provided by `HttpServerStarter::new`.
-            TcpListener::from_std(listener)?
-        };
-
+        let tcp = into_tokio_listener(tcp)?;
         let local_addr = tcp.local_addr()?;
 
-        let https_acceptor = HttpsAcceptor::new(acceptor.clone(), tcp);
-
-        let app_state = Arc::new(DropshotState {
+        let app_state = Arc::new(DropshotState::new(
             private,
-            config: server_config,
-            router: api.into_router(),
+            server_config,
+            api,
             middleware,
             local_addr,
-            tls_acceptor: Some(acceptor),
-            handler_waitgroup_worker: DebugIgnore(handler_waitgroup_worker),
-        });
+            Some(Arc::clone(&acceptor)),
+            Some(handler_waitgroup_worker),
+        ));
 
+        let https_acceptor = HttpsAcceptor::new(
+            acceptor,
+            tcp,
+            app_state.config.tcp.clone(),
+            Arc::clone(&app_state.connection_filter),
+            ProxyProtocol::new(&app_state.config),
+        );
         let make_service = ServerConnectionHandler::new(Arc::clone(&app_state));
-        let server = Server::builder(https_acceptor).serve(make_service);
+        let server = server_builder(https_acceptor, &app_state, None)
+            .serve(make_service);
 
         Ok((InnerHttpsServerStarter(server), app_state, local_addr))
     }
+
+    /// Set up an HTTPS server accepting connections on the specified listener
+    /// for an existing server's state.  This is used for listeners beyond the
+    /// first.
+    fn with_state(
+        tcp: std::net::TcpListener,
+        tls: &ConfigTls,
+        app_state: Arc<DropshotState<C>>,
+    ) -> Result<(InnerHttpsServerStarter<C>, SocketAddr), GenericError> {
+        let acceptor = Arc::new(Mutex::new(TlsAcceptor::from(Arc::new(
+            rustls::ServerConfig::try_from(tls)?,
+        ))));
+        let tcp = into_tokio_listener(tcp)?;
+        let local_addr = tcp.local_addr()?;
+        let https_acceptor = HttpsAcceptor::new(
+            acceptor,
+            tcp,
+            app_state.config.tcp.clone(),
+            Arc::clone(&app_state.connection_filter),
+            ProxyProtocol::new(&app_state.config),
+        );
+        let builder = server_builder(https_acceptor, &app_state, None);
+        let server = builder.serve(ServerConnectionHandler::new(app_state));
+        Ok((InnerHttpsServerStarter(server), local_addr))
+    }
 }
 
-impl<C: ServerContext> Service<&TlsConn> for ServerConnectionHandler<C> {
-    type Response = ServerRequestHandler<C>;
-    type Error = GenericError;
-    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+/// Returns a builder for a hyper server that accepts connections from
+/// `acceptor` (subject to `filter`, for acceptors that don't apply it
+/// themselves) and serves them according to the server's configuration
+fn server_builder<A, C: ServerContext>(
+    acceptor: A,
+    app_state: &DropshotState<C>,
+    filter: Option<Arc<ConnectionFilterSlot>>,
+) -> hyper::server::Builder<ConnectionAcceptor<A>, ConnectionExecutor> {
+    let mut builder =
+        Server::builder(ConnectionAcceptor::new(acceptor, app_state, filter))
+            .executor(ConnectionExecutor::new(app_state));
+    if let Some(max) = app_state.config.max_request_header_bytes {
+        // hyper's limits are generous enough that requests just over ours
+        // still make it to `request_size_error()`, which produces a proper
+        // error response.
+        let uri_max = app_state.config.max_uri_length.unwrap_or(max);
+        builder = builder
+            .http1_max_buf_size(std::cmp::max(
+                HYPER_MIN_BUF_SIZE,
+                2 * (max + uri_max),
+            ))
+            .http2_max_header_list_size(
+                u32::try_from(2 * max).unwrap_or(u32::MAX),
+            );
+    }
+    builder
+}
 
-    fn poll_ready(
-        &mut self,
-        _ctx: &mut Context<'_>,
-    ) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+/// Smallest buffer size that hyper accepts for reading HTTP/1 requests
+const HYPER_MIN_BUF_SIZE: usize = 8192;
+
+/// Spawns the tasks that hyper uses to serve each connection, such that they
+/// can all be stopped if graceful shutdown times out
+///
+/// Stopping the task that accepts connections doesn't affect these, so without
+/// this, a connection with a request that never completes would stay open
+/// forever.
+#[derive(Clone)]
+struct ConnectionExecutor {
+    aborting: tokio::sync::watch::Receiver<bool>,
+}
+
+impl ConnectionExecutor {
+    fn new<C: ServerContext>(app_state: &DropshotState<C>) -> Self {
+        ConnectionExecutor { aborting: app_state.aborting.subscribe() }
     }
+}
 
-    fn call(&mut self, conn: &TlsConn) -> Self::Future {
-        let server = Arc::clone(&self.server);
-        let remote_addr = conn.remote_addr();
-        Box::pin(http_connection_handle(server, remote_addr))
+impl<F> hyper::rt::Executor<F> for ConnectionExecutor
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn execute(&self, fut: F) {
+        let mut aborting = self.aborting.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = fut => (),
+                // Dropping the connection's future closes it.  If the sender
+                // goes away first, the server is gone without having timed
+                // out, so the connection is left alone.
+                Ok(_) = aborting.wait_for(|a| *a) => (),
+            }
+        });
+    }
+}
+
+fn bind_tcp(
+    bind_address: &SocketAddr,
+    config: &ConfigTcp,
+) -> std::io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(*bind_address),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    // This is what `std::net::TcpListener::bind` does, so that the server can
+    // be restarted while connections from its predecessor linger.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if config.reuse_port {
+        #[cfg(all(
+            unix,
+            not(any(target_os = "illumos", target_os = "solaris"))
+        ))]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(all(
+            unix,
+            not(any(target_os = "illumos", target_os = "solaris"))
+        )))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not supported on this platform",
+        ));
     }
+    socket.bind(&(*bind_address).into())?;
+    let backlog = config.backlog.unwrap_or(ConfigTcp::DEFAULT_BACKLOG);
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+/// Applies the options in `config` to connections accepted by `incoming`
+fn configure_incoming(incoming: &mut AddrIncoming, config: &ConfigTcp) {
+    incoming.set_nodelay(config.nodelay);
+    if let Some(time) = config.keepalive_time_secs {
+        incoming.set_keepalive(Some(Duration::from_secs(time)));
+        incoming.set_keepalive_interval(
+            config.keepalive_interval_secs.map(Duration::from_secs),
+        );
+        incoming.set_keepalive_retries(config.keepalive_retries);
+    }
+}
+
+/// Applies the options in `config` to a newly accepted connection
+///
+/// This is the equivalent of [`configure_incoming`] for listeners that
+/// don't use `AddrIncoming`.
+fn configure_tcp_stream(
+    stream: &TcpStream,
+    config: &ConfigTcp,
+) -> std::io::Result<()> {
+    if config.nodelay {
+        stream.set_nodelay(true)?;
+    }
+    let Some(time) = config.keepalive_time_secs else {
+        return Ok(());
+    };
+    let keepalive =
+        socket2::TcpKeepalive::new().with_time(Duration::from_secs(time));
+    // As with `AddrIncoming`, these are ignored where they're unsupported.
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "illumos",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "windows",
+    ))]
+    let keepalive = match config.keepalive_interval_secs {
+        Some(interval) => {
+            keepalive.with_interval(Duration::from_secs(interval))
+        }
+        None => keepalive,
+    };
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "illumos",
+        target_os = "linux",
+        target_os = "macos",
+    ))]
+    let keepalive = match config.keepalive_retries {
+        Some(retries) => keepalive.with_retries(retries),
+        None => keepalive,
+    };
+    socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+fn into_tokio_listener(
+    listener: std::net::TcpListener,
+) -> std::io::Result<TcpListener> {
+    listener.set_nonblocking(true)?;
+    // We use `from_std` instead of just calling `bind` here directly to avoid
+    // invoking an async function, to match the interface provided by
+    // `HttpServerStarter::new`.
+    TcpListener::from_std(listener)
 }
 
 type SharedBoxFuture<T> = Shared<Pin<Box<dyn Future<Output = T> + Send>>>;
@@ -652,8 +1971,13 @@ impl FusedFuture for ShutdownWaitFuture {
 /// - C: Caller-supplied server context
 pub struct HttpServer<C: ServerContext> {
     probe_registration: ProbeRegistration,
+    #[cfg(feature = "server-registry")]
+    _registration: crate::registry::Registration,
     app_state: Arc<DropshotState<C>>,
     local_addr: SocketAddr,
+    local_addrs: Vec<SocketAddr>,
+    /// copies of the TCP listeners, until graceful shutdown begins
+    tcp_listeners: Arc<std::sync::Mutex<Vec<std::net::TcpListener>>>,
     closer: CloseHandle,
     join_future: SharedBoxFuture<Result<(), String>>,
 }
@@ -668,10 +1992,137 @@ impl<C: ServerContext> HttpServer<C> {
         self.local_addr
     }
 
+    /// Returns the addresses of all of the server's listeners, starting with
+    /// the one reported by [`HttpServer::local_addr`]
+    ///
+    /// See [`HttpServerStarter::listen`].
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns new handles for the server's TCP listening sockets (in the
+    /// order in which the listeners were added), for handing off to another
+    /// process
+    ///
+    /// This supports restarting a server (e.g., to deploy a new version)
+    /// without refusing any connections: the new process is given the
+    /// listening sockets (see [`crate::pass_listeners`]) and starts accepting
+    /// connections on them with [`HttpServerStarter::new_with_listener`],
+    /// after which this server can be shut down gracefully with
+    /// [`HttpServer::close`].  Connections are accepted by both processes in
+    /// the meantime.
+    ///
+    /// Unix domain sockets and HTTP/3 listeners are not included.  It's an
+    /// error to call this once graceful shutdown has begun, since the server
+    /// no longer has its listening sockets open at that point.
+    pub fn try_clone_listeners(
+        &self,
+    ) -> std::io::Result<Vec<std::net::TcpListener>> {
+        if *self.app_state.phase.borrow() == ServerPhase::ShuttingDown {
+            return Err(io_error(
+                "server has stopped accepting connections".to_string(),
+            ));
+        }
+        let tcp_listeners = self.tcp_listeners.lock().unwrap();
+        tcp_listeners.iter().map(|l| l.try_clone()).collect()
+    }
+
     pub fn app_private(&self) -> &C {
         &self.app_state.private
     }
 
+    /// Returns the number of connections currently open
+    pub fn connection_count(&self) -> usize {
+        self.app_state.connection_count()
+    }
+
+    /// Returns the number of requests currently being handled
+    pub fn request_in_flight_count(&self) -> usize {
+        self.app_state.request_in_flight_count()
+    }
+
+    /// Returns how long it's been since the server was created
+    pub fn uptime(&self) -> Duration {
+        self.app_state.uptime()
+    }
+
+    /// Describes the requests handled by each of the server's endpoints
+    ///
+    /// See [`DropshotState::route_stats`].
+    pub fn route_stats(&self) -> Vec<RouteStats> {
+        self.app_state.route_stats()
+    }
+
+    /// Sets whether the server rejects new requests with a 503 ("Service
+    /// Unavailable") response
+    ///
+    /// See [`DropshotState::set_quiescing`].
+    pub fn set_quiescing(&self, quiescing: bool) {
+        self.app_state.set_quiescing(quiescing)
+    }
+
+    /// Returns whether the server is rejecting new requests (see
+    /// [`HttpServer::set_quiescing`])
+    pub fn is_quiescing(&self) -> bool {
+        self.app_state.is_quiescing()
+    }
+
+    /// Sets the versions of the API that the server accepts requests for
+    ///
+    /// See [`DropshotState::set_supported_versions`].
+    pub fn set_supported_versions(&self, versions: ApiEndpointVersions) {
+        self.app_state.set_supported_versions(versions)
+    }
+
+    /// Returns the versions of the API that the server accepts requests for
+    /// (see [`HttpServer::set_supported_versions`])
+    pub fn supported_versions(&self) -> ApiEndpointVersions {
+        self.app_state.supported_versions()
+    }
+
+    /// Changes the registry that websocket handlers register their
+    /// connections with
+    ///
+    /// See [`DropshotState::set_websocket_registry`].
+    pub fn set_websocket_registry(
+        &self,
+        registry: Option<Arc<WebsocketRegistry>>,
+    ) {
+        self.app_state.set_websocket_registry(registry)
+    }
+
+    /// Handles `request` within this process, just as though it had been
+    /// received on one of the server's listeners
+    ///
+    /// The request goes through the server's middleware, router, and
+    /// handler, and it counts toward the server's limits and statistics, but
+    /// there's no connection involved: the request is given to the handler
+    /// as-is, and the response is returned without being serialized.  This
+    /// makes for fast tests, and for cheap calls from a service to itself.
+    /// Handlers see the request as coming from the server's own address
+    /// ([`HttpServer::local_addr`]).
+    ///
+    /// To handle requests for an API without running a server at all, see
+    /// [`crate::ApiService`].
+    pub async fn handle_request(
+        &self,
+        request: Request<Body>,
+    ) -> Response<Body> {
+        let result = http_request_handle_wrap(
+            Arc::clone(&self.app_state),
+            self.local_addr,
+            request,
+        )
+        .await;
+        match result {
+            Ok(response) => response,
+            // Failures are normally turned into error responses by now, but
+            // any other failure is reported like an unexpected error.
+            Err(error) => HttpError::for_internal_error(error.to_string())
+                .into_response(&generate_request_id()),
+        }
+    }
+
     pub fn using_tls(&self) -> bool {
         self.app_state.using_tls()
     }
@@ -709,22 +2160,114 @@ impl<C: ServerContext> HttpServer<C> {
     }
 
     /// Signals the currently running server to stop and waits for it to exit.
-    pub async fn close(mut self) -> Result<(), String> {
-        self.closer
+    ///
+    /// If [`ConfigDropshot::shutdown_timeout_ms`] is set, this behaves like
+    /// [`HttpServer::close_with_timeout`] with that timeout.  Otherwise, it
+    /// waits for as long as it takes for all requests to complete.
+    pub async fn close(self) -> Result<(), String> {
+        let timeout = self.app_state.config.shutdown_timeout;
+        self.close_inner(timeout).await
+    }
+
+    /// Signals the currently running server to stop and waits up to `timeout`
+    /// for it to exit
+    ///
+    /// As with [`HttpServer::close`], the server stops accepting connections
+    /// (after any lame-duck period), asks clients to close the connections
+    /// they have, and waits for requests that are already in progress to
+    /// complete, periodically logging how many remain.  If they haven't all
+    /// completed once `timeout` has elapsed (not counting the lame-duck
+    /// period), the server's remaining connections are closed and this
+    /// returns an error.  Closing the connections cancels the handlers of
+    /// the requests on them in [`HandlerTaskMode::CancelOnDisconnect`] mode.
+    /// In [`HandlerTaskMode::Detached`] mode, the handlers keep running, but
+    /// this no longer waits for them.
+    pub async fn close_with_timeout(
+        self,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        self.close_inner(Some(timeout)).await
+    }
+
+    async fn close_inner(
+        mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(), String> {
+        // If the server has a shutdown signal that's already completed, the
+        // server is already shutting down and the receiver may be gone.
+        let _ = self
+            .closer
             .close_channel
             .take()
             .expect("cannot close twice")
-            .send(())
-            .expect("failed to send close signal");
+            .send(());
+
+        let monitor = ShutdownMonitor::new(&self.app_state);
+        monitor.wait(self.join_future.clone(), timeout).await
+    }
+}
+
+/// Tracks graceful shutdown of a server, enforcing the shutdown timeout
+struct ShutdownMonitor {
+    phase: tokio::sync::watch::Receiver<ServerPhase>,
+    aborting: Arc<tokio::sync::watch::Sender<bool>>,
+    requests_in_flight: Arc<AtomicUsize>,
+}
 
-        // We _must_ explicitly drop our app state before awaiting join_future.
-        // If we are running handlers in `Detached` mode, our `app_state` has a
-        // `waitgroup::Worker` that they all clone, and `join_future` will await
-        // all of them being dropped. That means we must drop our "primary"
-        // clone of it, too!
-        mem::drop(self.app_state);
+impl ShutdownMonitor {
+    fn new<C: ServerContext>(app_state: &DropshotState<C>) -> Self {
+        ShutdownMonitor {
+            phase: app_state.phase.subscribe(),
+            aborting: Arc::clone(&app_state.aborting),
+            requests_in_flight: Arc::clone(&app_state.requests_in_flight),
+        }
+    }
+
+    /// Waits for the server to finish shutting down, closing its remaining
+    /// connections if that takes longer than `timeout`
+    async fn wait(
+        mut self,
+        mut join_future: SharedBoxFuture<Result<(), String>>,
+        timeout: Option<Duration>,
+    ) -> Result<(), String> {
+        let Some(timeout) = timeout else {
+            return join_future.await;
+        };
+
+        // The clock starts once the lame-duck period (if any) is over.
+        let waiting = async {
+            let _ =
+                self.phase.wait_for(|p| *p == ServerPhase::ShuttingDown).await;
+            tokio::time::sleep(timeout).await;
+        };
+        tokio::pin!(waiting);
+        let mut progress = tokio::time::interval(SHUTDOWN_PROGRESS_INTERVAL);
+        progress.tick().await;
+        loop {
+            tokio::select! {
+                result = &mut join_future => return result,
+                _ = &mut waiting => break,
+                _ = progress.tick() => {
+                    info!(
+                        requests_in_flight =
+                            self.requests_in_flight.load(Ordering::SeqCst),
+                        "waiting for requests to complete before shutdown"
+                    );
+                }
+            }
+        }
 
-        self.join_future.await
+        let remaining = self.requests_in_flight.load(Ordering::SeqCst);
+        warn!(
+            timeout = ?timeout,
+            requests_in_flight = remaining,
+            "graceful shutdown timed out; closing remaining connections"
+        );
+        self.aborting.send_replace(true);
+        Err(format!(
+            "graceful shutdown timed out with {} requests in flight",
+            remaining
+        ))
     }
 }
 
@@ -766,17 +2309,31 @@ impl<C: ServerContext> FusedFuture for HttpServer<C> {
 /// connection.
 async fn http_connection_handle<C: ServerContext>(
     server: Arc<DropshotState<C>>,
+    connection: Arc<ConnectionState>,
     remote_addr: SocketAddr,
+    peer_credentials: Option<PeerCredentials>,
+    client_certificate: Option<ClientCertificate>,
 ) -> Result<ServerRequestHandler<C>, GenericError> {
-    trace!(remote_addr = %remote_addr, "accepted connection");
-    Ok(ServerRequestHandler::new(server, remote_addr))
+    trace!(
+        remote_addr = %remote_addr,
+        peer_credentials = ?peer_credentials,
+        client_certificate = client_certificate.is_some(),
+        "accepted connection"
+    );
+    Ok(ServerRequestHandler::new(
+        server,
+        connection,
+        remote_addr,
+        peer_credentials,
+        client_certificate,
+    ))
 }
 
 /// Initial entry point for handling a new request to the HTTP server.  This is
 /// invoked by Hyper when a new request is received.  This function returns a
 /// Result that either represents a valid HTTP response or an error (which will
 /// also get turned into an HTTP response).
-async fn http_request_handle_wrap<C: ServerContext>(
+pub(crate) async fn http_request_handle_wrap<C: ServerContext>(
     server: Arc<DropshotState<C>>,
     remote_addr: SocketAddr,
     request: Request<Body>,
@@ -785,9 +2342,58 @@ async fn http_request_handle_wrap<C: ServerContext>(
     // straightforward, since the request handling code can simply return early
     // with an error and we'll treat it like an error from any of the endpoints
     // themselves.
+    let mut request = match server.mounts.route(request) {
+        Route::Mounted(service, request) => {
+            return service.handle(remote_addr, request).await;
+        }
+        Route::Local(request) => request,
+    };
+
     let request_id = generate_request_id();
+    let http_version = request.version();
+    server.requests_total.fetch_add(1, Ordering::SeqCst);
+    let requests_in_flight =
+        server.requests_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    let _in_flight = guard(&server.requests_in_flight, |n| {
+        n.fetch_sub(1, Ordering::SeqCst);
+    });
+    let overload = server
+        .config
+        .max_concurrent_requests
+        .filter(|max| requests_in_flight > *max)
+        .map(|max| format!("too many concurrent requests (max {})", max));
+    let accept = if server.config.error_content_negotiation {
+        request.headers().get(http::header::ACCEPT).cloned()
+    } else {
+        None
+    };
+    let problem_instance = match server.config.error_response_format {
+        ErrorResponseFormat::Dropshot => None,
+        ErrorResponseFormat::ProblemDetails => {
+            Some(request.uri().path().to_string())
+        }
+    };
+    let origin = request.headers().get(http::header::ORIGIN).cloned();
+    let client_ip = resolve_client_ip(
+        remote_addr.ip(),
+        request.headers(),
+        &server.config.trusted_proxies,
+    );
+    // This is needed again once the request reaches its handler, which may be
+    // by way of the middleware.
+    request.extensions_mut().insert(ClientIp(client_ip));
+    let error_request_info = server.error_mapper.is_some().then(|| {
+        RequestInfo::new(&request, remote_addr).with_client_ip(client_ip)
+    });
 
-    trace!("incoming request");
+    trace!(
+        request_id = %request_id,
+        remote_addr = %remote_addr,
+        client_ip = %client_ip,
+        method = %request.method(),
+        uri = %request.uri(),
+        "incoming request"
+    );
     #[cfg(feature = "usdt-probes")]
     probes::request__start!(|| {
         let uri = request.uri();
@@ -826,7 +2432,44 @@ async fn http_request_handle_wrap<C: ServerContext>(
         });
     });
 
-    let maybe_response = if let Some(middleware) = &server.middleware {
+    let quiescing = server.is_quiescing();
+    let size_error = request_size_error(&server.config, &request);
+    let maybe_response = if let Some(message) = overload {
+        server.requests_shed.fetch_add(1, Ordering::SeqCst);
+        warn!(
+            request_id = %request_id,
+            remote_addr = %remote_addr,
+            reason = %message,
+            "server overloaded; rejecting request"
+        );
+        Err(HttpError::for_unavail(None, message))
+    } else if quiescing {
+        trace!(
+            request_id = %request_id,
+            remote_addr = %remote_addr,
+            "server quiescing; rejecting request"
+        );
+        Err(HttpError::for_unavail(None, String::from("server is quiescing")))
+    } else if let Some(error) = size_error {
+        warn!(
+            request_id = %request_id,
+            remote_addr = %remote_addr,
+            reason = %error.internal_message,
+            "rejecting oversized request"
+        );
+        Err(error)
+    } else if let Some(mut response) =
+        cors::preflight_response(&server.config.cors, &server.router, &request)
+    {
+        // CORS preflight requests are answered here, without involving the
+        // middleware (which might otherwise reject them for lacking
+        // credentials, since browsers never send any with a preflight).
+        response.headers_mut().insert(
+            HEADER_REQUEST_ID,
+            http::header::HeaderValue::from_str(&request_id).unwrap(),
+        );
+        Ok(response)
+    } else if let Some(middleware) = &server.middleware {
         middleware
             .handle(
                 server.clone(),
@@ -852,17 +2495,49 @@ async fn http_request_handle_wrap<C: ServerContext>(
             )
             .await
     } else {
-        http_request_handle(server, request, request_id.clone(), remote_addr)
-            .await
+        http_request_handle(
+            Arc::clone(&server),
+            request,
+            request_id.clone(),
+            remote_addr,
+        )
+        .await
     };
 
     // If `http_request_handle` completed, it means the request wasn't
     // cancelled and we can safely "defuse" the scopeguard.
     let _ = ScopeGuard::into_inner(on_disconnect);
 
-    let response = match maybe_response {
+    let mut response = match maybe_response {
         Err(error) => {
-            let r = error.into_response(&request_id);
+            let format = server.config.error_response_format;
+            let instance = problem_instance.as_deref();
+            let r = if let (Some(mapper), Some(request_info)) =
+                (&*server.error_mapper, &error_request_info)
+            {
+                let mut r = mapper(error, request_info);
+                r.headers_mut().insert(
+                    HEADER_REQUEST_ID,
+                    http::header::HeaderValue::from_str(&request_id).unwrap(),
+                );
+                r
+            } else if server.config.error_content_negotiation {
+                error.into_negotiated_response_with(
+                    &request_id,
+                    accept.as_ref(),
+                    format,
+                    instance,
+                )
+            } else {
+                match format {
+                    ErrorResponseFormat::Dropshot => {
+                        error.into_response(&request_id)
+                    }
+                    ErrorResponseFormat::ProblemDetails => {
+                        error.into_problem_response(&request_id, instance)
+                    }
+                }
+            };
 
             #[cfg(feature = "usdt-probes")]
             probes::request__done!(|| {
@@ -894,12 +2569,39 @@ async fn http_request_handle_wrap<C: ServerContext>(
         }
     };
 
+    cors::add_response_headers(
+        &server.config.cors,
+        origin.as_ref(),
+        response.headers_mut(),
+    );
+
+    // hyper fills in the Date header from the system clock unless the
+    // response already has one.
+    if server.clock.read().unwrap().is_some()
+        && !response.headers().contains_key(http::header::DATE)
+    {
+        response
+            .headers_mut()
+            .insert(http::header::DATE, http_date(server.now()));
+    }
+
+    streamed_body::spawn_streamed_body(&mut response);
+
+    // During shutdown, ask HTTP/1 clients not to reuse this connection.  (For
+    // HTTP/2, hyper sends GOAWAY once graceful shutdown begins.)
+    if server.is_draining() && http_version < http::Version::HTTP_2 {
+        response.headers_mut().insert(
+            http::header::CONNECTION,
+            http::header::HeaderValue::from_static("close"),
+        );
+    }
+
     Ok(response)
 }
 
 async fn http_request_handle<C: ServerContext>(
     server: Arc<DropshotState<C>>,
-    request: Request<Body>,
+    mut request: Request<Body>,
     request_id: String,
     remote_addr: std::net::SocketAddr,
 ) -> Result<Response<Body>, HttpError> {
@@ -909,16 +2611,132 @@ async fn http_request_handle<C: ServerContext>(
     // TODO-hardening: add a request read timeout as well so that we don't allow
     // this to take forever.
     // TODO-correctness: Do we need to dump the body on errors?
-    let method = request.method();
-    let uri = request.uri();
-    let lookup_result =
-        server.router.lookup_route(&method, uri.path().into())?;
+    // Phases of handling the request are only timed if the response is going
+    // to report how long they took.
+    let want_timing = server
+        .config
+        .server_timing
+        .wants_timing(request.headers(), &server.server_timing_nrequests);
+    let route_start = want_timing.then(Instant::now);
+    let (version, lookup_result) = server.route_request(&mut request)?;
+    let matching_version = version
+        .as_ref()
+        .map(|version| server.prerelease_matching.matching_version(version));
+    let route_time = route_start.map(|start| start.elapsed());
+    let route = server.route_stats.begin(
+        request.method(),
+        &lookup_result.path,
+        &lookup_result.versions,
+    );
+    if let (Some(observer), Some(version)) =
+        (&*server.version_observer, &version)
+    {
+        observer(&lookup_result.operation_id, version, &lookup_result.versions);
+    }
+    let deprecated_version =
+        version.clone().filter(|_| lookup_result.deprecated);
+    if let Some(version) = &deprecated_version {
+        warn!(
+            request_id = %request_id,
+            operation_id = %lookup_result.operation_id,
+            api_version = %version,
+            "request for deprecated API version"
+        );
+    }
+    let mut result = http_request_run(
+        Arc::clone(&server),
+        request,
+        request_id,
+        remote_addr,
+        lookup_result,
+        matching_version,
+        route_time,
+    )
+    .await;
+    if let (Some(version), Ok(response)) = (&deprecated_version, &mut result) {
+        add_deprecation_headers(response.headers_mut(), version);
+    }
+    route.end(match &result {
+        Ok(response) => response.status(),
+        Err(error) => error.status_code,
+    });
+    result
+}
+
+/// Adds headers to a response telling the client that `version` of the
+/// endpoint it called is deprecated
+fn add_deprecation_headers(
+    headers: &mut http::HeaderMap,
+    version: &semver::Version,
+) {
+    headers.insert(
+        http::header::HeaderName::from_static("deprecation"),
+        http::header::HeaderValue::from_static("true"),
+    );
+    let warning = format!(
+        "299 - \"API version {} of this endpoint is deprecated\"",
+        version
+    );
+    // A semver only contains characters that are valid in a header.
+    headers.insert(
+        http::header::WARNING,
+        http::header::HeaderValue::from_str(&warning).unwrap(),
+    );
+}
+
+/// Client address resolved when a request arrives (see
+/// [`resolve_client_ip`]), carried in the request's extensions
+#[derive(Clone, Copy)]
+struct ClientIp(std::net::IpAddr);
+
+/// Handles a request that's been routed to the endpoint described by
+/// `lookup_result`
+async fn http_request_run<C: ServerContext>(
+    server: Arc<DropshotState<C>>,
+    request: Request<Body>,
+    request_id: String,
+    remote_addr: std::net::SocketAddr,
+    lookup_result: RouterLookupResult<C>,
+    api_version: Option<semver::Version>,
+    route_time: Option<Duration>,
+) -> Result<Response<Body>, HttpError> {
+    let deadline = Deadline::from_headers(
+        request.headers(),
+        server.config.request_timeout_max,
+    );
+    let preferences = Preferences::from_headers(request.headers());
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map_or_else(|| remote_addr.ip(), |client_ip| client_ip.0);
+    let request_body_max_bytes = lookup_result
+        .request_body_max_bytes
+        .unwrap_or(server.config.request_body_max_bytes);
+    let request = match &*server.schema_validator {
+        Some(validator) => {
+            validator
+                .check_request(
+                    &lookup_result.operation_id,
+                    request,
+                    request_body_max_bytes,
+                )
+                .await?
+        }
+        None => request,
+    };
     let rqctx = RequestContext {
         server: Arc::clone(&server),
-        request: RequestInfo::new(&request, remote_addr),
+        request: RequestInfo::new(&request, remote_addr)
+            .with_client_ip(client_ip),
         path_variables: lookup_result.variables,
         body_content_type: lookup_result.body_content_type,
+        request_body_max_bytes,
+        page_limits: lookup_result.page_limits,
+        deadline,
+        preferences,
         request_id: request_id.clone(),
+        api_version,
+        server_timing: route_time.is_some(),
     };
     let handler = lookup_result.handler;
 
@@ -926,14 +2744,31 @@ async fn http_request_handle<C: ServerContext>(
         HandlerTaskMode::CancelOnDisconnect => {
             // For CancelOnDisconnect, we run the request handler directly: if
             // the client disconnects, we will be cancelled, and therefore this
-            // future will too.
-            handler.handle_request(rqctx, request).await?
+            // future will too.  The same goes for the client's deadline, if
+            // it gave one.
+            let handler_future = handler.handle_request(rqctx, request);
+            if server.error_mapper.is_some() {
+                // With an error mapper, a panic is reported like any other
+                // error (see `ApiDescription::map_error`).
+                match deadline
+                    .bound(
+                        panic::AssertUnwindSafe(handler_future).catch_unwind(),
+                    )
+                    .await?
+                {
+                    Ok(result) => result?,
+                    Err(panic) => return Err(handler_panic_error(panic)),
+                }
+            } else {
+                deadline.bound(handler_future).await??
+            }
         }
         HandlerTaskMode::Detached => {
             // Spawn the handler so if we're cancelled, the handler still runs
             // to completion.
             let (tx, rx) = oneshot::channel();
-            let worker = server.handler_waitgroup_worker.clone();
+            let worker =
+                server.handler_waitgroup_worker.lock().unwrap().clone();
             let handler_task = tokio::spawn(async move {
                 let result = handler.handle_request(rqctx, request).await;
 
@@ -965,7 +2800,10 @@ async fn http_request_handle<C: ServerContext>(
             // `handle_request` panics. We will propogate such a panic here,
             // just as we would have in `CancelOnDisconnect` mode above (where
             // we call the handler directly).
-            match rx.await {
+            //
+            // If the client's deadline passes first, we stop waiting, but the
+            // handler still runs to completion as above.
+            match deadline.bound(rx).await? {
                 Ok(result) => result?,
                 Err(_) => {
                     error!("handler panicked; propogating panic");
@@ -977,18 +2815,89 @@ async fn http_request_handle<C: ServerContext>(
                     let task_err = handler_task.await.expect_err(
                         "task failed to send result but didn't panic",
                     );
+                    if server.error_mapper.is_some() && task_err.is_panic() {
+                        return Err(handler_panic_error(task_err.into_panic()));
+                    }
                     panic::resume_unwind(task_err.into_panic());
                 }
             }
         }
     };
+    if let Some(validator) = &*server.schema_validator {
+        response = validator
+            .check_response(&lookup_result.operation_id, response)
+            .await?;
+    }
     response.headers_mut().insert(
         HEADER_REQUEST_ID,
         http::header::HeaderValue::from_str(&request_id).unwrap(),
     );
+    if let Some(route_time) = route_time {
+        let timing = ServerTiming::of(&mut response);
+        timing.route = Some(route_time);
+        let value = timing.header_value();
+        response.headers_mut().insert(HEADER_SERVER_TIMING, value);
+    }
     Ok(response)
 }
 
+/// Returns the error reported in place of a handler's panic, for servers with
+/// an error mapper
+fn handler_panic_error(panic: Box<dyn std::any::Any + Send>) -> HttpError {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(non-string panic payload)");
+    error!(panic = message, "handler panicked; reporting an internal error");
+    HttpError::for_internal_error(format!("handler panicked: {}", message))
+}
+
+/// Returns the error to report if `request` exceeds the limits in `config` on
+/// the size of its URI or headers
+fn request_size_error(
+    config: &ServerConfig,
+    request: &Request<Body>,
+) -> Option<HttpError> {
+    let too_large = |message: String| {
+        Some(HttpError::for_client_error(
+            None,
+            http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            message,
+        ))
+    };
+
+    let uri_length =
+        request.uri().path_and_query().map_or(0, |pq| pq.as_str().len());
+    if let Some(max) = config.max_uri_length.filter(|max| uri_length > *max) {
+        return Some(HttpError::for_client_error(
+            None,
+            http::StatusCode::URI_TOO_LONG,
+            format!("request URI is too long (max {} bytes)", max),
+        ));
+    }
+
+    let headers = request.headers();
+    if let Some(max) =
+        config.max_request_headers.filter(|max| headers.len() > *max)
+    {
+        return too_large(format!("too many header fields (max {})", max));
+    }
+    if let Some(max) = config.max_request_header_bytes {
+        let header_bytes: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if header_bytes > max {
+            return too_large(format!(
+                "header fields are too large (max {} bytes)",
+                max
+            ));
+        }
+    }
+    None
+}
+
 // This function should probably be parametrized by some name of the service
 // that is expected to be unique within an organization.  That way, it would be
 // possible to determine from a given request id which service it was from.
@@ -1016,14 +2925,18 @@ impl<C: ServerContext> ServerConnectionHandler<C> {
     }
 }
 
-impl<T: ServerContext> Service<&AddrStream> for ServerConnectionHandler<T> {
+impl<C, T> Service<&ServerConnection<T>> for ServerConnectionHandler<C>
+where
+    C: ServerContext,
+    T: ConnectionInfo,
+{
     // Recall that a Service in this context is just something that takes a
     // request (which could be anything) and produces a response (which could be
-    // anything).  This being a connection handler, the request type is an
-    // AddrStream (which wraps a TCP connection) and the response type is
-    // another Service: one that accepts HTTP requests and produces HTTP
-    // responses.
-    type Response = ServerRequestHandler<T>;
+    // anything).  This being a connection handler, the request type is a
+    // ServerConnection (which wraps a TCP, TLS, or Unix domain socket
+    // connection) and the response type is another Service: one that accepts
+    // HTTP requests and produces HTTP responses.
+    type Response = ServerRequestHandler<C>;
     type Error = GenericError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -1035,17 +2948,21 @@ impl<T: ServerContext> Service<&AddrStream> for ServerConnectionHandler<T> {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, conn: &AddrStream) -> Self::Future {
-        // We're given a borrowed reference to the AddrStream, but our interface
-        // is async (which is good, so that we can support time-consuming
-        // operations as part of receiving requests).  To avoid having to ensure
-        // that conn's lifetime exceeds that of this async operation, we simply
-        // copy the only useful information out of the conn: the SocketAddr.  We
-        // may want to create our own connection type to encapsulate the socket
-        // address and any other per-connection state that we want to keep.
+    fn call(&mut self, conn: &ServerConnection<T>) -> Self::Future {
+        // We're given a borrowed reference to the connection, but our
+        // interface is async (which is good, so that we can support
+        // time-consuming operations as part of receiving requests).  To avoid
+        // having to ensure that conn's lifetime exceeds that of this async
+        // operation, we simply copy the useful information out of the conn.
         let server = Arc::clone(&self.server);
-        let remote_addr = conn.remote_addr();
-        Box::pin(http_connection_handle(server, remote_addr))
+        let io = conn.io();
+        Box::pin(http_connection_handle(
+            server,
+            Arc::clone(conn.state()),
+            io.remote_addr(),
+            io.peer_credentials(),
+            io.client_certificate(),
+        ))
     }
 }
 
@@ -1057,14 +2974,32 @@ impl<T: ServerContext> Service<&AddrStream> for ServerConnectionHandler<T> {
 pub struct ServerRequestHandler<C: ServerContext> {
     /// backend state that will be made available to the request handler
     server: Arc<DropshotState<C>>,
+    /// state of the connection on which requests are received
+    connection: Arc<ConnectionState>,
     remote_addr: SocketAddr,
+    /// credentials of the peer, for connections on a Unix domain socket
+    peer_credentials: Option<PeerCredentials>,
+    /// certificate presented by the client, for TLS connections
+    client_certificate: Option<ClientCertificate>,
 }
 
 impl<C: ServerContext> ServerRequestHandler<C> {
     /// Create a ServerRequestHandler object with the given state object that
     /// will be provided to the handler function.
-    fn new(server: Arc<DropshotState<C>>, remote_addr: SocketAddr) -> Self {
-        ServerRequestHandler { server, remote_addr }
+    fn new(
+        server: Arc<DropshotState<C>>,
+        connection: Arc<ConnectionState>,
+        remote_addr: SocketAddr,
+        peer_credentials: Option<PeerCredentials>,
+        client_certificate: Option<ClientCertificate>,
+    ) -> Self {
+        ServerRequestHandler {
+            server,
+            connection,
+            remote_addr,
+            peer_credentials,
+            client_certificate,
+        }
     }
 }
 
@@ -1081,16 +3016,54 @@ impl<C: ServerContext> Service<Request<Body>> for ServerRequestHandler<C> {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
-        Box::pin(http_request_handle_wrap(
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        // The credentials are passed along with the request so that they
+        // make it to the `RequestInfo` (even by way of any middleware).
+        if let Some(peer_credentials) = self.peer_credentials {
+            req.extensions_mut().insert(peer_credentials);
+        }
+        if let Some(client_certificate) = &self.client_certificate {
+            req.extensions_mut().insert(client_certificate.clone());
+        }
+        let active_request = self.connection.begin_request();
+        if let Some(min_rate) = self.server.config.min_request_body_rate {
+            if !req.body().is_end_stream() {
+                let (parts, body) = req.into_parts();
+                let body = MinRateBody::new(
+                    body,
+                    min_rate,
+                    Arc::clone(&self.connection),
+                    Arc::clone(&self.server.slow_request_bodies),
+                );
+                req = Request::from_parts(parts, Body::wrap_stream(body));
+            }
+        }
+        let close_connection = active_request.is_last(&self.server.config)
+            && req.version() < http::Version::HTTP_2;
+        let response = http_request_handle_wrap(
             Arc::clone(&self.server),
             self.remote_addr,
             req,
-        ))
+        );
+        let alt_svc = self.server.config.alt_svc.clone();
+        Box::pin(async move {
+            let _active_request = active_request;
+            let mut response = response.await?;
+            if close_connection {
+                response.headers_mut().insert(
+                    http::header::CONNECTION,
+                    http::header::HeaderValue::from_static("close"),
+                );
+            }
+            if let Some(alt_svc) = alt_svc {
+                response.headers_mut().insert(http::header::ALT_SVC, alt_svc);
+            }
+            Ok(response)
+        })
     }
 }
 
-fn io_error(err: String) -> std::io::Error {
+pub(crate) fn io_error(err: String) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::Other, err)
 }
 
@@ -0,0 +1,164 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for the `Server-Timing` response header
+//!
+//! Dropshot can report how long it spent in each phase of handling a request
+//! (routing, running extractors, running the handler, and serializing the
+//! response) via the [`Server-Timing`] header, which browser developer tools
+//! and other frontend performance tooling know how to display.  This is
+//! disabled by default.  See [`ConfigServerTiming`] for how to enable it for
+//! a sample of requests or for requests that ask for it.
+//!
+//! [`Server-Timing`]: https://www.w3.org/TR/server-timing/
+
+use http::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Response};
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Response header reporting how long each phase of a request took
+pub const HEADER_SERVER_TIMING: &str = "server-timing";
+/// Request header with which a client asks for a `Server-Timing` header on
+/// the response (see [`ConfigServerTiming::on_request`])
+pub const HEADER_SERVER_TIMING_REQUESTED: &str = "x-server-timing";
+
+/// Configuration for the `Server-Timing` response header
+///
+/// In TOML, this looks like:
+///
+/// ```toml
+/// [server_timing]
+/// on_request = true
+/// sample_interval = 100
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ConfigServerTiming {
+    /// If true, responses to requests that include the `x-server-timing`
+    /// header (with any value) include a `Server-Timing` header
+    pub on_request: bool,
+    /// If nonzero, responses to one in every `sample_interval` requests
+    /// include a `Server-Timing` header
+    pub sample_interval: u64,
+}
+
+impl ConfigServerTiming {
+    /// Returns whether the response to a request with the given headers should
+    /// include a `Server-Timing` header.  `nrequests` counts requests for
+    /// sampling.
+    pub(crate) fn wants_timing(
+        &self,
+        headers: &HeaderMap,
+        nrequests: &AtomicU64,
+    ) -> bool {
+        if self.on_request
+            && headers.contains_key(HEADER_SERVER_TIMING_REQUESTED)
+        {
+            return true;
+        }
+        self.sample_interval != 0
+            && nrequests
+                .fetch_add(1, Ordering::Relaxed)
+                .checked_rem(self.sample_interval)
+                == Some(0)
+    }
+}
+
+/// How long each phase of handling a request took
+///
+/// This is accumulated in the extensions of the response as it's produced.
+/// Phases that didn't happen (e.g., because the request failed before the
+/// handler ran) are left out.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ServerTiming {
+    pub route: Option<Duration>,
+    pub extract: Option<Duration>,
+    pub handler: Option<Duration>,
+    pub serialize: Option<Duration>,
+}
+
+impl ServerTiming {
+    /// Returns the timings recorded for `response`, adding an empty set if
+    /// there aren't any yet
+    pub fn of(response: &mut Response<Body>) -> &mut ServerTiming {
+        let extensions = response.extensions_mut();
+        if extensions.get::<ServerTiming>().is_none() {
+            extensions.insert(ServerTiming::default());
+        }
+        extensions.get_mut::<ServerTiming>().unwrap()
+    }
+
+    /// Returns the value of the `Server-Timing` header for these timings
+    pub fn header_value(&self) -> HeaderValue {
+        let phases = [
+            ("route", self.route),
+            ("extract", self.extract),
+            ("handler", self.handler),
+            ("serialize", self.serialize),
+        ];
+        let mut value = String::new();
+        for (name, duration) in phases {
+            let Some(duration) = duration else {
+                continue;
+            };
+            if !value.is_empty() {
+                value.push_str(", ");
+            }
+            // Durations are reported in milliseconds.
+            write!(value, "{};dur={:.3}", name, duration.as_secs_f64() * 1e3)
+                .unwrap();
+        }
+        HeaderValue::from_str(&value).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigServerTiming;
+    use super::ServerTiming;
+    use http::header::HeaderMap;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wants_timing() {
+        let nrequests = AtomicU64::new(0);
+        let headers = HeaderMap::new();
+        let mut requested = HeaderMap::new();
+        requested.insert("x-server-timing", "1".parse().unwrap());
+
+        let config = ConfigServerTiming::default();
+        assert!(!config.wants_timing(&headers, &nrequests));
+        assert!(!config.wants_timing(&requested, &nrequests));
+
+        let config =
+            ConfigServerTiming { on_request: true, sample_interval: 0 };
+        assert!(!config.wants_timing(&headers, &nrequests));
+        assert!(config.wants_timing(&requested, &nrequests));
+
+        let config =
+            ConfigServerTiming { on_request: false, sample_interval: 3 };
+        let sampled = (0..9)
+            .filter(|_| config.wants_timing(&headers, &nrequests))
+            .count();
+        assert_eq!(sampled, 3);
+    }
+
+    #[test]
+    fn test_header_value() {
+        assert_eq!(ServerTiming::default().header_value(), "");
+
+        let timing = ServerTiming {
+            route: Some(Duration::from_micros(12)),
+            extract: None,
+            handler: Some(Duration::from_millis(25)),
+            serialize: Some(Duration::from_nanos(1500)),
+        };
+        assert_eq!(
+            timing.header_value(),
+            "route;dur=0.012, handler;dur=25.000, serialize;dur=0.002"
+        );
+    }
+}
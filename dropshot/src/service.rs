@@ -0,0 +1,285 @@
+// Copyright 2024 Oxide Computer Company
+//! Handling requests for an API without a listener of Dropshot's own
+//!
+//! [`ApiService`] is a [`hyper::service::Service`] (which is to say, a
+//! `tower::Service`) that handles each request the way an [`HttpServer`]
+//! would: with the same routing, middleware, error handling, and request
+//! limits.  This allows an API to be served by an existing hyper server, by an
+//! adapter for some other environment (e.g., a serverless function), or
+//! directly by tests that don't need to go through TCP.
+//!
+//! An `ApiService` can also be mounted under a path prefix of a server whose
+//! own API has a different context type (see
+//! [`HttpServerStarter::mount`]).  The server only sees the service's context
+//! type erased, as a `MountedService`.
+//!
+//! [`HttpServer`]: crate::HttpServer
+//! [`HttpServerStarter::mount`]: crate::HttpServerStarter::mount
+
+use crate::api_description::ApiDescription;
+use crate::config::ConfigDropshot;
+use crate::server::{
+    http_request_handle_wrap, DropshotState, Middleware, ServerConfig,
+    ServerContext,
+};
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+type GenericError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Address reported for clients when none has been provided (see
+/// [`ApiService::with_remote_addr`])
+const UNKNOWN_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// Handles requests for an API, without listening for connections
+///
+/// Each request goes through the same pipeline as it would in an
+/// [`HttpServer`](crate::HttpServer), and the responses are the same.  Clones
+/// of an `ApiService` share their context and state.
+///
+/// Options in the [`ConfigDropshot`] that only concern listeners or
+/// connections (like `bind_address`, `tcp`, or `max_connections`) don't
+/// apply: those are up to whatever is driving the service.  Since there's no
+/// server to shut down, [`DropshotState::is_draining`] is always false.
+///
+/// To serve the API with a hyper server of one's own:
+///
+/// ```no_run
+/// use dropshot::ApiDescription;
+/// use dropshot::ApiService;
+/// use hyper::server::conn::AddrStream;
+/// use hyper::service::make_service_fn;
+/// use std::convert::Infallible;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), hyper::Error> {
+/// let service = ApiService::new(ApiDescription::new(), ());
+/// let make_service = make_service_fn(move |conn: &AddrStream| {
+///     let service = service.clone().with_remote_addr(conn.remote_addr());
+///     async move { Ok::<_, Infallible>(service) }
+/// });
+/// hyper::Server::bind(&"127.0.0.1:8080".parse().unwrap())
+///     .serve(make_service)
+///     .await
+/// # }
+/// ```
+pub struct ApiService<C: ServerContext> {
+    state: Arc<DropshotState<C>>,
+    remote_addr: SocketAddr,
+}
+
+impl<C: ServerContext> ApiService<C> {
+    /// Returns a service for `api` with the default configuration, whose
+    /// handlers are given the context `private`
+    pub fn new(api: ApiDescription<C>, private: C) -> ApiService<C> {
+        ApiService::new_with_config(
+            &ConfigDropshot::default(),
+            api,
+            None,
+            private,
+        )
+    }
+
+    /// Returns a service for `api` configured as described by `config`, with
+    /// `middleware` (if any) applied to each request
+    pub fn new_with_config(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        middleware: Option<Arc<dyn Middleware<C>>>,
+        private: C,
+    ) -> ApiService<C> {
+        let server_config =
+            ServerConfig::new(config, api.error_response_format, None);
+        let state = DropshotState::new(
+            private,
+            server_config,
+            api,
+            middleware,
+            UNKNOWN_ADDR,
+            None,
+            None,
+        );
+        ApiService { state: Arc::new(state), remote_addr: UNKNOWN_ADDR }
+    }
+
+    /// Returns this service, but reporting that requests come from
+    /// `remote_addr`
+    ///
+    /// This is the address that handlers see as
+    /// [`RequestInfo::remote_addr`](crate::RequestInfo::remote_addr).
+    /// Otherwise, it's the unspecified address `0.0.0.0:0`.
+    pub fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = remote_addr;
+        self
+    }
+
+    /// Returns the state shared by this service's request handlers
+    pub fn app_state(&self) -> &Arc<DropshotState<C>> {
+        &self.state
+    }
+
+    /// Returns the context provided to this service's request handlers
+    pub fn app_private(&self) -> &C {
+        &self.state.private
+    }
+}
+
+impl<C: ServerContext> Clone for ApiService<C> {
+    fn clone(&self) -> Self {
+        ApiService {
+            state: Arc::clone(&self.state),
+            remote_addr: self.remote_addr,
+        }
+    }
+}
+
+impl<C: ServerContext> std::fmt::Debug for ApiService<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiService")
+            .field("remote_addr", &self.remote_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: ServerContext> Service<Request<Body>> for ApiService<C> {
+    type Response = Response<Body>;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        Box::pin(http_request_handle_wrap(
+            Arc::clone(&self.state),
+            self.remote_addr,
+            request,
+        ))
+    }
+}
+
+/// An [`ApiService`] whose context type has been erased
+pub(crate) trait MountedService: Send + Sync + 'static {
+    /// Handles `request`, which came from `remote_addr`
+    fn handle(
+        &self,
+        remote_addr: SocketAddr,
+        request: Request<Body>,
+    ) -> BoxFuture<'static, Result<Response<Body>, GenericError>>;
+}
+
+impl<C: ServerContext> MountedService for ApiService<C> {
+    fn handle(
+        &self,
+        remote_addr: SocketAddr,
+        request: Request<Body>,
+    ) -> BoxFuture<'static, Result<Response<Body>, GenericError>> {
+        Box::pin(http_request_handle_wrap(
+            Arc::clone(&self.state),
+            remote_addr,
+            request,
+        ))
+    }
+}
+
+/// Where a request received by the server is to be handled, as determined by
+/// [`MountTable::route`]
+pub(crate) enum Route {
+    /// by a mounted service, with the request rewritten to remove its prefix
+    Mounted(Arc<dyn MountedService>, Request<Body>),
+    /// by the server's own API
+    Local(Request<Body>),
+}
+
+/// Services mounted under path prefixes of a server
+///
+/// Services can be mounted after the server's listeners have been created
+/// (but before any requests are handled), so they share this table.
+#[derive(Default)]
+pub(crate) struct MountTable(RwLock<Vec<(String, Arc<dyn MountedService>)>>);
+
+impl MountTable {
+    /// Mounts `service` under `prefix`, which must begin with a "/" and not
+    /// end with one, and must not overlap with any other mounted prefix
+    pub(crate) fn add(
+        &self,
+        prefix: &str,
+        service: Arc<dyn MountedService>,
+    ) -> Result<(), String> {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            return Err(format!(
+                "mount prefix \"{}\" must begin with \"/\" and not end \
+                 with one",
+                prefix
+            ));
+        }
+        let mut mounts = self.0.write().unwrap();
+        if let Some((other, _)) = mounts.iter().find(|(other, _)| {
+            strip_prefix(prefix, other).is_some()
+                || strip_prefix(other, prefix).is_some()
+        }) {
+            return Err(format!(
+                "mount prefix \"{}\" overlaps with \"{}\"",
+                prefix, other
+            ));
+        }
+        mounts.push((prefix.to_string(), service));
+        Ok(())
+    }
+
+    /// Finds the service mounted under a prefix of `request`'s path, if any,
+    /// returning it along with the request rewritten to remove the prefix
+    ///
+    /// If there's no such service, the request is returned as-is.
+    pub(crate) fn route(&self, request: Request<Body>) -> Route {
+        let mounts = self.0.read().unwrap();
+        let path = request.uri().path();
+        let Some((rest, service)) =
+            mounts.iter().find_map(|(prefix, service)| {
+                strip_prefix(path, prefix).map(|rest| (rest, service))
+            })
+        else {
+            return Route::Local(request);
+        };
+
+        let rest = if rest.is_empty() { "/" } else { rest };
+        let path_and_query = match request.uri().query() {
+            Some(query) => format!("{}?{}", rest, query),
+            None => rest.to_string(),
+        };
+        let service = Arc::clone(service);
+        drop(mounts);
+
+        let (mut parts, body) = request.into_parts();
+        let mut uri = parts.uri.into_parts();
+        // This was part of a valid URI to begin with.
+        uri.path_and_query = Some(path_and_query.parse().unwrap());
+        parts.uri = http::Uri::from_parts(uri).unwrap();
+        Route::Mounted(service, Request::from_parts(parts, body))
+    }
+}
+
+/// Returns the rest of `path` if it's `prefix` or begins with `prefix/`
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl std::fmt::Debug for MountTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefixes = self.0.read().map_or_else(
+            |_| Vec::new(),
+            |mounts| mounts.iter().map(|(prefix, _)| prefix.clone()).collect(),
+        );
+        f.debug_tuple("MountTable").field(&prefixes).finish()
+    }
+}
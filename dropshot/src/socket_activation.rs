@@ -0,0 +1,525 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for systemd-style socket activation, and for handing listening
+//! sockets to a new process
+//!
+//! With socket activation, a service manager creates a server's listening
+//! sockets and passes them to the server when starting it.  This way, the
+//! server can be started on demand, restarted without refusing any
+//! connections, or run without the privileges needed to bind its sockets.
+//!
+//! A server can also restart itself without refusing any connections by
+//! passing its own listening sockets to a new process in much the same way
+//! (see [`pass_listeners`]).
+
+use socket2::{SockRef, Type};
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// First file descriptor passed by the service manager
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Environment variable that tells a process which listening sockets it's been
+/// passed by [`pass_listeners`], and by which process: its value is the
+/// passing process's id, a colon, and a comma-separated list of descriptors
+const INHERITED_FDS_VAR: &str = "DROPSHOT_LISTEN_FDS";
+
+/// Returns the TCP listeners passed to this process using the systemd socket
+/// activation protocol, in the order in which they were passed
+///
+/// This consumes the `LISTEN_PID`, `LISTEN_FDS`, and `LISTEN_FDNAMES`
+/// environment variables, which tell the process which file descriptors it's
+/// been passed.  They're removed from the environment (so that they're not
+/// inherited by child processes, and so that the descriptors aren't taken
+/// twice), so subsequent calls return an empty list.  The list is also empty if
+/// the process wasn't passed any sockets.  It's an error for any of the
+/// passed descriptors to be something other than a listening TCP socket, in
+/// which case none of them are taken.  The listeners are marked close-on-exec,
+/// so they're not inherited by child processes.
+///
+/// The listeners can be used with [`crate::HttpServerStarter::new_with_listener`]
+/// and [`crate::HttpServerStarter::listen_on`]:
+///
+/// ```no_run
+/// use dropshot::ApiDescription;
+/// use dropshot::ConfigDropshot;
+/// use dropshot::HttpServerStarter;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let mut listeners = dropshot::systemd_listeners()?.into_iter();
+/// let listener = listeners.next().ok_or("no socket passed")?;
+/// let starter = HttpServerStarter::new_with_listener(
+///     &ConfigDropshot::default(),
+///     ApiDescription::new(),
+///     None,
+///     (),
+///     None,
+///     listener,
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn systemd_listeners() -> io::Result<Vec<TcpListener>> {
+    let nfds = match listen_fds()? {
+        Some(nfds) => nfds,
+        None => return Ok(Vec::new()),
+    };
+    let fds = (0..nfds).map(|i| LISTEN_FDS_START + i).collect::<Vec<_>>();
+    take_listeners(&fds)
+}
+
+/// Takes ownership of the listeners passed to this process as descriptors
+/// `fds`, whose description has already been removed from the environment
+///
+/// Every descriptor is checked before any is taken, so that if one isn't a
+/// listening TCP socket, none are closed: we can't be sure that they're ours
+/// to close.  The descriptors that are taken are marked close-on-exec, so that
+/// they're not leaked into child processes.
+fn take_listeners(fds: &[RawFd]) -> io::Result<Vec<TcpListener>> {
+    for fd in fds {
+        check_listener(*fd)?;
+    }
+    fds.iter()
+        .map(|fd| {
+            // SAFETY: Our parent passed this descriptor to us to own, we've
+            // checked that it's a listening socket, and since the caller has
+            // removed the environment variables that describe it, nothing
+            // else will take it.
+            let listener = unsafe { TcpListener::from_raw_fd(*fd) };
+            SockRef::from(&listener).set_cloexec(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+/// Checks that descriptor `fd` is a listening TCP socket, without taking
+/// ownership of it
+fn check_listener(fd: RawFd) -> io::Result<()> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("passed file descriptor {} is not {}", fd, what),
+        )
+    };
+    // SAFETY: The descriptor is only borrowed for the duration of this
+    // function, and nothing closes it in the meantime.  (If it isn't open at
+    // all, the calls below fail with EBADF.)
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed);
+    // This fails for anything but a socket.
+    let socket_type =
+        socket.r#type().map_err(|e| invalid(&format!("a socket: {}", e)))?;
+    if socket_type != Type::STREAM {
+        return Err(invalid("a stream socket"));
+    }
+    // This fails for anything but an IP socket.
+    if socket.local_addr()?.as_socket().is_none() {
+        return Err(invalid("a TCP socket"));
+    }
+    // Not every system can tell us this (via SO_ACCEPTCONN).
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+    ))]
+    if !socket.is_listener()? {
+        return Err(invalid("a listening socket"));
+    }
+    Ok(())
+}
+
+/// Arranges for the process started by `command` to be passed `listeners`,
+/// which it can take with [`inherited_listeners`]
+///
+/// This is meant for restarting a server without refusing any connections:
+/// the running server's listeners (see
+/// [`crate::HttpServer::try_clone_listeners`]) are passed to a new process
+/// (e.g., a new version of the same program), which serves them with
+/// [`crate::HttpServerStarter::new_with_listener`].  Once it's ready, the old
+/// server shuts down gracefully, finishing the requests it's already
+/// received.
+///
+/// The listeners keep their descriptor numbers in the new process.  An
+/// environment variable tells it which descriptors they are and which
+/// process passed them, so that they're only taken by the process they were
+/// passed to (and not, say, by one of its children that inherited the
+/// variable).  `listeners` are closed in this process once `command` is
+/// dropped.
+///
+/// ```no_run
+/// # async fn restart(
+/// #     server: dropshot::HttpServer<()>,
+/// # ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let mut command = std::process::Command::new(std::env::current_exe()?);
+/// dropshot::pass_listeners(&mut command, server.try_clone_listeners()?);
+/// let _child = command.spawn()?;
+/// // ... (wait for the new process to report that it's ready)
+/// server.close().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn pass_listeners(command: &mut Command, listeners: Vec<TcpListener>) {
+    let fds = listeners
+        .iter()
+        .map(|listener| listener.as_raw_fd().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    command.env(INHERITED_FDS_VAR, format!("{}:{}", std::process::id(), fds));
+    command.env_remove("LISTEN_PID");
+    command.env_remove("LISTEN_FDS");
+    command.env_remove("LISTEN_FDNAMES");
+    let pre_exec = move || {
+        // Our descriptors are all close-on-exec, so these must be made
+        // inheritable.  This only affects the new process's copies.
+        for listener in &listeners {
+            SockRef::from(listener).set_cloexec(false)?;
+        }
+        Ok(())
+    };
+    // SAFETY: The closure only makes async-signal-safe system calls (fcntl),
+    // and it doesn't allocate or free memory.
+    unsafe {
+        command.pre_exec(pre_exec);
+    }
+}
+
+/// Returns the TCP listeners passed to this process with [`pass_listeners`],
+/// in the order in which they were passed
+///
+/// Like [`systemd_listeners`], this removes the environment variable that
+/// describes the listeners, so subsequent calls return an empty list.  The
+/// list is also empty if the listeners were passed to some other process
+/// (e.g., our parent, from which we inherited the variable).  It's an error
+/// for any of the passed descriptors to be something other than a listening
+/// TCP socket, in which case none of them are taken.  The listeners are
+/// marked close-on-exec, so they're not inherited by child processes.
+pub fn inherited_listeners() -> io::Result<Vec<TcpListener>> {
+    let value = std::env::var(INHERITED_FDS_VAR).ok();
+    std::env::remove_var(INHERITED_FDS_VAR);
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid value for {}: {:?}", INHERITED_FDS_VAR, value),
+        )
+    };
+    let (pid, fds) = value.split_once(':').ok_or_else(invalid)?;
+    let pid: u32 = pid.parse().map_err(|_| invalid())?;
+    let fds = fds
+        .split(',')
+        .filter(|fd| !fd.is_empty())
+        .map(|fd| fd.parse().ok().filter(|fd: &RawFd| *fd >= 0))
+        .collect::<Option<Vec<RawFd>>>()
+        .ok_or_else(invalid)?;
+    if pid != std::os::unix::process::parent_id() {
+        // These were meant for some other process (probably our parent).
+        return Ok(Vec::new());
+    }
+    take_listeners(&fds)
+}
+
+/// Returns the number of descriptors passed to this process (if any),
+/// removing the environment variables that describe them
+fn listen_fds() -> io::Result<Option<RawFd>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let nfds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let (Some(pid), Some(nfds)) = (pid, nfds) else {
+        return Ok(None);
+    };
+    let invalid = |name: &str, value: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid value for {}: {:?}", name, value),
+        )
+    };
+    let pid: u32 = pid.parse().map_err(|_| invalid("LISTEN_PID", &pid))?;
+    if pid != std::process::id() {
+        // These were meant for some other process (probably our parent).
+        return Ok(None);
+    }
+    let nfds: RawFd = nfds
+        .parse()
+        .ok()
+        .filter(|n| *n >= 0)
+        .ok_or_else(|| invalid("LISTEN_FDS", &nfds))?;
+    Ok(Some(nfds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::inherited_listeners;
+    use super::listen_fds;
+    use super::pass_listeners;
+    use super::systemd_listeners;
+    use std::net::{TcpListener, UdpSocket};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::process::Command;
+
+    /// Environment variable telling a child process which test to run
+    const CHILD_TEST_VAR: &str = "DROPSHOT_SOCKET_ACTIVATION_TEST";
+
+    /// Returns whether this is a new process started to run test `name` (in
+    /// which case the caller runs the test).  Otherwise, runs the test in a new
+    /// process and returns `false`.
+    ///
+    /// These tests modify the environment and this process's descriptors,
+    /// which isn't safe with other tests running concurrently in the same
+    /// process.
+    fn in_child_process(name: &str) -> bool {
+        if is_child_process(name) {
+            return true;
+        }
+        run_child(&mut child_command(name));
+        false
+    }
+
+    /// Returns whether this is a new process started to run test `name`
+    fn is_child_process(name: &str) -> bool {
+        if std::env::var(CHILD_TEST_VAR).as_deref() == Ok(name) {
+            std::env::remove_var(CHILD_TEST_VAR);
+            return true;
+        }
+        false
+    }
+
+    /// Returns a command that runs test `name` in a new process
+    fn child_command(name: &str) -> Command {
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command
+            .args([
+                &format!("socket_activation::test::{}", name),
+                "--exact",
+                "--test-threads=1",
+            ])
+            .env(CHILD_TEST_VAR, name);
+        command
+    }
+
+    /// Runs `command` (see [`child_command`]) and checks that its test passed
+    fn run_child(command: &mut Command) {
+        let output = command.output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "test failed in child process:\n{}\n{}",
+            stdout,
+            String::from_utf8_lossy(&output.stderr),
+        );
+        assert!(stdout.contains("1 passed"), "test not run:\n{}", stdout);
+    }
+
+    /// Moves descriptor `fd` to `target`, as a parent process would when
+    /// passing it to us
+    fn move_fd(fd: RawFd, target: RawFd) {
+        // SAFETY: We don't hold any other reference to `target`.
+        assert_eq!(unsafe { libc::dup2(fd, target) }, target);
+    }
+
+    fn is_open(fd: RawFd) -> bool {
+        // SAFETY: This only queries the descriptor's flags.
+        unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+    }
+
+    fn is_cloexec(fd: RawFd) -> bool {
+        // SAFETY: This only queries the descriptor's flags.
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        flags >= 0 && (flags & libc::FD_CLOEXEC) != 0
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        if !in_child_process("test_listen_fds") {
+            return;
+        }
+        let pid = std::process::id().to_string();
+
+        // Nothing passed.
+        assert!(systemd_listeners().unwrap().is_empty());
+
+        // Descriptors passed to some other process.
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "2");
+        assert_eq!(listen_fds().unwrap(), None);
+        assert!(std::env::var("LISTEN_PID").is_err());
+        assert!(std::env::var("LISTEN_FDS").is_err());
+
+        // Bad values.
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "-1");
+        assert_eq!(
+            listen_fds().unwrap_err().to_string(),
+            "invalid value for LISTEN_FDS: \"-1\""
+        );
+        std::env::set_var("LISTEN_PID", "me");
+        std::env::set_var("LISTEN_FDS", "1");
+        assert_eq!(
+            listen_fds().unwrap_err().to_string(),
+            "invalid value for LISTEN_PID: \"me\""
+        );
+
+        // Descriptors passed to us (but not, in this case, any actual ones).
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "0");
+        std::env::set_var("LISTEN_FDNAMES", "");
+        assert!(systemd_listeners().unwrap().is_empty());
+        assert!(std::env::var("LISTEN_FDNAMES").is_err());
+    }
+
+    #[test]
+    fn test_systemd_listeners() {
+        if !in_child_process("test_systemd_listeners") {
+            return;
+        }
+        let pid = std::process::id().to_string();
+
+        // A listener and a UDP socket: the latter is rejected, and neither is
+        // closed.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        move_fd(listener.as_raw_fd(), 3);
+        move_fd(udp.as_raw_fd(), 4);
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "2");
+        assert_eq!(
+            systemd_listeners().unwrap_err().to_string(),
+            "passed file descriptor 4 is not a stream socket"
+        );
+        assert!(is_open(3));
+        assert!(is_open(4));
+
+        // The same goes for a TCP socket that isn't listening.
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::STREAM,
+            None,
+        )
+        .unwrap();
+        socket
+            .bind(
+                &"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into(),
+            )
+            .unwrap();
+        move_fd(socket.as_raw_fd(), 4);
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "2");
+        assert_eq!(
+            systemd_listeners().unwrap_err().to_string(),
+            "passed file descriptor 4 is not a listening socket"
+        );
+        assert!(is_open(4));
+
+        // The same goes for something other than a socket.
+        let file = std::fs::File::open("/dev/null").unwrap();
+        move_fd(file.as_raw_fd(), 4);
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "2");
+        let error = systemd_listeners().unwrap_err().to_string();
+        assert!(
+            error.starts_with("passed file descriptor 4 is not a socket"),
+            "{}",
+            error
+        );
+        assert!(is_open(4));
+
+        // A valid listener is taken, and not inherited by child processes.
+        move_fd(3, 4);
+        std::env::set_var("LISTEN_PID", &pid);
+        std::env::set_var("LISTEN_FDS", "1");
+        std::env::set_var("LISTEN_FDNAMES", "http");
+        let listeners = systemd_listeners().unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].as_raw_fd(), 3);
+        assert_eq!(listeners[0].local_addr().unwrap(), addr);
+        assert!(is_cloexec(3));
+    }
+
+    #[test]
+    fn test_pass_listeners() {
+        if !in_child_process("test_pass_listeners") {
+            return;
+        }
+
+        // Nothing passed.
+        assert!(inherited_listeners().unwrap().is_empty());
+
+        // Listeners passed to another process keep their descriptor numbers,
+        // and are taken there.
+        let listeners = (0..2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect::<Vec<_>>();
+        let clones = listeners
+            .iter()
+            .map(|l| l.try_clone().unwrap())
+            .collect::<Vec<_>>();
+        let fds = clones
+            .iter()
+            .map(|l| l.as_raw_fd().to_string())
+            .collect::<Vec<_>>();
+        let addrs = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().to_string())
+            .collect::<Vec<_>>();
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            &format!(
+                "echo $DROPSHOT_LISTEN_FDS; test -e /dev/fd/{} && test -e \
+                 /dev/fd/{}",
+                fds[0], fds[1]
+            ),
+        ]);
+        pass_listeners(&mut command, clones);
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{}:{}\n", std::process::id(), fds.join(","))
+        );
+
+        let mut command = child_command("test_inherited_listeners");
+        command.env("DROPSHOT_TEST_ADDRS", addrs.join(","));
+        pass_listeners(&mut command, listeners);
+        run_child(&mut command);
+
+        // Listeners passed to some other process are left alone.
+        std::env::set_var("DROPSHOT_LISTEN_FDS", "1:0");
+        assert!(inherited_listeners().unwrap().is_empty());
+        assert!(std::env::var("DROPSHOT_LISTEN_FDS").is_err());
+
+        for bad in ["many", "1:a", "1:3,-1"] {
+            std::env::set_var("DROPSHOT_LISTEN_FDS", bad);
+            assert_eq!(
+                inherited_listeners().unwrap_err().to_string(),
+                format!("invalid value for DROPSHOT_LISTEN_FDS: {:?}", bad)
+            );
+            assert!(std::env::var("DROPSHOT_LISTEN_FDS").is_err());
+        }
+    }
+
+    /// Run by `test_pass_listeners` in a process it passes listeners to
+    #[test]
+    fn test_inherited_listeners() {
+        if !is_child_process("test_inherited_listeners") {
+            return;
+        }
+        let addrs = std::env::var("DROPSHOT_TEST_ADDRS").unwrap();
+        let listeners = inherited_listeners().unwrap();
+        let taken = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(taken.join(","), addrs);
+        assert!(listeners.iter().all(|l| is_cloexec(l.as_raw_fd())));
+        assert!(std::env::var("DROPSHOT_LISTEN_FDS").is_err());
+    }
+}
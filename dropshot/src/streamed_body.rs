@@ -0,0 +1,307 @@
+// Copyright 2024 Oxide Computer Company
+//! Response bodies produced from an async `Stream`
+//!
+//! [`StreamedBody`] turns a `Stream` of byte chunks into a response body,
+//! optionally followed by HTTP trailers computed once the stream has finished
+//! (e.g., a checksum of everything that was sent).  Streams can also be tied
+//! to the server's lifecycle so that they're cut off when graceful shutdown
+//! begins rather than holding it up indefinitely.
+
+use crate::handler::RequestContext;
+use crate::server::{ServerContext, ServerPhase};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, Stream, StreamExt};
+use http::HeaderMap;
+use hyper::{Body, Response};
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::watch;
+use tracing::warn;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Builds a response body from a `Stream` of chunks, with optional trailers
+///
+/// The stream is consumed by a separate task, which the server starts once the
+/// handler has returned the response made by
+/// [`StreamedBody::into_response`].  If the stream produces an error, the body is aborted:
+/// the client sees the response end abnormally (a reset stream in HTTP/2, or
+/// a closed connection in HTTP/1) rather than a truncated but seemingly
+/// complete response.  The same happens if the client goes away.
+///
+/// Trailers are only sent to HTTP/2 clients; they're silently dropped on
+/// HTTP/1 connections.  Handlers should generally list the trailer fields in
+/// a `Trailer` response header.
+///
+/// ```
+/// use bytes::Bytes;
+/// use dropshot::endpoint;
+/// use dropshot::HttpError;
+/// use dropshot::RequestContext;
+/// use dropshot::StreamedBody;
+/// use futures::stream;
+/// use http::Response;
+/// use hyper::Body;
+/// use std::convert::Infallible;
+///
+/// #[endpoint {
+///     method = GET,
+///     path = "/numbers",
+/// }]
+/// async fn get_numbers(
+///     rqctx: RequestContext<()>,
+/// ) -> Result<Response<Body>, HttpError> {
+///     let chunks = (0..100).map(|i| Ok::<_, Infallible>(Bytes::from(
+///         format!("{}\n", i),
+///     )));
+///     let body = StreamedBody::new(stream::iter(chunks))
+///         .trailers(async {
+///             let mut trailers = http::HeaderMap::new();
+///             trailers.insert("x-count", http::HeaderValue::from_static("100"));
+///             trailers
+///         })
+///         .cancel_on_shutdown(&rqctx);
+///     Ok(body.into_response(
+///         Response::builder().header(http::header::TRAILER, "x-count"),
+///     )?)
+/// }
+/// ```
+pub struct StreamedBody {
+    stream: BoxStream<'static, Result<Bytes, BoxError>>,
+    trailers: Option<BoxFuture<'static, HeaderMap>>,
+    shutdown: Option<watch::Receiver<ServerPhase>>,
+}
+
+impl StreamedBody {
+    /// Creates a body whose contents are the chunks produced by `stream`
+    pub fn new<S, E>(stream: S) -> StreamedBody
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        StreamedBody {
+            stream: stream.map(|item| item.map_err(Into::into)).boxed(),
+            trailers: None,
+            shutdown: None,
+        }
+    }
+
+    /// Sends the headers produced by `trailers` after the last chunk
+    ///
+    /// `trailers` is not polled until `stream` has been exhausted, so it can
+    /// report on state accumulated while streaming (e.g., a running hash
+    /// shared with the stream).  It's not polled at all if the body is
+    /// aborted.
+    pub fn trailers<F>(mut self, trailers: F) -> StreamedBody
+    where
+        F: Future<Output = HeaderMap> + Send + 'static,
+    {
+        self.trailers = Some(trailers.boxed());
+        self
+    }
+
+    /// Aborts the body if the server begins graceful shutdown before the
+    /// stream is finished
+    ///
+    /// Without this, graceful shutdown waits for the stream to finish, which
+    /// may never happen for streams that follow some ongoing activity.  The
+    /// cutoff happens once any lame-duck period (see
+    /// [`crate::ConfigDropshot::lame_duck_period_ms`]) is over.
+    pub fn cancel_on_shutdown<C: ServerContext>(
+        self,
+        rqctx: &RequestContext<C>,
+    ) -> StreamedBody {
+        self.with_shutdown(rqctx.server.phase.subscribe())
+    }
+
+    /// Aborts the body once `shutdown` reports that graceful shutdown has
+    /// begun
+    pub(crate) fn with_shutdown(
+        mut self,
+        shutdown: watch::Receiver<ServerPhase>,
+    ) -> StreamedBody {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Returns a response with this body, whose status and headers come from
+    /// `builder`
+    ///
+    /// The body's contents are only produced once the server sends the
+    /// response.
+    pub fn into_response(
+        self,
+        builder: http::response::Builder,
+    ) -> Result<Response<Body>, http::Error> {
+        let (sender, body) = Body::channel();
+        builder
+            .extension(StreamedBodyTask(Mutex::new(Some((self, sender)))))
+            .body(body)
+    }
+
+    async fn run(mut self, mut sender: hyper::body::Sender) {
+        let mut shutdown = self.shutdown.take();
+        loop {
+            let next = tokio::select! {
+                next = self.stream.next() => next,
+                _ = wait_for_shutdown(&mut shutdown) => {
+                    warn!("aborting streamed response body for shutdown");
+                    sender.abort();
+                    return;
+                }
+            };
+            match next {
+                Some(Ok(chunk)) => {
+                    if sender.send_data(chunk).await.is_err() {
+                        // The client went away.
+                        return;
+                    }
+                }
+                Some(Err(error)) => {
+                    warn!(%error, "aborting streamed response body");
+                    sender.abort();
+                    return;
+                }
+                None => break,
+            }
+        }
+
+        if let Some(trailers) = self.trailers {
+            let trailers = trailers.await;
+            let _ = sender.send_trailers(trailers).await;
+        }
+    }
+}
+
+/// Resolves once `shutdown` reports that graceful shutdown has begun, or
+/// never if there's nothing to wait for
+async fn wait_for_shutdown(
+    shutdown: &mut Option<watch::Receiver<ServerPhase>>,
+) {
+    if let Some(receiver) = shutdown {
+        if receiver.wait_for(|p| *p == ServerPhase::ShuttingDown).await.is_ok()
+        {
+            return;
+        }
+    }
+    // If the server is gone, so is any reason to stop.
+    futures::future::pending().await
+}
+
+/// The work of producing a [`StreamedBody`], carried by its response until
+/// the server starts it (see [`spawn_streamed_body`])
+///
+/// The lock only makes this `Sync`, as response extensions must be.
+struct StreamedBodyTask(Mutex<Option<(StreamedBody, hyper::body::Sender)>>);
+
+impl StreamedBodyTask {
+    fn take(&mut self) -> Option<(StreamedBody, hyper::body::Sender)> {
+        self.0.get_mut().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl Drop for StreamedBodyTask {
+    fn drop(&mut self) {
+        // A response that's dropped without being sent must not look like a
+        // complete, empty one.
+        if let Some((_, sender)) = self.take() {
+            sender.abort();
+        }
+    }
+}
+
+/// Starts producing the body of `response` if it's a [`StreamedBody`]
+///
+/// This is called by the server on every response it sends, from within the
+/// runtime.
+pub(crate) fn spawn_streamed_body(response: &mut Response<Body>) {
+    if let Some(mut task) =
+        response.extensions_mut().remove::<StreamedBodyTask>()
+    {
+        if let Some((streamed, sender)) = task.take() {
+            tokio::spawn(streamed.run(sender));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::spawn_streamed_body;
+    use super::StreamedBody;
+    use crate::server::ServerPhase;
+    use bytes::Bytes;
+    use futures::stream;
+    use futures::StreamExt;
+    use http::{HeaderMap, HeaderValue};
+    use hyper::body::HttpBody;
+    use hyper::{Body, Response};
+    use std::convert::Infallible;
+    use tokio::sync::watch;
+
+    /// Returns the body of the response for `streamed`, as the server would
+    /// send it
+    fn send(streamed: StreamedBody) -> Body {
+        let mut response = streamed.into_response(Response::builder()).unwrap();
+        spawn_streamed_body(&mut response);
+        response.into_body()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_trailers() {
+        let chunks = ["one", "two", "three"]
+            .into_iter()
+            .map(|s| Ok::<_, Infallible>(Bytes::from(s)));
+        let mut body =
+            send(StreamedBody::new(stream::iter(chunks)).trailers(async {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("x-sum", HeaderValue::from_static("abc"));
+                trailers
+            }));
+
+        let mut contents = Vec::new();
+        while let Some(chunk) = body.data().await {
+            contents.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(contents, b"onetwothree");
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["x-sum"], "abc");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_error() {
+        let chunks = vec![Ok(Bytes::from("one")), Err("bad chunk")];
+        let mut body = send(
+            StreamedBody::new(stream::iter(chunks))
+                .trailers(async { panic!("trailers after an error") }),
+        );
+        assert_eq!(body.data().await.unwrap().unwrap(), "one");
+        assert!(body.data().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_shutdown() {
+        let (phase_tx, phase_rx) = watch::channel(ServerPhase::Running);
+        let chunks = stream::iter([Ok::<_, Infallible>(Bytes::from("one"))])
+            .chain(stream::pending());
+        let mut body = send(StreamedBody::new(chunks).with_shutdown(phase_rx));
+
+        assert_eq!(body.data().await.unwrap().unwrap(), "one");
+        phase_tx.send_replace(ServerPhase::Draining);
+        phase_tx.send_replace(ServerPhase::ShuttingDown);
+        assert!(body.data().await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_streamed_body_not_sent() {
+        // Making the response doesn't need a runtime, and if the response is
+        // never sent, its body is aborted rather than empty.
+        let chunks = stream::iter([Ok::<_, Infallible>(Bytes::from("one"))]);
+        let response =
+            StreamedBody::new(chunks).into_response(Response::builder());
+        let mut body = response.unwrap().into_body();
+        let data = futures::executor::block_on(body.data());
+        assert!(data.unwrap().is_err());
+    }
+}
@@ -2,31 +2,52 @@
 //! Automated testing facilities.  These are intended for use both by this crate
 //! and dependents of this crate.
 
+use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use chrono::DateTime;
 use chrono::Utc;
+use futures::Stream;
+use futures::StreamExt;
 use http::method::Method;
 use hyper::{
     body::to_bytes, client::HttpConnector, Body, Client, Request, Response,
     StatusCode, Uri,
 };
+#[cfg(feature = "tls-test")]
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+#[cfg(feature = "tls-test")]
+use rustls::pki_types::CertificateDer;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     convert::TryFrom,
     fmt::Debug,
+    fmt::Write as _,
     fs,
     iter::Iterator,
     net::SocketAddr,
     path::Path,
     sync::atomic::{AtomicU32, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use crate::api_compat::compare_openapi;
 use crate::api_description::ApiDescription;
+use crate::api_description::OpenApiDefinition;
+use crate::clock::Clock;
 use crate::config::ConfigDropshot;
+#[cfg(feature = "tls-test")]
+use crate::config::ConfigTls;
 use crate::error::HttpErrorResponseBody;
+use crate::http_util::CONTENT_TYPE_JSON;
 use crate::http_util::CONTENT_TYPE_URL_ENCODED;
+use crate::pagination::OffsetResultsPage;
 use crate::pagination::ResultsPage;
 use crate::server::{HttpServer, HttpServerStarter, ServerContext};
+use crate::versioning::VersionPolicy;
+use semver::Version;
+use std::future::Future;
 use tracing::info;
 
 enum AllowedValue<'a> {
@@ -64,6 +85,59 @@ const ALLOWED_HEADERS: [AllowedHeader<'static>; 8] = [
     AllowedHeader::new(TEST_HEADER_2),
 ];
 
+/// Which headers [`ClientTestContext`] accepts in responses
+#[derive(Clone, Debug)]
+enum HeaderPolicy {
+    /// only headers in [`ALLOWED_HEADERS`] (with the values allowed there) or
+    /// in `added` (with any value), and not in `removed`
+    AllowList { added: BTreeSet<String>, removed: BTreeSet<String> },
+    /// any header not in the set
+    DenyList(BTreeSet<String>),
+}
+
+impl HeaderPolicy {
+    fn allows(
+        &self,
+        name: &http::HeaderName,
+        value: &http::HeaderValue,
+    ) -> bool {
+        let (added, removed) = match self {
+            HeaderPolicy::AllowList { added, removed } => (added, removed),
+            HeaderPolicy::DenyList(denied) => {
+                return !denied.contains(name.as_str())
+            }
+        };
+        if removed.contains(name.as_str()) {
+            return false;
+        }
+        if added.contains(name.as_str()) {
+            return true;
+        }
+        let Some(allowed_header) =
+            ALLOWED_HEADERS.iter().find(|h| name == h.name)
+        else {
+            return false;
+        };
+        match allowed_header.value {
+            AllowedValue::Any => true,
+            AllowedValue::OneOf(allowed_values) => {
+                let value =
+                    value.to_str().expect("Cannot turn header value to string");
+                allowed_values.contains(&value)
+            }
+        }
+    }
+}
+
+impl Default for HeaderPolicy {
+    fn default() -> Self {
+        HeaderPolicy::AllowList {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
 /// ClientTestContext encapsulates several facilities associated with using an
 /// HTTP client for testing.
 #[derive(Clone)]
@@ -72,12 +146,207 @@ pub struct ClientTestContext {
     pub bind_address: SocketAddr,
     /// HTTP client, used for making requests against the test server
     pub client: Client<HttpConnector>,
+    /// HTTPS client, used instead of `client` for making requests against the
+    /// test server if set up with [`ClientTestContext::with_tls`]
+    #[cfg(feature = "tls-test")]
+    pub https_client: Option<Client<HttpsConnector<HttpConnector>>>,
+    /// how the server under test determines the version of the API that each
+    /// request is for
+    version_policy: VersionPolicy,
+    /// version of the API that requests are for, if set with
+    /// [`ClientTestContext::with_version`]
+    version: Option<Version>,
+    /// which headers are accepted in responses
+    header_policy: HeaderPolicy,
+    /// clock that the server under test uses, if it's not the system clock
+    /// (see [`ClientTestContext::with_clock`])
+    clock: Option<TestClock>,
 }
 
 impl ClientTestContext {
     /// Set up a `ClientTestContext` for running tests against an API server.
     pub fn new(server_addr: SocketAddr) -> ClientTestContext {
-        ClientTestContext { bind_address: server_addr, client: Client::new() }
+        ClientTestContext {
+            bind_address: server_addr,
+            client: Client::new(),
+            #[cfg(feature = "tls-test")]
+            https_client: None,
+            version_policy: VersionPolicy::Unversioned,
+            version: None,
+            header_policy: HeaderPolicy::default(),
+            clock: None,
+        }
+    }
+
+    /// Set up a `ClientTestContext` for running tests against a server for an
+    /// API with the given version policy, so that clients made with
+    /// [`ClientTestContext::with_version`] know how to give the version.
+    /// ([`TestContext`] does this for its client.)
+    pub fn new_versioned(
+        server_addr: SocketAddr,
+        version_policy: VersionPolicy,
+    ) -> ClientTestContext {
+        ClientTestContext {
+            version_policy,
+            ..ClientTestContext::new(server_addr)
+        }
+    }
+
+    /// Returns a client that makes requests over HTTPS, trusting `root_cert`
+    /// (e.g., a self-signed certificate, or the root of the server's
+    /// certificate chain) to identify the server
+    ///
+    /// Requests are made to "localhost" (at the server's port), so the
+    /// server's certificate must be valid for that name.  ([`TestContext`]
+    /// does this for its client when the server uses TLS.)
+    ///
+    /// This requires the feature flag `"tls-test"`.
+    #[cfg(feature = "tls-test")]
+    pub fn with_tls(
+        &self,
+        root_cert: CertificateDer<'static>,
+    ) -> ClientTestContext {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(root_cert).expect("invalid root certificate");
+        ClientTestContext {
+            https_client: Some(https_client(roots)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns whether requests are made over HTTPS (see
+    /// `ClientTestContext::with_tls`)
+    fn uses_tls(&self) -> bool {
+        #[cfg(feature = "tls-test")]
+        if self.https_client.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Sends `request` to the test server with the HTTPS client, if there is
+    /// one, or else with the HTTP client
+    fn send_request(
+        &self,
+        request: Request<Body>,
+    ) -> hyper::client::ResponseFuture {
+        #[cfg(feature = "tls-test")]
+        if let Some(https_client) = &self.https_client {
+            return https_client.request(request);
+        }
+        self.client.request(request)
+    }
+
+    /// Returns a client that also accepts the header `name`, with any value, in
+    /// responses
+    ///
+    /// By default, responses may only include a fixed set of headers that
+    /// Dropshot itself produces (some only with particular values), so that
+    /// tests catch headers leaking unexpectedly.  Servers that legitimately
+    /// add other headers can allow them with this.  (If the client
+    /// accepts any header except a few, this removes `name` from those.)
+    pub fn with_allowed_header(&self, name: &str) -> ClientTestContext {
+        let name = name.to_ascii_lowercase();
+        let mut header_policy = self.header_policy.clone();
+        match &mut header_policy {
+            HeaderPolicy::AllowList { added, removed } => {
+                removed.remove(&name);
+                added.insert(name);
+            }
+            HeaderPolicy::DenyList(denied) => {
+                denied.remove(&name);
+            }
+        }
+        ClientTestContext { header_policy, ..self.clone() }
+    }
+
+    /// Returns a client that fails any response that includes the header
+    /// `name` (see [`ClientTestContext::with_allowed_header`])
+    pub fn without_allowed_header(&self, name: &str) -> ClientTestContext {
+        let name = name.to_ascii_lowercase();
+        let mut header_policy = self.header_policy.clone();
+        match &mut header_policy {
+            HeaderPolicy::AllowList { added, removed } => {
+                added.remove(&name);
+                removed.insert(name);
+            }
+            HeaderPolicy::DenyList(denied) => {
+                denied.insert(name);
+            }
+        }
+        ClientTestContext { header_policy, ..self.clone() }
+    }
+
+    /// Returns a client that accepts any header in responses except those in
+    /// `names`, rather than only a fixed set of headers (see
+    /// [`ClientTestContext::with_allowed_header`])
+    pub fn with_denied_headers<I, S>(&self, names: I) -> ClientTestContext
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let denied = names
+            .into_iter()
+            .map(|name| name.as_ref().to_ascii_lowercase())
+            .collect();
+        ClientTestContext {
+            header_policy: HeaderPolicy::DenyList(denied),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client for a server that uses `clock` (see
+    /// [`crate::HttpServerStarter::clock`]), so that it checks the `Date`
+    /// header of each response against that clock rather than the system
+    /// clock.  ([`TestContext`] does this for its client.)
+    pub fn with_clock(&self, clock: TestClock) -> ClientTestContext {
+        ClientTestContext { clock: Some(clock), ..self.clone() }
+    }
+
+    /// Returns a client whose requests are all for `version` of the API, given
+    /// however the server's [`VersionPolicy`] expects (e.g., in a header)
+    ///
+    /// # Panics
+    ///
+    /// Requests made with the client panic if the API isn't versioned, or if
+    /// its policy doesn't know how to give the version (see
+    /// [`crate::DynamicVersionPolicy::request_add_version`]).
+    pub fn with_version(&self, version: Version) -> ClientTestContext {
+        ClientTestContext { version: Some(version), ..self.clone() }
+    }
+
+    /// Runs `test` with a client for each of `versions` (see
+    /// [`ClientTestContext::with_version`]), one after another, for checking
+    /// an endpoint's behavior across versions of the API
+    pub async fn for_each_version<I, F, Fut>(&self, versions: I, mut test: F)
+    where
+        I: IntoIterator<Item = Version>,
+        F: FnMut(ClientTestContext, Version) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        for version in versions {
+            test(self.with_version(version.clone()), version).await;
+        }
+    }
+
+    /// Makes the same request, without a body, for each version of the API in
+    /// `expected`, checking that it gets the status code given for that
+    /// version, and returns the results in the same order
+    pub async fn make_request_for_versions(
+        &self,
+        method: Method,
+        path: &str,
+        expected: &[(Version, StatusCode)],
+    ) -> Vec<Result<Response<Body>, HttpErrorResponseBody>> {
+        let mut results = Vec::with_capacity(expected.len());
+        for (version, expected_status) in expected {
+            let client = self.with_version(version.clone());
+            let result = client
+                .make_request_no_body(method.clone(), path, *expected_status)
+                .await;
+            results.push(result);
+        }
+        results
     }
 
     /// Given the path for an API endpoint (e.g., "/projects"), return a Uri that
@@ -85,14 +354,54 @@ impl ClientTestContext {
     /// appends the path to a base URL constructed from the server's IP address
     /// and port.
     pub fn url(&self, path: &str) -> Uri {
+        let (scheme, authority) = if self.uses_tls() {
+            ("https", format!("localhost:{}", self.bind_address.port()))
+        } else {
+            ("http", self.bind_address.to_string())
+        };
         Uri::builder()
-            .scheme("http")
-            .authority(format!("{}", self.bind_address).as_str())
+            .scheme(scheme)
+            .authority(authority.as_str())
             .path_and_query(path)
             .build()
             .expect("attempted to construct invalid URI")
     }
 
+    /// Returns a builder for a request to `path` (which may include a query
+    /// string) that can set headers, query parameters, a body, and the
+    /// expected status code before sending it with
+    /// [`TestRequestBuilder::send`], e.g.:
+    ///
+    /// ```no_run
+    /// # use dropshot::test_util::ClientTestContext;
+    /// # async fn f(client: &ClientTestContext) {
+    /// let response = client
+    ///     .request(http::Method::POST, "/projects")
+    ///     .header("x-custom", "value")
+    ///     .query(&[("dry_run", "true")])
+    ///     .json(&serde_json::json!({ "name": "project1" }))
+    ///     .expect_status(http::StatusCode::CREATED)
+    ///     .send()
+    ///     .await
+    ///     .expect("expected success");
+    /// # }
+    /// ```
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> TestRequestBuilder<'_> {
+        TestRequestBuilder {
+            client: self,
+            method,
+            path: path.to_string(),
+            query: Vec::new(),
+            headers: http::HeaderMap::new(),
+            body: Body::empty(),
+            expected_status: StatusCode::OK,
+        }
+    }
+
     /// Execute an HTTP request against the test server and perform basic
     /// validation of the result, including:
     ///
@@ -219,12 +528,222 @@ impl ClientTestContext {
         self.make_request_with_request(request, expected_status).await
     }
 
+    /// Makes `count` requests against the test server concurrently, each
+    /// built by `make_request` from its index (e.g., with
+    /// [`TestRequestBuilder::build`]), and returns their statuses, bodies, and
+    /// latencies, in order, for checking behavior under concurrency (e.g.,
+    /// locking, rate limits, or consistency between pages)
+    ///
+    /// If `simultaneous` is true, the requests wait for each other to be
+    /// ready before any of them is sent, so that they're sent as close to
+    /// simultaneously as possible.  Unlike
+    /// [`ClientTestContext::make_request`], the responses aren't validated.
+    ///
+    /// ```no_run
+    /// # use dropshot::test_util::ClientTestContext;
+    /// # async fn f(client: &ClientTestContext) {
+    /// let results = client
+    ///     .make_concurrent_requests(10, true, |i| {
+    ///         client
+    ///             .request(http::Method::PUT, "/counter")
+    ///             .json(&i)
+    ///             .build()
+    ///     })
+    ///     .await;
+    /// assert_eq!(results.count(http::StatusCode::CONFLICT), 9);
+    /// # }
+    /// ```
+    pub async fn make_concurrent_requests<F>(
+        &self,
+        count: usize,
+        simultaneous: bool,
+        mut make_request: F,
+    ) -> ConcurrentResults
+    where
+        F: FnMut(usize) -> Request<Body>,
+    {
+        let barrier = Arc::new(tokio::sync::Barrier::new(count));
+        let tasks = (0..count)
+            .map(|index| {
+                let mut request = make_request(index);
+                self.add_version(&mut request);
+                let response = self.send_request(request);
+                let barrier = Arc::clone(&barrier);
+                tokio::spawn(async move {
+                    if simultaneous {
+                        barrier.wait().await;
+                    }
+                    let start = Instant::now();
+                    let response = response
+                        .await
+                        .expect("failed to make request to server");
+                    let (parts, body) = response.into_parts();
+                    let body =
+                        to_bytes(body).await.expect("error reading body");
+                    ConcurrentResponse {
+                        index,
+                        status: parts.status,
+                        headers: parts.headers,
+                        body,
+                        latency: start.elapsed(),
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut responses = Vec::with_capacity(count);
+        for task in tasks {
+            responses.push(task.await.expect("request task panicked"));
+        }
+        ConcurrentResults { responses }
+    }
+
+    /// Makes `request` against the test server over a new connection,
+    /// injecting `fault` (see [`ClientFault`]), and returns whatever the
+    /// server sent back, for testing how the server copes with misbehaving
+    /// clients (e.g., with timeouts, or with [`crate::HandlerTaskMode`])
+    ///
+    /// The request is sent as HTTP/1.1 with "Connection: close" (and a
+    /// "Content-Length" for the body, unless it already has one), so that the
+    /// server closes the connection after responding.  Unlike
+    /// [`ClientTestContext::make_request`], the response isn't validated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the client uses TLS (see `ClientTestContext::with_tls`, which
+    /// requires the feature flag `"tls-test"`), since faults are injected into
+    /// the raw connection.
+    pub async fn make_faulty_request(
+        &self,
+        mut request: Request<Body>,
+        fault: ClientFault,
+    ) -> FaultyResponse {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        assert!(
+            !self.uses_tls(),
+            "faults can't be injected into TLS connections"
+        );
+        self.add_version(&mut request);
+        let (parts, body) = request.into_parts();
+        let body = to_bytes(body).await.expect("error reading request body");
+
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\n",
+            parts.method,
+            parts.uri.path_and_query().map_or("/", |p| p.as_str())
+        )
+        .into_bytes();
+        if !parts.headers.contains_key(http::header::HOST) {
+            head.extend(format!("host: {}\r\n", self.bind_address).bytes());
+        }
+        if !parts.headers.contains_key(http::header::CONTENT_LENGTH) {
+            head.extend(format!("content-length: {}\r\n", body.len()).bytes());
+        }
+        head.extend(b"connection: close\r\n");
+        for (name, value) in &parts.headers {
+            head.extend(name.as_str().bytes());
+            head.extend(b": ");
+            head.extend(value.as_bytes());
+            head.extend(b"\r\n");
+        }
+        head.extend(b"\r\n");
+
+        let mut stream = tokio::net::TcpStream::connect(self.bind_address)
+            .await
+            .expect("failed to connect to server");
+        info!(
+            method = %parts.method,
+            uri = %parts.uri,
+            fault = ?fault,
+            "client faulty request"
+        );
+        stream.write_all(&head).await.expect("failed to send request headers");
+
+        let mut raw = Vec::new();
+        let mut server_closed = false;
+        match fault {
+            ClientFault::ResetMidBody { after } => {
+                let sent = &body[..after.min(body.len())];
+                // The server may give up on the request (e.g., if it's too
+                // large) before the client gets this far.
+                let _ = stream.write_all(sent).await;
+                // Closing a connection with a zero linger time resets it.
+                socket2::SockRef::from(&stream)
+                    .set_linger(Some(Duration::ZERO))
+                    .unwrap();
+                drop(stream);
+            }
+            ClientFault::TrickleBody { chunk_size, interval } => {
+                for chunk in body.chunks(chunk_size.max(1)) {
+                    tokio::time::sleep(interval).await;
+                    if stream.write_all(chunk).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = stream.read_to_end(&mut raw).await;
+                server_closed = true;
+            }
+            ClientFault::StallAfterHeaders { duration } => {
+                let read = stream.read_to_end(&mut raw);
+                server_closed =
+                    matches!(tokio::time::timeout(duration, read).await, Ok(_));
+            }
+            ClientFault::DisconnectAfterResponseHeaders => {
+                let _ = stream.write_all(&body).await;
+                let mut buf = [0u8; 1024];
+                loop {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => {
+                            server_closed = true;
+                            break;
+                        }
+                        Ok(n) => raw.extend_from_slice(&buf[..n]),
+                    }
+                    if raw.windows(4).any(|w| w == b"\r\n\r\n") {
+                        break;
+                    }
+                }
+                drop(stream);
+            }
+        }
+
+        let status = raw
+            .strip_prefix(b"HTTP/1.1 ")
+            .and_then(|rest| rest.get(..3))
+            .and_then(|code| StatusCode::from_bytes(code).ok());
+        info!(status = ?status, server_closed, "client faulty response");
+        FaultyResponse { status, raw, server_closed }
+    }
+
+    /// Returns the current time according to the server's clock
+    fn now(&self) -> DateTime<Utc> {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Utc::now(),
+        }
+    }
+
+    /// Adds the API version to `request`, if this client was made with
+    /// [`ClientTestContext::with_version`]
+    fn add_version(&self, request: &mut Request<Body>) {
+        if let Some(version) = &self.version {
+            assert!(
+                self.version_policy.request_add_version(request, version),
+                "test client can't give the API version with policy {:?}",
+                self.version_policy
+            );
+        }
+    }
+
     pub async fn make_request_with_request(
         &self,
-        request: Request<Body>,
+        mut request: Request<Body>,
         expected_status: StatusCode,
     ) -> Result<Response<Body>, HttpErrorResponseBody> {
-        let time_before = chrono::offset::Utc::now().timestamp();
+        self.add_version(&mut request);
+
+        let time_before = self.now().timestamp();
         info!(
             method = %request.method(),
             uri = %request.uri(),
@@ -233,8 +752,7 @@ impl ClientTestContext {
         );
 
         let mut response = self
-            .client
-            .request(request)
+            .send_request(request)
             .await
             .expect("failed to make request to server");
 
@@ -249,25 +767,7 @@ impl ClientTestContext {
         // statically-defined above.
         let headers = response.headers();
         for (header_name, header_value) in headers {
-            let mut okay = false;
-            for allowed_header in ALLOWED_HEADERS.iter() {
-                if header_name == allowed_header.name {
-                    match allowed_header.value {
-                        AllowedValue::Any => {
-                            okay = true;
-                        }
-                        AllowedValue::OneOf(allowed_values) => {
-                            let header = header_value
-                                .to_str()
-                                .expect("Cannot turn header value to string");
-                            okay = allowed_values.contains(&header);
-                        }
-                    }
-                    break;
-                }
-            }
-
-            if !okay {
+            if !self.header_policy.allows(header_name, header_value) {
                 panic!("header name not in allowed list: \"{}\"", header_name);
             }
         }
@@ -282,7 +782,7 @@ impl ClientTestContext {
         //
         // Note that the Date header typically only has precision down to one
         // second, so we don't want to try to do a more precise comparison.
-        let time_after = chrono::offset::Utc::now().timestamp();
+        let time_after = self.now().timestamp();
         let date_header = headers
             .get(http::header::DATE)
             .expect("missing Date header")
@@ -331,12 +831,266 @@ impl ClientTestContext {
     }
 }
 
+/// Builds a request against the test server, made with
+/// [`ClientTestContext::request`]
+///
+/// The response is validated like [`ClientTestContext::make_request`], against
+/// the expected status code (by default, "200 OK").
+pub struct TestRequestBuilder<'a> {
+    client: &'a ClientTestContext,
+    method: Method,
+    path: String,
+    query: Vec<String>,
+    headers: http::HeaderMap,
+    body: Body,
+    expected_status: StatusCode,
+}
+
+impl<'a> TestRequestBuilder<'a> {
+    /// Adds a header to the request (in addition to any others with the same
+    /// name)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` is invalid.
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        http::HeaderName: TryFrom<K>,
+        <http::HeaderName as TryFrom<K>>::Error: Debug,
+        http::HeaderValue: TryFrom<V>,
+        <http::HeaderValue as TryFrom<V>>::Error: Debug,
+    {
+        let name =
+            http::HeaderName::try_from(name).expect("invalid header name");
+        let value =
+            http::HeaderValue::try_from(value).expect("invalid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Adds `params` (e.g., a struct, or a slice of name-value pairs) to the
+    /// request's query string, after any given in the path or by earlier calls
+    pub fn query<T: Serialize + ?Sized>(mut self, params: &T) -> Self {
+        let query = serde_urlencoded::to_string(params)
+            .expect("failed to encode query parameters");
+        if !query.is_empty() {
+            self.query.push(query);
+        }
+        self
+    }
+
+    /// Sets the request body to `body`, encoded as JSON (with a content type
+    /// of "application/json")
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        self.body = serde_json::to_string(body).unwrap().into();
+        self.headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(CONTENT_TYPE_JSON),
+        );
+        self
+    }
+
+    /// Sets the request body to `body`, URL-encoded (with a content type of
+    /// "application/x-www-form-urlencoded")
+    pub fn url_encoded<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        self.body = serde_urlencoded::to_string(body).unwrap().into();
+        self.headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(CONTENT_TYPE_URL_ENCODED),
+        );
+        self
+    }
+
+    /// Sets the raw request body, without setting a content type
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the status code that the response is expected to have
+    pub fn expect_status(mut self, expected_status: StatusCode) -> Self {
+        self.expected_status = expected_status;
+        self
+    }
+
+    /// Returns the request without sending it (e.g., for
+    /// [`ClientTestContext::make_concurrent_requests`]), ignoring the
+    /// expected status code
+    pub fn build(self) -> Request<Body> {
+        let mut path = self.path;
+        for query in &self.query {
+            path.push(if path.contains('?') { '&' } else { '?' });
+            path.push_str(query);
+        }
+        let mut request = Request::builder()
+            .method(self.method)
+            .uri(self.client.url(&path))
+            .body(self.body)
+            .expect("attempted to construct invalid request");
+        *request.headers_mut() = self.headers;
+        request
+    }
+
+    /// Makes the request, validating the response like
+    /// [`ClientTestContext::make_request_with_request`]
+    pub async fn send(self) -> Result<Response<Body>, HttpErrorResponseBody> {
+        let client = self.client;
+        let expected_status = self.expected_status;
+        client.make_request_with_request(self.build(), expected_status).await
+    }
+}
+
+/// A client-side fault to inject into a request with
+/// [`ClientTestContext::make_faulty_request`]
+#[derive(Clone, Copy, Debug)]
+pub enum ClientFault {
+    /// Send the request headers and the first `after` bytes of the body, then
+    /// reset the connection (with a TCP RST) without waiting for a response
+    ResetMidBody { after: usize },
+    /// Send the body `chunk_size` bytes at a time, waiting `interval` before
+    /// each chunk, then wait for the response
+    TrickleBody { chunk_size: usize, interval: Duration },
+    /// Send only the request headers, never the body, and wait up to
+    /// `duration` for the server to respond and close the connection
+    StallAfterHeaders { duration: Duration },
+    /// Send the whole request, then close the connection as soon as the
+    /// response headers have arrived, without reading the body
+    DisconnectAfterResponseHeaders,
+}
+
+/// What the server sent back to [`ClientTestContext::make_faulty_request`]
+#[derive(Debug)]
+pub struct FaultyResponse {
+    /// status of the response, if the server started one
+    pub status: Option<StatusCode>,
+    /// everything received from the server (the response headers and any of
+    /// the body that was read, as sent on the wire)
+    pub raw: Vec<u8>,
+    /// whether the server closed the connection before the client did
+    pub server_closed: bool,
+}
+
+/// One of the responses to [`ClientTestContext::make_concurrent_requests`]
+#[derive(Debug)]
+pub struct ConcurrentResponse {
+    /// index of the request (as given to the function that built it)
+    pub index: usize,
+    pub status: StatusCode,
+    pub headers: http::HeaderMap,
+    pub body: bytes::Bytes,
+    /// time from sending the request to receiving the whole response body
+    pub latency: Duration,
+}
+
+/// The responses to [`ClientTestContext::make_concurrent_requests`], with
+/// summaries of their statuses and latencies
+#[derive(Debug)]
+pub struct ConcurrentResults {
+    /// responses, in the order of their requests
+    pub responses: Vec<ConcurrentResponse>,
+}
+
+impl ConcurrentResults {
+    /// Returns the number of responses with each status code
+    pub fn status_counts(&self) -> BTreeMap<StatusCode, usize> {
+        let mut counts = BTreeMap::new();
+        for response in &self.responses {
+            *counts.entry(response.status).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Returns the number of responses with status code `status`
+    pub fn count(&self, status: StatusCode) -> usize {
+        self.responses.iter().filter(|r| r.status == status).count()
+    }
+
+    /// Returns the latency that `percentile` percent (from 0 to 100) of the
+    /// requests took at most (by the nearest-rank method), or zero if there
+    /// were no requests
+    pub fn latency_percentile(&self, percentile: f64) -> Duration {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "percentile must be between 0 and 100"
+        );
+        let mut latencies =
+            self.responses.iter().map(|r| r.latency).collect::<Vec<_>>();
+        latencies.sort();
+        let rank =
+            (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied().unwrap_or(Duration::ZERO)
+    }
+
+    /// Returns the longest latency of any request (zero if there were none)
+    pub fn latency_max(&self) -> Duration {
+        self.latency_percentile(100.0)
+    }
+}
+
+/// A [`Clock`] that tests control, so that they can move the time forward
+/// rather than sleeping (e.g., to expire something)
+///
+/// The clock starts out following the system clock.  It can be moved forward
+/// with [`TestClock::advance`] (after which it still runs, but ahead of the
+/// system clock), or stopped with [`TestClock::freeze`] or
+/// [`TestClock::set`].  Clones share the same time, so a test can keep one
+/// and give another to the server (as [`TestContext`] does).
+#[derive(Clone, Debug, Default)]
+pub struct TestClock(Arc<std::sync::Mutex<TestClockState>>);
+
+#[derive(Debug, Default)]
+struct TestClockState {
+    /// how far ahead of the system clock this clock is, while it's running
+    offset: chrono::Duration,
+    /// the time this clock is stopped at, if it's stopped
+    frozen: Option<DateTime<Utc>>,
+}
+
+impl TestClock {
+    /// Returns a clock that follows the system clock until it's changed
+    pub fn new() -> TestClock {
+        TestClock::default()
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let duration = chrono::Duration::from_std(duration)
+            .expect("duration out of range");
+        let mut state = self.0.lock().unwrap();
+        match &mut state.frozen {
+            Some(frozen) => *frozen += duration,
+            None => state.offset += duration,
+        }
+    }
+
+    /// Stops the clock at the current time
+    pub fn freeze(&self) {
+        let now = self.now();
+        self.0.lock().unwrap().frozen = Some(now);
+    }
+
+    /// Stops the clock at `time`
+    pub fn set(&self, time: DateTime<Utc>) {
+        self.0.lock().unwrap().frozen = Some(time);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        let state = self.0.lock().unwrap();
+        state.frozen.unwrap_or_else(|| Utc::now() + state.offset)
+    }
+}
+
 /// TestContext is used to manage a matched server and client for the common
 /// test-case pattern of setting up a logger, server, and client and tearing them
 /// all down at the end.
 pub struct TestContext<Context: ServerContext> {
     pub client_testctx: ClientTestContext,
     pub server: HttpServer<Context>,
+    /// the server's clock, which tests can use to control the time (see
+    /// [`TestClock`])
+    pub clock: TestClock,
 }
 
 impl<Context: ServerContext> TestContext<Context> {
@@ -359,15 +1113,74 @@ impl<Context: ServerContext> TestContext<Context> {
         );
 
         // Set up the server itself.
+        let version_policy = api.version_policy.clone();
+        let clock = TestClock::new();
         let server =
             HttpServerStarter::new(&config_dropshot, api, None, private)
                 .unwrap()
+                .clock(Arc::new(clock.clone()))
                 .start();
 
         let server_addr = server.local_addr();
-        let client_testctx = ClientTestContext::new(server_addr);
+        let client_testctx =
+            ClientTestContext::new_versioned(server_addr, version_policy)
+                .with_clock(clock.clone());
+
+        TestContext { client_testctx, server, clock }
+    }
+
+    /// Like [`TestContext::new`], but the server serves HTTPS using `tls`, and
+    /// the client trusts its certificate
+    ///
+    /// If `tls` is `None`, the server uses a freshly generated self-signed
+    /// certificate for "localhost".  Otherwise, `tls` must be
+    /// [`ConfigTls::AsFile`] or [`ConfigTls::AsBytes`], with a certificate
+    /// valid for "localhost", and the last certificate in its chain is the
+    /// one that the client trusts.  (For other configurations, use
+    /// [`HttpServerStarter::new_with_tls`] and
+    /// [`ClientTestContext::with_tls`] directly.)
+    ///
+    /// This requires the feature flag `"tls-test"`.
+    #[cfg(feature = "tls-test")]
+    pub fn new_with_tls(
+        api: ApiDescription<Context>,
+        private: Context,
+        config_dropshot: &ConfigDropshot,
+        tls: Option<ConfigTls>,
+    ) -> TestContext<Context> {
+        assert_eq!(
+            0,
+            config_dropshot.bind_address.port(),
+            "test suite only supports binding on port 0 (any available port)"
+        );
+
+        let (tls, root_cert) = match tls {
+            Some(tls) => {
+                let root_cert = tls_root_cert(&tls);
+                (tls, root_cert)
+            }
+            None => self_signed_tls(),
+        };
+        let version_policy = api.version_policy.clone();
+        let clock = TestClock::new();
+        let server = HttpServerStarter::new_with_tls(
+            &config_dropshot,
+            api,
+            None,
+            private,
+            Some(tls),
+        )
+        .unwrap()
+        .clock(Arc::new(clock.clone()))
+        .start();
+
+        let server_addr = server.local_addr();
+        let client_testctx =
+            ClientTestContext::new_versioned(server_addr, version_policy)
+                .with_tls(root_cert)
+                .with_clock(clock.clone());
 
-        TestContext { client_testctx, server }
+        TestContext { client_testctx, server, clock }
     }
 
     /// Requests a graceful shutdown of the server, waits for that to complete,
@@ -378,6 +1191,61 @@ impl<Context: ServerContext> TestContext<Context> {
     }
 }
 
+/// Returns a client that can make requests over HTTP, or over HTTPS to servers
+/// identified by `roots`
+#[cfg(feature = "tls-test")]
+fn https_client(
+    roots: rustls::RootCertStore,
+) -> Client<HttpsConnector<HttpConnector>> {
+    let tls_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// Returns TLS configuration with a new self-signed certificate for
+/// "localhost", along with the certificate
+#[cfg(feature = "tls-test")]
+fn self_signed_tls() -> (ConfigTls, CertificateDer<'static>) {
+    let key_pair =
+        rcgen::KeyPair::generate().expect("key pair generation failed");
+    let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+        .expect("invalid certificate params")
+        .self_signed(&key_pair)
+        .expect("failed to generate self-signed certificate");
+    let tls = ConfigTls::AsBytes {
+        certs: cert.pem().into_bytes(),
+        key: key_pair.serialize_pem().into_bytes(),
+    };
+    (tls, cert.der().clone())
+}
+
+/// Returns the last certificate in the chain that `tls` identifies the server
+/// with
+#[cfg(feature = "tls-test")]
+fn tls_root_cert(tls: &ConfigTls) -> CertificateDer<'static> {
+    let pem = match tls {
+        ConfigTls::AsFile { cert_file, .. } => {
+            fs::read(cert_file).expect("failed to read certificate file")
+        }
+        ConfigTls::AsBytes { certs, .. } => certs.clone(),
+        _ => panic!(
+            "TestContext::new_with_tls only supports ConfigTls::AsFile and \
+             ConfigTls::AsBytes"
+        ),
+    };
+    rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .expect("invalid certificate chain")
+        .pop()
+        .expect("certificate chain is empty")
+}
+
 /// Given a Hyper Response whose body is expected to represent newline-separated
 /// JSON, each line of which is expected to be parseable via Serde as type T,
 /// asynchronously read the body of the response and parse it accordingly,
@@ -409,16 +1277,209 @@ pub async fn read_ndjson<T: DeserializeOwned>(
         .collect::<Vec<T>>()
 }
 
+/// Given a Hyper response whose body is a stream of NDJSON values or of
+/// server-sent events (depending on its content type), returns a reader that
+/// parses them as type T one at a time, as they arrive
+///
+/// Unlike [`read_ndjson`], this doesn't wait for the whole body, so tests can
+/// check when each event arrives, e.g.:
+///
+/// ```no_run
+/// # use dropshot::test_util::read_events;
+/// # use std::time::Duration;
+/// # async fn f(mut response: http::Response<hyper::Body>) {
+/// let mut events = read_events::<u32>(&mut response);
+/// assert_eq!(events.next_within(Duration::from_secs(1)).await, 0);
+/// events.assert_none_within(Duration::from_millis(100)).await;
+/// assert_eq!(events.next_within(Duration::from_secs(1)).await, 1);
+/// events.assert_end_within(Duration::from_secs(1)).await;
+/// # }
+/// ```
+///
+/// Each server-sent event's data (with multiple "data" lines joined by
+/// newlines) is parsed as JSON.  Other fields, comments, and events without
+/// data are ignored.
+pub fn read_events<T: DeserializeOwned>(
+    response: &mut Response<Body>,
+) -> EventReader<'_, T> {
+    let content_type = response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .expect("missing content-type");
+    let sse = if content_type == crate::CONTENT_TYPE_NDJSON {
+        false
+    } else if content_type == crate::CONTENT_TYPE_EVENT_STREAM {
+        true
+    } else {
+        panic!("unexpected content-type: {:?}", content_type);
+    };
+    EventReader {
+        body: response.body_mut(),
+        sse,
+        buf: Vec::new(),
+        data: None,
+        done: false,
+        _phantom: std::marker::PhantomData,
+    }
+}
+
+/// Reads the events in a streaming response body, made with [`read_events`]
+pub struct EventReader<'a, T> {
+    body: &'a mut Body,
+    /// whether the body consists of server-sent events (or else NDJSON)
+    sse: bool,
+    /// received data that hasn't been parsed yet
+    buf: Vec<u8>,
+    /// data of the server-sent event in progress
+    data: Option<String>,
+    /// whether the body has ended
+    done: bool,
+    _phantom: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<'a, T: DeserializeOwned> EventReader<'a, T> {
+    /// Waits for the next event, returning `None` once the body ends
+    ///
+    /// This is cancel-safe, so it can be raced against a timeout without
+    /// losing events.
+    pub async fn next(&mut self) -> Option<T> {
+        use hyper::body::HttpBody;
+
+        loop {
+            if let Some(event) = self.parse_buffered() {
+                return Some(event);
+            }
+            if self.done {
+                return None;
+            }
+            match self.body.data().await {
+                Some(Ok(chunk)) => self.buf.extend_from_slice(&chunk),
+                Some(Err(e)) => panic!("error reading body: {:#}", e),
+                None => {
+                    // A final NDJSON value needn't end with a newline, but a
+                    // server-sent event that isn't finished is dropped.
+                    self.done = true;
+                    if !self.sse && !self.buf.is_empty() {
+                        self.buf.push(b'\n');
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next event
+    ///
+    /// # Panics
+    ///
+    /// Panics if no event arrives in time, or if the body ends first.
+    pub async fn next_within(&mut self, timeout: Duration) -> T {
+        match tokio::time::timeout(timeout, self.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => panic!("body ended while waiting for an event"),
+            Err(_) => panic!("no event within {:?}", timeout),
+        }
+    }
+
+    /// Checks that no event arrives for `duration` (although the body may end)
+    pub async fn assert_none_within(&mut self, duration: Duration) {
+        if let Ok(Some(_)) = tokio::time::timeout(duration, self.next()).await {
+            panic!("unexpected event within {:?}", duration);
+        }
+    }
+
+    /// Checks that the body ends, without any more events, within `timeout`
+    pub async fn assert_end_within(&mut self, timeout: Duration) {
+        match tokio::time::timeout(timeout, self.next()).await {
+            Ok(None) => (),
+            Ok(Some(_)) => panic!("unexpected event before end of body"),
+            Err(_) => panic!("body did not end within {:?}", timeout),
+        }
+    }
+
+    /// Parses the next event from complete lines that have been received
+    fn parse_buffered(&mut self) -> Option<T> {
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let mut line = self.buf.drain(..=end).collect::<Vec<_>>();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let line = String::from_utf8(line)
+                .expect("response contained non-UTF-8 bytes");
+
+            if !self.sse {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Some(
+                    serde_json::from_str(&line)
+                        .expect("failed to parse event as expected type"),
+                );
+            }
+
+            if line.is_empty() {
+                if let Some(data) = self.data.take() {
+                    return Some(
+                        serde_json::from_str(&data)
+                            .expect("failed to parse event as expected type"),
+                    );
+                }
+                continue;
+            }
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => {
+                    (field, value.strip_prefix(' ').unwrap_or(value))
+                }
+                None => (line.as_str(), ""),
+            };
+            if field == "data" {
+                match &mut self.data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => self.data = Some(value.to_string()),
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Given a Hyper response, waits up to `timeout` for the next chunk of its
+/// body, returning `None` if the body has ended
+///
+/// # Panics
+///
+/// Panics if no chunk arrives in time, or if there's an error reading the
+/// body.
+pub async fn next_chunk_within(
+    response: &mut Response<Body>,
+    timeout: Duration,
+) -> Option<bytes::Bytes> {
+    use hyper::body::HttpBody;
+
+    match tokio::time::timeout(timeout, response.body_mut().data()).await {
+        Ok(chunk) => chunk.map(|c| c.expect("error reading body")),
+        Err(_) => panic!("no chunk within {:?}", timeout),
+    }
+}
+
 /// Given a Hyper response whose body is expected to be a JSON object that should
 /// be parseable via Serde as type T, asynchronously read the body of the
-/// response and parse it, returning an instance of T.
+/// response and parse it, returning an instance of T.  (RFC 9457
+/// `application/problem+json` bodies are accepted, too.)
 pub async fn read_json<T: DeserializeOwned>(
     response: &mut Response<Body>,
 ) -> T {
     let headers = response.headers();
-    assert_eq!(
-        crate::CONTENT_TYPE_JSON,
-        headers.get(http::header::CONTENT_TYPE).expect("missing content-type")
+    let content_type =
+        headers.get(http::header::CONTENT_TYPE).expect("missing content-type");
+    assert!(
+        content_type == crate::CONTENT_TYPE_JSON
+            || content_type == crate::CONTENT_TYPE_PROBLEM_JSON,
+        "unexpected content-type: {:?}",
+        content_type
     );
     let body_bytes =
         to_bytes(response.body_mut()).await.expect("error reading body");
@@ -576,6 +1637,85 @@ pub async fn iter_collection<T: Clone + DeserializeOwned>(
     (rv, npages)
 }
 
+/// Stream the pages of a paginated collection.
+///
+/// Unlike [`iter_collection`], pages are fetched only as the stream is polled,
+/// so a test can stop partway through a large collection by dropping the
+/// stream, and can check each page as it arrives.
+pub fn stream_collection_pages<'a, T: DeserializeOwned + 'a>(
+    client: &'a ClientTestContext,
+    collection_url: &'a str,
+    initial_params: &'a str,
+    limit: usize,
+) -> impl Stream<Item = ResultsPage<T>> + 'a {
+    async_stream::stream! {
+        let mut url =
+            format!("{}?limit={}&{}", collection_url, limit, initial_params);
+        loop {
+            let page = objects_list_page::<T>(client, &url).await;
+            assert!(page.items.len() <= limit);
+            let next_page = page.next_page.clone();
+            yield page;
+            match next_page {
+                Some(token) => {
+                    url = format!(
+                        "{}?limit={}&page_token={}",
+                        collection_url, limit, token
+                    );
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Stream the items of a paginated collection, fetching pages as needed.
+///
+/// See [`stream_collection_pages`].
+pub fn stream_collection<'a, T: DeserializeOwned + 'a>(
+    client: &'a ClientTestContext,
+    collection_url: &'a str,
+    initial_params: &'a str,
+    limit: usize,
+) -> impl Stream<Item = T> + 'a {
+    stream_collection_pages(client, collection_url, initial_params, limit)
+        .flat_map(|page| futures::stream::iter(page.items))
+}
+
+/// Iterate a collection paginated by offset.
+pub async fn iter_collection_by_offset<T: Clone + DeserializeOwned>(
+    client: &ClientTestContext,
+    collection_url: &str,
+    initial_params: &str,
+    limit: usize,
+) -> (Vec<T>, usize) {
+    let mut rv = Vec::new();
+    let mut npages = 0;
+    let mut offset = Some(0);
+
+    while let Some(next) = offset {
+        let mut response = client
+            .make_request_with_body(
+                Method::GET,
+                &format!(
+                    "{}?limit={}&offset={}&{}",
+                    collection_url, limit, next, initial_params
+                ),
+                "".into(),
+                StatusCode::OK,
+            )
+            .await
+            .unwrap();
+        let page = read_json::<OffsetResultsPage<T>>(&mut response).await;
+        assert!(page.items.len() <= limit);
+        rv.extend_from_slice(&page.items);
+        npages += 1;
+        offset = page.next_offset;
+    }
+
+    (rv, npages)
+}
+
 static TEST_SUITE_LOGGER_ID: AtomicU32 = AtomicU32::new(0);
 
 /// Returns a unique prefix for log files generated by other processes.
@@ -620,6 +1760,491 @@ pub fn read_config<T: DeserializeOwned + Debug>(
     result
 }
 
+// Snapshot testing facilities
+
+/// Environment variable that, when set to "1", makes the snapshot assertions
+/// (re)write their snapshot files instead of checking them
+///
+/// `EXPECTORATE=overwrite`, which updates the rest of a package's checked-in
+/// test output, has the same effect.
+pub const SNAPSHOT_UPDATE_ENV: &str = "DROPSHOT_UPDATE_SNAPSHOTS";
+
+/// Placeholder that replaces request ids in snapshots by default
+pub const SNAPSHOT_REDACTED_REQUEST_ID: &str = "<request_id>";
+/// Placeholder that replaces timestamps in snapshots by default
+pub const SNAPSHOT_REDACTED_TIMESTAMP: &str = "<timestamp>";
+
+type SnapshotRule =
+    Box<dyn Fn(&str, &serde_json::Value) -> Option<serde_json::Value>>;
+
+/// Rules for replacing values that change from run to run (like request ids
+/// and timestamps) before a value is compared against its snapshot
+///
+/// Each rule is given the JSON pointer of a value (e.g., "/items/0/id") and
+/// the value itself, and returns a replacement, if any.  The first rule that
+/// returns a replacement wins, and the replacement is not itself searched.
+///
+/// The default rules replace the value of every "request_id" member with
+/// [`SNAPSHOT_REDACTED_REQUEST_ID`] and every string that's an RFC 3339
+/// timestamp with [`SNAPSHOT_REDACTED_TIMESTAMP`].
+pub struct SnapshotRedactions {
+    rules: Vec<SnapshotRule>,
+}
+
+impl SnapshotRedactions {
+    /// Returns a set of rules that redacts nothing
+    pub fn none() -> SnapshotRedactions {
+        SnapshotRedactions { rules: Vec::new() }
+    }
+
+    /// Replaces the value of every object member called `key`
+    pub fn key(self, key: &str, placeholder: &str) -> SnapshotRedactions {
+        let suffix = format!("/{}", key.replace('~', "~0").replace('/', "~1"));
+        let placeholder = serde_json::Value::from(placeholder);
+        self.with(move |pointer, _| {
+            pointer.ends_with(&suffix).then(|| placeholder.clone())
+        })
+    }
+
+    /// Replaces the value at exactly the JSON pointer `pointer`
+    pub fn pointer(
+        self,
+        pointer: &str,
+        placeholder: &str,
+    ) -> SnapshotRedactions {
+        let pointer = pointer.to_string();
+        let placeholder = serde_json::Value::from(placeholder);
+        self.with(move |p, _| (p == pointer).then(|| placeholder.clone()))
+    }
+
+    /// Replaces every string that's an RFC 3339 timestamp
+    pub fn timestamps(self, placeholder: &str) -> SnapshotRedactions {
+        let placeholder = serde_json::Value::from(placeholder);
+        self.with(move |_, value| {
+            value
+                .as_str()
+                .filter(|s| DateTime::parse_from_rfc3339(s).is_ok())
+                .map(|_| placeholder.clone())
+        })
+    }
+
+    /// Adds an arbitrary rule
+    pub fn with<F>(mut self, rule: F) -> SnapshotRedactions
+    where
+        F: Fn(&str, &serde_json::Value) -> Option<serde_json::Value> + 'static,
+    {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Returns a copy of `value` with all of the rules applied
+    pub fn apply(&self, value: &serde_json::Value) -> serde_json::Value {
+        self.apply_at(&mut String::new(), value)
+    }
+
+    fn apply_at(
+        &self,
+        pointer: &mut String,
+        value: &serde_json::Value,
+    ) -> serde_json::Value {
+        if let Some(replacement) =
+            self.rules.iter().find_map(|rule| rule(pointer, value))
+        {
+            return replacement;
+        }
+
+        let len = pointer.len();
+        let mut descend = |token: &str, child: &serde_json::Value| {
+            pointer.push('/');
+            pointer.push_str(&token.replace('~', "~0").replace('/', "~1"));
+            let child = self.apply_at(pointer, child);
+            pointer.truncate(len);
+            child
+        };
+        match value {
+            serde_json::Value::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| descend(&i.to_string(), item))
+                .collect(),
+            serde_json::Value::Object(members) => members
+                .iter()
+                .map(|(key, member)| (key.clone(), descend(key, member)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            _ => value.clone(),
+        }
+    }
+}
+
+impl Default for SnapshotRedactions {
+    fn default() -> SnapshotRedactions {
+        SnapshotRedactions::none()
+            .key("request_id", SNAPSHOT_REDACTED_REQUEST_ID)
+            .timestamps(SNAPSHOT_REDACTED_TIMESTAMP)
+    }
+}
+
+/// Returns the path of a file relative to the package being tested (unless
+/// it's absolute)
+fn package_path(path: &Utf8Path) -> Utf8PathBuf {
+    let base = std::env::var("CARGO_MANIFEST_DIR")
+        .unwrap_or_else(|_| String::from("."));
+    Utf8PathBuf::from(base).join(path)
+}
+
+/// Checks `value` (serialized as JSON, with the default
+/// [`SnapshotRedactions`] applied) against the snapshot file "`name`.json" in
+/// the package's "tests/snapshots" directory, panicking if they differ
+///
+/// If the environment variable [`SNAPSHOT_UPDATE_ENV`] is set to "1", the
+/// snapshot file is written instead.  Snapshots are never created otherwise,
+/// so a missing snapshot also fails the test.
+pub fn assert_json_snapshot<T: Serialize + ?Sized>(name: &str, value: &T) {
+    assert_json_snapshot_with(name, value, &SnapshotRedactions::default());
+}
+
+/// Like [`assert_json_snapshot`], but with the given redactions
+pub fn assert_json_snapshot_with<T: Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+    redactions: &SnapshotRedactions,
+) {
+    let value = serde_json::to_value(value).unwrap_or_else(|e| {
+        panic!("snapshot {:?}: serializing: {:#}", name, e)
+    });
+    check_snapshot(
+        &package_path(Utf8Path::new("tests/snapshots")),
+        name,
+        &redactions.apply(&value),
+        snapshot_update(),
+    );
+}
+
+/// Checks the OpenAPI document for `api` against the snapshot file
+/// "`name`.json" in the package's "tests/snapshots" directory
+///
+/// The document's title is `name` and its version is "0.0.0".  For an API that
+/// declares versions (see [`ApiDescription::api_versions`]), the document for
+/// each version is checked instead, against "`name`-`version`.json".  See
+/// [`assert_json_snapshot`] for how to update the snapshots.  No redactions
+/// are applied.
+pub fn assert_openapi_snapshot<C: ServerContext>(
+    name: &str,
+    api: &ApiDescription<C>,
+) {
+    for (version, spec) in openapi_documents(api, name) {
+        let name = match version {
+            Some(version) => format!("{}-{}", name, version),
+            None => name.to_string(),
+        };
+        let spec = spec.unwrap_or_else(|e| {
+            panic!("snapshot {:?}: generating OpenAPI document: {:#}", name, e)
+        });
+        assert_json_snapshot_with(&name, &spec, &SnapshotRedactions::none());
+    }
+}
+
+/// Returns the OpenAPI document for each version of `api` declared with
+/// [`ApiDescription::api_versions`], or the single "0.0.0" document of an API
+/// that declares none
+fn openapi_documents<C: ServerContext>(
+    api: &ApiDescription<C>,
+    title: &str,
+) -> Vec<(Option<Version>, serde_json::Result<serde_json::Value>)> {
+    let versions = api.openapi_all_versions(title);
+    if versions.is_empty() {
+        return vec![(None, api.openapi(title, "0.0.0").json())];
+    }
+    versions
+        .into_iter()
+        .map(|(version, definition)| (Some(version), definition.json()))
+        .collect()
+}
+
+/// Checks the OpenAPI document `openapi` against the checked-in file at `path`
+/// (relative to the package being tested, unless it's absolute), panicking
+/// with the differences between them if they don't match
+///
+/// Unlike [`assert_openapi_snapshot`], this keeps the document's title,
+/// version, and other metadata, so the file can be the spec that's published
+/// for the API, e.g.:
+///
+/// ```no_run
+/// # use dropshot::test_util::assert_openapi_file;
+/// # fn api() -> dropshot::ApiDescription<()> { unimplemented!() }
+/// assert_openapi_file(
+///     "openapi/my-api.json",
+///     api().openapi("My API", "1.0.0").description("My API"),
+/// );
+/// ```
+///
+/// See [`assert_json_snapshot`] for how to update the file.
+pub fn assert_openapi_file<C: ServerContext>(
+    path: impl AsRef<Utf8Path>,
+    openapi: &OpenApiDefinition<'_, C>,
+) {
+    let path = path.as_ref();
+    let spec = openapi.json().unwrap_or_else(|e| {
+        panic!("{:?}: generating OpenAPI document: {:#}", path, e)
+    });
+    check_snapshot_file(
+        &package_path(path),
+        path.as_str(),
+        &spec,
+        snapshot_update(),
+    );
+}
+
+/// Checks that the OpenAPI document for `api` doesn't break clients written
+/// against the document in the file `old_path`, panicking with the list of
+/// breaking changes (see [`compare_openapi`]) if it does
+///
+/// This is meant for a test that gates changes to an API on compatibility with
+/// a spec that was checked in or published earlier.  The title and version of
+/// the generated document don't matter, since they aren't compared.
+///
+/// For an API that declares versions (see [`ApiDescription::api_versions`]),
+/// `old_path` is instead a directory with a "`version`.json" file for each
+/// version, as written by [`ApiDescription::write_openapi_all_versions`].  The
+/// document for each version is checked against its file, versions without a
+/// file are taken to be new, and files for versions the API no longer declares
+/// are reported as breaking changes.
+pub fn assert_openapi_compatible<C: ServerContext>(
+    old_path: &Path,
+    api: &ApiDescription<C>,
+) {
+    let read = |path: &Path| {
+        let old = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("reading {:?}: {:#}", path, e));
+        serde_json::from_str::<serde_json::Value>(&old)
+            .unwrap_or_else(|e| panic!("parsing {:?}: {:#}", path, e))
+    };
+
+    let mut changes = Vec::new();
+    // Versions with a file in `old_path`, once we know the API is versioned
+    let mut old_versions: Option<BTreeSet<Version>> = None;
+    for (version, new) in openapi_documents(api, "compat") {
+        let new = new
+            .unwrap_or_else(|e| panic!("generating OpenAPI document: {:#}", e));
+        let Some(version) = version else {
+            changes.extend(
+                compare_openapi(&read(old_path), &new)
+                    .iter()
+                    .map(|change| change.to_string()),
+            );
+            continue;
+        };
+        let old_versions = old_versions.get_or_insert_with(|| {
+            fs::read_dir(old_path)
+                .unwrap_or_else(|e| panic!("reading {:?}: {:#}", old_path, e))
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    Version::parse(name.strip_suffix(".json")?).ok()
+                })
+                .collect()
+        });
+        if !old_versions.remove(&version) {
+            // This version is new.
+            continue;
+        }
+        let path = old_path.join(format!("{}.json", version));
+        changes.extend(
+            compare_openapi(&read(&path), &new)
+                .iter()
+                .map(|change| format!("version {}: {}", version, change)),
+        );
+    }
+    // Whatever's left are versions that clients may use but the API no
+    // longer provides.
+    changes.extend(
+        old_versions
+            .iter()
+            .flatten()
+            .map(|version| format!("version {}: removed", version)),
+    );
+
+    if changes.is_empty() {
+        return;
+    }
+    let changes = changes
+        .iter()
+        .map(|change| format!("  {}", change))
+        .collect::<Vec<_>>()
+        .join("\n");
+    panic!(
+        "OpenAPI document is not compatible with {:?}:\n{}\n",
+        old_path, changes
+    );
+}
+
+/// Returns whether the snapshot assertions should write their files instead of
+/// checking them (see [`SNAPSHOT_UPDATE_ENV`])
+fn snapshot_update() -> bool {
+    std::env::var(SNAPSHOT_UPDATE_ENV).as_deref() == Ok("1")
+        || std::env::var("EXPECTORATE").as_deref() == Ok("overwrite")
+}
+
+fn check_snapshot(
+    dir: &Utf8Path,
+    name: &str,
+    value: &serde_json::Value,
+    update: bool,
+) {
+    check_snapshot_file(
+        &dir.join(format!("{}.json", name)),
+        name,
+        value,
+        update,
+    )
+}
+
+fn check_snapshot_file(
+    path: &Utf8Path,
+    name: &str,
+    value: &serde_json::Value,
+    update: bool,
+) {
+    let actual = format!("{}\n", serde_json::to_string_pretty(value).unwrap());
+
+    if update {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .unwrap_or_else(|e| panic!("creating {:?}: {:#}", dir, e));
+        }
+        fs::write(path, actual)
+            .unwrap_or_else(|e| panic!("writing {:?}: {:#}", path, e));
+        return;
+    }
+
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "snapshot {:?}: reading {:?}: {:#} (set {}=1 to create it)",
+            name, path, e, SNAPSHOT_UPDATE_ENV
+        ),
+    };
+    if expected == actual {
+        return;
+    }
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    let (expected_line, actual_line) = loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => break (e.unwrap_or("<EOF>"), a.unwrap_or("<EOF>")),
+        }
+    };
+
+    // Summarize the differences by location in the document, which is easier
+    // to follow than a line diff of a large document.  If the snapshot isn't
+    // even JSON, fall back to showing the whole new value.
+    let details = match serde_json::from_str(&expected) {
+        Ok(expected) => snapshot_differences(&expected, value),
+        Err(_) => format!("full value:\n{}", actual),
+    };
+    panic!(
+        "snapshot {:?} does not match {:?} (set {}=1 to update it)\n\
+         first difference at line {}:\n\
+         - {}\n\
+         + {}\n\
+         {}",
+        name,
+        path,
+        SNAPSHOT_UPDATE_ENV,
+        line,
+        expected_line,
+        actual_line,
+        details
+    );
+}
+
+/// Describes the differences between the snapshot `expected` and the value it
+/// was checked against
+fn snapshot_differences(
+    expected: &serde_json::Value,
+    actual: &serde_json::Value,
+) -> String {
+    let mut differences = Vec::new();
+    json_diff("", expected, actual, &mut differences);
+    let count = differences.len();
+    let mut details = String::from("differences:\n");
+    for difference in differences.into_iter().take(SNAPSHOT_MAX_DIFFERENCES) {
+        writeln!(details, "  {}", difference).unwrap();
+    }
+    if count > SNAPSHOT_MAX_DIFFERENCES {
+        writeln!(
+            details,
+            "  ... and {} more",
+            count - SNAPSHOT_MAX_DIFFERENCES
+        )
+        .unwrap();
+    }
+    details
+}
+
+/// Maximum number of differences listed when a snapshot doesn't match
+const SNAPSHOT_MAX_DIFFERENCES: usize = 50;
+
+/// Appends to `out` a description of each difference between `expected` and
+/// `actual` (at the JSON pointer `pointer`): "- `pointer`" for a value only in
+/// `expected`, "+ `pointer`" for one only in `actual`, and
+/// "~ `pointer`: `old` -> `new`" for one that changed
+fn json_diff(
+    pointer: &str,
+    expected: &serde_json::Value,
+    actual: &serde_json::Value,
+    out: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    let child = |key: &str| {
+        format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"))
+    };
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                match actual.get(key) {
+                    Some(actual_value) => json_diff(
+                        &child(key),
+                        expected_value,
+                        actual_value,
+                        out,
+                    ),
+                    None => out.push(format!("- {}", child(key))),
+                }
+            }
+            for key in actual.keys() {
+                if !expected.contains_key(key) {
+                    out.push(format!("+ {}", child(key)));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (i, expected_value) in expected.iter().enumerate() {
+                match actual.get(i) {
+                    Some(actual_value) => json_diff(
+                        &child(&i.to_string()),
+                        expected_value,
+                        actual_value,
+                        out,
+                    ),
+                    None => out.push(format!("- {}", child(&i.to_string()))),
+                }
+            }
+            for i in expected.len()..actual.len() {
+                out.push(format!("+ {}", child(&i.to_string())));
+            }
+        }
+        (expected, actual) if expected != actual => {
+            out.push(format!("~ {}: {} -> {}", pointer, expected, actual));
+        }
+        _ => (),
+    }
+}
+
 // Bunyan testing facilities
 
 /// Represents a Bunyan log record.  This form does not support any non-standard
@@ -708,12 +2333,18 @@ mod test {
     const T1_STR: &str = "2020-03-24T00:00:00Z";
     const T2_STR: &str = "2020-03-25T00:00:00Z";
 
+    use super::check_snapshot;
+    use super::json_diff;
     use super::verify_bunyan_records;
     use super::verify_bunyan_records_sequential;
     use super::BunyanLogRecord;
     use super::BunyanLogRecordSpec;
+    use super::ClientTestContext;
+    use super::SnapshotRedactions;
+    use camino::Utf8PathBuf;
     use chrono::DateTime;
     use chrono::Utc;
+    use serde_json::json;
 
     fn make_dummy_record() -> BunyanLogRecord {
         let t1: DateTime<Utc> =
@@ -1033,4 +2664,156 @@ mod test {
         ];
         verify_bunyan_records_sequential(v2.iter(), None, None);
     }
+
+    #[test]
+    fn test_snapshot_redactions() {
+        let value = json!({
+            "request_id": "a3b1c9",
+            "time": T1_STR,
+            "items": [
+                { "request_id": 5, "name": "2020-03-24" },
+                { "a/b": "x", "c": "y" },
+            ],
+        });
+        assert_eq!(
+            SnapshotRedactions::default().apply(&value),
+            json!({
+                "request_id": "<request_id>",
+                "time": "<timestamp>",
+                "items": [
+                    { "request_id": "<request_id>", "name": "2020-03-24" },
+                    { "a/b": "x", "c": "y" },
+                ],
+            })
+        );
+        assert_eq!(SnapshotRedactions::none().apply(&value), value);
+
+        let redactions = SnapshotRedactions::none()
+            .pointer("/items/1/a~1b", "<ab>")
+            .key("c", "<c>")
+            .with(|pointer, _| (pointer == "/time").then(|| json!(0)));
+        assert_eq!(
+            redactions.apply(&value),
+            json!({
+                "request_id": "a3b1c9",
+                "time": 0,
+                "items": [
+                    { "request_id": 5, "name": "2020-03-24" },
+                    { "a/b": "<ab>", "c": "<c>" },
+                ],
+            })
+        );
+    }
+
+    fn snapshot_tempdir() -> (tempfile::TempDir, Utf8PathBuf) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir =
+            Utf8PathBuf::try_from(tempdir.path().join("snapshots")).unwrap();
+        (tempdir, dir)
+    }
+
+    #[test]
+    fn test_snapshot_update_and_check() {
+        let (_tempdir, dir) = snapshot_tempdir();
+        let value = json!({ "count": 3 });
+        check_snapshot(&dir, "widget", &value, true);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("widget.json")).unwrap(),
+            "{\n  \"count\": 3\n}\n"
+        );
+        check_snapshot(&dir, "widget", &value, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "set DROPSHOT_UPDATE_SNAPSHOTS=1 to create it")]
+    fn test_snapshot_missing() {
+        let (_tempdir, dir) = snapshot_tempdir();
+        check_snapshot(&dir, "widget", &json!({}), false);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "first difference at line 2:\n-   \"count\": 3\n\
+                               +   \"count\": 4"
+    )]
+    fn test_snapshot_mismatch() {
+        let (_tempdir, dir) = snapshot_tempdir();
+        check_snapshot(&dir, "widget", &json!({ "count": 3 }), true);
+        check_snapshot(&dir, "widget", &json!({ "count": 4 }), false);
+    }
+
+    #[test]
+    fn test_header_policy() {
+        let allowed = |client: &ClientTestContext, name: &str, value: &str| {
+            client.header_policy.allows(
+                &http::HeaderName::try_from(name).unwrap(),
+                &http::HeaderValue::try_from(value).unwrap(),
+            )
+        };
+
+        // By default, only the fixed set of headers is accepted, with any
+        // restrictions on their values.
+        let client = ClientTestContext::new("127.0.0.1:0".parse().unwrap());
+        assert!(allowed(&client, "content-type", "text/plain"));
+        assert!(allowed(&client, "transfer-encoding", "chunked"));
+        assert!(!allowed(&client, "transfer-encoding", "gzip"));
+        assert!(!allowed(&client, "x-rate-limit", "10"));
+
+        // Headers can be added (with any value) and removed.
+        let client = client
+            .with_allowed_header("X-Rate-Limit")
+            .with_allowed_header("transfer-encoding")
+            .without_allowed_header("content-type");
+        assert!(allowed(&client, "x-rate-limit", "10"));
+        assert!(allowed(&client, "transfer-encoding", "gzip"));
+        assert!(!allowed(&client, "content-type", "text/plain"));
+        let client = client.with_allowed_header("content-type");
+        assert!(allowed(&client, "content-type", "text/plain"));
+
+        // In deny-list mode, anything not denied is accepted.
+        let client = client.with_denied_headers(["x-secret"]);
+        assert!(allowed(&client, "x-anything", "1"));
+        assert!(!allowed(&client, "x-secret", "1"));
+        let client = client
+            .with_allowed_header("x-secret")
+            .without_allowed_header("X-Anything");
+        assert!(allowed(&client, "x-secret", "1"));
+        assert!(!allowed(&client, "x-anything", "1"));
+    }
+
+    #[test]
+    fn test_json_diff() {
+        let expected = json!({
+            "paths": {
+                "/projects": { "get": { "operationId": "list" } },
+                "/old": {}
+            },
+            "tags": ["a", "b"],
+            "info": { "version": "1.0.0" }
+        });
+        let actual = json!({
+            "paths": {
+                "/projects": { "get": { "operationId": "project_list" } },
+                "/new": {}
+            },
+            "tags": ["a", "b", "c"],
+            "info": { "version": "1.0.0" }
+        });
+        let mut differences = Vec::new();
+        json_diff("", &expected, &actual, &mut differences);
+        assert_eq!(
+            differences,
+            vec![
+                "- /paths/~1old",
+                "~ /paths/~1projects/get/operationId: \"list\" -> \
+                 \"project_list\"",
+                "+ /paths/~1new",
+                "+ /tags/2",
+            ]
+        );
+
+        let mut differences = Vec::new();
+        json_diff("", &expected, &expected, &mut differences);
+        assert!(differences.is_empty());
+    }
 }
@@ -0,0 +1,177 @@
+// Copyright 2024 Oxide Computer Company
+//! Authenticating TLS clients with certificates ("mutual TLS")
+//!
+//! See [`crate::ConfigTls::with_client_auth`].
+
+use crate::config::{ClientCertificateCheck, ConfigClientAuth, ConfigPem};
+use rustls::pki_types::{CertificateDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    CertificateError, DigitallySignedStruct, DistinguishedName, RootCertStore,
+    SignatureScheme,
+};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Certificate chain presented by a TLS client, which has been verified
+/// according to the server's [`ConfigClientAuth`]
+///
+/// Dropshot doesn't interpret the certificates any further: consumers can
+/// parse them with the X.509 library of their choice to determine the
+/// client's identity (e.g., from the subject of the end-entity certificate).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientCertificate {
+    chain: Arc<[CertificateDer<'static>]>,
+}
+
+impl ClientCertificate {
+    pub(crate) fn new(chain: &[CertificateDer<'_>]) -> Option<Self> {
+        if chain.is_empty() {
+            return None;
+        }
+        let chain = chain.iter().map(|c| c.clone().into_owned()).collect();
+        Some(ClientCertificate { chain })
+    }
+
+    /// Returns the client's own (DER-encoded) certificate
+    pub fn end_entity(&self) -> &CertificateDer<'static> {
+        &self.chain[0]
+    }
+
+    /// Returns the whole certificate chain, starting with the client's own
+    /// certificate and followed by any intermediate certificates that it
+    /// presented
+    pub fn chain(&self) -> &[CertificateDer<'static>] {
+        &self.chain
+    }
+}
+
+/// Builds the verifier for client certificates described by `config`
+pub(crate) fn client_cert_verifier(
+    config: &ConfigClientAuth,
+) -> std::io::Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    let ca_certs = read_pem(&config.ca_certs)?;
+    for cert in rustls_pemfile::certs(&mut ca_certs.as_slice()) {
+        let cert = cert.map_err(|e| {
+            io_error(format!("failed to load CA certificate: {}", e))
+        })?;
+        roots.add(cert).map_err(|e| {
+            io_error(format!("failed to load CA certificate: {}", e))
+        })?;
+    }
+
+    let mut crls = Vec::new();
+    for crl_config in &config.crls {
+        let pem = read_pem(crl_config)?;
+        for crl in rustls_pemfile::crls(&mut pem.as_slice()) {
+            crls.push(crl.map_err(|e| {
+                io_error(format!("failed to load revocation list: {}", e))
+            })?);
+        }
+    }
+
+    let mut builder =
+        WebPkiClientVerifier::builder(Arc::new(roots)).with_crls(crls);
+    if !config.required {
+        builder = builder.allow_unauthenticated();
+    }
+    let verifier = builder.build().map_err(|e| {
+        io_error(format!("invalid client authentication configuration: {}", e))
+    })?;
+
+    Ok(match &config.revocation_check {
+        None => verifier,
+        Some(check) => Arc::new(CheckedClientCertVerifier {
+            inner: verifier,
+            check: Arc::clone(check),
+        }),
+    })
+}
+
+fn read_pem(config: &ConfigPem) -> std::io::Result<Vec<u8>> {
+    match config {
+        ConfigPem::AsBytes(bytes) => Ok(bytes.clone()),
+        ConfigPem::AsFile(path) => std::fs::read(path).map_err(|e| {
+            io_error(format!("failed to open {}: {}", path.display(), e))
+        }),
+    }
+}
+
+fn io_error(err: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, err)
+}
+
+/// Applies [`ConfigClientAuth::revocation_check`] to certificates that are
+/// otherwise valid
+struct CheckedClientCertVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    check: ClientCertificateCheck,
+}
+
+impl std::fmt::Debug for CheckedClientCertVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CheckedClientCertVerifier")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientCertVerifier for CheckedClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified =
+            self.inner.verify_client_cert(end_entity, intermediates, now)?;
+        let mut chain = vec![end_entity.clone()];
+        chain.extend(intermediates.iter().cloned());
+        let certificate = ClientCertificate::new(&chain).unwrap();
+        if let Err(error) = (self.check)(&certificate) {
+            warn!(%error, "rejected client certificate");
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::Revoked,
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error>
+    {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error>
+    {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -8,6 +8,9 @@ use serde::{
 };
 
 /// Serialize an instance of T into a `BTreeMap<String, String>`.
+///
+/// T must be a struct whose fields are strings, numbers, booleans, unit enum
+/// variants, or newtypes around these.  Fields that are `None` are omitted.
 pub(crate) fn to_map<T>(input: &T) -> Result<BTreeMap<String, String>, MapError>
 where
     T: Serialize,
@@ -190,8 +193,9 @@ impl SerializeStruct for MapSerializeStruct {
         T: Serialize,
     {
         let mut serializer = StringSerializer;
-        let value = value.serialize(&mut serializer)?;
-        self.output.insert(key.to_string(), value);
+        if let Some(value) = value.serialize(&mut serializer)? {
+            self.output.insert(key.to_string(), value);
+        }
         Ok(())
     }
 
@@ -200,12 +204,20 @@ impl SerializeStruct for MapSerializeStruct {
     }
 }
 
-/// A trivial `Serializer` used to extract a `String`. One could imagine
-/// extending this to convert other scalars into strings, but for now we'll just
-/// work with strings.
+/// A trivial `Serializer` used to extract a `String` from a scalar.  `None`
+/// produces no string at all.
 struct StringSerializer;
+
+macro_rules! ser_display {
+    ($i:ident, $t:ty) => {
+        fn $i(self, v: $t) -> Result<Self::Ok, Self::Error> {
+            Ok(Some(v.to_string()))
+        }
+    };
+}
+
 impl<'a> Serializer for &'a mut StringSerializer {
-    type Ok = String;
+    type Ok = Option<String>;
     type Error = MapError;
 
     type SerializeSeq = Impossible<Self::Ok, Self::Error>;
@@ -216,33 +228,57 @@ impl<'a> Serializer for &'a mut StringSerializer {
     type SerializeStruct = Impossible<Self::Ok, Self::Error>;
     type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
 
-    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        Ok(v.to_string())
+    ser_display!(serialize_str, &str);
+    ser_display!(serialize_bool, bool);
+    ser_display!(serialize_i8, i8);
+    ser_display!(serialize_i16, i16);
+    ser_display!(serialize_i32, i32);
+    ser_display!(serialize_i64, i64);
+    ser_display!(serialize_u8, u8);
+    ser_display!(serialize_u16, u16);
+    ser_display!(serialize_u32, u32);
+    ser_display!(serialize_u64, u64);
+    ser_display!(serialize_f32, f32);
+    ser_display!(serialize_f64, f64);
+    ser_display!(serialize_char, char);
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: Serialize,
+    {
+        value.serialize(self)
     }
 
-    ser_err!(serialize_bool, _v: bool);
-    ser_err!(serialize_i8, _v: i8);
-    ser_err!(serialize_i16, _v: i16);
-    ser_err!(serialize_i32, _v: i32);
-    ser_err!(serialize_i64, _v: i64);
-    ser_err!(serialize_u8, _v: u8);
-    ser_err!(serialize_u16, _v: u16);
-    ser_err!(serialize_u32, _v: u32);
-    ser_err!(serialize_u64, _v: u64);
-    ser_err!(serialize_f32, _v: f32);
-    ser_err!(serialize_f64, _v: f64);
-    ser_err!(serialize_char, _v: char);
     ser_err!(serialize_bytes, _v: &[u8]);
-    ser_err!(serialize_none);
     ser_err!(serialize_unit);
-    ser_t_err!(serialize_some, _value: &T);
     ser_err!(serialize_unit_struct, _name: &'static str);
-    ser_err!(
-        serialize_unit_variant,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-    );
     ser_t_err!(
         serialize_newtype_variant,
         _name: &'static str,
@@ -250,7 +286,6 @@ impl<'a> Serializer for &'a mut StringSerializer {
         _variant: &'static str,
         _value: &T,
     );
-    ser_t_err!(serialize_newtype_struct, _name: &'static str, _value: &T);
 
     fn serialize_seq(
         self,
@@ -349,18 +384,72 @@ mod test {
     }
 
     #[test]
-    fn test_to_map_num() {
+    fn test_to_map_scalars() {
         #[derive(Serialize)]
-        struct Bad {
+        enum Kind {
+            #[serde(rename = "big")]
+            Big,
+        }
+
+        #[derive(Serialize)]
+        struct Tag(String);
+
+        #[derive(Serialize)]
+        struct Valid {
+            a: u32,
+            b: i64,
+            c: bool,
+            d: f64,
+            e: Kind,
+            f: Tag,
+            g: Option<u64>,
+            h: Option<u64>,
+        }
+
+        let valid = Valid {
+            a: 0xb,
+            b: -3,
+            c: true,
+            d: 0.5,
+            e: Kind::Big,
+            f: Tag("\"v1\"".to_string()),
+            g: Some(7),
+            h: None,
+        };
+
+        let map = to_map(&valid).unwrap();
+
+        assert_eq!(
+            map.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("a".to_string(), "11".to_string()),
+                ("b".to_string(), "-3".to_string()),
+                ("c".to_string(), "true".to_string()),
+                ("d".to_string(), "0.5".to_string()),
+                ("e".to_string(), "big".to_string()),
+                ("f".to_string(), "\"v1\"".to_string()),
+                ("g".to_string(), "7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_map_nested() {
+        #[derive(Serialize)]
+        struct Inner {
             a: String,
-            b: u32,
         }
 
-        let bad = Bad { a: "a".to_string(), b: 0xb };
+        #[derive(Serialize)]
+        struct Bad {
+            a: Inner,
+        }
+
+        let bad = Bad { a: Inner { a: "a".to_string() } };
 
         assert_eq!(
             to_map(&bad),
-            Err(MapError("invalid type: u32".to_string()))
+            Err(MapError("cannot serialize a struct".to_string()))
         );
     }
 
@@ -0,0 +1,129 @@
+// Copyright 2024 Oxide Computer Company
+//! Accepting connections on a Unix domain socket
+//!
+//! See [`crate::ConfigUnixSocket`].
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// Address reported as both the local and remote address of connections
+/// accepted on a Unix domain socket, which have no IP address
+pub(crate) const UNIX_SOCKET_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// Credentials of the process on the other end of a Unix domain socket
+///
+/// These are determined by the operating system when the connection is
+/// established, so (unlike anything in the request itself) they can be relied
+/// upon to identify the client.  See
+/// [`crate::RequestInfo::peer_credentials`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    /// effective user id of the peer process
+    pub uid: u32,
+    /// effective group id of the peer process
+    pub gid: u32,
+    /// process id of the peer, on systems that report it
+    pub pid: Option<i32>,
+}
+
+#[cfg(unix)]
+pub(crate) use imp::*;
+
+#[cfg(unix)]
+mod imp {
+    use super::PeerCredentials;
+    use crate::config::ConfigUnixSocket;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::net::{UnixListener, UnixStream};
+
+    impl From<tokio::net::unix::UCred> for PeerCredentials {
+        fn from(cred: tokio::net::unix::UCred) -> PeerCredentials {
+            PeerCredentials {
+                uid: cred.uid(),
+                gid: cred.gid(),
+                pid: cred.pid(),
+            }
+        }
+    }
+
+    /// Implements `hyper::server::accept::Accept` for a Unix domain socket
+    pub(crate) struct UnixAcceptor(UnixListener);
+
+    impl UnixAcceptor {
+        /// Creates the socket described by `config` and begins listening on
+        /// it
+        pub(crate) fn bind(
+            config: &ConfigUnixSocket,
+        ) -> std::io::Result<UnixAcceptor> {
+            let listener = match config.mode {
+                None => std::os::unix::net::UnixListener::bind(&config.path)?,
+                Some(mode) => bind_with_mode(&config.path, mode)?,
+            };
+            listener.set_nonblocking(true)?;
+            // As with TCP, we use `from_std` to avoid requiring an async
+            // context here.
+            Ok(UnixAcceptor(UnixListener::from_std(listener)?))
+        }
+    }
+
+    /// Creates a socket at `path` whose permission bits are `mode` from the
+    /// moment it appears there
+    ///
+    /// Setting the mode after binding at `path` would leave a window in which
+    /// clients could connect with whatever permissions the umask allowed, and
+    /// changing the process-wide umask would affect other threads.  Instead,
+    /// the socket is bound in a new directory next to `path` that only we can
+    /// access, given its mode there, and then linked into place.  (Linking,
+    /// unlike renaming, fails if something already exists at `path`.)
+    fn bind_with_mode(
+        path: &Path,
+        mode: u32,
+    ) -> std::io::Result<std::os::unix::net::UnixListener> {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // `tempfile` creates directories with mode 0700.
+        let tempdir = tempfile::Builder::new().tempdir_in(parent)?;
+        let temp_path = tempdir.path().join("s");
+        let listener = std::os::unix::net::UnixListener::bind(&temp_path)?;
+        std::fs::set_permissions(
+            &temp_path,
+            std::fs::Permissions::from_mode(mode),
+        )?;
+        std::fs::hard_link(&temp_path, path)?;
+        // Dropping `tempdir` removes it along with the temporary link.
+        Ok(listener)
+    }
+
+    impl hyper::server::accept::Accept for UnixAcceptor {
+        type Conn = UnixStream;
+        type Error = std::io::Error;
+
+        fn poll_accept(
+            self: Pin<&mut Self>,
+            ctx: &mut Context,
+        ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+            loop {
+                match self.0.poll_accept(ctx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok((stream, _))) => {
+                        return Poll::Ready(Some(Ok(stream)))
+                    }
+                    // As with TLS, a connection that went away before we
+                    // got to it is not a reason to stop accepting others.
+                    Poll::Ready(Err(e))
+                        if e.kind()
+                            == std::io::ErrorKind::ConnectionAborted =>
+                    {
+                        continue
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                }
+            }
+        }
+    }
+}
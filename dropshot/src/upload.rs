@@ -0,0 +1,653 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for resumable uploads assembled from chunks
+//!
+//! Uploading a multi-gigabyte file in a single request means starting over
+//! whenever the connection drops.  Instead, clients can send the file as a
+//! series of requests (typically `PATCH`), each carrying one chunk of it and a
+//! `Content-Range` header saying where the chunk goes (e.g., `bytes
+//! 0-1048575/5000000`, or `bytes 0-1048575/*` if the total size isn't known
+//! yet).  After an interruption, the client asks how much the server has (the
+//! `Upload-Offset` header of any progress response) and resumes from there.
+//!
+//! Dropshot provides the pieces for endpoints implementing this:
+//!
+//! * The [`ContentRange`] extractor parses the chunk's `Content-Range` header.
+//! * [`UploadStore`] is the interface to wherever partial uploads are kept,
+//!   and [`TempFileUploadStore`] keeps them in files in a local directory.
+//! * [`receive_chunk`] appends a chunk to an upload, tolerating chunks that
+//!   are retried after being partly or completely received.
+//! * [`UploadProgress`] describes how much of an upload has been received.
+//!   It converts into an [`HttpResponseUploadProgress`]: a 204 ("No Content")
+//!   response with `Upload-Offset` (and, if known, `Upload-Length`) headers.
+//!
+//! Chunks must arrive in order: one that starts beyond the end of what's been
+//! received is rejected with a 409 ("Conflict"), as is one whose total size
+//! disagrees with that given by earlier chunks.
+//!
+//! ```
+//! use dropshot::endpoint;
+//! use dropshot::receive_chunk;
+//! use dropshot::ContentRange;
+//! use dropshot::HttpError;
+//! use dropshot::HttpResponseUploadProgress;
+//! use dropshot::Path;
+//! use dropshot::RequestContext;
+//! use dropshot::StreamingBody;
+//! use dropshot::TempFileUploadStore;
+//! use schemars::JsonSchema;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize, JsonSchema)]
+//! struct UploadPath {
+//!     upload_id: String,
+//! }
+//!
+//! #[endpoint {
+//!     method = PATCH,
+//!     path = "/uploads/{upload_id}",
+//! }]
+//! async fn upload_chunk(
+//!     rqctx: RequestContext<TempFileUploadStore>,
+//!     path: Path<UploadPath>,
+//!     range: ContentRange,
+//!     body: StreamingBody,
+//! ) -> Result<HttpResponseUploadProgress, HttpError> {
+//!     let store = rqctx.context();
+//!     let upload_id = path.into_inner().upload_id;
+//!     let progress = receive_chunk(store, &upload_id, range, body).await?;
+//!     if progress.is_complete() {
+//!         // The whole file is at `store.path(&upload_id)`.
+//!     }
+//!     Ok(progress.into_response())
+//! }
+//! ```
+
+use crate::api_description::{
+    ApiEndpointBodyContentType, ApiEndpointParameter,
+    ApiEndpointParameterLocation, ApiSchemaGenerator, ExtensionMode,
+};
+use crate::error::HttpError;
+use crate::extractor::StreamingBody;
+use crate::handler::{HttpResponseHeaders, HttpResponseUpdatedNoContent};
+use crate::server::ServerContext;
+use crate::{ExtractorMetadata, RequestContext, SharedExtractor};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use http::header;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+/// Maximum length of an upload id accepted by [`TempFileUploadStore`]
+const UPLOAD_ID_MAX_LEN: usize = 128;
+
+/// `ContentRange` is an extractor for the `Content-Range` header of a request
+/// carrying one chunk of an upload
+///
+/// Requests without a valid header of the form `bytes first-last/total` (or
+/// `bytes first-last/*`) are rejected with a 400 ("Bad Request").
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ContentRange {
+    /// offset of the first byte of the chunk
+    pub first: u64,
+    /// offset of the last byte of the chunk (inclusive)
+    pub last: u64,
+    /// total size of the upload, if the client knows it
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parses the value of a `Content-Range` header, returning `None` if it's
+    /// malformed or inconsistent
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let (unit, spec) = value.trim().split_once(' ')?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let (range, total) = spec.trim().split_once('/')?;
+        let (first, last) = range.split_once('-')?;
+        let parse_pos = |s: &str| {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                None
+            } else {
+                s.parse::<u64>().ok()
+            }
+        };
+        let first = parse_pos(first)?;
+        let last = parse_pos(last)?;
+        let total = match total {
+            "*" => None,
+            total => Some(parse_pos(total)?),
+        };
+        if first > last || total.map_or(false, |total| last >= total) {
+            return None;
+        }
+        Some(ContentRange { first, last, total })
+    }
+
+    /// Returns the number of bytes in the chunk
+    pub fn len(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+#[async_trait]
+impl SharedExtractor for ContentRange {
+    async fn from_request<Context: ServerContext>(
+        rqctx: &RequestContext<Context>,
+    ) -> Result<ContentRange, HttpError> {
+        let value =
+            rqctx.request.headers().get(header::CONTENT_RANGE).ok_or_else(
+                || {
+                    HttpError::for_bad_request(
+                        None,
+                        String::from("missing Content-Range header"),
+                    )
+                },
+            )?;
+        value.to_str().ok().and_then(ContentRange::parse).ok_or_else(|| {
+            HttpError::for_bad_request(
+                None,
+                format!("invalid Content-Range header: {:?}", value),
+            )
+        })
+    }
+
+    fn metadata(
+        _content_type: ApiEndpointBodyContentType,
+    ) -> ExtractorMetadata {
+        let schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        let parameter = ApiEndpointParameter::new_named(
+            &ApiEndpointParameterLocation::Header,
+            String::from("Content-Range"),
+            Some(String::from(
+                "position of this chunk within the upload (e.g., \"bytes \
+                 0-1023/4096\")",
+            )),
+            true,
+            ApiSchemaGenerator::Static {
+                schema: Box::new(schema.into()),
+                dependencies: indexmap::IndexMap::default(),
+            },
+            Vec::new(),
+        );
+        ExtractorMetadata {
+            parameters: vec![parameter],
+            extension_mode: ExtensionMode::None,
+        }
+    }
+}
+
+/// Storage for partially-received uploads
+///
+/// Each upload is a sequence of bytes that only ever grows at the end, and
+/// possibly the total size it will have once complete.  An upload that has
+/// never been written to is empty, with no known size.
+#[async_trait]
+pub trait UploadStore: Send + Sync {
+    /// Returns how much of upload `upload_id` has been stored
+    async fn progress(
+        &self,
+        upload_id: &str,
+    ) -> Result<UploadProgress, HttpError>;
+
+    /// Appends `data` to upload `upload_id`, which the caller expects to have
+    /// `offset` bytes so far and, if `total` is given, to have `total` bytes
+    /// once complete.  If it doesn't have `offset` bytes (e.g., because
+    /// another request appended to it concurrently), if `total` disagrees
+    /// with the size given with earlier chunks, or if `data` would extend it
+    /// beyond its size, this should fail with a 409 ("Conflict") rather than
+    /// store anything.  Otherwise, a `total` not known before is recorded.
+    async fn append(
+        &self,
+        upload_id: &str,
+        offset: u64,
+        total: Option<u64>,
+        data: Bytes,
+    ) -> Result<(), HttpError>;
+
+    /// Discards upload `upload_id`
+    async fn remove(&self, upload_id: &str) -> Result<(), HttpError>;
+}
+
+/// An [`UploadStore`] that keeps each upload in a file in a local directory
+///
+/// Upload ids are used as file names, so they may contain only ASCII letters,
+/// digits, `-`, and `_`, and be at most 128 characters long.  Other ids are
+/// rejected with a 400 ("Bad Request").  The total size of an upload, once
+/// known, is kept alongside it in a file with the suffix `.length`.
+///
+/// Appends to each upload are serialized, while different uploads proceed
+/// independently.  An upload's file is kept open between appends until the
+/// upload is complete.
+#[derive(Debug)]
+pub struct TempFileUploadStore {
+    dir: PathBuf,
+    // Holds the temporary directory (if any) so that it's removed on drop.
+    _tempdir: Option<tempfile::TempDir>,
+    uploads: std::sync::Mutex<BTreeMap<String, Arc<UploadSlot>>>,
+}
+
+/// The state of one upload in a [`TempFileUploadStore`], which is `None` when
+/// its file isn't open
+type UploadSlot = tokio::sync::Mutex<Option<OpenUpload>>;
+
+#[derive(Debug)]
+struct OpenUpload {
+    file: tokio::fs::File,
+    offset: u64,
+    total: Option<u64>,
+}
+
+impl TempFileUploadStore {
+    /// Creates a store in a new temporary directory, which is removed (along
+    /// with any uploads still in it) when the store is dropped
+    pub fn new() -> std::io::Result<TempFileUploadStore> {
+        let tempdir = tempfile::tempdir()?;
+        Ok(TempFileUploadStore {
+            dir: tempdir.path().to_path_buf(),
+            _tempdir: Some(tempdir),
+            uploads: std::sync::Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Creates a store in the existing directory `dir`, where uploads persist
+    /// until removed (e.g., so that they can be resumed after a restart)
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> TempFileUploadStore {
+        TempFileUploadStore {
+            dir: dir.as_ref().to_path_buf(),
+            _tempdir: None,
+            uploads: std::sync::Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the path of the file holding upload `upload_id`
+    pub fn path(&self, upload_id: &str) -> Result<PathBuf, HttpError> {
+        let valid = !upload_id.is_empty()
+            && upload_id.len() <= UPLOAD_ID_MAX_LEN
+            && upload_id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(HttpError::for_bad_request(
+                None,
+                format!("invalid upload id: {:?}", upload_id),
+            ));
+        }
+        Ok(self.dir.join(upload_id))
+    }
+
+    /// Returns the path of the file holding the total size of the upload
+    /// whose file is at `path`.  Since upload ids can't contain `.`, this never
+    /// collides with another upload's file.
+    fn length_path(path: &Path) -> PathBuf {
+        path.with_extension("length")
+    }
+
+    fn slot(&self, upload_id: &str) -> Arc<UploadSlot> {
+        let mut uploads = self.uploads.lock().unwrap();
+        Arc::clone(uploads.entry(upload_id.to_string()).or_default())
+    }
+
+    /// Reads the progress of the upload whose file is at `path` from disk
+    async fn read_progress(path: &Path) -> Result<UploadProgress, HttpError> {
+        let offset = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata.len(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(error) => return Err(upload_io_error(path, error)),
+        };
+        let length_path = Self::length_path(path);
+        let total = match tokio::fs::read_to_string(&length_path).await {
+            Ok(contents) => Some(contents.trim().parse().map_err(|_| {
+                HttpError::for_internal_error(format!(
+                    "upload length file {:?} is corrupt",
+                    length_path
+                ))
+            })?),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => return Err(upload_io_error(&length_path, error)),
+        };
+        Ok(UploadProgress { offset, total })
+    }
+}
+
+fn upload_io_error(path: &Path, error: std::io::Error) -> HttpError {
+    HttpError::for_internal_error(format!(
+        "accessing upload file {:?}: {}",
+        path, error
+    ))
+}
+
+#[async_trait]
+impl UploadStore for TempFileUploadStore {
+    async fn progress(
+        &self,
+        upload_id: &str,
+    ) -> Result<UploadProgress, HttpError> {
+        let path = self.path(upload_id)?;
+        let slot = self.slot(upload_id);
+        let open = slot.lock().await;
+        match &*open {
+            Some(upload) => Ok(UploadProgress {
+                offset: upload.offset,
+                total: upload.total,
+            }),
+            None => Self::read_progress(&path).await,
+        }
+    }
+
+    async fn append(
+        &self,
+        upload_id: &str,
+        offset: u64,
+        total: Option<u64>,
+        data: Bytes,
+    ) -> Result<(), HttpError> {
+        let path = self.path(upload_id)?;
+        let slot = self.slot(upload_id);
+        let mut open = slot.lock().await;
+        if open.is_none() {
+            let progress = Self::read_progress(&path).await?;
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .map_err(|e| upload_io_error(&path, e))?;
+            *open = Some(OpenUpload {
+                file,
+                offset: progress.offset,
+                total: progress.total,
+            });
+        }
+        let upload = open.as_mut().unwrap();
+
+        if upload.offset != offset {
+            return Err(upload_conflict(offset, upload.offset));
+        }
+        let total = match (upload.total, total) {
+            (Some(known), Some(given)) if known != given => {
+                return Err(upload_length_conflict(given, known));
+            }
+            (known, given) => known.or(given),
+        };
+        let end = offset + data.len() as u64;
+        if let Some(total) = total {
+            if end > total {
+                return Err(HttpError::for_client_error(
+                    None,
+                    StatusCode::CONFLICT,
+                    format!(
+                        "chunk ends at offset {}, beyond the upload's length \
+                         ({} bytes)",
+                        end, total
+                    ),
+                ));
+            }
+            if upload.total.is_none() {
+                let length_path = Self::length_path(&path);
+                tokio::fs::write(&length_path, total.to_string())
+                    .await
+                    .map_err(|e| upload_io_error(&length_path, e))?;
+                upload.total = Some(total);
+            }
+        }
+
+        // If a write fails partway, the file's length is no longer known, so
+        // it's reopened (and its length read again) on the next append.
+        let result = async {
+            upload.file.write_all(&data).await?;
+            upload.file.flush().await
+        }
+        .await;
+        if let Err(error) = result {
+            *open = None;
+            return Err(upload_io_error(&path, error));
+        }
+        upload.offset = end;
+
+        // Close the file once the upload is complete so that it can be used.
+        if upload.total == Some(upload.offset) {
+            *open = None;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, upload_id: &str) -> Result<(), HttpError> {
+        let path = self.path(upload_id)?;
+        let slot = self.slot(upload_id);
+        let mut open = slot.lock().await;
+        *open = None;
+        for path in [Self::length_path(&path), path] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => (),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => return Err(upload_io_error(&path, error)),
+            }
+        }
+        self.uploads.lock().unwrap().remove(upload_id);
+        Ok(())
+    }
+}
+
+fn upload_conflict(expected: u64, actual: u64) -> HttpError {
+    HttpError::for_client_error(
+        None,
+        StatusCode::CONFLICT,
+        format!(
+            "chunk starts at offset {}, but {} bytes have been received",
+            expected, actual
+        ),
+    )
+}
+
+fn upload_length_conflict(given: u64, known: u64) -> HttpError {
+    HttpError::for_client_error(
+        None,
+        StatusCode::CONFLICT,
+        format!(
+            "chunk gives the upload's length as {} bytes, but earlier chunks \
+             gave {} bytes",
+            given, known
+        ),
+    )
+}
+
+/// How much of an upload has been received
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UploadProgress {
+    /// number of bytes received
+    pub offset: u64,
+    /// total size of the upload, if known
+    pub total: Option<u64>,
+}
+
+/// Describes headers associated with an upload progress response.
+#[derive(JsonSchema, Serialize)]
+#[doc(hidden)]
+pub struct UploadProgressHeaders {
+    /// number of bytes of the upload received so far
+    #[serde(rename = "upload-offset")]
+    offset: u64,
+    /// total size of the upload, if known
+    #[serde(rename = "upload-length")]
+    length: Option<u64>,
+}
+
+/// See [`UploadProgress::into_response`]
+pub type HttpResponseUploadProgress =
+    HttpResponseHeaders<HttpResponseUpdatedNoContent, UploadProgressHeaders>;
+
+impl UploadProgress {
+    /// Returns whether the whole upload has been received
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.offset)
+    }
+
+    /// Returns a 204 ("No Content") response describing this progress in its
+    /// `Upload-Offset` and `Upload-Length` headers
+    pub fn into_response(self) -> HttpResponseUploadProgress {
+        HttpResponseHeaders::new(
+            HttpResponseUpdatedNoContent(),
+            UploadProgressHeaders { offset: self.offset, length: self.total },
+        )
+    }
+}
+
+/// Appends the chunk in `body`, whose position in the upload is given by
+/// `range`, to upload `upload_id` in `store`
+///
+/// Chunks that overlap what's already been received (e.g., because the client
+/// retried a request whose response it never saw) are fine: only the new part
+/// is stored.  A chunk that starts beyond the end of what's been received, or
+/// whose total size disagrees with that of earlier chunks, is rejected with a
+/// 409 ("Conflict"), and one whose body doesn't match the length given by
+/// `range` is rejected with a 400 ("Bad Request").  In the latter case, any
+/// part of the chunk that arrived before the problem was noticed is kept, so
+/// the client can resume after it.
+pub async fn receive_chunk<S: UploadStore + ?Sized>(
+    store: &S,
+    upload_id: &str,
+    range: ContentRange,
+    body: StreamingBody,
+) -> Result<UploadProgress, HttpError> {
+    let UploadProgress { mut offset, total } =
+        store.progress(upload_id).await?;
+    if range.first > offset {
+        return Err(upload_conflict(range.first, offset));
+    }
+    // This is checked again by the store when appending, but a chunk that
+    // was already received entirely isn't appended.
+    let total = match (total, range.total) {
+        (Some(known), Some(given)) if known != given => {
+            return Err(upload_length_conflict(given, known));
+        }
+        (known, given) => known.or(given),
+    };
+
+    let stream = body.into_stream();
+    tokio::pin!(stream);
+    // position in the upload of the next byte of the body
+    let mut position = range.first;
+    let end = range.last + 1;
+    while let Some(mut chunk) = stream.try_next().await? {
+        let chunk_len = chunk.len() as u64;
+        if chunk_len > end - position {
+            return Err(HttpError::for_bad_request(
+                None,
+                format!(
+                    "chunk is longer than its Content-Range ({} bytes)",
+                    range.len()
+                ),
+            ));
+        }
+        if position + chunk_len > offset {
+            // Skip whatever part of this we already have.
+            let skip = offset.saturating_sub(position);
+            let data = chunk.split_off(skip as usize);
+            let data_len = data.len() as u64;
+            store.append(upload_id, offset, range.total, data).await?;
+            offset += data_len;
+        }
+        position += chunk_len;
+    }
+
+    if position != end {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!(
+                "chunk is shorter than its Content-Range ({} of {} bytes)",
+                position - range.first,
+                range.len()
+            ),
+        ));
+    }
+
+    Ok(UploadProgress { offset, total })
+}
+
+#[cfg(test)]
+mod test {
+    use super::ContentRange;
+    use super::TempFileUploadStore;
+    use super::UploadProgress;
+    use super::UploadStore;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_content_range_parse() {
+        assert_eq!(
+            ContentRange::parse("bytes 0-99/1000"),
+            Some(ContentRange { first: 0, last: 99, total: Some(1000) })
+        );
+        assert_eq!(
+            ContentRange::parse("Bytes 100-199/*"),
+            Some(ContentRange { first: 100, last: 199, total: None })
+        );
+        assert_eq!(ContentRange::parse("bytes 0-0/1").unwrap().len(), 1);
+        assert_eq!(ContentRange::parse("bytes 5-4/10"), None);
+        assert_eq!(ContentRange::parse("bytes 0-10/10"), None);
+        assert_eq!(ContentRange::parse("bytes */10"), None);
+        assert_eq!(ContentRange::parse("bytes 0-+1/10"), None);
+        assert_eq!(ContentRange::parse("items 0-1/10"), None);
+        assert_eq!(ContentRange::parse("bytes=0-1/10"), None);
+    }
+
+    #[tokio::test]
+    async fn test_temp_file_upload_store() {
+        let store = TempFileUploadStore::new().unwrap();
+
+        // Of concurrent appends at the same offset, only one succeeds.
+        let results =
+            futures::future::join_all((0..4).map(|_| {
+                store.append("u1", 0, None, Bytes::from_static(b"abc"))
+            }))
+            .await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert_eq!(
+            store.progress("u1").await.unwrap(),
+            UploadProgress { offset: 3, total: None }
+        );
+
+        // The length is recorded with the first chunk that gives it, and
+        // persists across stores using the same directory.
+        store
+            .append("u1", 3, Some(6), Bytes::from_static(b"de"))
+            .await
+            .unwrap();
+        let error = store
+            .append("u1", 5, Some(7), Bytes::from_static(b"f"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::CONFLICT);
+        let error = store
+            .append("u1", 5, None, Bytes::from_static(b"fg"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code, http::StatusCode::CONFLICT);
+        let other = TempFileUploadStore::in_dir(&store.dir);
+        assert_eq!(
+            other.progress("u1").await.unwrap(),
+            UploadProgress { offset: 5, total: Some(6) }
+        );
+
+        store.append("u1", 5, None, Bytes::from_static(b"f")).await.unwrap();
+        let path = store.path("u1").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdef");
+
+        store.remove("u1").await.unwrap();
+        assert!(!path.exists());
+        assert_eq!(
+            store.progress("u1").await.unwrap(),
+            UploadProgress { offset: 0, total: None }
+        );
+    }
+}
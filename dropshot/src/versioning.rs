@@ -0,0 +1,842 @@
+// Copyright 2024 Oxide Computer Company
+//! Support for APIs with several versions
+//!
+//! An API may serve several versions (identified by semver) at once.  Each
+//! endpoint is in some range of versions (see [`ApiEndpointVersions`]), and
+//! an endpoint may be registered more than once for the same method and path,
+//! as long as their ranges don't overlap.  The server determines the version
+//! that each request is for according to the API's [`VersionPolicy`], and
+//! routes it to the endpoint that's in that version.  The OpenAPI definition
+//! for each version describes only the endpoints in that version (see
+//! [`crate::ApiDescription::openapi_all_versions`]).
+//!
+//! Clients may give the version in a header of their choosing (see
+//! [`ClientSpecifiesVersionInHeader`]), as a parameter of the media type in
+//! the "Accept" header (see [`ClientSpecifiesVersionInAcceptHeader`]), or at
+//! the start of the request's path (see [`ClientSpecifiesVersionInPath`]).
+//!
+//! A single handler can also serve versions in which the types of its request
+//! or response bodies differ, by converting between their forms (see
+//! [`ReplacesOlderVersion`]).
+//!
+//! Endpoints in old versions can be frozen (see
+//! [`crate::ApiEndpoint::frozen`]), so that an accidental change to them fails
+//! registration instead of breaking clients of those versions.
+//!
+//! ```
+//! use dropshot::endpoint;
+//! use dropshot::semver::Version;
+//! use dropshot::ApiDescription;
+//! use dropshot::ClientSpecifiesVersionInHeader;
+//! use dropshot::HttpError;
+//! use dropshot::HttpResponseOk;
+//! use dropshot::RequestContext;
+//! use dropshot::VersionPolicy;
+//! use std::sync::Arc;
+//!
+//! /// Returns the thing, as it was described before version 2.0.0
+//! #[endpoint {
+//!     method = GET,
+//!     path = "/thing",
+//!     versions = { until = "2.0.0" },
+//! }]
+//! async fn get_thing_v1(
+//!     _rqctx: RequestContext<()>,
+//! ) -> Result<HttpResponseOk<String>, HttpError> {
+//!     Ok(HttpResponseOk(String::from("thing")))
+//! }
+//!
+//! /// Returns the thing
+//! #[endpoint {
+//!     method = GET,
+//!     path = "/thing",
+//!     versions = { from = "2.0.0" },
+//! }]
+//! async fn get_thing(
+//!     _rqctx: RequestContext<()>,
+//! ) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+//!     Ok(HttpResponseOk(vec![String::from("thing")]))
+//! }
+//!
+//! let mut api = ApiDescription::new()
+//!     .api_versions([Version::new(1, 0, 0), Version::new(2, 0, 0)])
+//!     .version_policy(VersionPolicy::Dynamic(Arc::new(
+//!         ClientSpecifiesVersionInHeader::new(
+//!             "api-version".parse().unwrap(),
+//!             Version::new(2, 0, 0),
+//!         ),
+//!     )));
+//! api.register(get_thing_v1).unwrap();
+//! api.register(get_thing).unwrap();
+//! ```
+
+use crate::error::HttpError;
+use http::header::HeaderName;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use semver::Version;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// The versions of an API in which an endpoint appears
+///
+/// Ranges include their first version and exclude their last, so that an
+/// endpoint that's replaced in some version can be `Until` that version while
+/// its replacement is `From` it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum ApiEndpointVersions {
+    /// every version
+    #[default]
+    All,
+    /// this version and every later one
+    From(Version),
+    /// versions from the first (inclusive) until the second (exclusive)
+    FromUntil(Version, Version),
+    /// every version before this one
+    Until(Version),
+}
+
+impl ApiEndpointVersions {
+    /// Returns the versions from `from` (inclusive) until `until` (exclusive)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `until` isn't after `from`, since no versions would be in
+    /// the range.
+    pub fn from_until(from: Version, until: Version) -> ApiEndpointVersions {
+        assert!(
+            from.cmp_precedence(&until).is_lt(),
+            "versions range is empty: {} is not before {}",
+            from,
+            until
+        );
+        ApiEndpointVersions::FromUntil(from, until)
+    }
+
+    /// Returns the first version in the range, if it has one
+    fn start(&self) -> Option<&Version> {
+        match self {
+            ApiEndpointVersions::All | ApiEndpointVersions::Until(_) => None,
+            ApiEndpointVersions::From(from)
+            | ApiEndpointVersions::FromUntil(from, _) => Some(from),
+        }
+    }
+
+    /// Returns the first version after the range, if there is one
+    fn end(&self) -> Option<&Version> {
+        match self {
+            ApiEndpointVersions::All | ApiEndpointVersions::From(_) => None,
+            ApiEndpointVersions::Until(until)
+            | ApiEndpointVersions::FromUntil(_, until) => Some(until),
+        }
+    }
+
+    /// Returns whether `version` is in the range
+    ///
+    /// Versions are compared by semver precedence, which ignores build
+    /// metadata: "1.0.0+build.5" is in exactly the same ranges as "1.0.0".  A
+    /// prerelease precedes its release, so "2.0.0-beta.1" is in the range
+    /// `..2.0.0` but not `2.0.0..`.  (Servers can match prereleases with their
+    /// release instead; see [`PrereleaseMatching`].)
+    pub fn matches(&self, version: &Version) -> bool {
+        self.start().map_or(true, |start| start.cmp_precedence(version).is_le())
+            && self
+                .end()
+                .map_or(true, |end| version.cmp_precedence(end).is_lt())
+    }
+
+    /// Returns whether some version is in both this range and `other`
+    pub fn overlaps_with(&self, other: &ApiEndpointVersions) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Returns the range of versions in both this range and `other`, or
+    /// `None` if there are none
+    pub fn intersection(
+        &self,
+        other: &ApiEndpointVersions,
+    ) -> Option<ApiEndpointVersions> {
+        ApiEndpointVersions::from_bounds(
+            later_start(self.start(), other.start()),
+            earlier_end(self.end(), other.end()),
+        )
+    }
+
+    /// Returns the range of versions in either this range or `other`, or
+    /// `None` if that isn't a range, because there are versions between them
+    pub fn union(
+        &self,
+        other: &ApiEndpointVersions,
+    ) -> Option<ApiEndpointVersions> {
+        let ends_before =
+            |a: &ApiEndpointVersions, b: &ApiEndpointVersions| match (
+                a.end(),
+                b.start(),
+            ) {
+                (Some(end), Some(start)) => end.cmp_precedence(start).is_lt(),
+                _ => false,
+            };
+        if ends_before(self, other) || ends_before(other, self) {
+            return None;
+        }
+        ApiEndpointVersions::from_bounds(
+            earlier_start(self.start(), other.start()),
+            later_end(self.end(), other.end()),
+        )
+    }
+
+    /// Returns the gaps among `ranges`: the ranges of versions that are in
+    /// none of them, but that come between versions in some of them
+    ///
+    /// For example, the gaps among `..1.0.0` and `2.0.0..` are `1.0.0..2.0.0`.
+    /// This is useful for checking that each version of an API has some
+    /// endpoint for an operation (see
+    /// [`crate::ApiDescription::check_version_coverage`]).
+    pub fn gaps<'a, I>(ranges: I) -> Vec<ApiEndpointVersions>
+    where
+        I: IntoIterator<Item = &'a ApiEndpointVersions>,
+    {
+        let mut ranges = ranges.into_iter().collect::<Vec<_>>();
+        ranges.sort_by(|a, b| match (a.start(), b.start()) {
+            (None, None) => std::cmp::Ordering::Equal,
+            (None, Some(_)) => std::cmp::Ordering::Less,
+            (Some(_), None) => std::cmp::Ordering::Greater,
+            (Some(a), Some(b)) => a.cmp_precedence(b),
+        });
+
+        // The end of the versions covered by the ranges seen so far (or
+        // `None` before the first)
+        let mut covered: Option<Option<&Version>> = None;
+        let mut gaps = Vec::new();
+        for range in ranges {
+            if let (Some(Some(end)), Some(start)) = (covered, range.start()) {
+                if end.cmp_precedence(start).is_lt() {
+                    gaps.push(ApiEndpointVersions::FromUntil(
+                        end.clone(),
+                        start.clone(),
+                    ));
+                }
+            }
+            covered = Some(match covered {
+                None => range.end(),
+                Some(end) => later_end(end, range.end()),
+            });
+        }
+        gaps
+    }
+
+    /// Returns the range starting with `start` and ending before `end` (where
+    /// `None` means unbounded), or `None` if no versions are in it
+    fn from_bounds(
+        start: Option<&Version>,
+        end: Option<&Version>,
+    ) -> Option<ApiEndpointVersions> {
+        match (start, end) {
+            (None, None) => Some(ApiEndpointVersions::All),
+            (Some(start), None) => {
+                Some(ApiEndpointVersions::From(start.clone()))
+            }
+            (None, Some(end)) => Some(ApiEndpointVersions::Until(end.clone())),
+            (Some(start), Some(end)) => {
+                start.cmp_precedence(end).is_lt().then(|| {
+                    ApiEndpointVersions::FromUntil(start.clone(), end.clone())
+                })
+            }
+        }
+    }
+
+    /// Returns whether an endpoint in this range belongs in the OpenAPI
+    /// definition of `version`, if it's a semver
+    ///
+    /// Definitions whose version isn't a semver only describe endpoints that
+    /// are in every version.
+    pub(crate) fn documented_in(&self, version: Option<&Version>) -> bool {
+        match (self, version) {
+            (ApiEndpointVersions::All, _) => true,
+            (_, None) => false,
+            (versions, Some(version)) => versions.matches(version),
+        }
+    }
+}
+
+/// Returns the later of two ranges' first versions, where `None` (no first
+/// version) is the earliest
+fn later_start<'a>(
+    a: Option<&'a Version>,
+    b: Option<&'a Version>,
+) -> Option<&'a Version> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            Some(std::cmp::max_by(a, b, |a, b| a.cmp_precedence(b)))
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// Returns the earlier of two ranges' first versions
+fn earlier_start<'a>(
+    a: Option<&'a Version>,
+    b: Option<&'a Version>,
+) -> Option<&'a Version> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            Some(std::cmp::min_by(a, b, |a, b| a.cmp_precedence(b)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the earlier of two ranges' ends, where `None` (no end) is the
+/// latest
+fn earlier_end<'a>(
+    a: Option<&'a Version>,
+    b: Option<&'a Version>,
+) -> Option<&'a Version> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            Some(std::cmp::min_by(a, b, |a, b| a.cmp_precedence(b)))
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// Returns the later of two ranges' ends
+fn later_end<'a>(
+    a: Option<&'a Version>,
+    b: Option<&'a Version>,
+) -> Option<&'a Version> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            Some(std::cmp::max_by(a, b, |a, b| a.cmp_precedence(b)))
+        }
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for ApiEndpointVersions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiEndpointVersions::All => write!(f, "all versions"),
+            ApiEndpointVersions::From(from) => write!(f, "versions {}..", from),
+            ApiEndpointVersions::FromUntil(from, until) => {
+                write!(f, "versions {}..{}", from, until)
+            }
+            ApiEndpointVersions::Until(until) => {
+                write!(f, "versions ..{}", until)
+            }
+        }
+    }
+}
+
+/// A type whose form changed in some version of the API, replacing `Old`
+///
+/// This lets one handler serve requests for versions from both before and
+/// after the change, working only with the latest form:
+/// [`crate::VersionedBody`] converts request bodies sent in the older form
+/// into this one, and [`Versioned::for_request`] converts a response body back
+/// into the older form for requests that expect it.  (Changes too large for
+/// that are better made with separate handlers; see [`ApiEndpoint::versions`].)
+///
+/// [`ApiEndpoint::versions`]: crate::ApiEndpoint::versions
+pub trait ReplacesOlderVersion<Old>: From<Old> {
+    /// Returns the first version of the API in which the type takes this form
+    fn since() -> Version;
+
+    /// Returns whether requests for `version` of the API use the older form
+    ///
+    /// Requests to unversioned APIs use the latest form.
+    fn uses_older_form(version: Option<&Version>) -> bool {
+        version.is_some_and(|version| {
+            version.cmp_precedence(&Self::since()).is_lt()
+        })
+    }
+}
+
+/// A value in either the older form of a type, or its latest form `New` (see
+/// [`ReplacesOlderVersion`])
+///
+/// This serializes as whichever form it holds.  Its schema is that of the
+/// latest form, so the OpenAPI definitions of versions before the change
+/// should be checked (or adjusted with
+/// [`crate::ApiDescription::schema_visitor`]) if they need to be exact.
+#[derive(Debug)]
+pub enum Versioned<Old, New> {
+    /// the form used before [`ReplacesOlderVersion::since`]
+    Old(Old),
+    /// the latest form
+    New(New),
+}
+
+impl<Old, New> Versioned<Old, New>
+where
+    New: ReplacesOlderVersion<Old>,
+{
+    /// Returns `value` in the form that the request described by `rqctx`
+    /// expects
+    pub fn for_request<C: crate::ServerContext>(
+        rqctx: &crate::RequestContext<C>,
+        value: New,
+    ) -> Self
+    where
+        Old: From<New>,
+    {
+        if New::uses_older_form(rqctx.api_version.as_ref()) {
+            Versioned::Old(Old::from(value))
+        } else {
+            Versioned::New(value)
+        }
+    }
+
+    /// Returns the value in its latest form
+    pub fn into_latest(self) -> New {
+        match self {
+            Versioned::Old(old) => New::from(old),
+            Versioned::New(new) => new,
+        }
+    }
+}
+
+impl<Old: Serialize, New: Serialize> Serialize for Versioned<Old, New> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            Versioned::Old(old) => old.serialize(serializer),
+            Versioned::New(new) => new.serialize(serializer),
+        }
+    }
+}
+
+impl<Old, New: JsonSchema> JsonSchema for Versioned<Old, New> {
+    fn is_referenceable() -> bool {
+        New::is_referenceable()
+    }
+
+    fn schema_name() -> String {
+        New::schema_name()
+    }
+
+    fn schema_id() -> std::borrow::Cow<'static, str> {
+        New::schema_id()
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        New::json_schema(gen)
+    }
+}
+
+/// How requests for prerelease versions of the API (e.g., "2.0.0-beta.1") are
+/// matched with the versions of endpoints (see
+/// [`crate::ApiDescription::prerelease_matching`])
+///
+/// Either way, build metadata is ignored (see [`ApiEndpointVersions::matches`]).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PrereleaseMatching {
+    /// As semver orders them: a prerelease precedes its release, so a request
+    /// for "2.0.0-beta.1" is handled by the endpoints that are in versions
+    /// before 2.0.0
+    #[default]
+    Strict,
+    /// A prerelease is matched as though it were its release, so a request for
+    /// "2.0.0-beta.1" is handled by the endpoints that are in 2.0.0, which is
+    /// what clients of a prerelease usually want to try out
+    NextStable,
+}
+
+impl PrereleaseMatching {
+    /// Returns the version that endpoints' versions are matched with for a
+    /// request for `version`
+    pub(crate) fn matching_version(self, version: &Version) -> Version {
+        match self {
+            PrereleaseMatching::Strict => version.clone(),
+            PrereleaseMatching::NextStable => {
+                Version::new(version.major, version.minor, version.patch)
+            }
+        }
+    }
+}
+
+/// How the server determines the version of the API that each request is for
+#[derive(Clone, Debug, Default)]
+pub enum VersionPolicy {
+    /// The API isn't versioned.  Requests are routed without regard to the
+    /// versions of endpoints, so there may be only one endpoint for each
+    /// method and path.
+    #[default]
+    Unversioned,
+    /// Each request's version is determined by the given policy.
+    Dynamic(Arc<dyn DynamicVersionPolicy>),
+    /// Each request's version is given by the first segment of its path, as
+    /// in `/v2/thing`, which is removed before the request is routed.
+    PathPrefix(ClientSpecifiesVersionInPath),
+}
+
+impl VersionPolicy {
+    /// Returns the version that `request` is for, or `None` if the API isn't
+    /// versioned
+    ///
+    /// This may rewrite the request's URI to remove the version from it.
+    pub(crate) fn request_version(
+        &self,
+        request: &mut Request<Body>,
+    ) -> Result<Option<Version>, HttpError> {
+        match self {
+            VersionPolicy::Unversioned => Ok(None),
+            VersionPolicy::Dynamic(policy) => {
+                policy.request_extract_version(request).map(Some)
+            }
+            VersionPolicy::PathPrefix(policy) => {
+                policy.request_strip_version(request).map(Some)
+            }
+        }
+    }
+
+    /// Marks `request` as being for `version`, as a client would, returning
+    /// whether the policy knows how
+    pub(crate) fn request_add_version(
+        &self,
+        request: &mut Request<Body>,
+        version: &Version,
+    ) -> bool {
+        match self {
+            VersionPolicy::Unversioned => false,
+            VersionPolicy::Dynamic(policy) => {
+                policy.request_add_version(request, version)
+            }
+            VersionPolicy::PathPrefix(_) => {
+                let uri = request.uri();
+                let path_and_query = match uri.query() {
+                    Some(query) => {
+                        format!("/v{}{}?{}", version, uri.path(), query)
+                    }
+                    None => format!("/v{}{}", version, uri.path()),
+                };
+                let mut parts = uri.clone().into_parts();
+                parts.path_and_query = path_and_query.parse().ok();
+                match http::Uri::from_parts(parts) {
+                    Ok(uri) => {
+                        *request.uri_mut() = uri;
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+/// Function called for each request to a versioned API with the operation id
+/// of the endpoint that handles it, the version of the API that the client
+/// asked for, and the versions that the endpoint is in.  See
+/// [`crate::ApiDescription::version_observer`].
+pub type VersionObserver =
+    Arc<dyn Fn(&str, &Version, &ApiEndpointVersions) + Send + Sync>;
+
+/// Determines the version of the API that a request is for
+pub trait DynamicVersionPolicy: Debug + Send + Sync {
+    /// Returns the version that `request` is for, or the error to report to
+    /// the client if it doesn't identify a version that the server supports
+    fn request_extract_version(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Version, HttpError>;
+
+    /// Marks `request` as being for `version`, as a client would, returning
+    /// whether the policy knows how
+    ///
+    /// This is used by test clients (see
+    /// [`crate::test_util::ClientTestContext::with_version`]).  The default
+    /// implementation doesn't know how.
+    fn request_add_version(
+        &self,
+        _request: &mut Request<Body>,
+        _version: &Version,
+    ) -> bool {
+        false
+    }
+}
+
+/// Parses a version given as a media type parameter or path prefix, where
+/// "2" and "2.1" are short for "2.0.0" and "2.1.0"
+fn parse_short_version(value: &str) -> Option<Version> {
+    if let Ok(version) = Version::parse(value) {
+        return Some(version);
+    }
+    let mut parts = value.split('.').map(|part| {
+        if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse::<u64>().ok()
+        } else {
+            None
+        }
+    });
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Version::new(major, minor, 0))
+}
+
+/// Returns `version` if the server supports it, given that its latest version
+/// is `max_version`
+fn check_supported(
+    version: Version,
+    max_version: &Version,
+) -> Result<Version, HttpError> {
+    if version.cmp_precedence(max_version).is_gt() {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!("server does not support this API version: {}", version),
+        ));
+    }
+    Ok(version)
+}
+
+/// A [`DynamicVersionPolicy`] for clients that give the version in a header
+///
+/// Requests whose header isn't a semver, or that ask for a version later than
+/// the latest that the server supports, fail with a 400 ("Bad Request")
+/// response.  So do requests that lack the header, unless the policy has a
+/// default version (see [`ClientSpecifiesVersionInHeader::default_version`]).
+#[derive(Clone, Debug)]
+pub struct ClientSpecifiesVersionInHeader {
+    name: HeaderName,
+    max_version: Version,
+    default_version: Option<Version>,
+}
+
+impl ClientSpecifiesVersionInHeader {
+    /// Returns a policy that takes the version from the header `name`, for a
+    /// server whose latest version is `max_version`
+    pub fn new(
+        name: HeaderName,
+        max_version: Version,
+    ) -> ClientSpecifiesVersionInHeader {
+        ClientSpecifiesVersionInHeader {
+            name,
+            max_version,
+            default_version: None,
+        }
+    }
+
+    /// Treats requests that lack the header as being for `version` (e.g., the
+    /// latest stable version), rather than failing them
+    pub fn default_version(mut self, version: Version) -> Self {
+        self.default_version = Some(version);
+        self
+    }
+}
+
+impl DynamicVersionPolicy for ClientSpecifiesVersionInHeader {
+    fn request_extract_version(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Version, HttpError> {
+        let Some(value) = request.headers().get(&self.name) else {
+            return self.default_version.clone().ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    format!("missing expected header {:?}", self.name.as_str()),
+                )
+            });
+        };
+        let version = value
+            .to_str()
+            .ok()
+            .and_then(|value| Version::parse(value.trim()).ok())
+            .ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    format!(
+                        "header {:?}: expected a semver",
+                        self.name.as_str()
+                    ),
+                )
+            })?;
+        check_supported(version, &self.max_version)
+    }
+
+    fn request_add_version(
+        &self,
+        request: &mut Request<Body>,
+        version: &Version,
+    ) -> bool {
+        // A semver only contains characters that are valid in a header.
+        let value = http::HeaderValue::from_str(&version.to_string()).unwrap();
+        request.headers_mut().insert(self.name.clone(), value);
+        true
+    }
+}
+
+/// A [`DynamicVersionPolicy`] for clients that give the version as a parameter
+/// of the media type in the "Accept" header, as in `application/json;
+/// version=2`
+///
+/// The version may be a semver, or just its major (and minor) version, as in
+/// "2" or "2.1".  If the header lists several media types, the version is
+/// taken from the first that has the parameter.  Requests whose parameter
+/// isn't a version, or that ask for a version later than the latest that the
+/// server supports, fail with a 400 ("Bad Request") response.  So do requests
+/// with no such parameter, unless the policy has a default version (see
+/// [`ClientSpecifiesVersionInAcceptHeader::default_version`]).
+#[derive(Clone, Debug)]
+pub struct ClientSpecifiesVersionInAcceptHeader {
+    parameter: String,
+    max_version: Version,
+    default_version: Option<Version>,
+}
+
+impl ClientSpecifiesVersionInAcceptHeader {
+    /// Returns a policy that takes the version from the media type parameter
+    /// `parameter` (e.g., "version"), for a server whose latest version is
+    /// `max_version`
+    pub fn new(
+        parameter: &str,
+        max_version: Version,
+    ) -> ClientSpecifiesVersionInAcceptHeader {
+        ClientSpecifiesVersionInAcceptHeader {
+            parameter: parameter.to_ascii_lowercase(),
+            max_version,
+            default_version: None,
+        }
+    }
+
+    /// Treats requests with no version parameter as being for `version` (e.g.,
+    /// the latest stable version), rather than failing them
+    pub fn default_version(mut self, version: Version) -> Self {
+        self.default_version = Some(version);
+        self
+    }
+
+    /// Returns the value of the version parameter in `request`'s "Accept"
+    /// header, if it has one
+    fn find_parameter<'a>(
+        &self,
+        request: &'a Request<Body>,
+    ) -> Option<&'a str> {
+        request
+            .headers()
+            .get_all(http::header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .flat_map(|media_range| media_range.split(';').skip(1))
+            .find_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case(&self.parameter)
+                    .then(|| value.trim().trim_matches('"'))
+            })
+    }
+}
+
+impl DynamicVersionPolicy for ClientSpecifiesVersionInAcceptHeader {
+    fn request_extract_version(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Version, HttpError> {
+        let Some(value) = self.find_parameter(request) else {
+            return self.default_version.clone().ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    format!(
+                        "missing expected parameter {:?} in header \"accept\"",
+                        self.parameter
+                    ),
+                )
+            });
+        };
+        let version = parse_short_version(value).ok_or_else(|| {
+            HttpError::for_bad_request(
+                None,
+                format!(
+                    "header \"accept\": parameter {:?}: expected a version",
+                    self.parameter
+                ),
+            )
+        })?;
+        check_supported(version, &self.max_version)
+    }
+
+    fn request_add_version(
+        &self,
+        request: &mut Request<Body>,
+        version: &Version,
+    ) -> bool {
+        let accept = request
+            .headers()
+            .get(http::header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or(crate::CONTENT_TYPE_JSON);
+        let value = format!("{}; {}={}", accept, self.parameter, version);
+        match http::HeaderValue::from_str(&value) {
+            Ok(value) => {
+                request.headers_mut().insert(http::header::ACCEPT, value);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// The policy for clients that give the version as the first segment of the
+/// request's path, as in `/v2/thing` (see [`VersionPolicy::PathPrefix`])
+///
+/// The segment is "v" followed by a semver, or just its major (and minor)
+/// version, as in `/v2` or `/v2.1`.  It's removed from the request's path
+/// before routing, so endpoints are registered (and described in OpenAPI
+/// definitions) without it.  Requests whose path doesn't begin with a version,
+/// or that ask for a version later than the latest that the server supports,
+/// fail with a 400 ("Bad Request") response.
+#[derive(Clone, Debug)]
+pub struct ClientSpecifiesVersionInPath {
+    max_version: Version,
+}
+
+impl ClientSpecifiesVersionInPath {
+    /// Returns a policy for a server whose latest version is `max_version`
+    pub fn new(max_version: Version) -> ClientSpecifiesVersionInPath {
+        ClientSpecifiesVersionInPath { max_version }
+    }
+
+    /// Returns the version that `request` is for, having removed it from the
+    /// request's path
+    fn request_strip_version(
+        &self,
+        request: &mut Request<Body>,
+    ) -> Result<Version, HttpError> {
+        let uri = request.uri();
+        let path = uri.path().strip_prefix('/').unwrap_or(uri.path());
+        let (prefix, rest) = path.split_once('/').unwrap_or((path, ""));
+        let version = prefix
+            .strip_prefix('v')
+            .and_then(parse_short_version)
+            .ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    String::from(
+                        "expected path to begin with an API version \
+                         (e.g., \"/v1\")",
+                    ),
+                )
+            })?;
+        let version = check_supported(version, &self.max_version)?;
+
+        let path_and_query = match uri.query() {
+            Some(query) => format!("/{}?{}", rest, query),
+            None => format!("/{}", rest),
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().map_err(|_| {
+            HttpError::for_bad_request(None, String::from("invalid URI"))
+        })?);
+        *request.uri_mut() = http::Uri::from_parts(parts).map_err(|_| {
+            HttpError::for_bad_request(None, String::from("invalid URI"))
+        })?;
+        Ok(version)
+    }
+}
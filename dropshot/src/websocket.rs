@@ -5,24 +5,49 @@
 //!
 //! This exposes a raw upgraded HTTP connection to a user-provided async future,
 //! which will be spawned to handle the incoming connection.
+//!
+//! Dropshot doesn't otherwise implement the websockets protocol, but it
+//! follows the frames written and read on the connection closely enough to
+//! send queued messages and pings between them, to notice when the client has
+//! gone away (if keepalive is configured, see [`ConfigWebsocket`]), and to
+//! close the connection when the client sends too much.
 
 use crate::api_description::ExtensionMode;
+use crate::clock::Clock;
+use crate::config::ConfigWebsocket;
+use crate::server::ServerPhase;
+use crate::websocket_queue::WebsocketSendQueue;
+use crate::websocket_registry::{WebsocketRegistration, WebsocketRegistry};
 use crate::{
     ApiEndpointBodyContentType, ExclusiveExtractor, ExtractorMetadata,
-    HttpError, RequestContext, ServerContext,
+    HttpError, RequestContext, RequestInfo, ServerContext,
 };
 use async_trait::async_trait;
 use base64::Engine;
+use chrono::{DateTime, Utc};
+use debug_ignore::DebugIgnore;
 use http::header;
 use http::Response;
 use http::StatusCode;
 use hyper::upgrade::OnUpgrade;
+use hyper::upgrade::Upgraded;
 use hyper::Body;
 use schemars::JsonSchema;
 use serde_json::json;
 use sha1::{Digest, Sha1};
 use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Instant;
 use tracing::debug;
+use tracing::warn;
 
 /// WebsocketUpgrade is an ExclusiveExtractor used to upgrade and handle an HTTP
 /// request as a websocket when present in a Dropshot endpoint's function
@@ -48,15 +73,169 @@ pub type WebsocketEndpointResult = Result<Response<Body>, HttpError>;
 /// handler function. [`WebsocketConnection::into_inner`] can be used to
 /// access the raw upgraded connection, for passing to any implementation
 /// of the websockets protocol.
-pub struct WebsocketConnection(WebsocketConnectionRaw);
+pub struct WebsocketConnection {
+    raw: WebsocketConnectionRaw,
+    shutdown: WebsocketShutdown,
+    registry: Option<Arc<WebsocketRegistry>>,
+    /// tells the connection's task when it's disconnected through the registry
+    disconnect: Arc<tokio::sync::watch::Sender<bool>>,
+    subprotocol: Option<String>,
+    context: Arc<WebsocketContext>,
+}
+
+/// What the handler of an upgraded websocket connection knows about the
+/// request that was upgraded
+///
+/// This is captured before the upgrade, so that the handler can authorize
+/// what the client does on the connection without working it out again.
+/// Besides what Dropshot knows about the request, it carries the request's
+/// extensions, where middleware in front of Dropshot may have left the
+/// client's identity, and anything the endpoint added with
+/// [`WebsocketUpgrade::with_extension`] (such as the principal it
+/// authenticated).
+#[derive(Debug)]
+pub struct WebsocketContext {
+    /// unique id assigned to the request that was upgraded
+    pub request_id: String,
+    /// version of the API that the request was handled as, if the API is
+    /// versioned
+    pub api_version: Option<semver::Version>,
+    /// basic request information (method, URI, headers, the client's address
+    /// and certificate, etc.)
+    pub request: RequestInfo,
+    /// the request's extensions, along with any added by the endpoint
+    pub extensions: http::Extensions,
+}
+
+impl WebsocketContext {
+    /// Returns the extension of type `T`, if there is one
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+}
+
+/// Tells a websocket handler when the server begins shutting down, so that it
+/// can close the connection cleanly
+///
+/// Once this happens, the handler has until the end of the grace period (see
+/// [`ConfigWebsocket::shutdown_grace_period_ms`]) to finish.
+///
+/// ```
+/// use dropshot::WebsocketChannelResult;
+/// use dropshot::WebsocketConnection;
+/// use futures::{SinkExt, StreamExt};
+/// use tokio_tungstenite::tungstenite::protocol::Role;
+/// use tokio_tungstenite::tungstenite::Message;
+/// use tokio_tungstenite::WebSocketStream;
+///
+/// async fn handle(conn: WebsocketConnection) -> WebsocketChannelResult {
+///     let shutdown = conn.shutdown_signal();
+///     let mut ws = WebSocketStream::from_raw_socket(
+///         conn.into_inner(),
+///         Role::Server,
+///         None,
+///     )
+///     .await;
+///     loop {
+///         tokio::select! {
+///             msg = ws.next() => match msg {
+///                 Some(msg) => { let _ = msg?; /* ... */ }
+///                 None => return Ok(()),
+///             },
+///             () = shutdown.wait() => {
+///                 ws.close(None).await?;
+///                 return Ok(());
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct WebsocketShutdown(tokio::sync::watch::Receiver<ServerPhase>);
 
-/// A type that implements [tokio::io::AsyncRead] + [tokio::io::AsyncWrite].
-pub type WebsocketConnectionRaw = hyper::upgrade::Upgraded;
+impl WebsocketShutdown {
+    /// Returns whether the server has begun shutting down
+    pub fn is_shutting_down(&self) -> bool {
+        *self.0.borrow() == ServerPhase::ShuttingDown
+    }
+
+    /// Waits until the server begins shutting down
+    pub async fn wait(&self) {
+        // If the server is gone altogether, it's certainly shutting down.
+        let _ =
+            self.0.clone().wait_for(|p| *p == ServerPhase::ShuttingDown).await;
+    }
+}
+
+/// The raw upgraded connection held by a [`WebsocketConnection`], which
+/// implements [tokio::io::AsyncRead] + [tokio::io::AsyncWrite].
+///
+/// This also writes the messages queued with the connection's
+/// [`WebsocketSendQueue`] between the frames written to it, and if keepalive is
+/// configured (see [`ConfigWebsocket`]), sends pings there too and keeps track
+/// of the client's activity.
+/// If message limits are configured, it checks the frames read from it, and
+/// once the client exceeds a limit, it sends a Close frame and fails reads
+/// and writes from then on.
+pub struct WebsocketConnectionRaw {
+    upgraded: Upgraded,
+    monitor: ConnectionMonitor,
+}
 
 impl WebsocketConnection {
     /// Consumes `self` and returns the held raw connection.
     pub fn into_inner(self) -> WebsocketConnectionRaw {
-        self.0
+        self.raw
+    }
+
+    /// Returns a handle that tells when the server begins shutting down.
+    ///
+    /// Call this before [`WebsocketConnection::into_inner`].
+    pub fn shutdown_signal(&self) -> WebsocketShutdown {
+        self.shutdown.clone()
+    }
+
+    /// Returns what's known about the request that was upgraded (see
+    /// [`WebsocketContext`]).
+    ///
+    /// Call this before [`WebsocketConnection::into_inner`].
+    pub fn context(&self) -> Arc<WebsocketContext> {
+        Arc::clone(&self.context)
+    }
+
+    /// Returns the subprotocol chosen for the connection, if any (see
+    /// [`WebsocketUpgrade::with_subprotocols`]).
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+
+    /// Returns the queue of messages to be sent on the connection (see
+    /// [`WebsocketSendQueue`]).
+    ///
+    /// Under [`crate::WebsocketOverflowPolicy::Block`], don't wait for
+    /// [`WebsocketSendQueue::send`] in the same task that's reading or writing
+    /// the connection: queued messages are only written while it does so.
+    /// Call this before [`WebsocketConnection::into_inner`].
+    pub fn send_queue(&self) -> WebsocketSendQueue {
+        self.raw.monitor.send_queue.clone()
+    }
+
+    /// Registers the connection under `key` with the server's websocket
+    /// registry, so that the rest of the server can send it messages.
+    ///
+    /// Returns `None` if the server has no registry (see
+    /// [`crate::HttpServerStarter::websocket_registry`]).  Otherwise, the
+    /// handler should receive messages from the returned registration and
+    /// write them to the connection, and close the connection once
+    /// [`WebsocketRegistration::recv`] returns `None`.  Call this before
+    /// [`WebsocketConnection::into_inner`].
+    pub fn register(
+        &self,
+        key: impl Into<String>,
+    ) -> Option<WebsocketRegistration> {
+        self.registry.as_ref().map(|registry| {
+            registry.register(key.into(), Arc::clone(&self.disconnect))
+        })
     }
 }
 
@@ -64,7 +243,50 @@ impl WebsocketConnection {
 struct WebsocketUpgradeInner {
     upgrade_fut: OnUpgrade,
     accept_key: String,
+    /// subprotocols that the client offered, in its order of preference
+    subprotocol_offers: Vec<String>,
+    /// subprotocols that the handler speaks, in its order of preference
+    subprotocols: Vec<String>,
+    context: WebsocketContext,
     route: String,
+    permit: WebsocketConnectionPermit,
+    config: ConfigWebsocket,
+    shutting_down: tokio::sync::watch::Receiver<ServerPhase>,
+    aborting: tokio::sync::watch::Receiver<bool>,
+    registry: Option<Arc<WebsocketRegistry>>,
+    /// lets graceful shutdown wait for the handler to finish
+    worker: DebugIgnore<Option<waitgroup::Worker>>,
+    /// the server's clock, which paces the message rate limit
+    clock: Arc<dyn Clock>,
+}
+
+/// Accounts for one websocket connection in
+/// [`crate::DropshotState::websocket_connection_count`] for as long as it's
+/// held.
+#[derive(Debug)]
+struct WebsocketConnectionPermit(Arc<AtomicUsize>);
+
+impl WebsocketConnectionPermit {
+    /// Reserves a slot for a new websocket connection, failing if there are
+    /// already `max` connections.
+    fn acquire(
+        count: &Arc<AtomicUsize>,
+        max: Option<usize>,
+    ) -> Option<WebsocketConnectionPermit> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| match max {
+                Some(max) if n >= max => None,
+                _ => Some(n + 1),
+            })
+            .ok()
+            .map(|_| WebsocketConnectionPermit(Arc::clone(count)))
+    }
+}
+
+impl Drop for WebsocketConnectionPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 // Originally copied from tungstenite-0.17.3 (rather than taking a whole
@@ -85,7 +307,7 @@ fn derive_accept_key(request_key: &[u8]) -> String {
 #[async_trait]
 impl ExclusiveExtractor for WebsocketUpgrade {
     async fn from_request<Context: ServerContext>(
-        _rqctx: &RequestContext<Context>,
+        rqctx: &RequestContext<Context>,
         request: hyper::Request<hyper::Body>,
     ) -> Result<Self, HttpError> {
         if !request
@@ -144,10 +366,59 @@ impl ExclusiveExtractor for WebsocketUpgrade {
                 )
             })?;
 
+        let max_connections = rqctx.server.config.max_websocket_connections;
+        let permit = WebsocketConnectionPermit::acquire(
+            &rqctx.server.websocket_connections,
+            max_connections,
+        )
+        .ok_or_else(|| {
+            HttpError::for_unavail(
+                None,
+                format!(
+                    "too many websocket connections (max {})",
+                    max_connections.unwrap_or_default()
+                ),
+            )
+        })?;
+
+        let subprotocol_offers = request
+            .headers()
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|offer| !offer.is_empty())
+            .map(String::from)
+            .collect();
+
         let route = request.uri().to_string();
-        let upgrade_fut = hyper::upgrade::on(request);
+        let mut request = request;
+        let upgrade_fut = hyper::upgrade::on(&mut request);
+        let context = WebsocketContext {
+            request_id: rqctx.request_id.clone(),
+            api_version: rqctx.api_version.clone(),
+            request: rqctx.request.clone(),
+            extensions: std::mem::take(request.extensions_mut()),
+        };
 
-        Ok(Self(Some(WebsocketUpgradeInner { upgrade_fut, accept_key, route })))
+        Ok(Self(Some(WebsocketUpgradeInner {
+            upgrade_fut,
+            accept_key,
+            subprotocol_offers,
+            subprotocols: Vec::new(),
+            context,
+            route,
+            permit,
+            config: rqctx.server.config.websocket,
+            shutting_down: rqctx.server.phase.subscribe(),
+            aborting: rqctx.server.aborting.subscribe(),
+            registry: rqctx.server.websocket_registry(),
+            worker: DebugIgnore(
+                rqctx.server.handler_waitgroup_worker.lock().unwrap().clone(),
+            ),
+            clock: rqctx.server.clock(),
+        })))
     }
 
     fn metadata(
@@ -161,6 +432,58 @@ impl ExclusiveExtractor for WebsocketUpgrade {
 }
 
 impl WebsocketUpgrade {
+    /// Overrides the server's websocket settings (see [`ConfigWebsocket`])
+    /// for this connection.
+    ///
+    /// Settings that `overrides` leaves unset keep the server's values.  (This
+    /// is done for you by `#[channel]` when it's given any of the
+    /// `ping_interval_ms`, `pong_timeout_ms`, `idle_timeout_ms`,
+    /// `shutdown_grace_period_ms`, `max_frame_bytes`, `max_message_bytes`, or
+    /// `max_messages_per_second` attributes.)
+    pub fn with_config(mut self, overrides: ConfigWebsocket) -> Self {
+        if let Some(inner) = &mut self.0 {
+            inner.config = inner.config.merge(&overrides);
+        }
+        self
+    }
+
+    /// Adds `value` to the extensions in the handler's [`WebsocketContext`],
+    /// replacing any other extension of the same type.
+    ///
+    /// This is how an endpoint passes along what it worked out before the
+    /// upgrade, such as the principal it authenticated.
+    pub fn with_extension<T: Send + Sync + 'static>(
+        mut self,
+        value: T,
+    ) -> Self {
+        if let Some(inner) = &mut self.0 {
+            inner.context.extensions.insert(value);
+        }
+        self
+    }
+
+    /// Declares the subprotocols that the handler speaks, in order of
+    /// preference.
+    ///
+    /// The first of these that the client also offers (in its
+    /// `Sec-WebSocket-Protocol` header) is chosen for the connection, sent
+    /// back to the client, and made available to the handler through
+    /// [`WebsocketConnection::subprotocol`].  If the client offers none of
+    /// them, no subprotocol is chosen, and it's up to the client whether to
+    /// carry on.  (This is done for you by `#[channel]` when it's given the
+    /// `subprotocols` attribute.)
+    pub fn with_subprotocols<I, S>(mut self, subprotocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if let Some(inner) = &mut self.0 {
+            inner.subprotocols =
+                subprotocols.into_iter().map(Into::into).collect();
+        }
+        self
+    }
+
     /// Upgrade the HTTP connection to a websocket and spawn a user-provided
     /// async handler to service it.
     ///
@@ -207,22 +530,129 @@ impl WebsocketUpgrade {
             None => Err(HttpError::for_internal_error(
                 "Tried to handle websocket twice".to_string(),
             )),
-            Some(WebsocketUpgradeInner { upgrade_fut, accept_key, .. }) => {
+            Some(WebsocketUpgradeInner {
+                upgrade_fut,
+                accept_key,
+                subprotocol_offers,
+                subprotocols,
+                context,
+                route,
+                permit,
+                config,
+                shutting_down,
+                mut aborting,
+                registry,
+                worker,
+                clock,
+            }) => {
+                let subprotocol =
+                    subprotocols.into_iter().find(|subprotocol| {
+                        subprotocol_offers.contains(subprotocol)
+                    });
+                let response_subprotocol = subprotocol.clone();
                 tokio::spawn(async move {
-                    match upgrade_fut.await {
-                        Ok(upgrade) => {
-                            handler(WebsocketConnection(upgrade)).await
+                    // Hold the permit until the connection has been handled,
+                    // and keep graceful shutdown waiting until then too.
+                    let _permit = permit;
+                    let _worker = worker;
+                    let upgraded = upgrade_fut.await?;
+                    let keepalive = Keepalive::new(&config);
+                    let limits = MessageLimits::new(&config, clock);
+                    let send_queue = WebsocketSendQueue::new(
+                        route.clone(),
+                        config.send_queue_len,
+                        config.send_queue_max_bytes,
+                        config.send_queue_policy,
+                    );
+                    let (limit_tx, limit_rx) = tokio::sync::oneshot::channel();
+                    let monitor = ConnectionMonitor::new(
+                        keepalive.clone(),
+                        limits,
+                        send_queue.clone(),
+                        limit_tx,
+                    );
+                    let shutdown = WebsocketShutdown(shutting_down);
+                    let disconnect =
+                        Arc::new(tokio::sync::watch::channel(false).0);
+                    let mut disconnected = disconnect.subscribe();
+                    let handler_fut = handler(WebsocketConnection {
+                        raw: WebsocketConnectionRaw { upgraded, monitor },
+                        shutdown: shutdown.clone(),
+                        registry,
+                        disconnect: Arc::clone(&disconnect),
+                        subprotocol,
+                        context: Arc::new(context),
+                    });
+
+                    let keepalive_expired = async {
+                        match &keepalive {
+                            Some(keepalive) => keepalive.watch().await,
+                            None => std::future::pending().await,
                         }
-                        Err(e) => Err(e.into()),
-                    }
+                    };
+                    let limit_exceeded = async {
+                        match limit_rx.await {
+                            Ok(reason) => reason,
+                            Err(_) => std::future::pending().await,
+                        }
+                    };
+                    let grace_period = config.shutdown_grace_period();
+                    let shutdown_expired = async {
+                        shutdown.wait().await;
+                        tokio::select! {
+                            () = tokio::time::sleep(grace_period) => format!(
+                                "still running {:?} after shutdown began",
+                                grace_period
+                            ),
+                            _ = aborting.wait_for(|a| *a) => {
+                                String::from("graceful shutdown timed out")
+                            }
+                        }
+                    };
+
+                    // A handler disconnected through the registry gets the same
+                    // grace period to close the connection itself.
+                    let disconnect_expired = async {
+                        let _ = disconnected.wait_for(|d| *d).await;
+                        tokio::time::sleep(grace_period).await;
+                        String::from("disconnected through the registry")
+                    };
+
+                    // Likewise, the connection sends a Close frame once its
+                    // send queue overflows, the next time the handler uses it,
+                    // but the handler may not get around to that.
+                    let send_queue_overflowed = async {
+                        let reason = send_queue.overflowed().await;
+                        tokio::time::sleep(grace_period).await;
+                        reason
+                    };
+
+                    // Dropping the handler closes the connection.
+                    let reason = tokio::select! {
+                        result = handler_fut => return result,
+                        reason = keepalive_expired => reason,
+                        reason = limit_exceeded => reason,
+                        reason = shutdown_expired => reason,
+                        reason = disconnect_expired => reason,
+                        reason = send_queue_overflowed => reason,
+                    };
+                    warn!(
+                        route = %route,
+                        reason = %reason,
+                        "closing websocket connection"
+                    );
+                    Err(reason.into())
                 });
-                Response::builder()
+                let mut response = Response::builder()
                     .status(StatusCode::SWITCHING_PROTOCOLS)
                     .header(header::CONNECTION, "Upgrade")
                     .header(header::UPGRADE, "websocket")
-                    .header(header::SEC_WEBSOCKET_ACCEPT, accept_key)
-                    .body(Body::empty())
-                    .map_err(Into::into)
+                    .header(header::SEC_WEBSOCKET_ACCEPT, accept_key);
+                if let Some(subprotocol) = response_subprotocol {
+                    response = response
+                        .header(header::SEC_WEBSOCKET_PROTOCOL, subprotocol);
+                }
+                response.body(Body::empty()).map_err(Into::into)
             }
         }
     }
@@ -236,6 +666,589 @@ impl Drop for WebsocketUpgrade {
     }
 }
 
+/// An unmasked ping frame with no payload, as sent by a server
+const PING_FRAME: [u8; 2] = [0x89, 0x00];
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Keepalive state shared by a connection and the task that watches it
+struct Keepalive {
+    ping_interval: Option<Duration>,
+    pong_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    state: Mutex<KeepaliveState>,
+    /// notified when a ping has been sent
+    ping_sent: Notify,
+}
+
+struct KeepaliveState {
+    /// when the last message was sent or received (or the connection was
+    /// upgraded)
+    last_activity: Instant,
+    /// when the last ping was sent (or the connection was upgraded)
+    last_ping: Instant,
+    /// when the oldest ping that hasn't been answered was sent
+    awaiting_pong_since: Option<Instant>,
+    /// whether a ping should be sent as soon as possible
+    ping_due: bool,
+    /// waker for the task using the connection, to send a ping that's due
+    waker: Option<Waker>,
+}
+
+impl Keepalive {
+    fn new(config: &ConfigWebsocket) -> Option<Arc<Keepalive>> {
+        if !config.has_keepalive() {
+            return None;
+        }
+        let now = Instant::now();
+        Some(Arc::new(Keepalive {
+            ping_interval: config.ping_interval_ms.map(Duration::from_millis),
+            pong_timeout: config.pong_timeout_ms.map(Duration::from_millis),
+            idle_timeout: config.idle_timeout_ms.map(Duration::from_millis),
+            state: Mutex::new(KeepaliveState {
+                last_activity: now,
+                last_ping: now,
+                awaiting_pong_since: None,
+                ping_due: false,
+                waker: None,
+            }),
+            ping_sent: Notify::new(),
+        }))
+    }
+
+    /// Records a frame sent by the handler or received from the client
+    fn frame(&self, opcode: u8, received: bool) {
+        let mut state = self.state.lock().unwrap();
+        match opcode {
+            OPCODE_PONG if received => state.awaiting_pong_since = None,
+            OPCODE_PING | OPCODE_PONG => (),
+            _ => state.last_activity = Instant::now(),
+        }
+    }
+
+    /// Returns whether a ping is due, and if so, expects it to be sent
+    fn take_ping_due(&self, waker: &Waker) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            state.waker = Some(waker.clone());
+        }
+        std::mem::take(&mut state.ping_due)
+    }
+
+    fn ping_sent(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.last_ping = now;
+        state.awaiting_pong_since.get_or_insert(now);
+        self.ping_sent.notify_one();
+    }
+
+    /// Waits until the connection should be closed, arranging for pings to be
+    /// sent along the way, and returns why
+    async fn watch(&self) -> String {
+        loop {
+            let now = Instant::now();
+            let mut next_check = None;
+            {
+                let mut state = self.state.lock().unwrap();
+                if let (Some(timeout), Some(since)) =
+                    (self.pong_timeout, state.awaiting_pong_since)
+                {
+                    if now >= since + timeout {
+                        return format!(
+                            "no pong received within {:?}",
+                            timeout
+                        );
+                    }
+                    next_check = Some(since + timeout);
+                }
+                if let Some(timeout) = self.idle_timeout {
+                    let deadline = state.last_activity + timeout;
+                    if now >= deadline {
+                        return format!("idle for {:?}", timeout);
+                    }
+                    next_check = Some(next_check.map_or(deadline, |next| {
+                        std::cmp::min(next, deadline)
+                    }));
+                }
+                if let Some(interval) = self.ping_interval {
+                    let due = state.last_ping + interval;
+                    if now < due {
+                        next_check = Some(
+                            next_check
+                                .map_or(due, |next| std::cmp::min(next, due)),
+                        );
+                    } else if !state.ping_due {
+                        // The ping is sent by whoever's using the connection,
+                        // the next time they read or write it.
+                        state.ping_due = true;
+                        if let Some(waker) = state.waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+            }
+
+            match next_check {
+                Some(deadline) => tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => (),
+                    _ = self.ping_sent.notified() => (),
+                },
+                None => self.ping_sent.notified().await,
+            }
+        }
+    }
+}
+
+/// Follows the frames in one direction of a websocket connection, to find
+/// where each one starts
+#[derive(Default)]
+struct FrameTracker {
+    header: [u8; 14],
+    header_len: usize,
+    payload_left: u64,
+    /// whether the frames so far have begun a message without finishing it
+    fragmented: bool,
+}
+
+/// What [`FrameTracker`] reports about each frame
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FrameHeader {
+    opcode: u8,
+    /// length of the payload
+    len: u64,
+    /// length of the header itself
+    header_len: usize,
+}
+
+impl FrameTracker {
+    fn at_boundary(&self) -> bool {
+        self.header_len == 0 && self.payload_left == 0
+    }
+
+    /// Returns whether a whole new message may come next
+    fn at_message_boundary(&self) -> bool {
+        self.at_boundary() && !self.fragmented
+    }
+
+    /// Follows `bytes`, calling `on_frame` with each frame whose header they
+    /// complete, along with the offset in `bytes` of the end of the header
+    fn advance(
+        &mut self,
+        mut bytes: &[u8],
+        mut on_frame: impl FnMut(FrameHeader, usize),
+    ) {
+        let total = bytes.len();
+        while !bytes.is_empty() {
+            if self.payload_left > 0 {
+                let n = usize::try_from(self.payload_left)
+                    .unwrap_or(usize::MAX)
+                    .min(bytes.len());
+                self.payload_left -= n as u64;
+                bytes = &bytes[n..];
+                continue;
+            }
+
+            self.header[self.header_len] = bytes[0];
+            self.header_len += 1;
+            bytes = &bytes[1..];
+            if self.header_len < 2 {
+                continue;
+            }
+            let len_bytes = match self.header[1] & 0x7f {
+                126 => 2,
+                127 => 8,
+                _ => 0,
+            };
+            let mask_bytes = if self.header[1] & 0x80 != 0 { 4 } else { 0 };
+            if self.header_len < 2 + len_bytes + mask_bytes {
+                continue;
+            }
+
+            self.payload_left = match len_bytes {
+                0 => u64::from(self.header[1] & 0x7f),
+                n => self.header[2..2 + n]
+                    .iter()
+                    .fold(0, |len, byte| (len << 8) | u64::from(*byte)),
+            };
+            let header_len = std::mem::take(&mut self.header_len);
+            // Control frames may come between the frames of a message.
+            if self.header[0] & 0x08 == 0 {
+                self.fragmented = self.header[0] & 0x80 == 0;
+            }
+            on_frame(
+                FrameHeader {
+                    opcode: self.header[0] & 0x0f,
+                    len: self.payload_left,
+                    header_len,
+                },
+                total - bytes.len(),
+            );
+        }
+    }
+}
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Limits on the frames and messages that the client sends
+struct MessageLimits {
+    max_frame_bytes: Option<u64>,
+    max_message_bytes: Option<u64>,
+    max_messages_per_second: Option<u32>,
+    /// size of the message being received so far
+    message_bytes: u64,
+    /// how many more messages may be received right away (which grows at the
+    /// allowed rate, up to a second's worth)
+    allowance: f64,
+    /// when `allowance` was last brought up to date
+    allowance_updated: DateTime<Utc>,
+    /// the server's clock, by which the message rate is measured
+    clock: Arc<dyn Clock>,
+}
+
+impl MessageLimits {
+    fn new(
+        config: &ConfigWebsocket,
+        clock: Arc<dyn Clock>,
+    ) -> Option<MessageLimits> {
+        if !config.has_limits() {
+            return None;
+        }
+        Some(MessageLimits {
+            max_frame_bytes: config.max_frame_bytes,
+            max_message_bytes: config.max_message_bytes,
+            max_messages_per_second: config.max_messages_per_second,
+            message_bytes: 0,
+            allowance: config.max_messages_per_second.map_or(0.0, f64::from),
+            allowance_updated: clock.now(),
+            clock,
+        })
+    }
+
+    /// Checks a frame received from the client, returning the close code and
+    /// reason with which to close the connection if it exceeds a limit
+    fn check(&mut self, frame: &FrameHeader) -> Result<(), (u16, String)> {
+        if let Some(max) = self.max_frame_bytes {
+            if frame.len > max {
+                return Err((
+                    CLOSE_MESSAGE_TOO_BIG,
+                    format!("frame exceeds {} bytes", max),
+                ));
+            }
+        }
+
+        // Control frames may come between the frames of a message, and
+        // aren't part of it.
+        if frame.opcode & 0x8 != 0 {
+            return Ok(());
+        }
+        if frame.opcode != OPCODE_CONTINUATION {
+            self.message_bytes = 0;
+            if let Some(rate) = self.max_messages_per_second {
+                let now = self.clock.now();
+                // The clock may go backward, which is as if no time passed.
+                let elapsed =
+                    (now - self.allowance_updated).to_std().unwrap_or_default();
+                self.allowance = (self.allowance
+                    + elapsed.as_secs_f64() * f64::from(rate))
+                .min(f64::from(rate));
+                self.allowance_updated = now;
+                if self.allowance < 1.0 {
+                    return Err((
+                        CLOSE_POLICY_VIOLATION,
+                        format!("more than {} messages per second", rate),
+                    ));
+                }
+                self.allowance -= 1.0;
+            }
+        }
+        self.message_bytes = self.message_bytes.saturating_add(frame.len);
+        if let Some(max) = self.max_message_bytes {
+            if self.message_bytes > max {
+                return Err((
+                    CLOSE_MESSAGE_TOO_BIG,
+                    format!("message exceeds {} bytes", max),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns an unmasked Close frame, as sent by a server
+pub(crate) fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    // The payload of a control frame is at most 125 bytes.
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let reason = &reason[..end];
+    let mut frame = vec![0x88, 2 + reason.len() as u8];
+    frame.extend_from_slice(&code.to_be_bytes());
+    frame.extend_from_slice(reason.as_bytes());
+    frame
+}
+
+/// The part of a [`WebsocketConnectionRaw`] that follows the frames on the
+/// connection, to implement keepalive and message limits
+struct ConnectionMonitor {
+    keepalive: Option<Arc<Keepalive>>,
+    limits: Option<MessageLimits>,
+    send_queue: WebsocketSendQueue,
+    received: FrameTracker,
+    sent: FrameTracker,
+    /// control frame (or queued message) that the server is writing, and how
+    /// much of it has been written
+    control: Option<(Vec<u8>, usize)>,
+    /// whether such a frame has been written but not yet flushed
+    flush_pending: bool,
+    /// Close frame to send once the handler has read everything before the
+    /// frame that exceeded a limit (and isn't in the middle of writing one)
+    close_pending: Option<Vec<u8>>,
+    /// why the connection is being closed, once the client exceeds a limit or
+    /// the send queue overflows
+    closing: Option<String>,
+    /// whether the connection is being closed because the send queue
+    /// overflowed, in which case there's nothing left for the handler to read
+    /// first, so its writes send the Close frame too
+    overflowed: bool,
+    /// tells the connection's task that the client exceeded a limit, once
+    /// the Close frame has been sent
+    limit_exceeded: Option<tokio::sync::oneshot::Sender<String>>,
+}
+
+impl ConnectionMonitor {
+    fn new(
+        keepalive: Option<Arc<Keepalive>>,
+        limits: Option<MessageLimits>,
+        send_queue: WebsocketSendQueue,
+        limit_exceeded: tokio::sync::oneshot::Sender<String>,
+    ) -> ConnectionMonitor {
+        ConnectionMonitor {
+            keepalive,
+            limits,
+            send_queue,
+            received: FrameTracker::default(),
+            sent: FrameTracker::default(),
+            control: None,
+            flush_pending: false,
+            close_pending: None,
+            closing: None,
+            overflowed: false,
+            limit_exceeded: Some(limit_exceeded),
+        }
+    }
+
+    /// Sends a ping if one is due, then any queued messages (or the pending
+    /// Close frame, if `close`), as long as the handler isn't in the middle of
+    /// writing a frame (or, for queued messages, a fragmented message)
+    fn poll_control(
+        &mut self,
+        upgraded: &mut Upgraded,
+        cx: &mut Context<'_>,
+        close: bool,
+    ) -> Poll<io::Result<()>> {
+        if self.closing.is_none() {
+            if let Some(reason) = self.send_queue.take_overflow() {
+                self.close_pending =
+                    Some(close_frame(CLOSE_POLICY_VIOLATION, &reason));
+                self.closing = Some(reason);
+                self.overflowed = true;
+            }
+        }
+        loop {
+            if self.control.is_none() && self.sent.at_boundary() {
+                let pending_close =
+                    if close { self.close_pending.take() } else { None };
+                if let Some(frame) = pending_close {
+                    self.control = Some((frame, 0));
+                } else if self.closing.is_none() {
+                    if self
+                        .keepalive
+                        .as_ref()
+                        .is_some_and(|k| k.take_ping_due(cx.waker()))
+                    {
+                        self.control = Some((PING_FRAME.to_vec(), 0));
+                    } else if self.sent.at_message_boundary() {
+                        self.control = self
+                            .send_queue
+                            .pop(cx.waker())
+                            .map(|frame| (frame, 0));
+                    }
+                }
+            }
+            let Some((frame, written)) = &mut self.control else {
+                break;
+            };
+            while *written < frame.len() {
+                let n =
+                    ready!(Pin::new(&mut *upgraded)
+                        .poll_write(cx, &frame[*written..]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                *written += n;
+            }
+            if let Some(keepalive) = &self.keepalive {
+                match frame[0] & 0x0f {
+                    OPCODE_PING => keepalive.ping_sent(),
+                    opcode => keepalive.frame(opcode, false),
+                }
+            }
+            self.control = None;
+            self.flush_pending = true;
+        }
+        if self.flush_pending {
+            ready!(Pin::new(upgraded).poll_flush(cx))?;
+            self.flush_pending = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Follows the bytes received from the client.  If the client has
+    /// exceeded a limit, this begins closing the connection and returns how
+    /// many of the bytes came before the frame that exceeded it.
+    fn received(&mut self, bytes: &[u8]) -> Option<usize> {
+        let mut exceeded = None;
+        let keepalive = &self.keepalive;
+        let limits = &mut self.limits;
+        self.received.advance(bytes, |frame, end| {
+            if let Some(keepalive) = keepalive {
+                keepalive.frame(frame.opcode, true);
+            }
+            if let (Some(limits), None) = (limits.as_mut(), &exceeded) {
+                if let Err((code, reason)) = limits.check(&frame) {
+                    let start = end.saturating_sub(frame.header_len);
+                    exceeded = Some((code, reason, start));
+                }
+            }
+        });
+        let (code, reason, start) = exceeded?;
+        self.close_pending = Some(close_frame(code, &reason));
+        self.closing = Some(reason);
+        Some(start)
+    }
+
+    /// Sends a Close frame, if the handler isn't in the middle of writing a
+    /// frame, then fails with the reason that the connection is being closed
+    fn poll_close(
+        &mut self,
+        upgraded: &mut Upgraded,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Error> {
+        // If the handler is in the middle of writing a frame, it may never
+        // finish, so don't wait for it.
+        if self.control.is_none() && !self.sent.at_boundary() {
+            self.close_pending = None;
+        }
+        let result = ready!(self.poll_control(upgraded, cx, true));
+        let reason = self.closing.clone().unwrap_or_default();
+        if let Some(limit_exceeded) = self.limit_exceeded.take() {
+            let _ = limit_exceeded.send(reason.clone());
+        }
+        Poll::Ready(match result {
+            Ok(()) => io::Error::new(io::ErrorKind::InvalidData, reason),
+            Err(error) => error,
+        })
+    }
+}
+
+impl Drop for ConnectionMonitor {
+    fn drop(&mut self) {
+        self.send_queue.close();
+    }
+}
+
+impl AsyncRead for WebsocketConnectionRaw {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let monitor = &mut this.monitor;
+        if monitor.closing.is_some() {
+            let error = ready!(monitor.poll_close(&mut this.upgraded, cx));
+            return Poll::Ready(Err(error));
+        }
+        // The handler may be waiting for the client rather than writing
+        // anything, so this is also a chance to send queued messages and pings.
+        match monitor.poll_control(&mut this.upgraded, cx, false) {
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            // The send queue may have overflowed.
+            _ if monitor.closing.is_some() => {
+                let error = ready!(monitor.poll_close(&mut this.upgraded, cx));
+                return Poll::Ready(Err(error));
+            }
+            _ => (),
+        }
+        let start = buf.filled().len();
+        ready!(Pin::new(&mut this.upgraded).poll_read(cx, buf))?;
+        if let Some(valid) = monitor.received(&buf.filled()[start..]) {
+            // Pass along what the client sent before exceeding the limit, but
+            // nothing after that.
+            buf.set_filled(start + valid);
+            if valid == 0 {
+                let error = ready!(monitor.poll_close(&mut this.upgraded, cx));
+                return Poll::Ready(Err(error));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WebsocketConnectionRaw {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let monitor = &mut this.monitor;
+        ready!(monitor.poll_control(&mut this.upgraded, cx, false))?;
+        if let Some(reason) = &monitor.closing {
+            if monitor.overflowed {
+                let error = ready!(monitor.poll_close(&mut this.upgraded, cx));
+                return Poll::Ready(Err(error));
+            }
+            // Once the Close frame has been sent (or given up on), nothing
+            // more may be sent.
+            if monitor.close_pending.is_none() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    reason.clone(),
+                )));
+            }
+        }
+        let n = ready!(Pin::new(&mut this.upgraded).poll_write(cx, buf))?;
+        let keepalive = &monitor.keepalive;
+        monitor.sent.advance(&buf[..n], |frame, _| {
+            if let Some(keepalive) = keepalive {
+                keepalive.frame(frame.opcode, false);
+            }
+        });
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.monitor.poll_control(&mut this.upgraded, cx, false))?;
+        Pin::new(&mut this.upgraded).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().upgraded).poll_shutdown(cx)
+    }
+}
+
 // To indicate websocket usage by the endpoint to code generators (i.e. Progenitor)
 pub(crate) const WEBSOCKET_EXTENSION: &str = "x-dropshot-websocket";
 pub(crate) const WEBSOCKET_PARAM_SENTINEL: &str = "x-dropshot-websocket-param";
@@ -258,9 +1271,17 @@ impl JsonSchema for WebsocketUpgrade {
 
 #[cfg(test)]
 mod tests {
+    use super::close_frame;
+    use super::FrameHeader;
+    use super::FrameTracker;
+    use super::MessageLimits;
+    use super::WebsocketConnectionPermit;
+    use crate::clock::{Clock, SystemClock};
+    use crate::config::ConfigWebsocket;
     use crate::config::HandlerTaskMode;
     use crate::router::HttpRouter;
-    use crate::server::{DropshotState, ServerConfig};
+    use crate::server::{DropshotState, ServerConfig, ServerPhase};
+    use crate::test_util::TestClock;
     use crate::{
         ExclusiveExtractor, HttpError, RequestContext, RequestInfo,
         WebsocketUpgrade,
@@ -270,6 +1291,7 @@ mod tests {
     use hyper::Body;
     use std::net::{IpAddr, Ipv6Addr, SocketAddr};
     use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
     use waitgroup::WaitGroup;
@@ -293,6 +1315,30 @@ mod tests {
                     page_default_nitems: NonZeroU32::new(1).unwrap(),
                     default_handler_task_mode:
                         HandlerTaskMode::CancelOnDisconnect,
+                    trusted_proxies: Vec::new(),
+                    proxy_protocol: false,
+                    lame_duck_period: Default::default(),
+                    shutdown_timeout: None,
+                    keep_alive_timeout: None,
+                    header_read_timeout: None,
+                    request_timeout_max: None,
+                    min_request_body_rate: None,
+                    max_requests_per_connection: None,
+                    max_connection_age: None,
+                    max_connections: None,
+                    max_concurrent_requests: None,
+                    max_uri_length: None,
+                    max_request_header_bytes: None,
+                    max_request_headers: None,
+                    error_content_negotiation: false,
+                    error_response_format: Default::default(),
+                    max_websocket_connections: None,
+                    websocket: Default::default(),
+                    cors: Default::default(),
+                    server_timing: Default::default(),
+                    alt_svc: None,
+                    tcp: Default::default(),
+                    schema_validation: Default::default(),
                 },
                 router: HttpRouter::new(),
                 local_addr: SocketAddr::new(
@@ -300,15 +1346,49 @@ mod tests {
                     8080,
                 ),
                 middleware: None,
+                path_error_handler: DebugIgnore(None),
+                error_mapper: DebugIgnore(None),
+                version_policy: Default::default(),
+                version_observer: DebugIgnore(None),
+                prerelease_matching: Default::default(),
                 tls_acceptor: None,
-                handler_waitgroup_worker: DebugIgnore(
-                    WaitGroup::new().worker(),
+                handler_waitgroup_worker: DebugIgnore(std::sync::Mutex::new(
+                    Some(WaitGroup::new().worker()),
+                )),
+                phase: Arc::new(
+                    tokio::sync::watch::channel(ServerPhase::Running).0,
                 ),
+                aborting: Arc::new(tokio::sync::watch::channel(false).0),
+                websocket_connections: Default::default(),
+                server_timing_nrequests: Default::default(),
+                requests_in_flight: Default::default(),
+                requests_total: Default::default(),
+                connections: Default::default(),
+                connections_refused: Default::default(),
+                requests_shed: Default::default(),
+                header_read_timeouts: Default::default(),
+                slow_request_bodies: Default::default(),
+                connection_filter: Default::default(),
+                mounts: Default::default(),
+                created: std::time::Instant::now(),
+                quiescing: Default::default(),
+                supported_versions: Default::default(),
+                page_token_keys: Default::default(),
+                websocket_registry: Default::default(),
+                clock: Default::default(),
+                route_stats: Default::default(),
+                schema_validator: DebugIgnore(None),
             }),
             request: RequestInfo::new(&request, remote_addr),
             path_variables: Default::default(),
             body_content_type: Default::default(),
+            request_body_max_bytes: 0,
+            page_limits: Default::default(),
+            deadline: Default::default(),
+            preferences: Default::default(),
             request_id: "".to_string(),
+            api_version: None,
+            server_timing: false,
         };
         let fut = WebsocketUpgrade::from_request(&rqctx, request);
         tokio::time::timeout(Duration::from_secs(1), fut)
@@ -332,4 +1412,131 @@ mod tests {
             .await
             .expect("Task not spawned or never completed");
     }
+
+    #[test]
+    fn test_ws_frame_tracker() {
+        let mut tracker = FrameTracker::default();
+        let mut opcodes = Vec::new();
+        assert!(tracker.at_boundary());
+
+        // A masked text frame with a short payload, fed a byte at a time.
+        let frame = [0x81, 0x83, 1, 2, 3, 4, b'a', b'b', b'c'];
+        for (i, byte) in frame.iter().enumerate() {
+            tracker.advance(&[*byte], |frame, _| opcodes.push(frame.opcode));
+            assert_eq!(tracker.at_boundary(), i == frame.len() - 1);
+        }
+        assert_eq!(opcodes, vec![0x1]);
+
+        // An empty pong, then a binary frame with a 16-bit length, split
+        // partway through its payload.
+        let mut bytes = vec![0x8a, 0x00, 0x82, 126, 0x01, 0x00];
+        bytes.extend(std::iter::repeat(0).take(0x100));
+        tracker.advance(&bytes[..100], |frame, _| opcodes.push(frame.opcode));
+        assert_eq!(opcodes, vec![0x1, 0xa, 0x2]);
+        assert!(!tracker.at_boundary());
+        tracker.advance(&bytes[100..], |frame, _| opcodes.push(frame.opcode));
+        assert!(tracker.at_boundary());
+
+        // A frame with a 64-bit length.
+        let mut bytes = vec![0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0];
+        bytes.extend(std::iter::repeat(0).take(0x10000));
+        tracker.advance(&bytes, |frame, _| opcodes.push(frame.opcode));
+        assert_eq!(opcodes, vec![0x1, 0xa, 0x2, 0x2]);
+        assert!(tracker.at_boundary());
+
+        // A message fragmented across two frames, with a ping between them.
+        tracker.advance(&[0x01, 0x01, b'a'], |_, _| ());
+        assert!(tracker.at_boundary());
+        assert!(!tracker.at_message_boundary());
+        tracker.advance(&[0x89, 0x00], |_, _| ());
+        assert!(!tracker.at_message_boundary());
+        tracker.advance(&[0x80, 0x01, b'b'], |_, _| ());
+        assert!(tracker.at_message_boundary());
+    }
+
+    #[test]
+    fn test_ws_message_limits() {
+        let config = ConfigWebsocket {
+            max_frame_bytes: Some(100),
+            max_message_bytes: Some(150),
+            max_messages_per_second: Some(2),
+            ..Default::default()
+        };
+        let clock = || Arc::new(SystemClock) as Arc<dyn Clock>;
+        let mut limits = MessageLimits::new(&config, clock()).unwrap();
+        let frame = |opcode, len| FrameHeader { opcode, len, header_len: 2 };
+
+        // A message may span several frames, with control frames in between,
+        // as long as none is too big.
+        assert!(limits.check(&frame(0x1, 100)).is_ok());
+        assert!(limits.check(&frame(0x9, 0)).is_ok());
+        assert!(limits.check(&frame(0x0, 50)).is_ok());
+        let (code, _) = limits.check(&frame(0x2, 101)).unwrap_err();
+        assert_eq!(code, 1009);
+
+        // The next message starts over, but this one is too long altogether.
+        let mut limits = MessageLimits::new(&config, clock()).unwrap();
+        assert!(limits.check(&frame(0x2, 100)).is_ok());
+        let (code, reason) = limits.check(&frame(0x0, 51)).unwrap_err();
+        assert_eq!(code, 1009);
+        assert_eq!(reason, "message exceeds 150 bytes");
+
+        // Two messages may be sent at once, but not a third.
+        let test_clock = TestClock::new();
+        test_clock.freeze();
+        let mut limits =
+            MessageLimits::new(&config, Arc::new(test_clock.clone())).unwrap();
+        assert!(limits.check(&frame(0x1, 1)).is_ok());
+        assert!(limits.check(&frame(0x1, 1)).is_ok());
+        assert!(limits.check(&frame(0xa, 0)).is_ok());
+        let (code, _) = limits.check(&frame(0x1, 1)).unwrap_err();
+        assert_eq!(code, 1008);
+
+        // More are allowed at the configured rate, by the server's clock, up to
+        // a second's worth.
+        test_clock.advance(Duration::from_millis(500));
+        assert!(limits.check(&frame(0x1, 1)).is_ok());
+        assert!(limits.check(&frame(0x1, 1)).is_err());
+        test_clock.advance(Duration::from_secs(10));
+        assert!(limits.check(&frame(0x1, 1)).is_ok());
+        assert!(limits.check(&frame(0x1, 1)).is_ok());
+        assert!(limits.check(&frame(0x1, 1)).is_err());
+
+        assert!(
+            MessageLimits::new(&ConfigWebsocket::default(), clock()).is_none()
+        );
+    }
+
+    #[test]
+    fn test_ws_close_frame() {
+        assert_eq!(close_frame(1009, "big"), b"\x88\x05\x03\xf1big");
+        let frame = close_frame(1008, &"\u{e9}".repeat(100));
+        assert_eq!(frame.len(), 2 + 2 + 122);
+        assert_eq!(usize::from(frame[1]), frame.len() - 2);
+    }
+
+    #[test]
+    fn test_ws_connection_permit() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let first = WebsocketConnectionPermit::acquire(&count, Some(2))
+            .expect("first connection should be permitted");
+        let second = WebsocketConnectionPermit::acquire(&count, Some(2))
+            .expect("second connection should be permitted");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(WebsocketConnectionPermit::acquire(&count, Some(2)).is_none());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        drop(first);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let third = WebsocketConnectionPermit::acquire(&count, Some(2))
+            .expect("connection should be permitted after one closed");
+
+        // Without a limit, connections are counted but never refused.
+        let unlimited = WebsocketConnectionPermit::acquire(&count, None)
+            .expect("connection should be permitted without a limit");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        drop((second, third, unlimited));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,413 @@
+// Copyright 2024 Oxide Computer Company
+//! Bounded queue of messages waiting to be sent on a websocket connection
+//!
+//! Handlers that send messages as fast as they're produced, rather than as
+//! fast as the client receives them, can pile up unbounded amounts of data in
+//! their websockets implementation's buffers.  [`WebsocketSendQueue`] bounds
+//! that: messages are queued up to a limit, the connection writes them out
+//! between the frames that the handler writes itself, and once the client
+//! falls far enough behind to fill the queue, a policy (see
+//! [`WebsocketOverflowPolicy`]) decides what happens.
+
+use crate::config::WebsocketOverflowPolicy;
+use crate::websocket_registry::WebsocketMessage;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Waker;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Number of messages that a send queue holds when none is configured
+pub(crate) const DEFAULT_SEND_QUEUE_LEN: usize = 256;
+
+pub(crate) const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+
+/// Why a message couldn't be queued by a [`WebsocketSendQueue`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WebsocketSendError {
+    /// The queue is full (only from [`WebsocketSendQueue::try_send`], under
+    /// [`WebsocketOverflowPolicy::Block`])
+    Full,
+    /// The connection has been closed
+    Closed,
+}
+
+impl std::fmt::Display for WebsocketSendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebsocketSendError::Full => {
+                write!(f, "websocket send queue is full")
+            }
+            WebsocketSendError::Closed => {
+                write!(f, "websocket connection is closed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebsocketSendError {}
+
+/// Handle for queueing messages to be sent to a websocket client, returned by
+/// [`crate::WebsocketConnection::send_queue`]
+///
+/// Queued messages are written to the connection, each as a single frame,
+/// between the frames that the handler writes itself (and never in the middle
+/// of a fragmented message).  As with keepalive pings, this happens while the
+/// handler is reading or writing the connection, which handlers built on a
+/// websockets implementation usually always are.
+///
+/// The queue holds up to [`crate::ConfigWebsocket::send_queue_len`] messages
+/// (and [`crate::ConfigWebsocket::send_queue_max_bytes`] bytes).  A client
+/// whose queue fills up is falling behind, which is logged, and what happens
+/// next depends on the configured [`WebsocketOverflowPolicy`].
+///
+/// This is cheap to clone, so that other tasks can send messages too.
+#[derive(Clone, Debug)]
+pub struct WebsocketSendQueue(Arc<SendQueue>);
+
+#[derive(Debug)]
+struct SendQueue {
+    route: String,
+    max_len: usize,
+    max_bytes: Option<u64>,
+    policy: WebsocketOverflowPolicy,
+    state: Mutex<SendQueueState>,
+    /// notified when there's room in the queue or it's been closed
+    room: Notify,
+    /// notified when the queue overflows under the `Close` policy
+    overflowed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct SendQueueState {
+    /// encoded frames waiting to be sent
+    frames: VecDeque<Vec<u8>>,
+    /// total size of `frames`
+    bytes: u64,
+    /// number of messages dropped under the `DropOldest` policy
+    dropped: u64,
+    /// whether the client has fallen behind since the queue was last empty
+    behind: bool,
+    closed: bool,
+    /// whether the queue overflowed under the `Close` policy, and the
+    /// connection hasn't yet been told
+    overflowed: bool,
+    /// waker for the task using the connection, to write queued frames
+    waker: Option<Waker>,
+}
+
+impl WebsocketSendQueue {
+    pub(crate) fn new(
+        route: String,
+        max_len: Option<usize>,
+        max_bytes: Option<u64>,
+        policy: Option<WebsocketOverflowPolicy>,
+    ) -> WebsocketSendQueue {
+        WebsocketSendQueue(Arc::new(SendQueue {
+            route,
+            max_len: max_len.unwrap_or(DEFAULT_SEND_QUEUE_LEN).max(1),
+            max_bytes,
+            policy: policy.unwrap_or_default(),
+            state: Mutex::new(SendQueueState::default()),
+            room: Notify::new(),
+            overflowed: Notify::new(),
+        }))
+    }
+
+    /// Queues `message` to be sent, applying the overflow policy if the
+    /// queue is full
+    ///
+    /// Under [`WebsocketOverflowPolicy::Block`], this waits until there's
+    /// room.  Under [`WebsocketOverflowPolicy::Close`], it fails, and the
+    /// connection is closed.
+    pub async fn send(
+        &self,
+        message: WebsocketMessage,
+    ) -> Result<(), WebsocketSendError> {
+        let frame = data_frame(&message);
+        loop {
+            // Register for a wakeup before checking for room, so as not to
+            // miss one in between.
+            let room = self.0.room.notified();
+            match self.0.push(frame.clone()) {
+                Err(WebsocketSendError::Full) => room.await,
+                result => return result,
+            }
+        }
+    }
+
+    /// Queues `message` to be sent without waiting, applying the overflow
+    /// policy if the queue is full
+    ///
+    /// Under [`WebsocketOverflowPolicy::Block`], this fails with
+    /// [`WebsocketSendError::Full`] if there's no room.
+    pub fn try_send(
+        &self,
+        message: WebsocketMessage,
+    ) -> Result<(), WebsocketSendError> {
+        self.0.push(data_frame(&message))
+    }
+
+    /// Returns the number of messages waiting to be sent
+    pub fn len(&self) -> usize {
+        self.0.state.lock().unwrap().frames.len()
+    }
+
+    /// Returns whether no messages are waiting to be sent
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of messages dropped because the queue was full,
+    /// under [`WebsocketOverflowPolicy::DropOldest`]
+    pub fn dropped_count(&self) -> u64 {
+        self.0.state.lock().unwrap().dropped
+    }
+
+    /// Returns whether the connection has been closed
+    pub fn is_closed(&self) -> bool {
+        self.0.state.lock().unwrap().closed
+    }
+
+    /// Takes the next frame to send, if any, remembering `waker` to write
+    /// frames queued later
+    pub(crate) fn pop(&self, waker: &Waker) -> Option<Vec<u8>> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            state.waker = Some(waker.clone());
+        }
+        let frame = state.frames.pop_front()?;
+        state.bytes -= frame.len() as u64;
+        if state.frames.is_empty() {
+            state.behind = false;
+        }
+        drop(state);
+        self.0.room.notify_waiters();
+        Some(frame)
+    }
+
+    /// Discards queued messages and fails any more sends
+    pub(crate) fn close(&self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.closed = true;
+        state.frames.clear();
+        state.bytes = 0;
+        drop(state);
+        self.0.room.notify_waiters();
+    }
+
+    /// Returns why the connection should be closed, the first time this is
+    /// called after the queue overflows under
+    /// [`WebsocketOverflowPolicy::Close`]
+    pub(crate) fn take_overflow(&self) -> Option<String> {
+        let mut state = self.0.state.lock().unwrap();
+        std::mem::take(&mut state.overflowed).then(|| self.0.overflow_reason())
+    }
+
+    /// Waits until the queue overflows under
+    /// [`WebsocketOverflowPolicy::Close`], returning why the connection should
+    /// be closed
+    pub(crate) async fn overflowed(&self) -> String {
+        self.0.overflowed.notified().await;
+        self.0.overflow_reason()
+    }
+}
+
+impl SendQueue {
+    fn overflow_reason(&self) -> String {
+        format!(
+            "client fell behind: send queue of {} messages is full",
+            self.max_len
+        )
+    }
+
+    fn push(&self, frame: Vec<u8>) -> Result<(), WebsocketSendError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(WebsocketSendError::Closed);
+        }
+
+        // A message that's bigger than the byte limit on its own can still
+        // be sent once the queue is empty.
+        let len = frame.len() as u64;
+        let full = |state: &SendQueueState| {
+            state.frames.len() >= self.max_len
+                || self.max_bytes.is_some_and(|max| {
+                    !state.frames.is_empty() && state.bytes + len > max
+                })
+        };
+        if full(&state) {
+            if !std::mem::replace(&mut state.behind, true) {
+                warn!(
+                    route = %self.route,
+                    policy = ?self.policy,
+                    "websocket client is falling behind: send queue is full"
+                );
+            }
+            match self.policy {
+                WebsocketOverflowPolicy::Block => {
+                    return Err(WebsocketSendError::Full);
+                }
+                WebsocketOverflowPolicy::DropOldest => {
+                    while full(&state) {
+                        let Some(oldest) = state.frames.pop_front() else {
+                            break;
+                        };
+                        state.bytes -= oldest.len() as u64;
+                        state.dropped += 1;
+                    }
+                }
+                WebsocketOverflowPolicy::Close => {
+                    state.closed = true;
+                    state.overflowed = true;
+                    state.frames.clear();
+                    state.bytes = 0;
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                    drop(state);
+                    self.overflowed.notify_one();
+                    self.room.notify_waiters();
+                    return Err(WebsocketSendError::Closed);
+                }
+            }
+        }
+
+        state.bytes += len;
+        state.frames.push_back(frame);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+/// Returns an unmasked, unfragmented frame carrying `message`, as sent by a
+/// server
+fn data_frame(message: &WebsocketMessage) -> Vec<u8> {
+    match message {
+        WebsocketMessage::Text(text) => {
+            server_frame(OPCODE_TEXT, text.as_bytes())
+        }
+        WebsocketMessage::Binary(data) => server_frame(OPCODE_BINARY, data),
+    }
+}
+
+/// Returns an unmasked frame with the given opcode and payload, as sent by a
+/// server
+pub(crate) fn server_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod test {
+    use super::data_frame;
+    use super::WebsocketSendError;
+    use super::WebsocketSendQueue;
+    use crate::config::WebsocketOverflowPolicy;
+    use crate::websocket_registry::WebsocketMessage;
+    use futures::task::noop_waker;
+
+    fn text(s: &str) -> WebsocketMessage {
+        WebsocketMessage::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_data_frame() {
+        assert_eq!(data_frame(&text("hi")), b"\x81\x02hi");
+        let frame = data_frame(&WebsocketMessage::Binary(vec![0; 200]));
+        assert_eq!(&frame[..4], &[0x82, 126, 0, 200]);
+        assert_eq!(frame.len(), 204);
+        let frame = data_frame(&WebsocketMessage::Binary(vec![0; 0x10000]));
+        assert_eq!(&frame[..10], &[0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_send_queue_policies() {
+        let waker = noop_waker();
+        let new_queue = |policy| {
+            WebsocketSendQueue::new(
+                String::from("/test"),
+                Some(2),
+                None,
+                Some(policy),
+            )
+        };
+
+        // Blocking: sends wait until there's room.
+        let queue = new_queue(WebsocketOverflowPolicy::Block);
+        queue.send(text("a")).await.unwrap();
+        queue.try_send(text("b")).unwrap();
+        assert_eq!(queue.try_send(text("c")), Err(WebsocketSendError::Full));
+        let sender = queue.clone();
+        let blocked = tokio::spawn(async move { sender.send(text("c")).await });
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+        assert_eq!(queue.pop(&waker), Some(data_frame(&text("a"))));
+        blocked.await.unwrap().unwrap();
+        assert_eq!(queue.len(), 2);
+
+        // Dropping the oldest: sends always succeed.
+        let queue = new_queue(WebsocketOverflowPolicy::DropOldest);
+        for s in ["a", "b", "c", "d"] {
+            queue.send(text(s)).await.unwrap();
+        }
+        assert_eq!(queue.dropped_count(), 2);
+        assert_eq!(queue.pop(&waker), Some(data_frame(&text("c"))));
+        assert_eq!(queue.pop(&waker), Some(data_frame(&text("d"))));
+        assert_eq!(queue.pop(&waker), None);
+
+        // Closing: the send that overflows fails, and so do later ones.
+        let queue = new_queue(WebsocketOverflowPolicy::Close);
+        let overflowed = queue.clone();
+        let overflowed =
+            tokio::spawn(async move { overflowed.overflowed().await });
+        tokio::task::yield_now().await;
+        queue.send(text("a")).await.unwrap();
+        queue.send(text("b")).await.unwrap();
+        assert_eq!(
+            queue.send(text("c")).await,
+            Err(WebsocketSendError::Closed)
+        );
+        assert!(queue.is_closed());
+        assert!(queue.is_empty());
+        assert!(overflowed.await.unwrap().contains("send queue"));
+        assert!(queue.take_overflow().is_some());
+        assert!(queue.take_overflow().is_none());
+        assert_eq!(queue.try_send(text("d")), Err(WebsocketSendError::Closed));
+    }
+
+    #[test]
+    fn test_send_queue_max_bytes() {
+        let queue = WebsocketSendQueue::new(
+            String::from("/test"),
+            None,
+            Some(8),
+            Some(WebsocketOverflowPolicy::Block),
+        );
+        // A message bigger than the limit fits in an empty queue, but nothing
+        // fits after it.
+        queue.try_send(text("0123456789")).unwrap();
+        assert_eq!(queue.try_send(text("a")), Err(WebsocketSendError::Full));
+        queue.pop(&futures::task::noop_waker());
+        queue.try_send(text("abc")).unwrap();
+        queue.try_send(text("d")).unwrap();
+        assert_eq!(queue.try_send(text("e")), Err(WebsocketSendError::Full));
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright 2024 Oxide Computer Company
+//! Registry of live websocket connections, so that the rest of the server can
+//! send them messages
+//!
+//! Services like chat or notifications need to reach websocket connections
+//! from outside their handlers: from another request's handler, or from a
+//! background task.  With a [`WebsocketRegistry`] installed on the server
+//! (see [`crate::HttpServerStarter::websocket_registry`]), each websocket
+//! handler can register its connection under a key of its choosing (a user
+//! id, say) with [`crate::WebsocketConnection::register`].  Anything with the
+//! registry can then send messages to the connections registered under one
+//! key, several keys, or all of them, and can disconnect them.
+//!
+//! Dropshot doesn't implement the websockets protocol itself, so it doesn't
+//! write these messages to the connections.  Instead, each handler receives
+//! them from its [`WebsocketRegistration`] and writes them with whatever
+//! websockets implementation it uses.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+
+/// A message sent to websocket connections through a [`WebsocketRegistry`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WebsocketMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Tracks live websocket connections by user-assigned keys, and sends them
+/// messages
+///
+/// Several connections may be registered under the same key (e.g., for a user
+/// with several browser tabs open), in which case they all get the messages
+/// sent to that key.  Each connection has a queue of messages that its handler
+/// hasn't received yet.  Messages sent to a connection whose queue is full are
+/// dropped (and counted by [`WebsocketRegistry::dropped_message_count`]), so
+/// that one slow client can't hold up the others.
+///
+/// ```
+/// use dropshot::WebsocketMessage;
+/// use dropshot::WebsocketRegistry;
+///
+/// let registry = WebsocketRegistry::new();
+/// // No connections are registered yet, so this reaches nobody.
+/// let n = registry.broadcast(WebsocketMessage::Text("hello".to_string()));
+/// assert_eq!(n, 0);
+/// ```
+#[derive(Debug)]
+pub struct WebsocketRegistry {
+    queue_len: usize,
+    connections: Mutex<BTreeMap<String, BTreeMap<u64, Slot>>>,
+    next_id: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Slot {
+    sender: mpsc::Sender<WebsocketMessage>,
+    /// tells the connection's task that the connection has been disconnected
+    disconnect: Arc<watch::Sender<bool>>,
+}
+
+impl Default for WebsocketRegistry {
+    fn default() -> Self {
+        WebsocketRegistry::with_queue_len(WebsocketRegistry::DEFAULT_QUEUE_LEN)
+    }
+}
+
+impl WebsocketRegistry {
+    /// Number of messages that each connection's queue holds by default
+    pub const DEFAULT_QUEUE_LEN: usize = 256;
+
+    /// Returns a registry whose connections each queue up to
+    /// [`WebsocketRegistry::DEFAULT_QUEUE_LEN`] messages
+    pub fn new() -> WebsocketRegistry {
+        WebsocketRegistry::default()
+    }
+
+    /// Returns a registry whose connections each queue up to `queue_len`
+    /// messages (at least one)
+    pub fn with_queue_len(queue_len: usize) -> WebsocketRegistry {
+        WebsocketRegistry {
+            queue_len: queue_len.max(1),
+            connections: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends `message` to the connections registered under `key`, returning
+    /// how many it was queued for
+    pub fn send(&self, key: &str, message: WebsocketMessage) -> usize {
+        self.multicast([key], message)
+    }
+
+    /// Sends `message` to the connections registered under any of `keys`,
+    /// returning how many it was queued for
+    pub fn multicast<'a, I>(&self, keys: I, message: WebsocketMessage) -> usize
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let connections = self.connections.lock().unwrap();
+        let slots = keys
+            .into_iter()
+            .filter_map(|key| connections.get(key))
+            .flat_map(|slots| slots.values());
+        self.deliver(slots, message)
+    }
+
+    /// Sends `message` to every registered connection, returning how many it
+    /// was queued for
+    pub fn broadcast(&self, message: WebsocketMessage) -> usize {
+        let connections = self.connections.lock().unwrap();
+        self.deliver(
+            connections.values().flat_map(|slots| slots.values()),
+            message,
+        )
+    }
+
+    /// Disconnects the connections registered under `key`, returning how many
+    /// there were
+    ///
+    /// Each connection is removed from the registry, and its handler's
+    /// [`WebsocketRegistration::recv`] returns `None`, at which point the
+    /// handler should close the connection.  Handlers that are still running
+    /// at the end of the shutdown grace period (see
+    /// [`crate::ConfigWebsocket::shutdown_grace_period_ms`]) are cancelled,
+    /// closing their connections.
+    pub fn disconnect(&self, key: &str) -> usize {
+        let Some(slots) = self.connections.lock().unwrap().remove(key) else {
+            return 0;
+        };
+        for slot in slots.values() {
+            slot.disconnect.send_replace(true);
+        }
+        slots.len()
+    }
+
+    /// Returns the number of connections currently registered
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().values().map(BTreeMap::len).sum()
+    }
+
+    /// Returns the keys that connections are currently registered under
+    pub fn keys(&self) -> Vec<String> {
+        self.connections.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the number of messages queued for connections since the
+    /// registry was created (counting each connection that a message was
+    /// queued for)
+    pub fn sent_message_count(&self) -> u64 {
+        self.messages_sent.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of messages dropped because a connection's queue
+    /// was full (counting each connection that a message was dropped for)
+    pub fn dropped_message_count(&self) -> u64 {
+        self.messages_dropped.load(Ordering::SeqCst)
+    }
+
+    /// Registers a connection under `key`
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        key: String,
+        disconnect: Arc<watch::Sender<bool>>,
+    ) -> WebsocketRegistration {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel(self.queue_len);
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .insert(id, Slot { sender, disconnect });
+        WebsocketRegistration { registry: Arc::clone(self), key, id, receiver }
+    }
+
+    fn deliver<'a>(
+        &self,
+        slots: impl Iterator<Item = &'a Slot>,
+        message: WebsocketMessage,
+    ) -> usize {
+        let mut nsent = 0;
+        for slot in slots {
+            match slot.sender.try_send(message.clone()) {
+                Ok(()) => nsent += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    self.messages_dropped.fetch_add(1, Ordering::SeqCst);
+                }
+                // The handler has just stopped listening, and its registration
+                // is about to remove it from the registry.
+                Err(mpsc::error::TrySendError::Closed(_)) => (),
+            }
+        }
+        self.messages_sent.fetch_add(nsent as u64, Ordering::SeqCst);
+        nsent
+    }
+}
+
+/// A websocket connection's membership in a [`WebsocketRegistry`], returned by
+/// [`crate::WebsocketConnection::register`]
+///
+/// The connection stays registered until this is dropped or the connection is
+/// disconnected through the registry.
+#[derive(Debug)]
+pub struct WebsocketRegistration {
+    registry: Arc<WebsocketRegistry>,
+    key: String,
+    id: u64,
+    receiver: mpsc::Receiver<WebsocketMessage>,
+}
+
+impl WebsocketRegistration {
+    /// Returns the key that the connection is registered under
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Waits for the next message sent to this connection through the
+    /// registry
+    ///
+    /// Returns `None` once the connection has been disconnected through the
+    /// registry (see [`WebsocketRegistry::disconnect`]) and any messages sent
+    /// before that have been received.
+    pub async fn recv(&mut self) -> Option<WebsocketMessage> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for WebsocketRegistration {
+    fn drop(&mut self) {
+        let mut connections = self.registry.connections.lock().unwrap();
+        if let Some(slots) = connections.get_mut(&self.key) {
+            slots.remove(&self.id);
+            if slots.is_empty() {
+                connections.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WebsocketMessage;
+    use super::WebsocketRegistry;
+    use std::sync::Arc;
+
+    fn text(s: &str) -> WebsocketMessage {
+        WebsocketMessage::Text(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_websocket_registry() {
+        let registry = Arc::new(WebsocketRegistry::with_queue_len(2));
+        let disconnect = Arc::new(tokio::sync::watch::channel(false).0);
+        let mut alice1 =
+            registry.register("alice".to_string(), Arc::clone(&disconnect));
+        let mut alice2 =
+            registry.register("alice".to_string(), Arc::clone(&disconnect));
+        let mut bob =
+            registry.register("bob".to_string(), Arc::clone(&disconnect));
+        let carol = registry.register("carol".to_string(), disconnect.clone());
+        assert_eq!(registry.connection_count(), 4);
+        assert_eq!(registry.keys(), vec!["alice", "bob", "carol"]);
+        assert_eq!(alice1.key(), "alice");
+
+        assert_eq!(registry.send("alice", text("a")), 2);
+        assert_eq!(registry.send("nobody", text("x")), 0);
+        assert_eq!(registry.multicast(["bob", "nobody"], text("b")), 1);
+        assert_eq!(alice1.recv().await, Some(text("a")));
+        assert_eq!(alice2.recv().await, Some(text("a")));
+        assert_eq!(bob.recv().await, Some(text("b")));
+
+        // Carol isn't listening, so her queue fills up.
+        assert_eq!(registry.broadcast(text("c")), 4);
+        for registration in [&mut alice1, &mut alice2, &mut bob] {
+            assert_eq!(registration.recv().await, Some(text("c")));
+        }
+        assert_eq!(registry.broadcast(text("d")), 4);
+        assert_eq!(registry.broadcast(text("e")), 3);
+        assert_eq!(registry.sent_message_count(), 14);
+        assert_eq!(registry.dropped_message_count(), 1);
+
+        // Dropping a registration removes it from the registry.
+        drop(carol);
+        assert_eq!(registry.keys(), vec!["alice", "bob"]);
+
+        // Disconnecting a key signals its connections, which still get the
+        // messages sent before that.
+        assert_eq!(registry.disconnect("alice"), 2);
+        assert!(*disconnect.borrow());
+        assert_eq!(registry.keys(), vec!["bob"]);
+        assert_eq!(alice1.recv().await, Some(text("d")));
+        assert_eq!(alice1.recv().await, Some(text("e")));
+        assert_eq!(alice1.recv().await, None);
+        assert_eq!(registry.disconnect("alice"), 0);
+        assert_eq!(registry.connection_count(), 1);
+    }
+}
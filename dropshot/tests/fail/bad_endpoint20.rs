@@ -0,0 +1,22 @@
+// Copyright 2024 Oxide Computer Company
+
+#![allow(unused_imports)]
+
+use dropshot::endpoint;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+
+// Test: the status override is not a success status.
+#[endpoint {
+    method = GET,
+    path = "/test",
+    status = 404,
+}]
+async fn bad_status(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+// Copyright 2024 Oxide Computer Company
+
+#![allow(unused_imports)]
+
+use dropshot::endpoint;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+
+// Test: the status override forbids a body, but the response type has one.
+#[endpoint {
+    method = GET,
+    path = "/test",
+    status = 204,
+}]
+async fn body_with_no_content(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+fn main() {}
@@ -0,0 +1,227 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for handling requests without a network round trip, with an
+//! `ApiService` or with `HttpServer::handle_request`, and for mounting an
+//! `ApiService` on a server.
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ApiService;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServerStarter;
+use dropshot::RequestContext;
+use dropshot::TypedBody;
+use http::StatusCode;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, Service};
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+pub mod common;
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Greeting {
+    name: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/count",
+}]
+async fn get_count(
+    rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<usize>, HttpError> {
+    Ok(HttpResponseOk(*rqctx.context()))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/greet",
+}]
+async fn greet(
+    rqctx: RequestContext<usize>,
+    body: TypedBody<Greeting>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(format!(
+        "hello, {} (from {})",
+        body.into_inner().name,
+        rqctx.request.remote_addr().ip()
+    )))
+}
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_count).unwrap();
+    api.register(greet).unwrap();
+    api
+}
+
+async fn body_json(response: hyper::Response<Body>) -> serde_json::Value {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_api_service_in_process() {
+    let mut service = ApiService::new(api(), 3);
+    assert_eq!(*service.app_private(), 3);
+
+    let request = Request::get("/count").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("x-request-id"));
+    assert_eq!(body_json(response).await, 3);
+
+    let request = Request::post("/greet")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"name": "world"}"#))
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await, "hello, world (from 0.0.0.0)");
+
+    // Errors are reported just as they would be by a server.
+    let request = Request::get("/nonexistent").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(body_json(response).await["message"], "Not Found");
+
+    // So are the configured limits on requests.
+    let config =
+        ConfigDropshot { request_body_max_bytes: 8, ..Default::default() };
+    let mut service = ApiService::new_with_config(&config, api(), None, 3);
+    let request = Request::post("/greet")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"name": "world"}"#))
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(service.app_state().request_count(), 1);
+}
+
+#[tokio::test]
+async fn test_api_service_in_hyper_server() {
+    let service = ApiService::new(api(), 5);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let service = service.clone().with_remote_addr(conn.remote_addr());
+        async move { Ok::<_, Infallible>(service) }
+    });
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .serve(make_service);
+    let addr = server.local_addr();
+    let server_task = tokio::spawn(server);
+
+    let client = hyper::Client::new();
+    let request = Request::post(format!("http://{}/greet", addr))
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"name": "world"}"#))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await, "hello, world (from 127.0.0.1)");
+
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_server_handle_request() {
+    let server =
+        HttpServerStarter::new(&ConfigDropshot::default(), api(), None, 7)
+            .unwrap()
+            .start();
+
+    let request = Request::get("/count").body(Body::empty()).unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await, 7);
+
+    let request = Request::post("/greet")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"name": "self"}"#))
+        .unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        body_json(response).await,
+        format!("hello, self (from {})", server.local_addr().ip())
+    );
+
+    let request = Request::get("/nonexistent").body(Body::empty()).unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    server.close().await.unwrap();
+}
+
+#[endpoint {
+    method = GET,
+    path = "/name",
+}]
+async fn get_name(
+    rqctx: RequestContext<String>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(format!("{} ({})", rqctx.context(), rqctx.request.uri())))
+}
+
+#[tokio::test]
+async fn test_mounted_api() {
+    let mut admin_api = ApiDescription::new();
+    admin_api.register(get_name).unwrap();
+    let admin = ApiService::new(admin_api, String::from("admin"));
+    let server =
+        HttpServerStarter::new(&ConfigDropshot::default(), api(), None, 9)
+            .unwrap()
+            .mount("/admin", admin.clone())
+            .unwrap()
+            .start();
+
+    // Requests under the prefix go to the mounted API, without the prefix.
+    let request =
+        Request::get("/admin/name?verbose=true").body(Body::empty()).unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await, "admin (/name?verbose=true)");
+    assert_eq!(admin.app_state().request_count(), 1);
+
+    // Other requests go to the server's own API.
+    let request = Request::get("/count").body(Body::empty()).unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(body_json(response).await, 9);
+    let request = Request::get("/administrator").body(Body::empty()).unwrap();
+    let response = server.handle_request(request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(admin.app_state().request_count(), 1);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mount_prefix_errors() {
+    let starter = || {
+        HttpServerStarter::new(&ConfigDropshot::default(), api(), None, 9)
+            .unwrap()
+            .mount("/admin", ApiService::new(ApiDescription::new(), ()))
+            .unwrap()
+    };
+    let mount = |prefix: &str| {
+        let service = ApiService::new(ApiDescription::new(), ());
+        starter().mount(prefix, service).err().unwrap().to_string()
+    };
+
+    assert_eq!(
+        mount("/admin/more"),
+        "mount prefix \"/admin/more\" overlaps with \"/admin\""
+    );
+    assert_eq!(
+        mount("admin2"),
+        "mount prefix \"admin2\" must begin with \"/\" and not end with one"
+    );
+    assert_eq!(
+        mount("/admin2/"),
+        "mount prefix \"/admin2/\" must begin with \"/\" and not end with one"
+    );
+}
@@ -0,0 +1,95 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for controlling a server's clock.
+
+use chrono::{DateTime, Utc};
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use http::{Method, StatusCode};
+use std::time::Duration;
+
+pub mod common;
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_now).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/now",
+}]
+async fn get_now(
+    rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<DateTime<Utc>>, HttpError> {
+    Ok(HttpResponseOk(rqctx.server.now()))
+}
+
+#[tokio::test]
+async fn test_clock() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+    let start: DateTime<Utc> = "2020-01-01T00:00:00Z".parse().unwrap();
+
+    // Both handlers and the Date header follow the test's clock.
+    testctx.clock.set(start);
+    let mut response = client
+        .make_request_no_body(Method::GET, "/now", StatusCode::OK)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(http::header::DATE).unwrap(),
+        "Wed, 01 Jan 2020 00:00:00 GMT"
+    );
+    let now: DateTime<Utc> = read_json(&mut response).await;
+    assert_eq!(now, start);
+
+    // So do errors.
+    testctx.clock.advance(Duration::from_secs(3600));
+    let response = client
+        .make_request_no_body(
+            Method::GET,
+            "/nonexistent",
+            StatusCode::NOT_FOUND,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(response.message, "Not Found");
+    let mut response = client
+        .make_request_no_body(Method::GET, "/now", StatusCode::OK)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(http::header::DATE).unwrap(),
+        "Wed, 01 Jan 2020 01:00:00 GMT"
+    );
+    let now: DateTime<Utc> = read_json(&mut response).await;
+    assert_eq!(now, start + chrono::Duration::hours(1));
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_clock_running() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+
+    // By default, the clock follows the system clock, and it keeps running
+    // after it's moved ahead.
+    testctx.clock.advance(Duration::from_secs(86400));
+    let before = Utc::now() + chrono::Duration::days(1);
+    let mut response = client
+        .make_request_no_body(Method::GET, "/now", StatusCode::OK)
+        .await
+        .unwrap();
+    let now: DateTime<Utc> = read_json(&mut response).await;
+    let after = Utc::now() + chrono::Duration::days(1);
+    assert!(before <= now && now <= after);
+
+    testctx.teardown().await;
+}
@@ -107,6 +107,7 @@ fn make_config(
         ),
         request_body_max_bytes: 1024,
         default_handler_task_mode,
+        ..Default::default()
     }
 }
 
@@ -542,3 +543,35 @@ async fn test_config_handler_task_mode_detached() {
 
     server.close().await.unwrap();
 }
+
+#[tokio::test]
+async fn test_config_existing_listener() {
+    // The server uses the given listeners rather than binding its own.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let other_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let other_addr = other_listener.local_addr().unwrap();
+    let config = make_config("127.0.0.1", 1, HandlerTaskMode::Detached);
+    let server = HttpServerStarter::new_with_listener(
+        &config,
+        dropshot::ApiDescription::new(),
+        None,
+        0,
+        None,
+        listener,
+    )
+    .unwrap()
+    .listen_on(other_listener, None)
+    .unwrap()
+    .start();
+    assert_eq!(server.local_addr(), addr);
+    assert_eq!(server.local_addrs(), &[addr, other_addr]);
+
+    let client = hyper::Client::new();
+    for addr in [addr, other_addr] {
+        let uri: hyper::Uri = format!("http://{}/", addr).parse().unwrap();
+        let response = client.get(uri).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+    server.close().await.unwrap();
+}
@@ -0,0 +1,699 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for limits on connections, on concurrent requests, on the size
+//! of request headers, and on how slowly requests may be sent, for filtering
+//! connections, for TCP socket options, for the PROXY protocol, and for how the
+//! server copes with misbehaving clients.
+
+use dropshot::endpoint;
+use dropshot::test_util::ClientFault;
+use dropshot::test_util::ClientTestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigTcp;
+use dropshot::ConnectionFilter;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::HttpServerStarter;
+use dropshot::RequestContext;
+use dropshot::UntypedBody;
+use http::StatusCode;
+use hyper::client::conn::SendRequest;
+use hyper::{Body, Request, Response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+pub mod common;
+
+#[endpoint {
+    method = GET,
+    path = "/",
+}]
+async fn index(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/slow",
+}]
+async fn slow(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    Ok(HttpResponseOk(()))
+}
+
+/// Set once a request to `/busy` has started
+static BUSY_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[endpoint {
+    method = GET,
+    path = "/busy",
+}]
+async fn busy(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    BUSY_STARTED.store(true, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    Ok(HttpResponseOk(()))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/upload",
+}]
+async fn upload(
+    _rqctx: RequestContext<()>,
+    body: UntypedBody,
+) -> Result<HttpResponseOk<usize>, HttpError> {
+    Ok(HttpResponseOk(body.as_bytes().len()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/remote_addr",
+}]
+async fn remote_addr(
+    rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(rqctx.request.remote_addr().to_string()))
+}
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Stats {
+    connections: usize,
+    connections_refused: u64,
+    requests_shed: u64,
+    header_read_timeouts: u64,
+    slow_request_bodies: u64,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/stats",
+}]
+async fn server_stats(
+    rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Stats>, HttpError> {
+    Ok(HttpResponseOk(Stats {
+        connections: rqctx.server.connection_count(),
+        connections_refused: rqctx.server.refused_connection_count(),
+        requests_shed: rqctx.server.shed_request_count(),
+        header_read_timeouts: rqctx.server.header_read_timeout_count(),
+        slow_request_bodies: rqctx.server.slow_request_body_count(),
+    }))
+}
+
+fn start_server(config: &ConfigDropshot) -> HttpServer<()> {
+    starter(config).start()
+}
+
+fn starter(config: &ConfigDropshot) -> HttpServerStarter<()> {
+    let mut api = ApiDescription::new();
+    api.register(index).unwrap();
+    api.register(slow).unwrap();
+    api.register(busy).unwrap();
+    api.register(upload).unwrap();
+    api.register(server_stats).unwrap();
+    api.register(remote_addr).unwrap();
+    HttpServerStarter::new(config, api, None, ()).unwrap()
+}
+
+/// Opens an HTTP/1 connection to `server`, returning the handle for sending
+/// requests on it and the task driving it, which completes when it's closed
+async fn connect(
+    server: &HttpServer<()>,
+) -> (SendRequest<Body>, JoinHandle<Result<(), hyper::Error>>) {
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    let (sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+    (sender, tokio::spawn(conn))
+}
+
+async fn get(sender: &mut SendRequest<Body>, path: &str) -> Response<Body> {
+    let response = try_get(sender, path).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response
+}
+
+async fn try_get(sender: &mut SendRequest<Body>, path: &str) -> Response<Body> {
+    let request = Request::builder()
+        .uri(path)
+        .header(http::header::HOST, "localhost")
+        .body(Body::empty())
+        .unwrap();
+    sender.send_request(request).await.unwrap()
+}
+
+async fn get_stats(sender: &mut SendRequest<Body>) -> Stats {
+    let response = get(sender, "/stats").await;
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn closes_connection(response: &Response<Body>) -> bool {
+    response
+        .headers()
+        .get(http::header::CONNECTION)
+        .is_some_and(|value| value == "close")
+}
+
+#[tokio::test]
+async fn test_max_requests_per_connection() {
+    let config = ConfigDropshot {
+        max_requests_per_connection: Some(2),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender, conn) = connect(&server).await;
+
+    assert!(!closes_connection(&get(&mut sender, "/").await));
+    assert!(closes_connection(&get(&mut sender, "/").await));
+    tokio::time::timeout(Duration::from_secs(10), conn)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_max_connection_age() {
+    let config = ConfigDropshot {
+        max_connection_age_ms: Some(200),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender, conn) = connect(&server).await;
+
+    assert!(!closes_connection(&get(&mut sender, "/").await));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(closes_connection(&get(&mut sender, "/").await));
+    tokio::time::timeout(Duration::from_secs(10), conn)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_keep_alive_timeout() {
+    let config = ConfigDropshot {
+        keep_alive_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender, conn) = connect(&server).await;
+
+    // A request that takes longer than the timeout doesn't count as idle
+    // time.
+    let response = get(&mut sender, "/slow").await;
+    assert!(!closes_connection(&response));
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(!conn.is_finished());
+
+    // Once the connection is idle, it's closed.
+    tokio::time::timeout(Duration::from_secs(10), conn)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_header_read_timeout() {
+    let config = ConfigDropshot {
+        header_read_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+
+    // Start a request, but never finish its headers.
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").await.unwrap();
+    let mut buf = Vec::new();
+    let n = tokio::time::timeout(
+        Duration::from_secs(10),
+        stream.read_to_end(&mut buf),
+    )
+    .await
+    .unwrap()
+    .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    // Requests sent promptly are unaffected.
+    let (mut sender, _) = connect(&server).await;
+    let stats = get_stats(&mut sender).await;
+    assert_eq!(stats.header_read_timeouts, 1);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_min_request_body_rate() {
+    let config = ConfigDropshot {
+        min_request_body_rate: Some(1000),
+        request_body_max_bytes: 4096,
+        ..Default::default()
+    };
+    let server = start_server(&config);
+
+    // A body sent quickly enough is accepted.
+    let (mut sender, _) = connect(&server).await;
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/upload")
+        .header(http::header::HOST, "localhost")
+        .body(Body::from(vec![0u8; 2000]))
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    // A client that sends part of the body and then stalls has its
+    // connection closed.
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    stream
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\n\
+              Content-Length: 2000\r\n\r\n0123456789",
+        )
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    let _ = tokio::time::timeout(
+        Duration::from_secs(10),
+        stream.read_to_end(&mut buf),
+    )
+    .await
+    .unwrap();
+
+    let stats = get_stats(&mut sender).await;
+    assert_eq!(stats.slow_request_bodies, 1);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_faults() {
+    let config = ConfigDropshot {
+        min_request_body_rate: Some(1000),
+        request_body_max_bytes: 4096,
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let client = ClientTestContext::new(server.local_addr());
+    let upload_request = |len: usize| {
+        client
+            .request(http::Method::POST, "/upload")
+            .body(vec![0u8; len])
+            .build()
+    };
+
+    // A body trickled in quickly enough is accepted.
+    let response = client
+        .make_faulty_request(
+            upload_request(500),
+            ClientFault::TrickleBody {
+                chunk_size: 100,
+                interval: Duration::from_millis(10),
+            },
+        )
+        .await;
+    assert_eq!(response.status, Some(StatusCode::OK));
+    assert!(response.raw.ends_with(b"500"));
+
+    // A client that resets the connection partway through the body, or goes
+    // away before reading the response body, doesn't disturb the server.
+    let response = client
+        .make_faulty_request(
+            upload_request(2000),
+            ClientFault::ResetMidBody { after: 10 },
+        )
+        .await;
+    assert_eq!(response.status, None);
+    let response = client
+        .make_faulty_request(
+            client.request(http::Method::GET, "/").build(),
+            ClientFault::DisconnectAfterResponseHeaders,
+        )
+        .await;
+    assert_eq!(response.status, Some(StatusCode::OK));
+
+    // A client that never sends the body gets an error, and has its
+    // connection closed.
+    let response = client
+        .make_faulty_request(
+            upload_request(2000),
+            ClientFault::StallAfterHeaders {
+                duration: Duration::from_secs(10),
+            },
+        )
+        .await;
+    assert!(response.server_closed);
+    assert_eq!(response.status, Some(StatusCode::BAD_REQUEST));
+
+    let (mut sender, _) = connect(&server).await;
+    let stats = get_stats(&mut sender).await;
+    assert_eq!(stats.slow_request_bodies, 1);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let config =
+        ConfigDropshot { max_connections: Some(1), ..Default::default() };
+    let server = start_server(&config);
+    let (mut sender1, _conn1) = connect(&server).await;
+    let stats = get_stats(&mut sender1).await;
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.connections_refused, 0);
+
+    // Connections beyond the limit are closed as soon as they're accepted,
+    // without the server reading any requests from them.
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+        let _ = stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await;
+        let mut buf = Vec::new();
+        let nread = tokio::time::timeout(
+            Duration::from_secs(10),
+            stream.read_to_end(&mut buf),
+        )
+        .await
+        .unwrap()
+        .unwrap_or(0);
+        assert_eq!(nread, 0);
+    }
+
+    // The number of open connections never went past the limit, and no
+    // requests were shed.
+    let stats = get_stats(&mut sender1).await;
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.connections_refused, 3);
+    assert_eq!(stats.requests_shed, 0);
+
+    // Once the first connection closes, there's room for another.
+    drop(sender1);
+    let (mut sender3, _conn3) = loop {
+        let (mut sender, conn) = connect(&server).await;
+        let request = Request::builder()
+            .uri("/")
+            .header(http::header::HOST, "localhost")
+            .body(Body::empty())
+            .unwrap();
+        if sender.send_request(request).await.is_ok() {
+            break (sender, conn);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(get_stats(&mut sender3).await.connections, 1);
+
+    drop(sender3);
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_max_concurrent_requests() {
+    let config = ConfigDropshot {
+        max_concurrent_requests: Some(1),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender1, _conn1) = connect(&server).await;
+    let (mut sender2, _conn2) = connect(&server).await;
+
+    // While one request is in progress, others are rejected.
+    let busy_request =
+        tokio::spawn(async move { get(&mut sender1, "/busy").await.status() });
+    while !BUSY_STARTED.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let response = try_get(&mut sender2, "/").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(!closes_connection(&response));
+    assert_eq!(busy_request.await.unwrap(), StatusCode::OK);
+
+    // Afterward, requests are accepted again.
+    assert_eq!(get_stats(&mut sender2).await.requests_shed, 1);
+
+    drop(sender2);
+    server.close().await.unwrap();
+}
+
+/// Sends a GET request for `path` with the given extra header fields,
+/// returning the status and the error message in the response
+async fn get_with_headers(
+    sender: &mut SendRequest<Body>,
+    path: &str,
+    headers: &[(String, String)],
+) -> (StatusCode, Option<String>) {
+    let mut request =
+        Request::builder().uri(path).header(http::header::HOST, "localhost");
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let request = request.body(Body::empty()).unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let message = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|error| error["message"].as_str().map(str::to_owned));
+    (status, message)
+}
+
+#[tokio::test]
+async fn test_request_size_limits() {
+    let config = ConfigDropshot {
+        max_uri_length: Some(64),
+        max_request_header_bytes: Some(1024),
+        max_request_headers: Some(8),
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender, _) = connect(&server).await;
+
+    // Requests within the limits are handled as usual.
+    let path = format!("/?q={}", "a".repeat(60));
+    let headers = vec![("x-filler".to_string(), "b".repeat(900))];
+    let (status, _) = get_with_headers(&mut sender, &path, &headers).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let path = format!("/?q={}", "a".repeat(61));
+    let (status, message) = get_with_headers(&mut sender, &path, &[]).await;
+    assert_eq!(status, StatusCode::URI_TOO_LONG);
+    assert_eq!(message.unwrap(), "request URI is too long (max 64 bytes)");
+
+    let headers = vec![("x-filler".to_string(), "b".repeat(1024))];
+    let (status, message) = get_with_headers(&mut sender, "/", &headers).await;
+    assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert_eq!(
+        message.unwrap(),
+        "header fields are too large (max 1024 bytes)"
+    );
+
+    let headers = (0..8)
+        .map(|i| (format!("x-filler-{}", i), "b".to_string()))
+        .collect::<Vec<_>>();
+    let (status, message) = get_with_headers(&mut sender, "/", &headers).await;
+    assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert_eq!(message.unwrap(), "too many header fields (max 8)");
+
+    // The connection remains usable after those requests are rejected.
+    get(&mut sender, "/").await;
+
+    // Requests far beyond the limit are cut off by hyper, which doesn't
+    // provide an error body.
+    let (mut sender, _) = connect(&server).await;
+    let headers = vec![("x-filler".to_string(), "b".repeat(64 * 1024))];
+    let (status, message) = get_with_headers(&mut sender, "/", &headers).await;
+    assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert!(message.is_none());
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_options() {
+    let config = ConfigDropshot {
+        tcp: ConfigTcp {
+            nodelay: true,
+            reuse_port: cfg!(any(target_os = "linux", target_os = "macos")),
+            backlog: Some(16),
+            keepalive_time_secs: Some(60),
+            keepalive_interval_secs: Some(10),
+            keepalive_retries: Some(3),
+        },
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let (mut sender, _) = connect(&server).await;
+    get(&mut sender, "/").await;
+
+    // With `SO_REUSEPORT`, another server can share the address.
+    if config.tcp.reuse_port {
+        let config = ConfigDropshot {
+            bind_address: server.local_addr(),
+            ..config.clone()
+        };
+        let other_server = start_server(&config);
+        assert_eq!(other_server.local_addr(), server.local_addr());
+        other_server.close().await.unwrap();
+    }
+
+    drop(sender);
+    server.close().await.unwrap();
+}
+
+/// Returns whether the server closes `stream` without responding to a request
+async fn is_rejected(mut stream: TcpStream) -> bool {
+    // The server may close the connection before the request is written.
+    let _ =
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    let mut buf = Vec::new();
+    let n = tokio::time::timeout(
+        Duration::from_secs(10),
+        stream.read_to_end(&mut buf),
+    )
+    .await
+    .unwrap()
+    .unwrap_or(0);
+    n == 0
+}
+
+#[tokio::test]
+async fn test_connection_filter() {
+    let server = starter(&ConfigDropshot::default())
+        .connection_filter(|peer: SocketAddr| !peer.ip().is_loopback())
+        .start();
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    assert!(is_rejected(stream).await);
+    server.close().await.unwrap();
+
+    let server = starter(&ConfigDropshot::default())
+        .connection_filter(|peer: SocketAddr| peer.ip().is_loopback())
+        .start();
+    let (mut sender, _conn) = connect(&server).await;
+    get(&mut sender, "/").await;
+    drop(sender);
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() {
+    let config = ConfigDropshot {
+        proxy_protocol: true,
+        trusted_proxies: vec!["127.0.0.0/8".parse().unwrap()],
+        ..Default::default()
+    };
+    let server = start_server(&config);
+
+    // The address in the header is used as the connection's remote address.
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    stream
+        .write_all(
+            b"PROXY TCP4 192.0.2.1 127.0.0.1 5000 80\r\n\
+              GET /remote_addr HTTP/1.1\r\nHost: localhost\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    let response = String::from_utf8(buf).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\"192.0.2.1:5000\""), "{}", response);
+
+    // Connections without a header are closed.
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    assert!(is_rejected(stream).await);
+    server.close().await.unwrap();
+
+    // So are connections from peers that aren't trusted proxies, even with
+    // a header.
+    let config = ConfigDropshot {
+        proxy_protocol: true,
+        trusted_proxies: vec!["192.0.2.0/24".parse().unwrap()],
+        ..Default::default()
+    };
+    let server = start_server(&config);
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 5000 80\r\n")
+        .await
+        .unwrap();
+    assert!(is_rejected(stream).await);
+    server.close().await.unwrap();
+
+    // The PROXY protocol is useless without any trusted proxies.
+    let config = ConfigDropshot { proxy_protocol: true, ..Default::default() };
+    let error =
+        HttpServerStarter::new(&config, ApiDescription::new(), None, ())
+            .err()
+            .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "the PROXY protocol requires at least one trusted proxy"
+    );
+}
+
+/// Accepts a single connection at a time
+#[derive(Clone, Default)]
+struct OneAtATime(Arc<AtomicUsize>);
+
+impl ConnectionFilter for OneAtATime {
+    fn accept(&self, _peer: SocketAddr) -> bool {
+        self.0
+            .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    fn closed(&self, _peer: SocketAddr) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn test_connection_filter_closed() {
+    let filter = OneAtATime::default();
+    let server = starter(&ConfigDropshot::default())
+        .connection_filter(filter.clone())
+        .start();
+    let (mut sender1, conn1) = connect(&server).await;
+    get(&mut sender1, "/").await;
+
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    assert!(is_rejected(stream).await);
+    assert_eq!(get_stats(&mut sender1).await.connections, 1);
+
+    // Once the first connection closes, the filter is told about it and
+    // accepts another.
+    drop(sender1);
+    conn1.await.unwrap().unwrap();
+    while filter.0.load(Ordering::SeqCst) != 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let (mut sender2, _conn2) = connect(&server).await;
+    get(&mut sender2, "/").await;
+
+    drop(sender2);
+    server.close().await.unwrap();
+}
@@ -0,0 +1,226 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for CORS support.
+
+use dropshot::endpoint;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigCors;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpErrorResponseBody;
+use dropshot::HttpResponseOk;
+use dropshot::HttpResponseUpdatedNoContent;
+use dropshot::RequestContext;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+
+pub mod common;
+
+const CONSOLE_ORIGIN: &str = "https://console.example.com";
+const OTHER_ORIGIN: &str = "https://other.example.com";
+
+fn api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(get_widget).unwrap();
+    api.register(put_widget).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/widget",
+}]
+async fn get_widget(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/widget",
+}]
+async fn put_widget(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+fn setup(cors: ConfigCors) -> TestContext<()> {
+    let config = ConfigDropshot { cors, ..Default::default() };
+    let mut testctx = TestContext::new(api(), (), &config);
+    for name in [
+        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        header::ACCESS_CONTROL_MAX_AGE,
+        header::VARY,
+    ] {
+        testctx.client_testctx =
+            testctx.client_testctx.with_allowed_header(name.as_str());
+    }
+    testctx
+}
+
+async fn preflight(
+    testctx: &TestContext<()>,
+    origin: &str,
+    method: &str,
+    path: &str,
+    expected_status: StatusCode,
+) -> Result<Response<Body>, HttpErrorResponseBody> {
+    let client = &testctx.client_testctx;
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri(client.url(path))
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+    client.make_request_with_request(request, expected_status).await
+}
+
+async fn get_from(testctx: &TestContext<()>, origin: &str) -> Response<Body> {
+    let client = &testctx.client_testctx;
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(client.url("/widget"))
+        .header(header::ORIGIN, origin)
+        .body(Body::empty())
+        .unwrap();
+    client.make_request_with_request(request, StatusCode::OK).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_cors_disabled() {
+    let testctx = setup(ConfigCors::Disabled);
+
+    // Preflight requests are handled like any other request, and there's no
+    // OPTIONS endpoint.
+    let client = &testctx.client_testctx;
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri(client.url("/widget"))
+        .header(header::ORIGIN, CONSOLE_ORIGIN)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .body(Body::empty())
+        .unwrap();
+    client
+        .make_request_with_request(request, StatusCode::METHOD_NOT_ALLOWED)
+        .await
+        .unwrap_err();
+
+    let response = get_from(&testctx, CONSOLE_ORIGIN).await;
+    assert!(response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_cors_same_site_console() {
+    let testctx = setup(ConfigCors::SameSiteConsole {
+        allowed_origins: vec![String::from(CONSOLE_ORIGIN)],
+    });
+
+    let response = preflight(
+        &testctx,
+        CONSOLE_ORIGIN,
+        "PUT",
+        "/widget",
+        StatusCode::NO_CONTENT,
+    )
+    .await
+    .unwrap();
+    let headers = response.headers();
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], CONSOLE_ORIGIN);
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "PUT");
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+    assert_eq!(headers[header::VARY], "origin");
+
+    let response = get_from(&testctx, CONSOLE_ORIGIN).await;
+    let headers = response.headers();
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], CONSOLE_ORIGIN);
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+    // Other origins get no CORS headers, and their preflights aren't handled.
+    preflight(
+        &testctx,
+        OTHER_ORIGIN,
+        "PUT",
+        "/widget",
+        StatusCode::METHOD_NOT_ALLOWED,
+    )
+    .await
+    .unwrap_err();
+    let response = get_from(&testctx, OTHER_ORIGIN).await;
+    let headers = response.headers();
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    assert_eq!(headers[header::VARY], "origin");
+
+    // Preflights for routes that don't exist aren't handled either.
+    preflight(
+        &testctx,
+        CONSOLE_ORIGIN,
+        "DELETE",
+        "/widget",
+        StatusCode::METHOD_NOT_ALLOWED,
+    )
+    .await
+    .unwrap_err();
+    preflight(
+        &testctx,
+        CONSOLE_ORIGIN,
+        "GET",
+        "/gadget",
+        StatusCode::NOT_FOUND,
+    )
+    .await
+    .unwrap_err();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_cors_public_api() {
+    let testctx = setup(ConfigCors::PublicApi);
+
+    let response = preflight(
+        &testctx,
+        OTHER_ORIGIN,
+        "PUT",
+        "/widget",
+        StatusCode::NO_CONTENT,
+    )
+    .await
+    .unwrap();
+    let headers = response.headers();
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "PUT");
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    assert!(headers.get(header::VARY).is_none());
+
+    let response = get_from(&testctx, OTHER_ORIGIN).await;
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+    // Requests without an `Origin` header aren't CORS requests.
+    let response = testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/widget", StatusCode::OK)
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+
+    testctx.teardown().await;
+}
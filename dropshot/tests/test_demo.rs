@@ -15,34 +15,53 @@
 
 use dropshot::channel;
 use dropshot::endpoint;
+use dropshot::http_response_accepted;
 use dropshot::http_response_found;
+use dropshot::http_response_permanent_redirect;
 use dropshot::http_response_see_other;
 use dropshot::http_response_temporary_redirect;
 use dropshot::test_util::object_delete;
 use dropshot::test_util::read_content_length;
 use dropshot::test_util::read_json;
 use dropshot::test_util::read_string;
+use dropshot::test_util::TestContext;
 use dropshot::test_util::TEST_HEADER_1;
 use dropshot::test_util::TEST_HEADER_2;
 use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigWebsocket;
+use dropshot::Deadline;
+use dropshot::EventStream;
+use dropshot::EventStreamUpgrade;
 use dropshot::HttpError;
+use dropshot::HttpResponseAcceptedLocation;
 use dropshot::HttpResponseDeleted;
 use dropshot::HttpResponseFound;
 use dropshot::HttpResponseHeaders;
 use dropshot::HttpResponseOk;
+use dropshot::HttpResponsePermanentRedirect;
 use dropshot::HttpResponseSeeOther;
 use dropshot::HttpResponseTemporaryRedirect;
 use dropshot::HttpResponseUpdatedNoContent;
+use dropshot::HttpResponseWithStatus;
 use dropshot::Path;
 use dropshot::Query;
 use dropshot::RawRequest;
 use dropshot::RequestContext;
+use dropshot::SpooledBody;
 use dropshot::StreamingBody;
 use dropshot::TypedBody;
 use dropshot::UntypedBody;
 use dropshot::WebsocketChannelResult;
 use dropshot::WebsocketConnection;
+use dropshot::WebsocketEndpointResult;
+use dropshot::WebsocketMessage;
+use dropshot::WebsocketRegistry;
+use dropshot::WebsocketUpgrade;
 use dropshot::CONTENT_TYPE_JSON;
+use dropshot::HEADER_GRPC_TIMEOUT;
+use dropshot::HEADER_REQUEST_TIMEOUT;
 use futures::stream::StreamExt;
 use futures::SinkExt;
 use futures::TryStreamExt;
@@ -53,6 +72,8 @@ use hyper::Response;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_tungstenite::tungstenite::protocol::Role;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
@@ -73,18 +94,43 @@ fn demo_api() -> ApiDescription<usize> {
     api.register(demo_handler_path_param_u32).unwrap();
     api.register(demo_handler_untyped_body).unwrap();
     api.register(demo_handler_streaming_body).unwrap();
+    api.register(demo_handler_spooled_body).unwrap();
+    api.register(demo_handler_deadline).unwrap();
+    api.register(demo_handler_status_override).unwrap();
     api.register(demo_handler_raw_request).unwrap();
     api.register(demo_handler_delete).unwrap();
     api.register(demo_handler_head_get).unwrap();
     api.register(demo_handler_head_head).unwrap();
     api.register(demo_handler_headers).unwrap();
+    api.register(demo_handler_headers_typed).unwrap();
     api.register(demo_handler_302_bogus).unwrap();
     api.register(demo_handler_302_found).unwrap();
     api.register(demo_handler_303_see_other).unwrap();
     api.register(demo_handler_307_temporary_redirect).unwrap();
+    api.register(demo_handler_308_permanent_redirect).unwrap();
+    api.register(demo_handler_207_multi_status).unwrap();
+    api.register(ApiEndpoint::from(demo_handler_202_accepted).response_link(
+        "status",
+        "demo_handler_args_1",
+        &[("job", "$response.body#/job")],
+    ))
+    .unwrap();
     api.register(demo_handler_websocket).unwrap();
+    api.register(demo_handler_websocket_echo).unwrap();
+    api.register(demo_handler_websocket_keepalive).unwrap();
+    api.register(demo_handler_websocket_stubborn).unwrap();
+    api.register(demo_handler_websocket_chat).unwrap();
+    api.register(demo_handler_websocket_subprotocol).unwrap();
+    api.register(demo_handler_websocket_limited).unwrap();
+    api.register(demo_handler_websocket_queue_drop).unwrap();
+    api.register(demo_handler_websocket_queue_close).unwrap();
+    api.register(demo_handler_websocket_context).unwrap();
+    api.register(demo_handler_event_stream).unwrap();
     api.register(demo_handler_request_compat).unwrap();
     api.register(demo_handler_request_addresses).unwrap();
+    api.register(demo_handler_request_client_ip).unwrap();
+    api.register(dropshot::request_echo_endpoint("/testing/request_echo"))
+        .unwrap();
 
     // We don't need to exhaustively test these cases, as they're tested by unit
     // tests.
@@ -603,6 +649,40 @@ async fn test_demo_path_param_uuid() {
     testctx.teardown().await;
 }
 
+// Path parameter errors can be reported in whatever form the consumer likes.
+#[tokio::test]
+async fn test_demo_path_error_handler() {
+    let api = demo_api().path_error_handler(|error| {
+        HttpError::for_client_error(
+            Some(String::from("BadPathParameter")),
+            StatusCode::NOT_FOUND,
+            format!(
+                "name={:?} value={:?} expected={:?}",
+                error.name, error.value, error.expected
+            ),
+        )
+    });
+    let testctx = common::test_setup(api);
+
+    let error = testctx
+        .client_testctx
+        .make_request_with_body(
+            Method::GET,
+            "/testing/demo_path_u32/abcd",
+            Body::empty(),
+            StatusCode::NOT_FOUND,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.error_code.as_deref(), Some("BadPathParameter"));
+    assert_eq!(
+        error.message,
+        "name=Some(\"test1\") value=Some(\"abcd\") expected=Some(\"u32\")"
+    );
+
+    testctx.teardown().await;
+}
+
 // The "demo_path_param_u32" handler takes just a single u32 path parameter.
 #[tokio::test]
 async fn test_demo_path_param_u32() {
@@ -621,10 +701,9 @@ async fn test_demo_path_param_u32() {
         )
         .await
         .unwrap_err();
-    assert!(
-        error.message.starts_with("bad parameter in URL path:"),
-        "{}",
+    assert_eq!(
         error.message,
+        "bad parameter in URL path: test1: unable to parse 'abcd' as u32"
     );
 
     // Success case (use the number)
@@ -783,6 +862,190 @@ async fn test_streaming_body() {
     );
 }
 
+// Test `SpooledBody`, along with a per-endpoint body size limit.
+#[tokio::test]
+async fn test_spooled_body() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    // Success case: small bodies stay in memory.
+    let body: Vec<u8> = (0..512).map(|i| (i % 251) as u8).collect();
+    let mut response = client
+        .make_request_with_body(
+            Method::PUT,
+            "/testing/spooled_body",
+            body.clone().into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoSpooled = read_json(&mut response).await;
+    assert_eq!(json.nbytes, 512);
+    assert!(!json.spooled);
+    assert_eq!(json.checksum, body.iter().map(|b| u64::from(*b)).sum::<u64>());
+
+    // Success case: bodies over the server-wide limit are accepted up to the
+    // endpoint's limit, and written to a file.
+    let body: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    let mut response = client
+        .make_request_with_body(
+            Method::PUT,
+            "/testing/spooled_body",
+            body.clone().into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoSpooled = read_json(&mut response).await;
+    assert_eq!(json.nbytes, 4096);
+    assert!(json.spooled);
+    assert_eq!(json.checksum, body.iter().map(|b| u64::from(*b)).sum::<u64>());
+
+    // Error case: body exceeds the endpoint's limit.
+    let big_body = vec![0u8; 4097];
+    let error = client
+        .make_request_with_body(
+            Method::PUT,
+            "/testing/spooled_body",
+            big_body.into(),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "request body exceeded maximum size of 4096 bytes"
+    );
+
+    testctx.teardown().await;
+}
+
+// Test `Deadline`, derived from the request's timeout headers.
+#[tokio::test]
+async fn test_deadline() {
+    let api = demo_api();
+    let config = ConfigDropshot {
+        request_timeout_max_ms: Some(60_000),
+        ..Default::default()
+    };
+    let testctx = TestContext::new(api, 0_usize, &config);
+    let client = &testctx.client_testctx;
+    let request = |sleep_ms: u64, header: Option<(&str, &str)>| {
+        let mut builder = hyper::Request::builder().method(Method::GET).uri(
+            client.url(&format!("/testing/deadline?sleep_ms={}", sleep_ms)),
+        );
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    // Success case: no timeout headers means no deadline.
+    let mut response = client
+        .make_request_with_request(request(0, None), StatusCode::OK)
+        .await
+        .unwrap();
+    let json: DemoDeadline = read_json(&mut response).await;
+    assert_eq!(json.remaining_ms, None);
+
+    // Success case: the handler sees how much time it has left.
+    let mut response = client
+        .make_request_with_request(
+            request(0, Some((HEADER_REQUEST_TIMEOUT, "30"))),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoDeadline = read_json(&mut response).await;
+    let remaining_ms = json.remaining_ms.unwrap();
+    assert!(remaining_ms <= 30_000);
+    assert!(remaining_ms > 20_000);
+
+    // Error case: the handler takes longer than the client will wait.
+    let error = client
+        .make_request_with_request(
+            request(1_000, Some((HEADER_GRPC_TIMEOUT, "50m"))),
+            StatusCode::GATEWAY_TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.message, "request deadline exceeded");
+
+    // Success case: timeouts longer than the server allows are cut short.
+    let mut response = client
+        .make_request_with_request(
+            request(0, Some((HEADER_REQUEST_TIMEOUT, "3600"))),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoDeadline = read_json(&mut response).await;
+    assert!(json.remaining_ms.unwrap() <= 60_000);
+
+    // Success case: malformed timeouts are ignored.
+    let mut response = client
+        .make_request_with_request(
+            request(0, Some((HEADER_GRPC_TIMEOUT, "50"))),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoDeadline = read_json(&mut response).await;
+    assert_eq!(json.remaining_ms, None);
+
+    testctx.teardown().await;
+
+    // Success case: by default, timeout headers are ignored.
+    let testctx = common::test_setup(demo_api());
+    let client = &testctx.client_testctx;
+    let mut response = client
+        .make_request_with_request(
+            hyper::Request::builder()
+                .method(Method::GET)
+                .uri(client.url("/testing/deadline?sleep_ms=0"))
+                .header(HEADER_REQUEST_TIMEOUT, "30")
+                .body(Body::empty())
+                .unwrap(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let json: DemoDeadline = read_json(&mut response).await;
+    assert_eq!(json.remaining_ms, None);
+    testctx.teardown().await;
+}
+
+// Test overriding the success status and reason phrase of a response type
+// with `status` and `reason`.
+#[tokio::test]
+async fn test_status_override() {
+    let api = demo_api();
+    let spec = api.openapi("test", "1.0.0").json().unwrap();
+    let responses =
+        &spec["paths"]["/testing/status_override"]["post"]["responses"];
+    assert!(responses.get("200").is_none());
+    assert_eq!(responses["202"]["description"], "Queued");
+
+    let testctx = common::test_setup(api);
+    let mut response = testctx
+        .client_testctx
+        .make_request_no_body(
+            Method::POST,
+            "/testing/status_override",
+            StatusCode::ACCEPTED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.extensions().get::<hyper::ext::ReasonPhrase>().unwrap(),
+        &hyper::ext::ReasonPhrase::from_static(b"Queued")
+    );
+    let body: String = read_json(&mut response).await;
+    assert_eq!(body, "queued");
+    testctx.teardown().await;
+}
+
 // Test `RawRequest`.
 #[tokio::test]
 async fn test_raw_request() {
@@ -892,6 +1155,46 @@ async fn test_header_request() {
     assert_eq!(headers, vec!["hi", "howdy"]);
 }
 
+// Test typed response headers
+#[tokio::test]
+async fn test_header_typed() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let response = testctx
+        .client_testctx
+        .make_request(
+            Method::GET,
+            "/testing/headers_typed",
+            None as Option<()>,
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .expect("expected success");
+    let headers = response.headers();
+    assert_eq!(headers[TEST_HEADER_1], "41");
+    assert_eq!(headers[TEST_HEADER_2], "true");
+    assert!(headers.get(http::header::ETAG).is_none());
+
+    // The headers' types are reflected in the OpenAPI document.
+    let spec = demo_api().openapi("test", "1.0.0").json().unwrap();
+    let headers = &spec["paths"]["/testing/headers_typed"]["get"]["responses"]
+        ["204"]["headers"];
+    assert_eq!(
+        headers[TEST_HEADER_1]["schema"],
+        serde_json::json!({
+            "type": "integer",
+            "format": "uint32",
+            "minimum": 0,
+        })
+    );
+    assert_eq!(headers[TEST_HEADER_1]["required"], true);
+    assert_eq!(headers[TEST_HEADER_2]["schema"]["type"], "boolean");
+    assert_eq!(headers["ETag"]["schema"]["type"], "string");
+    assert_eq!(headers["ETag"]["required"], serde_json::Value::Null);
+
+    testctx.teardown().await;
+}
+
 // Test 302 "Found" response with an invalid header value
 #[tokio::test]
 async fn test_302_bogus() {
@@ -983,6 +1286,99 @@ async fn test_307_temporary_redirect() {
     assert_eq!(read_string(&mut response).await, "");
 }
 
+// Test 308 "Permanent Redirect" response
+#[tokio::test]
+async fn test_308_permanent_redirect() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let mut response = testctx
+        .client_testctx
+        .make_request(
+            Method::GET,
+            "/testing/308_permanent_redirect",
+            None as Option<()>,
+            StatusCode::PERMANENT_REDIRECT,
+        )
+        .await
+        .expect("expected success");
+    let headers = response
+        .headers()
+        .get_all(http::header::LOCATION)
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(headers, vec!["/path4"]);
+    assert_eq!(read_string(&mut response).await, "");
+}
+
+// Test 202 "Accepted" response with a status location
+#[tokio::test]
+async fn test_202_accepted() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let mut response = testctx
+        .client_testctx
+        .with_allowed_header(http::header::RETRY_AFTER.as_str())
+        .make_request(
+            Method::POST,
+            "/testing/202_accepted",
+            None as Option<()>,
+            StatusCode::ACCEPTED,
+        )
+        .await
+        .expect("expected success");
+    let headers = response.headers();
+    assert_eq!(headers[http::header::LOCATION], "/jobs/12");
+    assert_eq!(headers[http::header::RETRY_AFTER], "2");
+    let body: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(body, serde_json::json!({ "job": 12 }));
+
+    let spec = demo_api().openapi("demo", "1.0.0").json().unwrap();
+    let response =
+        &spec["paths"]["/testing/202_accepted"]["post"]["responses"]["202"];
+    assert_eq!(
+        response["links"],
+        serde_json::json!({
+            "status": {
+                "operationId": "demo_handler_args_1",
+                "parameters": { "job": "$response.body#/job" },
+            },
+        })
+    );
+    assert_eq!(response["headers"]["location"]["required"], true);
+    assert_eq!(response["headers"]["retry-after"]["schema"]["type"], "integer");
+    assert!(response["headers"]["retry-after"].get("required").is_none());
+}
+
+// Test a response with a status code that has no dedicated type
+#[tokio::test]
+async fn test_207_with_status() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let mut response = testctx
+        .client_testctx
+        .make_request(
+            Method::GET,
+            "/testing/207_multi_status",
+            None as Option<()>,
+            StatusCode::MULTI_STATUS,
+        )
+        .await
+        .expect("expected success");
+    let body: Vec<String> = read_json(&mut response).await;
+    assert_eq!(body, vec!["one", "two"]);
+
+    let spec = demo_api().openapi("demo", "1.0.0").json().unwrap();
+    let responses =
+        &spec["paths"]["/testing/207_multi_status"]["get"]["responses"];
+    assert_eq!(responses["207"]["description"], "multi-status");
+    assert_eq!(
+        responses["207"]["content"]["application/json"]["schema"]["type"],
+        "array"
+    );
+    assert!(responses.get("200").is_none());
+}
+
 // The "test_demo_websocket" handler upgrades to a websocket and exchanges
 // greetings with the client.
 #[tokio::test]
@@ -1004,65 +1400,726 @@ async fn test_demo_websocket() {
 }
 
 #[tokio::test]
-async fn test_request_compat() {
+async fn test_demo_websocket_limit() {
     let api = demo_api();
-    let testctx = common::test_setup(api);
-    let mut response = testctx
+    let config = ConfigDropshot {
+        max_websocket_connections: Some(1),
+        ..Default::default()
+    };
+    let testctx = TestContext::new(api, 0_usize, &config);
+
+    let path = format!(
+        "ws://{}/testing/websocket",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(&path).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello client".to_string()));
+
+    // While the first connection is open, another one is refused.
+    match tokio_tungstenite::connect_async(&path).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status().as_u16(), 503);
+        }
+        other => panic!("expected 503, got {:?}", other),
+    }
+
+    // Ordinary requests are unaffected.
+    testctx
         .client_testctx
-        .make_request(
+        .make_request_no_body(
             Method::GET,
             "/testing/request_compat",
-            None as Option<()>,
             StatusCode::OK,
         )
         .await
         .expect("expected success");
-    let json: String = read_json(&mut response).await;
-    assert_eq!(json, "dummy");
+
+    // Once the first connection is finished, new ones are accepted again.
+    ws.send(Message::Text("hello server".to_string())).await.unwrap();
+    let mut ws = loop {
+        match tokio_tungstenite::connect_async(&path).await {
+            Ok((ws, _)) => break ws,
+            Err(tokio_tungstenite::tungstenite::Error::Http(response))
+                if response.status().as_u16() == 503 =>
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            Err(error) => panic!("unexpected error: {:?}", error),
+        }
+    };
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello client".to_string()));
+    ws.send(Message::Text("hello server".to_string())).await.unwrap();
+
     testctx.teardown().await;
 }
 
+/// Reads from the websocket until the server closes it, failing if that takes
+/// more than a few seconds
+async fn websocket_wait_closed<S>(ws: &mut S)
+where
+    S: futures::Stream<
+            Item = Result<Message, tokio_tungstenite::tungstenite::Error>,
+        > + Unpin,
+{
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while let Some(Ok(_)) = ws.next().await {}
+    })
+    .await
+    .expect("websocket was not closed");
+}
+
 #[tokio::test]
-async fn test_request_remote_addr() {
+async fn test_demo_websocket_idle_timeout() {
     let api = demo_api();
-    let testctx = common::test_setup(api);
-    let laddr = testctx.server.local_addr();
-    let mut response = testctx
-        .client_testctx
-        .make_request(
-            Method::GET,
-            "/testing/request_addresses",
-            None as Option<()>,
-            StatusCode::OK,
-        )
-        .await
-        .expect("expected success");
-    let json: Vec<String> = read_json(&mut response).await;
-    assert_eq!(json.len(), 4);
-    // Confirm the local address and port seen from inside the server matches
-    // the one that we got from the test context:
-    assert_eq!(json[0], laddr.ip().to_string());
-    assert_eq!(json[1], laddr.port().to_string());
-    // There does not appear to be an easy way to determine which port was
-    // used for the outbound request we make here, but we at least know that
-    // it must not be the same as the server listen port:
-    assert_eq!(json[2], laddr.ip().to_string());
-    assert_ne!(json[3], laddr.port().to_string());
-    testctx.teardown().await;
-}
+    let config = ConfigDropshot {
+        websocket: ConfigWebsocket {
+            idle_timeout_ms: Some(300),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let testctx = TestContext::new(api, 0_usize, &config);
+    let path = format!(
+        "ws://{}/testing/websocket_echo",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) = tokio_tungstenite::connect_async(path).await.unwrap();
 
-// Demo handler functions
+    // Messages in either direction keep the connection open.
+    for _ in 0..3 {
+        ws.send(Message::Text("hello".to_string())).await.unwrap();
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("hello".to_string()));
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    }
 
-type RequestCtx = RequestContext<usize>;
+    // Once it's quiet for long enough, the server closes it.
+    let start = std::time::Instant::now();
+    websocket_wait_closed(&mut ws).await;
+    assert!(start.elapsed() >= std::time::Duration::from_millis(100));
 
-#[endpoint {
-    method = GET,
-    path = "/testing/demo1",
-}]
-async fn demo_handler_args_1(
-    _rqctx: RequestCtx,
-) -> Result<Response<Body>, HttpError> {
-    http_echo(&"demo_handler_args_1")
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_keepalive() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let path = format!(
+        "ws://{}/testing/websocket_keepalive",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) = tokio_tungstenite::connect_async(path).await.unwrap();
+
+    // The server pings the client, and the connection stays open for as long
+    // as the client answers (which it does as it reads).
+    let mut npings = 0;
+    let start = std::time::Instant::now();
+    while start.elapsed() < std::time::Duration::from_millis(500) {
+        ws.send(Message::Text("hello".to_string())).await.unwrap();
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Ping(_) => npings += 1,
+                msg => {
+                    assert_eq!(msg, Message::Text("hello".to_string()));
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(npings > 0);
+
+    // Once the client stops answering pings, the server closes the connection.
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    websocket_wait_closed(&mut ws).await;
+
+    // Connections without keepalive are unaffected.
+    let path = format!(
+        "ws://{}/testing/websocket_echo",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) = tokio_tungstenite::connect_async(path).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    ws.send(Message::Text("hello".to_string())).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello".to_string()));
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_shutdown() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let address = testctx.client_testctx.bind_address;
+    let (mut echo, _resp) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/testing/websocket_echo",
+        address
+    ))
+    .await
+    .unwrap();
+    let (mut stubborn, _resp) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/testing/websocket_stubborn",
+        address
+    ))
+    .await
+    .unwrap();
+    echo.send(Message::Text("hello".to_string())).await.unwrap();
+    let msg = echo.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello".to_string()));
+
+    let start = std::time::Instant::now();
+    let teardown = tokio::spawn(testctx.teardown());
+
+    // A handler that watches for shutdown closes its connection cleanly.
+    let msg = echo.next().await.unwrap().unwrap();
+    assert!(matches!(msg, Message::Close(_)), "unexpected message: {:?}", msg);
+
+    // One that doesn't is cancelled at the end of its grace period, and
+    // shutdown waits for that.
+    websocket_wait_closed(&mut stubborn).await;
+    teardown.await.unwrap();
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_demo_websocket_registry() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let registry = Arc::new(WebsocketRegistry::new());
+    testctx.server.set_websocket_registry(Some(Arc::clone(&registry)));
+    let address = testctx.client_testctx.bind_address;
+    let connect = |user: &'static str| {
+        tokio_tungstenite::connect_async(format!(
+            "ws://{}/testing/websocket_chat/{}",
+            address, user
+        ))
+    };
+    let (mut alice1, _resp) = connect("alice").await.unwrap();
+    let (mut alice2, _resp) = connect("alice").await.unwrap();
+    let (mut bob, _resp) = connect("bob").await.unwrap();
+
+    // The handlers register their connections once they're running.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while registry.connection_count() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connections were not registered");
+    assert_eq!(registry.keys(), vec!["alice", "bob"]);
+
+    let text = |s: &str| WebsocketMessage::Text(s.to_string());
+    assert_eq!(registry.send("alice", text("hi alice")), 2);
+    assert_eq!(registry.broadcast(text("hi all")), 3);
+    for ws in [&mut alice1, &mut alice2] {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("hi alice".to_string()));
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("hi all".to_string()));
+    }
+    let msg = bob.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hi all".to_string()));
+
+    // Disconnecting a key closes only its connections.
+    assert_eq!(registry.disconnect("bob"), 1);
+    let msg = bob.next().await.unwrap().unwrap();
+    assert!(matches!(msg, Message::Close(_)), "unexpected message: {:?}", msg);
+    assert_eq!(registry.keys(), vec!["alice"]);
+    assert_eq!(registry.send("bob", text("gone")), 0);
+
+    // Connections leave the registry when their handlers finish.
+    alice1.close(None).await.unwrap();
+    websocket_wait_closed(&mut alice1).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while registry.connection_count() > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connection was not unregistered");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_extensions() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let url = format!(
+        "ws://{}/testing/websocket",
+        testctx.client_testctx.bind_address
+    );
+
+    // Dropshot doesn't implement any extensions, so it declines offers of
+    // them (like compression), and messages are exchanged as is.
+    let mut request = url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert(
+        "sec-websocket-extensions",
+        "permessage-deflate; client_max_window_bits".parse().unwrap(),
+    );
+    let (mut ws, resp) =
+        tokio_tungstenite::connect_async(request).await.unwrap();
+    assert!(!resp.headers().contains_key("sec-websocket-extensions"));
+    ws.send(Message::Text("hello server".to_string())).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello client".to_string()));
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_subprotocol() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let url = format!(
+        "ws://{}/testing/websocket_subprotocol",
+        testctx.client_testctx.bind_address
+    );
+    let connect = |offer: Option<&'static str>| {
+        let mut request = url.as_str().into_client_request().unwrap();
+        if let Some(offer) = offer {
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", offer.parse().unwrap());
+        }
+        tokio_tungstenite::connect_async(request)
+    };
+
+    // The handler's preference wins over the client's.
+    let (mut ws, resp) = connect(Some("v1.chat, v2.chat")).await.unwrap();
+    assert_eq!(resp.headers()["sec-websocket-protocol"], "v2.chat");
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("v2.chat".to_string()));
+    websocket_wait_closed(&mut ws).await;
+
+    let (mut ws, resp) = connect(Some("v1.chat")).await.unwrap();
+    assert_eq!(resp.headers()["sec-websocket-protocol"], "v1.chat");
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("v1.chat".to_string()));
+    websocket_wait_closed(&mut ws).await;
+
+    // If there's nothing in common, no subprotocol is chosen.
+    for offer in [Some("v3.chat"), None] {
+        let (mut ws, resp) = connect(offer).await.unwrap();
+        assert!(!resp.headers().contains_key("sec-websocket-protocol"));
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("none".to_string()));
+        websocket_wait_closed(&mut ws).await;
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_limits() {
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let url = format!(
+        "ws://{}/testing/websocket_limited",
+        testctx.client_testctx.bind_address
+    );
+    let expect_close = |msg: Message, expected: CloseCode| match msg {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, expected),
+        msg => panic!("unexpected message: {:?}", msg),
+    };
+
+    // Messages within the limits are fine, but a message that's too big
+    // closes the connection.
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+    ws.send(Message::Text("hello".to_string())).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello".to_string()));
+    ws.send(Message::Text("x".repeat(17))).await.unwrap();
+    expect_close(ws.next().await.unwrap().unwrap(), CloseCode::Size);
+    websocket_wait_closed(&mut ws).await;
+
+    // So does sending messages too quickly.
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+    for n in 0..3 {
+        ws.send(Message::Text(n.to_string())).await.unwrap();
+    }
+    for n in 0..2 {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text(n.to_string()));
+    }
+    expect_close(ws.next().await.unwrap().unwrap(), CloseCode::Policy);
+    websocket_wait_closed(&mut ws).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_send_queue() {
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+
+    // Only the newest queued messages are sent, and the handler's own
+    // messages follow them.
+    let url = format!(
+        "ws://{}/testing/websocket_queue_drop",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+    for n in 6..10 {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text(n.to_string()));
+    }
+    ws.send(Message::Text("hello".to_string())).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text("hello".to_string()));
+    ws.close(None).await.unwrap();
+    websocket_wait_closed(&mut ws).await;
+
+    // Overflowing the queue closes the connection instead.
+    let url = format!(
+        "ws://{}/testing/websocket_queue_close",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+    match ws.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Policy)
+        }
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+    websocket_wait_closed(&mut ws).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_event_stream() {
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+
+    // Without an upgrade, the events are sent as server-sent events.
+    let mut response = testctx
+        .client_testctx
+        .with_allowed_header(http::header::CACHE_CONTROL.as_str())
+        .make_request_no_body(
+            Method::GET,
+            "/testing/event_stream",
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    assert_eq!(
+        read_string(&mut response).await,
+        "data: {\"n\":0}\n\ndata: {\"n\":1}\n\ndata: {\"n\":2}\n\n"
+    );
+
+    // With one, they're sent as websocket messages, and the server closes the
+    // connection once they run out.
+    let url = format!(
+        "ws://{}/testing/event_stream",
+        testctx.client_testctx.bind_address
+    );
+    let (mut ws, _resp) =
+        tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+    for n in 0..3 {
+        let msg = ws.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text(format!("{{\"n\":{}}}", n)));
+    }
+    match ws.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Normal)
+        }
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+    websocket_wait_closed(&mut ws).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_demo_websocket_context() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let url = format!(
+        "ws://{}/testing/websocket_context",
+        testctx.client_testctx.bind_address
+    );
+    let mut request = url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert("x-user", "alice".parse().unwrap());
+    let (mut ws, resp) =
+        tokio_tungstenite::connect_async(request).await.unwrap();
+
+    // The handler sees the principal that the endpoint authenticated, and
+    // the id of the request that was upgraded.
+    let request_id = resp.headers()["x-request-id"].to_str().unwrap();
+    let msg = ws.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text(format!("alice {} GET", request_id)));
+    websocket_wait_closed(&mut ws).await;
+
+    // Unauthenticated requests aren't upgraded.
+    let error = tokio_tungstenite::connect_async(url).await.unwrap_err();
+    match error {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            assert_eq!(response.status(), 401);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_compat() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let mut response = testctx
+        .client_testctx
+        .make_request(
+            Method::GET,
+            "/testing/request_compat",
+            None as Option<()>,
+            StatusCode::OK,
+        )
+        .await
+        .expect("expected success");
+    let json: String = read_json(&mut response).await;
+    assert_eq!(json, "dummy");
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_remote_addr() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let laddr = testctx.server.local_addr();
+    let mut response = testctx
+        .client_testctx
+        .make_request(
+            Method::GET,
+            "/testing/request_addresses",
+            None as Option<()>,
+            StatusCode::OK,
+        )
+        .await
+        .expect("expected success");
+    let json: Vec<String> = read_json(&mut response).await;
+    assert_eq!(json.len(), 4);
+    // Confirm the local address and port seen from inside the server matches
+    // the one that we got from the test context:
+    assert_eq!(json[0], laddr.ip().to_string());
+    assert_eq!(json[1], laddr.port().to_string());
+    // There does not appear to be an easy way to determine which port was
+    // used for the outbound request we make here, but we at least know that
+    // it must not be the same as the server listen port:
+    assert_eq!(json[2], laddr.ip().to_string());
+    assert_ne!(json[3], laddr.port().to_string());
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_client_ip() {
+    // By default, forwarding headers are ignored.
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let request = hyper::Request::builder()
+        .method(Method::GET)
+        .uri(testctx.client_testctx.url("/testing/request_client_ip"))
+        .header("x-forwarded-for", "192.0.2.1")
+        .body(Body::empty())
+        .expect("attempted to construct invalid request");
+    let mut response = testctx
+        .client_testctx
+        .make_request_with_request(request, StatusCode::OK)
+        .await
+        .expect("expected success");
+    let json: String = read_json(&mut response).await;
+    assert_eq!(json, "127.0.0.1");
+    testctx.teardown().await;
+
+    // Once the test client is a trusted proxy, the forwarded address is used.
+    let api = demo_api();
+    let config = ConfigDropshot {
+        trusted_proxies: vec!["127.0.0.0/8".parse().unwrap()],
+        ..Default::default()
+    };
+    let testctx = TestContext::new(api, 0_usize, &config);
+    let request = hyper::Request::builder()
+        .method(Method::GET)
+        .uri(testctx.client_testctx.url("/testing/request_client_ip"))
+        .header("x-forwarded-for", "192.0.2.1, 127.0.0.2")
+        .body(Body::empty())
+        .expect("attempted to construct invalid request");
+    let mut response = testctx
+        .client_testctx
+        .make_request_with_request(request, StatusCode::OK)
+        .await
+        .expect("expected success");
+    let json: String = read_json(&mut response).await;
+    assert_eq!(json, "192.0.2.1");
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_echo() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let laddr = testctx.server.local_addr();
+    let request = hyper::Request::builder()
+        .method(Method::GET)
+        .uri(testctx.client_testctx.url("/testing/request_echo?a=b"))
+        .header("x-custom", "one")
+        .header("x-custom", "two")
+        .header("authorization", "Bearer secret")
+        .body(Body::empty())
+        .expect("attempted to construct invalid request");
+    let mut response = testctx
+        .client_testctx
+        .make_request_with_request(request, StatusCode::OK)
+        .await
+        .expect("expected success");
+    let request_id = response
+        .headers()
+        .get(dropshot::HEADER_REQUEST_ID)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let echo: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(echo["request_id"], request_id);
+    assert_eq!(echo["method"], "GET");
+    assert_eq!(echo["uri"], "/testing/request_echo?a=b");
+    assert_eq!(echo["version"], "HTTP/1.1");
+    assert_eq!(echo["headers"]["x-custom"], serde_json::json!(["one", "two"]));
+    assert_eq!(
+        echo["headers"]["authorization"],
+        serde_json::json!(["<redacted>"])
+    );
+    assert_eq!(echo["client_ip"], "127.0.0.1");
+    assert_eq!(echo["local_addr"], laddr.to_string());
+    assert_eq!(echo["tls"], false);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_builder() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    // Headers may be repeated, and query parameters are added to any already
+    // in the path.
+    let mut response = client
+        .request(Method::GET, "/testing/request_echo?a=b")
+        .header("x-custom", "one")
+        .header("x-custom", "two")
+        .query(&[("c", "d e")])
+        .send()
+        .await
+        .expect("expected success");
+    let echo: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(echo["uri"], "/testing/request_echo?a=b&c=d+e");
+    assert_eq!(echo["headers"]["x-custom"], serde_json::json!(["one", "two"]));
+
+    // Query parameters and a JSON body.
+    let json_input = DemoJsonBody { test1: "bart".to_string(), test2: Some(0) };
+    let mut response = client
+        .request(Method::GET, "/testing/demo3")
+        .query(&DemoQueryArgs { test1: "martin".to_string(), test2: Some(2) })
+        .json(&json_input)
+        .send()
+        .await
+        .expect("expected success");
+    let json: DemoJsonAndQuery = read_json(&mut response).await;
+    assert_eq!(json.json.test1, "bart");
+    assert_eq!(json.json.test2, Some(0));
+    assert_eq!(json.query.test1, "martin");
+    assert_eq!(json.query.test2, Some(2));
+
+    // An expected error status.
+    let error = client
+        .request(Method::GET, "/testing/demo3")
+        .query(&[("test2", "2")])
+        .json(&json_input)
+        .expect_status(StatusCode::BAD_REQUEST)
+        .send()
+        .await
+        .expect_err("expected error");
+    assert_eq!(
+        error.message,
+        "unable to parse query string: missing field `test1`"
+    );
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_concurrent_requests() {
+    let api = demo_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    for simultaneous in [false, true] {
+        let results = client
+            .make_concurrent_requests(8, simultaneous, |i| {
+                let path =
+                    if i % 2 == 0 { "/testing/demo1" } else { "/nonexistent" };
+                client.request(Method::GET, path).build()
+            })
+            .await;
+        assert_eq!(results.responses.len(), 8);
+        assert_eq!(results.count(StatusCode::OK), 4);
+        assert_eq!(
+            results.status_counts().into_iter().collect::<Vec<_>>(),
+            vec![(StatusCode::OK, 4), (StatusCode::NOT_FOUND, 4)]
+        );
+        for (i, response) in results.responses.iter().enumerate() {
+            assert_eq!(response.index, i);
+            if i % 2 == 0 {
+                assert_eq!(response.status, StatusCode::OK);
+                assert_eq!(response.body, "\"demo_handler_args_1\"");
+            } else {
+                assert_eq!(response.status, StatusCode::NOT_FOUND);
+            }
+        }
+        assert!(results.latency_percentile(50.0) <= results.latency_max());
+        assert!(results.latency_max() > std::time::Duration::ZERO);
+    }
+
+    testctx.teardown().await;
+}
+
+// Demo handler functions
+
+type RequestCtx = RequestContext<usize>;
+
+#[endpoint {
+    method = GET,
+    path = "/testing/demo1",
+}]
+async fn demo_handler_args_1(
+    _rqctx: RequestCtx,
+) -> Result<Response<Body>, HttpError> {
+    http_echo(&"demo_handler_args_1")
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -1239,6 +2296,68 @@ async fn demo_handler_streaming_body(
     Ok(HttpResponseOk(DemoStreaming { nbytes }))
 }
 
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct DemoSpooled {
+    pub nbytes: u64,
+    pub spooled: bool,
+    pub checksum: u64,
+}
+#[endpoint {
+    method = PUT,
+    path = "/testing/spooled_body",
+    request_body_max_bytes = 4096,
+}]
+async fn demo_handler_spooled_body(
+    _rqctx: RequestContext<usize>,
+    body: SpooledBody,
+) -> Result<HttpResponseOk<DemoSpooled>, HttpError> {
+    let nbytes = body.len();
+    let spooled = body.is_spooled();
+    let mut content = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut body.into_reader(), &mut content)
+        .await
+        .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+    assert_eq!(content.len() as u64, nbytes);
+    let checksum = content.iter().map(|b| u64::from(*b)).sum();
+
+    Ok(HttpResponseOk(DemoSpooled { nbytes, spooled, checksum }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoDeadlineArgs {
+    pub sleep_ms: u64,
+}
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct DemoDeadline {
+    pub remaining_ms: Option<u64>,
+}
+#[endpoint {
+    method = GET,
+    path = "/testing/deadline",
+}]
+async fn demo_handler_deadline(
+    _rqctx: RequestContext<usize>,
+    query: Query<DemoDeadlineArgs>,
+    deadline: Deadline,
+) -> Result<HttpResponseOk<DemoDeadline>, HttpError> {
+    let remaining_ms = deadline.remaining().map(|d| d.as_millis() as u64);
+    let sleep_ms = query.into_inner().sleep_ms;
+    tokio::time::sleep(std::time::Duration::from_millis(sleep_ms)).await;
+    Ok(HttpResponseOk(DemoDeadline { remaining_ms }))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/testing/status_override",
+    status = 202,
+    reason = "Queued",
+}]
+async fn demo_handler_status_override(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("queued")))
+}
+
 #[derive(Deserialize, Serialize, JsonSchema)]
 pub struct DemoRaw {
     pub nbytes: usize,
@@ -1329,6 +2448,32 @@ async fn demo_handler_headers(
     Ok(response)
 }
 
+#[derive(JsonSchema, Serialize)]
+struct DemoTypedHeaders {
+    #[serde(rename = "x-dropshot-test-header-1")]
+    remaining: u32,
+    #[serde(rename = "x-dropshot-test-header-2")]
+    enabled: bool,
+    #[serde(rename = "ETag", skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/testing/headers_typed",
+}]
+async fn demo_handler_headers_typed(
+    _rqctx: RequestCtx,
+) -> Result<
+    HttpResponseHeaders<HttpResponseUpdatedNoContent, DemoTypedHeaders>,
+    HttpError,
+> {
+    Ok(HttpResponseHeaders::new(
+        HttpResponseUpdatedNoContent(),
+        DemoTypedHeaders { remaining: 41, enabled: true, etag: None },
+    ))
+}
+
 #[endpoint {
     method = GET,
     path = "/testing/302_bogus",
@@ -1369,6 +2514,45 @@ async fn demo_handler_307_temporary_redirect(
     Ok(http_response_temporary_redirect(String::from("/path3")).unwrap())
 }
 
+#[endpoint {
+    method = GET,
+    path = "/testing/308_permanent_redirect",
+}]
+async fn demo_handler_308_permanent_redirect(
+    _rqctx: RequestCtx,
+) -> Result<HttpResponsePermanentRedirect, HttpError> {
+    Ok(http_response_permanent_redirect(String::from("/path4")).unwrap())
+}
+
+#[endpoint {
+    method = GET,
+    path = "/testing/207_multi_status",
+}]
+async fn demo_handler_207_multi_status(
+    _rqctx: RequestCtx,
+) -> Result<HttpResponseWithStatus<Vec<String>, 207>, HttpError> {
+    Ok(HttpResponseWithStatus(vec![String::from("one"), String::from("two")]))
+}
+
+#[derive(Serialize, JsonSchema)]
+struct DemoJob {
+    job: u32,
+}
+
+#[endpoint {
+    method = POST,
+    path = "/testing/202_accepted",
+}]
+async fn demo_handler_202_accepted(
+    _rqctx: RequestCtx,
+) -> Result<HttpResponseAcceptedLocation<DemoJob>, HttpError> {
+    http_response_accepted(
+        DemoJob { job: 12 },
+        String::from("/jobs/12"),
+        Some(Duration::from_millis(1500)),
+    )
+}
+
 #[channel {
     protocol = WEBSOCKETS,
     path = "/testing/websocket"
@@ -1388,6 +2572,257 @@ async fn demo_handler_websocket(
     Ok(())
 }
 
+/// Echoes text messages until the client closes the connection or the server
+/// shuts down
+async fn websocket_echo(
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let shutdown = upgraded.shutdown_signal();
+    let mut ws_stream = WebSocketStream::from_raw_socket(
+        upgraded.into_inner(),
+        Role::Server,
+        None,
+    )
+    .await;
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => match msg {
+                Some(msg) => {
+                    if let Message::Text(text) = msg? {
+                        ws_stream.send(Message::Text(text)).await?;
+                    }
+                }
+                None => return Ok(()),
+            },
+            () = shutdown.wait() => {
+                ws_stream.close(None).await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_echo"
+}]
+async fn demo_handler_websocket_echo(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    websocket_echo(upgraded).await
+}
+
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_keepalive",
+    ping_interval_ms = 50,
+    pong_timeout_ms = 200,
+}]
+async fn demo_handler_websocket_keepalive(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    websocket_echo(upgraded).await
+}
+
+/// Ignores shutdown, holding the connection open until it's cancelled
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_stubborn",
+    shutdown_grace_period_ms = 200,
+}]
+async fn demo_handler_websocket_stubborn(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let _ws = upgraded.into_inner();
+    std::future::pending().await
+}
+
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_limited",
+    max_message_bytes = 16,
+    max_messages_per_second = 2,
+}]
+async fn demo_handler_websocket_limited(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    websocket_echo(upgraded).await
+}
+
+/// Queues more messages than the send queue holds, then echoes
+async fn websocket_queue_then_echo(
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let queue = upgraded.send_queue();
+    for n in 0..10 {
+        let _ = queue.try_send(WebsocketMessage::Text(n.to_string()));
+    }
+    websocket_echo(upgraded).await
+}
+
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_queue_drop",
+    send_queue_len = 4,
+    send_queue_policy = DropOldest,
+}]
+async fn demo_handler_websocket_queue_drop(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    websocket_queue_then_echo(upgraded).await
+}
+
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_queue_close",
+    send_queue_len = 4,
+    send_queue_policy = Close,
+}]
+async fn demo_handler_websocket_queue_close(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    websocket_queue_then_echo(upgraded).await
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct DemoEvent {
+    n: u32,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/testing/event_stream",
+}]
+async fn demo_handler_event_stream(
+    _rqctx: RequestCtx,
+    events: EventStreamUpgrade,
+) -> Result<EventStream<DemoEvent>, HttpError> {
+    events.stream(futures::stream::iter((0..3).map(|n| DemoEvent { n })))
+}
+
+/// Identity of the client, as authenticated by `demo_handler_websocket_context`
+struct Principal(String);
+
+/// Authenticates the client before upgrading, then reports what the handler
+/// knows about the request
+#[endpoint {
+    method = GET,
+    path = "/testing/websocket_context",
+}]
+async fn demo_handler_websocket_context(
+    rqctx: RequestCtx,
+    websock: WebsocketUpgrade,
+) -> WebsocketEndpointResult {
+    let user = rqctx
+        .request
+        .headers()
+        .get("x-user")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| {
+            HttpError::for_client_error(
+                None,
+                http::StatusCode::UNAUTHORIZED,
+                String::from("who are you?"),
+            )
+        })?;
+    websock.with_extension(Principal(user.to_string())).handle(
+        |upgraded| async move {
+            let context = upgraded.context();
+            let Principal(user) = context.extension::<Principal>().unwrap();
+            let report = format!(
+                "{} {} {}",
+                user,
+                context.request_id,
+                context.request.method()
+            );
+            let mut ws_stream = WebSocketStream::from_raw_socket(
+                upgraded.into_inner(),
+                Role::Server,
+                None,
+            )
+            .await;
+            ws_stream.send(Message::Text(report)).await?;
+            ws_stream.close(None).await?;
+            Ok(())
+        },
+    )
+}
+
+/// Reports the subprotocol chosen for the connection
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_subprotocol",
+    subprotocols = ["v2.chat", "v1.chat"],
+}]
+async fn demo_handler_websocket_subprotocol(
+    _rqctx: RequestCtx,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let report = upgraded.subprotocol().unwrap_or("none").to_string();
+    let mut ws_stream = WebSocketStream::from_raw_socket(
+        upgraded.into_inner(),
+        Role::Server,
+        None,
+    )
+    .await;
+    ws_stream.send(Message::Text(report)).await?;
+    ws_stream.close(None).await?;
+    Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ChatPath {
+    user: String,
+}
+
+/// Registers the connection under the user's name and forwards the messages
+/// sent to it through the server's websocket registry
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/testing/websocket_chat/{user}",
+}]
+async fn demo_handler_websocket_chat(
+    _rqctx: RequestCtx,
+    path: Path<ChatPath>,
+    upgraded: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let mut registration = upgraded
+        .register(path.into_inner().user)
+        .ok_or("server has no websocket registry")?;
+    let mut ws_stream = WebSocketStream::from_raw_socket(
+        upgraded.into_inner(),
+        Role::Server,
+        None,
+    )
+    .await;
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => match msg {
+                Some(msg) => { msg?; }
+                None => return Ok(()),
+            },
+            msg = registration.recv() => match msg {
+                Some(WebsocketMessage::Text(text)) => {
+                    ws_stream.send(Message::Text(text)).await?;
+                }
+                Some(WebsocketMessage::Binary(data)) => {
+                    ws_stream.send(Message::Binary(data)).await?;
+                }
+                None => {
+                    ws_stream.close(None).await?;
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
 #[endpoint {
     method = GET,
     path = "/testing/request_compat",
@@ -1421,6 +2856,16 @@ async fn demo_handler_request_addresses(
     ])
 }
 
+#[endpoint {
+    method = GET,
+    path = "/testing/request_client_ip",
+}]
+async fn demo_handler_request_client_ip(
+    rqctx: RequestCtx,
+) -> Result<Response<Body>, HttpError> {
+    http_echo(&rqctx.request.client_ip().to_string())
+}
+
 fn http_echo<T: Serialize>(t: &T) -> Result<Response<Body>, HttpError> {
     Ok(Response::builder()
         .header(http::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
@@ -0,0 +1,161 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for `ApiDescription::map_error`.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HandlerTaskMode;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::RequestInfo;
+use dropshot::TypedBody;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+pub mod common;
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new().map_error(envelope);
+    api.register(widget_post).unwrap();
+    api.register(widget_delete).unwrap();
+    api
+}
+
+/// Renders every error as `{ "error": { ... } }`.
+fn envelope(error: HttpError, request: &RequestInfo) -> Response<Body> {
+    let body = serde_json::json!({
+        "error": {
+            "status": error.status_code.as_u16(),
+            "message": error.external_message,
+            "method": request.method().as_str(),
+            "path": request.uri().path(),
+        },
+    });
+    Response::builder()
+        .status(error.status_code)
+        .header(http::header::CONTENT_TYPE, dropshot::CONTENT_TYPE_JSON)
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct Widget {
+    #[allow(dead_code)]
+    name: String,
+}
+
+#[endpoint {
+    method = POST,
+    path = "/widgets",
+}]
+async fn widget_post(
+    _rqctx: RequestContext<usize>,
+    _body: TypedBody<Widget>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Err(HttpError::for_unavail(None, String::from("out of widgets")))
+}
+
+#[endpoint {
+    method = DELETE,
+    path = "/widgets",
+}]
+async fn widget_delete(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    panic!("widgets are forever");
+}
+
+#[tokio::test]
+async fn test_error_mapper() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+
+    for (method, path, body, status, message) in [
+        // from routing
+        (Method::GET, "/gadgets", "", StatusCode::NOT_FOUND, "Not Found"),
+        (
+            Method::GET,
+            "/widgets",
+            "",
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method Not Allowed",
+        ),
+        // from an extractor
+        (
+            Method::POST,
+            "/widgets",
+            "{",
+            StatusCode::BAD_REQUEST,
+            "unable to parse JSON body: EOF while parsing an object at line \
+             1 column 1",
+        ),
+        // from the handler
+        (
+            Method::POST,
+            "/widgets",
+            "{\"name\": \"gizmo\"}",
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Service Unavailable",
+        ),
+        // from a panic in the handler
+        (
+            Method::DELETE,
+            "/widgets",
+            "",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+        ),
+    ] {
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(client.url(path))
+            .header(http::header::CONTENT_TYPE, dropshot::CONTENT_TYPE_JSON)
+            .body(Body::from(body))
+            .unwrap();
+        let mut response = client.client.request(request).await.unwrap();
+        assert_eq!(response.status(), status);
+        assert!(response.headers().contains_key(dropshot::HEADER_REQUEST_ID));
+        let body: serde_json::Value = read_json(&mut response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "status": status.as_u16(),
+                    "message": message,
+                    "method": method.as_str(),
+                    "path": path,
+                },
+            })
+        );
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_error_mapper_panic_cancel_on_disconnect() {
+    // Panics are reported the same way when the handler runs in the request's
+    // own task.
+    let testctx = common::test_setup_with_context(
+        api(),
+        0_usize,
+        HandlerTaskMode::CancelOnDisconnect,
+    );
+    let client = &testctx.client_testctx;
+
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri(client.url("/widgets"))
+        .body(Body::empty())
+        .unwrap();
+    let mut response = client.client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(body["error"]["message"], "Internal Server Error");
+
+    testctx.teardown().await;
+}
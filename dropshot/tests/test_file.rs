@@ -0,0 +1,158 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for `HttpResponseFile`.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_string;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseFile;
+use dropshot::Path;
+use dropshot::RequestContext;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+pub mod common;
+
+const CONTENT: &str = "the quick brown fox jumps over the lazy dog";
+
+struct FileContext {
+    dir: tempfile::TempDir,
+}
+
+fn api() -> ApiDescription<FileContext> {
+    let mut api = ApiDescription::new();
+    api.register(get_file).unwrap();
+    api.register(view_file).unwrap();
+    api
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct FilePath {
+    name: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/files/{name}",
+}]
+async fn get_file(
+    rqctx: RequestContext<FileContext>,
+    path: Path<FilePath>,
+) -> Result<HttpResponseFile, HttpError> {
+    let path = rqctx.context().dir.path().join(path.into_inner().name);
+    HttpResponseFile::from_path(&rqctx.request, path).await
+}
+
+#[endpoint {
+    method = GET,
+    path = "/view/{name}",
+}]
+async fn view_file(
+    rqctx: RequestContext<FileContext>,
+    path: Path<FilePath>,
+) -> Result<HttpResponseFile, HttpError> {
+    let path = rqctx.context().dir.path().join(path.into_inner().name);
+    HttpResponseFile::from_path(&rqctx.request, path)
+        .await?
+        .inline()
+        .content_type("text/x-fox")
+}
+
+fn setup() -> TestContext<FileContext> {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("fox.txt"), CONTENT).unwrap();
+    common::test_setup_with_context(
+        api(),
+        FileContext { dir },
+        dropshot::HandlerTaskMode::Detached,
+    )
+}
+
+async fn get(
+    testctx: &TestContext<FileContext>,
+    path: &str,
+    headers: &[(header::HeaderName, &str)],
+) -> Response<Body> {
+    let client = &testctx.client_testctx;
+    let mut builder =
+        Request::builder().method(Method::GET).uri(client.url(path));
+    for (name, value) in headers {
+        builder = builder.header(name, *value);
+    }
+    client.client.request(builder.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_file_download() {
+    let testctx = setup();
+
+    let mut response = get(&testctx, "/files/fox.txt", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers().clone();
+    assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+    assert_eq!(headers[header::CONTENT_LENGTH], "43");
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"fox.txt\"; filename*=UTF-8''fox.txt"
+    );
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(read_string(&mut response).await, CONTENT);
+    let etag = headers[header::ETAG].to_str().unwrap();
+    let last_modified = headers[header::LAST_MODIFIED].to_str().unwrap();
+
+    // The validators support conditional requests.
+    let mut response =
+        get(&testctx, "/files/fox.txt", &[(header::IF_NONE_MATCH, etag)]).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag);
+    assert_eq!(read_string(&mut response).await, "");
+    let response = get(
+        &testctx,
+        "/files/fox.txt",
+        &[(header::IF_MODIFIED_SINCE, last_modified)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let response =
+        get(&testctx, "/files/fox.txt", &[(header::IF_MATCH, "\"other\"")])
+            .await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Ranges work, including when resuming with If-Range.
+    let mut response = get(
+        &testctx,
+        "/files/fox.txt",
+        &[(header::RANGE, "bytes=-3"), (header::IF_RANGE, etag)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 40-42/43");
+    assert_eq!(read_string(&mut response).await, "dog");
+    let mut response = get(
+        &testctx,
+        "/files/fox.txt",
+        &[(header::RANGE, "bytes=-3"), (header::IF_RANGE, "\"stale\"")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_string(&mut response).await, CONTENT);
+
+    // The disposition and type can be overridden.
+    let response = get(&testctx, "/view/fox.txt", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/x-fox");
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "inline; filename=\"fox.txt\"; filename*=UTF-8''fox.txt"
+    );
+
+    let response = get(&testctx, "/files/wolf.txt", &[]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    testctx.teardown().await;
+}
@@ -0,0 +1,431 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for idempotent replay of requests with an `Idempotency-Key`.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::test_util::ClientTestContext;
+use dropshot::test_util::TestClock;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseCreated;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServerStarter;
+use dropshot::IdempotencyKey;
+use dropshot::IdempotencyMiddleware;
+use dropshot::InMemoryIdempotencyStore;
+use dropshot::RequestContext;
+use dropshot::StreamingBody;
+use dropshot::UntypedBody;
+use dropshot::HEADER_IDEMPOTENCY_KEY;
+use dropshot::HEADER_IDEMPOTENT_REPLAYED;
+use dropshot::HEADER_REQUEST_ID;
+use http::{Method, StatusCode};
+use hyper::{Body, Request};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod common;
+
+fn api() -> ApiDescription<AtomicUsize> {
+    let mut api = ApiDescription::new();
+    api.register(create_charge).unwrap();
+    api.register(fail_charge).unwrap();
+    api.register(echo_key).unwrap();
+    api.register(upload).unwrap();
+    api.register(upload_stream).unwrap();
+    api
+}
+
+#[endpoint {
+    method = POST,
+    path = "/charges",
+}]
+async fn create_charge(
+    rqctx: RequestContext<AtomicUsize>,
+) -> Result<HttpResponseCreated<usize>, HttpError> {
+    Ok(HttpResponseCreated(rqctx.context().fetch_add(1, Ordering::SeqCst)))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/charges/fail",
+}]
+async fn fail_charge(
+    rqctx: RequestContext<AtomicUsize>,
+) -> Result<HttpResponseCreated<usize>, HttpError> {
+    rqctx.context().fetch_add(1, Ordering::SeqCst);
+    Err(HttpError::for_unavail(None, String::from("try again later")))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/key",
+}]
+async fn echo_key(
+    _rqctx: RequestContext<AtomicUsize>,
+    key: IdempotencyKey,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(key.into_inner()))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/uploads",
+    request_body_max_bytes = 4096,
+}]
+async fn upload(
+    rqctx: RequestContext<AtomicUsize>,
+    body: UntypedBody,
+) -> Result<HttpResponseCreated<usize>, HttpError> {
+    rqctx.context().fetch_add(1, Ordering::SeqCst);
+    Ok(HttpResponseCreated(body.as_bytes().len()))
+}
+
+#[endpoint {
+    method = POST,
+    path = "/uploads/stream",
+}]
+async fn upload_stream(
+    rqctx: RequestContext<AtomicUsize>,
+    _body: StreamingBody,
+) -> Result<HttpResponseCreated<usize>, HttpError> {
+    Ok(HttpResponseCreated(rqctx.context().fetch_add(1, Ordering::SeqCst)))
+}
+
+fn request(method: Method, uri: http::Uri, key: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(HEADER_IDEMPOTENCY_KEY, key)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn request_as(
+    user: &str,
+    uri: http::Uri,
+    key: &str,
+    body: &'static str,
+) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(HEADER_IDEMPOTENCY_KEY, key)
+        .header("x-user", user)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_idempotent_replay() {
+    let config = ConfigDropshot::default();
+    let middleware = IdempotencyMiddleware::new(InMemoryIdempotencyStore::new(
+        Duration::from_secs(60),
+    ));
+    let server = HttpServerStarter::new(
+        &config,
+        api(),
+        Some(Arc::new(middleware)),
+        AtomicUsize::new(0),
+    )
+    .unwrap()
+    .start();
+    let client = ClientTestContext::new(server.local_addr())
+        .with_allowed_header(HEADER_IDEMPOTENT_REPLAYED);
+
+    // The first request with a key runs the handler.
+    let mut response = client
+        .make_request_with_request(
+            request(Method::POST, client.url("/charges"), "key-1"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert!(response.headers().get(HEADER_IDEMPOTENT_REPLAYED).is_none());
+    let first_request_id =
+        response.headers().get(HEADER_REQUEST_ID).unwrap().clone();
+    assert_eq!(read_json::<usize>(&mut response).await, 0);
+
+    // A retry gets the same response without running the handler again.
+    let mut response = client
+        .make_request_with_request(
+            request(Method::POST, client.url("/charges"), "key-1"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(HEADER_IDEMPOTENT_REPLAYED).unwrap(),
+        "true"
+    );
+    assert_ne!(
+        response.headers().get(HEADER_REQUEST_ID).unwrap(),
+        first_request_id
+    );
+    assert_eq!(read_json::<usize>(&mut response).await, 0);
+    assert_eq!(server.app_private().load(Ordering::SeqCst), 1);
+
+    // A different key is a different operation.
+    let mut response = client
+        .make_request_with_request(
+            request(Method::POST, client.url("/charges"), "key-2"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_json::<usize>(&mut response).await, 1);
+
+    // Requests without a key are unaffected.
+    client
+        .make_request_no_body(Method::POST, "/charges", StatusCode::CREATED)
+        .await
+        .unwrap();
+    assert_eq!(server.app_private().load(Ordering::SeqCst), 3);
+
+    // Reusing a key for a different request is an error.
+    let error = client
+        .make_request_with_request(
+            request(Method::POST, client.url("/charges/fail"), "key-1"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "idempotency key was already used for a different request"
+    );
+
+    // Server errors aren't recorded, so the client can retry.
+    for _ in 0..2 {
+        client
+            .make_request_with_request(
+                request(Method::POST, client.url("/charges/fail"), "key-3"),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .await
+            .unwrap_err();
+    }
+    assert_eq!(server.app_private().load(Ordering::SeqCst), 5);
+
+    // Malformed keys are rejected.
+    let error = client
+        .make_request_with_request(
+            request(Method::POST, client.url("/charges"), "bad key"),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "invalid idempotency-key header: expected 1 to 255 visible ASCII \
+         characters"
+    );
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotency_expiry() {
+    let clock = TestClock::new();
+    let middleware = IdempotencyMiddleware::new(InMemoryIdempotencyStore::new(
+        Duration::from_secs(60),
+    ));
+    let server = HttpServerStarter::new(
+        &ConfigDropshot::default(),
+        api(),
+        Some(Arc::new(middleware)),
+        AtomicUsize::new(0),
+    )
+    .unwrap()
+    .clock(Arc::new(clock.clone()))
+    .start();
+    let client = ClientTestContext::new(server.local_addr())
+        .with_clock(clock.clone())
+        .with_allowed_header(HEADER_IDEMPOTENT_REPLAYED);
+    let charge = |expected: usize| {
+        let client = client.clone();
+        async move {
+            let mut response = client
+                .make_request_with_request(
+                    request(Method::POST, client.url("/charges"), "key-1"),
+                    StatusCode::CREATED,
+                )
+                .await
+                .unwrap();
+            assert_eq!(read_json::<usize>(&mut response).await, expected);
+            response.headers().contains_key(HEADER_IDEMPOTENT_REPLAYED)
+        }
+    };
+
+    // Retries are replayed until the key expires by the server's clock, after
+    // which the key starts a new operation.
+    assert!(!charge(0).await);
+    clock.advance(Duration::from_secs(59));
+    assert!(charge(0).await);
+    clock.advance(Duration::from_secs(2));
+    assert!(!charge(1).await);
+    assert!(charge(1).await);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotency_body_and_principal() {
+    let config = ConfigDropshot::default();
+    let middleware = IdempotencyMiddleware::new(InMemoryIdempotencyStore::new(
+        Duration::from_secs(60),
+    ))
+    .with_principal(|request| {
+        let user = request.headers().get("x-user")?;
+        Some(user.to_str().ok()?.to_string())
+    });
+    let server = HttpServerStarter::new(
+        &config,
+        api(),
+        Some(Arc::new(middleware)),
+        AtomicUsize::new(0),
+    )
+    .unwrap()
+    .start();
+    let client = ClientTestContext::new(server.local_addr())
+        .with_allowed_header(HEADER_IDEMPOTENT_REPLAYED);
+
+    let mut response = client
+        .make_request_with_request(
+            request_as("alice", client.url("/charges"), "key-1", "a"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_json::<usize>(&mut response).await, 0);
+
+    // Reusing a key with a different body is an error.
+    let error = client
+        .make_request_with_request(
+            request_as("alice", client.url("/charges"), "key-1", "b"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "idempotency key was already used for a different request"
+    );
+
+    // Another principal's use of the same key is a separate operation.
+    let mut response = client
+        .make_request_with_request(
+            request_as("bob", client.url("/charges"), "key-1", "b"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert!(response.headers().get(HEADER_IDEMPOTENT_REPLAYED).is_none());
+    assert_eq!(read_json::<usize>(&mut response).await, 1);
+
+    // Each principal's retries get its own response.
+    let mut response = client
+        .make_request_with_request(
+            request_as("alice", client.url("/charges"), "key-1", "a"),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(HEADER_IDEMPOTENT_REPLAYED).unwrap(),
+        "true"
+    );
+    assert_eq!(read_json::<usize>(&mut response).await, 0);
+    assert_eq!(server.app_private().load(Ordering::SeqCst), 2);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotency_body_limits() {
+    let config = ConfigDropshot::default();
+    let middleware = IdempotencyMiddleware::new(InMemoryIdempotencyStore::new(
+        Duration::from_secs(60),
+    ));
+    let server = HttpServerStarter::new(
+        &config,
+        api(),
+        Some(Arc::new(middleware)),
+        AtomicUsize::new(0),
+    )
+    .unwrap()
+    .start();
+    let client = ClientTestContext::new(server.local_addr())
+        .with_allowed_header(HEADER_IDEMPOTENT_REPLAYED);
+    let upload_request = |path: &str, key: &str, len: usize| {
+        Request::builder()
+            .method(Method::POST)
+            .uri(client.url(path))
+            .header(HEADER_IDEMPOTENCY_KEY, key)
+            .body(Body::from(vec![b'x'; len]))
+            .unwrap()
+    };
+
+    // Bodies are buffered up to the endpoint's limit, not the server's.
+    let mut response = client
+        .make_request_with_request(
+            upload_request("/uploads", "key-1", 2048),
+            StatusCode::CREATED,
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_json::<usize>(&mut response).await, 2048);
+    client
+        .make_request_with_request(
+            upload_request("/uploads", "key-2", 8192),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+
+    // Bodies streamed to the handler aren't buffered at all, so this one,
+    // which the handler never reads, isn't subject to any limit.
+    for _ in 0..2 {
+        let mut response = client
+            .make_request_with_request(
+                upload_request("/uploads/stream", "key-3", 8192),
+                StatusCode::CREATED,
+            )
+            .await
+            .unwrap();
+        assert_eq!(read_json::<usize>(&mut response).await, 1);
+    }
+    assert_eq!(server.app_private().load(Ordering::SeqCst), 2);
+
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotency_key_extractor() {
+    let config = ConfigDropshot::default();
+    let testctx = dropshot::test_util::TestContext::new(
+        api(),
+        AtomicUsize::new(0),
+        &config,
+    );
+    let client = &testctx.client_testctx;
+
+    let mut response = client
+        .make_request_with_request(
+            request(Method::GET, client.url("/key"), "abc-123"),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_json::<String>(&mut response).await, "abc-123");
+
+    let error = client
+        .make_request_error(Method::GET, "/key", StatusCode::BAD_REQUEST)
+        .await;
+    assert_eq!(error.message, "missing idempotency-key header");
+
+    testctx.teardown().await;
+}
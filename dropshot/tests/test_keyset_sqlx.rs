@@ -0,0 +1,84 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for keyset queries made with sqlx.
+
+#![cfg(feature = "sqlx")]
+
+use dropshot::CompositeSort;
+use dropshot::Keyset;
+use dropshot::PageDirection;
+use dropshot::PaginationOrder::{Ascending, Descending};
+use dropshot::PositionalParams;
+use sqlx::Connection;
+use sqlx::SqliteConnection;
+
+/// Returns the ids of the page of items after `marker`, sorted by descending
+/// size and then ascending id
+async fn page(
+    conn: &mut SqliteConnection,
+    direction: PageDirection,
+    marker: Option<(i64, i64)>,
+) -> Vec<i64> {
+    let sort = CompositeSort::new(vec![Descending, Ascending]);
+    let query = Keyset::new(&["size", "id"], &sort)
+        .direction(direction)
+        .query::<_, i64, _>(&PositionalParams, marker.as_ref())
+        .unwrap();
+    let sql = format!(
+        "SELECT `id` FROM `items` WHERE `size` < ? AND {} ORDER BY {} LIMIT 2",
+        query.where_clause.as_deref().unwrap_or("TRUE"),
+        query.order_by,
+    );
+    // The keyset's values go after any others.
+    query
+        .bind_as(sqlx::query_as::<_, (i64,)>(&sql).bind(100_i64))
+        .fetch_all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(id,)| id)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_keyset_sqlx() {
+    let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+    sqlx::query("CREATE TABLE `items` (`id` INTEGER, `size` INTEGER)")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    for (id, size) in [(1, 10), (2, 30), (3, 20), (4, 30), (5, 10), (6, 200)] {
+        sqlx::query("INSERT INTO `items` VALUES (?, ?)")
+            .bind(id)
+            .bind(size)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    let forward = PageDirection::Forward;
+    assert_eq!(page(&mut conn, forward, None).await, vec![2, 4]);
+    assert_eq!(page(&mut conn, forward, Some((30, 4))).await, vec![3, 1]);
+    assert_eq!(page(&mut conn, forward, Some((10, 1))).await, vec![5]);
+    // Going backward from the first item of the second page gets the first
+    // page again, in reverse.
+    let backward = PageDirection::Backward;
+    assert_eq!(page(&mut conn, backward, Some((20, 3))).await, vec![4, 2]);
+
+    // Queries that don't return rows can use the keyset too.
+    let sort = CompositeSort::new(vec![Descending, Ascending]);
+    let query = Keyset::new(&["size", "id"], &sort)
+        .query::<_, i64, _>(&PositionalParams, Some(&(20, 3)))
+        .unwrap();
+    let sql = format!(
+        "DELETE FROM `items` WHERE {}",
+        query.where_clause.clone().unwrap()
+    );
+    let deleted =
+        query.bind(sqlx::query(&sql)).execute(&mut conn).await.unwrap();
+    assert_eq!(deleted.rows_affected(), 2);
+    assert_eq!(
+        page(&mut conn, forward, Some((20, 3))).await,
+        Vec::<i64>::new()
+    );
+}
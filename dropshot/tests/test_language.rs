@@ -0,0 +1,93 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the `Language` extractor.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::AvailableLanguages;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Language;
+use dropshot::RequestContext;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request};
+
+pub mod common;
+
+struct Greetings;
+impl AvailableLanguages for Greetings {
+    const LANGUAGES: &'static [&'static str] = &["en", "fr", "es-MX"];
+}
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(greeting).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/greeting",
+}]
+async fn greeting(
+    _rqctx: RequestContext<usize>,
+    language: Language<Greetings>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    let greeting = match language.as_str() {
+        "fr" => "bonjour",
+        "es-MX" => "hola",
+        _ => "hello",
+    };
+    Ok(HttpResponseOk(greeting.to_string()))
+}
+
+#[tokio::test]
+async fn test_language() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+
+    for (accept_language, expected) in [
+        (None, "hello"),
+        (Some("fr-FR, fr;q=0.9, en;q=0.8"), "bonjour"),
+        (Some("en;q=0.5, es;q=0.7, de"), "hola"),
+        (Some("de, ja"), "hello"),
+    ] {
+        let mut builder =
+            Request::builder().method(Method::GET).uri(client.url("/greeting"));
+        if let Some(accept_language) = accept_language {
+            builder = builder.header(header::ACCEPT_LANGUAGE, accept_language);
+        }
+        let request = builder.body(Body::empty()).unwrap();
+        let mut response = client
+            .make_request_with_request(request, StatusCode::OK)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_json::<String>(&mut response).await,
+            expected,
+            "Accept-Language: {:?}",
+            accept_language
+        );
+    }
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_language_openapi() {
+    let spec = api().openapi("test", "1.0.0").json().unwrap();
+    let parameters = &spec["paths"]["/greeting"]["get"]["parameters"];
+    assert_eq!(
+        parameters,
+        &serde_json::json!([{
+            "in": "header",
+            "name": "Accept-Language",
+            "description":
+                "preferred languages for the response (available: en, fr, \
+                 es-MX)",
+            "schema": { "type": "string" },
+        }])
+    );
+}
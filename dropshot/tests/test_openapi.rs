@@ -2,12 +2,14 @@
 
 use dropshot::{
     endpoint, http_response_found, http_response_see_other,
-    http_response_temporary_redirect, ApiDescription, FreeformBody, HttpError,
+    http_response_temporary_redirect, ApiDescription, ApiEndpoint, ApiWebhook,
+    EventStream, EventStreamUpgrade, FreeformBody, HttpError,
     HttpResponseAccepted, HttpResponseCreated, HttpResponseDeleted,
     HttpResponseFound, HttpResponseHeaders, HttpResponseOk,
     HttpResponseSeeOther, HttpResponseTemporaryRedirect,
     HttpResponseUpdatedNoContent, MultipartBody, PaginationParams, Path, Query,
-    RequestContext, ResultsPage, TagConfig, TagDetails, TypedBody, UntypedBody,
+    RequestContext, ResultsPage, TagConfig, TagDetails, TagGroup, TypedBody,
+    UntypedBody,
 };
 use hyper::Body;
 use schemars::JsonSchema;
@@ -481,6 +483,12 @@ fn make_api(
         api = api.tag_config(tag_config);
     }
 
+    make_api_into(api)
+}
+
+fn make_api_into(
+    mut api: ApiDescription<()>,
+) -> Result<ApiDescription<()>, String> {
     api.register(handler1)?;
     api.register(handler2)?;
     api.register(handler3)?;
@@ -535,6 +543,8 @@ fn test_openapi_fuller() -> Result<(), String> {
         allow_other_tags: true,
         endpoint_tag_policy: dropshot::EndpointTagPolicy::AtLeastOne,
         tag_definitions,
+        tag_order: vec![],
+        tag_groups: vec![],
     };
     let api = make_api(Some(tag_config))?;
     let mut output = Cursor::new(Vec::new());
@@ -551,3 +561,866 @@ fn test_openapi_fuller() -> Result<(), String> {
     expectorate::assert_contents("tests/test_openapi_fuller.json", actual);
     Ok(())
 }
+
+#[tokio::test]
+async fn test_openapi_endpoint() -> Result<(), String> {
+    use hyper::service::Service;
+
+    let mut api = ApiDescription::new();
+    // The document describes endpoints registered after this one, too.
+    api.register_openapi_endpoint("/openapi.json", "test", "threeve")?;
+    let mut api = make_api_into(api)?;
+    assert_eq!(
+        api.register_openapi_endpoint("/other.json", "test", "threeve")
+            .unwrap_err(),
+        "an OpenAPI endpoint has already been registered"
+    );
+    let expected = api.openapi("test", "threeve").json().unwrap();
+
+    let mut service = dropshot::ApiService::new(api, ());
+    let request =
+        hyper::Request::get("/openapi.json").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let actual: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[cfg(feature = "yaml")]
+#[tokio::test]
+async fn test_openapi_endpoint_yaml() -> Result<(), String> {
+    use hyper::service::Service;
+
+    let mut api = ApiDescription::new();
+    api.register_openapi_endpoint("/openapi.json", "test", "threeve")?;
+    let api = make_api_into(api)?;
+    let expected = api.openapi("test", "threeve").yaml().unwrap();
+
+    let mut service = dropshot::ApiService::new(api, ());
+    let request =
+        hyper::Request::get("/openapi.yaml").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+        "application/yaml"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(from_utf8(&body).unwrap(), expected);
+    Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct ExamplePathParams {
+    id: String,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct ExampleThing {
+    name: String,
+    size: u32,
+}
+
+fn small_thing() -> ExampleThing {
+    ExampleThing { name: String::from("small"), size: 1 }
+}
+
+fn large_thing() -> ExampleThing {
+    ExampleThing { name: String::from("large"), size: 1000 }
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/things/{id}",
+    request_examples = ["small_thing", "large_thing"],
+    response_examples = ["small_thing"],
+}]
+async fn put_thing(
+    _rqctx: RequestContext<()>,
+    _path: Path<ExamplePathParams>,
+    body: TypedBody<ExampleThing>,
+) -> Result<HttpResponseOk<ExampleThing>, HttpError> {
+    Ok(HttpResponseOk(body.into_inner()))
+}
+
+#[test]
+fn test_openapi_examples() {
+    let mut api = ApiDescription::new();
+    let endpoint = ApiEndpoint::from(put_thing).parameter_example(
+        "id",
+        "abc",
+        "thing-abc",
+    );
+    api.register(endpoint).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+    let operation = &spec["paths"]["/things/{id}"]["put"];
+
+    assert_eq!(
+        operation["parameters"][0]["examples"],
+        serde_json::json!({ "abc": { "value": "thing-abc" } })
+    );
+    assert_eq!(
+        operation["requestBody"]["content"]["application/json"]["examples"],
+        serde_json::json!({
+            "small_thing": { "value": { "name": "small", "size": 1 } },
+            "large_thing": { "value": { "name": "large", "size": 1000 } },
+        })
+    );
+    assert_eq!(
+        operation["responses"]["200"]["content"]["application/json"]
+            ["examples"],
+        serde_json::json!({
+            "small_thing": { "value": { "name": "small", "size": 1 } },
+        })
+    );
+}
+
+#[endpoint {
+    method = GET,
+    path = "/secure",
+    security = [
+        { scheme = "oauth", scopes = ["read"] },
+        { scheme = "api_key" },
+    ],
+}]
+async fn get_secure(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[test]
+fn test_openapi_security() {
+    use dropshot::{ApiKeyLocation, OAuth2Flow, SecurityScheme};
+
+    let flow = OAuth2Flow::ClientCredentials {
+        token_url: String::from("https://auth.example.com/token"),
+        scopes: [(String::from("read"), String::from("read things"))].into(),
+    };
+    let security = |api: ApiDescription<()>| {
+        api.security_scheme("oauth", SecurityScheme::oauth2(vec![flow.clone()]))
+            .security_scheme(
+                "api_key",
+                SecurityScheme::api_key("X-Api-Key", ApiKeyLocation::Header)
+                    .description("an API key"),
+            )
+    };
+
+    let mut api = security(ApiDescription::new());
+    api.register(get_secure).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+    assert_eq!(
+        spec["paths"]["/secure"]["get"]["security"],
+        serde_json::json!([{ "oauth": ["read"] }, { "api_key": [] }])
+    );
+    assert_eq!(
+        spec["components"]["securitySchemes"],
+        serde_json::json!({
+            "api_key": {
+                "type": "apiKey",
+                "name": "X-Api-Key",
+                "in": "header",
+                "description": "an API key",
+            },
+            "oauth": {
+                "type": "oauth2",
+                "flows": {
+                    "clientCredentials": {
+                        "tokenUrl": "https://auth.example.com/token",
+                        "scopes": { "read": "read things" },
+                    },
+                },
+            },
+        })
+    );
+
+    // The requirements are available to middleware, too.
+    let service = dropshot::ApiService::new(api, ());
+    let requirements = service
+        .app_state()
+        .security_requirements(&http::Method::GET, "/secure")
+        .unwrap();
+    assert_eq!(requirements.len(), 2);
+    assert_eq!(requirements[0]["oauth"], ["read"]);
+
+    // Endpoints can only use declared schemes and scopes.
+    let mut api = ApiDescription::<()>::new();
+    assert_eq!(
+        api.register(get_secure).unwrap_err(),
+        "endpoint \"get_secure\" uses undeclared security scheme \"oauth\""
+    );
+    let mut api = security(ApiDescription::new());
+    let endpoint = ApiEndpoint::from(get_secure).security("api_key", &["all"]);
+    assert_eq!(
+        api.register(endpoint).unwrap_err(),
+        "endpoint \"get_secure\" requires scope \"all\", which security \
+         scheme \"api_key\" does not define"
+    );
+}
+
+#[test]
+fn test_openapi_info_and_servers() {
+    use dropshot::{ServerDetails, ServerVariable};
+
+    let api = make_api(None).unwrap();
+    let mut definition = api.openapi("test", "threeve");
+    definition
+        .contact_name("old mate")
+        .contact_url("https://example.com/support")
+        .contact_email("support@example.com")
+        .license("MPL-2.0", "https://mozilla.org/MPL/2.0/")
+        .terms_of_service("https://example.com/terms")
+        .info_extension("x-audience", serde_json::json!("external"))
+        .server(ServerDetails {
+            url: String::from("https://{region}.example.com/{base}"),
+            description: Some(String::from("production")),
+            variables: [
+                (
+                    String::from("region"),
+                    ServerVariable {
+                        default: String::from("us-east"),
+                        values: vec![
+                            String::from("us-east"),
+                            String::from("us-west"),
+                        ],
+                        description: Some(String::from("deployment region")),
+                    },
+                ),
+                (
+                    String::from("base"),
+                    ServerVariable {
+                        default: String::from("v1"),
+                        ..Default::default()
+                    },
+                ),
+            ]
+            .into(),
+        })
+        .server(ServerDetails {
+            url: String::from("http://localhost:8080"),
+            ..Default::default()
+        });
+    let spec = definition.json().unwrap();
+
+    assert_eq!(
+        spec["info"],
+        serde_json::json!({
+            "title": "test",
+            "version": "threeve",
+            "termsOfService": "https://example.com/terms",
+            "contact": {
+                "name": "old mate",
+                "url": "https://example.com/support",
+                "email": "support@example.com",
+            },
+            "license": {
+                "name": "MPL-2.0",
+                "url": "https://mozilla.org/MPL/2.0/",
+            },
+            "x-audience": "external",
+        })
+    );
+    assert_eq!(
+        spec["servers"],
+        serde_json::json!([
+            {
+                "url": "https://{region}.example.com/{base}",
+                "description": "production",
+                "variables": {
+                    "base": { "default": "v1" },
+                    "region": {
+                        "enum": ["us-east", "us-west"],
+                        "default": "us-east",
+                        "description": "deployment region",
+                    },
+                },
+            },
+            { "url": "http://localhost:8080" },
+        ])
+    );
+
+    // The written document is the same.
+    let mut out = Vec::new();
+    definition.write(&mut out).unwrap();
+    let written: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(written, spec);
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/things/{id}",
+    extensions = [{ name = "x-go-name", value = "PutThing" }],
+}]
+async fn put_thing_extended(
+    _rqctx: RequestContext<()>,
+    _path: Path<ExamplePathParams>,
+    body: TypedBody<ExampleThing>,
+) -> Result<HttpResponseOk<ExampleThing>, HttpError> {
+    Ok(HttpResponseOk(body.into_inner()))
+}
+
+#[test]
+fn test_openapi_extensions() {
+    let mut api = ApiDescription::new().schema_extension(
+        "ExampleThing",
+        "x-go-type",
+        "Thing",
+    );
+    let endpoint = ApiEndpoint::from(put_thing_extended)
+        .extension("x-rate-limit", 100)
+        .parameter_extension("id", "x-go-name", "ID");
+    api.register(endpoint).unwrap();
+    let spec = api
+        .openapi("test", "threeve")
+        .extension("x-generator", serde_json::json!({ "name": "dropshot" }))
+        .json()
+        .unwrap();
+
+    assert_eq!(spec["x-generator"], serde_json::json!({ "name": "dropshot" }));
+    let operation = &spec["paths"]["/things/{id}"]["put"];
+    assert_eq!(operation["x-go-name"], "PutThing");
+    assert_eq!(operation["x-rate-limit"], 100);
+    assert_eq!(operation["parameters"][0]["x-go-name"], "ID");
+    assert_eq!(
+        spec["components"]["schemas"]["ExampleThing"]["x-go-type"],
+        "Thing"
+    );
+}
+
+#[test]
+#[should_panic(expected = "extension name \"go-name\" must begin with \"x-\"")]
+fn test_openapi_bad_extension() {
+    let _ = ApiEndpoint::from(put_thing_extended).extension("go-name", "x");
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ThingEvent {
+    id: String,
+    thing: ExampleThing,
+}
+
+#[test]
+fn test_openapi_webhooks() {
+    let mut api = ApiDescription::new().webhook(
+        ApiWebhook::new::<ThingEvent>("thing_deleted")
+            .summary("a thing was deleted"),
+    );
+    let endpoint = ApiEndpoint::from(put_thing).callback(
+        "thing_updated",
+        "{$request.header.x-callback-url}",
+        ApiWebhook::new::<ThingEvent>("thing_updated")
+            .method(http::Method::PUT)
+            .description("Sent once the thing has been updated"),
+    );
+    api.register(endpoint).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let webhook = &spec["x-webhooks"]["thing_deleted"]["post"];
+    assert_eq!(webhook["operationId"], "thing_deleted");
+    assert_eq!(webhook["summary"], "a thing was deleted");
+    assert_eq!(
+        webhook["requestBody"]["content"]["application/json"]["schema"],
+        serde_json::json!({ "$ref": "#/components/schemas/ThingEvent" })
+    );
+    assert_eq!(webhook["responses"]["2XX"]["description"], "request received");
+
+    let callback = &spec["paths"]["/things/{id}"]["put"]["callbacks"]
+        ["thing_updated"]["{$request.header.x-callback-url}"]["put"];
+    assert_eq!(callback["operationId"], "thing_updated");
+    assert_eq!(callback["description"], "Sent once the thing has been updated");
+    assert_eq!(
+        callback["requestBody"]["content"]["application/json"]["schema"],
+        serde_json::json!({ "$ref": "#/components/schemas/ThingEvent" })
+    );
+
+    let schemas = &spec["components"]["schemas"];
+    assert_eq!(
+        schemas["ThingEvent"]["properties"]["thing"]["$ref"],
+        "#/components/schemas/ExampleThing"
+    );
+}
+
+#[test]
+#[should_panic(expected = "webhook \"thing_deleted\" has already been added")]
+fn test_openapi_duplicate_webhook() {
+    let _ = ApiDescription::<()>::new()
+        .webhook(ApiWebhook::new::<ThingEvent>("thing_deleted"))
+        .webhook(ApiWebhook::new::<ThingEvent>("thing_deleted"));
+}
+
+#[endpoint {
+    method = POST,
+    path = "/things",
+    external_docs = {
+        url = "https://example.com/things",
+        description = "Working with things",
+    },
+    links = [{
+        name = "GetThing",
+        operation_id = "get_thing",
+        parameters = { id = "$response.body#/name" },
+    }],
+}]
+async fn create_thing(
+    _rqctx: RequestContext<()>,
+    body: TypedBody<ExampleThing>,
+) -> Result<HttpResponseCreated<ExampleThing>, HttpError> {
+    Ok(HttpResponseCreated(body.into_inner()))
+}
+
+#[test]
+fn test_openapi_external_docs_and_links() {
+    let mut api = ApiDescription::new();
+    api.register(create_thing).unwrap();
+    api.register(
+        ApiEndpoint::from(put_thing)
+            .external_docs("https://example.com/things/update", None),
+    )
+    .unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let create = &spec["paths"]["/things"]["post"];
+    assert_eq!(
+        create["externalDocs"],
+        serde_json::json!({
+            "description": "Working with things",
+            "url": "https://example.com/things",
+        })
+    );
+    assert_eq!(
+        create["responses"]["201"]["links"]["GetThing"],
+        serde_json::json!({
+            "operationId": "get_thing",
+            "parameters": { "id": "$response.body#/name" },
+        })
+    );
+
+    let update = &spec["paths"]["/things/{id}"]["put"];
+    assert_eq!(
+        update["externalDocs"],
+        serde_json::json!({ "url": "https://example.com/things/update" })
+    );
+}
+
+#[test]
+fn test_openapi_schema_settings() {
+    let mut api = ApiDescription::new()
+        .schema_settings(|settings| settings.inline_subschemas = true);
+    api.register(put_thing).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let schema = &spec["paths"]["/things/{id}"]["put"]["requestBody"]
+        ["content"]["application/json"]["schema"];
+    assert_eq!(schema["type"], "object");
+    assert_eq!(schema["properties"]["size"]["format"], "uint32");
+    assert!(spec["components"]["schemas"].get("ExampleThing").is_none());
+}
+
+#[test]
+fn test_openapi_schema_visitor() {
+    let mut api = ApiDescription::new()
+        .schema_visitor(|name, _| *name = format!("Acme{}", name))
+        .schema_visitor(|name, schema| {
+            if name == "AcmeExampleThing" {
+                let schema = schema.as_object_mut().unwrap();
+                schema["properties"].as_object_mut().unwrap().remove("size");
+                schema["required"] = serde_json::json!(["name"]);
+            }
+        });
+    api.register(put_thing).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let schemas = &spec["components"]["schemas"];
+    assert!(schemas.get("ExampleThing").is_none());
+    assert_eq!(
+        schemas["AcmeExampleThing"],
+        serde_json::json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" } },
+            "required": ["name"],
+        })
+    );
+    let operation = &spec["paths"]["/things/{id}"]["put"];
+    assert_eq!(
+        operation["requestBody"]["content"]["application/json"]["schema"],
+        serde_json::json!({ "$ref": "#/components/schemas/AcmeExampleThing" })
+    );
+    assert_eq!(
+        operation["responses"]["200"]["content"]["application/json"]["schema"],
+        serde_json::json!({ "$ref": "#/components/schemas/AcmeExampleThing" })
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "schema visitors gave two schemas the name \"Thing\""
+)]
+fn test_openapi_schema_visitor_conflict() {
+    let mut api = ApiDescription::new()
+        .schema_visitor(|name, _| *name = String::from("Thing"));
+    // This has both "ExampleThing" and the "Error" that every API has.
+    api.register(put_thing).unwrap();
+    let _ = api.openapi("test", "threeve").json();
+}
+
+#[test]
+fn test_openapi_tag_order_and_groups() {
+    let tag_config = TagConfig {
+        tag_order: vec!["zebras".to_string(), "it".to_string()],
+        tag_groups: vec![TagGroup {
+            name: "Everything".to_string(),
+            tags: vec!["it".to_string(), "zebras".to_string()],
+        }],
+        ..Default::default()
+    };
+    let mut api = ApiDescription::new().tag_config(tag_config);
+    api.register(handler1).unwrap();
+    api.register(ApiEndpoint::from(put_thing).tag("zebras").tag("aardvarks"))
+        .unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let tags = spec["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| tag["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(tags, ["zebras", "it", "aardvarks"]);
+    assert_eq!(
+        spec["x-tagGroups"],
+        serde_json::json!([
+            { "name": "Everything", "tags": ["it", "zebras"] },
+        ])
+    );
+}
+
+fn merge_tags(name: &str, description: &str) -> TagConfig {
+    TagConfig {
+        allow_other_tags: false,
+        tag_definitions: vec![(
+            name.to_string(),
+            TagDetails {
+                description: Some(description.to_string()),
+                external_docs: None,
+            },
+        )]
+        .into_iter()
+        .collect(),
+        tag_order: vec![name.to_string()],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_openapi_merge() {
+    let mut api = ApiDescription::new().tag_config(merge_tags("it", "It"));
+    api.register(handler1).unwrap();
+    api.register(handler3).unwrap();
+
+    let mut things =
+        ApiDescription::new().tag_config(merge_tags("things", "Things"));
+    things.register(ApiEndpoint::from(put_thing).tag("things")).unwrap();
+    things.register(ApiEndpoint::from(create_thing).tag("things")).unwrap();
+
+    api.merge(things).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let mut paths =
+        spec["paths"].as_object().unwrap().keys().collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        ["/test/man/{x}", "/test/person", "/things", "/things/{id}"]
+    );
+    assert_eq!(
+        spec["tags"],
+        serde_json::json!([
+            { "name": "it", "description": "It" },
+            { "name": "things", "description": "Things" },
+        ])
+    );
+    assert_eq!(spec["paths"]["/things/{id}"]["put"]["tags"][0], "things");
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct OtherPathArgs {
+    name: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/test/man/{name}",
+}]
+async fn get_man(
+    _rqctx: RequestContext<()>,
+    _path: Path<OtherPathArgs>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[test]
+fn test_openapi_merge_conflicts() {
+    let mut api = ApiDescription::new();
+    api.register(handler3).unwrap();
+
+    let mut other = ApiDescription::new();
+    other.register(handler3).unwrap();
+    assert_eq!(
+        api.merge(other).unwrap_err(),
+        "both APIs have an endpoint for DELETE /test/man/{x}; operation id \
+         \"handler3\" is used by both APIs"
+    );
+
+    let mut other = ApiDescription::new();
+    other.register(get_man).unwrap();
+    assert_eq!(
+        api.merge(other).unwrap_err(),
+        "path \"/test/man/{name}\" names variable \"name\" where path \
+         \"/test/man/{x}\" names it \"x\""
+    );
+
+    // Tags that aren't defined by either API are subject to this API's tag
+    // policy, and nothing is merged if they violate it.
+    let mut api = ApiDescription::new().tag_config(merge_tags("it", "It"));
+    api.register(handler1).unwrap();
+    let mut other = ApiDescription::new();
+    other.register(ApiEndpoint::from(put_thing).tag("zebras")).unwrap();
+    assert_eq!(
+        api.merge(other).unwrap_err(),
+        "endpoint \"put_thing\": Invalid tag: zebras"
+    );
+    let spec = api.openapi("test", "threeve").json().unwrap();
+    assert_eq!(spec["paths"].as_object().unwrap().len(), 1);
+    assert_eq!(spec["tags"].as_array().unwrap().len(), 1);
+}
+
+#[endpoint {
+    method = GET,
+    path = "/admin/things",
+    tags = ["admin"],
+    audiences = ["internal"],
+}]
+async fn list_all_things(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Vec<ExampleThing>>, HttpError> {
+    Ok(HttpResponseOk(Vec::new()))
+}
+
+#[test]
+fn test_openapi_audiences() {
+    let mut api = ApiDescription::new();
+    api.register(handler1).unwrap();
+    api.register(handler14).unwrap();
+    api.register(list_all_things).unwrap();
+    api.register(ApiEndpoint::from(put_thing).audience("partner")).unwrap();
+
+    let paths = |spec: serde_json::Value| {
+        let mut paths = spec["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    };
+
+    let spec = api.openapi("test", "threeve").json().unwrap();
+    assert_eq!(spec["tags"], serde_json::json!([{ "name": "it" }]));
+    assert_eq!(paths(spec), ["/test/person"]);
+
+    let spec =
+        api.openapi("test", "threeve").audience("internal").json().unwrap();
+    assert_eq!(
+        spec["tags"],
+        serde_json::json!([{ "name": "admin" }, { "name": "it" }])
+    );
+    assert_eq!(paths(spec), ["/admin/things", "/test/person"]);
+
+    let spec =
+        api.openapi("test", "threeve").audience("partner").json().unwrap();
+    assert_eq!(paths(spec), ["/test/person", "/things/{id}"]);
+
+    let spec = api
+        .openapi("test", "threeve")
+        .audience("internal")
+        .filter(|endpoint| endpoint.tags.iter().any(|tag| tag == "admin"))
+        .json()
+        .unwrap();
+    assert_eq!(paths(spec), ["/admin/things"]);
+}
+
+#[test]
+#[should_panic(
+    expected = "endpoint \"handler14\" is unpublished, so it can't be \
+                documented for audience \"partner\""
+)]
+fn test_openapi_unpublished_audience() {
+    let _ = ApiEndpoint::from(handler14).audience("partner");
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_openapi_yaml() -> Result<(), String> {
+    let api = make_api(None)?;
+    let definition = api.openapi("test", "threeve");
+    let yaml = definition.yaml().unwrap();
+
+    // The YAML describes the same document as the JSON...
+    let parsed: serde_json::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(parsed, definition.json().unwrap());
+
+    // ... with its keys in a stable order.
+    let top_level = yaml
+        .lines()
+        .filter(|line| !line.starts_with([' ', '-']))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        top_level,
+        ["components:", "info:", "openapi: 3.0.3", "paths:", "tags:"]
+    );
+
+    let mut output = Cursor::new(Vec::new());
+    definition.write_yaml(&mut output).unwrap();
+    assert_eq!(from_utf8(output.get_ref()).unwrap(), yaml);
+    Ok(())
+}
+
+#[endpoint {
+    method = DELETE,
+    path = "/things/{id}",
+}]
+async fn delete_thing(
+    _rqctx: RequestContext<()>,
+    _path: Path<ExamplePathParams>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    Ok(HttpResponseDeleted())
+}
+
+#[test]
+fn test_openapi_shared_path_parameters() {
+    let mut api = ApiDescription::new();
+    api.register(put_thing).unwrap();
+    api.register(delete_thing).unwrap();
+    api.register(create_thing).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    // Both operations on "/things/{id}" take "id", so it's described once.
+    let item = &spec["paths"]["/things/{id}"];
+    assert_eq!(item["parameters"][0]["name"], "id");
+    assert_eq!(item["parameters"].as_array().unwrap().len(), 1);
+    assert!(item["put"].get("parameters").is_none());
+    assert!(item["delete"].get("parameters").is_none());
+
+    // Paths with a single operation are left alone.
+    let item = &spec["paths"]["/things"];
+    assert!(item.get("parameters").is_none());
+
+    let api = api.share_path_parameters(false);
+    let spec = api.openapi("test", "threeve").json().unwrap();
+    let item = &spec["paths"]["/things/{id}"];
+    assert!(item.get("parameters").is_none());
+    assert_eq!(item["put"]["parameters"][0]["name"], "id");
+    assert_eq!(item["delete"]["parameters"][0]["name"], "id");
+}
+
+#[endpoint {
+    method = GET,
+    path = "/things/export",
+    response_content_types = ["text/csv", "application/octet-stream"],
+}]
+async fn export_things(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<FreeformBody>, HttpError> {
+    unimplemented!();
+}
+
+#[endpoint {
+    method = GET,
+    path = "/things",
+    response_content_types = ["application/vnd.things+json"],
+}]
+async fn list_things(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Vec<ExampleThing>>, HttpError> {
+    unimplemented!();
+}
+
+#[test]
+fn test_openapi_response_content_types() {
+    let mut api = ApiDescription::new();
+    api.register(export_things).unwrap();
+    api.register(list_things).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    let content =
+        &spec["paths"]["/things/export"]["get"]["responses"]["200"]["content"];
+    assert_eq!(
+        content,
+        &serde_json::json!({
+            "text/csv": { "schema": { "type": "string" } },
+            "application/octet-stream": {
+                "schema": { "type": "string", "format": "binary" }
+            },
+        })
+    );
+
+    // JSON media types keep the schema of the response type.
+    let content =
+        &spec["paths"]["/things"]["get"]["responses"]["200"]["content"];
+    assert!(content.get("application/json").is_none());
+    let schema = &content["application/vnd.things+json"]["schema"];
+    assert_eq!(schema["type"], "array");
+    assert_eq!(schema["items"]["$ref"], "#/components/schemas/ExampleThing");
+}
+
+#[endpoint {
+    method = GET,
+    path = "/things/events",
+}]
+async fn watch_things(
+    _rqctx: RequestContext<()>,
+    _events: EventStreamUpgrade,
+) -> Result<EventStream<ExampleThing>, HttpError> {
+    unimplemented!();
+}
+
+#[test]
+fn test_openapi_event_stream() {
+    let mut api = ApiDescription::new();
+    api.register(watch_things).unwrap();
+    let spec = api.openapi("test", "threeve").json().unwrap();
+
+    // The events' schema is documented as that of the event stream, and the
+    // operation is marked as also available over a websocket.
+    let operation = &spec["paths"]["/things/events"]["get"];
+    assert_eq!(operation["x-dropshot-event-stream"], serde_json::json!({}));
+    assert!(operation.get("x-dropshot-websocket").is_none());
+    let content = &operation["responses"]["200"]["content"];
+    assert_eq!(
+        content,
+        &serde_json::json!({
+            "text/event-stream": {
+                "schema": { "$ref": "#/components/schemas/ExampleThing" }
+            },
+        })
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "endpoint \"export_things\" has invalid response content type \
+                \"csv\""
+)]
+fn test_openapi_bad_response_content_type() {
+    let _ = ApiEndpoint::from(export_things).response_content_type("csv");
+}
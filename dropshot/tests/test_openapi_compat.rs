@@ -0,0 +1,219 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Tests for finding breaking changes between OpenAPI documents
+
+use dropshot::compare_openapi;
+use serde_json::json;
+
+/// Returns a document with one operation, "POST /things", whose request and
+/// response bodies are `Thing`
+fn make_spec(
+    parameters: serde_json::Value,
+    thing: serde_json::Value,
+) -> serde_json::Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "test", "version": "1.0.0" },
+        "paths": {
+            "/things": {
+                "post": {
+                    "operationId": "create_thing",
+                    "parameters": parameters,
+                    "requestBody": {
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "$ref": "#/components/schemas/Thing"
+                                }
+                            }
+                        },
+                        "required": true
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "successful creation",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "$ref": "#/components/schemas/Thing"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "components": { "schemas": { "Thing": thing } }
+    })
+}
+
+fn thing(kinds: &[&str], required: &[&str]) -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string", "maxLength": 63 },
+            "kind": { "type": "string", "enum": kinds },
+            "size": { "type": "integer", "format": "uint32", "minimum": 0 }
+        },
+        "required": required
+    })
+}
+
+fn describe(old: &serde_json::Value, new: &serde_json::Value) -> Vec<String> {
+    compare_openapi(old, new).iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_compat_unchanged() {
+    let spec = make_spec(json!([]), thing(&["a", "b"], &["name"]));
+    assert_eq!(describe(&spec, &spec), Vec::<String>::new());
+}
+
+#[test]
+fn test_compat_additions() {
+    // New optional parameters and new operations don't break anything.
+    let old = make_spec(json!([]), thing(&["a", "b"], &["name"]));
+    let mut new = make_spec(
+        json!([{
+            "in": "query",
+            "name": "dry_run",
+            "schema": { "type": "boolean" }
+        }]),
+        thing(&["a", "b"], &["name"]),
+    );
+    new["paths"]["/things"]["get"] = json!({ "responses": {} });
+    assert_eq!(describe(&old, &new), Vec::<String>::new());
+}
+
+#[test]
+fn test_compat_removed_operation() {
+    let old = make_spec(json!([]), thing(&["a"], &["name"]));
+    let mut new = old.clone();
+    new["paths"]["/things"].as_object_mut().unwrap().remove("post");
+    assert_eq!(
+        describe(&old, &new),
+        vec!["POST /things: operation was removed"]
+    );
+}
+
+#[test]
+fn test_compat_required_parameters() {
+    let old = make_spec(
+        json!([{
+            "in": "query",
+            "name": "dry_run",
+            "schema": { "type": "boolean" }
+        }]),
+        thing(&["a"], &["name"]),
+    );
+    let new = make_spec(
+        json!([
+            {
+                "in": "query",
+                "name": "dry_run",
+                "required": true,
+                "schema": { "type": "boolean" }
+            },
+            {
+                "in": "header",
+                "name": "x-tenant",
+                "required": true,
+                "schema": { "type": "string" }
+            }
+        ]),
+        thing(&["a"], &["name"]),
+    );
+    assert_eq!(
+        describe(&old, &new),
+        vec![
+            "POST /things: query parameter \"dry_run\": parameter became \
+             required",
+            "POST /things: header parameter \"x-tenant\": required parameter \
+             was added",
+        ]
+    );
+}
+
+#[test]
+fn test_compat_narrowed_types() {
+    // `Thing` is both sent and received, so narrowing the enum breaks
+    // requests and widening it breaks responses.
+    let old = make_spec(json!([]), thing(&["a", "b"], &["name"]));
+    let new = make_spec(json!([]), thing(&["a", "c"], &["name", "kind"]));
+    assert_eq!(
+        describe(&old, &new),
+        vec![
+            "POST /things: request body (application/json): property \
+             \"kind\" became required",
+            "POST /things: request body (application/json): property \
+             \"kind\": enum value \"b\" is no longer accepted",
+            "POST /things: response 201 (application/json): property \
+             \"kind\": enum value \"c\" was added",
+        ]
+    );
+
+    let mut new = old.clone();
+    let size = &mut new["components"]["schemas"]["Thing"]["properties"]["size"];
+    size["type"] = json!("string");
+    let name = &mut new["components"]["schemas"]["Thing"]["properties"]["name"];
+    name["maxLength"] = json!(31);
+    assert_eq!(
+        describe(&old, &new),
+        vec![
+            "POST /things: request body (application/json): property \
+             \"name\": maxLength changed from 63 to 31",
+            "POST /things: request body (application/json): property \
+             \"size\": type changed from \"integer\" to \"string\"",
+            "POST /things: response 201 (application/json): property \
+             \"size\": type changed from \"integer\" to \"string\"",
+        ]
+    );
+}
+
+#[test]
+fn test_compat_responses() {
+    let old = make_spec(json!([]), thing(&["a"], &["name", "kind"]));
+    let new = make_spec(json!([]), thing(&["a"], &["name"]));
+    assert_eq!(
+        describe(&old, &new),
+        vec![
+            "POST /things: response 201 (application/json): property \
+             \"kind\" is no longer always present",
+        ]
+    );
+
+    let mut new = old.clone();
+    let responses = &mut new["paths"]["/things"]["post"]["responses"];
+    let created = responses.as_object_mut().unwrap().remove("201").unwrap();
+    responses["200"] = created;
+    assert_eq!(
+        describe(&old, &new),
+        vec!["POST /things: response 201: response was removed"]
+    );
+}
+
+#[test]
+fn test_compat_path_item_parameters() {
+    // Moving a parameter from the operation to its path item changes nothing.
+    let id = json!({
+        "in": "path",
+        "name": "id",
+        "required": true,
+        "schema": { "type": "string" }
+    });
+    let old = make_spec(json!([id]), thing(&["a"], &["name"]));
+    let mut new = make_spec(json!([]), thing(&["a"], &["name"]));
+    new["paths"]["/things"]["parameters"] = json!([id]);
+    assert_eq!(describe(&old, &new), Vec::<String>::new());
+
+    // A parameter newly required by the path item applies to its operations.
+    let old = make_spec(json!([]), thing(&["a"], &["name"]));
+    assert_eq!(
+        describe(&old, &new),
+        vec![
+            "POST /things: path parameter \"id\": required parameter was \
+              added"
+        ]
+    );
+}
@@ -2,23 +2,38 @@
 
 //! Test cases for API handler functions that use pagination.
 
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
 use chrono::DateTime;
 use chrono::Utc;
 use dropshot::endpoint;
 use dropshot::test_util::iter_collection;
+use dropshot::test_util::iter_collection_by_offset;
 use dropshot::test_util::object_get;
 use dropshot::test_util::objects_list_page;
+use dropshot::test_util::stream_collection;
+use dropshot::test_util::stream_collection_pages;
 use dropshot::test_util::ClientTestContext;
+use dropshot::test_util::TestClock;
 use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
 use dropshot::EmptyScanParams;
 use dropshot::HttpError;
 use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::HttpServerStarter;
+use dropshot::OffsetPaginationParams;
+use dropshot::OffsetResultsPage;
+use dropshot::PageDirection;
+use dropshot::PageTokenKeys;
 use dropshot::PaginationOrder;
 use dropshot::PaginationParams;
 use dropshot::Query;
 use dropshot::RequestContext;
 use dropshot::ResultsPage;
+use dropshot::ScanNavigation;
 use dropshot::WhichPage;
+use futures::StreamExt;
 use http::Method;
 use http::StatusCode;
 use hyper::Body;
@@ -33,9 +48,11 @@ use std::env::current_exe;
 use std::fmt::Debug;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::ops::Bound;
 use std::sync::atomic::AtomicU16;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 use subprocess::Exec;
@@ -145,6 +162,10 @@ fn paginate_api() -> ApiDescription<usize> {
     api.register(api_with_extra_params).unwrap();
     api.register(api_with_required_params).unwrap();
     api.register(api_dictionary).unwrap();
+    api.register(api_integers_by_offset).unwrap();
+    api.register(api_integers_with_navigation).unwrap();
+    api.register(api_integers_with_limits).unwrap();
+    api.register(api_integers_bidirectional).unwrap();
     api
 }
 
@@ -170,7 +191,13 @@ async fn api_integers(
     rqctx: RequestContext<usize>,
     query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
 ) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
-    let pag_params = query.into_inner();
+    list_integers(&rqctx, query.into_inner())
+}
+
+fn list_integers(
+    rqctx: &RequestContext<usize>,
+    pag_params: PaginationParams<EmptyScanParams, IntegersPageSelector>,
+) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
     let limit = rqctx.page_limit(&pag_params)?.get() as u16;
 
     let start = match &pag_params.page {
@@ -178,11 +205,14 @@ async fn api_integers(
         WhichPage::Next(IntegersPageSelector { last_seen }) => *last_seen,
     };
 
-    Ok(HttpResponseOk(ResultsPage::new(
-        range_u16(start, limit),
-        &EmptyScanParams {},
-        page_selector_for,
-    )?))
+    Ok(HttpResponseOk(
+        ResultsPage::new(
+            range_u16(start, limit),
+            &EmptyScanParams {},
+            page_selector_for,
+        )?
+        .seal(rqctx.page_token_keys())?,
+    ))
 }
 
 #[tokio::test]
@@ -339,6 +369,596 @@ async fn test_paginate_basic() {
     testctx.teardown().await;
 }
 
+// Tests for navigation metadata
+
+/// Scan state for "/intapi_nav", which knows where in the collection each page
+/// begins.
+struct IntegersNavigation {
+    start: u16,
+}
+
+impl ScanNavigation<u16> for IntegersNavigation {
+    fn total_count(&self, _items: &[u16]) -> Option<u64> {
+        Some(u64::from(u16::MAX - 1))
+    }
+
+    fn has_previous(&self, _items: &[u16]) -> Option<bool> {
+        Some(self.start > 0)
+    }
+}
+
+/// "/intapi_nav": the same collection as "/intapi", with navigation metadata
+/// on each page.
+#[endpoint {
+    method = GET,
+    path = "/intapi_nav",
+}]
+async fn api_integers_with_navigation(
+    rqctx: RequestContext<usize>,
+    query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
+    let pag_params = query.into_inner();
+    let limit = rqctx.page_limit(&pag_params)?.get() as u16;
+
+    let start = match &pag_params.page {
+        WhichPage::First(..) => 0,
+        WhichPage::Next(IntegersPageSelector { last_seen }) => *last_seen,
+    };
+
+    Ok(HttpResponseOk(ResultsPage::new_with_navigation(
+        range_u16(start, limit),
+        &IntegersNavigation { start },
+        |n, _| IntegersPageSelector { last_seen: *n },
+    )?))
+}
+
+#[tokio::test]
+async fn test_paginate_navigation() {
+    let api = paginate_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    let page = objects_list_page::<u16>(client, "/intapi_nav?limit=3").await;
+    assert_sequence_from(&page.items, 1, 3);
+    assert_eq!(page.total_count, Some(65534));
+    assert_eq!(page.has_previous, Some(false));
+    assert_eq!(page.last_cursor, page.next_page);
+
+    // The first cursor resumes the scan just after the first item.
+    let first_cursor = page.first_cursor.unwrap();
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_nav?limit=3&page_token={}", first_cursor),
+    )
+    .await;
+    assert_sequence_from(&page.items, 2, 3);
+    assert_eq!(page.has_previous, Some(true));
+
+    // Pages built without navigation leave these fields out altogether.
+    let mut response = client
+        .make_request_with_body(
+            Method::GET,
+            "/intapi?limit=3",
+            "".into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let page: serde_json::Value =
+        dropshot::test_util::read_json(&mut response).await;
+    assert_eq!(
+        page.as_object().unwrap().keys().collect::<Vec<_>>(),
+        vec!["items", "next_page"]
+    );
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_paginate_streaming() {
+    let api = paginate_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    // Only the pages that are needed get fetched, and each can be checked as
+    // it arrives.
+    let pages = stream_collection_pages::<u16>(client, "/intapi", "", 100)
+        .take(3)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pages.len(), 3);
+    for (i, page) in pages.iter().enumerate() {
+        assert_sequence_from(&page.items, 100 * i as u16 + 1, 100);
+        assert!(page.next_page.is_some());
+    }
+
+    // Items stream across page boundaries.
+    let items = stream_collection::<u16>(client, "/intapi", "", 7)
+        .take(30)
+        .collect::<Vec<_>>()
+        .await;
+    assert_sequence_from(&items, 1, 30);
+
+    // Streaming the whole collection finds the same items as iterating it.
+    let items = stream_collection::<u16>(client, "/intapi", "", 10000)
+        .collect::<Vec<_>>()
+        .await;
+    assert_sequence_from(&items, 1, u16::MAX - 1);
+    let (iterated, _) =
+        iter_collection::<u16>(client, "/intapi", "", 10000).await;
+    assert_eq!(items, iterated);
+
+    testctx.teardown().await;
+}
+
+// Tests for paging backward
+// Tests for paging backward
+
+/// "/intapi_bidi": the same collection as "/intapi", but clients can page
+/// backward as well as forward.
+#[endpoint {
+    method = GET,
+    path = "/intapi_bidi",
+}]
+async fn api_integers_bidirectional(
+    rqctx: RequestContext<usize>,
+    query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
+    let pag_params = query.into_inner();
+    let limit = rqctx.page_limit(&pag_params)?.get() as u16;
+
+    let items = match (&pag_params.page, pag_params.direction()) {
+        (WhichPage::First(..), _) => range_u16(0, limit),
+        (WhichPage::Next(IntegersPageSelector { last_seen }), dir) => {
+            match dir {
+                PageDirection::Forward => range_u16(*last_seen, limit),
+                // Going backward, the items before the marker are found in
+                // descending order.
+                PageDirection::Backward => {
+                    let end = *last_seen;
+                    let start = end.saturating_sub(limit).max(1);
+                    (start..end).rev().collect()
+                }
+            }
+        }
+    };
+
+    Ok(HttpResponseOk(ResultsPage::new_bidirectional(
+        items,
+        &EmptyScanParams {},
+        page_selector_for,
+        &pag_params,
+    )?))
+}
+
+#[tokio::test]
+async fn test_paginate_backward() {
+    let api = paginate_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    // The first page has nothing before it.
+    let page = objects_list_page::<u16>(client, "/intapi_bidi?limit=4").await;
+    assert_sequence_from(&page.items, 1, 4);
+    assert!(page.prev_page.is_none());
+
+    // Page forward twice, then back twice.
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?page_token={}", page.next_page.unwrap()),
+    )
+    .await;
+    assert_sequence_from(&page.items, 5, 100);
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?limit=4&page_token={}", page.next_page.unwrap()),
+    )
+    .await;
+    assert_sequence_from(&page.items, 105, 4);
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?limit=5&page_token={}", page.prev_page.unwrap()),
+    )
+    .await;
+    assert_sequence_from(&page.items, 100, 5);
+
+    // Switch direction again: going forward from a page that was fetched
+    // backward picks up right after its last item.
+    let next_token = page.next_page.clone().unwrap();
+    let forward = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?limit=3&page_token={}", next_token),
+    )
+    .await;
+    assert_sequence_from(&forward.items, 105, 3);
+
+    // Paging backward past the start of the collection yields a short page and
+    // then an empty one.
+    let page = objects_list_page::<u16>(
+        client,
+        &format!(
+            "/intapi_bidi?limit=98&page_token={}",
+            page.prev_page.unwrap()
+        ),
+    )
+    .await;
+    assert_sequence_from(&page.items, 2, 98);
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?page_token={}", page.prev_page.unwrap()),
+    )
+    .await;
+    assert_sequence_from(&page.items, 1, 1);
+    let page = objects_list_page::<u16>(
+        client,
+        &format!("/intapi_bidi?page_token={}", page.prev_page.unwrap()),
+    )
+    .await;
+    assert!(page.items.is_empty());
+    assert!(page.prev_page.is_none());
+    assert!(page.next_page.is_none());
+
+    testctx.teardown().await;
+}
+
+// Tests for per-endpoint page limits
+
+/// "/intapi_limits": the same collection as "/intapi", with its own default
+/// and maximum page sizes.
+#[endpoint {
+    method = GET,
+    path = "/intapi_limits",
+    page_default_limit = 5,
+    page_max_limit = 20,
+}]
+async fn api_integers_with_limits(
+    rqctx: RequestContext<usize>,
+    query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
+    list_integers(&rqctx, query.into_inner())
+}
+
+#[tokio::test]
+async fn test_paginate_endpoint_limits() {
+    let api = paginate_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    let page = objects_list_page::<u16>(client, "/intapi_limits").await;
+    assert_sequence_from(&page.items, 1, 5);
+    let page =
+        objects_list_page::<u16>(client, "/intapi_limits?limit=20").await;
+    assert_sequence_from(&page.items, 1, 20);
+
+    // Larger pages are rejected, rather than reduced as they are for the
+    // server-wide maximum.
+    assert_error(
+        client,
+        "/intapi_limits?limit=21",
+        "limit must not be greater than 20",
+    )
+    .await;
+    let page = objects_list_page::<u16>(client, "/intapi?limit=20000").await;
+    assert_eq!(page.items.len(), 10000);
+
+    // The limits appear in the schema of the "limit" parameter.
+    let spec = serde_json::to_value(
+        paginate_api().openapi("test", "1.0.0").json().unwrap(),
+    )
+    .unwrap();
+    let limit = spec["paths"]["/intapi_limits"]["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["name"] == "limit")
+        .unwrap();
+    assert_eq!(limit["schema"]["maximum"], 20.0);
+    assert_eq!(limit["schema"]["default"], 5);
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_paginate_bad_endpoint_limits() {
+    let endpoint: dropshot::ApiEndpoint<usize> = api_integers.into();
+    let endpoint = endpoint
+        .page_default_limit(NonZeroU32::new(50).unwrap())
+        .page_max_limit(NonZeroU32::new(20).unwrap());
+    let error = ApiDescription::new().register(endpoint).unwrap_err();
+    assert_eq!(
+        error,
+        "endpoint \"api_integers\" has a default page limit (50) greater than \
+         its maximum (20)"
+    );
+}
+
+#[test]
+#[should_panic(expected = "endpoint \"api_empty\" has no parameter \"limit\"")]
+fn test_paginate_limits_unpaginated() {
+    #[endpoint {
+        method = GET,
+        path = "/empty",
+        page_max_limit = 10,
+    }]
+    async fn api_empty(
+        _rqctx: RequestContext<usize>,
+    ) -> Result<HttpResponseOk<()>, HttpError> {
+        Ok(HttpResponseOk(()))
+    }
+
+    let _ = ApiDescription::new().register(api_empty);
+}
+
+// Tests for sealed page tokens
+
+/// Starts a server for `paginate_api()` that seals page tokens with `keys`, if
+/// any
+fn sealed_server(
+    keys: Option<PageTokenKeys>,
+) -> (HttpServer<usize>, ClientTestContext) {
+    let mut starter = HttpServerStarter::new(
+        &ConfigDropshot::default(),
+        paginate_api(),
+        None,
+        0usize,
+    )
+    .unwrap();
+    if let Some(keys) = keys {
+        starter = starter.page_token_keys(keys);
+    }
+    let server = starter.start();
+    let client = ClientTestContext::new(server.local_addr());
+    (server, client)
+}
+
+#[tokio::test]
+async fn test_paginate_sealed_tokens() {
+    let old_key = [1u8; 32];
+    let new_key = [2u8; 32];
+
+    // Get an ordinary token from a server that doesn't seal them.
+    let (plain_server, plain_client) = sealed_server(None);
+    let page = objects_list_page::<u16>(&plain_client, "/intapi?limit=3").await;
+    let plain_token = page.next_page.unwrap();
+
+    // Scanning works just as it does without sealing.
+    let (old_server, client) =
+        sealed_server(Some(PageTokenKeys::new(&old_key)));
+    let page = objects_list_page::<u16>(&client, "/intapi?limit=3").await;
+    assert_sequence_from(&page.items, 1, 3);
+    let sealed_token = page.next_page.unwrap();
+    let page = objects_list_page::<u16>(
+        &client,
+        &format!("/intapi?limit=3&page_token={}", sealed_token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 4, 3);
+    let (items, _) =
+        iter_collection::<u16>(&client, "/intapi", "", 10000).await;
+    assert_sequence_from(&items, 1, u16::MAX - 1);
+
+    // The selector can't be read out of a sealed token.
+    let decoded = URL_SAFE.decode(&sealed_token).unwrap();
+    assert!(!String::from_utf8_lossy(&decoded).contains("last_seen"));
+
+    // Tokens that weren't sealed with the server's keys are rejected, whether
+    // they're unsealed or altered.
+    let message = "unable to parse query string: failed to parse pagination \
+        token: corrupted token";
+    assert_error(
+        &client,
+        &format!("/intapi?page_token={}", plain_token),
+        message,
+    )
+    .await;
+    let mut tampered = decoded.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_error(
+        &client,
+        &format!("/intapi?page_token={}", URL_SAFE.encode(&tampered)),
+        message,
+    )
+    .await;
+
+    // Keys belong to the server that was given them: pages built elsewhere
+    // (outside of a handler, or for another server) aren't affected.
+    let page =
+        ResultsPage::new(vec![1u16], &EmptyScanParams {}, |n: &u16, _| *n)
+            .unwrap();
+    let decoded = URL_SAFE.decode(page.next_page.unwrap()).unwrap();
+    assert!(String::from_utf8_lossy(&decoded).contains("page_start"));
+
+    // After rotation, tokens sealed with the old key still work as long as
+    // it's kept as a previous key, and new tokens use the new key.
+    let (rotated_server, client) = sealed_server(Some(
+        PageTokenKeys::new(&new_key).with_previous(&old_key),
+    ));
+    let page = objects_list_page::<u16>(
+        &client,
+        &format!("/intapi?limit=3&page_token={}", sealed_token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 4, 3);
+    let new_token = page.next_page.unwrap();
+
+    let (new_server, client) =
+        sealed_server(Some(PageTokenKeys::new(&new_key)));
+    assert_error(
+        &client,
+        &format!("/intapi?page_token={}", sealed_token),
+        message,
+    )
+    .await;
+    let page = objects_list_page::<u16>(
+        &client,
+        &format!("/intapi?limit=3&page_token={}", new_token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 7, 3);
+
+    // A server that doesn't seal tokens still takes ordinary ones.
+    let page = objects_list_page::<u16>(
+        &plain_client,
+        &format!("/intapi?limit=3&page_token={}", plain_token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 4, 3);
+
+    for server in [plain_server, old_server, rotated_server, new_server] {
+        server.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_paginate_sealed_token_expiry() {
+    let clock = TestClock::new();
+    clock.freeze();
+    let keys = PageTokenKeys::new(&[1u8; 32]).with_ttl(Duration::from_secs(60));
+    let server = HttpServerStarter::new(
+        &ConfigDropshot::default(),
+        paginate_api(),
+        None,
+        0usize,
+    )
+    .unwrap()
+    .page_token_keys(keys)
+    .clock(Arc::new(clock.clone()))
+    .start();
+    let client =
+        ClientTestContext::new(server.local_addr()).with_clock(clock.clone());
+
+    // Tokens last for the TTL, by the server's clock.
+    let page = objects_list_page::<u16>(&client, "/intapi?limit=3").await;
+    let token = page.next_page.unwrap();
+    clock.advance(Duration::from_secs(59));
+    let page = objects_list_page::<u16>(
+        &client,
+        &format!("/intapi?limit=3&page_token={}", token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 4, 3);
+    let next_token = page.next_page.unwrap();
+
+    // Then they're rejected, though later tokens last longer.
+    clock.advance(Duration::from_secs(1));
+    assert_error(
+        &client,
+        &format!("/intapi?page_token={}", token),
+        "unable to parse query string: failed to parse pagination token: \
+         expired token",
+    )
+    .await;
+    let page = objects_list_page::<u16>(
+        &client,
+        &format!("/intapi?limit=3&page_token={}", next_token),
+    )
+    .await;
+    assert_sequence_from(&page.items, 7, 3);
+
+    server.close().await.unwrap();
+}
+
+// Tests for pagination by offset
+
+/// "/intapi_offset": the integers 1 through 1000, paginated by offset.
+#[endpoint {
+    method = GET,
+    path = "/intapi_offset",
+}]
+async fn api_integers_by_offset(
+    rqctx: RequestContext<usize>,
+    query: Query<OffsetPaginationParams>,
+) -> Result<HttpResponseOk<OffsetResultsPage<u16>>, HttpError> {
+    let pag_params = query.into_inner();
+    let limit = rqctx.offset_page_limit(&pag_params)?;
+    let items = (1..=1000u16)
+        .skip(usize::try_from(pag_params.offset()).unwrap_or(usize::MAX))
+        .take(limit.get() as usize)
+        .collect();
+
+    Ok(HttpResponseOk(OffsetResultsPage::new(items, &pag_params, limit)?))
+}
+
+#[tokio::test]
+async fn test_paginate_by_offset() {
+    let api = paginate_api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+
+    let page = object_get::<OffsetResultsPage<u16>>(
+        client,
+        "/intapi_offset?limit=10&offset=5",
+    )
+    .await;
+    assert_sequence_from(&page.items, 6, 10);
+    assert_eq!(page.next_offset, Some(15));
+
+    // Past the end of the collection, there's nothing left to fetch.
+    let page = object_get::<OffsetResultsPage<u16>>(
+        client,
+        "/intapi_offset?offset=995",
+    )
+    .await;
+    assert_sequence_from(&page.items, 996, 5);
+    assert_eq!(page.next_offset, None);
+
+    // Iterate the whole collection.  1000 items by 100 is 11 pages, since the
+    // last full page can't tell that nothing follows it.
+    let (items, npages) =
+        iter_collection_by_offset::<u16>(client, "/intapi_offset", "", 100)
+            .await;
+    assert_sequence_from(&items, 1, 1000);
+    assert_eq!(npages, 11);
+    let (items, npages) =
+        iter_collection_by_offset::<u16>(client, "/intapi_offset", "", 300)
+            .await;
+    assert_sequence_from(&items, 1, 1000);
+    assert_eq!(npages, 4);
+
+    assert_error(
+        client,
+        "/intapi_offset?offset=-1",
+        "unable to parse query string: invalid digit found in string",
+    )
+    .await;
+    assert_error(
+        client,
+        "/intapi_offset?limit=0",
+        "unable to parse query string: invalid value: integer `0`, \
+        expected a nonzero u32",
+    )
+    .await;
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_paginate_by_offset_openapi() {
+    let mut api = ApiDescription::<usize>::new();
+    api.register(api_integers_by_offset).unwrap();
+    let spec =
+        serde_json::to_value(api.openapi("test", "1.0.0").json().unwrap())
+            .unwrap();
+    let operation = &spec["paths"]["/intapi_offset"]["get"];
+
+    assert_eq!(
+        operation["x-dropshot-offset-pagination"],
+        serde_json::json!({})
+    );
+    assert!(operation.get("x-dropshot-pagination").is_none());
+    let names = operation["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["limit", "offset"]);
+    assert_eq!(
+        spec["components"]["schemas"]["uint16OffsetResultsPage"]["required"],
+        serde_json::json!(["items"])
+    );
+}
+
 // Tests for an empty collection
 
 /// "/empty": an empty collection of u16s, useful for testing the case where the
@@ -516,11 +1136,14 @@ async fn api_with_required_params(
         WhichPage::Next(IntegersPageSelector { last_seen }) => *last_seen,
     };
 
-    Ok(HttpResponseOk(ResultsPage::new(
-        range_u16(start, limit),
-        &EmptyScanParams {},
-        page_selector_for,
-    )?))
+    Ok(HttpResponseOk(
+        ResultsPage::new(
+            range_u16(start, limit),
+            &EmptyScanParams {},
+            page_selector_for,
+        )?
+        .seal(rqctx.page_token_keys())?,
+    ))
 }
 
 #[tokio::test]
@@ -969,3 +1592,44 @@ async fn test_example_multiple_resources() {
         );
     }
 }
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+struct ExampleEvent {
+    id: u32,
+    created_at: DateTime<Utc>,
+}
+
+/// Tests the "pagination-composite-sort" example.
+#[tokio::test]
+async fn test_example_composite_sort() {
+    let exctx = start_example("pagination-composite-sort", 12233).await;
+    let client = &exctx.client;
+
+    // By default, the newest events come first, with ties broken by ascending
+    // id.  Page breaks fall within groups of events created at the same time,
+    // so this only works if the marker includes the id.
+    let newest =
+        assert_collection_iter::<ExampleEvent>(client, "/events", "").await;
+    assert_eq!(newest.len(), 999);
+    newest.windows(2).for_each(|slice| {
+        assert!(
+            slice[0].created_at > slice[1].created_at
+                || (slice[0].created_at == slice[1].created_at
+                    && slice[0].id < slice[1].id)
+        );
+    });
+
+    let oldest = assert_collection_iter::<ExampleEvent>(
+        client,
+        "/events",
+        "order=oldest-first",
+    )
+    .await;
+    assert_eq!(oldest.len(), 999);
+    oldest.windows(2).for_each(|slice| {
+        assert!(
+            (slice[0].created_at, slice[0].id)
+                < (slice[1].created_at, slice[1].id)
+        );
+    });
+}
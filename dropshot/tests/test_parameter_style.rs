@@ -0,0 +1,208 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for parameters in styles other than single values
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ApiService;
+use dropshot::CommaDelimited;
+use dropshot::DeepObject;
+use dropshot::Exploded;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Path;
+use dropshot::PipeDelimited;
+use dropshot::Query;
+use dropshot::RequestContext;
+use http::StatusCode;
+use hyper::service::Service;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Filter {
+    kind: Option<String>,
+    size: Option<u32>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ThingsQuery {
+    id: Option<Exploded<u32>>,
+    fields: Option<CommaDelimited<String>>,
+    sizes: Option<PipeDelimited<u32>>,
+    filter: Option<DeepObject<Filter>>,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ThingsPath {
+    owners: CommaDelimited<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ThingsRequest {
+    owners: Vec<String>,
+    id: Option<Vec<u32>>,
+    fields: Option<Vec<String>>,
+    sizes: Option<Vec<u32>>,
+    filter: Option<Filter>,
+    limit: Option<u32>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/owners/{owners}/things",
+}]
+async fn list_things(
+    _rqctx: RequestContext<()>,
+    path: Path<ThingsPath>,
+    query: Query<ThingsQuery>,
+) -> Result<HttpResponseOk<ThingsRequest>, HttpError> {
+    let query = query.into_inner();
+    Ok(HttpResponseOk(ThingsRequest {
+        owners: path.into_inner().owners.into_inner(),
+        id: query.id.map(Exploded::into_inner),
+        fields: query.fields.map(CommaDelimited::into_inner),
+        sizes: query.sizes.map(PipeDelimited::into_inner),
+        filter: query.filter.map(DeepObject::into_inner),
+        limit: query.limit,
+    }))
+}
+
+fn api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(list_things).unwrap();
+    api
+}
+
+async fn get(uri: &str) -> (StatusCode, serde_json::Value) {
+    let mut service = ApiService::new(api(), ());
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_parameter_styles() {
+    let (status, body) =
+        get("/owners/alice,bob/things?id=3&limit=10&id=1&fields=name,size\
+         &sizes=1%7C2&filter%5Bkind%5D=small&filter%5Bsize%5D=2")
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "owners": ["alice", "bob"],
+            "id": [3, 1],
+            "fields": ["name", "size"],
+            "sizes": [1, 2],
+            "filter": { "kind": "small", "size": 2 },
+            "limit": 10
+        })
+    );
+
+    // Each style is the only way its parameter is accepted.
+    let (status, body) = get("/owners/alice/things?id=1,2").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("unable to parse query string"));
+    let (status, _) = get("/owners/alice/things?sizes=1,2").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get("/owners/alice/things?fields=a&fields=b").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_parameter_styles_openapi() {
+    let spec = api().openapi("things", "1.0.0").json().unwrap();
+    let parameters = spec["paths"]["/owners/{owners}/things"]["get"]
+        ["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|parameter| {
+            (
+                parameter["name"].as_str().unwrap(),
+                (parameter["style"].clone(), parameter["explode"].clone()),
+            )
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    // "form" and "simple" are the default styles for query and path
+    // parameters, so they're omitted.
+    let null = serde_json::Value::Null;
+    assert_eq!(parameters["owners"], (null.clone(), json!(false)));
+    assert_eq!(parameters["id"], (null.clone(), json!(true)));
+    assert_eq!(parameters["fields"], (null.clone(), json!(false)));
+    assert_eq!(parameters["sizes"], (json!("pipeDelimited"), json!(false)));
+    assert_eq!(parameters["filter"], (json!("deepObject"), json!(true)));
+    assert_eq!(parameters["limit"], (null.clone(), null));
+
+    // The sentinel that declares the style isn't part of the schema.
+    let id = spec["paths"]["/owners/{owners}/things"]["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|parameter| parameter["name"] == "id")
+        .unwrap();
+    assert_eq!(
+        id["schema"],
+        json!({
+            "nullable": true,
+            "type": "array",
+            "items": { "type": "integer", "format": "uint32", "minimum": 0 }
+        })
+    );
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UnstyledPath {
+    owners: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UnstyledQuery {
+    id: Option<Vec<u32>>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/unstyled/{owners}",
+}]
+async fn unstyled_path(
+    _rqctx: RequestContext<()>,
+    path: Path<UnstyledPath>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    Ok(HttpResponseOk(path.into_inner().owners))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/unstyled",
+}]
+async fn unstyled_query(
+    _rqctx: RequestContext<()>,
+    query: Query<UnstyledQuery>,
+) -> Result<HttpResponseOk<Option<Vec<u32>>>, HttpError> {
+    Ok(HttpResponseOk(query.into_inner().id))
+}
+
+#[test]
+fn test_parameter_styles_not_scalar() {
+    // Path and query parameters that are arrays or objects are accepted only
+    // when they have a style that says how to spell them.  `api()` registers
+    // styled arrays and objects in both the path and the query string.
+    let _ = api();
+
+    let mut api = ApiDescription::<()>::new();
+    let error = api.register(unstyled_path).unwrap_err();
+    assert!(error.contains("owners"), "unexpected error: {}", error);
+    let error = api.register(unstyled_query).unwrap_err();
+    assert!(error.contains("id"), "unexpected error: {}", error);
+}
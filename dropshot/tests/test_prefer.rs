@@ -0,0 +1,129 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the `Prefer` header.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::test_util::read_string;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseCreated;
+use dropshot::HttpResponsePreferred;
+use dropshot::RequestContext;
+use dropshot::HEADER_PREFER;
+use dropshot::HEADER_PREFERENCE_APPLIED;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod common;
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+struct Widget {
+    name: String,
+}
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(create_widget).unwrap();
+    api
+}
+
+#[endpoint {
+    method = POST,
+    path = "/widgets",
+}]
+async fn create_widget(
+    rqctx: RequestContext<usize>,
+) -> Result<HttpResponsePreferred<HttpResponseCreated<Widget>>, HttpError> {
+    if rqctx.preferences.respond_async {
+        return HttpResponsePreferred::accepted_async("/operations/1");
+    }
+    let widget = Widget { name: String::from("sprocket") };
+    Ok(HttpResponsePreferred::new(
+        &rqctx.preferences,
+        HttpResponseCreated(widget),
+    ))
+}
+
+async fn create(
+    testctx: &dropshot::test_util::TestContext<usize>,
+    prefer: Option<&str>,
+    expected_status: StatusCode,
+) -> Response<Body> {
+    let client =
+        testctx.client_testctx.with_allowed_header(HEADER_PREFERENCE_APPLIED);
+    let mut builder =
+        Request::builder().method(Method::POST).uri(client.url("/widgets"));
+    if let Some(prefer) = prefer {
+        builder = builder.header(HEADER_PREFER, prefer);
+    }
+    let request = builder.body(Body::empty()).unwrap();
+    client.make_request_with_request(request, expected_status).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_prefer() {
+    let testctx = common::test_setup(api());
+
+    // No preference: the whole representation.
+    let mut response = create(&testctx, None, StatusCode::CREATED).await;
+    assert!(response.headers().get(HEADER_PREFERENCE_APPLIED).is_none());
+    assert_eq!(
+        read_json::<Widget>(&mut response).await,
+        Widget { name: String::from("sprocket") }
+    );
+
+    // return=representation: the same, but we say so.
+    let mut response =
+        create(&testctx, Some("return=representation"), StatusCode::CREATED)
+            .await;
+    assert_eq!(
+        response.headers()[HEADER_PREFERENCE_APPLIED],
+        "return=representation"
+    );
+    assert_eq!(read_json::<Widget>(&mut response).await.name, "sprocket");
+
+    // return=minimal: no body.
+    let mut response =
+        create(&testctx, Some("return=minimal"), StatusCode::CREATED).await;
+    assert_eq!(response.headers()[HEADER_PREFERENCE_APPLIED], "return=minimal");
+    assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+    assert_eq!(read_string(&mut response).await, "");
+
+    // respond-async: 202 pointing at a status monitor.
+    let mut response =
+        create(&testctx, Some("respond-async, wait=10"), StatusCode::ACCEPTED)
+            .await;
+    assert_eq!(response.headers()[HEADER_PREFERENCE_APPLIED], "respond-async");
+    assert_eq!(response.headers()[header::LOCATION], "/operations/1");
+    assert_eq!(read_string(&mut response).await, "");
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_prefer_openapi() {
+    let spec = api().openapi("test", "1.0.0").json().unwrap();
+    let responses = &spec["paths"]["/widgets"]["post"]["responses"];
+    assert_eq!(
+        responses["201"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/Widget"
+    );
+    assert_eq!(
+        responses["202"],
+        serde_json::json!({
+            "description": "the operation was started asynchronously",
+            "headers": {
+                "Location": {
+                    "description": "status monitor for the operation",
+                    "style": "simple",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }
+            },
+        })
+    );
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for RFC 9457 ("problem details") error responses.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::ErrorResponseFormat;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::ProblemDetails;
+use dropshot::RequestContext;
+use http::{Method, StatusCode};
+use hyper::{Body, Request};
+
+pub mod common;
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new()
+        .error_response_format(ErrorResponseFormat::ProblemDetails);
+    api.register(teapot).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/teapot",
+}]
+async fn teapot(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Err(HttpError::for_client_error(
+        Some(String::from("Teapot")),
+        StatusCode::IM_A_TEAPOT,
+        String::from("short and stout"),
+    ))
+}
+
+#[tokio::test]
+async fn test_problem_details() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+
+    // Errors from handlers use the problem details format...
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(client.url("/teapot"))
+        .body(Body::empty())
+        .unwrap();
+    let mut response = client.client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "application/problem+json"
+    );
+    let request_id = response.headers()[dropshot::HEADER_REQUEST_ID]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "type": "about:blank",
+            "title": "I'm a teapot",
+            "status": 418,
+            "detail": "short and stout",
+            "instance": "/teapot",
+            "request_id": request_id,
+            "error_code": "Teapot",
+        })
+    );
+
+    // ... as do errors generated by Dropshot itself.
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(client.url("/nothing"))
+        .body(Body::empty())
+        .unwrap();
+    let mut response = client.client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let error: ProblemDetails = read_json(&mut response).await;
+    assert_eq!(error.detail, "Not Found");
+    assert_eq!(error.status, 404);
+    assert_eq!(error.instance.as_deref(), Some("/nothing"));
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_problem_details_openapi() {
+    let spec = api().openapi("test", "1.0.0").json().unwrap();
+    let content = &spec["components"]["responses"]["Error"]["content"];
+    assert_eq!(
+        content,
+        &serde_json::json!({
+            "application/problem+json": {
+                "schema": { "$ref": "#/components/schemas/ProblemDetails" },
+            },
+        })
+    );
+    assert!(spec["components"]["schemas"].get("Error").is_none());
+    let schema = &spec["components"]["schemas"]["ProblemDetails"];
+    assert_eq!(
+        schema["required"],
+        serde_json::json!(["detail", "request_id", "status", "type"])
+    );
+    assert_eq!(schema["properties"]["status"]["type"], "integer");
+}
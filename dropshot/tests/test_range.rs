@@ -0,0 +1,159 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for range requests.
+
+use dropshot::endpoint;
+use dropshot::test_util::read_string;
+use dropshot::test_util::ClientTestContext;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponsePartialContent;
+use dropshot::Range;
+use dropshot::RequestContext;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use std::io::Write;
+
+pub mod common;
+
+const CONTENT: &str = "the quick brown fox jumps over the lazy dog";
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_bytes).unwrap();
+    api.register(get_file).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/bytes",
+}]
+async fn get_bytes(
+    _rqctx: RequestContext<usize>,
+    range: Range,
+) -> Result<HttpResponsePartialContent, HttpError> {
+    HttpResponsePartialContent::from_bytes(&range, "text/plain", CONTENT.into())
+}
+
+#[endpoint {
+    method = GET,
+    path = "/file",
+}]
+async fn get_file(
+    _rqctx: RequestContext<usize>,
+    range: Range,
+) -> Result<HttpResponsePartialContent, HttpError> {
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(CONTENT.as_bytes()).unwrap();
+    HttpResponsePartialContent::from_file(
+        &range,
+        "text/plain",
+        tokio::fs::File::from_std(file),
+    )
+    .await
+}
+
+/// Returns a client that accepts the headers of range responses
+fn range_client(testctx: &TestContext<usize>) -> ClientTestContext {
+    testctx
+        .client_testctx
+        .with_allowed_header(header::ACCEPT_RANGES.as_str())
+        .with_allowed_header(header::CONTENT_RANGE.as_str())
+}
+
+async fn get_range(
+    testctx: &TestContext<usize>,
+    path: &str,
+    range: Option<&str>,
+    expected_status: StatusCode,
+) -> Response<Body> {
+    let client = range_client(testctx);
+    let mut builder =
+        Request::builder().method(Method::GET).uri(client.url(path));
+    if let Some(range) = range {
+        builder = builder.header(header::RANGE, range);
+    }
+    let request = builder.body(Body::empty()).unwrap();
+    match client.make_request_with_request(request, expected_status).await {
+        Ok(response) => response,
+        Err(error) => panic!("unexpected error response: {:?}", error),
+    }
+}
+
+async fn check_ranges(path: &str) {
+    let testctx = common::test_setup(api());
+
+    // No range: the whole thing.
+    let mut response = get_range(&testctx, path, None, StatusCode::OK).await;
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert!(response.headers().get(header::CONTENT_RANGE).is_none());
+    assert_eq!(read_string(&mut response).await, CONTENT);
+
+    // A range in the middle.
+    let mut response = get_range(
+        &testctx,
+        path,
+        Some("bytes=4-8"),
+        StatusCode::PARTIAL_CONTENT,
+    )
+    .await;
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 4-8/43");
+    assert_eq!(read_string(&mut response).await, "quick");
+
+    // An open-ended range that runs past the end.
+    let mut response = get_range(
+        &testctx,
+        path,
+        Some("bytes=40-100"),
+        StatusCode::PARTIAL_CONTENT,
+    )
+    .await;
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 40-42/43");
+    assert_eq!(read_string(&mut response).await, "dog");
+
+    // A suffix.
+    let mut response = get_range(
+        &testctx,
+        path,
+        Some("bytes=-8"),
+        StatusCode::PARTIAL_CONTENT,
+    )
+    .await;
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 35-42/43");
+    assert_eq!(read_string(&mut response).await, "lazy dog");
+
+    // Multiple ranges are ignored.
+    let mut response =
+        get_range(&testctx, path, Some("bytes=0-2,4-8"), StatusCode::OK).await;
+    assert_eq!(read_string(&mut response).await, CONTENT);
+
+    // A range that starts past the end can't be satisfied.  (This response
+    // has no body, so it doesn't pass the checks that `ClientTestContext`
+    // applies to error responses.)
+    let client = range_client(&testctx);
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(client.url(path))
+        .header(header::RANGE, "bytes=43-")
+        .body(Body::empty())
+        .unwrap();
+    let mut response = client.client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */43");
+    assert_eq!(read_string(&mut response).await, "");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_range_bytes() {
+    check_ranges("/bytes").await;
+}
+
+#[tokio::test]
+async fn test_range_file() {
+    check_ranges("/file").await;
+}
@@ -0,0 +1,121 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Tests for exporting an API's schemas as JSON Schemas and TypeScript
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::TypedBody;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// A project, which holds things
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Project {
+    name: String,
+    description: Option<String>,
+    kind: ProjectKind,
+    parent_kind: Option<ProjectKind>,
+    members: Vec<String>,
+    labels: HashMap<String, String>,
+}
+
+/// How big a project is
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ProjectKind {
+    Small,
+    Large,
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/project",
+}]
+async fn put_project(
+    _rqctx: RequestContext<()>,
+    body: TypedBody<Project>,
+) -> Result<HttpResponseOk<Project>, HttpError> {
+    Ok(HttpResponseOk(body.into_inner()))
+}
+
+fn api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(put_project).unwrap();
+    api
+}
+
+#[test]
+fn test_export_json_schemas() {
+    let schemas = api().openapi("projects", "1.0.0").json_schemas().unwrap();
+    assert_eq!(
+        schemas.keys().collect::<Vec<_>>(),
+        ["Error.json", "Project.json", "ProjectKind.json"]
+    );
+
+    let project = &schemas["Project.json"];
+    assert_eq!(project["$schema"], "http://json-schema.org/draft-07/schema#");
+    assert_eq!(project["$id"], "Project.json");
+    assert_eq!(project["title"], "Project");
+    assert_eq!(project["description"], "A project, which holds things");
+    let properties = &project["properties"];
+    assert_eq!(
+        properties["description"],
+        json!({ "type": ["string", "null"] })
+    );
+    assert_eq!(properties["kind"], json!({ "$ref": "ProjectKind.json" }));
+    assert_eq!(
+        properties["parent_kind"],
+        json!({
+            "anyOf": [
+                { "allOf": [{ "$ref": "ProjectKind.json" }] },
+                { "type": "null" }
+            ]
+        })
+    );
+
+    let kind = &schemas["ProjectKind.json"];
+    assert_eq!(kind["enum"], json!(["small", "large"]));
+}
+
+#[test]
+fn test_export_typescript() {
+    let mut out = Vec::new();
+    api().openapi("projects", "1.0.0").write_typescript(&mut out).unwrap();
+    let types = String::from_utf8(out).unwrap();
+    assert_eq!(
+        types,
+        "// Types for projects (version 1.0.0)\n\
+         // This file is generated from the API's OpenAPI definition.\n\
+         \n\
+         /**\n \
+         * Error information from a response.\n \
+         */\n\
+         export interface Error {\n    \
+             error_code?: string;\n    \
+             message: string;\n    \
+             request_id: string;\n\
+         }\n\
+         \n\
+         /**\n \
+         * A project, which holds things\n \
+         */\n\
+         export interface Project {\n    \
+             description?: string | null;\n    \
+             kind: ProjectKind;\n    \
+             labels: { [key: string]: string };\n    \
+             members: string[];\n    \
+             name: string;\n    \
+             parent_kind?: ProjectKind | null;\n\
+         }\n\
+         \n\
+         /**\n \
+         * How big a project is\n \
+         */\n\
+         export type ProjectKind = \"small\" | \"large\";\n"
+    );
+}
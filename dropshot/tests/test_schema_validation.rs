@@ -0,0 +1,138 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for validating bodies against the API's schemas
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ApiService;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigSchemaValidation;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::TypedBody;
+use http::StatusCode;
+use hyper::service::Service;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Project {
+    name: String,
+    size: u32,
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/project",
+}]
+async fn put_project(
+    _rqctx: RequestContext<()>,
+    body: TypedBody<Project>,
+) -> Result<HttpResponseOk<Project>, HttpError> {
+    Ok(HttpResponseOk(body.into_inner()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/project",
+}]
+async fn get_project(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Project>, HttpError> {
+    Ok(HttpResponseOk(Project { name: String::from("too long"), size: 1 }))
+}
+
+/// Returns a service whose schemas are stricter than its types: project
+/// names are limited to 5 characters, which the handlers don't know about
+fn service(schema_validation: ConfigSchemaValidation) -> ApiService<()> {
+    let mut api = ApiDescription::new().schema_visitor(|name, schema| {
+        if name == "Project" {
+            schema["properties"]["name"]["maxLength"] = json!(5);
+        }
+    });
+    api.register(put_project).unwrap();
+    api.register(get_project).unwrap();
+    let config = ConfigDropshot { schema_validation, ..Default::default() };
+    ApiService::new_with_config(&config, api, None, ())
+}
+
+async fn call(
+    service: &mut ApiService<()>,
+    request: Request<Body>,
+) -> (StatusCode, serde_json::Value) {
+    let response = service.call(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn put(project: serde_json::Value) -> Request<Body> {
+    Request::put("/project")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(project.to_string()))
+        .unwrap()
+}
+
+fn get() -> Request<Body> {
+    Request::get("/project").body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_schema_validation_enforce() {
+    let mut service = service(ConfigSchemaValidation::Enforce);
+
+    let (status, body) =
+        call(&mut service, put(json!({ "name": "small", "size": 3 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "small", "size": 3 }));
+
+    let (status, body) =
+        call(&mut service, put(json!({ "name": "bigger", "size": -1 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "request body does not match its schema: \
+         $.name: longer than 5 characters; $.size: -1 is less than 0"
+    );
+
+    // Bodies that aren't even JSON are left to the extractor.
+    let request = Request::put("/project")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from("{"))
+        .unwrap();
+    let (status, body) = call(&mut service, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("unable to parse JSON body"));
+
+    let (status, body) = call(&mut service, get()).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["message"], "Internal Server Error");
+}
+
+#[tokio::test]
+async fn test_schema_validation_log() {
+    let mut service = service(ConfigSchemaValidation::Log);
+
+    let (status, body) =
+        call(&mut service, put(json!({ "name": "bigger", "size": 3 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "bigger", "size": 3 }));
+
+    let (status, body) = call(&mut service, get()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "too long", "size": 1 }));
+}
+
+#[tokio::test]
+async fn test_schema_validation_disabled() {
+    let mut service = service(ConfigSchemaValidation::default());
+    let (status, _) =
+        call(&mut service, put(json!({ "name": "bigger", "size": 3 }))).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,135 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for inspecting and quiescing a running server.
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use http::{Method, StatusCode};
+
+pub mod common;
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_thing).unwrap();
+    api.register(get_bad).unwrap();
+    api.register(get_broken).unwrap();
+    // The same operation at another path is counted separately.
+    let mut other_thing: ApiEndpoint<usize> = get_thing.into();
+    other_thing.path = String::from("/other-thing");
+    api.register(other_thing).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/thing",
+}]
+async fn get_thing(
+    rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<usize>, HttpError> {
+    Ok(HttpResponseOk(rqctx.server.request_in_flight_count()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/bad",
+}]
+async fn get_bad(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Err(HttpError::for_bad_request(None, String::from("bad")))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/broken",
+}]
+async fn get_broken(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Err(HttpError::for_internal_error(String::from("broken")))
+}
+
+#[tokio::test]
+async fn test_server_inspection() {
+    let testctx = common::test_setup(api());
+    let server = &testctx.server;
+    let client = &testctx.client_testctx;
+
+    assert_eq!(server.request_in_flight_count(), 0);
+    assert!(!server.is_quiescing());
+    let uptime = server.uptime();
+
+    client
+        .make_request_no_body(Method::GET, "/thing", StatusCode::OK)
+        .await
+        .unwrap();
+    client
+        .make_request_no_body(Method::GET, "/thing", StatusCode::OK)
+        .await
+        .unwrap();
+    client
+        .make_request_no_body(Method::GET, "/bad", StatusCode::BAD_REQUEST)
+        .await
+        .unwrap_err();
+    client
+        .make_request_no_body(
+            Method::GET,
+            "/broken",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .await
+        .unwrap_err();
+    // Requests that don't match any endpoint aren't counted by route.
+    client
+        .make_request_no_body(Method::GET, "/nothing", StatusCode::NOT_FOUND)
+        .await
+        .unwrap_err();
+
+    assert!(server.uptime() > uptime);
+    assert!(server.connection_count() >= 1);
+    assert_eq!(server.request_in_flight_count(), 0);
+
+    let stats = server.route_stats();
+    let paths: Vec<_> = stats.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(paths, ["/bad", "/broken", "/other-thing", "/thing"]);
+    let thing = &stats[3];
+    assert_eq!(thing.method, "GET");
+    assert_eq!(thing.operation_id, "get_thing");
+    assert_eq!(thing.versions, "all versions");
+    assert_eq!(thing.requests, 2);
+    assert_eq!(thing.in_flight, 0);
+    assert_eq!(thing.client_errors, 0);
+    assert_eq!(thing.server_errors, 0);
+    assert_eq!((stats[0].requests, stats[0].client_errors), (1, 1));
+    assert_eq!((stats[1].requests, stats[1].server_errors), (1, 1));
+    assert_eq!(stats[2].operation_id, "get_thing");
+    assert_eq!(stats[2].requests, 0);
+
+    // While quiescing, every request is rejected, but the server keeps
+    // running and can be brought back.
+    server.set_quiescing(true);
+    assert!(server.is_quiescing());
+    let error = client
+        .make_request_error(
+            Method::GET,
+            "/thing",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .await;
+    assert_eq!(error.message, "Service Unavailable");
+    assert_eq!(server.route_stats()[3].requests, 2);
+
+    server.set_quiescing(false);
+    client
+        .make_request_no_body(Method::GET, "/thing", StatusCode::OK)
+        .await
+        .unwrap();
+    assert_eq!(server.route_stats()[3].requests, 3);
+
+    testctx.teardown().await;
+}
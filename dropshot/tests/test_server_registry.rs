@@ -0,0 +1,98 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the registry of running servers.
+
+#![cfg(feature = "server-registry")]
+
+use dropshot::endpoint;
+use dropshot::running_servers;
+use dropshot::running_servers_endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use http::{Method, StatusCode};
+use hyper::Body;
+use std::net::SocketAddr;
+
+pub mod common;
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_thing).unwrap();
+    api
+}
+
+fn admin_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(running_servers_endpoint("/servers")).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/thing",
+}]
+async fn get_thing(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("thing")))
+}
+
+#[tokio::test]
+async fn test_server_registry() {
+    let testctx = common::test_setup(api());
+    let admin_testctx = common::test_setup(admin_api());
+    let addr = testctx.server.local_addr();
+    let admin_addr = admin_testctx.server.local_addr();
+
+    testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/thing", StatusCode::OK)
+        .await
+        .unwrap();
+
+    // Other tests in this process may have servers running, too, so we only
+    // look at the ones we started.
+    let servers = running_servers();
+    let server = servers.iter().find(|s| s.local_addr == addr).unwrap();
+    let admin_server =
+        servers.iter().find(|s| s.local_addr == admin_addr).unwrap();
+    assert!(server.id < admin_server.id);
+    assert!(!server.tls);
+    assert!(!server.draining);
+    assert_eq!(server.nroutes, 1);
+    assert_eq!(server.requests_total, 1);
+    assert_eq!(server.requests_in_flight, 0);
+    assert_eq!(admin_server.requests_total, 0);
+
+    // The admin endpoint reports the same thing, counting its own request.
+    let mut response = admin_testctx
+        .client_testctx
+        .make_request_with_body(
+            Method::GET,
+            "/servers",
+            Body::empty(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let servers = read_json::<Vec<serde_json::Value>>(&mut response).await;
+    let admin_server = servers
+        .iter()
+        .find(|s| {
+            s["local_addr"].as_str().unwrap().parse::<SocketAddr>().unwrap()
+                == admin_addr
+        })
+        .unwrap();
+    assert_eq!(admin_server["requests_total"], 1);
+    assert_eq!(admin_server["requests_in_flight"], 1);
+    assert!(servers.iter().any(|s| s["local_addr"] == addr.to_string()));
+
+    // Servers disappear once they've been shut down.
+    testctx.teardown().await;
+    assert!(running_servers().iter().all(|s| s.local_addr != addr));
+    admin_testctx.teardown().await;
+    assert!(running_servers().iter().all(|s| s.local_addr != admin_addr));
+}
@@ -0,0 +1,105 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the `Server-Timing` response header.
+
+use dropshot::endpoint;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigServerTiming;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::HEADER_SERVER_TIMING;
+use dropshot::HEADER_SERVER_TIMING_REQUESTED;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+
+pub mod common;
+
+fn api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(get_widget).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/widget",
+}]
+async fn get_widget(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+fn setup(server_timing: ConfigServerTiming) -> TestContext<()> {
+    let config = ConfigDropshot { server_timing, ..Default::default() };
+    let mut testctx = TestContext::new(api(), (), &config);
+    testctx.client_testctx =
+        testctx.client_testctx.with_allowed_header(HEADER_SERVER_TIMING);
+    testctx
+}
+
+async fn get_widget_timing(
+    testctx: &TestContext<()>,
+    requested: bool,
+) -> Option<String> {
+    let client = &testctx.client_testctx;
+    let mut builder =
+        Request::builder().method(Method::GET).uri(client.url("/widget"));
+    if requested {
+        builder = builder.header(HEADER_SERVER_TIMING_REQUESTED, "1");
+    }
+    let request = builder.body(Body::empty()).unwrap();
+    let response: Response<Body> = client
+        .make_request_with_request(request, StatusCode::OK)
+        .await
+        .unwrap();
+    response
+        .headers()
+        .get(HEADER_SERVER_TIMING)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_server_timing_disabled() {
+    let testctx = setup(ConfigServerTiming::default());
+    assert_eq!(get_widget_timing(&testctx, false).await, None);
+    assert_eq!(get_widget_timing(&testctx, true).await, None);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_server_timing_on_request() {
+    let testctx =
+        setup(ConfigServerTiming { on_request: true, sample_interval: 0 });
+    assert_eq!(get_widget_timing(&testctx, false).await, None);
+
+    let timing = get_widget_timing(&testctx, true).await.unwrap();
+    let phases = timing
+        .split(", ")
+        .map(|metric| {
+            let (name, duration) = metric.split_once(";dur=").unwrap();
+            assert!(duration.parse::<f64>().unwrap() >= 0.0);
+            name
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(phases, ["route", "extract", "handler", "serialize"]);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_server_timing_sampled() {
+    let testctx =
+        setup(ConfigServerTiming { on_request: false, sample_interval: 2 });
+    let mut nsampled = 0;
+    for _ in 0..6 {
+        if get_widget_timing(&testctx, false).await.is_some() {
+            nsampled += 1;
+        }
+    }
+    assert_eq!(nsampled, 3);
+    testctx.teardown().await;
+}
@@ -0,0 +1,244 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the behavior of a server while it's shutting down.
+
+use dropshot::test_util::TestContext;
+use dropshot::{
+    endpoint, ApiDescription, ConfigDropshot, HandlerTaskMode, HttpError,
+    HttpResponseOk, HttpServerStarter, RequestContext,
+};
+use http::{Method, StatusCode};
+use hyper::{Body, Request};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod common;
+
+fn api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(ready).unwrap();
+    api
+}
+
+#[endpoint {
+    method = GET,
+    path = "/ready",
+}]
+async fn ready(
+    rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    if rqctx.server.is_draining() {
+        Err(HttpError::for_unavail(None, String::from("draining")))
+    } else {
+        Ok(HttpResponseOk(()))
+    }
+}
+
+#[tokio::test]
+async fn test_lame_duck_period() {
+    let config =
+        ConfigDropshot { lame_duck_period_ms: 2000, ..Default::default() };
+    let testctx = TestContext::new(api(), (), &config);
+    let client = testctx.client_testctx.clone();
+
+    // Before shutdown, we're ready and connections may be reused.
+    let response = client
+        .make_request_no_body(Method::GET, "/ready", StatusCode::OK)
+        .await
+        .expect("expected success");
+    assert!(response.headers().get(http::header::CONNECTION).is_none());
+
+    let teardown_task = tokio::spawn(testctx.teardown());
+
+    // Once shutdown has been requested, requests are still served for the
+    // lame-duck period, but readiness fails and clients are told to close
+    // their connections.
+    let response = loop {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(client.url("/ready"))
+            .body(Body::empty())
+            .unwrap();
+        let response = client.client.request(request).await.unwrap();
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            break response;
+        }
+        assert!(!teardown_task.is_finished());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(
+        response.headers().get(http::header::CONNECTION).unwrap(),
+        "close"
+    );
+    assert!(!teardown_task.is_finished());
+
+    teardown_task.await.unwrap();
+}
+
+#[endpoint {
+    method = GET,
+    path = "/hang",
+}]
+async fn hang(
+    rqctx: RequestContext<HangState>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    let state = rqctx.context();
+    state.started.store(true, Ordering::SeqCst);
+    // Record when this handler is cancelled.
+    let cancelled = Arc::clone(&state.cancelled);
+    let _guard = scopeguard::guard((), move |_| {
+        cancelled.store(true, Ordering::SeqCst);
+    });
+    futures::future::pending().await
+}
+
+#[derive(Default)]
+struct HangState {
+    started: AtomicBool,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[tokio::test]
+async fn test_shutdown_timeout() {
+    let mut api = ApiDescription::new();
+    api.register(hang).unwrap();
+    let state = HangState::default();
+    let cancelled = Arc::clone(&state.cancelled);
+    let config = ConfigDropshot {
+        default_handler_task_mode: HandlerTaskMode::CancelOnDisconnect,
+        ..Default::default()
+    };
+    let server =
+        HttpServerStarter::new(&config, api, None, state).unwrap().start();
+    let url = format!("http://{}/hang", server.local_addr());
+
+    // Start a request that will never finish on its own.
+    let client = hyper::Client::new();
+    let request = tokio::spawn(client.get(url.parse().unwrap()));
+    while !server.app_private().started.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let error = server
+        .close_with_timeout(Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert_eq!(error, "graceful shutdown timed out with 1 requests in flight");
+
+    // The connection was closed, cancelling the handler.
+    request.await.unwrap().unwrap_err();
+    assert!(cancelled.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_shutdown_timeout_idle() {
+    let config = ConfigDropshot {
+        shutdown_timeout_ms: Some(60_000),
+        ..Default::default()
+    };
+    let testctx = TestContext::new(api(), (), &config);
+    testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/ready", StatusCode::OK)
+        .await
+        .expect("expected success");
+
+    // With nothing in progress, shutdown finishes right away.
+    tokio::time::timeout(Duration::from_secs(10), testctx.teardown())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_signal() {
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server =
+        HttpServerStarter::new(&ConfigDropshot::default(), api(), None, ())
+            .unwrap()
+            .shutdown_signal(async {
+                let _ = rx.await;
+            })
+            .start();
+    let url = format!("http://{}/ready", server.local_addr());
+    let response =
+        hyper::Client::new().get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let shutdown = server.wait_for_shutdown();
+    assert!(tokio::time::timeout(Duration::from_millis(100), shutdown)
+        .await
+        .is_err());
+
+    // Completing the signal shuts the server down without `close()`.
+    tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_signal_timeout() {
+    let mut api = ApiDescription::new();
+    api.register(hang).unwrap();
+    let state = HangState::default();
+    let config = ConfigDropshot {
+        default_handler_task_mode: HandlerTaskMode::CancelOnDisconnect,
+        shutdown_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = HttpServerStarter::new(&config, api, None, state)
+        .unwrap()
+        .shutdown_signal(async {
+            let _ = rx.await;
+        })
+        .start();
+    let url = format!("http://{}/hang", server.local_addr());
+
+    let client = hyper::Client::new();
+    let request = tokio::spawn(client.get(url.parse().unwrap()));
+    while !server.app_private().started.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The configured timeout applies to shutdown triggered by the signal.
+    tx.send(()).unwrap();
+    let error = server.await.unwrap_err();
+    assert_eq!(error, "graceful shutdown timed out with 1 requests in flight");
+    request.await.unwrap().unwrap_err();
+}
+
+#[tokio::test]
+async fn test_listener_handoff() {
+    let old_server =
+        HttpServerStarter::new(&ConfigDropshot::default(), api(), None, ())
+            .unwrap()
+            .start();
+    let url = format!("http://{}/ready", old_server.local_addr());
+
+    // The new server (which would usually be in another process) serves the
+    // same socket as the old one.
+    let mut listeners = old_server.try_clone_listeners().unwrap();
+    assert_eq!(listeners.len(), 1);
+    let new_server = HttpServerStarter::new_with_listener(
+        &ConfigDropshot::default(),
+        api(),
+        None,
+        (),
+        None,
+        listeners.remove(0),
+    )
+    .unwrap()
+    .start();
+    assert_eq!(new_server.local_addr(), old_server.local_addr());
+
+    // Once the old server has shut down, connections are still accepted.
+    old_server.close().await.unwrap();
+    let response =
+        hyper::Client::new().get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    new_server.close().await.unwrap();
+}
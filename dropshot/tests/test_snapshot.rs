@@ -0,0 +1,146 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for the snapshot facilities in `test_util`.
+
+use chrono::{DateTime, Utc};
+use dropshot::endpoint;
+use dropshot::semver::Version;
+use dropshot::test_util::assert_json_snapshot;
+use dropshot::test_util::assert_openapi_compatible;
+use dropshot::test_util::assert_openapi_file;
+use dropshot::test_util::assert_openapi_snapshot;
+use dropshot::test_util::object_get;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use http::{Method, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod common;
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct Widget {
+    name: String,
+    created: DateTime<Utc>,
+}
+
+fn api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_widget).unwrap();
+    api
+}
+
+/// Fetch the one widget.
+#[endpoint {
+    method = GET,
+    path = "/widget",
+}]
+async fn get_widget(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<Widget>, HttpError> {
+    Ok(HttpResponseOk(Widget {
+        name: String::from("sprocket"),
+        created: Utc::now(),
+    }))
+}
+
+/// Returns an API with versions 1.0.0 and 2.0.0, which has gadgets only from
+/// version 2.0.0 on (and gadgets only if `gadgets` is set)
+fn versioned_api(gadgets: bool) -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(get_widget).unwrap();
+    if gadgets {
+        api.register(get_gadget).unwrap();
+    }
+    api.api_versions([Version::new(1, 0, 0), Version::new(2, 0, 0)])
+}
+
+/// Fetch the one gadget.
+#[endpoint {
+    method = GET,
+    path = "/gadget",
+    versions = { from = "2.0.0" },
+}]
+async fn get_gadget(
+    _rqctx: RequestContext<usize>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("gizmo")))
+}
+
+#[tokio::test]
+async fn test_snapshot_responses() {
+    let testctx = common::test_setup(api());
+    let client = &testctx.client_testctx;
+
+    // The timestamp changes on every request, so it's redacted.
+    let widget = object_get::<Widget>(client, "/widget").await;
+    assert_json_snapshot("test_snapshot_widget", &widget);
+
+    // So does the request id of an error.
+    let error = client
+        .make_request_error(Method::GET, "/nonexistent", StatusCode::NOT_FOUND)
+        .await;
+    assert_json_snapshot("test_snapshot_not_found", &error);
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_snapshot_openapi() {
+    assert_openapi_snapshot("test_snapshot_openapi", &api());
+}
+
+#[test]
+fn test_snapshot_openapi_file() {
+    // The document keeps its own title, version, and other metadata.
+    assert_openapi_file(
+        "tests/snapshots/test_snapshot_openapi_file.json",
+        api().openapi("Widget API", "1.2.3").description("All about widgets"),
+    );
+}
+
+#[test]
+fn test_snapshot_openapi_versions() {
+    // Each version gets its own snapshot.
+    assert_openapi_snapshot(
+        "test_snapshot_openapi_versions",
+        &versioned_api(true),
+    );
+}
+
+#[test]
+fn test_openapi_compatible_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    versioned_api(true).write_openapi_all_versions("old", dir.path()).unwrap();
+    assert_openapi_compatible(dir.path(), &versioned_api(true));
+
+    // New versions have nothing to be compatible with.
+    assert_openapi_compatible(
+        dir.path(),
+        &versioned_api(true).api_versions([Version::new(3, 0, 0)]),
+    );
+}
+
+#[test]
+#[should_panic(expected = "version 2.0.0: GET /gadget: operation was removed")]
+fn test_openapi_incompatible_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    versioned_api(true).write_openapi_all_versions("old", dir.path()).unwrap();
+    assert_openapi_compatible(dir.path(), &versioned_api(false));
+}
+
+#[test]
+#[should_panic(expected = "version 1.0.0: removed")]
+fn test_openapi_removed_version() {
+    let dir = tempfile::tempdir().unwrap();
+    versioned_api(true).write_openapi_all_versions("old", dir.path()).unwrap();
+    let mut api = ApiDescription::new();
+    api.register(get_widget).unwrap();
+    api.register(get_gadget).unwrap();
+    assert_openapi_compatible(
+        dir.path(),
+        &api.api_versions([Version::new(2, 0, 0)]),
+    );
+}
@@ -2,10 +2,17 @@
 
 //! Test cases for streaming requests.
 
-use dropshot::{endpoint, ApiDescription, HttpError, RequestContext};
+use bytes::Bytes;
+use dropshot::test_util::{next_chunk_within, read_events};
+use dropshot::{
+    endpoint, ApiDescription, HttpError, RequestContext, StreamedBody,
+};
+use futures::StreamExt;
 use http::{Method, Response, StatusCode};
 use hyper::{body::HttpBody, Body};
 use hyper_staticfile::FileBytesStream;
+use std::convert::Infallible;
+use std::time::Duration;
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 pub mod common;
@@ -14,6 +21,8 @@ fn api() -> ApiDescription<usize> {
     let mut api = ApiDescription::new();
     api.register(api_streaming).unwrap();
     api.register(api_not_streaming).unwrap();
+    api.register(api_ndjson).unwrap();
+    api.register(api_sse).unwrap();
     api
 }
 
@@ -62,6 +71,52 @@ async fn api_not_streaming(
         .body(serde_json::to_string("not-streaming").unwrap().into())?)
 }
 
+/// Returns a response whose body is `chunks`, with a pause before each one
+/// after the first
+fn delayed_response(
+    content_type: &str,
+    chunks: &'static [&'static str],
+) -> Result<Response<Body>, HttpError> {
+    let chunks = futures::stream::iter(chunks.iter().copied().enumerate())
+        .then(|(i, chunk)| async move {
+            if i > 0 {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            Ok::<_, Infallible>(Bytes::from(chunk))
+        });
+    Ok(StreamedBody::new(chunks).into_response(
+        Response::builder().header(http::header::CONTENT_TYPE, content_type),
+    )?)
+}
+
+#[endpoint {
+    method = GET,
+    path = "/ndjson",
+}]
+async fn api_ndjson(
+    _rqctx: RequestContext<usize>,
+) -> Result<Response<Body>, HttpError> {
+    // The last value is split across chunks and has no trailing newline.
+    delayed_response(dropshot::CONTENT_TYPE_NDJSON, &["0\n", "1\n\n2", "3"])
+}
+
+#[endpoint {
+    method = GET,
+    path = "/sse",
+}]
+async fn api_sse(
+    _rqctx: RequestContext<usize>,
+) -> Result<Response<Body>, HttpError> {
+    delayed_response(
+        dropshot::CONTENT_TYPE_EVENT_STREAM,
+        &[
+            ": comment\ndata: 0\n\n",
+            "event: update\nid: 1\ndata: [1,\r\ndata:2]\r\n\r\ndata",
+            ": \"done\"\n\n",
+        ],
+    )
+}
+
 fn check_has_transfer_encoding(
     response: &Response<Body>,
     expected_value: Option<&str>,
@@ -151,3 +206,44 @@ async fn test_non_streaming_servers_do_not_use_transfer_encoding() {
     check_has_transfer_encoding(&response, None);
     testctx.teardown().await;
 }
+
+#[tokio::test]
+async fn test_streaming_events() {
+    let api = api();
+    let testctx = common::test_setup(api);
+    let client = &testctx.client_testctx;
+    let timeout = Duration::from_secs(5);
+
+    let mut response = client
+        .make_request_no_body(Method::GET, "/ndjson", StatusCode::OK)
+        .await
+        .expect("Expected GET request to succeed");
+    let mut events = read_events::<u32>(&mut response);
+    assert_eq!(events.next_within(timeout).await, 0);
+    events.assert_none_within(Duration::from_millis(100)).await;
+    assert_eq!(events.next_within(timeout).await, 1);
+    assert_eq!(events.next_within(timeout).await, 23);
+    events.assert_end_within(timeout).await;
+
+    let mut response = client
+        .make_request_no_body(Method::GET, "/sse", StatusCode::OK)
+        .await
+        .expect("Expected GET request to succeed");
+    let mut events = read_events::<serde_json::Value>(&mut response);
+    assert_eq!(events.next_within(timeout).await, 0);
+    events.assert_none_within(Duration::from_millis(100)).await;
+    assert_eq!(events.next_within(timeout).await, serde_json::json!([1, 2]));
+    assert_eq!(events.next_within(timeout).await, "done");
+    events.assert_end_within(timeout).await;
+
+    // Chunks can be read one at a time, too.
+    let mut response = client
+        .make_request_no_body(Method::GET, "/ndjson", StatusCode::OK)
+        .await
+        .expect("Expected GET request to succeed");
+    let chunk = next_chunk_within(&mut response, timeout).await;
+    assert_eq!(chunk.unwrap(), "0\n");
+    while next_chunk_within(&mut response, timeout).await.is_some() {}
+
+    testctx.teardown().await;
+}
@@ -0,0 +1,135 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for stubs of APIs, made with `ApiDescription::into_stub`
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ApiService;
+use dropshot::HttpError;
+use dropshot::HttpResponseCreated;
+use dropshot::HttpResponseDeleted;
+use dropshot::HttpResponseOk;
+use dropshot::Path;
+use dropshot::RequestContext;
+use dropshot::TypedBody;
+use http::StatusCode;
+use hyper::service::Service;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// The real server's context, which the stub doesn't need
+struct Database {
+    projects: Mutex<Vec<Project>>,
+}
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Project {
+    name: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct ProjectPath {
+    name: String,
+}
+
+fn example_project() -> Project {
+    Project { name: String::from("example") }
+}
+
+#[endpoint {
+    method = POST,
+    path = "/projects",
+    response_examples = ["example_project"],
+}]
+async fn create_project(
+    rqctx: RequestContext<Database>,
+    body: TypedBody<Project>,
+) -> Result<HttpResponseCreated<Project>, HttpError> {
+    let project = body.into_inner();
+    let name = project.name.clone();
+    rqctx.context().projects.lock().unwrap().push(project);
+    Ok(HttpResponseCreated(Project { name }))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/projects/{name}",
+}]
+async fn get_project(
+    _rqctx: RequestContext<Database>,
+    _path: Path<ProjectPath>,
+) -> Result<HttpResponseOk<Project>, HttpError> {
+    unimplemented!()
+}
+
+#[endpoint {
+    method = DELETE,
+    path = "/projects/{name}",
+}]
+async fn delete_project(
+    _rqctx: RequestContext<Database>,
+    _path: Path<ProjectPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    unimplemented!()
+}
+
+fn api() -> ApiDescription<Database> {
+    let mut api = ApiDescription::new();
+    api.register(create_project).unwrap();
+    api.register(get_project).unwrap();
+    api.register(delete_project).unwrap();
+    api.register_openapi_endpoint("/openapi", "projects", "1.0.0").unwrap();
+    api
+}
+
+async fn body_json(response: hyper::Response<Body>) -> serde_json::Value {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_stub_responses() {
+    let mut service = ApiService::new(api().into_stub(), ());
+
+    // The example is returned, and the request isn't examined.
+    let request = Request::post("/projects").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        body_json(response).await,
+        serde_json::json!({ "name": "example" })
+    );
+
+    let request = Request::delete("/projects/p1").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let request = Request::get("/projects/p1").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    let error = body_json(response).await;
+    assert_eq!(error["error_code"], "NotImplemented");
+    assert_eq!(
+        error["message"],
+        "operation \"get_project\" has no example response"
+    );
+
+    // Routing still works as usual.
+    let request = Request::get("/teams").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_stub_openapi() {
+    let original = api().openapi("projects", "1.0.0").json().unwrap();
+    let mut service = ApiService::new(api().into_stub(), ());
+
+    let request = Request::get("/openapi").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await, original);
+}
@@ -4,8 +4,8 @@
 //! mode, including certificate loading and supported modes.
 
 use dropshot::{
-    ConfigDropshot, ConfigTls, HandlerTaskMode, HttpResponseOk,
-    HttpServerStarter,
+    ConfigClientAuth, ConfigDropshot, ConfigHttp3, ConfigListener, ConfigPem,
+    ConfigTls, HandlerTaskMode, HttpResponseOk, HttpServerStarter,
 };
 use std::convert::TryFrom;
 use std::path::Path;
@@ -108,6 +108,7 @@ fn make_server(cert_file: &Path, key_file: &Path) -> HttpServerStarter<i32> {
         bind_address: "127.0.0.1:0".parse().unwrap(),
         request_body_max_bytes: 1024,
         default_handler_task_mode: HandlerTaskMode::CancelOnDisconnect,
+        ..Default::default()
     };
     let config_tls = Some(ConfigTls::AsFile {
         cert_file: cert_file.to_path_buf(),
@@ -228,6 +229,75 @@ async fn test_tls_only() {
     server.close().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_tls_with_plaintext_listener() {
+    // Generate key for the server
+    let (certs, key) = common::generate_tls_key();
+    let (cert_file, key_file) = common::tls_key_to_file(&certs, &key);
+
+    // Serve HTTPS on one port and plaintext HTTP on another.
+    let server = make_server(cert_file.path(), key_file.path())
+        .listen(&ConfigListener {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            tls: None,
+        })
+        .unwrap()
+        .start();
+    let addrs = server.local_addrs().to_vec();
+    assert_eq!(addrs.len(), 2);
+    assert_eq!(addrs[0], server.local_addr());
+    assert_ne!(addrs[0].port(), addrs[1].port());
+
+    let https_client = make_https_client(make_pki_verifier(&certs));
+    let https_uri: hyper::Uri =
+        format!("https://localhost:{}/", addrs[0].port()).parse().unwrap();
+    https_client.get(https_uri).await.unwrap();
+
+    let http_client = hyper::Client::builder().build_http::<hyper::Body>();
+    let http_uri: hyper::Uri =
+        format!("http://localhost:{}/", addrs[1].port()).parse().unwrap();
+    http_client.get(http_uri).await.unwrap();
+    server.close().await.unwrap();
+
+    // The other way around: plaintext first, then HTTPS.
+    let config = ConfigDropshot::default();
+    let server = HttpServerStarter::new(
+        &config,
+        dropshot::ApiDescription::new(),
+        None,
+        0,
+    )
+    .unwrap()
+    .listen(&ConfigListener {
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        tls: Some(ConfigTls::AsFile {
+            cert_file: cert_file.path().to_path_buf(),
+            key_file: key_file.path().to_path_buf(),
+        }),
+    })
+    .unwrap()
+    .start();
+    let addrs = server.local_addrs().to_vec();
+    assert!(!server.using_tls());
+    let http_uri: hyper::Uri =
+        format!("http://localhost:{}/", addrs[0].port()).parse().unwrap();
+    http_client.get(http_uri).await.unwrap();
+    let https_uri: hyper::Uri =
+        format!("https://localhost:{}/", addrs[1].port()).parse().unwrap();
+    https_client.get(https_uri).await.unwrap();
+
+    // Shutting down stops all of the listeners.
+    server.close().await.unwrap();
+    let http_client = hyper::Client::builder().build_http::<hyper::Body>();
+    let http_uri: hyper::Uri =
+        format!("http://localhost:{}/", addrs[0].port()).parse().unwrap();
+    assert!(http_client.get(http_uri).await.unwrap_err().is_connect());
+    let https_client = make_https_client(make_pki_verifier(&certs));
+    let https_uri: hyper::Uri =
+        format!("https://localhost:{}/", addrs[1].port()).parse().unwrap();
+    assert!(https_client.get(https_uri).await.unwrap_err().is_connect());
+}
+
 #[tokio::test]
 async fn test_tls_refresh_certificates() {
     // Generate key for the server
@@ -398,6 +468,7 @@ async fn test_server_is_https() {
         bind_address: "127.0.0.1:0".parse().unwrap(),
         request_body_max_bytes: 1024,
         default_handler_task_mode: HandlerTaskMode::CancelOnDisconnect,
+        ..Default::default()
     };
     let config_tls = Some(ConfigTls::AsFile {
         cert_file: cert_file.path().to_path_buf(),
@@ -465,3 +536,273 @@ async fn test_server_is_http() {
         .await
         .expect_err("expected failure");
 }
+
+#[cfg(feature = "tls-test")]
+#[tokio::test]
+async fn test_test_context_tls_self_signed() {
+    let mut api = dropshot::ApiDescription::new();
+    api.register(tls_check_handler).unwrap();
+
+    let testctx = dropshot::test_util::TestContext::new_with_tls(
+        api,
+        0_usize,
+        &ConfigDropshot::default(),
+        None,
+    );
+    assert_eq!(testctx.client_testctx.url("/").scheme_str(), Some("https"));
+    assert!(testctx.server.using_tls());
+
+    testctx
+        .client_testctx
+        .make_request(
+            hyper::Method::GET,
+            "/?tls=true",
+            None as Option<()>,
+            hyper::StatusCode::OK,
+        )
+        .await
+        .expect("expected success");
+    testctx.teardown().await;
+}
+
+#[cfg(feature = "tls-test")]
+#[tokio::test]
+async fn test_test_context_tls_from_config() {
+    // The client should trust the root of the configured certificate chain.
+    let (certs, key) = common::generate_tls_key();
+    let (cert_file, key_file) = common::tls_key_to_file(&certs, &key);
+    let config_tls = ConfigTls::AsFile {
+        cert_file: cert_file.path().to_path_buf(),
+        key_file: key_file.path().to_path_buf(),
+    };
+
+    let mut api = dropshot::ApiDescription::new();
+    api.register(tls_check_handler).unwrap();
+    let testctx = dropshot::test_util::TestContext::new_with_tls(
+        api,
+        0_usize,
+        &ConfigDropshot::default(),
+        Some(config_tls),
+    );
+
+    testctx
+        .client_testctx
+        .make_request(
+            hyper::Method::GET,
+            "/?tls=true",
+            None as Option<()>,
+            hyper::StatusCode::OK,
+        )
+        .await
+        .expect("expected success");
+    testctx.teardown().await;
+}
+
+#[dropshot::endpoint {
+    method = GET,
+    path = "/",
+}]
+async fn client_cert_handler(
+    rqctx: dropshot::RequestContext<usize>,
+) -> Result<HttpResponseOk<Option<Vec<u8>>>, dropshot::HttpError> {
+    let cert = rqctx.request.client_certificate();
+    Ok(HttpResponseOk(cert.map(|c| c.end_entity().to_vec())))
+}
+
+fn make_mtls_client(
+    server_certs: &[rustls::pki_types::CertificateDer<'static>],
+    client_key: Option<(
+        Vec<rustls::pki_types::CertificateDer<'static>>,
+        rustls::pki_types::PrivateKeyDer<'static>,
+    )>,
+) -> hyper::Client<
+    hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>,
+> {
+    let builder = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(make_pki_verifier(server_certs));
+    let tls_config = match client_key {
+        Some((certs, key)) => {
+            builder.with_client_auth_cert(certs, key).unwrap()
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let https_connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_only()
+        .enable_http1()
+        .build();
+    hyper::Client::builder().build(https_connector)
+}
+
+#[tokio::test]
+async fn test_tls_client_auth() {
+    let (certs, key) = common::generate_tls_key();
+    let (certs_pem, key_pem) = common::tls_key_to_buffer(&certs, &key);
+    let (client_certs, client_key) = common::generate_tls_key();
+    // The server trusts the client's root certificate; the client presents
+    // its own certificate and the intermediate.
+    let (client_root_pem, _) =
+        common::tls_key_to_buffer(&vec![client_certs[2].clone()], &client_key);
+    let client_chain = client_certs[..2].to_vec();
+    let (other_certs, other_key) = common::generate_tls_key();
+
+    let start_server = |client_auth: ConfigClientAuth| {
+        let tls = ConfigTls::AsBytes {
+            certs: certs_pem.clone(),
+            key: key_pem.clone(),
+        }
+        .with_client_auth(client_auth);
+        let mut api = dropshot::ApiDescription::new();
+        api.register(client_cert_handler).unwrap();
+        HttpServerStarter::new_with_tls(
+            &ConfigDropshot::default(),
+            api,
+            None,
+            0,
+            Some(tls),
+        )
+        .unwrap()
+        .start()
+    };
+    let get = |server: &dropshot::HttpServer<usize>, client_key| {
+        let client = make_mtls_client(&certs, client_key);
+        let uri: hyper::Uri =
+            format!("https://localhost:{}/", server.local_addr().port())
+                .parse()
+                .unwrap();
+        async move {
+            let response = client.get(uri).await?;
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Ok::<_, hyper::Error>(
+                serde_json::from_slice::<Option<Vec<u8>>>(&body).unwrap(),
+            )
+        }
+    };
+
+    // Client certificates are required.
+    let client_auth = ConfigClientAuth {
+        ca_certs: ConfigPem::AsBytes(client_root_pem.clone()),
+        required: true,
+        crls: Vec::new(),
+        revocation_check: None,
+    };
+    let server = start_server(client_auth.clone());
+    let presented =
+        get(&server, Some((client_chain.clone(), client_key.clone_key())))
+            .await
+            .unwrap();
+    assert_eq!(presented.unwrap(), client_certs[0].to_vec());
+    get(&server, None).await.unwrap_err();
+    get(&server, Some((other_certs[..2].to_vec(), other_key.clone_key())))
+        .await
+        .unwrap_err();
+    server.close().await.unwrap();
+
+    // Client certificates are optional.
+    let server = start_server(ConfigClientAuth {
+        required: false,
+        ..client_auth.clone()
+    });
+    assert_eq!(get(&server, None).await.unwrap(), None);
+    let presented =
+        get(&server, Some((client_chain.clone(), client_key.clone_key())))
+            .await
+            .unwrap();
+    assert_eq!(presented.unwrap(), client_certs[0].to_vec());
+    server.close().await.unwrap();
+
+    // An extra check can reject certificates that are otherwise valid.
+    let revoked = client_certs[0].clone();
+    let server = start_server(ConfigClientAuth {
+        revocation_check: Some(Arc::new(move |cert| {
+            if *cert.end_entity() == revoked {
+                Err(String::from("certificate revoked"))
+            } else {
+                Ok(())
+            }
+        })),
+        ..client_auth.clone()
+    });
+    get(&server, Some((client_chain.clone(), client_key.clone_key())))
+        .await
+        .unwrap_err();
+    server.close().await.unwrap();
+
+    // Client authentication can't be combined with a dynamic configuration.
+    let raw = rustls::ServerConfig::try_from(&ConfigTls::AsBytes {
+        certs: certs_pem.clone(),
+        key: key_pem.clone(),
+    })
+    .unwrap();
+    let error = HttpServerStarter::new_with_tls(
+        &ConfigDropshot::default(),
+        dropshot::ApiDescription::<usize>::new(),
+        None,
+        0,
+        Some(ConfigTls::Dynamic(raw).with_client_auth(client_auth)),
+    )
+    .err()
+    .unwrap();
+    assert!(error.to_string().starts_with("client authentication can only"));
+}
+
+#[tokio::test]
+async fn test_http3_requires_tls() {
+    let config = ConfigDropshot {
+        http3: Some(ConfigHttp3 {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            alt_svc_max_age_secs: 86400,
+        }),
+        ..Default::default()
+    };
+    let result = HttpServerStarter::new(
+        &config,
+        dropshot::ApiDescription::new(),
+        None,
+        0,
+    );
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "http3")]
+#[tokio::test]
+async fn test_http3_advertised() {
+    let (certs, key) = common::generate_tls_key();
+    let (cert_file, key_file) = common::tls_key_to_file(&certs, &key);
+    let config = ConfigDropshot {
+        http3: Some(ConfigHttp3 {
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            alt_svc_max_age_secs: 3600,
+        }),
+        ..Default::default()
+    };
+    let config_tls = Some(ConfigTls::AsFile {
+        cert_file: cert_file.path().to_path_buf(),
+        key_file: key_file.path().to_path_buf(),
+    });
+    let server = HttpServerStarter::new_with_tls(
+        &config,
+        dropshot::ApiDescription::new(),
+        None,
+        0,
+        config_tls,
+    )
+    .unwrap()
+    .start();
+    let addrs = server.local_addrs().to_vec();
+    assert_eq!(addrs.len(), 2);
+
+    // Responses over HTTPS advertise the HTTP/3 listener's port.
+    let client = make_https_client(make_pki_verifier(&certs));
+    let uri: hyper::Uri =
+        format!("https://localhost:{}/", addrs[0].port()).parse().unwrap();
+    let response = client.get(uri).await.unwrap();
+    assert_eq!(
+        response.headers().get(http::header::ALT_SVC).unwrap(),
+        &format!("h3=\":{}\"; ma=3600", addrs[1].port())
+    );
+
+    server.close().await.unwrap();
+}
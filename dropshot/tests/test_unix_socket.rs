@@ -0,0 +1,159 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for serving over a Unix domain socket.
+
+#![cfg(unix)]
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigTls;
+use dropshot::ConfigUnixSocket;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServerStarter;
+use dropshot::RequestContext;
+use http::StatusCode;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub mod common;
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+struct Peer {
+    remote_addr: String,
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/peer",
+}]
+async fn get_peer(
+    rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Peer>, HttpError> {
+    let credentials = rqctx.request.peer_credentials().ok_or_else(|| {
+        HttpError::for_internal_error("no credentials".into())
+    })?;
+    Ok(HttpResponseOk(Peer {
+        remote_addr: rqctx.request.remote_addr().to_string(),
+        uid: credentials.uid,
+        gid: credentials.gid,
+        pid: credentials.pid,
+    }))
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "dropshot-{}-{}.sock",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn unix_config(path: &Path, mode: Option<u32>) -> ConfigDropshot {
+    ConfigDropshot {
+        unix_socket: Some(ConfigUnixSocket { path: path.to_path_buf(), mode }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_unix_socket() {
+    // Use a directory of our own so we can check what else was left in it.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("peer.sock");
+    let mut api = ApiDescription::new();
+    api.register(get_peer).unwrap();
+    let server =
+        HttpServerStarter::new(&unix_config(&path, Some(0o600)), api, None, ())
+            .unwrap()
+            .start();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let entries = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(entries, ["peer.sock"]);
+
+    let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::handshake(stream).await.unwrap();
+    tokio::spawn(conn);
+    let request = Request::builder()
+        .uri("/peer")
+        .header(http::header::HOST, "localhost")
+        .body(Body::empty())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let peer: Peer = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(peer.remote_addr, "0.0.0.0:0");
+    assert_eq!(peer.uid, unsafe { libc::getuid() });
+    assert_eq!(peer.gid, unsafe { libc::getgid() });
+    if let Some(pid) = peer.pid {
+        assert_eq!(pid, std::process::id() as i32);
+    }
+
+    drop(sender);
+    server.close().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_unix_socket_bad_config() {
+    // Something already exists at the path.
+    let path = socket_path("exists");
+    std::fs::write(&path, "").unwrap();
+    let error = HttpServerStarter::new(
+        &unix_config(&path, None),
+        ApiDescription::new(),
+        None,
+        (),
+    )
+    .err()
+    .unwrap();
+    assert!(error.to_string().starts_with("failed to bind to"));
+    // The same goes when setting the socket's mode, and what was there is left
+    // alone.
+    std::fs::write(&path, "mine").unwrap();
+    let error = HttpServerStarter::new(
+        &unix_config(&path, Some(0o600)),
+        ApiDescription::new(),
+        None,
+        (),
+    )
+    .err()
+    .unwrap();
+    assert!(error.to_string().starts_with("failed to bind to"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "mine");
+    std::fs::remove_file(&path).unwrap();
+
+    // TLS is not supported.
+    let path = socket_path("tls");
+    let error = HttpServerStarter::new_with_tls(
+        &unix_config(&path, None),
+        ApiDescription::new(),
+        None,
+        (),
+        Some(ConfigTls::AsBytes { certs: Vec::new(), key: Vec::new() }),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "TLS is not supported on Unix domain sockets"
+    );
+    assert!(!path.exists());
+}
@@ -0,0 +1,180 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for resumable uploads.
+
+use dropshot::endpoint;
+use dropshot::receive_chunk;
+use dropshot::ApiDescription;
+use dropshot::ContentRange;
+use dropshot::HandlerTaskMode;
+use dropshot::HttpError;
+use dropshot::HttpResponseUploadProgress;
+use dropshot::Path;
+use dropshot::RequestContext;
+use dropshot::StreamingBody;
+use dropshot::TempFileUploadStore;
+use dropshot::UploadStore;
+use http::header;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+pub mod common;
+
+fn api() -> ApiDescription<TempFileUploadStore> {
+    let mut api = ApiDescription::new();
+    api.register(upload_chunk).unwrap();
+    api.register(upload_status).unwrap();
+    api
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UploadPath {
+    upload_id: String,
+}
+
+#[endpoint {
+    method = PATCH,
+    path = "/uploads/{upload_id}",
+}]
+async fn upload_chunk(
+    rqctx: RequestContext<TempFileUploadStore>,
+    path: Path<UploadPath>,
+    range: ContentRange,
+    body: StreamingBody,
+) -> Result<HttpResponseUploadProgress, HttpError> {
+    let upload_id = path.into_inner().upload_id;
+    let progress =
+        receive_chunk(rqctx.context(), &upload_id, range, body).await?;
+    Ok(progress.into_response())
+}
+
+#[endpoint {
+    method = GET,
+    path = "/uploads/{upload_id}",
+}]
+async fn upload_status(
+    rqctx: RequestContext<TempFileUploadStore>,
+    path: Path<UploadPath>,
+) -> Result<HttpResponseUploadProgress, HttpError> {
+    let upload_id = path.into_inner().upload_id;
+    let progress = rqctx.context().progress(&upload_id).await?;
+    Ok(progress.into_response())
+}
+
+async fn send_chunk(
+    client: &dropshot::test_util::ClientTestContext,
+    upload_id: &str,
+    content_range: &str,
+    body: &'static str,
+) -> Response<Body> {
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri(client.url(&format!("/uploads/{}", upload_id)))
+        .header(header::CONTENT_RANGE, content_range)
+        .body(Body::from(body))
+        .unwrap();
+    client.client.request(request).await.unwrap()
+}
+
+fn header_str<'a>(response: &'a Response<Body>, name: &str) -> Option<&'a str> {
+    response.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn test_upload_chunks() {
+    let store = TempFileUploadStore::new().unwrap();
+    let testctx = common::test_setup_with_context(
+        api(),
+        store,
+        HandlerTaskMode::Detached,
+    );
+    let client = &testctx
+        .client_testctx
+        .with_allowed_header("upload-offset")
+        .with_allowed_header("upload-length");
+
+    let response = send_chunk(client, "u1", "bytes 0-4/15", "hello").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header_str(&response, "upload-offset"), Some("5"));
+    assert_eq!(header_str(&response, "upload-length"), Some("15"));
+
+    // A chunk beyond what's been received is rejected.
+    let response = send_chunk(client, "u1", "bytes 10-14/15", "!!!!!").await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // A retried chunk that overlaps what's been received is fine.
+    let response = send_chunk(client, "u1", "bytes 3-9/15", "lo, wor").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header_str(&response, "upload-offset"), Some("10"));
+
+    // A chunk whose body doesn't match its range is rejected, though the part
+    // of a short one that arrived is kept.
+    let response = send_chunk(client, "u1", "bytes 10-11/15", "ld!!").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send_chunk(client, "u1", "bytes 10-14/15", "ld").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The client can find out where to resume.
+    let response = client
+        .make_request_no_body(
+            Method::GET,
+            "/uploads/u1",
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .unwrap();
+    assert_eq!(header_str(&response, "upload-offset"), Some("12"));
+    assert_eq!(header_str(&response, "upload-length"), Some("15"));
+
+    // Chunks must agree on the upload's length, even ones that were already
+    // received.
+    let response = send_chunk(client, "u1", "bytes 12-14/16", "!!!").await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send_chunk(client, "u1", "bytes 0-4/16", "hello").await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send_chunk(client, "u1", "bytes 12-13/*", "!!").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header_str(&response, "upload-offset"), Some("14"));
+    assert_eq!(header_str(&response, "upload-length"), Some("15"));
+
+    let response = send_chunk(client, "u1", "bytes 14-14/15", "!").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header_str(&response, "upload-offset"), Some("15"));
+
+    let store = testctx.server.app_private();
+    let path = store.path("u1").unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "hello, world!!!");
+
+    // Once removed, an upload can start over with a different length.
+    store.remove("u1").await.unwrap();
+    let response = send_chunk(client, "u1", "bytes 0-1/2", "hi").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header_str(&response, "upload-length"), Some("2"));
+
+    // Bad upload ids and ranges are rejected.
+    let response = send_chunk(client, "a.b", "bytes 0-1/2", "..").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send_chunk(client, "u2", "bytes 0-5/2", "..").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_upload_openapi() {
+    let spec = api().openapi("test", "1.0.0").json().unwrap();
+    let operation = &spec["paths"]["/uploads/{upload_id}"]["patch"];
+    let parameters = operation["parameters"].as_array().unwrap();
+    let content_range = parameters
+        .iter()
+        .find(|p| p["name"] == "Content-Range")
+        .expect("missing Content-Range parameter");
+    assert_eq!(content_range["in"], "header");
+    assert_eq!(content_range["required"], true);
+
+    let headers = &operation["responses"]["204"]["headers"];
+    assert_eq!(headers["upload-offset"]["required"], true);
+    assert!(headers["upload-length"].get("required").is_none());
+}
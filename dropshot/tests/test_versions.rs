@@ -0,0 +1,812 @@
+// Copyright 2024 Oxide Computer Company
+
+//! Test cases for APIs with several versions
+
+use dropshot::endpoint;
+use dropshot::semver::Version;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::ApiEndpointVersions;
+use dropshot::ApiService;
+use dropshot::ClientSpecifiesVersionInAcceptHeader;
+use dropshot::ClientSpecifiesVersionInHeader;
+use dropshot::ClientSpecifiesVersionInPath;
+use dropshot::HandlerTaskMode;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::PrereleaseMatching;
+use dropshot::ReplacesOlderVersion;
+use dropshot::RequestContext;
+use dropshot::VersionPolicy;
+use dropshot::Versioned;
+use dropshot::VersionedBody;
+use http::StatusCode;
+use hyper::service::Service;
+use hyper::{Body, Request};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+
+pub mod common;
+
+const VERSION_HEADER: &str = "api-version";
+
+#[endpoint {
+    method = GET,
+    path = "/thing",
+    versions = { until = "2.0.0" },
+    deprecated_versions = { from = "1.1.0" },
+}]
+async fn get_thing_v1(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("thing")))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/thing",
+    versions = { from = "2.0.0" },
+}]
+async fn get_thing(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    Ok(HttpResponseOk(vec![String::from("thing")]))
+}
+
+#[endpoint {
+    method = DELETE,
+    path = "/thing",
+    versions = { from = "1.1.0", until = "3.0.0" },
+}]
+async fn delete_thing(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/ping",
+}]
+async fn ping(
+    _rqctx: RequestContext<()>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+/// A widget, as it was described before version 2.0.0
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct WidgetV1 {
+    name: String,
+}
+
+/// A widget
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct Widget {
+    name: String,
+    size: u32,
+}
+
+impl From<WidgetV1> for Widget {
+    fn from(old: WidgetV1) -> Widget {
+        Widget { name: old.name, size: 1 }
+    }
+}
+
+impl From<Widget> for WidgetV1 {
+    fn from(new: Widget) -> WidgetV1 {
+        WidgetV1 { name: new.name }
+    }
+}
+
+impl ReplacesOlderVersion<WidgetV1> for Widget {
+    fn since() -> Version {
+        Version::new(2, 0, 0)
+    }
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/widget",
+}]
+async fn put_widget(
+    rqctx: RequestContext<()>,
+    body: VersionedBody<WidgetV1, Widget>,
+) -> Result<HttpResponseOk<Versioned<WidgetV1, Widget>>, HttpError> {
+    let mut widget = body.into_inner();
+    widget.size *= 2;
+    Ok(HttpResponseOk(Versioned::for_request(&rqctx, widget)))
+}
+
+fn api() -> ApiDescription<()> {
+    api_with_policy(VersionPolicy::Dynamic(Arc::new(
+        ClientSpecifiesVersionInHeader::new(
+            VERSION_HEADER.parse().unwrap(),
+            Version::new(3, 0, 0),
+        ),
+    )))
+}
+
+fn api_with_policy(policy: VersionPolicy) -> ApiDescription<()> {
+    let mut api = ApiDescription::new()
+        .api_versions([
+            Version::new(1, 0, 0),
+            Version::new(1, 1, 0),
+            Version::new(2, 0, 0),
+            Version::new(3, 0, 0),
+        ])
+        .version_policy(policy);
+    api.register(get_thing_v1).unwrap();
+    api.register(get_thing).unwrap();
+    api.register(delete_thing).unwrap();
+    api.register(ping).unwrap();
+    api
+}
+
+async fn call(
+    method: http::Method,
+    path: &str,
+    version: Option<&str>,
+) -> (StatusCode, serde_json::Value) {
+    let mut request = Request::builder().method(method).uri(path);
+    if let Some(version) = version {
+        request = request.header(VERSION_HEADER, version);
+    }
+    call_with(api(), request.body(Body::empty()).unwrap()).await
+}
+
+async fn call_with(
+    api: ApiDescription<()>,
+    request: Request<Body>,
+) -> (StatusCode, serde_json::Value) {
+    let mut service = ApiService::new(api, ());
+    let response = service.call(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_versions_routing() {
+    let get = |version| call(http::Method::GET, "/thing", Some(version));
+    assert_eq!(get("1.0.0").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("1.9.3").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("2.0.0").await, (StatusCode::OK, json!(["thing"])));
+    assert_eq!(get("3.0.0").await, (StatusCode::OK, json!(["thing"])));
+
+    let delete = |version| call(http::Method::DELETE, "/thing", Some(version));
+    assert_eq!(delete("1.0.0").await.0, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(delete("2.0.0").await, (StatusCode::OK, json!(null)));
+    assert_eq!(delete("3.0.0").await.0, StatusCode::METHOD_NOT_ALLOWED);
+
+    let (status, _) = call(http::Method::GET, "/ping", Some("1.0.0")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = call(http::Method::GET, "/thing", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "missing expected header \"api-version\"");
+    let (status, body) = get("two").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "header \"api-version\": expected a semver");
+    let (status, body) = get("3.0.1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "server does not support this API version: 3.0.1"
+    );
+}
+
+#[test]
+fn test_versions_arithmetic() {
+    let v = |major| Version::new(major, 0, 0);
+    let all = ApiEndpointVersions::All;
+    let until_2 = ApiEndpointVersions::Until(v(2));
+    let from_2 = ApiEndpointVersions::From(v(2));
+    let from_1_until_3 = ApiEndpointVersions::from_until(v(1), v(3));
+    let from_3 = ApiEndpointVersions::From(v(3));
+
+    assert_eq!(
+        until_2.intersection(&from_1_until_3),
+        Some(ApiEndpointVersions::from_until(v(1), v(2)))
+    );
+    assert_eq!(all.intersection(&from_3), Some(from_3.clone()));
+    assert_eq!(until_2.intersection(&from_2), None);
+
+    assert_eq!(until_2.union(&from_2), Some(all.clone()));
+    assert_eq!(
+        from_1_until_3.union(&from_2),
+        Some(ApiEndpointVersions::From(v(1)))
+    );
+    assert_eq!(until_2.union(&from_3), None);
+
+    assert_eq!(ApiEndpointVersions::gaps([&until_2, &from_2]), []);
+    assert_eq!(
+        ApiEndpointVersions::gaps([&from_3, &until_2]),
+        [ApiEndpointVersions::from_until(v(2), v(3))]
+    );
+    assert_eq!(
+        ApiEndpointVersions::gaps([
+            &ApiEndpointVersions::Until(v(1)),
+            &ApiEndpointVersions::from_until(v(2), v(3)),
+            &ApiEndpointVersions::From(v(4)),
+        ]),
+        [
+            ApiEndpointVersions::from_until(v(1), v(2)),
+            ApiEndpointVersions::from_until(v(3), v(4)),
+        ]
+    );
+}
+
+#[test]
+fn test_versions_coverage() {
+    api().check_version_coverage().unwrap();
+
+    #[endpoint {
+        method = GET,
+        path = "/thing",
+        versions = { from = "2.1.0" },
+    }]
+    async fn get_thing_v2_1(
+        _rqctx: RequestContext<()>,
+    ) -> Result<HttpResponseOk<()>, HttpError> {
+        Ok(HttpResponseOk(()))
+    }
+
+    let mut api = ApiDescription::new();
+    api.register(get_thing_v1).unwrap();
+    api.register(get_thing_v2_1).unwrap();
+    api.register(ping).unwrap();
+    assert_eq!(
+        api.check_version_coverage().unwrap_err(),
+        "GET /thing: no endpoint in versions 2.0.0..2.1.0"
+    );
+}
+
+#[tokio::test]
+async fn test_versions_supported_window() {
+    let mut service = ApiService::new(api(), ());
+    let state = Arc::clone(service.app_state());
+    assert_eq!(state.supported_versions(), ApiEndpointVersions::All);
+    let mut get = |version: &str| {
+        let request = Request::builder()
+            .uri("/thing")
+            .header(VERSION_HEADER, version)
+            .body(Body::empty())
+            .unwrap();
+        let response = service.call(request);
+        async move {
+            let response = response.await.unwrap();
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await;
+            let body: serde_json::Value =
+                serde_json::from_slice(&body.unwrap()).unwrap();
+            (status, body)
+        }
+    };
+    assert_eq!(get("3.0.0").await.0, StatusCode::OK);
+
+    // Retract version 3.0.0 (and any later ones).
+    state.set_supported_versions(ApiEndpointVersions::from_until(
+        Version::new(1, 1, 0),
+        Version::new(3, 0, 0),
+    ));
+    let (status, body) = get("3.0.0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "server does not support this API version: 3.0.0 (supported: \
+         versions 1.1.0..3.0.0)"
+    );
+    assert_eq!(get("1.0.0").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(get("2.0.0").await, (StatusCode::OK, json!(["thing"])));
+
+    state.set_supported_versions(ApiEndpointVersions::All);
+    assert_eq!(get("3.0.0").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_versions_observer() {
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let api = {
+        let seen = Arc::clone(&seen);
+        api().version_observer(move |operation_id, version, versions| {
+            seen.lock()
+                .unwrap()
+                .push(format!("{} {} ({})", operation_id, version, versions));
+        })
+    };
+    let mut service = ApiService::new(api, ());
+    for (path, version) in
+        [("/thing", "1.2.0"), ("/thing", "2.0.0"), ("/ping", "3.0.0")]
+    {
+        let request = Request::builder()
+            .uri(path)
+            .header(VERSION_HEADER, version)
+            .body(Body::empty())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "get_thing_v1 1.2.0 (versions ..2.0.0)",
+            "get_thing 2.0.0 (versions 2.0.0..)",
+            "ping 3.0.0 (all versions)",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_versions_prerelease() {
+    let get = |version| call(http::Method::GET, "/thing", Some(version));
+    assert_eq!(get("2.0.0-beta.1").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("1.0.0+build.5").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("2.0.0+build.5").await, (StatusCode::OK, json!(["thing"])));
+    assert_eq!(get("3.0.0+build.5").await, (StatusCode::OK, json!(["thing"])));
+    let (status, _) = get("3.0.1-beta.1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let get = |version: &str| {
+        let request = Request::builder()
+            .uri("/thing")
+            .header(VERSION_HEADER, version)
+            .body(Body::empty())
+            .unwrap();
+        call_with(
+            api().prerelease_matching(PrereleaseMatching::NextStable),
+            request,
+        )
+    };
+    assert_eq!(get("2.0.0-beta.1").await, (StatusCode::OK, json!(["thing"])));
+    assert_eq!(get("1.9.9").await, (StatusCode::OK, json!("thing")));
+
+    let api = api()
+        .api_versions([Version::parse("2.0.0-rc.1").unwrap()])
+        .prerelease_matching(PrereleaseMatching::NextStable);
+    let definitions = api.openapi_all_versions("things");
+    let spec =
+        definitions[&Version::parse("2.0.0-rc.1").unwrap()].json().unwrap();
+    assert_eq!(spec["paths"]["/thing"]["get"]["operationId"], "get_thing");
+}
+
+#[test]
+fn test_versions_matching() {
+    let v = |version| Version::parse(version).unwrap();
+    let from = ApiEndpointVersions::From(v("2.0.0"));
+    let until = ApiEndpointVersions::Until(v("2.0.0"));
+    assert!(from.matches(&v("2.0.0+build.5")));
+    assert!(!until.matches(&v("2.0.0+build.5")));
+    assert!(!from.matches(&v("2.0.0-beta.1")));
+    assert!(until.matches(&v("2.0.0-beta.1")));
+    assert!(from.matches(&v("2.0.1-beta.1")));
+
+    // Bounds that differ only in build metadata are the same version.
+    let until_build = ApiEndpointVersions::Until(v("2.0.0+build.1"));
+    assert!(!from.overlaps_with(&until_build));
+    assert!(from.overlaps_with(&ApiEndpointVersions::Until(v("2.0.1-rc.1"))));
+}
+
+fn widget_api() -> ApiDescription<()> {
+    let mut api = api();
+    api.register(put_widget).unwrap();
+    api
+}
+
+#[tokio::test]
+async fn test_versions_converted_bodies() {
+    let put = |version: &str, body: serde_json::Value| {
+        let request = Request::builder()
+            .method(http::Method::PUT)
+            .uri("/widget")
+            .header(VERSION_HEADER, version)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        call_with(widget_api(), request)
+    };
+    assert_eq!(
+        put("1.0.0", json!({ "name": "old" })).await,
+        (StatusCode::OK, json!({ "name": "old" }))
+    );
+    assert_eq!(
+        put("2.0.0", json!({ "name": "new", "size": 2 })).await,
+        (StatusCode::OK, json!({ "name": "new", "size": 4 }))
+    );
+    let (status, _) = put("2.0.0", json!({ "name": "old" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The body is documented in its latest form.
+    let spec = widget_api().openapi("things", "2.0.0").json().unwrap();
+    let operation = &spec["paths"]["/widget"]["put"];
+    assert_eq!(
+        operation["requestBody"]["content"]["application/json"]["schema"]
+            ["$ref"],
+        "#/components/schemas/Widget"
+    );
+    assert_eq!(
+        operation["responses"]["200"]["content"]["application/json"]["schema"]
+            ["$ref"],
+        "#/components/schemas/Widget"
+    );
+}
+
+#[tokio::test]
+async fn test_versions_deprecated() {
+    let mut service = ApiService::new(api(), ());
+    let mut get = |version: &str| {
+        let request = Request::builder()
+            .uri("/thing")
+            .header(VERSION_HEADER, version)
+            .body(Body::empty())
+            .unwrap();
+        service.call(request)
+    };
+
+    let response = get("1.0.0").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
+    assert!(response.headers().get(http::header::WARNING).is_none());
+
+    let response = get("1.9.3").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "true");
+    assert_eq!(
+        response.headers()[http::header::WARNING],
+        "299 - \"API version 1.9.3 of this endpoint is deprecated\""
+    );
+
+    let response = get("2.0.0").await.unwrap();
+    assert!(response.headers().get("deprecation").is_none());
+
+    let api = api();
+    let definitions = api.openapi_all_versions("things");
+    let deprecated = |version| {
+        let spec = definitions[&version].json().unwrap();
+        spec["paths"]["/thing"]["get"]["deprecated"] == true
+    };
+    assert!(!deprecated(Version::new(1, 0, 0)));
+    assert!(deprecated(Version::new(1, 1, 0)));
+    assert!(!deprecated(Version::new(2, 0, 0)));
+}
+
+#[tokio::test]
+async fn test_versions_default_version() {
+    let api = || {
+        api_with_policy(VersionPolicy::Dynamic(Arc::new(
+            ClientSpecifiesVersionInHeader::new(
+                VERSION_HEADER.parse().unwrap(),
+                Version::new(3, 0, 0),
+            )
+            .default_version(Version::new(1, 1, 0)),
+        )))
+    };
+    let request = |version: Option<&str>| {
+        let mut request = Request::builder().uri("/thing");
+        if let Some(version) = version {
+            request = request.header(VERSION_HEADER, version);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    assert_eq!(
+        call_with(api(), request(None)).await,
+        (StatusCode::OK, json!("thing"))
+    );
+    assert_eq!(
+        call_with(api(), request(Some("2.0.0"))).await,
+        (StatusCode::OK, json!(["thing"]))
+    );
+    // A header that's given but invalid is still an error.
+    let (status, _) = call_with(api(), request(Some("two"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let api = api_with_policy(VersionPolicy::Dynamic(Arc::new(
+        ClientSpecifiesVersionInAcceptHeader::new(
+            "version",
+            Version::new(3, 0, 0),
+        )
+        .default_version(Version::new(3, 0, 0)),
+    )));
+    let request = Request::builder()
+        .uri("/thing")
+        .header(http::header::ACCEPT, "application/json")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        call_with(api, request).await,
+        (StatusCode::OK, json!(["thing"]))
+    );
+}
+
+#[tokio::test]
+async fn test_versions_accept_header() {
+    let api = || {
+        api_with_policy(VersionPolicy::Dynamic(Arc::new(
+            ClientSpecifiesVersionInAcceptHeader::new(
+                "version",
+                Version::new(3, 0, 0),
+            ),
+        )))
+    };
+    let get = |accept: &str| {
+        let request = Request::builder()
+            .uri("/thing")
+            .header(http::header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap();
+        call_with(api(), request)
+    };
+    assert_eq!(
+        get("application/json; version=1").await,
+        (StatusCode::OK, json!("thing"))
+    );
+    assert_eq!(
+        get("application/json;version=\"2.0.0\"").await,
+        (StatusCode::OK, json!(["thing"]))
+    );
+    assert_eq!(
+        get("text/html, application/json; q=0.9; Version=2.1").await,
+        (StatusCode::OK, json!(["thing"]))
+    );
+
+    let (status, body) = get("application/json").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "missing expected parameter \"version\" in header \"accept\""
+    );
+    let (status, body) = get("application/json; version=two").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "header \"accept\": parameter \"version\": expected a version"
+    );
+    let (status, body) = get("application/json; version=4").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "server does not support this API version: 4.0.0"
+    );
+}
+
+#[tokio::test]
+async fn test_versions_path_prefix() {
+    let api = || {
+        api_with_policy(VersionPolicy::PathPrefix(
+            ClientSpecifiesVersionInPath::new(Version::new(3, 0, 0)),
+        ))
+    };
+    let request = |method: http::Method, uri: &str| {
+        Request::builder().method(method).uri(uri).body(Body::empty()).unwrap()
+    };
+    let get = |uri: &str| call_with(api(), request(http::Method::GET, uri));
+    assert_eq!(get("/v1/thing").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("/v1.9/thing").await, (StatusCode::OK, json!("thing")));
+    assert_eq!(get("/v2.0.0/thing").await, (StatusCode::OK, json!(["thing"])));
+    assert_eq!(get("/v3/thing?x=1").await, (StatusCode::OK, json!(["thing"])));
+    let (status, _) =
+        call_with(api(), request(http::Method::DELETE, "/v1/thing")).await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    let (status, _) =
+        call_with(api(), request(http::Method::DELETE, "/v1.1/thing")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for uri in ["/thing", "/", "/vtwo/thing"] {
+        let (status, body) = get(uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["message"],
+            "expected path to begin with an API version (e.g., \"/v1\")"
+        );
+    }
+    let (status, body) = get("/v4/thing").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["message"],
+        "server does not support this API version: 4.0.0"
+    );
+}
+
+#[test]
+fn test_versions_openapi() {
+    let api = api();
+    let operations = |spec: &serde_json::Value| {
+        spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| format!("{} {}", method, path))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let definitions = api.openapi_all_versions("things");
+    let versions =
+        definitions.keys().map(ToString::to_string).collect::<Vec<_>>();
+    assert_eq!(versions, ["1.0.0", "1.1.0", "2.0.0", "3.0.0"]);
+
+    let v1 = definitions[&Version::new(1, 0, 0)].json().unwrap();
+    assert_eq!(v1["info"]["version"], "1.0.0");
+    assert_eq!(operations(&v1), ["get /ping", "get /thing"]);
+    assert_eq!(v1["paths"]["/thing"]["get"]["operationId"], "get_thing_v1");
+
+    let v1_1 = definitions[&Version::new(1, 1, 0)].json().unwrap();
+    assert_eq!(operations(&v1_1), ["get /ping", "delete /thing", "get /thing"]);
+
+    let v3 = definitions[&Version::new(3, 0, 0)].json().unwrap();
+    assert_eq!(operations(&v3), ["get /ping", "get /thing"]);
+    assert_eq!(v3["paths"]["/thing"]["get"]["operationId"], "get_thing");
+
+    // Definitions whose version isn't a semver only describe endpoints that
+    // are in every version.
+    let unversioned = api.openapi("things", "latest").json().unwrap();
+    assert_eq!(operations(&unversioned), ["get /ping"]);
+}
+
+#[tokio::test]
+async fn test_versions_openapi_endpoint() {
+    let make_api = || {
+        let mut api = api();
+        api.register_openapi_endpoint("/openapi.json", "things", "3.0.0")
+            .unwrap();
+        api
+    };
+    let definitions = make_api()
+        .openapi_all_versions("things")
+        .into_iter()
+        .map(|(version, definition)| (version, definition.json().unwrap()))
+        .collect::<std::collections::BTreeMap<_, _>>();
+    let get = |version: &str| {
+        let request = Request::get("/openapi.json")
+            .header(VERSION_HEADER, version)
+            .body(Body::empty())
+            .unwrap();
+        call_with(make_api(), request)
+    };
+
+    // Each declared version is served its own definition.
+    for (version, expected) in &definitions {
+        assert_eq!(
+            get(&version.to_string()).await,
+            (StatusCode::OK, expected.clone())
+        );
+    }
+
+    // Other versions are served the definition of the version that the
+    // endpoint was registered with.
+    let (status, spec) = get("1.9.3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(spec, definitions[&Version::new(3, 0, 0)]);
+}
+
+#[test]
+fn test_versions_write_openapi() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = api().write_openapi_all_versions("things", dir.path()).unwrap();
+    let names = paths
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["1.0.0.json", "1.1.0.json", "2.0.0.json", "3.0.0.json"]);
+
+    let contents = std::fs::read(&paths[2]).unwrap();
+    let spec: serde_json::Value = serde_json::from_slice(&contents).unwrap();
+    assert_eq!(spec["info"]["version"], "2.0.0");
+    assert_eq!(spec["paths"]["/thing"]["get"]["operationId"], "get_thing");
+}
+
+#[test]
+#[should_panic(expected = "URI path \"/thing\": attempted to create \
+                           duplicate route for method \"GET\"")]
+fn test_versions_overlap() {
+    let mut api = api();
+    api.register(get_thing_v1).unwrap();
+}
+
+#[test]
+fn test_versions_frozen() {
+    let fingerprint = ApiEndpoint::from(get_thing_v1).fingerprint();
+    assert_eq!(fingerprint, ApiEndpoint::from(get_thing_v1).fingerprint());
+    assert_ne!(fingerprint, ApiEndpoint::from(get_thing).fingerprint());
+
+    let mut api = ApiDescription::new();
+    api.register(ApiEndpoint::from(get_thing_v1).frozen(&fingerprint)).unwrap();
+    api.register(ApiEndpoint::from(get_thing).frozen(&fingerprint))
+        .unwrap_err();
+
+    // Changing an endpoint's response type changes its fingerprint.
+    let error = ApiDescription::<()>::new()
+        .register(
+            ApiEndpoint::from(get_thing)
+                .versions(ApiEndpointVersions::Until(Version::new(2, 0, 0)))
+                .frozen(&fingerprint),
+        )
+        .unwrap_err();
+    assert_eq!(
+        error,
+        format!(
+            "endpoint \"get_thing\" (versions ..2.0.0) is frozen, but its \
+             interface has changed (its fingerprint is now \"{}\")",
+            ApiEndpoint::from(get_thing).fingerprint()
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_versions_test_client() {
+    let policies = [
+        VersionPolicy::Dynamic(Arc::new(ClientSpecifiesVersionInHeader::new(
+            VERSION_HEADER.parse().unwrap(),
+            Version::new(3, 0, 0),
+        ))),
+        VersionPolicy::Dynamic(Arc::new(
+            ClientSpecifiesVersionInAcceptHeader::new(
+                "version",
+                Version::new(3, 0, 0),
+            ),
+        )),
+        VersionPolicy::PathPrefix(ClientSpecifiesVersionInPath::new(
+            Version::new(3, 0, 0),
+        )),
+    ];
+    for policy in policies {
+        let testctx = common::test_setup_with_context(
+            api_with_policy(policy),
+            (),
+            HandlerTaskMode::Detached,
+        );
+        let client = testctx
+            .client_testctx
+            .with_allowed_header("deprecation")
+            .with_allowed_header("warning");
+
+        // Version 1.5.0 of "GET /thing" is deprecated, so its response has
+        // headers saying so, which the client allows.
+        let versions = [
+            Version::new(1, 0, 0),
+            Version::new(1, 5, 0),
+            Version::new(2, 0, 0),
+            Version::new(3, 0, 0),
+        ];
+        client
+            .for_each_version(versions, |client, version| async move {
+                let mut response = client
+                    .make_request_no_body(
+                        http::Method::GET,
+                        "/thing",
+                        StatusCode::OK,
+                    )
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = read_json(&mut response).await;
+                if version.major < 2 {
+                    assert_eq!(body, json!("thing"));
+                } else {
+                    assert_eq!(body, json!(["thing"]));
+                }
+            })
+            .await;
+
+        let results = client
+            .make_request_for_versions(
+                http::Method::DELETE,
+                "/thing",
+                &[
+                    (Version::new(1, 0, 0), StatusCode::METHOD_NOT_ALLOWED),
+                    (Version::new(1, 5, 0), StatusCode::OK),
+                    (Version::new(3, 0, 0), StatusCode::METHOD_NOT_ALLOWED),
+                ],
+            )
+            .await;
+        assert!(results[1].is_ok());
+
+        testctx.teardown().await;
+    }
+}
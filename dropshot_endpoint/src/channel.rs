@@ -22,6 +22,17 @@ pub(crate) fn do_channel(
         tags,
         unpublished,
         deprecated,
+        ping_interval_ms,
+        pong_timeout_ms,
+        idle_timeout_ms,
+        shutdown_grace_period_ms,
+        max_frame_bytes,
+        max_message_bytes,
+        max_messages_per_second,
+        send_queue_len,
+        send_queue_max_bytes,
+        send_queue_policy,
+        subprotocols,
         _dropshot_crate,
     } = from_tokenstream(&attr)?;
     match protocol {
@@ -79,10 +90,63 @@ pub(crate) fn do_channel(
 
             let (conn_name, conn_type) = found.unwrap();
 
+            let config = if ping_interval_ms.is_some()
+                || pong_timeout_ms.is_some()
+                || idle_timeout_ms.is_some()
+                || shutdown_grace_period_ms.is_some()
+                || max_frame_bytes.is_some()
+                || max_message_bytes.is_some()
+                || max_messages_per_second.is_some()
+                || send_queue_len.is_some()
+                || send_queue_max_bytes.is_some()
+                || send_queue_policy.is_some()
+            {
+                let ping_interval_ms = quote_option(ping_interval_ms);
+                let pong_timeout_ms = quote_option(pong_timeout_ms);
+                let idle_timeout_ms = quote_option(idle_timeout_ms);
+                let shutdown_grace_period_ms =
+                    quote_option(shutdown_grace_period_ms);
+                let max_frame_bytes = quote_option(max_frame_bytes);
+                let max_message_bytes = quote_option(max_message_bytes);
+                let max_messages_per_second =
+                    quote_option(max_messages_per_second);
+                let send_queue_len = quote_option(send_queue_len);
+                let send_queue_max_bytes = quote_option(send_queue_max_bytes);
+                let send_queue_policy = quote_option(send_queue_policy);
+                quote! {
+                    let __dropshot_websocket_upgrade = __dropshot_websocket_upgrade
+                        .with_config(dropshot::ConfigWebsocket {
+                            ping_interval_ms: #ping_interval_ms,
+                            pong_timeout_ms: #pong_timeout_ms,
+                            idle_timeout_ms: #idle_timeout_ms,
+                            shutdown_grace_period_ms: #shutdown_grace_period_ms,
+                            max_frame_bytes: #max_frame_bytes,
+                            max_message_bytes: #max_message_bytes,
+                            max_messages_per_second: #max_messages_per_second,
+                            send_queue_len: #send_queue_len,
+                            send_queue_max_bytes: #send_queue_max_bytes,
+                            send_queue_policy: #send_queue_policy,
+                        });
+                }
+            } else {
+                quote! {}
+            };
+
+            let subprotocols = if subprotocols.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    let __dropshot_websocket_upgrade = __dropshot_websocket_upgrade
+                        .with_subprotocols([#(#subprotocols),*]);
+                }
+            };
+
             let new_item = quote! {
                 #(#attrs)*
                 #vis #sig {
                     async fn __dropshot_websocket_handler(#inner_args) #inner_output #body
+                    #config
+                    #subprotocols
                     __dropshot_websocket_upgrade.handle(move | #conn_name: #conn_type | async move {
                         __dropshot_websocket_handler(#(#arg_names),*).await
                     })
@@ -94,8 +158,23 @@ pub(crate) fn do_channel(
                 path,
                 tags,
                 unpublished,
+                audiences: Vec::new(),
                 deprecated,
                 content_type: Some("application/json".to_string()),
+                request_body_max_bytes: None,
+                page_default_limit: None,
+                page_max_limit: None,
+                status: None,
+                reason: None,
+                request_examples: Vec::new(),
+                response_examples: Vec::new(),
+                response_content_types: Vec::new(),
+                security: Vec::new(),
+                extensions: Vec::new(),
+                external_docs: None,
+                links: Vec::new(),
+                versions: None,
+                deprecated_versions: None,
                 _dropshot_crate,
             };
             endpoint::do_endpoint_inner(metadata, attr, new_item)
@@ -103,12 +182,39 @@ pub(crate) fn do_channel(
     }
 }
 
+fn quote_option<T: ToTokens>(value: Option<T>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    }
+}
+
 #[allow(non_snake_case)]
 #[derive(Deserialize, Debug)]
 enum ChannelProtocol {
     WEBSOCKETS,
 }
 
+/// Mirrors `dropshot::WebsocketOverflowPolicy`, for the `send_queue_policy`
+/// attribute
+#[derive(Deserialize, Debug)]
+enum ChannelSendQueuePolicy {
+    Block,
+    DropOldest,
+    Close,
+}
+
+impl ToTokens for ChannelSendQueuePolicy {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let variant = match self {
+            ChannelSendQueuePolicy::Block => quote! { Block },
+            ChannelSendQueuePolicy::DropOldest => quote! { DropOldest },
+            ChannelSendQueuePolicy::Close => quote! { Close },
+        };
+        tokens.extend(quote! { dropshot::WebsocketOverflowPolicy::#variant });
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct ChannelMetadata {
     protocol: ChannelProtocol,
@@ -119,5 +225,17 @@ struct ChannelMetadata {
     unpublished: bool,
     #[serde(default)]
     deprecated: bool,
+    ping_interval_ms: Option<u64>,
+    pong_timeout_ms: Option<u64>,
+    idle_timeout_ms: Option<u64>,
+    shutdown_grace_period_ms: Option<u64>,
+    max_frame_bytes: Option<u64>,
+    max_message_bytes: Option<u64>,
+    max_messages_per_second: Option<u32>,
+    send_queue_len: Option<usize>,
+    send_queue_max_bytes: Option<u64>,
+    send_queue_policy: Option<ChannelSendQueuePolicy>,
+    #[serde(default)]
+    subprotocols: Vec<String>,
     _dropshot_crate: Option<String>,
 }
@@ -2,12 +2,14 @@
 
 //! Support for HTTP `#[endpoint]` macros.
 
+use proc_macro2::{Delimiter, TokenTree};
 use quote::format_ident;
 use quote::quote;
 use quote::{quote_spanned, ToTokens};
 use serde::Deserialize;
 use serde_tokenstream::from_tokenstream;
 use serde_tokenstream::Error;
+use std::collections::BTreeMap;
 use syn::spanned::Spanned;
 
 use crate::syn_parsing::ItemFnForSignature;
@@ -22,6 +24,7 @@ const USAGE: &str = "Endpoint handlers must have the following signature:
         [body_param: TypedBody<J>,]
         [body_param: UntypedBody,]
         [body_param: StreamingBody,]
+        [body_param: SpooledBody,]
         [raw_request: RawRequest,]
     ) -> Result<HttpResponse*, HttpError>";
 
@@ -55,6 +58,87 @@ pub(crate) fn do_endpoint_inner(
             "invalid content type for endpoint",
         ));
     }
+    if let Some(reason) = &metadata.reason {
+        if reason.is_empty()
+            || reason.bytes().any(|b| b.is_ascii_control() && b != b'\t')
+        {
+            return Err(Error::new_spanned(
+                &attr,
+                "invalid reason phrase for endpoint",
+            ));
+        }
+    }
+
+    let status_field = attr_field(&attr, "status");
+    if let Some(status) = metadata.status {
+        if !(200..400).contains(&status) {
+            return Err(Error::new_spanned(
+                &status_field,
+                "success status must be a 2xx or 3xx status code",
+            ));
+        }
+    }
+
+    if let Some(extension) = metadata
+        .extensions
+        .iter()
+        .find(|extension| !extension.name.starts_with("x-"))
+    {
+        return Err(Error::new_spanned(
+            &attr,
+            format!(
+                "extension name \"{}\" must begin with \"x-\"",
+                extension.name
+            ),
+        ));
+    }
+    if let Some(content_type) = metadata
+        .response_content_types
+        .iter()
+        .find(|content_type| !is_media_type(content_type))
+    {
+        return Err(Error::new_spanned(
+            &attr,
+            format!("invalid response content type \"{}\"", content_type),
+        ));
+    }
+    for (name, limit) in [
+        ("page_default_limit", metadata.page_default_limit),
+        ("page_max_limit", metadata.page_max_limit),
+    ] {
+        if limit == Some(0) {
+            return Err(Error::new_spanned(
+                &attr,
+                format!("{} must be greater than zero", name),
+            ));
+        }
+    }
+    if let (Some(default), Some(max)) =
+        (metadata.page_default_limit, metadata.page_max_limit)
+    {
+        if default > max {
+            return Err(Error::new_spanned(
+                &attr,
+                format!(
+                    "page_default_limit ({}) must not be greater than \
+                     page_max_limit ({})",
+                    default, max
+                ),
+            ));
+        }
+    }
+    let request_examples = example_paths(&attr, &metadata.request_examples)?;
+    let response_examples = example_paths(&attr, &metadata.response_examples)?;
+    let versions = metadata
+        .versions
+        .as_ref()
+        .map(|versions| versions.parse(&attr))
+        .transpose()?;
+    let deprecated_versions = metadata
+        .deprecated_versions
+        .as_ref()
+        .map(|versions| versions.parse(&attr))
+        .transpose()?;
 
     let mut errors = Vec::new();
 
@@ -141,12 +225,110 @@ pub(crate) fn do_endpoint_inner(
         quote! { .visible(false) }
     });
 
+    let audiences = metadata.audiences.iter().map(|audience| {
+        quote! { .audience(#audience) }
+    });
+
     let deprecated = metadata.deprecated.then(|| {
         quote! { .deprecated(true) }
     });
 
+    let request_body_max_bytes = metadata.request_body_max_bytes.map(|n| {
+        quote! { .request_body_max_bytes(#n) }
+    });
+
+    let page_default_limit = metadata.page_default_limit.map(|n| {
+        quote! {
+            .page_default_limit(::std::num::NonZeroU32::new(#n).unwrap())
+        }
+    });
+
+    let page_max_limit = metadata.page_max_limit.map(|n| {
+        quote! {
+            .page_max_limit(::std::num::NonZeroU32::new(#n).unwrap())
+        }
+    });
+
+    let success_status = metadata.status.map(|n| {
+        quote! {
+            .success_status(::std::convert::TryFrom::try_from(#n).unwrap())
+        }
+    });
+    let success_reason = metadata.reason.as_ref().map(|reason| {
+        quote! { .success_reason(#reason) }
+    });
+
+    let request_examples = request_examples.iter().map(|(name, path)| {
+        quote! { .request_example(#name, #path()) }
+    });
+    let response_examples = response_examples.iter().map(|(name, path)| {
+        quote! { .response_example(#name, #path()) }
+    });
+
+    let response_content_types =
+        metadata.response_content_types.iter().map(|content_type| {
+            quote! { .response_content_type(#content_type) }
+        });
+
+    let security = metadata.security.iter().map(|requirement| {
+        let scheme = &requirement.scheme;
+        let scopes = &requirement.scopes;
+        quote! { .security(#scheme, &[#(#scopes),*]) }
+    });
+
+    let extensions = metadata.extensions.iter().map(|extension| {
+        let name = &extension.name;
+        let value = &extension.value;
+        quote! { .extension(#name, #value) }
+    });
+
+    let external_docs = metadata.external_docs.as_ref().map(|docs| {
+        let url = &docs.url;
+        let description = match &docs.description {
+            Some(description) => quote! { Some(#description) },
+            None => quote! { None },
+        };
+        quote! { .external_docs(#url, #description) }
+    });
+
+    let links = metadata.links.iter().map(|link| {
+        let name = &link.name;
+        let operation_id = &link.operation_id;
+        let parameters = link
+            .parameters
+            .iter()
+            .map(|(name, value)| quote! { (#name, #value) });
+        quote! { .response_link(#name, #operation_id, &[#(#parameters),*]) }
+    });
+
     let dropshot = get_crate(metadata._dropshot_crate);
 
+    let versions_range = |(from, until): (Option<String>, Option<String>)| {
+        let version = |v: String| {
+            quote! { #dropshot::semver::Version::parse(#v).unwrap() }
+        };
+        match (from.map(version), until.map(version)) {
+            (None, None) => quote! { #dropshot::ApiEndpointVersions::All },
+            (Some(from), None) => {
+                quote! { #dropshot::ApiEndpointVersions::From(#from) }
+            }
+            (None, Some(until)) => {
+                quote! { #dropshot::ApiEndpointVersions::Until(#until) }
+            }
+            (Some(from), Some(until)) => quote! {
+                #dropshot::ApiEndpointVersions::from_until(#from, #until)
+            },
+        }
+    };
+    let versions = versions.map(|versions| {
+        let versions = versions_range(versions);
+        quote! { .versions(#versions) }
+    });
+    let deprecated_versions = deprecated_versions.map(|versions| {
+        let versions = versions_range(versions);
+        quote! { .deprecate_versions(#versions) }
+    });
+
     let first_arg = match ast.sig.inputs.first() {
         Some(syn::FnArg::Typed(syn::PatType {
             attrs: _,
@@ -274,6 +456,30 @@ pub(crate) fn do_endpoint_inner(
         }
         syn::ReturnType::Type(_, ret_ty) => {
             let span = ret_ty.span();
+            // Statuses that forbid a body only suit response types without
+            // one, which we can only check once we know the type.  The check
+            // is attributed to the `status` field, so that's where the
+            // compiler reports it.
+            let status_check = metadata
+                .status
+                .filter(|n| matches!(n, 204 | 205 | 304))
+                .map(|_| {
+                    let status_span = status_field
+                        .clone()
+                        .into_iter()
+                        .next()
+                        .map_or(span, |token| token.span());
+                    quote_spanned! { status_span=>
+                        const _: () = if <<#ret_ty as ResultTrait>::T
+                            as #dropshot::HttpResponse>::HAS_BODY
+                        {
+                            panic!(
+                                "responses with status 204, 205, or 304 must \
+                                 not have a body"
+                            );
+                        };
+                    }
+                });
             quote_spanned! { span=>
                 const _: fn() = || {
                     // Pick apart the Result type.
@@ -318,6 +524,8 @@ pub(crate) fn do_endpoint_inner(
                     validate_result_error_type::<
                         <#ret_ty as ResultTrait>::E,
                     >();
+
+                    #status_check
                 };
             }
         }
@@ -346,7 +554,22 @@ pub(crate) fn do_endpoint_inner(
             #description
             #(#tags)*
             #visible
+            #(#audiences)*
             #deprecated
+            #request_body_max_bytes
+            #page_default_limit
+            #page_max_limit
+            #success_status
+            #success_reason
+            #(#request_examples)*
+            #(#response_examples)*
+            #(#response_content_types)*
+            #(#security)*
+            #(#extensions)*
+            #external_docs
+            #(#links)*
+            #versions
+            #deprecated_versions
         }
     } else {
         quote! {
@@ -402,6 +625,13 @@ pub(crate) fn do_endpoint_inner(
         ));
     }
 
+    if metadata.unpublished && !metadata.audiences.is_empty() {
+        errors.push(Error::new_spanned(
+            &attr,
+            "unpublished endpoints cannot be restricted to audiences",
+        ));
+    }
+
     Ok((stream, errors))
 }
 
@@ -440,11 +670,181 @@ pub(crate) struct EndpointMetadata {
     #[serde(default)]
     pub(crate) unpublished: bool,
     #[serde(default)]
+    pub(crate) audiences: Vec<String>,
+    #[serde(default)]
     pub(crate) deprecated: bool,
     pub(crate) content_type: Option<String>,
+    #[serde(default)]
+    pub(crate) request_body_max_bytes: Option<usize>,
+    #[serde(default)]
+    pub(crate) page_default_limit: Option<u32>,
+    #[serde(default)]
+    pub(crate) page_max_limit: Option<u32>,
+    #[serde(default)]
+    pub(crate) status: Option<u16>,
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+    #[serde(default)]
+    pub(crate) request_examples: Vec<String>,
+    #[serde(default)]
+    pub(crate) response_examples: Vec<String>,
+    #[serde(default)]
+    pub(crate) response_content_types: Vec<String>,
+    #[serde(default)]
+    pub(crate) security: Vec<SecurityRequirement>,
+    #[serde(default)]
+    pub(crate) extensions: Vec<Extension>,
+    pub(crate) external_docs: Option<ExternalDocs>,
+    #[serde(default)]
+    pub(crate) links: Vec<Link>,
+    pub(crate) versions: Option<Versions>,
+    pub(crate) deprecated_versions: Option<Versions>,
     pub(crate) _dropshot_crate: Option<String>,
 }
 
+/// One of the security schemes that may be used to call an endpoint
+#[derive(Deserialize, Debug)]
+pub(crate) struct SecurityRequirement {
+    scheme: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// A vendor extension for the operation in the OpenAPI document
+#[derive(Deserialize, Debug)]
+pub(crate) struct Extension {
+    name: String,
+    value: String,
+}
+
+/// Documentation for the operation outside of the OpenAPI document
+#[derive(Deserialize, Debug)]
+pub(crate) struct ExternalDocs {
+    url: String,
+    description: Option<String>,
+}
+
+/// An OpenAPI link from the operation's successful response to another
+/// operation
+#[derive(Deserialize, Debug)]
+pub(crate) struct Link {
+    name: String,
+    operation_id: String,
+    #[serde(default)]
+    parameters: BTreeMap<String, String>,
+}
+
+/// A range of versions of the API (e.g., those in which the operation appears)
+#[derive(Deserialize, Debug)]
+pub(crate) struct Versions {
+    from: Option<String>,
+    until: Option<String>,
+}
+
+impl Versions {
+    /// Checks that the versions are semvers and that the range isn't empty,
+    /// returning them in their canonical form
+    fn parse(
+        &self,
+        attr: &proc_macro2::TokenStream,
+    ) -> Result<(Option<String>, Option<String>), Error> {
+        let parse = |version: &Option<String>| {
+            version
+                .as_ref()
+                .map(|version| {
+                    semver::Version::parse(version).map_err(|_| {
+                        Error::new_spanned(
+                            attr,
+                            format!("invalid version \"{}\"", version),
+                        )
+                    })
+                })
+                .transpose()
+        };
+        let from = parse(&self.from)?;
+        let until = parse(&self.until)?;
+        if let (Some(from), Some(until)) = (&from, &until) {
+            if from.cmp_precedence(until).is_ge() {
+                return Err(Error::new_spanned(
+                    attr,
+                    format!(
+                        "versions range is empty: {} is not before {}",
+                        from, until
+                    ),
+                ));
+            }
+        }
+        Ok((from.map(|v| v.to_string()), until.map(|v| v.to_string())))
+    }
+}
+
+/// Returns the tokens of the `name = value` field of the macro's attribute, for
+/// reporting errors about that field, or the whole attribute if it has no
+/// such field
+fn attr_field(
+    attr: &proc_macro2::TokenStream,
+    name: &str,
+) -> proc_macro2::TokenStream {
+    let mut tokens: Vec<TokenTree> = attr.clone().into_iter().collect();
+    if let [TokenTree::Group(group)] = tokens.as_slice() {
+        if group.delimiter() == Delimiter::Brace {
+            tokens = group.stream().into_iter().collect();
+        }
+    }
+    let is_comma = |token: &TokenTree| match token {
+        TokenTree::Punct(punct) => punct.as_char() == ',',
+        _ => false,
+    };
+    tokens
+        .split(is_comma)
+        .find(|field| match field.first() {
+            Some(TokenTree::Ident(ident)) => ident == name,
+            _ => false,
+        })
+        .map_or_else(|| attr.clone(), |field| field.iter().cloned().collect())
+}
+
+/// Returns whether `content_type` looks like a media type: a type and subtype
+/// made of the characters RFC 6838 allows, optionally followed by parameters
+fn is_media_type(content_type: &str) -> bool {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    match essence.split_once('/') {
+        Some((type_, subtype)) => is_name(type_) && is_name(subtype),
+        None => false,
+    }
+}
+
+/// Parses the paths of the functions that produce examples, returning each
+/// along with the name of its example (the name of the function)
+fn example_paths(
+    attr: &proc_macro2::TokenStream,
+    paths: &[String],
+) -> Result<Vec<(String, syn::Path)>, Error> {
+    paths
+        .iter()
+        .map(|path| {
+            let parsed = syn::parse_str::<syn::Path>(path).ok();
+            let name = parsed
+                .as_ref()
+                .and_then(|p| p.segments.last())
+                .map(|segment| segment.ident.to_string());
+            match (parsed, name) {
+                (Some(parsed), Some(name)) => Ok((name, parsed)),
+                _ => Err(Error::new_spanned(
+                    attr,
+                    format!("invalid example function \"{}\"", path),
+                )),
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1088,6 +1488,167 @@ mod tests {
         assert_eq!("extraneous member `methud`", msg);
     }
 
+    #[test]
+    fn test_endpoint_bad_reason() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                reason = "Queued\r\nX-Injected: true",
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("invalid reason phrase for endpoint", msg);
+    }
+
+    #[test]
+    fn test_endpoint_bad_status() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                status = 404,
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("success status must be a 2xx or 3xx status code", msg);
+    }
+
+    #[test]
+    fn test_endpoint_bad_example() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                response_examples = ["not a path"],
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("invalid example function \"not a path\"", msg);
+    }
+
+    #[test]
+    fn test_endpoint_bad_response_content_type() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                response_content_types = ["text/plain", "png"],
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("invalid response content type \"png\"", msg);
+    }
+
+    #[test]
+    fn test_endpoint_bad_page_limits() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                page_max_limit = 0,
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("page_max_limit must be greater than zero", msg);
+
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                page_default_limit = 50,
+                page_max_limit = 20,
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!(
+            "page_default_limit (50) must not be greater than page_max_limit \
+             (20)",
+            msg
+        );
+    }
+
+    #[test]
+    fn test_endpoint_bad_extension() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                extensions = [{ name = "go-name", value = "Xyz" }],
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("extension name \"go-name\" must begin with \"x-\"", msg);
+    }
+
+    #[test]
+    fn test_endpoint_bad_versions() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                versions = { from = "1.2", until = "2.0.0" },
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("invalid version \"1.2\"", msg);
+
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                versions = { from = "2.0.0", until = "2.0.0" },
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("versions range is empty: 2.0.0 is not before 2.0.0", msg);
+
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                deprecated_versions = { until = "one" },
+            },
+            quote! {
+                async fn handler_xyz(_rqctx: RequestContext<()>) {}
+            },
+        );
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!("invalid version \"one\"", msg);
+    }
+
     #[test]
     fn test_endpoint_not_async() {
         let (_, errors) = do_endpoint(
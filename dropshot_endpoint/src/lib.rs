@@ -36,6 +36,47 @@ mod util;
 ///     deprecated = { true | false },
 ///     // A value of `true` causes the operation to be omitted from the API description
 ///     unpublished = { true | false },
+///     // Restricts the operation to the API descriptions for these audiences
+///     audiences = [ "internal", "partner" ],
+///     // Overrides the server's `request_body_max_bytes` for this operation
+///     request_body_max_bytes = 1048576,
+///     // For paginated operations, the default and maximum `limit` (overriding
+///     // the server's); larger limits are rejected with a 400
+///     page_default_limit = 20,
+///     page_max_limit = 200,
+///     // Overrides the success status code of the response type (e.g., to
+///     // return 202 from a handler returning `HttpResponseOk`)
+///     status = 202,
+///     // Replaces the reason phrase of successful (HTTP/1) responses, which
+///     // also describes the response in the OpenAPI document
+///     reason = "Queued",
+///     // Functions returning examples of the request and response bodies for
+///     // the OpenAPI document (each example is named after its function)
+///     request_examples = [ "example_request" ],
+///     response_examples = [ "example_response" ],
+///     // Media types of the successful response, documented in place of the
+///     // response type's own (e.g., for handlers returning `Response<Body>`)
+///     response_content_types = [ "text/plain", "image/png" ],
+///     // Security schemes (declared on the `ApiDescription`), any one of
+///     // which may be used to call the operation, with any OAuth2 scopes
+///     security = [ { scheme = "oauth2", scopes = [ "read" ] } ],
+///     // Vendor extensions (with string values) for the operation
+///     extensions = [ { name = "x-go-name", value = "GetThing" } ],
+///     // Documentation for the operation outside of the OpenAPI document
+///     external_docs = { url = "https://example.com/docs", description = "Guide" },
+///     // OpenAPI links from the successful response to other operations,
+///     // with values (usually runtime expressions) for their parameters
+///     links = [ {
+///         name = "GetThing",
+///         operation_id = "get_thing",
+///         parameters = { id = "$response.body#/id" },
+///     } ],
+///     // Versions of the API in which the operation appears, from the first
+///     // (inclusive) until the second (exclusive); either may be omitted
+///     versions = { from = "1.0.0", until = "2.0.0" },
+///     // Versions of the API in which the operation is deprecated, in the
+///     // same form as `versions`
+///     deprecated_versions = { until = "1.5.0" },
 /// }]
 /// ```
 ///
@@ -70,6 +111,36 @@ pub fn endpoint(
 /// ```ignore
 /// #[dropshot::channel { protocol = WEBSOCKETS, path = "/my/ws/channel/{id}" }]
 /// ```
+///
+/// The server's websocket settings (see
+/// [`ConfigWebsocket`](../dropshot/struct.ConfigWebsocket.html)) can be
+/// overridden for a channel with the `ping_interval_ms`, `pong_timeout_ms`,
+/// `idle_timeout_ms`, `shutdown_grace_period_ms`, `max_frame_bytes`,
+/// `max_message_bytes`, `max_messages_per_second`, `send_queue_len`,
+/// `send_queue_max_bytes`, and `send_queue_policy` attributes:
+///
+/// ```ignore
+/// #[dropshot::channel {
+///     protocol = WEBSOCKETS,
+///     path = "/my/ws/channel/{id}",
+///     ping_interval_ms = 30000,
+///     pong_timeout_ms = 10000,
+///     send_queue_policy = DropOldest,
+/// }]
+/// ```
+///
+/// Handlers that speak particular subprotocols list them, in order of
+/// preference, with the `subprotocols` attribute.  The first one that the
+/// client also offers is chosen during the handshake (see
+/// [`WebsocketConnection::subprotocol`](../dropshot/struct.WebsocketConnection.html#method.subprotocol)):
+///
+/// ```ignore
+/// #[dropshot::channel {
+///     protocol = WEBSOCKETS,
+///     path = "/my/ws/graphql",
+///     subprotocols = ["graphql-transport-ws", "graphql-ws"],
+/// }]
+/// ```
 #[proc_macro_attribute]
 pub fn channel(
     attr: proc_macro::TokenStream,